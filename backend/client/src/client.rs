@@ -0,0 +1,409 @@
+//! The HTTP client.
+//!
+//! Requests that didn't reach the server are retried with exponential
+//! backoff. Requests that are safe to repeat — reads, and moves, which carry
+//! an `Idempotency-Key` — are also retried on timeouts, `429` and `5xx`.
+
+use std::{fmt, time::Duration};
+
+use futures_util::{Stream, stream};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, header::CONTENT_TYPE};
+use serde::{Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+use crate::types::{
+    ErrorBody, GameView, MoveBatchRequest, MoveRequest, NewGameRequest, NewGameResponse,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request failed without a response.
+    Http(reqwest::Error),
+    /// The server answered with an error.
+    Api { status: u16, body: ErrorBody },
+    /// The response wasn't what the API returns.
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request failed: {e}"),
+            Error::Api { status, body } => {
+                write!(f, "{} ({}): {}", status, body.code, body.message)
+            }
+            Error::Decode(e) => write!(f, "unexpected response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How often, and how patiently, requests are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Tries in all, the first included.
+    pub attempts: u32,
+    /// The wait after the first failure, doubled after each one after it.
+    pub first_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            first_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Retry {
+    /// No retries.
+    pub const NEVER: Retry = Retry {
+        attempts: 1,
+        first_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    /// The wait after the `failed`th failed try.
+    fn delay(&self, failed: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failed.saturating_sub(1));
+        self.first_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Whether a failed request may be sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    /// Only if it never reached the server.
+    Unsent,
+    /// Whatever happened to it.
+    Safe,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+    retry: Retry,
+    session_token: Option<String>,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// A client for the server at `base_url`, e.g. `http://localhost:3000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::builder()
+                .connect_timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client builds"),
+            retry: Retry::default(),
+            session_token: None,
+            api_key: None,
+        }
+    }
+
+    /// Sends a session's access token, so games are recorded against its
+    /// account.
+    pub fn with_session_token(mut self, token: impl Into<String>) -> Self {
+        self.session_token = Some(token.into());
+        self
+    }
+
+    /// Sends an API key, for its quotas.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Starts a game against the AI, who plays O.
+    pub async fn create_game(&self, request: &NewGameRequest) -> Result<NewGameResponse, Error> {
+        let response = self
+            .send(
+                Method::POST,
+                "/newgame",
+                Some(request),
+                Repeat::Unsent,
+                |r| r,
+            )
+            .await?;
+        decode(response).await
+    }
+
+    pub async fn game(&self, game_id: Uuid) -> Result<GameView, Error> {
+        let path = format!("/games/{game_id}");
+        let response = self
+            .send(Method::GET, &path, None::<&()>, Repeat::Safe, |r| r)
+            .await?;
+        decode(response).await
+    }
+
+    /// The game once its version is past `since_version`, for clients that
+    /// poll instead of [`subscribe`](Self::subscribe). If nothing changes
+    /// for a while, or the game is over, it comes back as it is.
+    pub async fn wait_for_change(
+        &self,
+        game_id: Uuid,
+        since_version: u64,
+    ) -> Result<GameView, Error> {
+        // Given up on by the server before the request times out here.
+        let path = format!(
+            "/games/{game_id}/wait?since_version={since_version}&timeout_secs={}",
+            REQUEST_TIMEOUT.as_secs() - 2
+        );
+        let response = self
+            .send(Method::GET, &path, None::<&()>, Repeat::Safe, |r| r)
+            .await?;
+        decode(response).await
+    }
+
+    /// Plays a move. Against the AI its reply is in the returned game;
+    /// player-vs-player games need the mover's `seat_token`.
+    pub async fn make_move(
+        &self,
+        game_id: Uuid,
+        request: &MoveRequest,
+        seat_token: Option<&str>,
+    ) -> Result<GameView, Error> {
+        let path = format!("/games/{game_id}/move");
+        // The same key on every try, so the server plays the move once.
+        let key = Uuid::new_v4().to_string();
+        let response = self
+            .send(Method::POST, &path, Some(request), Repeat::Safe, |r| {
+                let r = r.header("idempotency-key", &key);
+                match seat_token {
+                    Some(token) => r.header("seat-token", token),
+                    None => r,
+                }
+            })
+            .await?;
+        decode(response).await
+    }
+
+    /// Plays a move against the AI without waiting for its reply: the
+    /// returned game has just the move, and the reply follows on
+    /// [`subscribe`](Self::subscribe) or in a later [`game`](Self::game).
+    pub async fn make_move_in_background(
+        &self,
+        game_id: Uuid,
+        request: &MoveRequest,
+    ) -> Result<GameView, Error> {
+        let path = format!("/games/{game_id}/move");
+        let key = Uuid::new_v4().to_string();
+        let response = self
+            .send(Method::POST, &path, Some(request), Repeat::Safe, |r| {
+                r.header("idempotency-key", &key)
+                    .header("prefer", "respond-async")
+            })
+            .await?;
+        decode(response).await
+    }
+
+    /// Plays several moves at once, all of them or, if any is refused,
+    /// none. In player-vs-player games each move is for the side to move,
+    /// whose seat must be among `seat_tokens`.
+    pub async fn make_moves(
+        &self,
+        game_id: Uuid,
+        request: &MoveBatchRequest,
+        seat_tokens: &[&str],
+    ) -> Result<GameView, Error> {
+        let path = format!("/games/{game_id}/moves");
+        let response = self
+            .send(Method::POST, &path, Some(request), Repeat::Unsent, |r| {
+                seat_tokens
+                    .iter()
+                    .fold(r, |r, token| r.header("seat-token", *token))
+            })
+            .await?;
+        decode(response).await
+    }
+
+    /// The game now, then again after every change, until it is over or
+    /// the server closes the stream.
+    pub async fn subscribe(
+        &self,
+        game_id: Uuid,
+    ) -> Result<impl Stream<Item = Result<GameView, Error>> + use<>, Error> {
+        let path = format!("/games/{game_id}/stream");
+        let response = self
+            .send(Method::GET, &path, None::<&()>, Repeat::Safe, |r| r)
+            .await?;
+        let events = stream::unfold(
+            Some((response, EventParser::default())),
+            |open| async move {
+                let (mut response, mut parser) = open?;
+                loop {
+                    if let Some((event, data)) = parser.next_event() {
+                        if event != "game" {
+                            continue;
+                        }
+                        let view = serde_json::from_str::<GameView>(&data).map_err(Error::Decode);
+                        let open = match &view {
+                            Ok(view) if !view.is_over() => Some((response, parser)),
+                            _ => None,
+                        };
+                        return Some((view, open));
+                    }
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => parser.push(&chunk),
+                        Ok(None) => return None,
+                        Err(e) => return Some((Err(Error::Http(e)), None)),
+                    }
+                }
+            },
+        );
+        Ok(events)
+    }
+
+    /// Sends a request to `/api/v1{path}`, retrying as `repeat` allows, and
+    /// turns error responses into `Error::Api`.
+    async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+        repeat: Repeat,
+        customize: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Response, Error> {
+        let url = format!("{}/api/v1{}", self.base_url, path);
+        let body = body
+            .map(|body| serde_json::to_string(body).expect("requests serialize"))
+            .unwrap_or_default();
+        let mut failed = 0;
+        loop {
+            let mut request = self
+                .http
+                .request(method.clone(), &url)
+                .timeout(REQUEST_TIMEOUT);
+            if !body.is_empty() {
+                request = request
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body.clone());
+            }
+            if let Some(token) = &self.session_token {
+                request = request.bearer_auth(token);
+            }
+            if let Some(key) = &self.api_key {
+                request = request.header("x-api-key", key);
+            }
+            let result = customize(request).send().await;
+            failed += 1;
+            let again = failed < self.retry.attempts;
+            match result {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if again && repeat == Repeat::Safe && retryable(response.status()) => {
+                }
+                Ok(response) => return Err(api_error(response).await),
+                Err(e) if again && (e.is_connect() || repeat == Repeat::Safe && e.is_timeout()) => {
+                }
+                Err(e) => return Err(Error::Http(e)),
+            }
+            tokio::time::sleep(self.retry.delay(failed)).await;
+        }
+    }
+}
+
+fn retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    let bytes = response.bytes().await.map_err(Error::Http)?;
+    serde_json::from_slice(&bytes).map_err(Error::Decode)
+}
+
+async fn api_error(response: Response) -> Error {
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    // Errors from outside the API, e.g. a proxy, aren't JSON.
+    let body = serde_json::from_str(&text).unwrap_or_else(|_| ErrorBody {
+        code: "http_error".to_string(),
+        message: if text.is_empty() {
+            status.to_string()
+        } else {
+            text
+        },
+        details: None,
+    });
+    Error::Api {
+        status: status.as_u16(),
+        body,
+    }
+}
+
+/// Splits a server-sent event stream into events as its bytes arrive.
+#[derive(Debug, Default)]
+struct EventParser {
+    buffer: Vec<u8>,
+}
+
+impl EventParser {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer
+            .extend(chunk.iter().filter(|&&byte| byte != b'\r'));
+    }
+
+    /// The next whole event's name and data. Comments, which keep the
+    /// connection alive, are skipped.
+    fn next_event(&mut self) -> Option<(String, String)> {
+        loop {
+            let end = self.buffer.windows(2).position(|pair| pair == b"\n\n")?;
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let block = String::from_utf8_lossy(&block);
+            let mut event = "message".to_string();
+            let mut data: Vec<&str> = Vec::new();
+            for line in block.lines() {
+                let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match field {
+                    "event" => event = value.to_string(),
+                    "data" => data.push(value),
+                    _ => {}
+                }
+            }
+            if !data.is_empty() {
+                return Some((event, data.join("\n")));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_split_across_chunks_and_backoff_doubles() {
+        let mut parser = EventParser::default();
+        parser.push(b": keep-alive\n\nevent: game\r\ndata: {\"a\":");
+        assert_eq!(parser.next_event(), None);
+        parser.push(b"1}\r\n\r\ndata: x\n");
+        assert_eq!(
+            parser.next_event(),
+            Some(("game".to_string(), "{\"a\":1}".to_string()))
+        );
+        assert_eq!(parser.next_event(), None);
+
+        let retry = Retry {
+            attempts: 5,
+            first_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        let delays: Vec<u64> = (1..=4)
+            .map(|failed| retry.delay(failed).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [100, 200, 300, 300]);
+    }
+}
@@ -0,0 +1,22 @@
+//! A client for the Laika HTTP API. Its types are `laika-types`, the same
+//! ones the server sends.
+//!
+//! ```no_run
+//! use laika_client::{Client, types::{MoveRequest, NewGameRequest}};
+//!
+//! # async fn play() -> Result<(), laika_client::Error> {
+//! let client = Client::new("http://localhost:3000");
+//! let game = client.create_game(&NewGameRequest::default()).await?;
+//! let view = client
+//!     .make_move(game.game_id, &MoveRequest::new(1, 1), None)
+//!     .await?;
+//! println!("{:?}", view.status);
+//! # Ok(())
+//! # }
+//! ```
+
+pub use laika_types as types;
+
+mod client;
+
+pub use client::{Client, Error, Retry};
@@ -0,0 +1,39 @@
+-- Players who can occupy a seat in a game. Human players are anonymous for
+-- now, so only the built-in AI has a row.
+CREATE TABLE players (
+    id UUID PRIMARY KEY,
+    display_name TEXT NOT NULL,
+    is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO players (id, display_name, is_bot)
+VALUES ('00000000-0000-0000-0000-000000000001', 'Minimax', TRUE);
+
+CREATE TABLE games (
+    id UUID PRIMARY KEY,
+    x_player_id UUID REFERENCES players (id),
+    o_player_id UUID REFERENCES players (id),
+    -- The full `GameState` as served by the API.
+    state JSONB NOT NULL,
+    -- Denormalized from `state` for querying.
+    status TEXT NOT NULL,
+    version BIGINT NOT NULL,
+    idempotent_moves JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX games_status_idx ON games (status);
+CREATE INDEX games_finished_at_idx ON games (finished_at);
+
+CREATE TABLE moves (
+    game_id UUID NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    ply BIGINT NOT NULL,
+    player TEXT NOT NULL,
+    row_index SMALLINT NOT NULL,
+    col_index SMALLINT NOT NULL,
+    played_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (game_id, ply)
+);
@@ -0,0 +1,14 @@
+-- Player-vs-player games record who holds each seat; tournament games point
+-- back at their tournament.
+ALTER TABLE games
+    ADD COLUMN mode JSONB NOT NULL DEFAULT '{"kind": "vs_engine"}',
+    ADD COLUMN tournament_id UUID;
+
+-- Tournaments are small and always read whole, so they are stored as one
+-- document each.
+CREATE TABLE tournaments (
+    id UUID PRIMARY KEY,
+    state JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
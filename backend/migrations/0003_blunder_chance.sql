@@ -0,0 +1,3 @@
+-- Engine games can be handicapped so the AI sometimes plays a weaker move.
+ALTER TABLE games
+    ADD COLUMN blunder_chance DOUBLE PRECISION NOT NULL DEFAULT 0;
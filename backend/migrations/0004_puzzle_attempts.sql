@@ -0,0 +1,13 @@
+-- Attempts at the puzzle of the day. Each name gets one attempt per day, and
+-- the leaderboard ranks the solved ones by answered_at - started_at.
+CREATE TABLE puzzle_attempts (
+    id UUID PRIMARY KEY,
+    token TEXT NOT NULL,
+    name TEXT NOT NULL,
+    date DATE NOT NULL,
+    puzzle_id INTEGER NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    answered_at TIMESTAMPTZ,
+    correct BOOLEAN NOT NULL DEFAULT false,
+    UNIQUE (date, name)
+);
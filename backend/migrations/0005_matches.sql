@@ -0,0 +1,11 @@
+-- Best-of-N matches are stored whole, like tournaments; their games point
+-- back at them.
+ALTER TABLE games
+    ADD COLUMN match_id UUID;
+
+CREATE TABLE matches (
+    id UUID PRIMARY KEY,
+    state JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+-- Player-vs-player games can be played under the pie rule, which lets O swap
+-- sides after X's first move.
+ALTER TABLE games
+    ADD COLUMN pie_rule JSONB NOT NULL DEFAULT '"off"';
@@ -0,0 +1,8 @@
+-- Human players get a row once they start a session. Guests have no
+-- username; the full account, including its sessions, games, and stats, is
+-- kept in `account`.
+ALTER TABLE players
+    ADD COLUMN username TEXT,
+    ADD COLUMN account JSONB;
+
+CREATE UNIQUE INDEX players_username_idx ON players (lower(username));
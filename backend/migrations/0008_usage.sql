@@ -0,0 +1,7 @@
+-- What each session account or API key has used of its quotas, keyed by
+-- `account:<id>` or `key:<name>`.
+CREATE TABLE usage (
+    subject TEXT PRIMARY KEY,
+    usage JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
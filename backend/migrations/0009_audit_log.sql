@@ -0,0 +1,12 @@
+-- Who did what, when, and from where. Rows are only ever inserted; the rules
+-- below make updates and deletes do nothing.
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    at TIMESTAMPTZ NOT NULL,
+    entry JSONB NOT NULL
+);
+
+CREATE INDEX audit_log_at_idx ON audit_log (at);
+
+CREATE RULE audit_log_no_update AS ON UPDATE TO audit_log DO INSTEAD NOTHING;
+CREATE RULE audit_log_no_delete AS ON DELETE TO audit_log DO INSTEAD NOTHING;
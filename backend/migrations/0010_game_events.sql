@@ -0,0 +1,11 @@
+-- Each game's event log, in order. A game's position and result are rebuilt
+-- from it on load; `games.state` and `moves` are kept up to date alongside
+-- it for querying. Games stored before this table existed get their log
+-- filled in from `moves` the first time they are loaded.
+CREATE TABLE game_events (
+    game_id UUID NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    seq BIGINT NOT NULL,
+    at TIMESTAMPTZ NOT NULL,
+    event JSONB NOT NULL,
+    PRIMARY KEY (game_id, seq)
+);
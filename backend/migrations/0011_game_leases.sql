@@ -0,0 +1,10 @@
+-- Which instance may change each game when several share the database. A
+-- lease that has run out can be taken over by any instance.
+CREATE TABLE game_leases (
+    game_id UUID PRIMARY KEY REFERENCES games (id) ON DELETE CASCADE,
+    owner UUID NOT NULL,
+    url TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX game_leases_owner_idx ON game_leases (owner);
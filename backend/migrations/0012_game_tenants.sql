@@ -0,0 +1,6 @@
+-- Games started in a tenant can only be reached from that tenant; NULL for
+-- games open to everyone.
+ALTER TABLE games
+    ADD COLUMN tenant TEXT;
+
+CREATE INDEX games_tenant_idx ON games (tenant);
@@ -0,0 +1,31 @@
+-- Lets games be searched by variant and player without reading their state.
+-- A game can be several variants at once; see `search::Variant`.
+ALTER TABLE games
+    ADD COLUMN variants TEXT[] NOT NULL DEFAULT '{standard}';
+
+UPDATE games
+SET variants = COALESCE(
+    NULLIF(
+        ARRAY_REMOVE(
+            ARRAY[
+                CASE
+                    WHEN jsonb_array_length(state->'board') <> 3
+                        OR jsonb_array_length(state->'board'->0) <> 3
+                    THEN 'large'
+                END,
+                CASE WHEN (state->'rules'->>'toroidal')::boolean THEN 'toroidal' END,
+                CASE WHEN state->'board' @> '[["Blocked"]]' THEN 'blocked' END
+            ],
+            NULL
+        ),
+        '{}'
+    ),
+    '{standard}'
+);
+
+CREATE INDEX games_variants_idx ON games USING GIN (variants);
+CREATE INDEX games_created_at_idx ON games (created_at);
+CREATE INDEX games_version_idx ON games (version);
+CREATE INDEX games_x_name_idx ON games ((mode->'x'->>'name'));
+CREATE INDEX games_o_name_idx ON games ((mode->'o'->>'name'));
+CREATE INDEX games_bot_id_idx ON games ((mode->>'bot_id'));
@@ -0,0 +1,8 @@
+-- Finished games are archived rather than dropped once they expire, and
+-- deleted after a retention period; see `archive`. Games that expired
+-- before this are archived the next time the server starts.
+ALTER TABLE games
+    ADD COLUMN archived_at TIMESTAMPTZ,
+    ADD COLUMN restored_at TIMESTAMPTZ;
+
+CREATE INDEX games_archived_at_idx ON games (archived_at) WHERE archived_at IS NOT NULL;
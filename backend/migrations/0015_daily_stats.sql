@@ -0,0 +1,12 @@
+-- One row per UTC day, rolled up from the games that finished that day; see
+-- `rollup`. Rows are replaced if a day is rolled up again.
+CREATE TABLE daily_stats (
+    day DATE PRIMARY KEY,
+    games BIGINT NOT NULL,
+    x_wins BIGINT NOT NULL,
+    o_wins BIGINT NOT NULL,
+    draws BIGINT NOT NULL,
+    average_moves DOUBLE PRECISION NOT NULL,
+    distinct_players BIGINT NOT NULL,
+    rolled_up_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,7 @@
+-- The engine experiment variant each game was put in, if any; see
+-- `experiments`. Results are tallied by experiment name.
+ALTER TABLE games
+    ADD COLUMN experiment JSONB;
+
+CREATE INDEX games_experiment_idx ON games ((experiment->>'experiment'))
+    WHERE experiment IS NOT NULL;
@@ -0,0 +1,8 @@
+-- Games against the engine, counted by difficulty in each daily rollup, and
+-- whether a game's blunder chance was picked by the tuner; see
+-- `calibration`. Days rolled up before this have no counts.
+ALTER TABLE daily_stats
+    ADD COLUMN by_difficulty JSONB NOT NULL DEFAULT '{}';
+
+ALTER TABLE games
+    ADD COLUMN auto_tuned BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,1327 @@
+//! Player accounts. Starting a session creates an anonymous guest account
+//! that games and results are recorded against from then on. A guest
+//! registers by picking a username and password, or by logging in with an
+//! OAuth provider; if that login already belongs to an account, the guest's
+//! games and stats are handed over to it instead.
+//!
+//! A session is a short-lived access token, sent as `Authorization: Bearer
+//! <token>`, plus a refresh token that trades for a fresh pair. Refresh
+//! tokens rotate on every use, and presenting one that was already used ends
+//! the session, since it must have leaked.
+//! Every account lives in one book behind a single lock, so moving a guest's
+//! games to another account happens all at once or not at all.
+
+use std::{collections::HashMap, fmt, num::NonZeroU32, sync::LazyLock};
+
+use chrono::{DateTime, Utc};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    config::AccountsConfig,
+    game::{GameStatus, Player},
+    state::{AppState, new_token},
+};
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 128;
+const MAX_USERNAME_LEN: usize = 24;
+/// The longest address SMTP can deliver to.
+const MAX_EMAIL_LEN: usize = 254;
+/// Browsers an account can get pushes in at once. Subscribing another
+/// drops the oldest.
+const MAX_PUSH_SUBSCRIPTIONS: usize = 10;
+
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Results of the finished games an account played.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Stats {
+    fn record(&mut self, player: Player, status: GameStatus) {
+        match status {
+            GameStatus::InProgress => {}
+            GameStatus::Draw => self.draws += 1,
+            GameStatus::Win(winner) if winner == player => self.wins += 1,
+            GameStatus::Win(_) => self.losses += 1,
+        }
+    }
+
+    fn absorb(&mut self, other: Stats) {
+        self.wins += other.wins;
+        self.losses += other.losses;
+        self.draws += other.draws;
+    }
+}
+
+/// A login with an OAuth provider.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Identity {
+    /// The provider's name in the configuration, such as `github`.
+    pub provider: String,
+    /// The provider's ID for the player.
+    pub subject: String,
+}
+
+/// What an account may do besides play. Each role may do everything the
+/// roles before it may.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Player,
+    /// Runs tournaments other players organized.
+    Moderator,
+    /// Uses the admin API, including handing out roles.
+    Admin,
+}
+
+/// A game an account has a seat in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnedGame {
+    pub game_id: Uuid,
+    pub player: Player,
+}
+
+/// Which emails an account wants.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notifications {
+    pub email: Option<String>,
+    /// Email when the opponent in a player-vs-player game has moved and the
+    /// player hasn't answered for a while.
+    pub your_move: bool,
+    /// Secret in every email's unsubscribe link, replaced whenever the
+    /// address changes.
+    pub unsubscribe_token: Option<String>,
+}
+
+/// A browser's Web Push subscription.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PushSubscription {
+    /// The push service's URL for the browser, which pushes are posted to.
+    pub endpoint: String,
+    /// The browser's P-256 public key, in base64url.
+    pub p256dh: String,
+    /// The browser's authentication secret, in base64url.
+    pub auth: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One logged-in client of an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub access_token: String,
+    pub access_expires_at: DateTime<Utc>,
+    pub refresh_token: String,
+    pub refresh_expires_at: DateTime<Utc>,
+    /// The refresh token this one replaced.
+    pub previous_refresh_token: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Session {
+    fn new(config: &AccountsConfig, now: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            access_token: new_token(),
+            access_expires_at: now + config.access_token_ttl(),
+            refresh_token: new_token(),
+            refresh_expires_at: now + config.refresh_token_ttl(),
+            previous_refresh_token: None,
+            created_at: now,
+        }
+    }
+
+    /// Replaces both tokens.
+    fn rotate(&mut self, config: &AccountsConfig, now: DateTime<Utc>) {
+        self.access_token = new_token();
+        self.access_expires_at = now + config.access_token_ttl();
+        let refresh_token = std::mem::replace(&mut self.refresh_token, new_token());
+        self.previous_refresh_token = Some(refresh_token);
+        self.refresh_expires_at = now + config.refresh_token_ttl();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub id: Uuid,
+    /// `None` while the account is a guest.
+    pub username: Option<String>,
+    /// `pbkdf2-sha256$<iterations>$<salt>$<hash>`, hex encoded.
+    pub password_hash: Option<String>,
+    /// OAuth logins linked to the account.
+    #[serde(default)]
+    pub identities: Vec<Identity>,
+    pub created_at: DateTime<Utc>,
+    pub registered_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub role: Role,
+    pub sessions: Vec<Session>,
+    /// Oldest first.
+    pub games: Vec<OwnedGame>,
+    pub stats: Stats,
+    #[serde(default)]
+    pub notifications: Notifications,
+    /// Oldest first.
+    #[serde(default)]
+    pub push_subscriptions: Vec<PushSubscription>,
+}
+
+impl Account {
+    fn guest(now: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            username: None,
+            password_hash: None,
+            identities: Vec::new(),
+            created_at: now,
+            registered_at: None,
+            role: Role::Player,
+            sessions: Vec::new(),
+            games: Vec::new(),
+            stats: Stats::default(),
+            notifications: Notifications::default(),
+            push_subscriptions: Vec::new(),
+        }
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.username.is_none() && self.identities.is_empty()
+    }
+
+    /// Takes over everything `guest` played, and its sessions.
+    fn absorb(&mut self, guest: Account) {
+        debug_assert!(guest.is_guest(), "only guests are merged");
+        for game in guest.games {
+            if !self.games.contains(&game) {
+                self.games.push(game);
+            }
+        }
+        self.stats.absorb(guest.stats);
+        self.sessions.extend(guest.sessions);
+        for subscription in guest.push_subscriptions {
+            self.add_push_subscription(subscription);
+        }
+    }
+
+    /// Adds a subscription, replacing any earlier one for the same browser.
+    fn add_push_subscription(&mut self, subscription: PushSubscription) {
+        self.push_subscriptions
+            .retain(|existing| existing.endpoint != subscription.endpoint);
+        self.push_subscriptions.push(subscription);
+        let excess = self
+            .push_subscriptions
+            .len()
+            .saturating_sub(MAX_PUSH_SUBSCRIPTIONS);
+        self.push_subscriptions.drain(..excess);
+    }
+
+    /// Starts a session, dropping any that can no longer be refreshed.
+    fn open_session(&mut self, config: &AccountsConfig, now: DateTime<Utc>) -> Session {
+        self.sessions
+            .retain(|session| session.refresh_expires_at > now);
+        let session = Session::new(config, now);
+        self.sessions.push(session.clone());
+        session
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountError {
+    /// The session token is unknown, or the session was ended.
+    InvalidSession,
+    /// The access token has expired; refreshing the session gets a new one.
+    SessionExpired,
+    InvalidCredentials,
+    AlreadyRegistered,
+    /// The OAuth login belongs to a different registered account.
+    #[cfg_attr(not(feature = "oauth"), allow(dead_code))]
+    IdentityTaken,
+    NotFound,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            AccountError::InvalidSession => "Session token is not valid",
+            AccountError::SessionExpired => "Access token has expired; refresh the session",
+            AccountError::InvalidCredentials => "Wrong username or password",
+            AccountError::AlreadyRegistered => "This session belongs to a registered account",
+            AccountError::IdentityTaken => "This login is linked to a different account",
+            AccountError::NotFound => "No such account or session",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+/// Lower-cased, so usernames are unique regardless of case.
+fn username_key(username: &str) -> String {
+    username.to_ascii_lowercase()
+}
+
+pub fn check_username(username: &str) -> Result<String, Error> {
+    let username = username.trim();
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !(3..=MAX_USERNAME_LEN).contains(&username.len()) || !valid_chars {
+        return Err(Error::BadRequest(
+            "username must be 3 to 24 letters, digits, '_' or '-'",
+        ));
+    }
+    Ok(username.to_string())
+}
+
+pub fn check_password(password: &str) -> Result<(), Error> {
+    if !(MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&password.chars().count()) {
+        return Err(Error::BadRequest("password must be 8 to 128 characters"));
+    }
+    Ok(())
+}
+
+/// Checks that `email` looks like an address. Whether it works only shows
+/// when mail is sent to it.
+pub fn check_email(email: &str) -> Result<String, Error> {
+    let email = email.trim();
+    let valid = email.len() <= MAX_EMAIL_LEN
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && domain.contains('.') && !domain.contains('@')
+        });
+    if !valid {
+        return Err(Error::BadRequest(
+            "email must be an address like ada@example.com",
+        ));
+    }
+    Ok(email.to_string())
+}
+
+fn iterations() -> NonZeroU32 {
+    NonZeroU32::new(PBKDF2_ITERATIONS).expect("iterations are non-zero")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations(),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+    format!(
+        "pbkdf2-sha256${}${}${}",
+        PBKDF2_ITERATIONS,
+        hex(&salt),
+        hex(&hash)
+    )
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some("pbkdf2-sha256"), Some(iterations), Some(salt), Some(hash), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return false;
+    };
+    let (Some(iterations), Some(salt), Some(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        unhex(salt),
+        unhex(hash),
+    ) else {
+        return false;
+    };
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+/// Runs `hash_password` on the blocking pool: it is slow on purpose, and
+/// would otherwise stall every other request on the same worker.
+async fn hash_off_runtime(password: &str) -> Result<String, Error> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|_| Error::Internal)
+}
+
+/// Checked in place of a missing password hash, so that unknown usernames
+/// take as long to turn away as wrong passwords and can't be told apart.
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| hash_password(&Uuid::new_v4().to_string()));
+
+/// Runs `verify_password` on the blocking pool, as `hash_off_runtime` does.
+/// A missing hash never matches, but takes as long to check.
+async fn verify_off_runtime(password: &str, stored: Option<String>) -> Result<bool, Error> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || match stored {
+        Some(stored) => verify_password(&password, &stored),
+        None => {
+            verify_password(&password, &DUMMY_HASH);
+            false
+        }
+    })
+    .await
+    .map_err(|_| Error::Internal)
+}
+
+/// Every account, with indexes by token, username, and game.
+#[derive(Debug, Default)]
+struct Book {
+    accounts: HashMap<Uuid, Account>,
+    /// By access token.
+    sessions: HashMap<String, Uuid>,
+    /// By current and previous refresh token.
+    refresh_tokens: HashMap<String, Uuid>,
+    usernames: HashMap<String, Uuid>,
+    identities: HashMap<Identity, Uuid>,
+    /// Accounts with a seat in each game.
+    owners: HashMap<Uuid, Vec<Uuid>>,
+}
+
+impl Book {
+    /// Adds `account`, replacing any earlier version of it.
+    fn insert(&mut self, account: Account) {
+        self.remove(account.id);
+        for session in &account.sessions {
+            self.sessions
+                .insert(session.access_token.clone(), account.id);
+            self.refresh_tokens
+                .insert(session.refresh_token.clone(), account.id);
+            if let Some(previous) = &session.previous_refresh_token {
+                self.refresh_tokens.insert(previous.clone(), account.id);
+            }
+        }
+        if let Some(username) = &account.username {
+            self.usernames.insert(username_key(username), account.id);
+        }
+        for identity in &account.identities {
+            self.identities.insert(identity.clone(), account.id);
+        }
+        for game in &account.games {
+            let owners = self.owners.entry(game.game_id).or_default();
+            if !owners.contains(&account.id) {
+                owners.push(account.id);
+            }
+        }
+        self.accounts.insert(account.id, account);
+    }
+
+    fn remove(&mut self, id: Uuid) -> Option<Account> {
+        let account = self.accounts.remove(&id)?;
+        for session in &account.sessions {
+            self.sessions.remove(&session.access_token);
+            self.refresh_tokens.remove(&session.refresh_token);
+            if let Some(previous) = &session.previous_refresh_token {
+                self.refresh_tokens.remove(previous);
+            }
+        }
+        if let Some(username) = &account.username {
+            self.usernames.remove(&username_key(username));
+        }
+        for identity in &account.identities {
+            self.identities.remove(identity);
+        }
+        for game in &account.games {
+            if let Some(owners) = self.owners.get_mut(&game.game_id) {
+                owners.retain(|owner| *owner != id);
+                if owners.is_empty() {
+                    self.owners.remove(&game.game_id);
+                }
+            }
+        }
+        Some(account)
+    }
+
+    /// The account and session `access_token` belongs to.
+    fn by_session(
+        &self,
+        access_token: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(&Account, &Session), AccountError> {
+        let (account, session) = self
+            .sessions
+            .get(access_token)
+            .and_then(|id| self.accounts.get(id))
+            .and_then(|account| {
+                let session = account
+                    .sessions
+                    .iter()
+                    .find(|session| session.access_token == access_token)?;
+                Some((account, session))
+            })
+            .ok_or(AccountError::InvalidSession)?;
+        if session.access_expires_at <= now {
+            return Err(AccountError::SessionExpired);
+        }
+        Ok((account, session))
+    }
+
+    fn by_username(&self, username: &str) -> Option<&Account> {
+        self.usernames
+            .get(&username_key(username))
+            .and_then(|id| self.accounts.get(id))
+    }
+}
+
+#[derive(Debug)]
+pub struct Accounts {
+    book: Mutex<Book>,
+    /// Held while writing an account to the store, so a slow store holds up
+    /// other writes but not the book.
+    saving: Mutex<()>,
+    config: AccountsConfig,
+}
+
+impl Accounts {
+    pub fn new(config: AccountsConfig) -> Self {
+        Self {
+            book: Mutex::new(Book::default()),
+            saving: Mutex::new(()),
+            config,
+        }
+    }
+
+    /// Puts accounts loaded from storage back.
+    pub async fn restore(&self, accounts: Vec<Account>) {
+        let mut book = self.book.lock().await;
+        for account in accounts {
+            book.insert(account);
+        }
+    }
+
+    /// The ID of the account `access_token` is a session for.
+    pub async fn session(&self, access_token: &str) -> Result<Uuid, AccountError> {
+        let book = self.book.lock().await;
+        book.by_session(access_token, Utc::now())
+            .map(|(account, _)| account.id)
+    }
+
+    /// Like `session`, along with the account's role.
+    pub async fn session_role(&self, access_token: &str) -> Result<(Uuid, Role), AccountError> {
+        let book = self.book.lock().await;
+        book.by_session(access_token, Utc::now())
+            .map(|(account, _)| (account.id, account.role))
+    }
+}
+
+// --- Operations ---
+
+/// Creates a guest account and returns it with its first session.
+pub async fn start_guest(state: &AppState) -> Result<(Account, Session), Error> {
+    let now = Utc::now();
+    let mut account = Account::guest(now);
+    let session = account.open_session(&state.accounts.config, now);
+    let mut book = state.accounts.book.lock().await;
+    state
+        .store
+        .save_account(&account)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(account.clone());
+    log::info!("Started guest account {}", account.id);
+    Ok((account, session))
+}
+
+/// The account behind `access_token`, and the ID of the session.
+pub async fn get(state: &AppState, access_token: &str) -> Result<(Account, Uuid), Error> {
+    let book = state.accounts.book.lock().await;
+    book.by_session(access_token, Utc::now())
+        .map(|(account, session)| (account.clone(), session.id))
+        .map_err(Error::Account)
+}
+
+/// Trades a refresh token for a new access and refresh token. A refresh
+/// token that was already used ends its session.
+pub async fn refresh(state: &AppState, refresh_token: &str) -> Result<(Account, Session), Error> {
+    let now = Utc::now();
+    let mut book = state.accounts.book.lock().await;
+    let mut account = book
+        .refresh_tokens
+        .get(refresh_token)
+        .and_then(|id| book.accounts.get(id))
+        .cloned()
+        .ok_or(Error::Account(AccountError::InvalidSession))?;
+    let index = account
+        .sessions
+        .iter()
+        .position(|session| {
+            session.refresh_token == refresh_token
+                || session.previous_refresh_token.as_deref() == Some(refresh_token)
+        })
+        .ok_or(Error::Account(AccountError::InvalidSession))?;
+    let session = &mut account.sessions[index];
+    let reused = session.refresh_token != refresh_token;
+    if reused || session.refresh_expires_at <= now {
+        let session = account.sessions.remove(index);
+        if reused {
+            log::warn!(
+                "Refresh token reused for session {} of account {}; ending the session",
+                session.id,
+                account.id
+            );
+        }
+        state
+            .store
+            .save_account(&account)
+            .await
+            .map_err(Error::Storage)?;
+        book.insert(account);
+        return Err(Error::Account(AccountError::InvalidSession));
+    }
+    session.rotate(&state.accounts.config, now);
+    let session = session.clone();
+    state
+        .store
+        .save_account(&account)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(account.clone());
+    Ok((account, session))
+}
+
+/// Ends the sessions of `account_id` that `which` picks, and returns how
+/// many were ended.
+async fn end_sessions(
+    state: &AppState,
+    book: &mut Book,
+    account_id: Uuid,
+    which: impl Fn(&Session) -> bool,
+) -> Result<usize, Error> {
+    let Some(account) = book.accounts.get(&account_id) else {
+        return Ok(0);
+    };
+    let mut updated = account.clone();
+    updated.sessions.retain(|session| !which(session));
+    let ended = account.sessions.len() - updated.sessions.len();
+    if ended > 0 {
+        state
+            .store
+            .save_account(&updated)
+            .await
+            .map_err(Error::Storage)?;
+        book.insert(updated);
+    }
+    Ok(ended)
+}
+
+/// Ends the session `access_token` belongs to; with `everywhere`, every
+/// session of its account. Returns how many sessions were ended.
+pub async fn log_out(
+    state: &AppState,
+    access_token: &str,
+    everywhere: bool,
+) -> Result<usize, Error> {
+    let mut book = state.accounts.book.lock().await;
+    let (account, session) = book
+        .by_session(access_token, Utc::now())
+        .map_err(Error::Account)?;
+    let (account_id, session_id) = (account.id, session.id);
+    end_sessions(state, &mut book, account_id, |session| {
+        everywhere || session.id == session_id
+    })
+    .await
+}
+
+/// Ends every session of an account, for admins. Returns how many were ended.
+pub async fn revoke_account_sessions(state: &AppState, account_id: Uuid) -> Result<usize, Error> {
+    let mut book = state.accounts.book.lock().await;
+    if !book.accounts.contains_key(&account_id) {
+        return Err(Error::Account(AccountError::NotFound));
+    }
+    end_sessions(state, &mut book, account_id, |_| true).await
+}
+
+/// Ends one session of any account, for admins.
+pub async fn revoke_session(state: &AppState, session_id: Uuid) -> Result<(), Error> {
+    let mut book = state.accounts.book.lock().await;
+    let account_id = book
+        .accounts
+        .values()
+        .find(|account| {
+            account
+                .sessions
+                .iter()
+                .any(|session| session.id == session_id)
+        })
+        .map(|account| account.id)
+        .ok_or(Error::Account(AccountError::NotFound))?;
+    end_sessions(state, &mut book, account_id, |session| {
+        session.id == session_id
+    })
+    .await?;
+    Ok(())
+}
+
+/// Gives an account a role, for admins. Guests can only be players.
+pub async fn set_role(state: &AppState, account_id: Uuid, role: Role) -> Result<Account, Error> {
+    let mut book = state.accounts.book.lock().await;
+    let account = book
+        .accounts
+        .get(&account_id)
+        .ok_or(Error::Account(AccountError::NotFound))?;
+    if account.is_guest() && role != Role::Player {
+        return Err(Error::BadRequest("Guests can't be given a role"));
+    }
+    let mut updated = account.clone();
+    updated.role = role;
+    state
+        .store
+        .save_account(&updated)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(updated.clone());
+    Ok(updated)
+}
+
+/// Sets which emails the account behind `token` gets, and where. Guests
+/// can't get any, since they can't log back in to change them.
+pub async fn set_notifications(
+    state: &AppState,
+    token: &str,
+    email: Option<String>,
+    your_move: bool,
+) -> Result<Account, Error> {
+    let mut book = state.accounts.book.lock().await;
+    let (account, _) = book.by_session(token, Utc::now()).map_err(Error::Account)?;
+    if account.is_guest() {
+        return Err(Error::BadRequest("Guests can't get emails; register first"));
+    }
+    let mut updated = account.clone();
+    let notifications = &mut updated.notifications;
+    if email != notifications.email {
+        notifications.unsubscribe_token = email.as_ref().map(|_| new_token());
+        notifications.email = email;
+    }
+    notifications.your_move = your_move && notifications.email.is_some();
+    state
+        .store
+        .save_account(&updated)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(updated.clone());
+    Ok(updated)
+}
+
+/// Turns off every email of the account whose unsubscribe link held
+/// `token`.
+pub async fn unsubscribe(state: &AppState, token: &str) -> Result<(), Error> {
+    let mut book = state.accounts.book.lock().await;
+    let account = book
+        .accounts
+        .values()
+        .find(|account| account.notifications.unsubscribe_token.as_deref() == Some(token))
+        .ok_or(Error::Account(AccountError::NotFound))?;
+    if !account.notifications.your_move {
+        return Ok(());
+    }
+    let mut updated = account.clone();
+    updated.notifications.your_move = false;
+    state
+        .store
+        .save_account(&updated)
+        .await
+        .map_err(Error::Storage)?;
+    log::info!("Account {} unsubscribed from emails", updated.id);
+    book.insert(updated);
+    Ok(())
+}
+
+/// Sends pushes for the account behind `token` to another browser.
+#[cfg_attr(not(feature = "webpush"), allow(dead_code))]
+pub async fn subscribe_push(
+    state: &AppState,
+    token: &str,
+    subscription: PushSubscription,
+) -> Result<(), Error> {
+    let mut book = state.accounts.book.lock().await;
+    let (account, _) = book.by_session(token, Utc::now()).map_err(Error::Account)?;
+    let mut updated = account.clone();
+    updated.add_push_subscription(subscription);
+    state
+        .store
+        .save_account(&updated)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(updated);
+    Ok(())
+}
+
+/// Stops pushes to the browser behind `endpoint`, for the account behind
+/// `token` if `token` is given and for any account otherwise. Returns
+/// whether it was subscribed.
+#[cfg_attr(not(feature = "webpush"), allow(dead_code))]
+pub async fn unsubscribe_push(
+    state: &AppState,
+    token: Option<&str>,
+    endpoint: &str,
+) -> Result<bool, Error> {
+    let mut book = state.accounts.book.lock().await;
+    let account = match token {
+        Some(token) => {
+            book.by_session(token, Utc::now())
+                .map_err(Error::Account)?
+                .0
+        }
+        None => match book.accounts.values().find(|account| {
+            account
+                .push_subscriptions
+                .iter()
+                .any(|subscription| subscription.endpoint == endpoint)
+        }) {
+            Some(account) => account,
+            None => return Ok(false),
+        },
+    };
+    let mut updated = account.clone();
+    updated
+        .push_subscriptions
+        .retain(|subscription| subscription.endpoint != endpoint);
+    if updated.push_subscriptions.len() == account.push_subscriptions.len() {
+        return Ok(false);
+    }
+    state
+        .store
+        .save_account(&updated)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(updated);
+    Ok(true)
+}
+
+/// The accounts playing `player` in a game, for notifying them.
+pub async fn seated(state: &AppState, game_id: Uuid, player: Player) -> Vec<Account> {
+    let book = state.accounts.book.lock().await;
+    let owners = book.owners.get(&game_id).cloned().unwrap_or_default();
+    owners
+        .iter()
+        .filter_map(|owner| book.accounts.get(owner))
+        .filter(|account| account.games.contains(&OwnedGame { game_id, player }))
+        .cloned()
+        .collect()
+}
+
+/// Turns the guest account behind `token` into a registered one. If
+/// `username` is taken and `password` is its password, the guest's games,
+/// stats, and sessions move to that account and the guest goes away.
+pub async fn register(
+    state: &AppState,
+    token: &str,
+    username: String,
+    password: &str,
+) -> Result<Account, Error> {
+    // The password is hashed or checked without holding the book, which
+    // every signed-in request needs, so the book is looked at again after.
+    let existing = {
+        let book = state.accounts.book.lock().await;
+        registrant(&book, token)?;
+        book.by_username(&username)
+            .map(|existing| (existing.id, existing.password_hash.clone()))
+    };
+
+    if let Some((existing_id, hash)) = existing {
+        if !verify_off_runtime(password, hash.clone()).await? {
+            return Err(Error::Account(AccountError::InvalidCredentials));
+        }
+        let mut book = state.accounts.book.lock().await;
+        let guest = registrant(&book, token)?;
+        let existing = book
+            .accounts
+            .get(&existing_id)
+            .filter(|existing| existing.password_hash == hash)
+            .cloned()
+            .ok_or(Error::Account(AccountError::InvalidCredentials))?;
+        return merge(state, &mut book, guest, existing).await;
+    }
+
+    let password_hash = hash_off_runtime(password).await?;
+    let mut book = state.accounts.book.lock().await;
+    let mut registered = registrant(&book, token)?;
+    // Someone else took the username while the password was hashed.
+    if book.by_username(&username).is_some() {
+        return Err(Error::Account(AccountError::InvalidCredentials));
+    }
+    registered.username = Some(username);
+    registered.password_hash = Some(password_hash);
+    registered.registered_at = Some(Utc::now());
+    // In the book first, which holds the username while it is saved.
+    book.insert(registered.clone());
+    drop(book);
+    if let Err(e) = save(state, registered.id).await {
+        update_in_book(state, registered.id, |account| {
+            account.username = None;
+            account.password_hash = None;
+            account.registered_at = None;
+        })
+        .await;
+        return Err(e);
+    }
+    log::info!("Registered guest account {}", registered.id);
+    Ok(registered)
+}
+
+/// Writes the latest version of `account_id` through to the store, without
+/// holding the book while it does.
+async fn save(state: &AppState, account_id: Uuid) -> Result<(), Error> {
+    let _saving = state.accounts.saving.lock().await;
+    // Taken after waiting our turn, so a write that was overtaken can't put
+    // back an older version.
+    let Some(account) = state
+        .accounts
+        .book
+        .lock()
+        .await
+        .accounts
+        .get(&account_id)
+        .cloned()
+    else {
+        return Ok(());
+    };
+    state
+        .store
+        .save_account(&account)
+        .await
+        .map_err(Error::Storage)
+}
+
+/// Applies `change` to the account in the book, e.g. to take back a change
+/// that couldn't be saved.
+async fn update_in_book(state: &AppState, account_id: Uuid, change: impl FnOnce(&mut Account)) {
+    let mut book = state.accounts.book.lock().await;
+    if let Some(mut account) = book.accounts.get(&account_id).cloned() {
+        change(&mut account);
+        book.insert(account);
+    }
+}
+
+/// The guest account behind `token`, which is about to register.
+fn registrant(book: &Book, token: &str) -> Result<Account, Error> {
+    let (guest, _) = book.by_session(token, Utc::now()).map_err(Error::Account)?;
+    if !guest.is_guest() {
+        return Err(Error::Account(AccountError::AlreadyRegistered));
+    }
+    Ok(guest.clone())
+}
+
+/// Hands `guest`'s games, stats, and sessions to `account` and removes the
+/// guest, in storage and in `book` alike.
+async fn merge(
+    state: &AppState,
+    book: &mut Book,
+    guest: Account,
+    mut account: Account,
+) -> Result<Account, Error> {
+    let guest_id = guest.id;
+    account.absorb(guest);
+    state
+        .store
+        .merge_accounts(guest_id, &account)
+        .await
+        .map_err(Error::Storage)?;
+    book.remove(guest_id);
+    book.insert(account.clone());
+    log::info!("Merged guest account {} into {}", guest_id, account.id);
+    Ok(account)
+}
+
+/// Starts a new session for the account `identity` belongs to. A first login
+/// creates the account, or links it to `linking`: the account of the session
+/// the login was started from. A guest session that logs in to an existing
+/// account is merged into it.
+#[cfg_attr(not(feature = "oauth"), allow(dead_code))]
+pub async fn login_with_identity(
+    state: &AppState,
+    identity: Identity,
+    linking: Option<Uuid>,
+) -> Result<(Account, Session), Error> {
+    let now = Utc::now();
+    let mut book = state.accounts.book.lock().await;
+    let linking = linking.and_then(|id| book.accounts.get(&id)).cloned();
+    let existing = book
+        .identities
+        .get(&identity)
+        .and_then(|id| book.accounts.get(id))
+        .cloned();
+    let mut account = match (existing, linking) {
+        (Some(existing), Some(linking)) if existing.id != linking.id => {
+            if !linking.is_guest() {
+                return Err(Error::Account(AccountError::IdentityTaken));
+            }
+            merge(state, &mut book, linking, existing).await?
+        }
+        (Some(existing), _) => existing,
+        (None, linking) => {
+            let mut account = linking.unwrap_or_else(|| Account::guest(now));
+            if account.is_guest() {
+                account.registered_at = Some(now);
+            }
+            account.identities.push(identity);
+            account
+        }
+    };
+    let session = account.open_session(&state.accounts.config, now);
+    state
+        .store
+        .save_account(&account)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(account.clone());
+    Ok((account, session))
+}
+
+/// Starts a new session for a registered account.
+pub async fn login(
+    state: &AppState,
+    username: &str,
+    password: &str,
+) -> Result<(Account, Session), Error> {
+    let (account_id, hash) = {
+        let book = state.accounts.book.lock().await;
+        match book.by_username(username) {
+            Some(account) => (Some(account.id), account.password_hash.clone()),
+            None => (None, None),
+        }
+    };
+    // Checked even for unknown usernames, which would otherwise be turned
+    // away quicker than wrong passwords.
+    let verified = verify_off_runtime(password, hash.clone()).await?;
+    let Some(account_id) = account_id.filter(|_| verified) else {
+        return Err(Error::Account(AccountError::InvalidCredentials));
+    };
+    let mut book = state.accounts.book.lock().await;
+    // The account may have gone, or its password changed, in the meantime.
+    let mut account = book
+        .accounts
+        .get(&account_id)
+        .filter(|account| account.password_hash == hash)
+        .cloned()
+        .ok_or(Error::Account(AccountError::InvalidCredentials))?;
+    let session = account.open_session(&state.accounts.config, Utc::now());
+    book.insert(account.clone());
+    drop(book);
+    if let Err(e) = save(state, account_id).await {
+        // A session that wasn't saved isn't handed out.
+        update_in_book(state, account_id, |account| {
+            account.sessions.retain(|open| open.id != session.id);
+        })
+        .await;
+        return Err(e);
+    }
+    Ok((account, session))
+}
+
+/// Records that `account_id` plays `player` in a new game.
+pub async fn adopt(
+    state: &AppState,
+    account_id: Uuid,
+    game_id: Uuid,
+    player: Player,
+) -> Result<(), Error> {
+    let mut book = state.accounts.book.lock().await;
+    let Some(account) = book.accounts.get(&account_id) else {
+        return Err(Error::Account(AccountError::InvalidSession));
+    };
+    let mut updated = account.clone();
+    updated.games.push(OwnedGame { game_id, player });
+    state
+        .store
+        .save_account(&updated)
+        .await
+        .map_err(Error::Storage)?;
+    book.insert(updated);
+    Ok(())
+}
+
+/// Adds a finished game's result to the stats of every account in it.
+pub async fn record_result(
+    state: &AppState,
+    game_id: Uuid,
+    status: GameStatus,
+) -> Result<(), Error> {
+    let mut book = state.accounts.book.lock().await;
+    let owners = book.owners.get(&game_id).cloned().unwrap_or_default();
+    for owner in owners {
+        let Some(account) = book.accounts.get(&owner) else {
+            continue;
+        };
+        let mut updated = account.clone();
+        for game in account.games.iter().filter(|game| game.game_id == game_id) {
+            updated.stats.record(game.player, status);
+        }
+        state
+            .store
+            .save_account(&updated)
+            .await
+            .map_err(Error::Storage)?;
+        book.insert(updated);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{state::GameRegistry, store::MemoryStore};
+
+    #[test]
+    fn test_passwords_verify_against_their_hash_only() {
+        let hash = hash_password("correct horse");
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct hors", &hash));
+        assert_ne!(hash, hash_password("correct horse"));
+        assert!(!verify_password("correct horse", "plaintext"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_usernames_are_refused_like_wrong_passwords() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let (_, guest) = start_guest(&state).await.unwrap();
+        register(&state, &guest.access_token, "Ada".to_string(), "hunter22")
+            .await
+            .unwrap();
+        for (username, password) in [("Ada", "hunter2"), ("Grace", "hunter22")] {
+            assert!(matches!(
+                login(&state, username, password).await,
+                Err(Error::Account(AccountError::InvalidCredentials))
+            ));
+        }
+        assert!(!verify_off_runtime("", None).await.unwrap());
+        login(&state, "ada", "hunter22").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_registering_into_an_existing_account_moves_the_guest_over() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let (_, first) = start_guest(&state).await.unwrap();
+        let first = first.access_token;
+        let game = Uuid::new_v4();
+        let owner = state.accounts.session(&first).await.unwrap();
+        adopt(&state, owner, game, Player::X).await.unwrap();
+        record_result(&state, game, GameStatus::Win(Player::X))
+            .await
+            .unwrap();
+        let account = register(&state, &first, "Ada".to_string(), "hunter22")
+            .await
+            .unwrap();
+        assert_eq!(account.id, owner);
+        assert!(matches!(
+            register(&state, &first, "ada2".to_string(), "hunter22").await,
+            Err(Error::Account(AccountError::AlreadyRegistered))
+        ));
+
+        // A second guest session, e.g. on another device.
+        let (guest, second) = start_guest(&state).await.unwrap();
+        let second = second.access_token;
+        let other_game = Uuid::new_v4();
+        adopt(&state, guest.id, other_game, Player::O)
+            .await
+            .unwrap();
+        record_result(&state, other_game, GameStatus::Draw)
+            .await
+            .unwrap();
+        assert!(matches!(
+            register(&state, &second, "ADA".to_string(), "wrong password").await,
+            Err(Error::Account(AccountError::InvalidCredentials))
+        ));
+
+        let merged = register(&state, &second, "ADA".to_string(), "hunter22")
+            .await
+            .unwrap();
+        assert_eq!(merged.id, owner);
+        assert_eq!(merged.games.len(), 2);
+        assert_eq!(
+            merged.stats,
+            Stats {
+                wins: 1,
+                losses: 0,
+                draws: 1
+            }
+        );
+        assert_eq!(state.accounts.session(&second).await, Ok(owner));
+        let book = state.accounts.book.lock().await;
+        assert!(!book.accounts.contains_key(&guest.id));
+        assert_eq!(book.owners[&other_game], vec![owner]);
+    }
+
+    #[tokio::test]
+    async fn test_oauth_logins_link_or_merge_guests() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let identity = Identity {
+            provider: "github".to_string(),
+            subject: "42".to_string(),
+        };
+        let (guest, _) = start_guest(&state).await.unwrap();
+        let (linked, _) = login_with_identity(&state, identity.clone(), Some(guest.id))
+            .await
+            .unwrap();
+        assert_eq!(linked.id, guest.id);
+        assert!(!linked.is_guest());
+
+        // Logging in again from a new device finds the same account...
+        let (again, session) = login_with_identity(&state, identity.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(again.id, guest.id);
+        assert_eq!(
+            state.accounts.session(&session.access_token).await,
+            Ok(guest.id)
+        );
+
+        // ...and brings along what was played there as a guest.
+        let (second, second_session) = start_guest(&state).await.unwrap();
+        adopt(&state, second.id, Uuid::new_v4(), Player::O)
+            .await
+            .unwrap();
+        let (merged, _) = login_with_identity(&state, identity.clone(), Some(second.id))
+            .await
+            .unwrap();
+        assert_eq!(merged.id, guest.id);
+        assert_eq!(merged.games.len(), 1);
+        assert_eq!(
+            state.accounts.session(&second_session.access_token).await,
+            Ok(guest.id)
+        );
+
+        // A registered account can't take over someone else's login.
+        let (other, other_session) = start_guest(&state).await.unwrap();
+        register(
+            &state,
+            &other_session.access_token,
+            "bob".to_string(),
+            "hunter22",
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            login_with_identity(&state, identity, Some(other.id)).await,
+            Err(Error::Account(AccountError::IdentityTaken))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens_rotate_and_reuse_ends_the_session() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let (account, first) = start_guest(&state).await.unwrap();
+        let (_, refreshed) = refresh(&state, &first.refresh_token).await.unwrap();
+        assert_eq!(refreshed.id, first.id);
+        assert_ne!(refreshed.access_token, first.access_token);
+        assert_eq!(
+            state.accounts.session(&first.access_token).await,
+            Err(AccountError::InvalidSession)
+        );
+        assert_eq!(
+            state.accounts.session(&refreshed.access_token).await,
+            Ok(account.id)
+        );
+
+        // Replaying the old refresh token means it leaked: the session ends.
+        assert!(matches!(
+            refresh(&state, &first.refresh_token).await,
+            Err(Error::Account(AccountError::InvalidSession))
+        ));
+        assert_eq!(
+            state.accounts.session(&refreshed.access_token).await,
+            Err(AccountError::InvalidSession)
+        );
+        assert!(refresh(&state, &refreshed.refresh_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_access_tokens_and_logging_out_everywhere() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let (_, guest) = start_guest(&state).await.unwrap();
+        register(&state, &guest.access_token, "ada".to_string(), "hunter22")
+            .await
+            .unwrap();
+        let (_, laptop) = login(&state, "ada", "hunter22").await.unwrap();
+        let (_, phone) = login(&state, "ada", "hunter22").await.unwrap();
+
+        {
+            let mut book = state.accounts.book.lock().await;
+            let mut account = book
+                .by_session(&phone.access_token, Utc::now())
+                .unwrap()
+                .0
+                .clone();
+            account.sessions.last_mut().unwrap().access_expires_at = Utc::now();
+            book.insert(account);
+        }
+        assert_eq!(
+            state.accounts.session(&phone.access_token).await,
+            Err(AccountError::SessionExpired)
+        );
+        let (_, phone) = refresh(&state, &phone.refresh_token).await.unwrap();
+
+        assert_eq!(
+            log_out(&state, &laptop.access_token, false).await.unwrap(),
+            1
+        );
+        assert!(state.accounts.session(&laptop.access_token).await.is_err());
+        assert_eq!(log_out(&state, &phone.access_token, true).await.unwrap(), 2);
+        assert!(state.accounts.session(&guest.access_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_only_registered_accounts_get_roles() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let (guest, session) = start_guest(&state).await.unwrap();
+        assert!(matches!(
+            set_role(&state, guest.id, Role::Moderator).await,
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(
+            set_role(&state, Uuid::new_v4(), Role::Admin).await,
+            Err(Error::Account(AccountError::NotFound))
+        ));
+
+        register(&state, &session.access_token, "ada".to_string(), "hunter22")
+            .await
+            .unwrap();
+        set_role(&state, guest.id, Role::Moderator).await.unwrap();
+        let (id, role) = state
+            .accounts
+            .session_role(&session.access_token)
+            .await
+            .unwrap();
+        assert_eq!((id, role), (guest.id, Role::Moderator));
+        assert!(Role::Admin > Role::Moderator && Role::Moderator > Role::Player);
+    }
+}
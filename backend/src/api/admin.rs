@@ -0,0 +1,716 @@
+//! Operational endpoints under `/admin`, for accounts with the admin role
+//! and for holders of the admin token, which is separate from anything
+//! players use.
+
+use std::{
+    net::IpAddr,
+    sync::{Arc, atomic::Ordering},
+};
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{FromRef, Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Admin, ClientIp, RequireRole, bearer_token, constant_time_eq};
+use crate::{
+    Error,
+    account::{self, Role},
+    archive,
+    audit::{self, Action, Actor, AuditEntry},
+    drain::{self, Progress},
+    experiments::{self, VariantResults},
+    export,
+    flags::{Flag, FlagList},
+    game::GameStatus,
+    import::{self, DumpedGame},
+    search::{self, FoundGame, GameQuery},
+    state::{AppState, GameEntry},
+};
+
+pub fn router(state: AppState, token: Option<&str>) -> Router<AppState> {
+    Router::new()
+        .route("/games", get(list_games))
+        .route("/games/{game_id}", delete(delete_game))
+        .route("/archive", get(list_archive))
+        .route("/archive/{game_id}/restore", post(restore_game))
+        .route("/export/games", get(export_games))
+        .route("/import/games", post(import_games))
+        .route("/stats", get(registry_stats))
+        .route("/maintenance", get(get_maintenance).put(set_maintenance))
+        .route("/drain", get(get_drain).post(start_drain))
+        .route("/flags", get(list_flags))
+        .route("/flags/{flag}", put(set_flag).delete(clear_flag))
+        .route("/experiments", get(experiment_results))
+        .route(
+            "/accounts/{account_id}/sessions",
+            delete(revoke_account_sessions),
+        )
+        .route("/sessions/{session_id}", delete(revoke_session))
+        .route("/accounts/{account_id}/role", put(set_role))
+        .route("/audit", get(get_audit_log))
+        .route_layer(middleware::from_fn_with_state(
+            AdminAuth {
+                token: token.map(Arc::from),
+                state,
+            },
+            require_admin,
+        ))
+}
+
+#[derive(Clone)]
+struct AdminAuth {
+    token: Option<Arc<str>>,
+    state: AppState,
+}
+
+impl FromRef<AdminAuth> for AppState {
+    fn from_ref(auth: &AdminAuth) -> Self {
+        auth.state.clone()
+    }
+}
+
+/// Lets the request through with the admin token or an admin's session.
+async fn require_admin(
+    State(auth): State<AdminAuth>,
+    admin: Result<RequireRole<Admin>, Error>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(given) = bearer_token(request.headers()) else {
+        return Error::Unauthorized("Missing or invalid admin token").into_response();
+    };
+    let has_token = auth
+        .token
+        .is_some_and(|token| constant_time_eq(given.as_bytes(), token.as_bytes()));
+    match admin {
+        _ if has_token => next.run(request).await,
+        Ok(_) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Who is acting as admin: an admin's account, or else the token holder.
+fn admin_actor(admin: Option<RequireRole<Admin>>) -> Actor {
+    admin.map_or(Actor::AdminToken, |admin| Actor::Account {
+        account_id: admin.account_id,
+    })
+}
+
+/// Narrows a listing to one tenant's games.
+#[derive(Debug, Deserialize)]
+struct TenantQuery {
+    tenant: Option<String>,
+}
+
+impl TenantQuery {
+    fn includes(&self, entry: &GameEntry) -> bool {
+        self.tenant.is_none() || entry.tenant == self.tenant
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GameSummary {
+    game_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    status: GameStatus,
+    version: u64,
+    moves: u64,
+    created_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archived_at: Option<DateTime<Utc>>,
+    /// `None` for games only in storage.
+    approximate_bytes: Option<usize>,
+}
+
+impl GameSummary {
+    async fn new(state: &AppState, found: FoundGame) -> Self {
+        let FoundGame {
+            game_id,
+            created_at,
+            entry,
+        } = found;
+        let approximate_bytes = match state.game(&game_id) {
+            Some(game) => Some(game.lock().await.approximate_size()),
+            None => None,
+        };
+        Self {
+            game_id,
+            tenant: entry.tenant,
+            status: entry.state.status,
+            version: entry.state.version,
+            // Every accepted move, and nothing else, bumps the version.
+            moves: entry.state.version,
+            created_at,
+            finished_at: entry.finished_at,
+            archived_at: entry.archived_at,
+            approximate_bytes,
+        }
+    }
+}
+
+/// Finds games by player, variant, status, creation time, and tenant, in
+/// progress or archived, newest first unless sorted otherwise.
+async fn list_games(
+    State(state): State<AppState>,
+    Query(query): Query<GameQuery>,
+) -> Result<Json<Vec<GameSummary>>, Error> {
+    search_games(&state, &query).await.map(Json)
+}
+
+/// Like `list_games`, for archived games only.
+async fn list_archive(
+    State(state): State<AppState>,
+    Query(query): Query<GameQuery>,
+) -> Result<Json<Vec<GameSummary>>, Error> {
+    let query = GameQuery {
+        archived: Some(true),
+        ..query
+    };
+    search_games(&state, &query).await.map(Json)
+}
+
+async fn search_games(state: &AppState, query: &GameQuery) -> Result<Vec<GameSummary>, Error> {
+    let found = match state
+        .store
+        .search_games(query)
+        .await
+        .map_err(Error::Storage)?
+    {
+        Some(found) => found,
+        None => search::in_registry(&state.snapshot_for_store().await, query),
+    };
+    let mut games = Vec::with_capacity(found.len());
+    for found in found {
+        games.push(GameSummary::new(state, found).await);
+    }
+    Ok(games)
+}
+
+/// Brings an archived game back, readable by its players again until it
+/// expires once more.
+async fn restore_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+) -> Result<StatusCode, Error> {
+    archive::restore(&state, game_id).await?;
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::RestoreGame { game_id },
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a game from the registry or the archive, and from storage,
+/// whatever its status.
+async fn delete_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+) -> Result<StatusCode, Error> {
+    match state.game(&game_id) {
+        Some(game) => {
+            // Wait for any move in flight so it can't write the game back
+            // afterwards.
+            let _entry = game.lock().await;
+            state
+                .store
+                .delete_game(game_id)
+                .await
+                .map_err(Error::Storage)?;
+            state.games.remove(&game_id);
+        }
+        None if archive::delete(&state, game_id).await? => {}
+        None => return Err(Error::GameNotFound(game_id)),
+    }
+    log::warn!("Admin deleted game {}", game_id);
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::DeleteGame { game_id },
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    since: Option<DateTime<Utc>>,
+}
+
+/// Streams every game, live or archived, or only those changed at or after
+/// `?since=`, as newline-delimited JSON.
+async fn export_games(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+) -> Response {
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::ExportGames { since: query.since },
+    )
+    .await;
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(export::games(state, query.since)),
+    )
+        .into_response()
+}
+
+/// The longest line of a dump read on an import.
+const MAX_IMPORT_LINE_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Default, Serialize)]
+struct ImportReport {
+    imported: usize,
+    failed: usize,
+    /// One per non-empty line, in order.
+    results: Vec<ImportResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportResult {
+    /// Counting from 1.
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_id: Option<Uuid>,
+    imported: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl ImportReport {
+    fn add(&mut self, line: usize, game_id: Option<Uuid>, result: Result<(), String>) {
+        match &result {
+            Ok(()) => self.imported += 1,
+            Err(_) => self.failed += 1,
+        }
+        self.results.push(ImportResult {
+            line,
+            game_id,
+            imported: result.is_ok(),
+            error: result.err(),
+        });
+    }
+
+    /// Checks and loads one line of a dump.
+    async fn import_line(&mut self, state: &AppState, number: usize, line: &[u8]) {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return;
+        }
+        let dumped: DumpedGame = match serde_json::from_slice(line) {
+            Ok(dumped) => dumped,
+            Err(e) => return self.add(number, None, Err(format!("Invalid record: {}", e))),
+        };
+        let game_id = dumped.game_id;
+        let result = match dumped.verify() {
+            Ok(entry) => import::load(state, game_id, entry).await,
+            Err(e) => Err(e),
+        };
+        self.add(number, Some(game_id), result);
+    }
+}
+
+/// Loads games from a dump written by `GET /admin/export/games`, checking
+/// each game's moves through the rules engine. Lines are read as they
+/// arrive; each succeeds or fails on its own.
+async fn import_games(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+    body: Body,
+) -> Json<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut chunks = body.into_data_stream();
+    let mut buffer = Vec::new();
+    let mut number = 0;
+    loop {
+        let chunk = match chunks.next().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => {
+                report.add(number + 1, None, Err(format!("Failed to read: {}", e)));
+                break;
+            }
+            None => {
+                number += 1;
+                report.import_line(&state, number, &buffer).await;
+                break;
+            }
+        };
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            number += 1;
+            report.import_line(&state, number, &line).await;
+        }
+        if buffer.len() > MAX_IMPORT_LINE_BYTES {
+            report.add(number + 1, None, Err("Line too long".to_string()));
+            break;
+        }
+    }
+    log::warn!(
+        "Admin imported {} games, {} failed",
+        report.imported,
+        report.failed
+    );
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::ImportGames {
+            imported: report.imported,
+            failed: report.failed,
+        },
+    )
+    .await;
+    Json(report)
+}
+
+#[derive(Debug, Serialize)]
+struct RegistryStats {
+    games: usize,
+    in_progress: usize,
+    finished: usize,
+    approximate_bytes: usize,
+    maintenance: bool,
+    draining: bool,
+}
+
+/// Registry size and a rough estimate of the memory it holds, for the whole
+/// registry or only `?tenant=`'s games.
+async fn registry_stats(
+    State(state): State<AppState>,
+    Query(query): Query<TenantQuery>,
+) -> Json<RegistryStats> {
+    let registry = state.snapshot().await;
+    let games: Vec<&GameEntry> = registry
+        .values()
+        .filter(|entry| query.includes(entry))
+        .collect();
+    let in_progress = games
+        .iter()
+        .filter(|entry| entry.state.status == GameStatus::InProgress)
+        .count();
+    Json(RegistryStats {
+        games: games.len(),
+        in_progress,
+        finished: games.len() - in_progress,
+        approximate_bytes: games.iter().map(|entry| entry.approximate_size()).sum(),
+        maintenance: state.in_maintenance(),
+        draining: state.drain.is_draining(),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Maintenance {
+    enabled: bool,
+}
+
+async fn get_maintenance(State(state): State<AppState>) -> Json<Maintenance> {
+    Json(Maintenance {
+        enabled: state.in_maintenance(),
+    })
+}
+
+/// Turns maintenance mode on or off. While on, starting games fails with
+/// `503 Service Unavailable`.
+async fn set_maintenance(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+    Json(maintenance): Json<Maintenance>,
+) -> Json<Maintenance> {
+    state
+        .maintenance
+        .store(maintenance.enabled, Ordering::Relaxed);
+    log::warn!(
+        "Maintenance mode {}",
+        if maintenance.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::SetMaintenance {
+            enabled: maintenance.enabled,
+        },
+    )
+    .await;
+    Json(maintenance)
+}
+
+async fn get_drain(State(state): State<AppState>) -> Json<Progress> {
+    Json(drain::progress(&state))
+}
+
+/// Starts draining the server ahead of a restart, or reports on the drain
+/// already underway.
+async fn start_drain(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+) -> (StatusCode, Json<Progress>) {
+    if let Some(started) = drain::start(&state) {
+        audit::record(
+            &state,
+            admin_actor(admin),
+            ip,
+            Action::StartDrain {
+                deadline: started.deadline,
+                games_in_progress: started.games_at_start,
+            },
+        )
+        .await;
+    }
+    (StatusCode::ACCEPTED, Json(drain::progress(&state)))
+}
+
+async fn list_flags(State(state): State<AppState>) -> Json<FlagList> {
+    Json(state.flags.list())
+}
+
+#[derive(Debug, Deserialize)]
+struct SetFlag {
+    enabled: bool,
+}
+
+/// Switches a capability on or off, server-wide or for `?tenant=`, until the
+/// next restart.
+async fn set_flag(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+    Path(flag): Path<Flag>,
+    Query(query): Query<TenantQuery>,
+    Json(request): Json<SetFlag>,
+) -> Result<Json<FlagList>, Error> {
+    change_flag(&state, admin, ip, flag, query.tenant, Some(request.enabled)).await
+}
+
+/// Puts a tenant's flag back to the server-wide setting.
+async fn clear_flag(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+    Path(flag): Path<Flag>,
+    Query(query): Query<TenantQuery>,
+) -> Result<Json<FlagList>, Error> {
+    if query.tenant.is_none() {
+        return Err(Error::BadRequest(
+            "Give the tenant whose flag to clear; server-wide flags can only be set",
+        ));
+    }
+    change_flag(&state, admin, ip, flag, query.tenant, None).await
+}
+
+async fn change_flag(
+    state: &AppState,
+    admin: Option<RequireRole<Admin>>,
+    ip: Option<IpAddr>,
+    flag: Flag,
+    tenant: Option<String>,
+    enabled: Option<bool>,
+) -> Result<Json<FlagList>, Error> {
+    if let Some(tenant) = &tenant
+        && state.tenants.get(tenant).is_none()
+    {
+        return Err(Error::TenantNotFound(tenant.clone()));
+    }
+    state.flags.set(flag, tenant.as_deref(), enabled);
+    log::warn!(
+        "Feature flag {} {} for {}",
+        flag.as_str(),
+        match enabled {
+            Some(true) => "enabled",
+            Some(false) => "disabled",
+            None => "cleared",
+        },
+        tenant.as_deref().unwrap_or("the whole server")
+    );
+    audit::record(
+        state,
+        admin_actor(admin),
+        ip,
+        Action::SetFlag {
+            flag,
+            tenant,
+            enabled,
+        },
+    )
+    .await;
+    Ok(Json(state.flags.list()))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExperimentQuery {
+    /// Defaults to the experiment running now.
+    name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExperimentReport {
+    name: String,
+    /// Whether new games are still being put in it.
+    running: bool,
+    variants: Vec<VariantResults>,
+}
+
+/// How each variant of an engine experiment has fared so far.
+async fn experiment_results(
+    State(state): State<AppState>,
+    Query(query): Query<ExperimentQuery>,
+) -> Result<Json<ExperimentReport>, Error> {
+    let running = state.experiment.name();
+    let Some(name) = query.name.or_else(|| running.map(str::to_string)) else {
+        return Err(Error::BadRequest(
+            "No experiment is running; give the name of a past one",
+        ));
+    };
+    let variants = experiments::results(&state, &name)
+        .await
+        .map_err(Error::Storage)?;
+    Ok(Json(ExperimentReport {
+        running: running == Some(name.as_str()),
+        name,
+        variants,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct RevokedSessions {
+    ended: usize,
+}
+
+/// Logs an account out everywhere.
+async fn revoke_account_sessions(
+    State(state): State<AppState>,
+    Path(account_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+) -> Result<Json<RevokedSessions>, Error> {
+    let ended = account::revoke_account_sessions(&state, account_id).await?;
+    log::warn!("Admin ended {} sessions of account {}", ended, account_id);
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::RevokeSessions {
+            account_id: Some(account_id),
+            session_id: None,
+            sessions: ended,
+        },
+    )
+    .await;
+    Ok(Json(RevokedSessions { ended }))
+}
+
+async fn revoke_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+) -> Result<StatusCode, Error> {
+    account::revoke_session(&state, session_id).await?;
+    log::warn!("Admin ended session {}", session_id);
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::RevokeSessions {
+            account_id: None,
+            session_id: Some(session_id),
+            sessions: 1,
+        },
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleRequest {
+    role: Role,
+}
+
+#[derive(Debug, Serialize)]
+struct AccountRole {
+    id: Uuid,
+    username: Option<String>,
+    role: Role,
+}
+
+/// Makes an account a player, moderator, or admin.
+async fn set_role(
+    State(state): State<AppState>,
+    Path(account_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    admin: Option<RequireRole<Admin>>,
+    Json(request): Json<RoleRequest>,
+) -> Result<Json<AccountRole>, Error> {
+    let account = account::set_role(&state, account_id, request.role).await?;
+    log::warn!("Admin made account {} a {:?}", account_id, account.role);
+    audit::record(
+        &state,
+        admin_actor(admin),
+        ip,
+        Action::SetRole {
+            account_id,
+            role: account.role,
+        },
+    )
+    .await;
+    Ok(Json(AccountRole {
+        id: account.id,
+        username: account.username,
+        role: account.role,
+    }))
+}
+
+/// Most audit entries returned at once, and the default.
+const MAX_AUDIT_ENTRIES: usize = 1000;
+const DEFAULT_AUDIT_ENTRIES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    /// Inclusive.
+    from: Option<DateTime<Utc>>,
+    /// Exclusive.
+    to: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+}
+
+/// Audit entries in a time range, oldest first.
+async fn get_audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Json<Vec<AuditEntry>> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_ENTRIES)
+        .min(MAX_AUDIT_ENTRIES);
+    Json(state.audit.between(query.from, query.to, limit))
+}
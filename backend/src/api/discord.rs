@@ -0,0 +1,29 @@
+//! The endpoint a Discord application's interactions are posted to; see
+//! `discord`.
+
+use axum::{Json, body::Bytes, extract::State, http::HeaderMap};
+use chrono::Utc;
+use serde_json::Value;
+
+use crate::{
+    Error,
+    discord::{self, Interaction, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    state::AppState,
+};
+
+pub async fn receive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, Error> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    state.discord.verify(
+        header(TIMESTAMP_HEADER),
+        header(SIGNATURE_HEADER),
+        &body,
+        Utc::now(),
+    )?;
+    let interaction: Interaction = serde_json::from_slice(&body)
+        .map_err(|_| Error::BadRequest("Expected an interaction from Discord"))?;
+    Ok(Json(discord::interact(&state, interaction).await))
+}
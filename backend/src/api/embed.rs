@@ -0,0 +1,56 @@
+//! A page showing one game, for other sites to drop in with an iframe:
+//! `<iframe src="https://laika.example/embed/{game_id}">`. It is a single
+//! self-contained HTML file that polls `GET /api/v1/games/{game_id}` and
+//! redraws the board, so the frontend doesn't have to be running.
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    response::Html,
+    routing::get,
+};
+use uuid::Uuid;
+
+use crate::{Error, state::AppState};
+
+const PAGE: &str = include_str!("embed.html");
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/{game_id}", get(embed))
+}
+
+async fn embed(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<Html<String>, Error> {
+    if state.game(&game_id).is_none() {
+        return Err(Error::GameNotFound(game_id));
+    }
+    Ok(Html(PAGE.replace("__GAME_ID__", &game_id.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        game::GameState,
+        state::{GameEntry, GameRegistry},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_the_page_polls_its_own_game() {
+        let game_id = Uuid::new_v4();
+        let registry = GameRegistry::from([(game_id, GameEntry::new(GameState::default()))]);
+        let state = AppState::new(registry, Arc::new(MemoryStore));
+        let Html(page) = embed(State(state.clone()), Path(game_id)).await.unwrap();
+        assert!(page.contains(&format!("\"../api/v1/games/{game_id}\"")));
+        assert!(!page.contains("__GAME_ID__"));
+        assert!(matches!(
+            embed(State(state), Path(Uuid::new_v4())).await,
+            Err(Error::GameNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! The versioned REST API.
+//!
+//! Each version owns its handlers and wire types, so a breaking change ships
+//! as a new module mounted under `/api/v2` while older clients keep getting
+//! the shapes they were written against. Handlers translate between the
+//! version's types and the game operations in the crate root.
+
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    Router,
+    extract::{ConnectInfo, FromRef, FromRequestParts, OptionalFromRequestParts},
+    http::{HeaderMap, header::AUTHORIZATION, request::Parts},
+    middleware,
+    routing::{get, post},
+};
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    account::{AccountError, Role},
+    cluster,
+    config::AdminConfig,
+    health, metrics,
+    state::AppState,
+    tenant,
+};
+
+mod admin;
+mod discord;
+mod embed;
+mod preview;
+#[cfg(feature = "slack")]
+mod slack;
+#[cfg(feature = "telegram")]
+mod telegram;
+mod v1;
+
+pub fn router(state: AppState, admin_config: &AdminConfig) -> Router {
+    let router = Router::new()
+        .nest("/api/v1", v1::router())
+        // Paths from before versioning existed stay pinned to v1.
+        .nest("/api", v1::router())
+        .nest(
+            "/admin",
+            admin::router(state.clone(), admin_config.token.as_deref()),
+        )
+        .nest("/embed", embed::router())
+        .nest("/g", preview::router())
+        .route("/metrics", get(metrics::export))
+        .route("/readyz", get(health::readyz))
+        .route("/integrations/discord", post(discord::receive));
+    #[cfg(feature = "slack")]
+    let router = router.route("/integrations/slack", post(slack::receive));
+    #[cfg(feature = "telegram")]
+    let router = router.route("/integrations/telegram", post(telegram::receive));
+    let router = router.route_layer(middleware::from_fn(metrics::track));
+    #[cfg(feature = "sentry")]
+    let router = router.route_layer(middleware::from_fn(crate::reporting::capture));
+    router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            cluster::route_to_owner,
+        ))
+        // Runs first, so other tenants' games aren't taken over or
+        // redirected either.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            tenant::isolate,
+        ))
+        .with_state(state)
+}
+
+/// The address the request came from, for the audit log. `None` over the
+/// Unix socket, and whenever the server wasn't started with connection info.
+pub(crate) struct ClientIp(pub Option<IpAddr>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        ))
+    }
+}
+
+/// A role a route requires, as a type so the route can name it in its
+/// extractors.
+pub(crate) trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub(crate) struct Moderator;
+
+impl RequiredRole for Moderator {
+    const ROLE: Role = Role::Moderator;
+}
+
+pub(crate) struct Admin;
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// The account behind the request's session, which must have role `R` or a
+/// higher one. As an `Option`, callers without the role get `None` instead
+/// of an error.
+pub(crate) struct RequireRole<R> {
+    pub account_id: Uuid,
+    role: PhantomData<R>,
+}
+
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token =
+            bearer_token(&parts.headers).ok_or(Error::Account(AccountError::InvalidSession))?;
+        let (account_id, role) = AppState::from_ref(state)
+            .accounts
+            .session_role(token)
+            .await
+            .map_err(Error::Account)?;
+        if role < R::ROLE {
+            return Err(Error::Forbidden("Your account's role does not allow this"));
+        }
+        Ok(Self {
+            account_id,
+            role: PhantomData,
+        })
+    }
+}
+
+impl<S, R> OptionalFromRequestParts<S> for RequireRole<R>
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(
+            <Self as FromRequestParts<S>>::from_request_parts(parts, state)
+                .await
+                .ok(),
+        )
+    }
+}
+
+/// Compares in time independent of where the inputs first differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The token from an `Authorization: Bearer` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bearer_token_must_match_exactly() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, "Bearer s3cret".parse().unwrap());
+        let given = bearer_token(&headers).unwrap();
+        assert!(constant_time_eq(given.as_bytes(), b"s3cret"));
+        assert!(!constant_time_eq(given.as_bytes(), b"s3cre"));
+        assert!(!constant_time_eq(given.as_bytes(), b"s3creT"));
+
+        headers.insert(AUTHORIZATION, "Basic s3cret".parse().unwrap());
+        assert_eq!(bearer_token(&headers), None);
+    }
+}
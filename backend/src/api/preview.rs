@@ -0,0 +1,167 @@
+//! Link previews: `/g/{game_id}` is a page with Open Graph and Twitter
+//! card tags, so a game shared in Slack, Discord or iMessage unfurls into
+//! its board and where it stands. People who open the link see the same,
+//! with a link to the live view at `/embed/{game_id}`.
+//!
+//! Scrapers need absolute URLs, taken from `server.public_url`, or from the
+//! request when that isn't set.
+
+use axum::{
+    Router,
+    extract::{OriginalUri, Path, State},
+    http::{HeaderMap, header},
+    response::Html,
+    routing::get,
+};
+use uuid::Uuid;
+
+use crate::{Error, render, state::AppState};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/{game_id}", get(preview))
+}
+
+/// Makes `text` safe in HTML text and quoted attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The server's address as the client sees it: `server.public_url`, or
+/// else the `Host` the request was sent to, over the scheme a proxy in
+/// front reports in `X-Forwarded-Proto`.
+fn base_url(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(url) = &state.public_url {
+        return url.to_string();
+    }
+    let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let scheme = value(header::HeaderName::from_static("x-forwarded-proto")).unwrap_or("http");
+    let host = value(header::HOST).unwrap_or("localhost");
+    format!("{scheme}://{host}")
+}
+
+async fn preview(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<Html<String>, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let game_state = game.lock().await.state;
+
+    // Any tenant prefix the link was shared with.
+    let prefix = uri
+        .path()
+        .strip_suffix(&format!("/g/{game_id}"))
+        .unwrap_or("");
+    let base = format!("{}{prefix}", base_url(&state, &headers));
+    let page_url = escape(&format!("{base}/g/{game_id}"));
+    // The version busts caches, so a link shared again shows the board as
+    // it is now.
+    let image_url = escape(&format!(
+        "{base}/api/v1/games/{game_id}/board.png?v={}",
+        game_state.version
+    ));
+    let embed_url = escape(&format!("{base}/embed/{game_id}"));
+    let (rows, cols) = game_state.board.size();
+    let (width, height) = render::image_size(rows, cols);
+    let description = escape(&game_state.summary());
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Laika game: {description}</title>
+<meta name="description" content="{description}">
+<meta property="og:type" content="website">
+<meta property="og:site_name" content="Laika">
+<meta property="og:title" content="Laika game">
+<meta property="og:description" content="{description}">
+<meta property="og:url" content="{page_url}">
+<meta property="og:image" content="{image_url}">
+<meta property="og:image:type" content="image/png">
+<meta property="og:image:width" content="{width}">
+<meta property="og:image:height" content="{height}">
+<meta property="og:image:alt" content="The board: {description}">
+<meta name="twitter:card" content="summary_large_image">
+<meta name="twitter:title" content="Laika game">
+<meta name="twitter:description" content="{description}">
+<meta name="twitter:image" content="{image_url}">
+</head>
+<body style="font: 16px system-ui, sans-serif; text-align: center">
+<img src="{image_url}" width="{width}" height="{height}" alt="The board">
+<p>{description}</p>
+<p><a href="{embed_url}">Watch live</a></p>
+</body>
+</html>
+"#
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{HeaderValue, Uri};
+
+    use super::*;
+    use crate::{
+        game::{GameState, GameStatus, Player, PlayerMove, try_move},
+        state::{GameEntry, GameRegistry},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_previews_describe_the_game_with_absolute_urls() {
+        let mut entry = GameEntry::new(GameState::default());
+        try_move(&mut entry.state, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+        assert_eq!(entry.state.summary(), "O to move, 1 move played");
+        let mut finished = entry.state;
+        finished.status = GameStatus::Win(Player::X);
+        finished.version = 5;
+        assert_eq!(finished.summary(), "X won after 5 moves");
+
+        let game_id = Uuid::new_v4();
+        let state = AppState::new(
+            GameRegistry::from([(game_id, entry)]),
+            Arc::new(MemoryStore),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("play.example\"><x"));
+        let uri: Uri = format!("/t/acme/g/{game_id}").parse().unwrap();
+        let Html(page) = preview(
+            State(state.clone()),
+            Path(game_id),
+            OriginalUri(uri.clone()),
+            headers.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(page.contains(&format!(
+            r#"<meta property="og:image" content="http://play.example&quot;&gt;&lt;x/t/acme/api/v1/games/{game_id}/board.png?v=1">"#
+        )));
+        assert!(
+            page.contains(r#"<meta property="og:description" content="O to move, 1 move played">"#)
+        );
+
+        let state = state.with_public_url(Some("https://laika.example/"));
+        let Html(page) = preview(State(state), Path(game_id), OriginalUri(uri), headers)
+            .await
+            .unwrap();
+        assert!(page.contains(&format!(
+            r#"<meta property="og:url" content="https://laika.example/t/acme/g/{game_id}">"#
+        )));
+    }
+}
@@ -0,0 +1,43 @@
+//! The endpoint a Slack app's slash command and interactive buttons post
+//! to; see `slack`.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+
+use crate::{
+    Error,
+    slack::{self, Interaction, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    state::AppState,
+};
+
+/// Answers a slash command with the new game's message, or takes a button
+/// click and answers it later through its `response_url`, since Slack only
+/// waits three seconds and the AI may take longer than that.
+pub async fn receive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    state.slack.verify(
+        header(TIMESTAMP_HEADER),
+        header(SIGNATURE_HEADER),
+        &body,
+        Utc::now(),
+    )?;
+    let request: slack::Request = serde_urlencoded::from_bytes(&body)
+        .map_err(|_| Error::BadRequest("Expected a form from Slack"))?;
+    if let Some(payload) = request.payload {
+        let interaction: Interaction = serde_json::from_str(&payload)
+            .map_err(|_| Error::BadRequest("Expected a JSON payload from Slack"))?;
+        tokio::spawn(slack::click(state, interaction));
+        return Ok(StatusCode::OK.into_response());
+    }
+    Ok(Json(slack::command(&state, &request.user_id, &request.text).await).into_response())
+}
@@ -0,0 +1,28 @@
+//! The webhook Telegram posts the bot's updates to; see `telegram`.
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+
+use crate::{
+    Error,
+    state::AppState,
+    telegram::{self, SECRET_HEADER, Update},
+};
+
+/// Takes an update and answers it through the Bot API, so Telegram isn't
+/// kept waiting on the AI.
+pub async fn receive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(update): Json<Update>,
+) -> Result<StatusCode, Error> {
+    let secret = headers
+        .get(SECRET_HEADER)
+        .and_then(|value| value.to_str().ok());
+    state.telegram.verify(secret)?;
+    tokio::spawn(telegram::handle(state, update));
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,418 @@
+//! Account endpoints: start a guest session, register it, log in and out,
+//! refresh sessions, and look up the account behind a session.
+
+use std::net::IpAddr;
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Html,
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{ClientIp, Player, bearer_token};
+use crate::{
+    Error,
+    account::{
+        self, Account, AccountError, Role, Session, Stats, check_email, check_password,
+        check_username,
+    },
+    audit::{self, Action, Actor},
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/session", post(start_session))
+        .route("/session/login", post(log_in))
+        .route("/session/refresh", post(refresh))
+        .route("/session/logout", post(log_out))
+        .route("/session/logout-all", post(log_out_everywhere))
+        .route("/account", get(get_account))
+        .route("/account/sessions", get(list_sessions))
+        .route("/account/register", post(register))
+        .route(
+            "/account/notifications",
+            get(get_notifications).post(set_notifications),
+        )
+        .route("/unsubscribe", get(confirm_unsubscribe).post(unsubscribe))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+pub struct CredentialsRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsView {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl From<Stats> for StatsView {
+    fn from(stats: Stats) -> Self {
+        Self {
+            wins: stats.wins,
+            losses: stats.losses,
+            draws: stats.draws,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountGameView {
+    pub game_id: Uuid,
+    pub player: Player,
+}
+
+/// An account without its password hash or sessions.
+#[derive(Debug, Serialize)]
+pub struct AccountView {
+    pub id: Uuid,
+    /// `null` for guests.
+    pub username: Option<String>,
+    pub guest: bool,
+    pub role: Role,
+    /// OAuth providers the account can log in with.
+    pub providers: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub registered_at: Option<DateTime<Utc>>,
+    /// Oldest first.
+    pub games: Vec<AccountGameView>,
+    pub stats: StatsView,
+}
+
+impl From<&Account> for AccountView {
+    fn from(account: &Account) -> Self {
+        Self {
+            id: account.id,
+            username: account.username.clone(),
+            guest: account.is_guest(),
+            role: account.role,
+            providers: account
+                .identities
+                .iter()
+                .map(|identity| identity.provider.clone())
+                .collect(),
+            created_at: account.created_at,
+            registered_at: account.registered_at,
+            games: account
+                .games
+                .iter()
+                .map(|game| AccountGameView {
+                    game_id: game.game_id,
+                    player: game.player.into(),
+                })
+                .collect(),
+            stats: account.stats.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub account: AccountView,
+    /// The access token. Send as `Authorization: Bearer <token>`.
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    /// Trades for a new token and refresh token at `/session/refresh`.
+    pub refresh_token: String,
+    pub refresh_expires_at: DateTime<Utc>,
+}
+
+impl SessionResponse {
+    pub fn new(account: &Account, session: Session) -> Self {
+        Self {
+            account: AccountView::from(account),
+            token: session.access_token,
+            expires_at: session.access_expires_at,
+            refresh_token: session.refresh_token,
+            refresh_expires_at: session.refresh_expires_at,
+        }
+    }
+}
+
+/// A session without its tokens.
+#[derive(Debug, Serialize)]
+pub struct SessionView {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// When the session ends unless it is refreshed.
+    pub refresh_expires_at: DateTime<Utc>,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+/// Which emails the account gets. Sent back as is to change them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationsView {
+    /// `null` for no emails at all.
+    pub email: Option<String>,
+    /// Email when it's the account's move in a player-vs-player game and
+    /// it hasn't answered for a while. Needs `email`.
+    #[serde(default)]
+    pub your_move: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogOutResponse {
+    /// How many sessions were ended.
+    pub ended: usize,
+}
+
+pub(super) fn session_token(headers: &HeaderMap) -> Result<&str, Error> {
+    bearer_token(headers).ok_or(Error::Account(AccountError::InvalidSession))
+}
+
+// --- Handlers ---
+
+/// Starts a session for a new guest account.
+async fn start_session(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+) -> Result<(StatusCode, Json<SessionResponse>), Error> {
+    let (account, session) = account::start_guest(&state).await?;
+    audit::record(
+        &state,
+        Actor::Account {
+            account_id: account.id,
+        },
+        ip,
+        Action::StartGuest {
+            account_id: account.id,
+        },
+    )
+    .await;
+    Ok((
+        StatusCode::CREATED,
+        Json(SessionResponse::new(&account, session)),
+    ))
+}
+
+/// Starts a new session for a registered account.
+async fn log_in(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(request): Json<CredentialsRequest>,
+) -> Result<(StatusCode, Json<SessionResponse>), Error> {
+    let (account, session) =
+        match account::login(&state, &request.username, &request.password).await {
+            Ok(login) => login,
+            Err(e @ Error::Account(AccountError::InvalidCredentials)) => {
+                let username = request.username;
+                let action = Action::LoginFailed { username };
+                audit::record(&state, Actor::Anonymous, ip, action).await;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+    audit::record(
+        &state,
+        Actor::Account {
+            account_id: account.id,
+        },
+        ip,
+        Action::Login {
+            account_id: account.id,
+            method: "password".to_string(),
+        },
+    )
+    .await;
+    Ok((
+        StatusCode::CREATED,
+        Json(SessionResponse::new(&account, session)),
+    ))
+}
+
+/// Replaces the session's tokens. The old refresh token stops working.
+async fn refresh(
+    State(state): State<AppState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<SessionResponse>, Error> {
+    let (account, session) = account::refresh(&state, &request.refresh_token).await?;
+    Ok(Json(SessionResponse::new(&account, session)))
+}
+
+/// Ends the calling session.
+async fn log_out(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+) -> Result<Json<LogOutResponse>, Error> {
+    end_sessions(&state, ip, &headers, false).await
+}
+
+/// Ends every session of the calling account, including this one.
+async fn log_out_everywhere(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+) -> Result<Json<LogOutResponse>, Error> {
+    end_sessions(&state, ip, &headers, true).await
+}
+
+async fn end_sessions(
+    state: &AppState,
+    ip: Option<IpAddr>,
+    headers: &HeaderMap,
+    everywhere: bool,
+) -> Result<Json<LogOutResponse>, Error> {
+    let token = session_token(headers)?;
+    let account_id = state
+        .accounts
+        .session(token)
+        .await
+        .map_err(Error::Account)?;
+    let ended = account::log_out(state, token, everywhere).await?;
+    audit::record(
+        state,
+        Actor::Account { account_id },
+        ip,
+        Action::Logout {
+            account_id,
+            sessions: ended,
+        },
+    )
+    .await;
+    Ok(Json(LogOutResponse { ended }))
+}
+
+async fn get_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<AccountView>, Error> {
+    let (account, _) = account::get(&state, session_token(&headers)?).await?;
+    Ok(Json(AccountView::from(&account)))
+}
+
+async fn list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SessionView>>, Error> {
+    let (account, current) = account::get(&state, session_token(&headers)?).await?;
+    Ok(Json(
+        account
+            .sessions
+            .iter()
+            .map(|session| SessionView {
+                id: session.id,
+                created_at: session.created_at,
+                refresh_expires_at: session.refresh_expires_at,
+                current: session.id == current,
+            })
+            .collect(),
+    ))
+}
+
+async fn get_notifications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<NotificationsView>, Error> {
+    let (account, _) = account::get(&state, session_token(&headers)?).await?;
+    Ok(Json(NotificationsView {
+        email: account.notifications.email,
+        your_move: account.notifications.your_move,
+    }))
+}
+
+/// Changes which emails the account gets. Only registered accounts can
+/// have any.
+async fn set_notifications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<NotificationsView>,
+) -> Result<Json<NotificationsView>, Error> {
+    let email = request.email.as_deref().map(check_email).transpose()?;
+    let account =
+        account::set_notifications(&state, session_token(&headers)?, email, request.your_move)
+            .await?;
+    Ok(Json(NotificationsView {
+        email: account.notifications.email,
+        your_move: account.notifications.your_move,
+    }))
+}
+
+/// The page an email's unsubscribe link opens. Unsubscribing takes a
+/// `POST`, so link scanners that follow every link don't do it by mistake.
+async fn confirm_unsubscribe(Query(_): Query<UnsubscribeQuery>) -> Html<&'static str> {
+    Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Unsubscribe from Laika</title></head>
+<body style="font: 16px system-ui, sans-serif; text-align: center">
+<p>Stop getting emails when it's your move?</p>
+<form method="post"><button type="submit">Unsubscribe</button></form>
+</body>
+</html>
+"#,
+    )
+}
+
+/// Turns off the emails of the account the link was sent to. Mail clients
+/// post here directly for one-click unsubscribing.
+async fn unsubscribe(
+    State(state): State<AppState>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Html<&'static str>, Error> {
+    account::unsubscribe(&state, &query.token).await?;
+    Ok(Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Unsubscribed from Laika</title></head>
+<body style="font: 16px system-ui, sans-serif; text-align: center">
+<p>You won't get any more emails when it's your move.</p>
+</body>
+</html>
+"#,
+    ))
+}
+
+/// Registers the guest account behind the session. Giving the username and
+/// password of an existing account moves the guest's games and stats to it
+/// instead; the session then acts as that account.
+async fn register(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(request): Json<CredentialsRequest>,
+) -> Result<Json<AccountView>, Error> {
+    let username = check_username(&request.username)?;
+    check_password(&request.password)?;
+    let account = account::register(
+        &state,
+        session_token(&headers)?,
+        username,
+        &request.password,
+    )
+    .await?;
+    audit::record(
+        &state,
+        Actor::Account {
+            account_id: account.id,
+        },
+        ip,
+        Action::Register {
+            account_id: account.id,
+        },
+    )
+    .await;
+    Ok(Json(AccountView::from(&account)))
+}
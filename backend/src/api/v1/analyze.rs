@@ -0,0 +1,91 @@
+//! Analysis endpoints: what a position is worth with perfect play.
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::HeaderMap,
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{GameView, Player, caller};
+use crate::{
+    Error,
+    game::PlayerMove,
+    import, limits, notation,
+    solver::{self, Outcome},
+    state::AppState,
+    tenant::Tenant,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/analyze/value", get(get_value))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+pub struct ValueQuery {
+    /// The position as canonical notation; empty or missing for the empty
+    /// board.
+    #[serde(default)]
+    pub notation: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SquareView {
+    pub row: usize,
+    pub col: usize,
+    /// The cell's index, from 0 at the top left to 8.
+    pub cell: usize,
+    pub square: String,
+}
+
+impl From<PlayerMove> for SquareView {
+    fn from(player_move: PlayerMove) -> Self {
+        Self {
+            row: player_move.row,
+            col: player_move.col,
+            cell: player_move.row * 3 + player_move.col,
+            square: notation::square(player_move),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValueResponse {
+    pub game_state: GameView,
+    /// For the side to move: `win`, `draw`, or `loss`.
+    pub outcome: Outcome,
+    pub winner: Option<Player>,
+    /// Plies until the game ends with perfect play.
+    pub plies: u32,
+    /// Moves the winner still needs, "mate in N" style; `null` for a draw.
+    pub moves_to_win: Option<u32>,
+    pub best_move: Option<SquareView>,
+}
+
+// --- Handlers ---
+
+/// Solves a position, e.g. `GET /api/v1/analyze/value?notation=X:b2+O:a1`.
+/// Counts against the caller's analysis quota.
+async fn get_value(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Query(query): Query<ValueQuery>,
+) -> Result<Json<ValueResponse>, Error> {
+    let caller = caller(&state, &headers, &tenant).await?;
+    limits::count_analysis(&state, caller.as_ref()).await?;
+    let moves = notation::parse_moves(&query.notation).map_err(Error::InvalidImport)?;
+    let game_state = import::replay(&moves).map_err(Error::InvalidImport)?.state;
+    let value = solver::solve(&game_state);
+    Ok(Json(ValueResponse {
+        game_state: game_state.into(),
+        outcome: value.outcome,
+        winner: value.winner(game_state.to_play).map(Player::from),
+        plies: value.plies,
+        moves_to_win: value.moves_to_win(),
+        best_move: value.best_move.map(SquareView::from),
+    }))
+}
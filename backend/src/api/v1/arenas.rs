@@ -0,0 +1,221 @@
+//! Arena endpoints.
+
+use std::convert::Infallible;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{self, KeepAlive, Sse},
+    routing::{delete, get, post},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::{Stream, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use super::{NameRequest, check_name, seat_token};
+use crate::{
+    Error,
+    api::constant_time_eq,
+    arena::{self, Arena, DEFAULT_MINUTES, DEFAULT_TIME_CONTROL, MAX_MINUTES, Standing},
+    clock::TimeControl,
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/arenas", post(create_arena))
+        .route("/arenas/{arena_id}", get(get_arena))
+        .route("/arenas/{arena_id}/stream", get(stream_arena))
+        .route("/arenas/{arena_id}/players", post(join_arena))
+        .route(
+            "/arenas/{arena_id}/players/{player_id}",
+            delete(withdraw_player),
+        )
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+pub struct CreateArenaRequest {
+    pub name: String,
+    /// How long the arena pairs players; 30 minutes unless given.
+    pub minutes: Option<i64>,
+    /// Blitz, 30 seconds and 2 per move, unless given.
+    pub time_control: Option<TimeControl>,
+}
+
+/// An arena without its seat tokens.
+#[derive(Debug, Serialize)]
+pub struct ArenaView {
+    pub id: Uuid,
+    pub name: String,
+    pub time_control: TimeControl,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    /// Set once the window has passed and the last game has finished.
+    pub finished: bool,
+    pub standings: Vec<Standing>,
+}
+
+impl ArenaView {
+    fn new(arena: &Arena, now: DateTime<Utc>) -> Self {
+        Self {
+            id: arena.id,
+            name: arena.name.clone(),
+            time_control: arena.time_control,
+            starts_at: arena.starts_at,
+            ends_at: arena.ends_at,
+            finished: arena.is_finished(now),
+            standings: arena.standings(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArenaRegistration {
+    pub player_id: Uuid,
+    /// Send as the `Seat-Token` header when moving in arena games, and to
+    /// withdraw.
+    pub token: String,
+}
+
+// --- Handlers ---
+
+/// Opens an arena, which starts pairing players as soon as they join.
+async fn create_arena(
+    State(state): State<AppState>,
+    Json(request): Json<CreateArenaRequest>,
+) -> Result<(StatusCode, Json<ArenaView>), Error> {
+    let name = check_name(&request.name)?;
+    let minutes = request.minutes.unwrap_or(DEFAULT_MINUTES);
+    if !(1..=MAX_MINUTES).contains(&minutes) {
+        return Err(Error::BadRequest("minutes must be between 1 and 180"));
+    }
+    let time_control = request.time_control.unwrap_or(DEFAULT_TIME_CONTROL);
+    time_control.validate()?;
+    let now = Utc::now();
+    let arena = Arena::new(name, time_control, TimeDelta::minutes(minutes), now);
+    let view = ArenaView::new(&arena, now);
+    log::info!("Created arena {} for {} minutes", arena.id, minutes);
+    state.arenas.insert(arena);
+    Ok((StatusCode::CREATED, Json(view)))
+}
+
+async fn get_arena(
+    State(state): State<AppState>,
+    Path(arena_id): Path<Uuid>,
+) -> Result<Json<ArenaView>, Error> {
+    let arena = state
+        .arenas
+        .get(&arena_id)
+        .ok_or(Error::ArenaNotFound(arena_id))?;
+    let arena = arena.lock().await;
+    Ok(Json(ArenaView::new(&arena, Utc::now())))
+}
+
+/// Joins the arena. The player is paired as soon as someone else is free;
+/// their game shows up as their `game_id` in the standings.
+async fn join_arena(
+    State(state): State<AppState>,
+    Path(arena_id): Path<Uuid>,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<ArenaRegistration>), Error> {
+    let name = check_name(&request.name)?;
+    let arena = state
+        .arenas
+        .get(&arena_id)
+        .ok_or(Error::ArenaNotFound(arena_id))?;
+    let mut arena = arena.lock().await;
+    let player = arena::join(&state, &mut arena, name).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(ArenaRegistration {
+            player_id: player.id,
+            token: player.token,
+        }),
+    ))
+}
+
+/// Stops pairing a player, who keeps their points. Needs the player's
+/// `Seat-Token` header.
+async fn withdraw_player(
+    State(state): State<AppState>,
+    Path((arena_id, player_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, Error> {
+    let arena = state
+        .arenas
+        .get(&arena_id)
+        .ok_or(Error::ArenaNotFound(arena_id))?;
+    let mut arena = arena.lock().await;
+    let player = arena
+        .player(player_id)
+        .ok_or(Error::Arena(arena::ArenaError::UnknownPlayer))?;
+    if !seat_token(&headers)
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), player.token.as_bytes()))
+    {
+        return Err(Error::Forbidden("Only the player can withdraw"));
+    }
+    arena::withdraw(&state, &mut arena, player_id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Follows an arena: an `arena` event with the arena as it stands, then
+/// another whenever its standings change, until it is finished.
+async fn stream_arena(
+    State(state): State<AppState>,
+    Path(arena_id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, Error> {
+    // Subscribed before the first look, so no change falls in between.
+    let changes = state.arenas.subscribe();
+    let arena = state
+        .arenas
+        .get(&arena_id)
+        .ok_or(Error::ArenaNotFound(arena_id))?;
+    let first = ArenaView::new(&*arena.lock().await, Utc::now());
+    let frame = |view: &ArenaView| {
+        sse::Event::default()
+            .event("arena")
+            .json_data(view)
+            .expect("arenas serialize")
+    };
+    let following = stream::unfold(
+        (Some(first), changes, false),
+        move |(pending, mut changes, over)| {
+            let arena = arena.clone();
+            async move {
+                if let Some(view) = pending {
+                    let over = view.finished;
+                    return Some((Ok(frame(&view)), (None, changes, over)));
+                }
+                if over {
+                    return None;
+                }
+                let ends_in = {
+                    let ends_at = arena.lock().await.ends_at;
+                    (ends_at - Utc::now()).to_std().unwrap_or_default()
+                };
+                loop {
+                    tokio::select! {
+                        change = changes.recv() => match change {
+                            Ok(id) if id != arena_id => continue,
+                            // Missed changes may have been this arena's.
+                            Ok(_) | Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => return None,
+                        },
+                        // The end of the window changes nothing else, but
+                        // may finish the arena.
+                        _ = tokio::time::sleep(ends_in), if !ends_in.is_zero() => {}
+                    }
+                    let view = ArenaView::new(&*arena.lock().await, Utc::now());
+                    let over = view.finished;
+                    return Some((Ok(frame(&view)), (None, changes, over)));
+                }
+            }
+        },
+    );
+    Ok(Sse::new(following).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,203 @@
+//! Bot endpoints: third parties register their own engines, connect them
+//! over a WebSocket, and people start games against them.
+
+use axum::{
+    Json, Router,
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::Response,
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{GameView, NameRequest, NewGameResponse, check_name};
+use crate::{
+    Error,
+    bot::{self, MoveRequest},
+    codec::{Accept, Encoded},
+    flags::Flag,
+    game::PlayerMove,
+    state::AppState,
+    tenant::Tenant,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/bots", get(list_bots).post(register_bot))
+        .route("/bots/{bot_id}/ws", get(connect))
+        .route("/bots/{bot_id}/games", post(new_game))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct RegisteredBot {
+    pub bot_id: Uuid,
+    pub name: String,
+    /// Pass as `?token=` when connecting. Only shown once.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BotView {
+    pub bot_id: Uuid,
+    pub name: String,
+    pub online: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConnectQuery {
+    pub token: String,
+}
+
+/// Sent to the bot when it is its turn.
+#[derive(Debug, Serialize)]
+pub struct PositionMessage {
+    pub request_id: Uuid,
+    pub game_id: Uuid,
+    pub game_state: GameView,
+    pub deadline_ms: u64,
+}
+
+impl From<MoveRequest> for PositionMessage {
+    fn from(request: MoveRequest) -> Self {
+        Self {
+            request_id: request.request_id,
+            game_id: request.game_id,
+            game_state: request.game_state.into(),
+            deadline_ms: request.deadline_ms,
+        }
+    }
+}
+
+/// The bot's answer to a `PositionMessage`.
+#[derive(Debug, Deserialize)]
+pub struct BotMove {
+    pub request_id: Uuid,
+    #[serde(flatten)]
+    pub player_move: PlayerMove,
+}
+
+#[derive(Debug, Serialize)]
+struct BotMessageError {
+    error: &'static str,
+}
+
+// --- Handlers ---
+
+/// Registers a bot. The returned token is what the bot connects with.
+async fn register_bot(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<RegisteredBot>), Error> {
+    state.flags.require(Flag::Bots, &tenant)?;
+    let bot = state
+        .bots
+        .register(check_name(&request.name)?, Utc::now())
+        .map_err(Error::Bot)?;
+    log::info!("Registered bot {} ({})", bot.id, bot.name);
+    Ok((
+        StatusCode::CREATED,
+        Json(RegisteredBot {
+            bot_id: bot.id,
+            name: bot.name,
+            token: bot.token,
+        }),
+    ))
+}
+
+async fn list_bots(State(state): State<AppState>) -> Json<Vec<BotView>> {
+    let bots = state
+        .bots
+        .list()
+        .into_iter()
+        .map(|bot| BotView {
+            online: state.bots.is_online(bot.id),
+            bot_id: bot.id,
+            name: bot.name,
+            created_at: bot.created_at,
+        })
+        .collect();
+    Json(bots)
+}
+
+/// Starts a game against the bot, with the caller as X. The bot must be
+/// connected.
+async fn new_game(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(bot_id): Path<Uuid>,
+    Accept(format): Accept,
+) -> Result<Encoded<NewGameResponse>, Error> {
+    state.flags.require(Flag::Bots, &tenant)?;
+    let (game_id, game_state) = bot::create_game(&state, bot_id).await?;
+    Ok(Encoded(
+        format,
+        NewGameResponse {
+            game_id,
+            game_state: game_state.into(),
+        },
+    ))
+}
+
+/// The bot's connection. Positions are pushed to it as JSON text messages,
+/// and it answers each with `{"request_id", "row", "col"}`. A new connection
+/// replaces any earlier one for the same bot.
+async fn connect(
+    State(state): State<AppState>,
+    Path(bot_id): Path<Uuid>,
+    Query(query): Query<ConnectQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let bot = state.bots.get(bot_id).map_err(Error::Bot)?;
+    if bot.token != query.token {
+        return Err(Error::Forbidden("Invalid bot token"));
+    }
+    Ok(ws.on_upgrade(move |socket| hold(socket, state, bot_id)))
+}
+
+async fn hold(mut socket: WebSocket, state: AppState, bot_id: Uuid) {
+    let (connection_id, mut requests) = state.bots.attach(bot_id);
+    log::info!("Bot {} connected", bot_id);
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                // Replaced by a newer connection.
+                let Some(request) = request else { break };
+                let message = serde_json::to_string(&PositionMessage::from(request))
+                    .expect("position serializes");
+                if socket.send(Message::Text(message.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                };
+                let error = match serde_json::from_str::<BotMove>(&text) {
+                    Ok(answer) if state.bots.answer(bot_id, answer.request_id, answer.player_move) => {
+                        continue;
+                    }
+                    Ok(_) => "No move is pending for that request_id",
+                    Err(_) => "Expected {\"request_id\", \"row\", \"col\"}",
+                };
+                let reply = serde_json::to_string(&BotMessageError { error })
+                    .expect("error serializes");
+                if socket.send(Message::Text(reply.into())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    state.bots.detach(bot_id, connection_id);
+    log::info!("Bot {} disconnected", bot_id);
+}
@@ -0,0 +1,61 @@
+//! Clock endpoints for timed player-vs-player games.
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::HeaderMap,
+    routing::post,
+};
+use uuid::Uuid;
+
+use super::{GameView, seat_token};
+use crate::{
+    Error, clock,
+    codec::{Accept, Encoded},
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/games/{game_id}/pause", post(pause))
+        .route("/games/{game_id}/resume", post(resume))
+        .route("/games/{game_id}/claim-timeout", post(claim_timeout))
+}
+
+// --- Handlers ---
+
+/// Asks to pause the game, or agrees to the opponent's request, in which
+/// case the clock stops and no moves can be made until it is resumed.
+async fn pause(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state = clock::request_pause(&state, game_id, seat_token(&headers), true).await?;
+    Ok(Encoded(format, game_state.into()))
+}
+
+/// Asks to resume a paused game, or agrees to the opponent's request, in
+/// which case the side to move's clock starts again.
+async fn resume(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state = clock::request_pause(&state, game_id, seat_token(&headers), false).await?;
+    Ok(Encoded(format, game_state.into()))
+}
+
+/// Claims that the opponent has run out of time. Fails unless the server's
+/// own clock agrees.
+async fn claim_timeout(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state = clock::claim_timeout(&state, game_id, seat_token(&headers)).await?;
+    Ok(Encoded(format, game_state.into()))
+}
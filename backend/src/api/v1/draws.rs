@@ -0,0 +1,63 @@
+//! Draw offer endpoints for player-vs-player games. Each needs the caller's
+//! `Seat-Token` header.
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::HeaderMap,
+    routing::post,
+};
+use uuid::Uuid;
+
+use super::{GameView, seat_token};
+use crate::{
+    Error,
+    codec::{Accept, Encoded},
+    draw::{self, DrawAction},
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/games/{game_id}/offer-draw", post(offer_draw))
+        .route("/games/{game_id}/accept-draw", post(accept_draw))
+        .route("/games/{game_id}/decline-draw", post(decline_draw))
+}
+
+// --- Handlers ---
+
+/// Offers a draw. If the opponent has already offered one, the game is
+/// drawn.
+async fn offer_draw(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state =
+        draw::respond(&state, game_id, seat_token(&headers), DrawAction::Offer).await?;
+    Ok(Encoded(format, game_state.into()))
+}
+
+/// Accepts the opponent's draw offer, ending the game.
+async fn accept_draw(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state =
+        draw::respond(&state, game_id, seat_token(&headers), DrawAction::Accept).await?;
+    Ok(Encoded(format, game_state.into()))
+}
+
+async fn decline_draw(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state =
+        draw::respond(&state, game_id, seat_token(&headers), DrawAction::Decline).await?;
+    Ok(Encoded(format, game_state.into()))
+}
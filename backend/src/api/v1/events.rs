@@ -0,0 +1,402 @@
+//! A game's event log, the game as it stood at any point in it, finished
+//! games played back move by move over server-sent events, and games in
+//! progress followed live the same way, or by long polling.
+
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    Router,
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::sse::{self, KeepAlive, Sse},
+    routing::get,
+};
+use futures_util::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use super::{
+    Cell, Ending, GameView,
+    resume::{MoveView, move_view},
+};
+use crate::{
+    Error,
+    codec::{Accept, Encoded},
+    events::{self, Event, EventRecord},
+    game,
+    state::{AppState, GameEntry, GameEvent},
+};
+
+/// How long playback waits between moves when the request doesn't say.
+const DEFAULT_PLAYBACK_INTERVAL_MS: u64 = 1000;
+const PLAYBACK_INTERVAL_MS: std::ops::RangeInclusive<u64> = 100..=10_000;
+
+/// How long a long poll waits for a change when the request doesn't say.
+/// Both this and the longest wait stay under `server.request_timeout_secs`'s
+/// default.
+const DEFAULT_WAIT_SECS: u64 = 20;
+const WAIT_SECS: std::ops::RangeInclusive<u64> = 1..=25;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/games/{game_id}/events", get(get_game_events))
+        .route("/games/{game_id}/replay", get(get_game_replay))
+        .route("/games/{game_id}/replay/stream", get(stream_game_replay))
+        .route("/games/{game_id}/stream", get(stream_game))
+        .route("/games/{game_id}/wait", get(wait_for_change))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EventsQuery {
+    /// Only return events up to and including this one, and the game as it
+    /// stood right after it.
+    pub through: Option<u64>,
+}
+
+pub use laika_types::{EventRecordView, EventView, EventsResponse};
+
+/// An event in a game whose board is `size`, in rows and columns.
+fn event_view(record: &EventRecord, size: (usize, usize)) -> EventRecordView {
+    let event = match &record.event {
+        Event::GameCreated { board, rules } => EventView::GameCreated {
+            board: board
+                .rows()
+                .into_iter()
+                .map(|row| row.into_iter().map(Cell::from).collect())
+                .collect(),
+            win_length: rules.win_length,
+            toroidal: rules.toroidal,
+        },
+        Event::MoveMade(record) => EventView::MoveMade(move_view(record, size)),
+        Event::GameFinished { status, ending } => EventView::GameFinished {
+            status: (*status).into(),
+            ending: ending.map(Ending::from),
+        },
+    };
+    EventRecordView {
+        seq: record.seq,
+        at: record.at,
+        event,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReplayQuery {
+    /// How many moves in; the end of the game when left out.
+    pub ply: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayResponse {
+    pub ply: u64,
+    /// Moves in the whole game, so `ply` runs from 0 to this.
+    pub plies: u64,
+    /// The move that was just played; `None` before the first.
+    pub last_move: Option<MoveView>,
+    /// The game after `ply` moves, replayed from its history. Only the
+    /// last ply shows how the game ended, and none show clocks or draw
+    /// offers.
+    pub game_state: GameView,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PlaybackQuery {
+    /// Milliseconds between moves.
+    pub interval_ms: Option<u64>,
+    /// The ply to start from; the starting position when left out.
+    pub from: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaitQuery {
+    /// The `version` of the game the caller already has.
+    pub since_version: u64,
+    pub timeout_secs: Option<u64>,
+}
+
+impl ReplayResponse {
+    /// `entry` as it stood after `ply` moves; `None` if fewer were played.
+    fn at(entry: &GameEntry, ply: u64) -> Result<Option<Self>, Error> {
+        let Some(game_state) = events::replay(&entry.events, ply)? else {
+            return Ok(None);
+        };
+        let moves = entry.moves();
+        let size = entry.state.board.size();
+        Ok(Some(Self {
+            ply,
+            plies: moves.len() as u64,
+            last_move: ply
+                .checked_sub(1)
+                .map(|index| move_view(&moves[index as usize], size)),
+            game_state: game_state.into(),
+        }))
+    }
+}
+
+// --- Handlers ---
+
+/// Lists a game's events, oldest first. With `through`, the log stops there
+/// and `game_state` is rebuilt from it, showing the game at that point.
+async fn get_game_events(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Query(query): Query<EventsQuery>,
+    Accept(format): Accept,
+) -> Result<Encoded<EventsResponse>, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    let size = entry.state.board.size();
+    let (logged, game_state) = match query.through {
+        Some(through) if through + 1 < entry.events.len() as u64 => {
+            let logged = &entry.events[..=through as usize];
+            (logged, events::fold(logged)?)
+        }
+        _ => (&entry.events[..], entry.state),
+    };
+    Ok(Encoded(
+        format,
+        EventsResponse {
+            events: logged
+                .iter()
+                .map(|record| event_view(record, size))
+                .collect(),
+            game_state: game_state.into(),
+        },
+    ))
+}
+
+/// The game as it stood after `ply` moves, for stepping through it.
+async fn get_game_replay(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Query(query): Query<ReplayQuery>,
+    Accept(format): Accept,
+) -> Result<Encoded<ReplayResponse>, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    let ply = query.ply.unwrap_or(entry.moves().len() as u64);
+    let replay =
+        ReplayResponse::at(&entry, ply)?.ok_or(Error::BadRequest("ply is past the last move"))?;
+    Ok(Encoded(format, replay))
+}
+
+/// Plays a finished game back: a `ply` event per position, shaped like a
+/// replay response and `interval_ms` apart, then an `end` event. Each `ply`
+/// event's ID is its ply, so a reconnecting client picks up after the last
+/// one it got.
+async fn stream_game_replay(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Query(query): Query<PlaybackQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, Error> {
+    let interval_ms = query.interval_ms.unwrap_or(DEFAULT_PLAYBACK_INTERVAL_MS);
+    if !PLAYBACK_INTERVAL_MS.contains(&interval_ms) {
+        return Err(Error::BadRequest(
+            "interval_ms must be between 100 and 10000",
+        ));
+    }
+    let resumed = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok()?.parse::<u64>().ok())
+        .map(|ply| ply + 1);
+    let from = resumed.or(query.from).unwrap_or(0);
+
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    if entry.state.status == game::GameStatus::InProgress {
+        return Err(Error::BadRequest("Only finished games can be played back"));
+    }
+    let mut frames = Vec::new();
+    for ply in from..=entry.moves().len() as u64 {
+        let Some(replay) = ReplayResponse::at(&entry, ply)? else {
+            break;
+        };
+        let frame = sse::Event::default()
+            .event("ply")
+            .id(ply.to_string())
+            .json_data(&replay)
+            .expect("replays serialize");
+        frames.push(frame);
+    }
+    drop(entry);
+    frames.push(sse::Event::default().event("end").data(""));
+
+    let interval = Duration::from_millis(interval_ms);
+    let playback =
+        stream::iter(frames.into_iter().enumerate()).then(move |(index, frame)| async move {
+            if index > 0 {
+                tokio::time::sleep(interval).await;
+            }
+            Ok(frame)
+        });
+    Ok(Sse::new(playback).keep_alive(KeepAlive::default()))
+}
+
+/// Long polling, for clients that can't follow `/stream`: answers with the
+/// game as soon as its `version` is past `since_version`, or as it stands
+/// once it is over or `timeout_secs` pass without a change.
+async fn wait_for_change(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Query(query): Query<WaitQuery>,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let timeout_secs = query.timeout_secs.unwrap_or(DEFAULT_WAIT_SECS);
+    if !WAIT_SECS.contains(&timeout_secs) {
+        return Err(Error::BadRequest("timeout_secs must be between 1 and 25"));
+    }
+    let timeout = tokio::time::sleep(Duration::from_secs(timeout_secs));
+    tokio::pin!(timeout);
+    // Subscribed before the first look, so no change falls in between.
+    let mut updates = state.updates.subscribe();
+    loop {
+        let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+        let game_state = game.lock().await.state;
+        if game_state.version > query.since_version
+            || game_state.status != game::GameStatus::InProgress
+        {
+            return Ok(Encoded(format, game_state.into()));
+        }
+        loop {
+            tokio::select! {
+                _ = &mut timeout => return Ok(Encoded(format, game_state.into())),
+                update = updates.recv() => match update {
+                    Ok(update) if update.game_id != game_id => continue,
+                    // Missed updates may have been this game's.
+                    Ok(_) | Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => return Ok(Encoded(format, game_state.into())),
+                },
+            }
+        }
+    }
+}
+
+/// Follows a game: a `game` event with the game as it stands, then another
+/// after every change to it, until it is over or purged.
+async fn stream_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, Error> {
+    // Subscribed before the first look, so no change falls in between.
+    let updates = state.updates.subscribe();
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let first: GameView = game.lock().await.state.into();
+    let frame = |view: &GameView| {
+        sse::Event::default()
+            .event("game")
+            .json_data(view)
+            .expect("games serialize")
+    };
+    let following = stream::unfold(
+        (Some(first), updates, false),
+        move |(pending, mut updates, over)| {
+            let state = state.clone();
+            async move {
+                if let Some(view) = pending {
+                    let over = view.is_over();
+                    return Some((Ok(frame(&view)), (None, updates, over)));
+                }
+                if over {
+                    return None;
+                }
+                loop {
+                    match updates.recv().await {
+                        Ok(update) if update.game_id != game_id => continue,
+                        Ok(update) if update.event == GameEvent::Expired => return None,
+                        // Missed updates may have been this game's.
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return None,
+                    }
+                    let game = state.game(&game_id)?;
+                    let view: GameView = game.lock().await.state.into();
+                    let over = view.is_over();
+                    return Some((Ok(frame(&view)), (None, updates, over)));
+                }
+            }
+        },
+    );
+    Ok(Sse::new(following).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{sync::Mutex, time::Instant};
+
+    use super::*;
+    use crate::{
+        codec::Format,
+        game::{GameState, Player, PlayerMove, try_move},
+        state::GameRegistry,
+        store::MemoryStore,
+    };
+
+    fn state_with_game() -> (AppState, Uuid) {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let game_id = Uuid::new_v4();
+        state.games.insert(
+            game_id,
+            Arc::new(Mutex::new(GameEntry::new(GameState::default()))),
+        );
+        (state, game_id)
+    }
+
+    async fn wait(state: AppState, game_id: Uuid, timeout_secs: u64) -> GameView {
+        let query = WaitQuery {
+            since_version: 0,
+            timeout_secs: Some(timeout_secs),
+        };
+        let Encoded(_, view) = wait_for_change(
+            State(state),
+            Path(game_id),
+            Query(query),
+            Accept(Format::Json),
+        )
+        .await
+        .unwrap();
+        view
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_wait_ends_as_soon_as_the_game_changes() {
+        let (state, game_id) = state_with_game();
+        let started = Instant::now();
+        let waiting = tokio::spawn(wait(state.clone(), game_id, 25));
+        while state.updates.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let game = state.game(&game_id).unwrap();
+        try_move(
+            &mut game.lock().await.state,
+            Player::X,
+            PlayerMove { row: 1, col: 1 },
+        )
+        .unwrap();
+        state.publish(game_id, GameEvent::Moved { finished: false });
+        let view = waiting.await.unwrap();
+        assert_eq!(view.version, 1);
+        assert!(started.elapsed() < Duration::from_secs(25));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_wait_without_a_change_ends_at_the_timeout() {
+        let (state, game_id) = state_with_game();
+        let started = Instant::now();
+        let waiting = tokio::spawn(wait(state.clone(), game_id, 5));
+        while state.updates.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        // Changes to other games don't end the wait.
+        state.publish(Uuid::new_v4(), GameEvent::Moved { finished: false });
+
+        let view = waiting.await.unwrap();
+        assert_eq!(view.version, 0);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,148 @@
+//! Invite endpoints: X invites an opponent with a single-use link, and
+//! whoever opens it first takes the O seat.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get, post},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{GameView, NameRequest, check_name, seat_token};
+use crate::{
+    Error,
+    invite::{self, DEFAULT_TTL_SECS, InviteError, MAX_TTL_SECS},
+    state::{AppState, GameMode},
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/games/{game_id}/invite", post(create_invite))
+        .route(
+            "/games/{game_id}/invites/{invite_id}",
+            delete(revoke_invite),
+        )
+        .route("/invites/{token}", get(get_invite).post(redeem_invite))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct InviteRequest {
+    /// X's display name, used when a fresh engine game is opened up.
+    pub name: Option<String>,
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedInvite {
+    pub invite_id: Uuid,
+    pub token: String,
+    /// Path to share; opening it shows the invite, posting to it accepts.
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+    /// X's seat token, returned only when this request opened the game up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seat_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteView {
+    pub invite_id: Uuid,
+    pub game_id: Uuid,
+    pub host: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RedeemedInvite {
+    pub game_id: Uuid,
+    pub game_state: GameView,
+    /// Send as the `Seat-Token` header when moving as O.
+    pub token: String,
+}
+
+// --- Handlers ---
+
+/// Issues a single-use invite for the O seat. A game with no moves yet that
+/// is set up against the engine becomes a player-vs-player game with the
+/// caller as X; otherwise the caller must send X's `Seat-Token`.
+async fn create_invite(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(request): Json<InviteRequest>,
+) -> Result<(StatusCode, Json<CreatedInvite>), Error> {
+    let name = check_name(request.name.as_deref().unwrap_or("X"))?;
+    let ttl_secs = request.ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+    if !(1..=MAX_TTL_SECS).contains(&ttl_secs) {
+        return Err(Error::BadRequest(
+            "ttl_secs must be between 1 second and 7 days",
+        ));
+    }
+    let ttl = TimeDelta::seconds(ttl_secs as i64);
+    let issued = invite::create(&state, game_id, seat_token(&headers), name, ttl).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedInvite {
+            invite_id: issued.invite.id,
+            url: format!("/api/v1/invites/{}", issued.token),
+            token: issued.token,
+            expires_at: issued.invite.expires_at,
+            seat_token: issued.host_token,
+        }),
+    ))
+}
+
+/// Shows who is inviting, without using the invite up.
+async fn get_invite(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<InviteView>, Error> {
+    let invite = state
+        .invites
+        .peek(&token, Utc::now())
+        .map_err(Error::Invite)?;
+    let game = state
+        .game(&invite.game_id)
+        .ok_or(Error::GameNotFound(invite.game_id))?;
+    let host = match &game.lock().await.mode {
+        GameMode::Open { x } => x.name.clone(),
+        _ => return Err(Error::Invite(InviteError::SeatTaken)),
+    };
+    Ok(Json(InviteView {
+        invite_id: invite.id,
+        game_id: invite.game_id,
+        host,
+        expires_at: invite.expires_at,
+    }))
+}
+
+/// Uses the invite, seating the caller as O.
+async fn redeem_invite(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Json(request): Json<NameRequest>,
+) -> Result<Json<RedeemedInvite>, Error> {
+    let (game_id, game_state, seat) =
+        invite::redeem(&state, &token, check_name(&request.name)?).await?;
+    Ok(Json(RedeemedInvite {
+        game_id,
+        game_state: game_state.into(),
+        token: seat.token,
+    }))
+}
+
+/// Revokes an outstanding invite. Requires X's `Seat-Token`.
+async fn revoke_invite(
+    State(state): State<AppState>,
+    Path((game_id, invite_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, Error> {
+    invite::revoke(&state, game_id, invite_id, seat_token(&headers)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,68 @@
+//! Reports the caller's quotas and how much of them is used.
+
+use axum::{Json, Router, extract::State, http::HeaderMap, routing::get};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::caller;
+use crate::{
+    Error,
+    limits::{self, LimitError},
+    state::AppState,
+    tenant::Tenant,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/limits", get(get_limits))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct QuotaView {
+    /// `null` when unlimited.
+    pub limit: Option<u32>,
+    pub used: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalysisQuotaView {
+    /// Calls per minute; `null` when unlimited.
+    pub limit: Option<u32>,
+    pub used: u32,
+    /// When `used` starts over; `null` if nothing is counted yet.
+    pub resets_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitsResponse {
+    /// `guest`, `registered`, or the API key's tier.
+    pub tier: String,
+    pub analysis: AnalysisQuotaView,
+    pub concurrent_games: QuotaView,
+}
+
+// --- Handlers ---
+
+async fn get_limits(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> Result<Json<LimitsResponse>, Error> {
+    let caller = caller(&state, &headers, &tenant)
+        .await?
+        .ok_or(Error::Limit(LimitError::Anonymous))?;
+    let report = limits::report(&state, &caller).await;
+    Ok(Json(LimitsResponse {
+        tier: report.tier,
+        analysis: AnalysisQuotaView {
+            limit: report.quotas.analysis_per_minute,
+            used: report.analysis_calls,
+            resets_at: report.analysis_resets_at,
+        },
+        concurrent_games: QuotaView {
+            limit: report.quotas.concurrent_games,
+            used: report.games_in_progress,
+        },
+    }))
+}
@@ -0,0 +1,153 @@
+//! Lobby endpoints: open a lobby, share its code, and join with it.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{GameView, NameRequest, check_name, session_account};
+use crate::{
+    Error,
+    clock::TimeControl,
+    flags::Flag,
+    lobby::{self, Lobby, LobbyError},
+    state::AppState,
+    tenant::Tenant,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/lobby", post(open_lobby))
+        .route("/lobby/{code}", get(get_lobby))
+        .route("/lobby/{code}/join", post(join_lobby))
+}
+
+// --- Wire Types ---
+
+/// A lobby without its seat tokens.
+#[derive(Debug, Serialize)]
+pub struct LobbyView {
+    pub code: String,
+    pub host: String,
+    pub guest: Option<String>,
+    /// `null` until someone joins; the host polls for it.
+    pub game_id: Option<Uuid>,
+    pub pie_rule: bool,
+    pub time_control: Option<TimeControl>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<&Lobby> for LobbyView {
+    fn from(lobby: &Lobby) -> Self {
+        Self {
+            code: lobby.code.clone(),
+            host: lobby.host.name.clone(),
+            guest: lobby.guest.as_ref().map(|seat| seat.name.clone()),
+            game_id: lobby.game_id,
+            pie_rule: lobby.pie_rule,
+            time_control: lobby.time_control,
+            expires_at: lobby.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenLobbyRequest {
+    pub name: String,
+    /// After X's first move, O may swap sides instead of replying.
+    #[serde(default)]
+    pub pie_rule: bool,
+    /// Play a timed game, e.g. `{"initial_secs": 300, "increment_secs": 3}`.
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenedLobby {
+    pub lobby: LobbyView,
+    /// Send as the `Seat-Token` header when moving as X.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JoinedLobby {
+    pub lobby: LobbyView,
+    pub game_id: Uuid,
+    pub game_state: GameView,
+    /// Send as the `Seat-Token` header when moving as O.
+    pub token: String,
+}
+
+// --- Handlers ---
+
+/// Opens a lobby with the caller in the X seat.
+async fn open_lobby(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Json(request): Json<OpenLobbyRequest>,
+) -> Result<(StatusCode, Json<OpenedLobby>), Error> {
+    state.flags.require(Flag::Lobbies, &tenant)?;
+    state.accepting_games()?;
+    if let Some(time_control) = &request.time_control {
+        time_control.validate()?;
+    }
+    let lobby = state
+        .lobbies
+        .open(
+            check_name(&request.name)?,
+            session_account(&state, &headers).await?,
+            request.pie_rule,
+            request.time_control,
+            Utc::now(),
+        )
+        .map_err(Error::Lobby)?;
+    log::info!("Opened lobby {}", lobby.code);
+    Ok((
+        StatusCode::CREATED,
+        Json(OpenedLobby {
+            lobby: LobbyView::from(&lobby),
+            token: lobby.host.token,
+        }),
+    ))
+}
+
+async fn get_lobby(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<LobbyView>, Error> {
+    let lobby = state
+        .lobbies
+        .get(&code, Utc::now())
+        .ok_or(Error::Lobby(LobbyError::NotFound))?;
+    Ok(Json(LobbyView::from(&lobby)))
+}
+
+/// Takes the O seat and starts the game.
+async fn join_lobby(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<JoinedLobby>), Error> {
+    let account_id = session_account(&state, &headers).await?;
+    let lobby = lobby::join(&state, &code, check_name(&request.name)?, account_id).await?;
+    let game_id = lobby.game_id.expect("joined lobbies have a game");
+    let guest = lobby.guest.as_ref().expect("joined lobbies have a guest");
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let game_state = game.lock().await.state;
+    Ok((
+        StatusCode::CREATED,
+        Json(JoinedLobby {
+            lobby: LobbyView::from(&lobby),
+            game_id,
+            game_state: game_state.into(),
+            token: guest.token.clone(),
+        }),
+    ))
+}
@@ -0,0 +1,145 @@
+//! Best-of-N match endpoints.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::check_name;
+use crate::{
+    Error,
+    matches::{self, MAX_BEST_OF, Match, MatchGame, MatchStatus},
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/matches", post(create_match))
+        .route("/matches/{match_id}", get(get_match))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct MatchGameView {
+    pub game_id: Uuid,
+    /// Names of the players on each side.
+    pub x: String,
+    pub o: String,
+    pub finished: bool,
+    /// Name of the winner; `null` for a draw or while the game is on.
+    pub winner: Option<String>,
+}
+
+/// A match without its seat tokens.
+#[derive(Debug, Serialize)]
+pub struct MatchView {
+    pub id: Uuid,
+    pub best_of: u32,
+    pub players: [String; 2],
+    /// Games won, in the same order as `players`.
+    pub score: [u32; 2],
+    pub draws: u32,
+    pub games: Vec<MatchGameView>,
+    /// The game to play next; `null` once the match is over.
+    pub current_game: Option<Uuid>,
+    pub status: MatchStatus,
+    /// `null` while in progress or if the match ended level.
+    pub winner: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Match> for MatchView {
+    fn from(m: &Match) -> Self {
+        let name = |index: usize| m.players[index].name.clone();
+        let game_view = |game: &MatchGame| MatchGameView {
+            game_id: game.game_id,
+            x: name(game.x),
+            o: name(1 - game.x),
+            finished: game.result.is_some(),
+            winner: game.winner().map(name),
+        };
+        Self {
+            id: m.id,
+            best_of: m.best_of,
+            players: [name(0), name(1)],
+            score: m.score,
+            draws: m.draws,
+            games: m.games.iter().map(game_view).collect(),
+            current_game: m.current_game(),
+            status: m.status,
+            winner: m.winner.map(name),
+            created_at: m.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateMatchRequest {
+    /// The first player has X in the first game.
+    pub players: [String; 2],
+    pub best_of: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayerToken {
+    pub name: String,
+    /// Send as the `Seat-Token` header when moving in any game of the match.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedMatch {
+    #[serde(rename = "match")]
+    pub m: MatchView,
+    /// Only shown once.
+    pub players: Vec<PlayerToken>,
+}
+
+// --- Handlers ---
+
+/// Creates a match between two players and starts its first game.
+async fn create_match(
+    State(state): State<AppState>,
+    Json(request): Json<CreateMatchRequest>,
+) -> Result<(StatusCode, Json<CreatedMatch>), Error> {
+    if !(1..=MAX_BEST_OF).contains(&request.best_of) {
+        return Err(Error::BadRequest("best_of must be between 1 and 25"));
+    }
+    let [first, second] = &request.players;
+    let names = [check_name(first)?, check_name(second)?];
+    if names[0] == names[1] {
+        return Err(Error::BadRequest("The players need different names"));
+    }
+    let m = matches::create(&state, names, request.best_of).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedMatch {
+            m: MatchView::from(&m),
+            players: m
+                .players
+                .iter()
+                .map(|seat| PlayerToken {
+                    name: seat.name.clone(),
+                    token: seat.token.clone(),
+                })
+                .collect(),
+        }),
+    ))
+}
+
+async fn get_match(
+    State(state): State<AppState>,
+    Path(match_id): Path<Uuid>,
+) -> Result<Json<MatchView>, Error> {
+    let m = state
+        .find_match(&match_id)
+        .ok_or(Error::MatchNotFound(match_id))?;
+    let m = m.lock().await;
+    Ok(Json(MatchView::from(&*m)))
+}
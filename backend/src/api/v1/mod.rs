@@ -0,0 +1,700 @@
+//! Version 1 of the REST API.
+//!
+//! The response types here are frozen copies of the JSON the API returned
+//! when it was versioned; the core ones live in the `laika-types` crate,
+//! so clients decode exactly what is sent. Core types may grow new
+//! variants or fields; the `From` impls below are where they get mapped
+//! back onto the v1 shapes.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use laika_types as wire;
+
+use super::{ClientIp, bearer_token};
+use crate::{
+    API_KEY_HEADER, Error, IDEMPOTENCY_KEY_HEADER, MoveRequest, PREFER_HEADER,
+    PREFERENCE_APPLIED_HEADER, SEAT_TOKEN_HEADER, account,
+    audit::{self, Action, Actor},
+    clock::{Clock, IncrementMode, TimeControl, TimeoutAction},
+    codec::{self, Accept, Decoded, Encoded},
+    flags::Flag,
+    game::{self, GameState, PlayerMove, Rules},
+    import::ImportRequest,
+    limits::Caller,
+    notation, render,
+    simulate::{self, MAX_SIMULATION_GAMES, SimulationReport, SimulationRequest},
+    state::AppState,
+    tenant::Tenant,
+};
+
+mod accounts;
+mod analyze;
+mod arenas;
+mod bots;
+mod clocks;
+mod draws;
+mod events;
+mod invites;
+mod limits;
+mod lobbies;
+mod matches;
+mod notakto;
+#[cfg(feature = "oauth")]
+mod oauth;
+mod presence;
+#[cfg(feature = "webpush")]
+mod push;
+mod puzzles;
+mod resume;
+mod schema;
+mod stats;
+mod three_player;
+mod tournaments;
+#[cfg(feature = "webhooks")]
+mod webhooks;
+
+pub fn router() -> Router<AppState> {
+    let router = Router::new()
+        .route("/newgame", post(new_game))
+        .route("/games/import", post(import_game))
+        .route("/games/{game_id}", get(get_game_state))
+        .route("/games/{game_id}/move", post(update_game_state))
+        .route("/games/{game_id}/moves", post(play_moves))
+        .route("/games/{game_id}/notation", get(get_game_notation))
+        .route("/games/{game_id}/board.txt", get(get_game_board_text))
+        .route("/games/{game_id}/board.png", get(get_game_board_png))
+        .route("/games/{game_id}/swap", post(swap_sides))
+        .route("/simulate", post(simulate_games))
+        .merge(accounts::router())
+        .merge(limits::router())
+        .merge(clocks::router())
+        .merge(draws::router())
+        .merge(events::router())
+        .merge(tournaments::router())
+        .merge(matches::router())
+        .merge(arenas::router())
+        .merge(notakto::router())
+        .merge(three_player::router())
+        .merge(lobbies::router())
+        .merge(invites::router())
+        .merge(resume::router())
+        .merge(presence::router())
+        .merge(bots::router())
+        .merge(analyze::router())
+        .merge(puzzles::router())
+        .merge(schema::router())
+        .merge(stats::router());
+    #[cfg(feature = "webhooks")]
+    let router = router.merge(webhooks::router());
+    #[cfg(feature = "oauth")]
+    let router = router.merge(oauth::router());
+    #[cfg(feature = "webpush")]
+    let router = router.merge(push::router());
+    router
+}
+
+// --- Wire Types ---
+
+pub use laika_types::{
+    Cell, ClocksView, Ending, GameStatus, GameView, NewGameRequest, NewGameResponse, Player,
+};
+
+impl From<game::Player> for Player {
+    fn from(player: game::Player) -> Self {
+        match player {
+            game::Player::X => Player::X,
+            game::Player::O => Player::O,
+        }
+    }
+}
+
+impl From<game::Cell> for Cell {
+    fn from(cell: game::Cell) -> Self {
+        match cell {
+            game::Cell::Empty => Cell::Empty,
+            game::Cell::Occupied(player) => Cell::Occupied(player.into()),
+            game::Cell::Blocked => Cell::Blocked,
+        }
+    }
+}
+
+impl From<game::GameStatus> for GameStatus {
+    fn from(status: game::GameStatus) -> Self {
+        match status {
+            game::GameStatus::InProgress => GameStatus::InProgress,
+            game::GameStatus::Draw => GameStatus::Draw,
+            game::GameStatus::Win(player) => GameStatus::Win(player.into()),
+        }
+    }
+}
+
+impl From<game::Ending> for Ending {
+    fn from(ending: game::Ending) -> Self {
+        match ending {
+            game::Ending::ByAgreement => Ending::ByAgreement,
+            game::Ending::OnTime => Ending::OnTime,
+            game::Ending::ByAbsence => Ending::ByAbsence,
+            game::Ending::ByForfeit => Ending::ByForfeit,
+        }
+    }
+}
+
+fn time_control_view(control: TimeControl) -> wire::TimeControl {
+    wire::TimeControl {
+        initial_secs: control.initial_secs,
+        increment_secs: control.increment_secs,
+        mode: match control.mode {
+            IncrementMode::Fischer => wire::IncrementMode::Fischer,
+            IncrementMode::Delay => wire::IncrementMode::Delay,
+        },
+        on_timeout: match control.on_timeout {
+            TimeoutAction::Forfeit => wire::TimeoutAction::Forfeit,
+            TimeoutAction::RandomMove => wire::TimeoutAction::RandomMove,
+            TimeoutAction::Draw => wire::TimeoutAction::Draw,
+        },
+    }
+}
+
+fn clocks_view(clock: &Clock, to_play: game::Player, now: DateTime<Utc>) -> ClocksView {
+    ClocksView {
+        x_ms: clock.remaining_ms(game::Player::X, to_play, now),
+        o_ms: clock.remaining_ms(game::Player::O, to_play, now),
+        running: clock.running_since.map(|_| to_play.into()),
+        time_control: time_control_view(clock.control),
+        paused_at: clock.paused_at,
+        pause_offered_by: clock.pause_offer.map(Player::from),
+    }
+}
+
+impl From<GameState> for GameView {
+    fn from(game_state: GameState) -> Self {
+        Self {
+            board: game_state
+                .board
+                .rows()
+                .into_iter()
+                .map(|row| row.into_iter().map(Cell::from).collect())
+                .collect(),
+            status: game_state.status.into(),
+            to_play: game_state.to_play.into(),
+            version: game_state.version,
+            clocks: game_state
+                .clock
+                .map(|clock| clocks_view(&clock, game_state.to_play, Utc::now())),
+            draw_offered_by: game_state.draw_offer.map(Player::from),
+            ending: game_state.ending.map(Ending::from),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotationResponse {
+    pub notation: String,
+}
+
+/// A display name for a tournament entrant or lobby seat.
+#[derive(Debug, Deserialize)]
+pub struct NameRequest {
+    pub name: String,
+}
+
+fn seat_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(SEAT_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Whether the caller sent `Prefer: respond-async` (RFC 7240), asking not to
+/// wait for work that can go on after the response.
+fn prefers_async(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(PREFER_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+}
+
+/// The account behind the caller's session, if they sent one. A session
+/// token that doesn't belong to any account is an error rather than being
+/// ignored, so a client with a stale token finds out.
+async fn session_account(state: &AppState, headers: &HeaderMap) -> Result<Option<Uuid>, Error> {
+    match bearer_token(headers) {
+        Some(token) => state
+            .accounts
+            .session(token)
+            .await
+            .map(Some)
+            .map_err(Error::Account),
+        None => Ok(None),
+    }
+}
+
+/// Who the request counts against for quotas: the API key if one was sent,
+/// otherwise the session's account in `tenant`. An unknown key or session
+/// token is an error, as in `session_account`.
+async fn caller(
+    state: &AppState,
+    headers: &HeaderMap,
+    Tenant(tenant): &Tenant,
+) -> Result<Option<Caller>, Error> {
+    if let Some(key) = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        return state.limits.api_key(key).map(Some).map_err(Error::Limit);
+    }
+    match bearer_token(headers) {
+        Some(token) => {
+            let (account, _) = account::get(state, token).await?;
+            Ok(Some(Caller::Account {
+                id: account.id,
+                guest: account.is_guest(),
+                tenant: tenant.clone(),
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Who is moving, for the audit log. Moves don't need a session, so a stale
+/// session token is ignored rather than rejected.
+async fn move_actor(state: &AppState, headers: &HeaderMap) -> Actor {
+    if let Some(token) = bearer_token(headers)
+        && let Ok(account_id) = state.accounts.session(token).await
+    {
+        return Actor::Account { account_id };
+    }
+    if seat_token(headers).is_some() {
+        Actor::Seat
+    } else {
+        Actor::Anonymous
+    }
+}
+
+fn check_name(name: &str) -> Result<String, Error> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 40 {
+        return Err(Error::BadRequest("name must be 1 to 40 characters"));
+    }
+    Ok(name.to_string())
+}
+
+// --- Handlers ---
+
+/// Creates a new game and returns the new game ID and state. The body is
+/// optional. With a session, the game is recorded against its account.
+async fn new_game(
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    tenant: Tenant,
+    headers: HeaderMap,
+    request: Option<Decoded<NewGameRequest>>,
+) -> Result<Encoded<NewGameResponse>, Error> {
+    let request = request.map(|Decoded(request)| request).unwrap_or_default();
+    if request.is_variant() {
+        state.flags.require(Flag::BoardVariants, &tenant)?;
+    }
+    if !request.blocked.is_empty() && request.random_blocked > 0 {
+        return Err(Error::BadRequest(
+            "Give either blocked or random_blocked, not both",
+        ));
+    }
+    let blocked = if request.random_blocked > 0 {
+        game::random_cells(request.rows, request.cols, request.random_blocked)
+    } else {
+        request
+            .blocked
+            .iter()
+            .map(|cell| PlayerMove {
+                row: cell.row,
+                col: cell.col,
+            })
+            .collect()
+    };
+    let rules = Rules {
+        toroidal: request.toroidal,
+        win_length: request.win_length,
+    };
+    let new_game = GameState::custom(request.rows, request.cols, rules, &blocked)?;
+    let account_id = session_account(&state, &headers).await?;
+    let caller = caller(&state, &headers, &tenant).await?;
+    let slot = crate::limits::reserve_game(&state, caller.as_ref()).await?;
+    let blunder_chance = request.blunder_chance.or_else(|| {
+        tenant
+            .config(&state)
+            .and_then(|config| config.blunder_chance)
+    });
+    let (game_id, game_state) =
+        crate::create_game(&state, blunder_chance, new_game, tenant.0).await?;
+    slot.fill(&state, game_id).await?;
+    if let Some(account_id) = account_id {
+        account::adopt(&state, account_id, game_id, game::Player::X).await?;
+    }
+    Ok(Encoded(
+        format,
+        NewGameResponse {
+            game_id,
+            game_state: game_state.into(),
+        },
+    ))
+}
+
+/// Registers a game played elsewhere, from notation or a JSON move list.
+async fn import_game(
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Decoded(import_request): Decoded<ImportRequest>,
+) -> Result<Encoded<NewGameResponse>, Error> {
+    let moves = import_request.moves().map_err(Error::InvalidImport)?;
+    let caller = caller(&state, &headers, &tenant).await?;
+    let slot = crate::limits::reserve_game(&state, caller.as_ref()).await?;
+    let (game_id, game_state) = crate::import_game(&state, &moves, tenant.0).await?;
+    slot.fill(&state, game_id).await?;
+    Ok(Encoded(
+        format,
+        NewGameResponse {
+            game_id,
+            game_state: game_state.into(),
+        },
+    ))
+}
+
+/// Returns the state of a game, including finished games that are still
+/// archived. Pollers sending the last `ETag` back in `If-None-Match` get
+/// `304 Not Modified` until the game changes.
+async fn get_game_state(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Response, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let game_state = game.lock().await.state;
+    let view = GameView::from(game_state);
+    Ok(codec::tagged(format, game_state.version, &view, &headers))
+}
+
+/// The board as plain text, one row per line, for terminals and chat:
+/// `X`, `O`, `.` for an empty cell, and `#` for a blocked one, then the
+/// status and who is to play.
+async fn get_game_board_text(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        entry.state.to_string(),
+    ))
+}
+
+/// The board as a PNG, for link previews and chat clients that won't show
+/// SVG. The last move's cell is tinted yellow and a winning line green.
+async fn get_game_board_png(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<([(header::HeaderName, &'static str); 1], Vec<u8>), Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    let last_move = entry.moves().last().map(|record| record.player_move);
+    Ok((
+        [(header::CONTENT_TYPE, "image/png")],
+        render::board_png(&entry.state, last_move),
+    ))
+}
+
+/// Exports the moves played so far in canonical text notation.
+async fn get_game_notation(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Accept(format): Accept,
+) -> Result<Encoded<NotationResponse>, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    Ok(Encoded(
+        format,
+        NotationResponse {
+            notation: notation::format_moves(entry.state.board.size().0, &entry.moves()),
+        },
+    ))
+}
+
+/// The move `request` makes in `game_id`, with a cell index or algebraic
+/// square turned into its row and column on the game's board. An index past
+/// the last cell lands below the board, and the move is refused as out of
+/// bounds.
+async fn resolve_move(
+    state: &AppState,
+    game_id: Uuid,
+    request: wire::MoveRequest,
+) -> Result<MoveRequest, Error> {
+    let size = board_size(state, game_id).await?;
+    Ok(MoveRequest {
+        player_move: resolve_target(size, request.target)?,
+        expected_version: request.expected_version,
+    })
+}
+
+async fn board_size(state: &AppState, game_id: Uuid) -> Result<(usize, usize), Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let size = game.lock().await.state.board.size();
+    Ok(size)
+}
+
+/// The square a move targets, on a board of `(rows, cols)`.
+fn resolve_target(
+    (rows, cols): (usize, usize),
+    target: wire::MoveTarget,
+) -> Result<PlayerMove, Error> {
+    match target {
+        wire::MoveTarget::Square { row, col } => Ok(PlayerMove { row, col }),
+        wire::MoveTarget::Cell { cell } => Ok(PlayerMove {
+            row: cell / cols,
+            col: cell % cols,
+        }),
+        wire::MoveTarget::Algebraic { square } => notation::parse_square_on((rows, cols), &square)
+            .ok_or(Error::InvalidMove("Unknown square")),
+    }
+}
+
+/// Submits a move, as a `row` and `col`, a `cell` index, or a `square`.
+/// Against the engine the AI replies in the same request, unless the caller
+/// prefers `respond-async`: then the move is answered with `202 Accepted` and
+/// the reply comes on the game's stream. Player-vs-player games need a
+/// `Seat-Token` header.
+async fn update_game_state(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Accept(format): Accept,
+    Decoded(request): Decoded<wire::MoveRequest>,
+) -> Result<Response, Error> {
+    let move_request = resolve_move(&state, game_id, request).await?;
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let actor = move_actor(&state, &headers).await;
+    let in_background = prefers_async(&headers);
+    let (game_state, reply) = crate::submit_move(
+        &state,
+        game_id,
+        move_request,
+        idempotency_key,
+        seat_token(&headers),
+        in_background,
+    )
+    .await?;
+    let PlayerMove { row, col } = move_request.player_move;
+    audit::record(&state, actor, ip, Action::Move { game_id, row, col }).await;
+    let game_state = match reply {
+        Some(_) if in_background => {
+            let view = Encoded::<GameView>(format, game_state.into());
+            let applied = [(PREFERENCE_APPLIED_HEADER, "respond-async")];
+            return Ok((StatusCode::ACCEPTED, applied, view).into_response());
+        }
+        Some(reply) => reply.await.map_err(|_| Error::Internal)??,
+        None => game_state,
+    };
+    Ok(Encoded::<GameView>(format, game_state.into()).into_response())
+}
+
+/// Submits several moves in one request, played in order and kept only if
+/// all of them are legal. Against the engine each gets the AI's reply as a
+/// single move would; in player-vs-player games each is for the side to
+/// move, so sending both seats' `Seat-Token` headers replays both sides.
+async fn play_moves(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Accept(format): Accept,
+    Decoded(request): Decoded<wire::MoveBatchRequest>,
+) -> Result<Encoded<GameView>, Error> {
+    if request.moves.is_empty() || request.moves.len() > game::MAX_SIDE * game::MAX_SIDE {
+        return Err(Error::BadRequest("moves must list between 1 and 64 moves"));
+    }
+    let size = board_size(&state, game_id).await?;
+    let moves = request
+        .moves
+        .into_iter()
+        .enumerate()
+        .map(|(index, target)| {
+            resolve_target(size, target).map_err(|error| Error::InBatch {
+                index,
+                error: Box::new(error),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let seat_tokens: Vec<&str> = headers
+        .get_all(SEAT_TOKEN_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    let actor = move_actor(&state, &headers).await;
+    let game_state = crate::play_moves(
+        &state,
+        game_id,
+        &moves,
+        request.expected_version,
+        &seat_tokens,
+    )
+    .await?;
+    for &PlayerMove { row, col } in &moves {
+        audit::record(&state, actor, ip, Action::Move { game_id, row, col }).await;
+    }
+    Ok(Encoded(format, game_state.into()))
+}
+
+/// Invokes the pie rule: O takes over X's first move instead of replying,
+/// and the first mover continues as O. Needs O's `Seat-Token` header.
+async fn swap_sides(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<GameView>, Error> {
+    let game_state = crate::swap_sides(&state, game_id, seat_token(&headers)).await?;
+    Ok(Encoded(format, game_state.into()))
+}
+
+/// Plays a batch of engine-vs-engine games and returns aggregate results.
+async fn simulate_games(
+    Json(request): Json<SimulationRequest>,
+) -> Result<Json<SimulationReport>, Error> {
+    if request.games == 0 || request.games > MAX_SIMULATION_GAMES {
+        return Err(Error::BadRequest("games must be between 1 and 1000"));
+    }
+
+    // The search is CPU-bound, so keep it off the async worker threads.
+    let report = tokio::task::spawn_blocking(move || simulate::simulate(&request))
+        .await
+        .expect("simulation task panicked");
+    log::info!(
+        "Simulated {} games of {:?} vs {:?}",
+        report.games,
+        request.x,
+        request.o
+    );
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{PlayerMove, try_move};
+
+    #[test]
+    fn test_game_view_matches_the_v1_wire_format() {
+        let mut game_state = GameState::default();
+        try_move(
+            &mut game_state,
+            game::Player::X,
+            PlayerMove { row: 0, col: 2 },
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(GameView::from(game_state)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "board": [
+                    ["Empty", "Empty", {"Occupied": "X"}],
+                    ["Empty", "Empty", "Empty"],
+                    ["Empty", "Empty", "Empty"],
+                ],
+                "status": "InProgress",
+                "to_play": "O",
+                "version": 1,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_the_client_plays_against_the_ai_and_follows_the_game() {
+        use std::{net::SocketAddr, sync::Arc};
+
+        use futures_util::StreamExt;
+        use laika_client::{
+            Client,
+            types::{MoveRequest, NewGameRequest},
+        };
+
+        use crate::{config::AdminConfig, state::GameRegistry, store::MemoryStore};
+
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let app = crate::api::router(state, &AdminConfig::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let client = Client::new(format!("http://{addr}"));
+        let created = client
+            .create_game(&NewGameRequest::default())
+            .await
+            .unwrap();
+        let game_id = created.game_id;
+        let following = client.subscribe(game_id).await.unwrap();
+        let mut view = created.game_state;
+        while !view.is_over() {
+            let (row, col) = (0..3)
+                .flat_map(|row| (0..3).map(move |col| (row, col)))
+                .find(|&(row, col)| view.board[row][col] == Cell::Empty)
+                .unwrap();
+            // Each of the three ways to name a cell.
+            let request = match view.version % 6 {
+                0 => MoveRequest::new(row, col),
+                2 => MoveRequest::cell(row * 3 + col),
+                _ => MoveRequest::square(format!("{}{}", (b'a' + col as u8) as char, 3 - row)),
+            };
+            let request = request.expecting(view.version);
+            view = client.make_move(game_id, &request, None).await.unwrap();
+        }
+        let stale = MoveRequest::new(0, 0).expecting(0);
+        let error = client.make_move(game_id, &stale, None).await.unwrap_err();
+        assert!(matches!(
+            error,
+            laika_client::Error::Api { status: 409, .. }
+        ));
+
+        let seen: Vec<_> = following.map(Result::unwrap).collect().await;
+        assert_eq!(seen.first().unwrap().version, 0);
+        assert_eq!(seen.last(), Some(&view));
+        assert_eq!(client.game(game_id).await.unwrap(), view);
+
+        // Against an AI replying in the background, a poll waits for it.
+        let game_id = client
+            .create_game(&NewGameRequest::default())
+            .await
+            .unwrap()
+            .game_id;
+        let moved = client
+            .make_move_in_background(game_id, &MoveRequest::cell(4))
+            .await
+            .unwrap();
+        assert_eq!(moved.version, 1);
+        let replied = client.wait_for_change(game_id, 1).await.unwrap();
+        assert_eq!(replied.version, 2);
+        assert_eq!(client.game(game_id).await.unwrap(), replied);
+    }
+}
@@ -0,0 +1,147 @@
+//! Notakto endpoints: misère tic-tac-toe against the engine.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Cell, Player};
+use crate::{
+    Error,
+    flags::Flag,
+    notakto::{self, NotaktoGame, NotaktoMove, Side},
+    state::AppState,
+    tenant::Tenant,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/notakto", post(new_game))
+        .route("/notakto/{game_id}", get(get_game))
+        .route("/notakto/{game_id}/move", post(play_move))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NewNotaktoRequest {
+    pub boards: usize,
+    /// Let the engine make the first move.
+    pub engine_first: bool,
+}
+
+impl Default for NewNotaktoRequest {
+    fn default() -> Self {
+        Self {
+            boards: 1,
+            engine_first: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotaktoBoardView {
+    pub cells: [[Cell; 3]; 3],
+    /// Has three in a row and takes no more moves.
+    pub dead: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotaktoStatus {
+    InProgress,
+    /// The engine killed the last board.
+    Won,
+    /// You killed the last board.
+    Lost,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotaktoView {
+    pub game_id: Uuid,
+    pub boards: Vec<NotaktoBoardView>,
+    /// Every move so far, by both sides, starting with `first`.
+    pub moves: Vec<NotaktoMove>,
+    pub first: Side,
+    pub status: NotaktoStatus,
+}
+
+impl From<&NotaktoGame> for NotaktoView {
+    fn from(game: &NotaktoGame) -> Self {
+        let boards = (0..game.position.boards.len())
+            .map(|board| {
+                let mask = game.position.boards[board];
+                NotaktoBoardView {
+                    cells: std::array::from_fn(|row| {
+                        std::array::from_fn(|col| {
+                            if mask & (1 << (row * 3 + col)) != 0 {
+                                Cell::Occupied(Player::X)
+                            } else {
+                                Cell::Empty
+                            }
+                        })
+                    }),
+                    dead: game.position.is_dead(board),
+                }
+            })
+            .collect();
+        Self {
+            game_id: game.id,
+            boards,
+            moves: game.moves.clone(),
+            first: game.first,
+            status: match game.loser {
+                None => NotaktoStatus::InProgress,
+                Some(Side::Engine) => NotaktoStatus::Won,
+                Some(Side::Human) => NotaktoStatus::Lost,
+            },
+        }
+    }
+}
+
+// --- Handlers ---
+
+/// Starts a Notakto game against the engine. If the engine goes first, its
+/// opening move is already on the board.
+async fn new_game(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Json(request): Json<NewNotaktoRequest>,
+) -> Result<(StatusCode, Json<NotaktoView>), Error> {
+    state.flags.require(Flag::Notakto, &tenant)?;
+    let first = if request.engine_first {
+        Side::Engine
+    } else {
+        Side::Human
+    };
+    let game = notakto::create(&state, request.boards, first).await?;
+    Ok((StatusCode::CREATED, Json(NotaktoView::from(&game))))
+}
+
+async fn get_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<Json<NotaktoView>, Error> {
+    let game = state
+        .notakto
+        .get(&game_id)
+        .map(|game| game.clone())
+        .ok_or(Error::GameNotFound(game_id))?;
+    let game = game.lock().await;
+    Ok(Json(NotaktoView::from(&*game)))
+}
+
+/// Places an X; the engine replies in the same request.
+async fn play_move(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Json(player_move): Json<NotaktoMove>,
+) -> Result<Json<NotaktoView>, Error> {
+    let game = notakto::play(&state, game_id, player_move).await?;
+    Ok(Json(NotaktoView::from(&game)))
+}
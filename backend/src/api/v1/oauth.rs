@@ -0,0 +1,99 @@
+//! OAuth login endpoints: list the providers, start a login, and finish it
+//! with the code the provider sent back.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{ClientIp, accounts::SessionResponse, session_account};
+use crate::{
+    Error,
+    audit::{self, Action, Actor},
+    oauth,
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/oauth/providers", get(list_providers))
+        .route("/oauth/{provider}/authorize", post(authorize))
+        .route("/oauth/{provider}/callback", post(callback))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct ProvidersResponse {
+    pub providers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthorizeResponse {
+    /// Send the player here.
+    pub url: String,
+    /// Comes back from the provider along with the code.
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+// --- Handlers ---
+
+async fn list_providers(State(state): State<AppState>) -> Json<ProvidersResponse> {
+    Json(ProvidersResponse {
+        providers: state.oauth.provider_names(),
+    })
+}
+
+/// Starts a login. With a session, the login is linked to its account.
+async fn authorize(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<AuthorizeResponse>, Error> {
+    let account_id = session_account(&state, &headers).await?;
+    let (url, oauth_state) = state
+        .oauth
+        .authorize_url(&provider, account_id, Utc::now())
+        .map_err(Error::OAuth)?;
+    Ok(Json(AuthorizeResponse {
+        url,
+        state: oauth_state,
+    }))
+}
+
+/// Finishes a login and starts a session for the account.
+async fn callback(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    ClientIp(ip): ClientIp,
+    Json(request): Json<CallbackRequest>,
+) -> Result<(StatusCode, Json<SessionResponse>), Error> {
+    let (account, session) =
+        oauth::complete(&state, &provider, &request.code, &request.state).await?;
+    audit::record(
+        &state,
+        Actor::Account {
+            account_id: account.id,
+        },
+        ip,
+        Action::Login {
+            account_id: account.id,
+            method: provider,
+        },
+    )
+    .await;
+    Ok((
+        StatusCode::CREATED,
+        Json(SessionResponse::new(&account, session)),
+    ))
+}
@@ -0,0 +1,130 @@
+//! Presence endpoints: a WebSocket each seat holds open while the player is
+//! around, and a snapshot of who is connected.
+
+use axum::{
+    Json, Router,
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+    routing::get,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Instant, interval};
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::Player,
+    presence::PresenceView,
+    state::{AppState, GameEvent, GameMode},
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/games/{game_id}/presence", get(get_presence))
+        .route("/games/{game_id}/presence/ws", get(connect))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct GamePresence {
+    pub x: PresenceView,
+    pub o: PresenceView,
+}
+
+impl GamePresence {
+    fn of(state: &AppState, game_id: Uuid) -> Self {
+        let now = Utc::now();
+        Self {
+            x: state.presence.status(game_id, Player::X, now),
+            o: state.presence.status(game_id, Player::O, now),
+        }
+    }
+}
+
+/// Browsers can't set headers on a WebSocket handshake, so the seat token
+/// comes in the query string.
+#[derive(Debug, Deserialize)]
+pub struct ConnectQuery {
+    pub seat_token: String,
+}
+
+// --- Handlers ---
+
+/// Who is connected to a player-vs-player game.
+async fn get_presence(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<Json<GamePresence>, Error> {
+    if state.game(&game_id).is_none() {
+        return Err(Error::GameNotFound(game_id));
+    }
+    Ok(Json(GamePresence::of(&state, game_id)))
+}
+
+/// Marks the seat online for as long as the socket stays open. Each text
+/// message is answered with the current presence of both seats.
+async fn connect(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Query(query): Query<ConnectQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let player = match &game.lock().await.mode {
+        GameMode::VsEngine | GameMode::VsBot { .. } => None,
+        mode => mode.seat_of(&query.seat_token),
+    }
+    .ok_or(Error::Forbidden(
+        "A valid seat token is required to connect to this game",
+    ))?;
+    Ok(ws.on_upgrade(move |socket| hold(socket, state, game_id, player)))
+}
+
+async fn hold(mut socket: WebSocket, state: AppState, game_id: Uuid, mut player: Player) {
+    let timeout = state.presence.config().heartbeat_timeout();
+    let mut updates = state.updates.subscribe();
+    state.presence.connect(game_id, player, Utc::now());
+    let mut ping = interval(timeout / 2);
+    let mut last_heard = Instant::now();
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if last_heard.elapsed() >= timeout
+                    || socket.send(Message::Ping(Default::default())).await.is_err()
+                {
+                    break;
+                }
+            }
+            update = updates.recv() => {
+                // After a pie-rule swap this connection holds the other seat.
+                if let Ok(update) = update
+                    && update.game_id == game_id
+                    && update.event == GameEvent::Swapped
+                {
+                    player = player.opponent();
+                }
+            }
+            message = socket.recv() => {
+                let message = match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(message)) => message,
+                };
+                last_heard = Instant::now();
+                state.presence.heartbeat(game_id, player, Utc::now());
+                if let Message::Text(_) = message {
+                    let reply = serde_json::to_string(&GamePresence::of(&state, game_id))
+                        .expect("presence serializes");
+                    if socket.send(Message::Text(reply.into())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    state.presence.disconnect(game_id, player, Utc::now());
+}
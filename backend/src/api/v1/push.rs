@@ -0,0 +1,95 @@
+//! Web Push endpoints: the key browsers subscribe with, and adding and
+//! removing the subscriptions of the session's account.
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::accounts::session_token;
+use crate::{
+    Error,
+    account::{self, PushSubscription},
+    push::check_subscription,
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/push/key", get(get_key))
+        .route("/push/subscriptions", post(subscribe).delete(unsubscribe))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct KeyResponse {
+    /// The VAPID public key, in base64url, to subscribe with as the
+    /// `applicationServerKey`.
+    pub public_key: String,
+}
+
+/// A `PushSubscription` as its `toJSON()` gives it.
+#[derive(Debug, Deserialize)]
+pub struct SubscriptionRequest {
+    pub endpoint: String,
+    pub keys: SubscriptionKeys,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubscriptionKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeRequest {
+    pub endpoint: String,
+}
+
+// --- Handlers ---
+
+async fn get_key(State(state): State<AppState>) -> Result<Json<KeyResponse>, Error> {
+    let public_key = state
+        .push
+        .public_key()
+        .ok_or(Error::Forbidden("Web Push isn't set up on this server"))?;
+    Ok(Json(KeyResponse {
+        public_key: public_key.to_string(),
+    }))
+}
+
+/// Sends the account's pushes to the browser behind the subscription too.
+/// Subscribing the same browser again replaces its keys.
+async fn subscribe(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<SubscriptionRequest>,
+) -> Result<StatusCode, Error> {
+    if state.push.public_key().is_none() {
+        return Err(Error::Forbidden("Web Push isn't set up on this server"));
+    }
+    let SubscriptionRequest { endpoint, keys } = request;
+    check_subscription(&endpoint, &keys.p256dh, &keys.auth)?;
+    let subscription = PushSubscription {
+        endpoint,
+        p256dh: keys.p256dh,
+        auth: keys.auth,
+        created_at: Utc::now(),
+    };
+    account::subscribe_push(&state, session_token(&headers)?, subscription).await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn unsubscribe(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<UnsubscribeRequest>,
+) -> Result<StatusCode, Error> {
+    account::unsubscribe_push(&state, Some(session_token(&headers)?), &request.endpoint).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
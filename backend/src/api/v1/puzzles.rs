@@ -0,0 +1,344 @@
+//! Puzzle endpoints: fetch a position with one right move, then answer it.
+//! The daily puzzle is the same for everyone and has a leaderboard, and so
+//! does puzzle rush, a timed run of one puzzle after another.
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{GameView, NameRequest, analyze::SquareView, check_name};
+use crate::{
+    Error,
+    game::PlayerMove,
+    puzzle::{Difficulty, Puzzle, PuzzleKind, daily, rush::Run},
+    state::AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/puzzle/random", get(random_puzzle))
+        .route("/puzzle/{puzzle_id}/answer", post(answer_puzzle))
+        .route("/puzzle/daily", get(daily_puzzle))
+        .route("/puzzle/daily/attempts", post(start_attempt))
+        .route(
+            "/puzzle/daily/attempts/{attempt_id}/answer",
+            post(answer_attempt),
+        )
+        .route("/puzzle/daily/leaderboard", get(leaderboard))
+        .route("/puzzle/rush", post(start_rush))
+        .route("/puzzle/rush/{rush_id}/answer", post(answer_rush))
+        .route("/puzzle/rush/leaderboard", get(rush_leaderboard))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+pub struct RandomQuery {
+    /// `easy`, `medium`, or `hard`; any difficulty when missing.
+    pub difficulty: Option<Difficulty>,
+}
+
+/// A puzzle without its solution.
+#[derive(Debug, Serialize)]
+pub struct PuzzleView {
+    pub puzzle_id: u32,
+    pub notation: String,
+    pub game_state: GameView,
+    /// `win`: find the winning move. `save`: find the only move that
+    /// doesn't lose.
+    pub kind: PuzzleKind,
+    pub difficulty: Difficulty,
+}
+
+impl From<&Puzzle> for PuzzleView {
+    fn from(puzzle: &Puzzle) -> Self {
+        Self {
+            puzzle_id: puzzle.id,
+            notation: puzzle.notation.clone(),
+            game_state: puzzle.game_state().into(),
+            kind: puzzle.kind,
+            difficulty: puzzle.difficulty,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnswerResponse {
+    pub correct: bool,
+    pub solution: SquareView,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyPuzzleView {
+    pub date: NaiveDate,
+    pub puzzle: PuzzleView,
+    /// Attempts started today, and how many of them solved it.
+    pub attempts: usize,
+    pub solved: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttemptView {
+    pub attempt_id: Uuid,
+    /// Send back with the answer. Only shown once.
+    pub token: String,
+    pub date: NaiveDate,
+    pub started_at: DateTime<Utc>,
+    pub puzzle: PuzzleView,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttemptAnswer {
+    pub token: String,
+    #[serde(flatten)]
+    pub player_move: PlayerMove,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttemptResult {
+    pub correct: bool,
+    /// Milliseconds from starting the attempt to answering it correctly.
+    pub solve_ms: Option<i64>,
+    /// Place on the day's leaderboard, if solved.
+    pub rank: Option<usize>,
+    pub solution: SquareView,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardQuery {
+    /// `YYYY-MM-DD`; today (UTC) when missing.
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardEntryView {
+    pub rank: usize,
+    pub name: String,
+    pub solve_ms: i64,
+    pub answered_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardView {
+    pub date: NaiveDate,
+    pub entries: Vec<LeaderboardEntryView>,
+}
+
+/// A puzzle rush after its latest answer.
+#[derive(Debug, Serialize)]
+pub struct RushView {
+    pub rush_id: Uuid,
+    /// Send back with the next answer. Each answer gets a new one.
+    pub token: String,
+    pub score: u32,
+    pub strikes: u32,
+    pub ends_at: DateTime<Utc>,
+    /// The puzzle to answer next; missing once the rush is over.
+    pub puzzle: Option<PuzzleView>,
+}
+
+impl RushView {
+    fn new(run: Run, puzzle: Option<&Puzzle>) -> Self {
+        Self {
+            rush_id: run.id,
+            token: run.token,
+            score: run.score,
+            strikes: run.strikes,
+            ends_at: run.ends_at,
+            puzzle: puzzle.map(PuzzleView::from),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RushResult {
+    pub correct: bool,
+    pub solution: SquareView,
+    #[serde(flatten)]
+    pub rush: RushView,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RushEntryView {
+    pub rank: usize,
+    pub name: String,
+    pub score: u32,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RushLeaderboardView {
+    pub entries: Vec<RushEntryView>,
+}
+
+// --- Handlers ---
+
+async fn random_puzzle(
+    State(state): State<AppState>,
+    Query(query): Query<RandomQuery>,
+) -> Result<Json<PuzzleView>, Error> {
+    let puzzle = state
+        .puzzles
+        .random(query.difficulty)
+        .map_err(Error::Puzzle)?;
+    Ok(Json(puzzle.into()))
+}
+
+/// Checks a move against the puzzle's solution, which is revealed either way.
+async fn answer_puzzle(
+    State(state): State<AppState>,
+    Path(puzzle_id): Path<u32>,
+    Json(answer): Json<PlayerMove>,
+) -> Result<Json<AnswerResponse>, Error> {
+    check_bounds(answer)?;
+    let puzzle = state.puzzles.get(puzzle_id).map_err(Error::Puzzle)?;
+    Ok(Json(AnswerResponse {
+        correct: answer == puzzle.solution,
+        solution: puzzle.solution.into(),
+    }))
+}
+
+/// Today's puzzle, without the solution. Start an attempt to get on the
+/// leaderboard.
+async fn daily_puzzle(State(state): State<AppState>) -> Result<Json<DailyPuzzleView>, Error> {
+    let date = Utc::now().date_naive();
+    let puzzle = state.puzzles.daily(date).map_err(Error::Puzzle)?;
+    let (attempts, solved) = state.puzzle_attempts.counts(date);
+    Ok(Json(DailyPuzzleView {
+        date,
+        puzzle: puzzle.into(),
+        attempts,
+        solved,
+    }))
+}
+
+/// Starts the clock on today's puzzle for `name`.
+async fn start_attempt(
+    State(state): State<AppState>,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<AttemptView>), Error> {
+    let (attempt, puzzle) = daily::start(&state, check_name(&request.name)?, Utc::now()).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(AttemptView {
+            attempt_id: attempt.id,
+            token: attempt.token,
+            date: attempt.date,
+            started_at: attempt.started_at,
+            puzzle: (&puzzle).into(),
+        }),
+    ))
+}
+
+/// Answers an attempt. Only the first answer counts.
+async fn answer_attempt(
+    State(state): State<AppState>,
+    Path(attempt_id): Path<Uuid>,
+    Json(answer): Json<AttemptAnswer>,
+) -> Result<Json<AttemptResult>, Error> {
+    check_bounds(answer.player_move)?;
+    let (attempt, puzzle) = daily::answer(
+        &state,
+        attempt_id,
+        &answer.token,
+        answer.player_move,
+        Utc::now(),
+    )
+    .await?;
+    let rank = state
+        .puzzle_attempts
+        .leaderboard(attempt.date)
+        .iter()
+        .find(|entry| entry.name == attempt.name)
+        .map(|entry| entry.rank);
+    Ok(Json(AttemptResult {
+        correct: attempt.correct,
+        solve_ms: attempt.solve_time().map(|time| time.num_milliseconds()),
+        rank,
+        solution: puzzle.solution.into(),
+    }))
+}
+
+async fn leaderboard(
+    State(state): State<AppState>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Json<LeaderboardView> {
+    let date = query.date.unwrap_or_else(|| Utc::now().date_naive());
+    let entries = state
+        .puzzle_attempts
+        .leaderboard(date)
+        .into_iter()
+        .map(|entry| LeaderboardEntryView {
+            rank: entry.rank,
+            name: entry.name,
+            solve_ms: entry.solve_time.num_milliseconds(),
+            answered_at: entry.answered_at,
+        })
+        .collect();
+    Json(LeaderboardView { date, entries })
+}
+
+/// Starts a puzzle rush for `name` and serves its first puzzle.
+async fn start_rush(
+    State(state): State<AppState>,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<RushView>), Error> {
+    let (run, puzzle) = state
+        .puzzle_rushes
+        .start(&state.puzzles, check_name(&request.name)?, Utc::now())
+        .map_err(Error::Puzzle)?;
+    Ok((StatusCode::CREATED, Json(RushView::new(run, Some(&puzzle)))))
+}
+
+/// Answers the rush's current puzzle with the rush's latest token, and
+/// serves the next one unless the rush is over.
+async fn answer_rush(
+    State(state): State<AppState>,
+    Path(rush_id): Path<Uuid>,
+    Json(answer): Json<AttemptAnswer>,
+) -> Result<Json<RushResult>, Error> {
+    check_bounds(answer.player_move)?;
+    let answered = state.puzzle_rushes.answer(
+        &state.puzzles,
+        rush_id,
+        &answer.token,
+        answer.player_move,
+        Utc::now(),
+    )?;
+    Ok(Json(RushResult {
+        correct: answered.correct,
+        solution: answered.puzzle.solution.into(),
+        rush: RushView::new(answered.run, answered.next.as_ref()),
+    }))
+}
+
+async fn rush_leaderboard(State(state): State<AppState>) -> Json<RushLeaderboardView> {
+    let entries = state
+        .puzzle_rushes
+        .leaderboard(Utc::now())
+        .into_iter()
+        .map(|entry| RushEntryView {
+            rank: entry.rank,
+            name: entry.name,
+            score: entry.score,
+            finished_at: entry.finished_at,
+        })
+        .collect();
+    Json(RushLeaderboardView { entries })
+}
+
+fn check_bounds(player_move: PlayerMove) -> Result<(), Error> {
+    if player_move.row > 2 || player_move.col > 2 {
+        return Err(Error::OutOfBounds {
+            row: player_move.row,
+            col: player_move.col,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Picking a game back up after a page refresh or a dropped connection.
+
+use axum::{
+    Router,
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{GameView, Player, seat_token};
+use crate::{
+    Error,
+    codec::{Accept, Encoded},
+    game::{self, MoveRecord, PlayerMove},
+    notation,
+    presence::PresenceView,
+    state::{AppState, GameMode},
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/games/{game_id}/resume", get(resume_game))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ResumeQuery {
+    /// The last version the client saw; moves after it are returned as
+    /// `missed`.
+    pub since: Option<u64>,
+}
+
+pub use laika_types::MoveView;
+
+/// A move in a game whose board is `rows` by `cols`.
+pub(super) fn move_view(record: &MoveRecord, (rows, cols): (usize, usize)) -> MoveView {
+    let PlayerMove { row, col } = record.player_move;
+    MoveView {
+        ply: record.ply,
+        player: record.player.into(),
+        row,
+        col,
+        cell: Some(row * cols + col),
+        square: notation::square_on(rows, record.player_move),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResumeResponse {
+    pub game_id: Uuid,
+    /// The side the caller plays.
+    pub you: Player,
+    pub opponent: Option<String>,
+    /// Whether the opponent is connected, in player-vs-player games.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opponent_presence: Option<PresenceView>,
+    pub game_state: GameView,
+    pub moves: Vec<MoveView>,
+    /// Moves played after `since`, in order.
+    pub missed: Vec<MoveView>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Whether the caller may swap sides under the pie rule right now.
+    pub can_swap: bool,
+}
+
+// --- Handlers ---
+
+/// Restores everything a client needs to carry on with a game. In
+/// player-vs-player games the `Seat-Token` header proves which seat is the
+/// caller's; engine games have only one human seat.
+async fn resume_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Query(query): Query<ResumeQuery>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<Encoded<ResumeResponse>, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    let (you, opponent) = match &entry.mode {
+        GameMode::VsEngine | GameMode::VsBot { .. } => (game::Player::X, None),
+        mode => {
+            let you = seat_token(&headers)
+                .and_then(|token| mode.seat_of(token))
+                .ok_or(Error::Forbidden(
+                    "A valid seat token is required to resume this game",
+                ))?;
+            let opponent = match (mode, you) {
+                (GameMode::Pvp { o, .. }, game::Player::X) => Some(o.name.clone()),
+                (GameMode::Pvp { x, .. }, game::Player::O) => Some(x.name.clone()),
+                _ => None,
+            };
+            (you, opponent)
+        }
+    };
+    let since = query.since.unwrap_or(entry.state.version);
+    let size = entry.state.board.size();
+    let moves = entry.moves();
+    Ok(Encoded(
+        format,
+        ResumeResponse {
+            game_id,
+            you: you.into(),
+            opponent_presence: opponent
+                .is_some()
+                .then(|| state.presence.status(game_id, you.opponent(), Utc::now())),
+            opponent,
+            game_state: entry.state.into(),
+            moves: moves.iter().map(|record| move_view(record, size)).collect(),
+            missed: moves
+                .iter()
+                .filter(|record| record.ply > since)
+                .map(|record| move_view(record, size))
+                .collect(),
+            finished_at: entry.finished_at,
+            can_swap: you == game::Player::O && entry.can_swap(),
+        },
+    ))
+}
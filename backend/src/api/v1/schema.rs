@@ -0,0 +1,130 @@
+//! JSON Schemas of the API's messages, for integrators who don't use the
+//! Rust types: to validate payloads, or to generate a client from.
+
+use std::sync::LazyLock;
+
+use axum::{Json, Router, routing::get};
+use laika_types::{
+    ErrorBody, EventsResponse, GameView, MoveBatchRequest, MoveRequest, NewGameRequest,
+    NewGameResponse,
+    schemars::{JsonSchema, SchemaGenerator, generate::SchemaSettings},
+};
+use serde_json::{Map, Value, json};
+
+use crate::state::AppState;
+
+/// Built once; the types can't change while the server runs.
+static SCHEMA: LazyLock<Value> = LazyLock::new(build);
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/schema", get(get_schema))
+}
+
+// --- Handlers ---
+
+async fn get_schema() -> Json<Value> {
+    Json(SCHEMA.clone())
+}
+
+/// One draft 2020-12 document: every type under `$defs`, and which of them
+/// each endpoint takes and returns, and each stream sends, as references.
+fn build() -> Value {
+    let mut generator = SchemaGenerator::new(SchemaSettings::draft2020_12());
+    let requests = references(
+        &mut generator,
+        &[
+            ("POST /api/v1/newgame", reference::<NewGameRequest>),
+            (
+                "POST /api/v1/games/{game_id}/move",
+                reference::<MoveRequest>,
+            ),
+            (
+                "POST /api/v1/games/{game_id}/moves",
+                reference::<MoveBatchRequest>,
+            ),
+        ],
+    );
+    let responses = references(
+        &mut generator,
+        &[
+            ("POST /api/v1/newgame", reference::<NewGameResponse>),
+            ("POST /api/v1/games/import", reference::<NewGameResponse>),
+            ("GET /api/v1/games/{game_id}", reference::<GameView>),
+            ("POST /api/v1/games/{game_id}/move", reference::<GameView>),
+            ("POST /api/v1/games/{game_id}/moves", reference::<GameView>),
+            ("POST /api/v1/games/{game_id}/swap", reference::<GameView>),
+            ("GET /api/v1/games/{game_id}/wait", reference::<GameView>),
+            (
+                "GET /api/v1/games/{game_id}/events",
+                reference::<EventsResponse>,
+            ),
+        ],
+    );
+    let game_event = reference::<GameView>(&mut generator);
+    let error = reference::<ErrorBody>(&mut generator);
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "requests": requests,
+        "responses": responses,
+        // Server-sent events, by stream and then event name.
+        "events": {
+            "GET /api/v1/games/{game_id}/stream": { "game": game_event },
+        },
+        // The body of every error response.
+        "error": error,
+        "$defs": generator.take_definitions(true),
+    })
+}
+
+type Reference = fn(&mut SchemaGenerator) -> Value;
+
+fn references(
+    generator: &mut SchemaGenerator,
+    endpoints: &[(&str, Reference)],
+) -> Map<String, Value> {
+    endpoints
+        .iter()
+        .map(|(endpoint, reference)| (endpoint.to_string(), reference(generator)))
+        .collect()
+}
+
+/// A `$ref` to `T`, whose schema is added to the definitions.
+fn reference<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    generator.subschema_for::<T>().to_value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every `$ref` in `value`.
+    fn refs(value: &Value, found: &mut Vec<String>) {
+        match value {
+            Value::Object(object) => {
+                if let Some(Value::String(target)) = object.get("$ref") {
+                    found.push(target.clone());
+                }
+                object.values().for_each(|value| refs(value, found));
+            }
+            Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_every_reference_resolves() {
+        let schema = build();
+        let mut found = Vec::new();
+        refs(&schema, &mut found);
+        for target in &found {
+            let name = target.strip_prefix("#/$defs/").unwrap();
+            assert!(schema["$defs"].get(name).is_some(), "{target} is undefined");
+        }
+        assert_eq!(
+            schema["responses"]["GET /api/v1/games/{game_id}"]["$ref"],
+            "#/$defs/GameView"
+        );
+        let cell = &schema["$defs"]["Cell"];
+        assert!(cell.to_string().contains("Occupied"));
+    }
+}
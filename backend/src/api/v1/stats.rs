@@ -0,0 +1,167 @@
+//! Daily game statistics, as rolled up each night by `rollup`, and how
+//! players fare at each difficulty over those days.
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    routing::get,
+};
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error,
+    calibration::{Outcomes, Tuning},
+    rollup::DailyStats,
+    state::AppState,
+};
+
+/// Days served when the range is left out.
+const DEFAULT_DAYS: u64 = 30;
+/// The longest range one request can ask for.
+const MAX_DAYS: i64 = 366;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/stats/daily", get(daily_stats))
+        .route("/stats/calibration", get(calibration))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+pub struct DailyStatsQuery {
+    /// Inclusive; `DEFAULT_DAYS` before `to` when left out.
+    pub from: Option<NaiveDate>,
+    /// Inclusive; yesterday when left out, the last day rolled up.
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyStatsView {
+    pub day: NaiveDate,
+    pub games: u64,
+    pub x_wins: u64,
+    pub o_wins: u64,
+    pub draws: u64,
+    pub average_moves: f64,
+    pub distinct_players: u64,
+    pub rolled_up_at: DateTime<Utc>,
+}
+
+impl From<DailyStats> for DailyStatsView {
+    fn from(stats: DailyStats) -> Self {
+        Self {
+            day: stats.day,
+            games: stats.games,
+            x_wins: stats.x_wins,
+            o_wins: stats.o_wins,
+            draws: stats.draws,
+            average_moves: stats.average_moves,
+            distinct_players: stats.distinct_players,
+            rolled_up_at: stats.rolled_up_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyStatsResponse {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Only the days rolled up so far, oldest first.
+    pub days: Vec<DailyStatsView>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DifficultyView {
+    /// The blunder chance, to the nearest tenth.
+    pub difficulty: f64,
+    pub games: u64,
+    pub player_wins: u64,
+    pub engine_wins: u64,
+    pub draws: u64,
+    pub player_win_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalibrationResponse {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Easiest last.
+    pub difficulties: Vec<DifficultyView>,
+    /// `None` unless `calibration.auto_tune` is on.
+    pub tuning: Option<Tuning>,
+}
+
+// --- Handlers ---
+
+/// The days a stats query asks for, checked.
+fn range(query: DailyStatsQuery) -> Result<(NaiveDate, NaiveDate), Error> {
+    let to = query
+        .to
+        .unwrap_or_else(|| Utc::now().date_naive() - Days::new(1));
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Days::new(DEFAULT_DAYS - 1));
+    if from > to {
+        return Err(Error::BadRequest("from must not be after to"));
+    }
+    if (to - from).num_days() >= MAX_DAYS {
+        return Err(Error::BadRequest(
+            "At most 366 days can be requested at once",
+        ));
+    }
+    Ok((from, to))
+}
+
+async fn daily_stats(
+    State(state): State<AppState>,
+    Query(query): Query<DailyStatsQuery>,
+) -> Result<Json<DailyStatsResponse>, Error> {
+    let (from, to) = range(query)?;
+    let days = state
+        .rollups
+        .range(from, to)
+        .into_iter()
+        .map(DailyStatsView::from)
+        .collect();
+    Ok(Json(DailyStatsResponse { from, to, days }))
+}
+
+/// Win rates by difficulty over the days rolled up in the range.
+async fn calibration(
+    State(state): State<AppState>,
+    Query(query): Query<DailyStatsQuery>,
+) -> Result<Json<CalibrationResponse>, Error> {
+    let (from, to) = range(query)?;
+    let mut totals: BTreeMap<String, Outcomes> = BTreeMap::new();
+    for day in state.rollups.range(from, to) {
+        for (difficulty, outcomes) in &day.by_difficulty {
+            totals
+                .entry(difficulty.clone())
+                .or_default()
+                .merge(outcomes);
+        }
+    }
+    let difficulties = totals
+        .into_iter()
+        .filter_map(|(difficulty, outcomes)| {
+            Some(DifficultyView {
+                difficulty: difficulty.parse().ok()?,
+                games: outcomes.games,
+                player_wins: outcomes.player_wins,
+                engine_wins: outcomes.engine_wins,
+                draws: outcomes.draws,
+                player_win_rate: outcomes.player_win_rate(),
+            })
+        })
+        .collect();
+    Ok(Json(CalibrationResponse {
+        from,
+        to,
+        difficulties,
+        tuning: state.tuner.tuning(),
+    }))
+}
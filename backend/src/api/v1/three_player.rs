@@ -0,0 +1,201 @@
+//! Three-player endpoints: open a game, fill the other two seats, and play.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{NameRequest, check_name, seat_token};
+use crate::{
+    Error,
+    flags::Flag,
+    game::{PlayerMove, Rules},
+    state::AppState,
+    tenant::Tenant,
+    three_player::{self, Mark, ThreePlayerGame, ThreePlayerStatus},
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/three-player", post(new_game))
+        .route("/three-player/{game_id}", get(get_game))
+        .route("/three-player/{game_id}/join", post(join_game))
+        .route("/three-player/{game_id}/move", post(play_move))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NewThreePlayerRequest {
+    pub name: String,
+    pub rows: usize,
+    pub cols: usize,
+    /// How many in a row win.
+    pub win_length: usize,
+}
+
+impl Default for NewThreePlayerRequest {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            rows: 5,
+            cols: 5,
+            win_length: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreePlayerStatusView {
+    /// Waiting for seats to fill.
+    Open,
+    InProgress,
+    Draw,
+    Win(char),
+}
+
+impl From<ThreePlayerStatus> for ThreePlayerStatusView {
+    fn from(status: ThreePlayerStatus) -> Self {
+        match status {
+            ThreePlayerStatus::Open => ThreePlayerStatusView::Open,
+            ThreePlayerStatus::InProgress => ThreePlayerStatusView::InProgress,
+            ThreePlayerStatus::Draw => ThreePlayerStatusView::Draw,
+            ThreePlayerStatus::Win(mark) => ThreePlayerStatusView::Win(mark.symbol()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThreePlayerSeatView {
+    pub mark: char,
+    pub name: String,
+}
+
+/// A three-player game without its seat tokens.
+#[derive(Debug, Serialize)]
+pub struct ThreePlayerView {
+    pub game_id: Uuid,
+    /// Each cell is `"X"`, `"O"`, `"Y"`, or `null` when empty.
+    pub board: Vec<Vec<Option<char>>>,
+    pub win_length: usize,
+    /// Taken seats, in turn order.
+    pub players: Vec<ThreePlayerSeatView>,
+    pub to_play: char,
+    pub moves: Vec<PlayerMove>,
+    pub status: ThreePlayerStatusView,
+}
+
+impl From<&ThreePlayerGame> for ThreePlayerView {
+    fn from(game: &ThreePlayerGame) -> Self {
+        Self {
+            game_id: game.id,
+            board: game
+                .board
+                .cells()
+                .into_iter()
+                .map(|row| row.into_iter().map(|cell| cell.map(Mark::symbol)).collect())
+                .collect(),
+            win_length: game.rules.win_length,
+            players: game
+                .seats
+                .iter()
+                .zip(Mark::all())
+                .map(|(seat, mark)| ThreePlayerSeatView {
+                    mark: mark.symbol(),
+                    name: seat.name.clone(),
+                })
+                .collect(),
+            to_play: game.to_play().symbol(),
+            moves: game.moves.clone(),
+            status: game.status.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeatedThreePlayer {
+    pub game: ThreePlayerView,
+    /// The mark this seat plays.
+    pub mark: char,
+    /// Send as the `Seat-Token` header when moving.
+    pub token: String,
+}
+
+// --- Handlers ---
+
+/// Opens a game with the caller as X. O and Y join with the game ID.
+async fn new_game(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Json(request): Json<NewThreePlayerRequest>,
+) -> Result<(StatusCode, Json<SeatedThreePlayer>), Error> {
+    state.flags.require(Flag::ThreePlayer, &tenant)?;
+    let rules = Rules {
+        win_length: request.win_length,
+        ..Rules::default()
+    };
+    let (game, token) = three_player::create(
+        &state,
+        check_name(&request.name)?,
+        request.rows,
+        request.cols,
+        rules,
+    )
+    .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(SeatedThreePlayer {
+            game: ThreePlayerView::from(&game),
+            mark: game.to_play().symbol(),
+            token,
+        }),
+    ))
+}
+
+async fn get_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+) -> Result<Json<ThreePlayerView>, Error> {
+    let game = state
+        .three_player
+        .get(&game_id)
+        .map(|game| game.clone())
+        .ok_or(Error::GameNotFound(game_id))?;
+    let game = game.lock().await;
+    Ok(Json(ThreePlayerView::from(&*game)))
+}
+
+/// Takes the next free seat: O, then Y. The game starts when Y is taken.
+async fn join_game(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<SeatedThreePlayer>), Error> {
+    let (game, mark, token) =
+        three_player::join(&state, game_id, check_name(&request.name)?).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(SeatedThreePlayer {
+            game: ThreePlayerView::from(&game),
+            mark: mark.symbol(),
+            token,
+        }),
+    ))
+}
+
+/// Places the mark of the seat named by the `Seat-Token` header.
+async fn play_move(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(player_move): Json<PlayerMove>,
+) -> Result<Json<ThreePlayerView>, Error> {
+    let game = three_player::play(&state, game_id, player_move, seat_token(&headers)).await?;
+    Ok(Json(ThreePlayerView::from(&game)))
+}
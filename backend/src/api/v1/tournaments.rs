@@ -0,0 +1,222 @@
+//! Tournament endpoints.
+
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{NameRequest, check_name};
+use crate::{
+    Error,
+    api::{Moderator, RequireRole, bearer_token, constant_time_eq},
+    state::AppState,
+    tournament::{
+        self, MAX_SWISS_ROUNDS, Match, Tournament, TournamentFormat, TournamentStatus,
+        swiss::Standing,
+    },
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/tournaments", post(create_tournament))
+        .route("/tournaments/{tournament_id}", get(get_tournament))
+        .route(
+            "/tournaments/{tournament_id}/players",
+            post(register_player),
+        )
+        .route("/tournaments/{tournament_id}/start", post(start_tournament))
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Serialize)]
+pub struct EntrantView {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchView {
+    pub x: Uuid,
+    /// `null` for a bye.
+    pub o: Option<Uuid>,
+    pub games: Vec<Uuid>,
+    pub winner: Option<Uuid>,
+    pub draw: bool,
+}
+
+impl From<&Match> for MatchView {
+    fn from(m: &Match) -> Self {
+        Self {
+            x: m.x,
+            o: m.o,
+            games: m.games.clone(),
+            winner: m.winner,
+            draw: m.draw,
+        }
+    }
+}
+
+/// A tournament without any of its secrets.
+#[derive(Debug, Serialize)]
+pub struct TournamentView {
+    pub id: Uuid,
+    pub name: String,
+    pub status: TournamentStatus,
+    pub format: TournamentFormat,
+    pub entrants: Vec<EntrantView>,
+    pub rounds: Vec<Vec<MatchView>>,
+    /// Swiss tournaments only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub standings: Option<Vec<Standing>>,
+    pub winner: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Tournament> for TournamentView {
+    fn from(tournament: &Tournament) -> Self {
+        Self {
+            id: tournament.id,
+            name: tournament.name.clone(),
+            status: tournament.status,
+            format: tournament.format,
+            entrants: tournament
+                .entrants
+                .iter()
+                .map(|entrant| EntrantView {
+                    id: entrant.id,
+                    name: entrant.name.clone(),
+                })
+                .collect(),
+            rounds: tournament
+                .rounds
+                .iter()
+                .map(|round| round.iter().map(MatchView::from).collect())
+                .collect(),
+            standings: tournament.standings(),
+            winner: tournament.winner,
+            created_at: tournament.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTournamentRequest {
+    pub name: String,
+    /// Single elimination unless given.
+    #[serde(default)]
+    pub format: TournamentFormat,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedTournament {
+    pub tournament: TournamentView,
+    /// Send as `Authorization: Bearer <token>` to start the tournament.
+    pub organizer_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Registration {
+    pub player_id: Uuid,
+    /// Send as the `Seat-Token` header when moving in tournament games.
+    pub token: String,
+}
+
+fn check_format(format: TournamentFormat) -> Result<TournamentFormat, Error> {
+    if let TournamentFormat::Swiss { rounds } = format
+        && !(1..=MAX_SWISS_ROUNDS).contains(&rounds)
+    {
+        return Err(Error::BadRequest("Swiss rounds must be between 1 and 12"));
+    }
+    Ok(format)
+}
+
+// --- Handlers ---
+
+/// Opens a tournament for registration.
+async fn create_tournament(
+    State(state): State<AppState>,
+    Json(request): Json<CreateTournamentRequest>,
+) -> Result<(StatusCode, Json<CreatedTournament>), Error> {
+    let tournament = Tournament::new(check_name(&request.name)?, check_format(request.format)?);
+    tournament::save(&state, &tournament).await?;
+    let created = CreatedTournament {
+        tournament: TournamentView::from(&tournament),
+        organizer_token: tournament.organizer_token.clone(),
+    };
+    log::info!("Created tournament {}", tournament.id);
+    state
+        .tournaments
+        .insert(tournament.id, Arc::new(Mutex::new(tournament)));
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+async fn get_tournament(
+    State(state): State<AppState>,
+    Path(tournament_id): Path<Uuid>,
+) -> Result<Json<TournamentView>, Error> {
+    let tournament = state
+        .tournament(&tournament_id)
+        .ok_or(Error::TournamentNotFound(tournament_id))?;
+    let tournament = tournament.lock().await;
+    Ok(Json(TournamentView::from(&*tournament)))
+}
+
+/// Registers a player while registration is open.
+async fn register_player(
+    State(state): State<AppState>,
+    Path(tournament_id): Path<Uuid>,
+    Json(request): Json<NameRequest>,
+) -> Result<(StatusCode, Json<Registration>), Error> {
+    let name = check_name(&request.name)?;
+    let tournament = state
+        .tournament(&tournament_id)
+        .ok_or(Error::TournamentNotFound(tournament_id))?;
+    let mut tournament = tournament.lock().await;
+    let entrant = tournament.register(name).map_err(Error::Tournament)?;
+    let registration = Registration {
+        player_id: entrant.id,
+        token: entrant.token.clone(),
+    };
+    tournament::save(&state, &tournament).await?;
+    Ok((StatusCode::CREATED, Json(registration)))
+}
+
+/// Closes registration, pairs the first round, and creates the first round's
+/// games. Only the organizer or a moderator may start a tournament.
+async fn start_tournament(
+    State(state): State<AppState>,
+    Path(tournament_id): Path<Uuid>,
+    headers: HeaderMap,
+    moderator: Option<RequireRole<Moderator>>,
+) -> Result<Json<TournamentView>, Error> {
+    let tournament = state
+        .tournament(&tournament_id)
+        .ok_or(Error::TournamentNotFound(tournament_id))?;
+    let mut tournament = tournament.lock().await;
+    let is_organizer = bearer_token(&headers).is_some_and(|token| {
+        constant_time_eq(token.as_bytes(), tournament.organizer_token.as_bytes())
+    });
+    if !is_organizer && moderator.is_none() {
+        return Err(Error::Forbidden(
+            "Only the organizer or a moderator can start this tournament",
+        ));
+    }
+    if let Some(moderator) = moderator {
+        log::info!(
+            "Moderator {} started tournament {}",
+            moderator.account_id,
+            tournament_id
+        );
+    }
+    tournament::start(&state, &mut tournament).await?;
+    Ok(Json(TournamentView::from(&*tournament)))
+}
@@ -0,0 +1,134 @@
+//! Webhook endpoints: register URLs that are POSTed to when a game changes,
+//! and inspect how deliveries to them went.
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::seat_token;
+use crate::{
+    Error,
+    state::AppState,
+    webhook::{self, Delivery, Webhook},
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/games/{game_id}/webhooks",
+            get(list_webhooks).post(create_webhook),
+        )
+        .route(
+            "/games/{game_id}/webhooks/{webhook_id}",
+            delete(delete_webhook),
+        )
+        .route(
+            "/games/{game_id}/webhooks/{webhook_id}/deliveries",
+            get(list_deliveries),
+        )
+}
+
+// --- Wire Types ---
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookRequest {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    pub webhook_id: Uuid,
+    pub url: String,
+    /// Key for checking the `Laika-Signature` header. Only shown once.
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookView {
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Webhook> for WebhookView {
+    fn from(hook: &Webhook) -> Self {
+        Self {
+            webhook_id: hook.id,
+            url: hook.url.clone(),
+            created_at: hook.created_at,
+        }
+    }
+}
+
+// --- Handlers ---
+
+/// Registers a webhook on a game. Player-vs-player games require a
+/// `Seat-Token` for either seat.
+async fn create_webhook(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(request): Json<WebhookRequest>,
+) -> Result<(StatusCode, Json<CreatedWebhook>), Error> {
+    webhook::authorize(&state, game_id, seat_token(&headers)).await?;
+    let hook = state
+        .webhooks
+        .register(game_id, &request.url)
+        .await
+        .map_err(Error::Webhook)?;
+    log::info!("Registered webhook {} on game {}", hook.id, game_id);
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhook {
+            webhook_id: hook.id,
+            url: hook.url.clone(),
+            secret: hook.secret.clone(),
+        }),
+    ))
+}
+
+async fn list_webhooks(
+    State(state): State<AppState>,
+    Path(game_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<WebhookView>>, Error> {
+    webhook::authorize(&state, game_id, seat_token(&headers)).await?;
+    let hooks = state.webhooks.list(game_id);
+    Ok(Json(
+        hooks.iter().map(|hook| hook.as_ref().into()).collect(),
+    ))
+}
+
+async fn delete_webhook(
+    State(state): State<AppState>,
+    Path((game_id, webhook_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, Error> {
+    webhook::authorize(&state, game_id, seat_token(&headers)).await?;
+    state
+        .webhooks
+        .remove(game_id, webhook_id)
+        .map_err(Error::Webhook)?;
+    log::info!("Removed webhook {} from game {}", webhook_id, game_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Recent delivery attempts to a webhook, oldest first.
+async fn list_deliveries(
+    State(state): State<AppState>,
+    Path((game_id, webhook_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Delivery>>, Error> {
+    webhook::authorize(&state, game_id, seat_token(&headers)).await?;
+    let hook = state
+        .webhooks
+        .get(game_id, webhook_id)
+        .map_err(Error::Webhook)?;
+    Ok(Json(hook.deliveries()))
+}
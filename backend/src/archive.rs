@@ -0,0 +1,198 @@
+//! Archiving finished games instead of dropping them.
+//!
+//! A game that has been over for `games.finished_ttl_secs` is archived: it
+//! leaves the live registry, so players can no longer reach it, but admins
+//! can still find it and restore it. Stores that keep games on their own,
+//! like PostgreSQL, hold archived games; otherwise they wait in
+//! `AppState::archive` and are saved with the registry. After
+//! `games.archive_retention_days` archived games are deleted for good.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{Error, state::AppState};
+
+/// Archives the finished games that have been over for `ttl`, or restored
+/// for that long, and returns their IDs. Games locked by a move in progress
+/// wait for the next round.
+pub async fn archive_expired(state: &AppState, now: DateTime<Utc>, ttl: TimeDelta) -> Vec<Uuid> {
+    let _registering = state.registering.read().await;
+    let mut expired = Vec::new();
+    state.games.retain(|id, game| {
+        let Ok(mut entry) = game.try_lock() else {
+            return true;
+        };
+        if !entry.is_expired(now, ttl) {
+            return true;
+        }
+        entry.archived_at = Some(now);
+        expired.push((*id, entry.clone()));
+        false
+    });
+
+    let mut archived = Vec::with_capacity(expired.len());
+    for (game_id, entry) in expired {
+        // A store that keeps the archive archives any game it still has as
+        // live when it next loads, so one missed here isn't lost; one that
+        // doesn't saves the game with the registry.
+        if let Err(e) = state.store.archive_game(game_id, &entry).await {
+            log::error!("Failed to archive game {}: {}", game_id, e);
+        }
+        if !state.store.keeps_archive() {
+            state.archive.insert(game_id, entry);
+        }
+        archived.push(game_id);
+    }
+    archived
+}
+
+/// Deletes the games archived more than `retention` ago. Returns how many.
+pub async fn purge(state: &AppState, now: DateTime<Utc>, retention: TimeDelta) -> usize {
+    let cutoff = now - retention;
+    let mut purged = Vec::new();
+    state.archive.retain(|id, entry| {
+        let keep = entry.archived_at.is_none_or(|at| at > cutoff);
+        if !keep {
+            purged.push(*id);
+        }
+        keep
+    });
+    for game_id in &purged {
+        if let Err(e) = state.store.delete_game(*game_id).await {
+            log::error!("Failed to delete archived game {}: {}", game_id, e);
+        }
+    }
+    let stored = state
+        .store
+        .purge_archived(cutoff)
+        .await
+        .unwrap_or_else(|e| {
+            log::error!("Failed to purge archived games: {}", e);
+            0
+        });
+    purged.len() + stored
+}
+
+/// Brings an archived game back into the registry, where it stays for
+/// another `games.finished_ttl_secs`.
+pub async fn restore(state: &AppState, game_id: Uuid) -> Result<(), Error> {
+    let _registering = state.registering.read().await;
+    let entry = match state.archive.remove(&game_id) {
+        Some((_, entry)) => entry,
+        None => state
+            .store
+            .load_game(game_id)
+            .await
+            .map_err(Error::Storage)?
+            .filter(|entry| entry.archived_at.is_some())
+            .ok_or(Error::GameNotFound(game_id))?,
+    };
+    let mut restored = entry.clone();
+    restored.archived_at = None;
+    restored.restored_at = Some(Utc::now());
+    if let Err(e) = state.store.restore_game(game_id, &restored).await {
+        if !state.store.keeps_archive() {
+            state.archive.insert(game_id, entry);
+        }
+        return Err(Error::Storage(e));
+    }
+    state.games.insert(game_id, Arc::new(Mutex::new(restored)));
+    log::info!("Restored archived game {}", game_id);
+    Ok(())
+}
+
+/// Deletes an archived game. `false` if there is no such game.
+pub async fn delete(state: &AppState, game_id: Uuid) -> Result<bool, Error> {
+    let archived = state.archive.remove(&game_id).is_some()
+        || (state.store.keeps_archive()
+            && state
+                .store
+                .load_game(game_id)
+                .await
+                .map_err(Error::Storage)?
+                .is_some_and(|entry| entry.archived_at.is_some()));
+    if archived {
+        state
+            .store
+            .delete_game(game_id)
+            .await
+            .map_err(Error::Storage)?;
+    }
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::GameState,
+        state::{GameEntry, GameRegistry},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_finished_games_are_archived_then_purged() {
+        let now = Utc::now();
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let shared = |entry: GameEntry| Arc::new(Mutex::new(entry));
+        let finished = |ago: TimeDelta| {
+            let mut entry = GameEntry::new(GameState::default());
+            entry.finished_at = Some(now - ago);
+            entry
+        };
+
+        let active = Uuid::new_v4();
+        state
+            .games
+            .insert(active, shared(GameEntry::new(GameState::default())));
+        let recently_finished = Uuid::new_v4();
+        state
+            .games
+            .insert(recently_finished, shared(finished(TimeDelta::minutes(5))));
+        let expired = Uuid::new_v4();
+        state
+            .games
+            .insert(expired, shared(finished(TimeDelta::hours(1))));
+        let expired_but_locked = Uuid::new_v4();
+        let locked_game = shared(finished(TimeDelta::hours(1)));
+        state.games.insert(expired_but_locked, locked_game.clone());
+        let guard = locked_game.try_lock().unwrap();
+
+        assert_eq!(
+            archive_expired(&state, now, TimeDelta::hours(1)).await,
+            [expired]
+        );
+        assert!(state.game(&active).is_some());
+        assert!(state.game(&recently_finished).is_some());
+        assert!(state.game(&expired).is_none());
+        assert!(state.game(&expired_but_locked).is_some());
+        assert_eq!(state.archive.get(&expired).unwrap().archived_at, Some(now));
+        drop(guard);
+
+        // A restored game gets a fresh hour before it's archived again.
+        restore(&state, expired).await.unwrap();
+        assert!(state.archive.is_empty());
+        assert!(
+            archive_expired(&state, now, TimeDelta::hours(1))
+                .await
+                .iter()
+                .all(|id| *id != expired)
+        );
+        assert!(matches!(
+            restore(&state, active).await,
+            Err(Error::GameNotFound(_))
+        ));
+
+        let later = now + TimeDelta::days(1);
+        archive_expired(&state, later, TimeDelta::hours(1)).await;
+        assert_eq!(state.archive.len(), 3);
+        assert!(delete(&state, expired).await.unwrap());
+        assert!(!delete(&state, active).await.unwrap());
+        assert_eq!(purge(&state, later, TimeDelta::days(2)).await, 0);
+        assert_eq!(purge(&state, later, TimeDelta::zero()).await, 2);
+        assert!(state.archive.is_empty());
+    }
+}
@@ -0,0 +1,537 @@
+//! Arenas: for a fixed window, everyone who joins is paired into fast timed
+//! games, and paired again as soon as their game ends. Wins and draws earn
+//! points, and the standings at the end of the window decide the arena.
+//!
+//! Whenever players are free, the free ones are paired by standing, the
+//! leader with the next best, so players meet others with similar scores.
+//! Nobody plays the same opponent twice in a row while others are still
+//! playing and could be paired with them instead. Unlike tournament games,
+//! X's clock starts with the game, so an absent player loses on time
+//! instead of holding up their opponent.
+//!
+//! Like `Tournament`, `Arena` is bookkeeping only: it hands out `Pairing`s,
+//! the caller creates the games, and finished games come back through
+//! `record_result`. Arenas are kept in memory only.
+
+use std::{cmp::Reverse, fmt, sync::Arc};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::sync::{Mutex, broadcast};
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    clock::{Clock, IncrementMode, TimeControl, TimeoutAction},
+    game::{GameStatus, Player},
+    state::{AppState, GameEntry, Seat, new_token},
+};
+
+pub const MAX_PLAYERS: usize = 64;
+pub const DEFAULT_MINUTES: i64 = 30;
+pub const MAX_MINUTES: i64 = 180;
+/// Points for a win and a draw; a loss earns nothing.
+pub const WIN_POINTS: u32 = 2;
+pub const DRAW_POINTS: u32 = 1;
+
+/// Finished arenas stay readable this long before they are dropped.
+const KEEP_FINISHED: TimeDelta = TimeDelta::days(1);
+
+/// Blitz: half a minute each and two seconds a move.
+pub const DEFAULT_TIME_CONTROL: TimeControl = TimeControl {
+    initial_secs: 30,
+    increment_secs: 2,
+    mode: IncrementMode::Fischer,
+    on_timeout: TimeoutAction::Forfeit,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaPlayer {
+    pub id: Uuid,
+    pub name: String,
+    /// Seat token for every game this player plays in the arena.
+    pub token: String,
+    pub points: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// The game they are playing, if any.
+    pub game_id: Option<Uuid>,
+    /// Cleared when they withdraw; they keep their points but aren't
+    /// paired again.
+    pub active: bool,
+    last_opponent: Option<Uuid>,
+    games_as_x: u32,
+}
+
+impl ArenaPlayer {
+    fn is_free(&self) -> bool {
+        self.active && self.game_id.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaGame {
+    pub game_id: Uuid,
+    pub x: Uuid,
+    pub o: Uuid,
+    /// `None` while the game is in progress.
+    pub result: Option<GameStatus>,
+}
+
+/// A game the arena needs created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pairing {
+    pub x: Uuid,
+    pub o: Uuid,
+}
+
+/// A player's place in the standings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Standing {
+    pub rank: usize,
+    pub player_id: Uuid,
+    pub name: String,
+    pub points: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// The game they are playing, if any.
+    pub game_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArenaError {
+    /// The arena's window has passed.
+    Over,
+    Full,
+    DuplicateName,
+    UnknownPlayer,
+    UnknownGame,
+}
+
+impl fmt::Display for ArenaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ArenaError::Over => "The arena is over",
+            ArenaError::Full => "The arena is full",
+            ArenaError::DuplicateName => "That name is already in the arena",
+            ArenaError::UnknownPlayer => "No such player in this arena",
+            ArenaError::UnknownGame => "Game is not part of this arena",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for ArenaError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arena {
+    pub id: Uuid,
+    pub name: String,
+    pub time_control: TimeControl,
+    pub starts_at: DateTime<Utc>,
+    /// No games are paired from then on; games already underway still
+    /// count.
+    pub ends_at: DateTime<Utc>,
+    /// In the order they joined.
+    pub players: Vec<ArenaPlayer>,
+    pub games: Vec<ArenaGame>,
+}
+
+impl Arena {
+    /// An arena open from `now` for `duration`.
+    pub fn new(
+        name: String,
+        time_control: TimeControl,
+        duration: TimeDelta,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            time_control,
+            starts_at: now,
+            ends_at: now + duration,
+            players: Vec::new(),
+            games: Vec::new(),
+        }
+    }
+
+    pub fn player(&self, id: Uuid) -> Option<&ArenaPlayer> {
+        self.players.iter().find(|player| player.id == id)
+    }
+
+    fn player_mut(&mut self, id: Uuid) -> Option<&mut ArenaPlayer> {
+        self.players.iter_mut().find(|player| player.id == id)
+    }
+
+    /// Over once the window has passed and its last game has finished.
+    pub fn is_finished(&self, now: DateTime<Utc>) -> bool {
+        now >= self.ends_at && self.games.iter().all(|game| game.result.is_some())
+    }
+
+    /// Adds `name` to the arena. They are paired by the next `pairings`.
+    pub fn join(&mut self, name: String, now: DateTime<Utc>) -> Result<&ArenaPlayer, ArenaError> {
+        if now >= self.ends_at {
+            return Err(ArenaError::Over);
+        }
+        if self.players.len() >= MAX_PLAYERS {
+            return Err(ArenaError::Full);
+        }
+        if self.players.iter().any(|player| player.name == name) {
+            return Err(ArenaError::DuplicateName);
+        }
+        self.players.push(ArenaPlayer {
+            id: Uuid::new_v4(),
+            name,
+            token: new_token(),
+            points: 0,
+            wins: 0,
+            draws: 0,
+            losses: 0,
+            game_id: None,
+            active: true,
+            last_opponent: None,
+            games_as_x: 0,
+        });
+        Ok(self.players.last().expect("just pushed"))
+    }
+
+    /// Stops pairing a player. A game they are playing still counts.
+    pub fn withdraw(&mut self, player_id: Uuid) -> Result<(), ArenaError> {
+        let player = self
+            .player_mut(player_id)
+            .ok_or(ArenaError::UnknownPlayer)?;
+        player.active = false;
+        Ok(())
+    }
+
+    /// Pairs the free players, best placed first, while the window lasts.
+    pub fn pairings(&self, now: DateTime<Utc>) -> Vec<Pairing> {
+        if now >= self.ends_at {
+            return Vec::new();
+        }
+        let mut free: Vec<&ArenaPlayer> = self
+            .players
+            .iter()
+            .filter(|player| player.is_free())
+            .collect();
+        free.sort_by_key(|player| Reverse(player.points));
+        // A rematch can wait while others are still playing.
+        let others_playing = self
+            .players
+            .iter()
+            .any(|player| player.active && player.game_id.is_some());
+        let mut pairings = Vec::new();
+        while !free.is_empty() {
+            let first = free.remove(0);
+            let index = free
+                .iter()
+                .position(|player| first.last_opponent != Some(player.id))
+                .or_else(|| (!others_playing && !free.is_empty()).then_some(0));
+            let Some(index) = index else {
+                continue;
+            };
+            let second = free.remove(index);
+            // Whoever has had X less often gets it.
+            let (x, o) = if second.games_as_x < first.games_as_x {
+                (second, first)
+            } else {
+                (first, second)
+            };
+            pairings.push(Pairing { x: x.id, o: o.id });
+        }
+        pairings
+    }
+
+    pub fn attach_game(&mut self, pairing: &Pairing, game_id: Uuid) {
+        self.games.push(ArenaGame {
+            game_id,
+            x: pairing.x,
+            o: pairing.o,
+            result: None,
+        });
+        for (id, opponent) in [(pairing.x, pairing.o), (pairing.o, pairing.x)] {
+            let player = self
+                .player_mut(id)
+                .expect("paired players are in the arena");
+            player.game_id = Some(game_id);
+            player.last_opponent = Some(opponent);
+        }
+        self.player_mut(pairing.x)
+            .expect("paired players are in the arena")
+            .games_as_x += 1;
+    }
+
+    /// Scores a finished game and returns the pairings that frees up.
+    pub fn record_result(
+        &mut self,
+        game_id: Uuid,
+        status: GameStatus,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Pairing>, ArenaError> {
+        let game = self
+            .games
+            .iter_mut()
+            .find(|game| game.game_id == game_id)
+            .ok_or(ArenaError::UnknownGame)?;
+        if game.result.is_some() || status == GameStatus::InProgress {
+            return Ok(Vec::new());
+        }
+        game.result = Some(status);
+        let (x, o) = (game.x, game.o);
+        for (id, side) in [(x, Player::X), (o, Player::O)] {
+            let player = self
+                .player_mut(id)
+                .expect("paired players are in the arena");
+            player.game_id = None;
+            match status {
+                GameStatus::Win(winner) if winner == side => {
+                    player.wins += 1;
+                    player.points += WIN_POINTS;
+                }
+                GameStatus::Win(_) => player.losses += 1,
+                GameStatus::Draw => {
+                    player.draws += 1;
+                    player.points += DRAW_POINTS;
+                }
+                GameStatus::InProgress => unreachable!("checked above"),
+            }
+        }
+        Ok(self.pairings(now))
+    }
+
+    /// Most points first, then most wins, then whoever joined first.
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut players: Vec<&ArenaPlayer> = self.players.iter().collect();
+        players.sort_by_key(|player| (Reverse(player.points), Reverse(player.wins)));
+        players
+            .into_iter()
+            .enumerate()
+            .map(|(index, player)| Standing {
+                rank: index + 1,
+                player_id: player.id,
+                name: player.name.clone(),
+                points: player.points,
+                wins: player.wins,
+                draws: player.draws,
+                losses: player.losses,
+                game_id: player.game_id,
+            })
+            .collect()
+    }
+}
+
+// --- Operations ---
+
+/// An arena, independently lockable.
+pub type SharedArena = Arc<Mutex<Arena>>;
+
+/// Every arena, and a channel that names each arena whose standings
+/// changed.
+pub struct Arenas {
+    arenas: DashMap<Uuid, SharedArena>,
+    changes: broadcast::Sender<Uuid>,
+}
+
+impl Default for Arenas {
+    fn default() -> Self {
+        Self {
+            arenas: DashMap::new(),
+            changes: broadcast::channel(64).0,
+        }
+    }
+}
+
+impl Arenas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<SharedArena> {
+        self.arenas.get(id).map(|arena| arena.clone())
+    }
+
+    /// Registers a new arena, dropping those that finished a while ago.
+    pub fn insert(&self, arena: Arena) {
+        let now = Utc::now();
+        self.arenas.retain(|_, arena| {
+            arena
+                .try_lock()
+                .map_or(true, |arena| arena.ends_at + KEEP_FINISHED > now)
+        });
+        self.arenas.insert(arena.id, Arc::new(Mutex::new(arena)));
+    }
+
+    /// Arena IDs as their standings change.
+    pub fn subscribe(&self) -> broadcast::Receiver<Uuid> {
+        self.changes.subscribe()
+    }
+
+    fn changed(&self, id: Uuid) {
+        // Nobody following the standings is fine.
+        let _ = self.changes.send(id);
+    }
+}
+
+/// Creates a game for each pairing and attaches it to the arena.
+async fn create_games(
+    state: &AppState,
+    arena: &mut Arena,
+    pairings: Vec<Pairing>,
+) -> Result<(), Error> {
+    for pairing in pairings {
+        let seat = |id| {
+            let player = arena.player(id).expect("paired players are in the arena");
+            Seat {
+                name: player.name.clone(),
+                token: player.token.clone(),
+            }
+        };
+        let mut entry = GameEntry::pvp(seat(pairing.x), seat(pairing.o));
+        entry.arena_id = Some(arena.id);
+        let mut clock = Clock::new(arena.time_control);
+        clock.running_since = Some(Utc::now());
+        entry.state.clock = Some(clock);
+        let game_id = crate::create_pvp_game(state, entry).await?;
+        arena.attach_game(&pairing, game_id);
+    }
+    Ok(())
+}
+
+/// Adds `name` to the arena and pairs whoever is free.
+pub async fn join(state: &AppState, arena: &mut Arena, name: String) -> Result<ArenaPlayer, Error> {
+    let now = Utc::now();
+    let player = arena.join(name, now).map_err(Error::Arena)?.clone();
+    let pairings = arena.pairings(now);
+    create_games(state, arena, pairings).await?;
+    state.arenas.changed(arena.id);
+    Ok(player)
+}
+
+pub fn withdraw(state: &AppState, arena: &mut Arena, player_id: Uuid) -> Result<(), Error> {
+    arena.withdraw(player_id).map_err(Error::Arena)?;
+    state.arenas.changed(arena.id);
+    Ok(())
+}
+
+/// Scores a finished game and pairs its players again.
+pub async fn record_result(
+    state: &AppState,
+    arena_id: Uuid,
+    game_id: Uuid,
+    status: GameStatus,
+) -> Result<(), Error> {
+    let arena = state
+        .arenas
+        .get(&arena_id)
+        .ok_or(Error::ArenaNotFound(arena_id))?;
+    let mut arena = arena.lock().await;
+    let pairings = arena
+        .record_result(game_id, status, Utc::now())
+        .map_err(Error::Arena)?;
+    create_games(state, &mut arena, pairings).await?;
+    state.arenas.changed(arena_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000 + secs, 0).unwrap()
+    }
+
+    /// Attaches a made-up game to every pairing.
+    fn start_all(arena: &mut Arena, pairings: &[Pairing]) -> Vec<Uuid> {
+        pairings
+            .iter()
+            .map(|pairing| {
+                let game_id = Uuid::new_v4();
+                arena.attach_game(pairing, game_id);
+                game_id
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_finished_players_are_paired_again_by_standing() {
+        let mut arena = Arena::new(
+            "Blitz".to_string(),
+            DEFAULT_TIME_CONTROL,
+            TimeDelta::minutes(30),
+            at(0),
+        );
+        for name in ["a", "b", "c", "d"] {
+            arena.join(name.to_string(), at(0)).unwrap();
+        }
+        let ids: Vec<Uuid> = arena.players.iter().map(|player| player.id).collect();
+        let pairings = arena.pairings(at(0));
+        assert_eq!(
+            pairings,
+            [
+                Pairing {
+                    x: ids[0],
+                    o: ids[1]
+                },
+                Pairing {
+                    x: ids[2],
+                    o: ids[3]
+                },
+            ]
+        );
+        let games = start_all(&mut arena, &pairings);
+        assert!(arena.pairings(at(1)).is_empty());
+
+        // a beats b, but waits until someone else is free rather than
+        // playing b again.
+        let next = arena
+            .record_result(games[0], GameStatus::Win(Player::X), at(60))
+            .unwrap();
+        assert!(next.is_empty());
+        let next = arena
+            .record_result(games[1], GameStatus::Draw, at(90))
+            .unwrap();
+        // a, leading, meets c, and d plays b. Colors go to whoever has had
+        // X less often.
+        assert_eq!(
+            next,
+            [
+                Pairing {
+                    x: ids[0],
+                    o: ids[2]
+                },
+                Pairing {
+                    x: ids[3],
+                    o: ids[1]
+                },
+            ]
+        );
+        let standings = arena.standings();
+        let points: Vec<(&str, u32)> = standings
+            .iter()
+            .map(|standing| (standing.name.as_str(), standing.points))
+            .collect();
+        assert_eq!(points, [("a", 2), ("c", 1), ("d", 1), ("b", 0)]);
+
+        // Nothing is paired after the window, but games underway still count.
+        let games = start_all(&mut arena, &next);
+        let end = at(0) + TimeDelta::minutes(30);
+        assert_eq!(arena.join("e".to_string(), end), Err(ArenaError::Over));
+        arena
+            .record_result(games[0], GameStatus::Win(Player::O), end)
+            .unwrap();
+        assert!(!arena.is_finished(end));
+        assert!(
+            arena
+                .record_result(games[1], GameStatus::Win(Player::O), end)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(arena.is_finished(end));
+        assert_eq!(arena.standings()[0].name, "c");
+    }
+}
@@ -0,0 +1,202 @@
+//! An append-only record of who did what, when, and from where: moves,
+//! sign-ins and other session changes, and admin actions.
+//!
+//! Entries are only ever added. They are kept in memory in the order they
+//! happened, for the admin API to read back, and appended to the store as
+//! they are recorded.
+
+use std::{net::IpAddr, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{account::Role, flags::Flag, state::AppState};
+
+/// Who did it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Actor {
+    /// Nobody identified themselves.
+    Anonymous,
+    Account {
+        account_id: Uuid,
+    },
+    /// Whoever holds a player-vs-player game's seat token.
+    Seat,
+    /// Whoever holds the admin token.
+    AdminToken,
+}
+
+/// What they did.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Action {
+    Move {
+        game_id: Uuid,
+        row: usize,
+        col: usize,
+    },
+    StartGuest {
+        account_id: Uuid,
+    },
+    Register {
+        account_id: Uuid,
+    },
+    /// `method` is `password` or the OAuth provider's name.
+    Login {
+        account_id: Uuid,
+        method: String,
+    },
+    LoginFailed {
+        username: String,
+    },
+    Logout {
+        account_id: Uuid,
+        sessions: usize,
+    },
+    DeleteGame {
+        game_id: Uuid,
+    },
+    RestoreGame {
+        game_id: Uuid,
+    },
+    ExportGames {
+        since: Option<DateTime<Utc>>,
+    },
+    ImportGames {
+        imported: usize,
+        failed: usize,
+    },
+    SetMaintenance {
+        enabled: bool,
+    },
+    /// `enabled: None` puts a tenant's flag back to the server-wide setting.
+    SetFlag {
+        flag: Flag,
+        tenant: Option<String>,
+        enabled: Option<bool>,
+    },
+    StartDrain {
+        deadline: DateTime<Utc>,
+        games_in_progress: usize,
+    },
+    /// Sessions ended by an admin: all of an account's, or just one.
+    RevokeSessions {
+        account_id: Option<Uuid>,
+        session_id: Option<Uuid>,
+        sessions: usize,
+    },
+    SetRole {
+        account_id: Uuid,
+        role: Role,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub actor: Actor,
+    /// The address the request came from; `None` when unknown.
+    pub ip: Option<IpAddr>,
+    pub action: Action,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    /// Oldest first.
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Puts entries loaded from storage back, ahead of anything recorded
+    /// since.
+    pub fn restore(&self, mut entries: Vec<AuditEntry>) {
+        entries.sort_by_key(|entry| entry.at);
+        let mut log = self.entries.lock().expect("audit log poisoned");
+        entries.append(&mut log);
+        *log = entries;
+    }
+
+    /// Entries from `from` up to but not including `to`, oldest first, at
+    /// most `limit` of them.
+    pub fn between(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Vec<AuditEntry> {
+        let log = self.entries.lock().expect("audit log poisoned");
+        let start = from.map_or(0, |from| log.partition_point(|entry| entry.at < from));
+        let end = to.map_or(log.len(), |to| log.partition_point(|entry| entry.at < to));
+        log[start..end.max(start)]
+            .iter()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, entry: AuditEntry) {
+        let mut log = self.entries.lock().expect("audit log poisoned");
+        // Entries recorded concurrently can arrive slightly out of order;
+        // keep the log sorted so time-range lookups can bisect it.
+        let at = log.partition_point(|other| other.at <= entry.at);
+        log.insert(at, entry);
+    }
+}
+
+// --- Operations ---
+
+/// Records that `actor` did `action`. The action already happened, so a
+/// failure to store the entry is logged rather than returned.
+pub async fn record(state: &AppState, actor: Actor, ip: Option<IpAddr>, action: Action) {
+    let entry = AuditEntry {
+        at: Utc::now(),
+        actor,
+        ip,
+        action,
+    };
+    if let Err(e) = state.store.append_audit(&entry).await {
+        log::error!("Failed to store audit entry {:?}: {}", entry, e);
+    }
+    state.audit.push(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn entry(at: DateTime<Utc>) -> AuditEntry {
+        AuditEntry {
+            at,
+            actor: Actor::Anonymous,
+            ip: None,
+            action: Action::SetMaintenance { enabled: true },
+        }
+    }
+
+    #[test]
+    fn test_entries_are_found_by_time_range() {
+        let log = AuditLog::default();
+        let start = Utc::now();
+        let minutes = |n| start + TimeDelta::minutes(n);
+        log.push(entry(minutes(2)));
+        log.push(entry(minutes(0)));
+        log.restore(vec![entry(minutes(-5))]);
+        log.push(entry(minutes(1)));
+
+        let all = log.between(None, None, 10);
+        let times: Vec<_> = all.iter().map(|entry| entry.at).collect();
+        assert_eq!(times, [minutes(-5), minutes(0), minutes(1), minutes(2)]);
+
+        let range = log.between(Some(minutes(0)), Some(minutes(2)), 10);
+        assert_eq!(range.len(), 2);
+        assert_eq!(log.between(Some(minutes(0)), None, 1)[0].at, minutes(0));
+        assert!(
+            log.between(Some(minutes(3)), Some(minutes(1)), 10)
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! `laika bench`: measures engine search speed so changes to the search can
+//! be compared before and after.
+
+use std::time::{Duration, Instant};
+
+use clap::Args;
+
+use crate::{
+    engine::EngineKind,
+    game::{GameState, GameStatus, MAX_SIDE, MIN_SIDE, Rules, try_move},
+};
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Engines to benchmark [default: all]
+    #[arg(long = "engine", value_enum)]
+    pub engines: Vec<EngineKind>,
+
+    /// Square board sizes to benchmark, e.g. `--size 3 --size 5` [default: 3 to 8]
+    #[arg(long = "size", value_parser = clap::value_parser!(u8).range(MIN_SIDE as i64..=MAX_SIDE as i64))]
+    pub sizes: Vec<u8>,
+
+    /// Times each measurement is repeated; the fastest run is reported
+    #[arg(long, default_value_t = 5)]
+    pub iterations: u32,
+}
+
+/// One row of the comparison table.
+#[derive(Debug, Clone, Copy)]
+struct BenchResult {
+    engine: EngineKind,
+    /// The board is `side` x `side`, played with the default rules.
+    side: usize,
+    /// Nodes visited choosing the first move on an empty board.
+    nodes: u64,
+    solve_time: Duration,
+    /// Total time for the engine to play a complete game against itself.
+    game_time: Duration,
+}
+
+impl BenchResult {
+    fn nodes_per_second(&self) -> f64 {
+        self.nodes as f64 / self.solve_time.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+fn bench_engine(engine: EngineKind, side: usize, iterations: u32) -> BenchResult {
+    let empty = GameState::custom(side, side, Rules::default(), &[])
+        .expect("bench sizes are checked when the arguments are parsed");
+    let mut nodes = 0;
+    let mut solve_time = Duration::MAX;
+    let mut game_time = Duration::MAX;
+
+    for _ in 0..iterations.max(1) {
+        let started = Instant::now();
+        let (_, stats) = engine.search(&empty);
+        solve_time = solve_time.min(started.elapsed());
+        nodes = stats.nodes;
+
+        let mut game_state = empty;
+        let started = Instant::now();
+        while game_state.status == GameStatus::InProgress {
+            let player = game_state.to_play;
+            let player_move = engine
+                .choose_move(&game_state)
+                .expect("an in-progress game always has a legal move");
+            try_move(&mut game_state, player, player_move)
+                .expect("engines only choose legal moves");
+        }
+        game_time = game_time.min(started.elapsed());
+    }
+
+    BenchResult {
+        engine,
+        side,
+        nodes,
+        solve_time,
+        game_time,
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Formats the comparison table, one row per engine and board size.
+fn table(results: &[BenchResult]) -> String {
+    let mut table = format!(
+        "{:<10} {:<6} {:>12} {:>14} {:>14} {:>14}\n",
+        "engine", "board", "nodes", "solve (ms)", "nodes/sec", "full game (ms)"
+    );
+    for result in results {
+        table.push_str(&format!(
+            "{:<10} {:<6} {:>12} {:>14.3} {:>14.0} {:>14.3}\n",
+            format!("{:?}", result.engine).to_lowercase(),
+            format!("{0}x{0}", result.side),
+            result.nodes,
+            millis(result.solve_time),
+            result.nodes_per_second(),
+            millis(result.game_time),
+        ));
+    }
+    table
+}
+
+/// Runs the benchmarks and prints a comparison table to stdout.
+pub fn run(args: &BenchArgs) {
+    let engines = if args.engines.is_empty() {
+        EngineKind::ALL.to_vec()
+    } else {
+        args.engines.clone()
+    };
+    let sizes: Vec<usize> = if args.sizes.is_empty() {
+        (MIN_SIDE..=MAX_SIDE).collect()
+    } else {
+        args.sizes.iter().map(|&side| usize::from(side)).collect()
+    };
+
+    if cfg!(debug_assertions) {
+        println!(
+            "note: this is a debug build; use `cargo run --release -- bench` for real numbers\n"
+        );
+    }
+    let results: Vec<BenchResult> = engines
+        .iter()
+        .flat_map(|&engine| {
+            sizes
+                .iter()
+                .map(move |&side| bench_engine(engine, side, args.iterations))
+        })
+        .collect();
+    print!("{}", table(&results));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_the_table_has_a_row_per_engine_and_size() {
+        let results = [
+            BenchResult {
+                engine: EngineKind::Minimax,
+                side: 3,
+                nodes: 5_000,
+                solve_time: Duration::from_millis(2),
+                game_time: Duration::from_micros(4_500),
+            },
+            BenchResult {
+                engine: EngineKind::Minimax,
+                side: 5,
+                nodes: 200_000,
+                solve_time: Duration::from_millis(100),
+                game_time: Duration::from_secs(1),
+            },
+        ];
+        let table = table(&results);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("engine     board "));
+        assert!(lines[0].ends_with("full game (ms)"));
+        let row: Vec<&str> = lines[1].split_whitespace().collect();
+        assert_eq!(row, ["minimax", "3x3", "5000", "2.000", "2500000", "4.500"]);
+        let row: Vec<&str> = lines[2].split_whitespace().collect();
+        assert_eq!(
+            row,
+            ["minimax", "5x5", "200000", "100.000", "2000000", "1000.000"]
+        );
+    }
+
+    #[test]
+    fn test_larger_boards_are_searched_from_empty() {
+        let result = bench_engine(EngineKind::Random, 4, 1);
+        assert_eq!(result.side, 4);
+        assert_eq!(result.nodes, 16);
+    }
+}
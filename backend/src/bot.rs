@@ -0,0 +1,305 @@
+//! External bots: engines run by third parties that play O against people.
+//!
+//! A bot registers once and gets a token, then holds a WebSocket open. When
+//! it is the bot's turn the server sends it the position and waits up to the
+//! configured deadline for a move. A bot that misses the deadline, drops the
+//! connection mid-turn, or answers with an illegal move forfeits the game.
+//! Bots are kept in memory; they have to register again after a restart.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    config::BotsConfig,
+    game::{Ending, GameState, GameStatus, Player, PlayerMove, try_move},
+    state::{AppState, GameEntry, GameEvent, GameMode, new_token},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bot {
+    pub id: Uuid,
+    pub name: String,
+    /// Secret the bot presents when it connects.
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A position sent to a bot, which answers with a move for `request_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveRequest {
+    pub request_id: Uuid,
+    pub game_id: Uuid,
+    pub game_state: GameState,
+    pub deadline_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotError {
+    NotFound,
+    /// The bot has no open connection.
+    Offline,
+    TooManyBots,
+    /// The bot didn't answer before the deadline.
+    Timeout,
+    IllegalMove,
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            BotError::NotFound => "No bot with that ID",
+            BotError::Offline => "The bot is not connected",
+            BotError::TooManyBots => "Too many registered bots",
+            BotError::Timeout => "The bot did not move in time",
+            BotError::IllegalMove => "The bot played an illegal move",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for BotError {}
+
+/// Every registered bot, its connection if it has one, and the moves it has
+/// been asked for.
+pub struct Bots {
+    bots: DashMap<Uuid, Bot>,
+    /// Each bot's open connection, tagged with an ID so a replaced
+    /// connection can't close its successor.
+    connections: DashMap<Uuid, (Uuid, mpsc::UnboundedSender<MoveRequest>)>,
+    /// Unanswered move requests, with the bot each was sent to.
+    pending: DashMap<Uuid, (Uuid, oneshot::Sender<PlayerMove>)>,
+    config: BotsConfig,
+}
+
+impl Bots {
+    pub fn new(config: BotsConfig) -> Self {
+        Self {
+            bots: DashMap::new(),
+            connections: DashMap::new(),
+            pending: DashMap::new(),
+            config,
+        }
+    }
+
+    pub fn register(&self, name: String, now: DateTime<Utc>) -> Result<Bot, BotError> {
+        if self.bots.len() >= self.config.max_registered {
+            return Err(BotError::TooManyBots);
+        }
+        let bot = Bot {
+            id: Uuid::new_v4(),
+            name,
+            token: new_token(),
+            created_at: now,
+        };
+        self.bots.insert(bot.id, bot.clone());
+        Ok(bot)
+    }
+
+    pub fn get(&self, bot_id: Uuid) -> Result<Bot, BotError> {
+        self.bots
+            .get(&bot_id)
+            .map(|bot| bot.clone())
+            .ok_or(BotError::NotFound)
+    }
+
+    /// Every bot, oldest first.
+    pub fn list(&self) -> Vec<Bot> {
+        let mut bots: Vec<Bot> = self.bots.iter().map(|bot| bot.clone()).collect();
+        bots.sort_by_key(|bot| bot.created_at);
+        bots
+    }
+
+    pub fn is_online(&self, bot_id: Uuid) -> bool {
+        self.connections
+            .get(&bot_id)
+            .is_some_and(|connection| !connection.1.is_closed())
+    }
+
+    /// Makes a new connection the bot's, replacing any earlier one. Returns
+    /// the connection's ID and the receiver move requests arrive on.
+    pub fn attach(&self, bot_id: Uuid) -> (Uuid, mpsc::UnboundedReceiver<MoveRequest>) {
+        let connection_id = Uuid::new_v4();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.connections.insert(bot_id, (connection_id, sender));
+        (connection_id, receiver)
+    }
+
+    /// Drops the bot's connection, unless it has already been replaced by a
+    /// newer one.
+    pub fn detach(&self, bot_id: Uuid, connection_id: Uuid) {
+        self.connections
+            .remove_if(&bot_id, |_, (current, _)| *current == connection_id);
+    }
+
+    /// Sends the position to the bot and waits for its move.
+    pub async fn ask(
+        &self,
+        bot_id: Uuid,
+        game_id: Uuid,
+        game_state: GameState,
+    ) -> Result<PlayerMove, BotError> {
+        let connection = self
+            .connections
+            .get(&bot_id)
+            .map(|connection| connection.1.clone())
+            .ok_or(BotError::Offline)?;
+        let request = MoveRequest {
+            request_id: Uuid::new_v4(),
+            game_id,
+            game_state,
+            deadline_ms: self.config.move_deadline_ms,
+        };
+        let (reply, answer) = oneshot::channel();
+        self.pending.insert(request.request_id, (bot_id, reply));
+        if connection.send(request).is_err() {
+            self.pending.remove(&request.request_id);
+            return Err(BotError::Offline);
+        }
+        let answer = tokio::time::timeout(self.config.move_deadline(), answer).await;
+        self.pending.remove(&request.request_id);
+        match answer {
+            Ok(Ok(player_move)) => Ok(player_move),
+            Ok(Err(_)) => Err(BotError::Offline),
+            Err(_) => Err(BotError::Timeout),
+        }
+    }
+
+    /// Hands a bot's answer to whoever asked. Returns false if no request
+    /// with that ID is waiting on this bot, e.g. because the deadline passed.
+    pub fn answer(&self, bot_id: Uuid, request_id: Uuid, player_move: PlayerMove) -> bool {
+        match self
+            .pending
+            .remove_if(&request_id, |_, (asked, _)| *asked == bot_id)
+        {
+            Some((_, (_, reply))) => reply.send(player_move).is_ok(),
+            None => false,
+        }
+    }
+}
+
+// --- Operations ---
+
+/// Starts a game with an anonymous human as X against the bot as O.
+pub async fn create_game(state: &AppState, bot_id: Uuid) -> Result<(Uuid, GameState), Error> {
+    state.accepting_games()?;
+    let bot = state.bots.get(bot_id).map_err(Error::Bot)?;
+    if !state.bots.is_online(bot_id) {
+        return Err(Error::Bot(BotError::Offline));
+    }
+    let game_id = Uuid::new_v4();
+    let mut entry = GameEntry::new(GameState::default());
+    entry.mode = GameMode::VsBot {
+        bot_id,
+        name: bot.name,
+    };
+
+    let game_state = entry.state;
+    state.insert_game(game_id, entry).await?;
+    state.publish(game_id, GameEvent::Created);
+    log::info!("Created game {} against bot {}", game_id, bot_id);
+    Ok((game_id, game_state))
+}
+
+/// Asks the bot for O's reply and applies it. If the bot fails to produce a
+/// legal move in time it forfeits, and `None` is returned.
+pub async fn take_turn(
+    state: &AppState,
+    bot_id: Uuid,
+    game_id: Uuid,
+    game_state: &mut GameState,
+) -> Option<PlayerMove> {
+    if game_state.status != GameStatus::InProgress {
+        return None;
+    }
+    let result = match state.bots.ask(bot_id, game_id, *game_state).await {
+        Ok(player_move) => try_move(game_state, Player::O, player_move)
+            .map(|()| player_move)
+            .map_err(|_| BotError::IllegalMove),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(player_move) => Some(player_move),
+        Err(e) => {
+            log::info!("Bot {} forfeited game {}: {}", bot_id, game_id, e);
+            game_state.status = GameStatus::Win(Player::X);
+            game_state.ending = Some(Ending::ByForfeit);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn bots(move_deadline_ms: u64) -> Bots {
+        Bots::new(BotsConfig {
+            move_deadline_ms,
+            max_registered: 2,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_bots_answer_move_requests() {
+        let bots = Arc::new(bots(1000));
+        let bot = bots.register("echo".to_string(), Utc::now()).unwrap();
+        assert_eq!(
+            bots.ask(bot.id, Uuid::new_v4(), GameState::default()).await,
+            Err(BotError::Offline)
+        );
+
+        let (connection_id, mut requests) = bots.attach(bot.id);
+        assert!(bots.is_online(bot.id));
+        let answering = bots.clone();
+        tokio::spawn(async move {
+            let request = requests.recv().await.unwrap();
+            // Another bot can't answer for this one.
+            assert!(!answering.answer(
+                Uuid::new_v4(),
+                request.request_id,
+                PlayerMove { row: 0, col: 0 }
+            ));
+            assert!(answering.answer(bot.id, request.request_id, PlayerMove { row: 1, col: 1 }));
+            answering.detach(bot.id, connection_id);
+        });
+        assert_eq!(
+            bots.ask(bot.id, Uuid::new_v4(), GameState::default()).await,
+            Ok(PlayerMove { row: 1, col: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_bots_time_out() {
+        let bots = bots(20);
+        let bot = bots.register("sleepy".to_string(), Utc::now()).unwrap();
+        let (connection_id, mut requests) = bots.attach(bot.id);
+        assert_eq!(
+            bots.ask(bot.id, Uuid::new_v4(), GameState::default()).await,
+            Err(BotError::Timeout)
+        );
+        // The answer comes in too late to count.
+        let request = requests.recv().await.unwrap();
+        assert!(!bots.answer(bot.id, request.request_id, PlayerMove { row: 0, col: 0 }));
+
+        // A reconnect replaces the old connection, and closing the old one
+        // afterwards leaves the new one in place.
+        let (newer, _requests) = bots.attach(bot.id);
+        bots.detach(bot.id, connection_id);
+        assert!(bots.is_online(bot.id));
+        bots.detach(bot.id, newer);
+        assert!(!bots.is_online(bot.id));
+
+        bots.register("second".to_string(), Utc::now()).unwrap();
+        assert_eq!(
+            bots.register("third".to_string(), Utc::now()),
+            Err(BotError::TooManyBots)
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Difficulty calibration: how often players beat the engine at each
+//! blunder chance, and optionally tuning the default chance to a target.
+//!
+//! Every finished game against the engine is counted by its difficulty, the
+//! blunder chance to the nearest tenth, in the `laika_engine_games_total`
+//! metric and in the daily rollups, which `GET /api/stats/calibration` adds
+//! up. With `calibration.auto_tune`, new games that don't ask for a blunder
+//! chance, and whose tenant doesn't set one, get the tuner's, and each of
+//! their results nudges it: down by `step * (1 - target)` when the player
+//! won, and up by `step * target` otherwise. The nudges cancel out once
+//! players win `target_win_rate` of those games.
+
+use std::sync::RwLock;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    config::CalibrationConfig,
+    game::{GameStatus, Player},
+    metrics,
+    state::{AppState, GameMode, SharedGame},
+};
+
+/// A blunder chance to the nearest tenth, e.g. `0.3`, by which games are
+/// counted.
+pub fn difficulty(blunder_chance: f64) -> String {
+    format!(
+        "{:.1}",
+        (blunder_chance.clamp(0.0, 1.0) * 10.0).round() / 10.0
+    )
+}
+
+/// How games at one difficulty ended, from the player's side: the player is
+/// X and the engine O.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outcomes {
+    pub games: u64,
+    pub player_wins: u64,
+    pub engine_wins: u64,
+    pub draws: u64,
+}
+
+impl Outcomes {
+    /// Counts a finished game.
+    pub fn add(&mut self, status: GameStatus) {
+        match status {
+            GameStatus::Win(Player::X) => self.player_wins += 1,
+            GameStatus::Win(Player::O) => self.engine_wins += 1,
+            GameStatus::Draw => self.draws += 1,
+            GameStatus::InProgress => return,
+        }
+        self.games += 1;
+    }
+
+    pub fn merge(&mut self, other: &Outcomes) {
+        self.games += other.games;
+        self.player_wins += other.player_wins;
+        self.engine_wins += other.engine_wins;
+        self.draws += other.draws;
+    }
+
+    /// 0 without any games.
+    pub fn player_win_rate(&self) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        self.player_wins as f64 / self.games as f64
+    }
+}
+
+/// Where auto-tuning stands, as reported by `GET /api/stats/calibration`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Tuning {
+    pub target_win_rate: f64,
+    /// What new games get now.
+    pub blunder_chance: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct Tuner {
+    config: CalibrationConfig,
+    blunder_chance: RwLock<f64>,
+}
+
+impl Tuner {
+    /// A tuner that carries on from `resumed`, the chance the latest tuned
+    /// game got, or starts from `calibration.initial_blunder_chance`.
+    pub fn new(config: CalibrationConfig, resumed: Option<f64>) -> Self {
+        let blunder_chance = resumed.unwrap_or(config.initial_blunder_chance);
+        Self {
+            config,
+            blunder_chance: RwLock::new(blunder_chance),
+        }
+    }
+
+    /// The tuned chance for a new game; `None` unless auto-tuning.
+    pub fn blunder_chance(&self) -> Option<f64> {
+        self.config
+            .auto_tune
+            .then(|| *self.blunder_chance.read().expect("tuner poisoned"))
+    }
+
+    pub fn tuning(&self) -> Option<Tuning> {
+        Some(Tuning {
+            target_win_rate: self.config.target_win_rate,
+            blunder_chance: self.blunder_chance()?,
+        })
+    }
+
+    /// Nudges the chance after a tuned game ended with `status`, returning
+    /// the new chance.
+    fn adjust(&self, status: GameStatus) -> f64 {
+        let target = self.config.target_win_rate;
+        let nudge = match status {
+            GameStatus::Win(Player::X) => -self.config.step * (1.0 - target),
+            _ => self.config.step * target,
+        };
+        let mut blunder_chance = self.blunder_chance.write().expect("tuner poisoned");
+        *blunder_chance = (*blunder_chance + nudge).clamp(0.0, 1.0);
+        *blunder_chance
+    }
+}
+
+/// The chance the most recently started tuned game got, to carry on from
+/// after a restart. Only games still in the registry are looked at.
+pub fn latest_tuned(games: &DashMap<Uuid, SharedGame>) -> Option<f64> {
+    games
+        .iter()
+        .filter_map(|game| {
+            let entry = game.try_lock().ok()?;
+            let created_at = entry.events.first()?.at;
+            entry
+                .auto_tuned
+                .then_some((created_at, entry.blunder_chance))
+        })
+        .max_by_key(|(created_at, _)| *created_at)
+        .map(|(_, blunder_chance)| blunder_chance)
+}
+
+/// Records how a game that just finished went, if it was against the
+/// engine, and tunes the chance on it if the tuner picked the game's.
+pub async fn record(state: &AppState, game_id: Uuid) {
+    let Some(game) = state.game(&game_id) else {
+        return;
+    };
+    let entry = game.lock().await;
+    if entry.mode != GameMode::VsEngine {
+        return;
+    }
+    let status = entry.state.status;
+    metrics::record_engine_game(entry.blunder_chance, status);
+    if entry.auto_tuned {
+        let blunder_chance = state.tuner.adjust(status);
+        log::debug!("Tuned blunder chance is now {:.3}", blunder_chance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{DateTime, TimeDelta};
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{game::GameState, state::GameEntry};
+
+    #[test]
+    fn test_tuning_settles_where_players_win_the_target_share() {
+        let config = CalibrationConfig {
+            auto_tune: true,
+            target_win_rate: 0.25,
+            step: 0.04,
+            initial_blunder_chance: 0.5,
+        };
+        let tuner = Tuner::new(config, None);
+        assert!((tuner.adjust(GameStatus::Win(Player::X)) - 0.47).abs() < 1e-9);
+        // One win to three other results is on target: no net change.
+        for status in [
+            GameStatus::Draw,
+            GameStatus::Win(Player::O),
+            GameStatus::Draw,
+        ] {
+            tuner.adjust(status);
+        }
+        assert!((tuner.blunder_chance().unwrap() - 0.5).abs() < 1e-9);
+        assert!(
+            Tuner::new(CalibrationConfig::default(), Some(0.5))
+                .blunder_chance()
+                .is_none()
+        );
+
+        let games = DashMap::new();
+        let started = [(true, 0.2), (false, 0.9), (true, 0.3)];
+        for (minutes, (auto_tuned, blunder_chance)) in started.into_iter().enumerate() {
+            let mut entry = GameEntry::new(GameState::default());
+            entry.events[0].at = DateTime::UNIX_EPOCH + TimeDelta::minutes(minutes as i64);
+            entry.auto_tuned = auto_tuned;
+            entry.blunder_chance = blunder_chance;
+            games.insert(Uuid::new_v4(), Arc::new(Mutex::new(entry)));
+        }
+        assert_eq!(latest_tuned(&games), Some(0.3));
+        assert_eq!(difficulty(0.25), "0.3");
+        assert_eq!(difficulty(1.4), "1.0");
+    }
+}
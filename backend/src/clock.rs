@@ -0,0 +1,675 @@
+//! Chess-style clocks for timed player-vs-player games.
+//!
+//! The server keeps the only authoritative clock. A move is charged the time
+//! between the previous move being accepted and this one arriving, so
+//! network latency counts against the mover, as it would on any online chess
+//! server. The first move of a game is free: the clock starts when it is
+//! made, which gives the second player time to show up.
+//!
+//! A player whose time runs out is dealt with by `sweep` even if
+//! nobody sends another request, or when their late move arrives, whichever
+//! comes first.
+//!
+//! Both players can agree to pause a game, which stops the clock and locks
+//! the board until they agree to resume. The sweeper resumes games that have
+//! been paused for longer than `clocks.max_pause_secs`.
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
+use tokio::sync::MutexGuard;
+use uuid::Uuid;
+
+use crate::{
+    Error, cluster,
+    config::ClocksConfig,
+    game::{Ending, GameState, GameStatus, Player, PlayerMove, try_move},
+    state::{AppState, GameEntry, GameEvent},
+};
+
+/// How often the sweeper looks for players who have run out of time.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest initial time a game can start with: one day.
+pub const MAX_INITIAL_SECS: u64 = 24 * 60 * 60;
+/// Largest increment or delay per move: one hour.
+pub const MAX_INCREMENT_SECS: u64 = 60 * 60;
+
+/// How `increment_secs` applies to each move.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncrementMode {
+    /// The increment is added to the mover's clock after every move.
+    #[default]
+    Fischer,
+    /// The mover's clock only starts running once the delay has passed, and
+    /// unused delay is lost.
+    Delay,
+}
+
+/// What happens to a player who runs out of time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutAction {
+    /// They lose.
+    #[default]
+    Forfeit,
+    /// A random move is played for them and the game goes on; from then on
+    /// they have only the increment or delay for each move.
+    RandomMove,
+    /// The game ends in a draw.
+    Draw,
+}
+
+/// The time control a game is played under, e.g. 5+3: five minutes each and
+/// three seconds per move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub initial_secs: u64,
+    #[serde(default)]
+    pub increment_secs: u64,
+    #[serde(default)]
+    pub mode: IncrementMode,
+    #[serde(default)]
+    pub on_timeout: TimeoutAction,
+}
+
+impl TimeControl {
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(1..=MAX_INITIAL_SECS).contains(&self.initial_secs) {
+            return Err(Error::BadRequest(
+                "initial_secs must be between 1 and 86400",
+            ));
+        }
+        if self.increment_secs > MAX_INCREMENT_SECS {
+            return Err(Error::BadRequest("increment_secs must be at most 3600"));
+        }
+        Ok(())
+    }
+}
+
+/// The mover ran out of time before their move arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flagged;
+
+/// Both players' remaining time. The side to move is the one whose clock is
+/// running, from `running_since` on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Clock {
+    pub control: TimeControl,
+    /// X's remaining time as of `running_since`, in milliseconds.
+    pub x_ms: u64,
+    pub o_ms: u64,
+    /// When the current turn started; `None` until the first move and
+    /// while paused.
+    pub running_since: Option<DateTime<Utc>>,
+    /// When the players agreed to pause.
+    #[serde(default)]
+    pub paused_at: Option<DateTime<Utc>>,
+    /// A player who asked to pause, or to resume while paused, and is
+    /// waiting for the other to agree. Cleared by the next move.
+    #[serde(default)]
+    pub pause_offer: Option<Player>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let initial_ms = control.initial_secs * 1000;
+        Self {
+            control,
+            x_ms: initial_ms,
+            o_ms: initial_ms,
+            running_since: None,
+            paused_at: None,
+            pause_offer: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    fn stored(&self, player: Player) -> u64 {
+        match player {
+            Player::X => self.x_ms,
+            Player::O => self.o_ms,
+        }
+    }
+
+    fn stored_mut(&mut self, player: Player) -> &mut u64 {
+        match player {
+            Player::X => &mut self.x_ms,
+            Player::O => &mut self.o_ms,
+        }
+    }
+
+    /// Milliseconds a turn that started at `running_since` has used up by
+    /// `now`, after any delay.
+    fn used_ms(&self, now: DateTime<Utc>) -> u64 {
+        let Some(since) = self.running_since else {
+            return 0;
+        };
+        let elapsed = (now - since).num_milliseconds().max(0) as u64;
+        match self.control.mode {
+            IncrementMode::Fischer => elapsed,
+            IncrementMode::Delay => elapsed.saturating_sub(self.control.increment_secs * 1000),
+        }
+    }
+
+    /// `player`'s remaining time at `now`, counting the turn in progress if
+    /// it is theirs.
+    pub fn remaining_ms(&self, player: Player, to_play: Player, now: DateTime<Utc>) -> u64 {
+        if player == to_play {
+            self.stored(player).saturating_sub(self.used_ms(now))
+        } else {
+            self.stored(player)
+        }
+    }
+
+    /// Whether the side to move has used more time than they had by `now`.
+    /// A player on zero is still fine while a delay covers them.
+    pub fn is_flagged(&self, to_play: Player, now: DateTime<Utc>) -> bool {
+        self.running_since.is_some() && self.used_ms(now) > self.stored(to_play)
+    }
+
+    /// Stops `player`'s clock for a move made at `now` and starts the
+    /// opponent's. Fails without changing anything if `player` had already
+    /// run out of time.
+    pub fn punch(&mut self, player: Player, now: DateTime<Utc>) -> Result<(), Flagged> {
+        if self.is_flagged(player, now) {
+            return Err(Flagged);
+        }
+        let remaining = self.remaining_ms(player, player, now);
+        let increment = match self.control.mode {
+            IncrementMode::Fischer => self.control.increment_secs * 1000,
+            IncrementMode::Delay => 0,
+        };
+        *self.stored_mut(player) = remaining + increment;
+        self.running_since = Some(now);
+        self.pause_offer = None;
+        Ok(())
+    }
+
+    /// Records that `player` ran out of time and the game ended for it:
+    /// their clock reads zero and nothing runs any more.
+    pub fn flag(&mut self, player: Player) {
+        *self.stored_mut(player) = 0;
+        self.running_since = None;
+    }
+
+    /// Records that `player` ran out of time but the game goes on: they are
+    /// left with just the increment, and the turn passes at `now`.
+    pub fn lapse(&mut self, player: Player, now: DateTime<Utc>) {
+        *self.stored_mut(player) = match self.control.mode {
+            IncrementMode::Fischer => self.control.increment_secs * 1000,
+            IncrementMode::Delay => 0,
+        };
+        self.running_since = Some(now);
+    }
+
+    /// Stops the clock once the game is over, charging the last turn.
+    pub fn stop(&mut self, to_play: Player, now: DateTime<Utc>) {
+        let remaining = self.remaining_ms(to_play, to_play, now);
+        *self.stored_mut(to_play) = remaining;
+        self.running_since = None;
+    }
+
+    /// Stops the clock at `now`, charging the turn so far, until `resume`.
+    pub fn pause(&mut self, to_play: Player, now: DateTime<Utc>) {
+        self.stop(to_play, now);
+        self.paused_at = Some(now);
+        self.pause_offer = None;
+    }
+
+    /// Restarts the side to move's clock at `now`.
+    pub fn resume(&mut self, now: DateTime<Utc>) {
+        self.running_since = Some(now);
+        self.paused_at = None;
+        self.pause_offer = None;
+    }
+}
+
+/// Applies the game's timeout action to the side to move, who has run out
+/// of time by `now`. Returns the move played for them, if any.
+pub fn apply_timeout(
+    game_state: &mut GameState,
+    now: DateTime<Utc>,
+) -> Result<Option<PlayerMove>, Error> {
+    let player = game_state.to_play;
+    let Some(clock) = &mut game_state.clock else {
+        return Ok(None);
+    };
+    match clock.control.on_timeout {
+        TimeoutAction::Forfeit => {
+            clock.flag(player);
+            game_state.status = GameStatus::Win(player.opponent());
+            game_state.ending = Some(Ending::OnTime);
+            Ok(None)
+        }
+        TimeoutAction::Draw => {
+            clock.flag(player);
+            game_state.status = GameStatus::Draw;
+            game_state.ending = Some(Ending::OnTime);
+            Ok(None)
+        }
+        TimeoutAction::RandomMove => {
+            clock.lapse(player, now);
+            let (row, col) = game_state
+                .board
+                .empty_cells()
+                .choose(&mut rand::rng())
+                .ok_or(Error::InvalidMove("No empty cell to move to"))?;
+            let random_move = PlayerMove { row, col };
+            try_move(game_state, player, random_move)?;
+            if game_state.status != GameStatus::InProgress
+                && let Some(clock) = &mut game_state.clock
+            {
+                clock.stop(game_state.to_play, now);
+            }
+            Ok(Some(random_move))
+        }
+    }
+}
+
+/// Applies the timeout action to `game_id` if its side to move is still out
+/// of time once the game is locked. Returns whether anything happened.
+async fn time_out(state: &AppState, game_id: Uuid) -> Result<bool, Error> {
+    let Some(game) = state.game(&game_id) else {
+        return Ok(false);
+    };
+    let entry = game.lock().await;
+    let now = Utc::now();
+    if entry.state.status != GameStatus::InProgress
+        || !entry
+            .state
+            .clock
+            .is_some_and(|clock| clock.is_flagged(entry.state.to_play, now))
+    {
+        return Ok(false);
+    }
+    finish_time_out(state, game_id, entry, now).await?;
+    Ok(true)
+}
+
+/// Applies the timeout action to a locked game whose side to move has run
+/// out of time, stores it, and tells subscribers.
+async fn finish_time_out(
+    state: &AppState,
+    game_id: Uuid,
+    mut entry: MutexGuard<'_, GameEntry>,
+    now: DateTime<Utc>,
+) -> Result<GameState, Error> {
+    let to_play = entry.state.to_play;
+    let mut updated = entry.clone();
+    let first_new_event = updated.events.len();
+    let played = match apply_timeout(&mut updated.state, now)? {
+        Some(random_move) => {
+            updated.record_move(to_play, random_move);
+            true
+        }
+        None => false,
+    };
+    let game_state = updated.state;
+    let status = game_state.status;
+    if status != GameStatus::InProgress {
+        updated.finish(now);
+    }
+    state
+        .store
+        .append_events(game_id, &updated.events[first_new_event..], &updated)
+        .await
+        .map_err(Error::Storage)?;
+    let (tournament_id, match_id, arena_id) =
+        (updated.tournament_id, updated.match_id, updated.arena_id);
+    *entry = updated;
+    drop(entry);
+
+    log::info!("{:?} ran out of time in game {}", to_play, game_id);
+    state.publish(
+        game_id,
+        if played {
+            GameEvent::Moved {
+                finished: status != GameStatus::InProgress,
+            }
+        } else {
+            GameEvent::TimedOut
+        },
+    );
+    if status != GameStatus::InProgress {
+        crate::game_finished(state, game_id, tournament_id, match_id, arena_id, status).await;
+    }
+    Ok(game_state)
+}
+
+/// Resumes `game_id` if it is still paused and has been for at least
+/// `max_pause` once the game is locked.
+async fn end_pause(state: &AppState, game_id: Uuid, max_pause: TimeDelta) -> Result<(), Error> {
+    let Some(game) = state.game(&game_id) else {
+        return Ok(());
+    };
+    let mut entry = game.lock().await;
+    let now = Utc::now();
+    let mut updated = entry.clone();
+    let Some(clock) = &mut updated.state.clock else {
+        return Ok(());
+    };
+    if clock
+        .paused_at
+        .is_none_or(|paused_at| now - paused_at < max_pause)
+    {
+        return Ok(());
+    }
+    clock.resume(now);
+    state
+        .store
+        .append_events(game_id, &[], &updated)
+        .await
+        .map_err(Error::Storage)?;
+    *entry = updated;
+    drop(entry);
+
+    log::info!("Game {} resumed after the longest allowed pause", game_id);
+    state.publish(game_id, GameEvent::PauseChanged);
+    Ok(())
+}
+
+/// Applies the timeout action in games where the side to move has run out of
+/// time, without waiting for anyone to make a request, and ends pauses that
+/// have gone on too long. Run every `SWEEP_INTERVAL`.
+pub async fn sweep(state: AppState, config: ClocksConfig) {
+    let now = Utc::now();
+    let mut flagged = Vec::new();
+    let mut overdue = Vec::new();
+    // Locked games are in the middle of a move; the next sweep gets them.
+    for game in state.games.iter() {
+        let Ok(entry) = game.try_lock() else {
+            continue;
+        };
+        let Some(clock) = entry.state.clock else {
+            continue;
+        };
+        if entry.state.status != GameStatus::InProgress {
+            continue;
+        }
+        if clock.is_flagged(entry.state.to_play, now) {
+            flagged.push(*game.key());
+        } else if clock
+            .paused_at
+            .is_some_and(|paused_at| now - paused_at >= config.max_pause())
+        {
+            overdue.push(*game.key());
+        }
+    }
+    // With several instances, only the one that owns a game changes it.
+    for game_id in flagged {
+        if !cluster::is_local(&state, game_id).await {
+            continue;
+        }
+        if let Err(e) = time_out(&state, game_id).await {
+            log::error!("Failed to time out game {}: {}", game_id, e);
+        }
+    }
+    for game_id in overdue {
+        if !cluster::is_local(&state, game_id).await {
+            continue;
+        }
+        if let Err(e) = end_pause(&state, game_id, config.max_pause()).await {
+            log::error!("Failed to resume game {}: {}", game_id, e);
+        }
+    }
+}
+
+// --- Operations ---
+
+/// Lets the player holding `seat_token` claim that their opponent, the side
+/// to move, has run out of time. The server checks the clock itself; if the
+/// claim holds, the game's timeout action is applied at once instead of at
+/// the next sweep.
+pub async fn claim_timeout(
+    state: &AppState,
+    game_id: Uuid,
+    seat_token: Option<&str>,
+) -> Result<GameState, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    let player = seat_token
+        .and_then(|token| entry.mode.seat_of(token))
+        .ok_or(Error::Forbidden(
+            "A valid seat token is required to claim a timeout",
+        ))?;
+    if entry.state.status != GameStatus::InProgress {
+        return Err(Error::GameOver);
+    }
+    let Some(clock) = entry.state.clock else {
+        return Err(Error::BadRequest("Only timed games have clocks to run out"));
+    };
+    if entry.state.to_play == player {
+        return Err(Error::InvalidMove(
+            "Only the player waiting can claim a timeout",
+        ));
+    }
+    let now = Utc::now();
+    if !clock.is_flagged(entry.state.to_play, now) {
+        return Err(Error::InvalidMove("Your opponent still has time left"));
+    }
+    finish_time_out(state, game_id, entry, now).await
+}
+
+/// Records that the player holding `seat_token` wants the game paused, or
+/// resumed if it is paused. Once both players have asked, it happens.
+pub async fn request_pause(
+    state: &AppState,
+    game_id: Uuid,
+    seat_token: Option<&str>,
+    pause: bool,
+) -> Result<GameState, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let mut entry = game.lock().await;
+    let player = seat_token
+        .and_then(|token| entry.mode.seat_of(token))
+        .ok_or(Error::Forbidden(
+            "A valid seat token is required to pause or resume this game",
+        ))?;
+    if entry.state.status != GameStatus::InProgress {
+        return Err(Error::GameOver);
+    }
+    let mut updated = entry.clone();
+    let to_play = updated.state.to_play;
+    let Some(clock) = &mut updated.state.clock else {
+        return Err(Error::BadRequest("Only timed games can be paused"));
+    };
+    match (pause, clock.is_paused()) {
+        (true, true) => return Err(Error::InvalidMove("Game is already paused")),
+        (false, false) => return Err(Error::InvalidMove("Game is not paused")),
+        (true, false) if clock.running_since.is_none() => {
+            return Err(Error::InvalidMove("The clock has not started yet"));
+        }
+        _ => {}
+    }
+    let now = Utc::now();
+    match clock.pause_offer {
+        Some(offered_by) if offered_by == player => return Ok(updated.state),
+        Some(_) if pause => clock.pause(to_play, now),
+        Some(_) => clock.resume(now),
+        None => clock.pause_offer = Some(player),
+    }
+    let agreed = clock.pause_offer.is_none();
+    state
+        .store
+        .append_events(game_id, &[], &updated)
+        .await
+        .map_err(Error::Storage)?;
+    let game_state = updated.state;
+    *entry = updated;
+    drop(entry);
+
+    if agreed {
+        log::info!(
+            "Game {} {}",
+            game_id,
+            if pause { "paused" } else { "resumed" }
+        );
+    }
+    state.publish(game_id, GameEvent::PauseChanged);
+    Ok(game_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn control(initial_secs: u64, increment_secs: u64, mode: IncrementMode) -> TimeControl {
+        TimeControl {
+            initial_secs,
+            increment_secs,
+            mode,
+            on_timeout: TimeoutAction::Forfeit,
+        }
+    }
+
+    #[test]
+    fn test_fischer_adds_the_increment_after_each_move() {
+        let start = Utc::now();
+        let mut clock = Clock::new(control(60, 2, IncrementMode::Fischer));
+        // The first move is free, but still earns the increment.
+        clock.punch(Player::X, start).unwrap();
+        assert_eq!(clock.x_ms, 62_000);
+
+        let later = start + TimeDelta::seconds(10);
+        assert_eq!(clock.remaining_ms(Player::O, Player::O, later), 50_000);
+        assert_eq!(clock.remaining_ms(Player::X, Player::O, later), 62_000);
+        clock.punch(Player::O, later).unwrap();
+        assert_eq!(clock.o_ms, 52_000);
+
+        let too_late = later + TimeDelta::seconds(63);
+        assert!(clock.is_flagged(Player::X, too_late));
+        assert_eq!(clock.punch(Player::X, too_late), Err(Flagged));
+        assert_eq!(clock.x_ms, 62_000);
+    }
+
+    #[test]
+    fn test_delay_only_charges_time_past_the_delay() {
+        let start = Utc::now();
+        let mut clock = Clock::new(control(60, 5, IncrementMode::Delay));
+        clock.punch(Player::X, start).unwrap();
+        assert_eq!(clock.x_ms, 60_000);
+
+        clock
+            .punch(Player::O, start + TimeDelta::seconds(3))
+            .unwrap();
+        assert_eq!(clock.o_ms, 60_000);
+        clock
+            .punch(Player::X, start + TimeDelta::seconds(3 + 12))
+            .unwrap();
+        assert_eq!(clock.x_ms, 53_000);
+
+        // With nothing left, the delay still covers each move.
+        let now = start + TimeDelta::seconds(100);
+        clock.lapse(Player::O, now);
+        assert_eq!(clock.o_ms, 0);
+        assert!(!clock.is_flagged(Player::O, now + TimeDelta::seconds(5)));
+        assert!(clock.is_flagged(Player::O, now + TimeDelta::seconds(6)));
+    }
+
+    #[test]
+    fn test_timeout_actions() {
+        let start = Utc::now();
+        let late = start + TimeDelta::seconds(11);
+        let timed = |on_timeout| {
+            let mut game_state = GameState::default();
+            let mut clock = Clock::new(TimeControl {
+                on_timeout,
+                ..control(10, 1, IncrementMode::Fischer)
+            });
+            clock.punch(Player::X, start).unwrap();
+            try_move(&mut game_state, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+            game_state.clock = Some(clock);
+            game_state
+        };
+
+        let mut game_state = timed(TimeoutAction::Forfeit);
+        assert_eq!(apply_timeout(&mut game_state, late).unwrap(), None);
+        assert_eq!(game_state.status, GameStatus::Win(Player::X));
+
+        let mut game_state = timed(TimeoutAction::Draw);
+        apply_timeout(&mut game_state, late).unwrap();
+        assert_eq!(game_state.status, GameStatus::Draw);
+        assert_eq!(game_state.clock.unwrap().running_since, None);
+
+        let mut game_state = timed(TimeoutAction::RandomMove);
+        let random_move = apply_timeout(&mut game_state, late).unwrap().unwrap();
+        assert_ne!(random_move, PlayerMove { row: 1, col: 1 });
+        assert_eq!(
+            (game_state.status, game_state.to_play),
+            (GameStatus::InProgress, Player::X)
+        );
+        let clock = game_state.clock.unwrap();
+        assert_eq!((clock.o_ms, clock.running_since), (1000, Some(late)));
+    }
+
+    #[test]
+    fn test_paused_clocks_do_not_run() {
+        let start = Utc::now();
+        let mut clock = Clock::new(control(60, 0, IncrementMode::Fischer));
+        clock.punch(Player::X, start).unwrap();
+        clock.pause(Player::O, start + TimeDelta::seconds(5));
+        assert_eq!(clock.o_ms, 55_000);
+
+        // A week later, O has lost nothing more and is not out of time.
+        let later = start + TimeDelta::days(7);
+        assert!(clock.is_paused());
+        assert!(!clock.is_flagged(Player::O, later));
+        assert_eq!(clock.remaining_ms(Player::O, Player::O, later), 55_000);
+
+        clock.resume(later);
+        assert_eq!(
+            clock.remaining_ms(Player::O, Player::O, later + TimeDelta::seconds(5)),
+            50_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_claims_are_checked_against_the_clock() {
+        use std::sync::Arc;
+
+        use crate::{
+            state::{GameRegistry, Seat},
+            store::MemoryStore,
+        };
+
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let seat = |name: &str| Seat {
+            name: name.to_string(),
+            token: name.to_string(),
+        };
+        let claim = |game_id, token| claim_timeout(&state, game_id, Some(token));
+        let mut entry = GameEntry::pvp(seat("ada"), seat("bob"));
+        let mut clock = Clock::new(control(10, 0, IncrementMode::Fischer));
+        clock.punch(Player::X, Utc::now()).unwrap();
+        try_move(&mut entry.state, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+        entry.state.clock = Some(clock);
+        let game_id = crate::create_pvp_game(&state, entry).await.unwrap();
+
+        assert!(matches!(
+            claim(game_id, "ada").await,
+            Err(Error::InvalidMove("Your opponent still has time left"))
+        ));
+        assert!(matches!(
+            claim(game_id, "bob").await,
+            Err(Error::InvalidMove(_))
+        ));
+
+        // Wind O's clock back past the end of their time.
+        let game = state.game(&game_id).unwrap();
+        if let Some(clock) = &mut game.lock().await.state.clock {
+            clock.running_since = Some(Utc::now() - TimeDelta::seconds(11));
+        }
+        let game_state = claim(game_id, "ada").await.unwrap();
+        assert_eq!(game_state.status, GameStatus::Win(Player::X));
+        assert_eq!(game_state.ending, Some(Ending::OnTime));
+    }
+}
@@ -0,0 +1,406 @@
+//! Ownership of games when several instances share one store.
+//!
+//! Any instance can serve reads from its own copy of a game, which it loads
+//! from the store when it has none and reloads once the copy is older than a
+//! lease. With `cluster.advertise_url` set, though, only one instance at a
+//! time changes a game: the one holding the game's lease in the store. Requests that would change a
+//! game elsewhere are redirected to the owner with `307 Temporary Redirect`,
+//! which keeps the method and body. Writing instances renew their leases as
+//! they go, so a game stays with one instance while it is being played and
+//! moves on freely once it goes quiet or its owner shuts down.
+
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{Error, config::ClusterConfig, state::AppState};
+
+/// The right of one instance to change a game until `expires_at`.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub game_id: Uuid,
+    /// The instance holding the lease.
+    pub owner: Uuid,
+    /// Where the owner takes requests.
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Which instance may change a game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Owner {
+    /// This one.
+    Local,
+    /// The instance at this base URL.
+    Remote(String),
+}
+
+pub struct Cluster {
+    config: ClusterConfig,
+    /// Identifies this instance's leases; new on every start.
+    instance_id: Uuid,
+    /// When the leases this instance holds run out, by game.
+    held: DashMap<Uuid, DateTime<Utc>>,
+    /// When games this instance doesn't own were last loaded for reading.
+    fetched: DashMap<Uuid, DateTime<Utc>>,
+}
+
+impl Cluster {
+    pub fn new(config: ClusterConfig) -> Self {
+        Self {
+            config,
+            instance_id: Uuid::new_v4(),
+            held: DashMap::new(),
+            fetched: DashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.advertise_url.is_some()
+    }
+
+    /// Drops leases that have run out, so games no longer played here don't
+    /// pile up, along with read copies due a reload anyway. Returns how many
+    /// leases were dropped.
+    pub fn forget_expired(&self, now: DateTime<Utc>) -> usize {
+        let ttl = self.config.lease_ttl();
+        self.fetched.retain(|_, fetched_at| now - *fetched_at < ttl);
+        let before = self.held.len();
+        self.held.retain(|_, expires_at| *expires_at > now);
+        before - self.held.len()
+    }
+}
+
+/// Decides which instance may change `game_id`, taking or renewing the lease
+/// for this one if nobody else holds it. A game this instance takes over is
+/// reloaded from the store first, since its owner may have changed it.
+pub async fn owner(state: &AppState, game_id: Uuid) -> Result<Owner, Error> {
+    let cluster = &state.cluster;
+    let Some(url) = &cluster.config.advertise_url else {
+        return Ok(Owner::Local);
+    };
+    let now = Utc::now();
+    let ttl = cluster.config.lease_ttl();
+    let held_until = cluster.held.get(&game_id).map(|expires_at| *expires_at);
+    // Renewing on every write would cost a round trip to the store each
+    // time; halfway through the lease is soon enough.
+    if held_until.is_some_and(|expires_at| expires_at - now > ttl / 2) {
+        return Ok(Owner::Local);
+    }
+
+    // Leases are only taken for games that exist, so made-up IDs don't
+    // fill the store and `held` with them.
+    let known = state.game(&game_id).is_some() || state.archive.contains_key(&game_id);
+    if !known
+        && state
+            .store
+            .load_game(game_id)
+            .await
+            .map_err(Error::Storage)?
+            .is_none()
+    {
+        return Err(Error::GameNotFound(game_id));
+    }
+
+    let lease = state
+        .store
+        .acquire_lease(game_id, cluster.instance_id, url, ttl)
+        .await
+        .map_err(Error::Storage)?;
+    if lease.owner != cluster.instance_id {
+        cluster.held.remove(&game_id);
+        return Ok(Owner::Remote(lease.url));
+    }
+    if held_until.is_none_or(|expires_at| expires_at <= now) {
+        reload(state, game_id).await?;
+    }
+    cluster.held.insert(game_id, lease.expires_at);
+    Ok(Owner::Local)
+}
+
+/// Whether this instance may change `game_id` now, for background tasks. A
+/// failure to check counts as no; the task tries again on its next round.
+pub async fn is_local(state: &AppState, game_id: Uuid) -> bool {
+    match owner(state, game_id).await {
+        Ok(owner) => owner == Owner::Local,
+        Err(e) => {
+            log::error!("Failed to check who owns game {}: {}", game_id, e);
+            false
+        }
+    }
+}
+
+/// Replaces the local copy of a game with the stored one.
+async fn reload(state: &AppState, game_id: Uuid) -> Result<(), Error> {
+    let Some(stored) = state
+        .store
+        .load_game(game_id)
+        .await
+        .map_err(Error::Storage)?
+    else {
+        return Ok(());
+    };
+    match state.game(&game_id) {
+        Some(game) => *game.lock().await = stored,
+        None => {
+            state.games.insert(game_id, Arc::new(Mutex::new(stored)));
+        }
+    }
+    log::info!("Took over game {}", game_id);
+    Ok(())
+}
+
+/// Makes the local copy of `game_id` fit to read from. Games this instance
+/// owns are always current; any other game is loaded from the store if there
+/// is no copy here, or if the copy was loaded longer than a lease ago, since
+/// without pub/sub nothing else tells this instance about changes.
+pub async fn refresh(state: &AppState, game_id: Uuid) -> Result<(), Error> {
+    let cluster = &state.cluster;
+    if !cluster.is_enabled() {
+        return Ok(());
+    }
+    let now = Utc::now();
+    if cluster
+        .held
+        .get(&game_id)
+        .is_some_and(|expires_at| *expires_at > now)
+    {
+        return Ok(());
+    }
+    let local = state.game(&game_id);
+    let fresh = cluster
+        .fetched
+        .get(&game_id)
+        .is_some_and(|fetched_at| now - *fetched_at < cluster.config.lease_ttl());
+    if local.is_some() && fresh {
+        return Ok(());
+    }
+
+    let stored = state
+        .store
+        .load_game(game_id)
+        .await
+        .map_err(Error::Storage)?;
+    cluster.fetched.insert(game_id, now);
+    let Some(stored) = stored else {
+        return Ok(());
+    };
+    match local {
+        Some(game) => {
+            let mut local = game.lock().await;
+            // A relayed copy may already be further along than the store.
+            if stored.events.len() >= local.events.len() {
+                *local = stored;
+            }
+        }
+        None => {
+            state
+                .games
+                .entry(game_id)
+                .or_insert_with(|| Arc::new(Mutex::new(stored)));
+        }
+    }
+    Ok(())
+}
+
+/// Gives up this instance's leases on shutdown.
+pub async fn release(state: &AppState) {
+    if !state.cluster.is_enabled() {
+        return;
+    }
+    if let Err(e) = state.store.release_leases(state.cluster.instance_id).await {
+        log::error!("Failed to release game leases: {}", e);
+    }
+}
+
+/// Middleware that sends requests to change a game to the instance that
+/// owns it. Reads are served here, after `refresh`, and requests that aren't
+/// about a game always go through.
+pub async fn route_to_owner(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.cluster.is_enabled() {
+        return next.run(request).await;
+    }
+    let Some(game_id) = game_id_in(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    if matches!(*request.method(), Method::GET | Method::HEAD) {
+        if let Err(e) = refresh(&state, game_id).await {
+            log::error!(
+                "Failed to refresh game {}, serving the local copy: {}",
+                game_id,
+                e
+            );
+        }
+        return next.run(request).await;
+    }
+    match owner(&state, game_id).await {
+        Ok(Owner::Local) => next.run(request).await,
+        Ok(Owner::Remote(url)) => {
+            // As sent, with any tenant prefix.
+            let uri = match request.extensions().get::<OriginalUri>() {
+                Some(OriginalUri(uri)) => uri,
+                None => request.uri(),
+            };
+            let path = uri.path_and_query().map_or("", |path| path.as_str());
+            log::info!("Redirecting a write to game {} to {}", game_id, url);
+            Redirect::temporary(&format!("{}{}", url.trim_end_matches('/'), path)).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// The game a path such as `/api/v1/games/{game_id}/move`,
+/// `/embed/{game_id}` or `/g/{game_id}` is about.
+pub fn game_id_in(path: &str) -> Option<Uuid> {
+    let mut segments = path.split('/');
+    segments.find(|segment| matches!(*segment, "games" | "embed" | "g"))?;
+    segments.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::GameState,
+        state::{GameEntry, GameRegistry},
+        store::{GameStore, StoreError},
+    };
+    use async_trait::async_trait;
+    use chrono::TimeDelta;
+
+    /// A shared store where another instance already holds every lease.
+    struct TakenStore;
+
+    #[async_trait]
+    impl GameStore for TakenStore {
+        async fn load(&self, _finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError> {
+            Ok(GameRegistry::new())
+        }
+
+        async fn acquire_lease(
+            &self,
+            game_id: Uuid,
+            _owner: Uuid,
+            _url: &str,
+            ttl: TimeDelta,
+        ) -> Result<Lease, StoreError> {
+            Ok(Lease {
+                game_id,
+                owner: Uuid::nil(),
+                url: "http://other:3000".to_string(),
+                expires_at: Utc::now() + ttl,
+            })
+        }
+    }
+
+    /// A shared store holding one game, as another instance last saved it.
+    struct SharedStore(std::sync::Mutex<GameEntry>);
+
+    #[async_trait]
+    impl GameStore for SharedStore {
+        async fn load(&self, _finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError> {
+            Ok(GameRegistry::new())
+        }
+
+        async fn load_game(&self, _id: Uuid) -> Result<Option<GameEntry>, StoreError> {
+            Ok(Some(self.0.lock().unwrap().clone()))
+        }
+    }
+
+    fn clustered(store: Arc<dyn GameStore>) -> AppState {
+        AppState::new(GameRegistry::new(), store).with_cluster(ClusterConfig {
+            advertise_url: Some("http://me:3000".to_string()),
+            ..ClusterConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_games_leased_elsewhere_belong_to_that_instance() {
+        let game_id = Uuid::new_v4();
+        let state = clustered(Arc::new(TakenStore));
+        state.games.insert(
+            game_id,
+            Arc::new(Mutex::new(GameEntry::new(GameState::default()))),
+        );
+        assert_eq!(
+            owner(&state, game_id).await.unwrap(),
+            Owner::Remote("http://other:3000".to_string())
+        );
+
+        let state = clustered(Arc::new(crate::store::MemoryStore));
+        state.games.insert(
+            game_id,
+            Arc::new(Mutex::new(GameEntry::new(GameState::default()))),
+        );
+        assert_eq!(owner(&state, game_id).await.unwrap(), Owner::Local);
+        assert_eq!(state.cluster.forget_expired(Utc::now()), 0);
+        // A store with nothing to reload leaves the local copy alone.
+        assert!(state.game(&game_id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_no_lease_is_taken_for_unknown_games() {
+        let state = clustered(Arc::new(crate::store::MemoryStore));
+        let game_id = Uuid::new_v4();
+        assert!(matches!(
+            owner(&state, game_id).await,
+            Err(Error::GameNotFound(id)) if id == game_id
+        ));
+        assert!(state.cluster.held.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_games_changed_elsewhere_are_loaded_for_reading() {
+        let game_id = Uuid::new_v4();
+        let store = Arc::new(SharedStore(std::sync::Mutex::new(GameEntry::new(
+            GameState::default(),
+        ))));
+        let state = clustered(store.clone());
+        refresh(&state, game_id).await.unwrap();
+        assert_eq!(state.game(&game_id).unwrap().lock().await.move_count(), 0);
+
+        // The owner moves; a fresh copy is served until it is a lease old.
+        store.0.lock().unwrap().record_move(
+            crate::game::Player::X,
+            crate::game::PlayerMove { row: 0, col: 0 },
+        );
+        refresh(&state, game_id).await.unwrap();
+        assert_eq!(state.game(&game_id).unwrap().lock().await.move_count(), 0);
+        state
+            .cluster
+            .fetched
+            .insert(game_id, Utc::now() - state.cluster.config.lease_ttl());
+        refresh(&state, game_id).await.unwrap();
+        assert_eq!(state.game(&game_id).unwrap().lock().await.move_count(), 1);
+    }
+
+    #[test]
+    fn test_game_ids_are_found_in_any_api_version() {
+        let game_id = Uuid::new_v4();
+        assert_eq!(
+            game_id_in(&format!("/api/v1/games/{game_id}/move")),
+            Some(game_id)
+        );
+        assert_eq!(
+            game_id_in(&format!("/admin/games/{game_id}")),
+            Some(game_id)
+        );
+        assert_eq!(game_id_in(&format!("/embed/{game_id}")), Some(game_id));
+        assert_eq!(game_id_in(&format!("/g/{game_id}")), Some(game_id));
+        assert_eq!(game_id_in("/api/games/import"), None);
+        assert_eq!(game_id_in("/api/v1/newgame"), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Content negotiation for game endpoints: JSON by default, MessagePack or
+//! CBOR when the client asks for them.
+//!
+//! Responses follow the `Accept` header and request bodies follow
+//! `Content-Type`, so bots can use the compact formats end to end.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, OptionalFromRequest, Request},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{ACCEPT, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY},
+        request::Parts,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        let essence = media_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// The first supported format listed in `Accept`, or JSON if none is.
+    fn from_accept(headers: &HeaderMap) -> Format {
+        headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(Format::from_media_type)
+            .unwrap_or_default()
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            // Named fields, so the payload mirrors the JSON shape.
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(|e| e.to_string())?;
+                Ok(buf)
+            }
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The response format the client asked for.
+pub struct Accept(pub Format);
+
+impl<S: Send + Sync> FromRequestParts<S> for Accept {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Accept(Format::from_accept(&parts.headers)))
+    }
+}
+
+/// A response body encoded in the negotiated format.
+pub struct Encoded<T>(pub Format, pub T);
+
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        let Encoded(format, value) = self;
+        match format.encode(&value) {
+            Ok(body) => (
+                [(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static(format.content_type()),
+                )],
+                body,
+            )
+                .into_response(),
+            Err(e) => {
+                log::error!("Failed to encode response as {:?}: {}", format, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// `value` encoded like `Encoded`, with a strong `ETag` of its `version` and
+/// a digest of the bytes sent. Draw offers, clocks, and endings change a game
+/// without changing its version, so the digest tells those apart, along with
+/// the format. A request whose `If-None-Match` already has the tag gets
+/// `304 Not Modified` without the body.
+pub fn tagged<T: Serialize>(
+    format: Format,
+    version: u64,
+    value: &T,
+    request_headers: &HeaderMap,
+) -> Response {
+    let body = match format.encode(value) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("Failed to encode response as {:?}: {}", format, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let digest: String = Sha256::digest(&body)[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let etag = format!("\"{}-{}\"", version, digest);
+    let cached = request_headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        // `If-None-Match` compares weakly, so `W/` makes no difference.
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag);
+    let headers = [
+        (ETAG, HeaderValue::from_str(&etag).expect("tags are ASCII")),
+        (VARY, HeaderValue::from_static("accept")),
+    ];
+    if cached {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    let content_type = [(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    )];
+    (headers, content_type, body).into_response()
+}
+
+/// A request body decoded according to its `Content-Type`. JSON bodies are
+/// handled exactly like axum's `Json` extractor.
+pub struct Decoded<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Decoded<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Format::from_media_type);
+        match format {
+            Some(format @ (Format::MessagePack | Format::Cbor)) => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                format
+                    .decode(&bytes)
+                    .map(Decoded)
+                    .map_err(|e| crate::Error::InvalidBody(e).into_response())
+            }
+            _ => <Json<T> as FromRequest<S>>::from_request(req, state)
+                .await
+                .map(|Json(value)| Decoded(value))
+                .map_err(IntoResponse::into_response),
+        }
+    }
+}
+
+/// Like `Option<Json<T>>`: a request without a `Content-Type` has no body to
+/// decode.
+impl<T: DeserializeOwned, S: Send + Sync> OptionalFromRequest<S> for Decoded<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        if !req.headers().contains_key(CONTENT_TYPE) {
+            return Ok(None);
+        }
+        <Self as FromRequest<S>>::from_request(req, state)
+            .await
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MoveRequest,
+        game::{GameState, PlayerMove},
+    };
+
+    #[test]
+    fn test_accept_picks_the_first_supported_format() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Format::from_accept(&headers), Format::Json);
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html, application/cbor;q=0.9, */*"),
+        );
+        assert_eq!(Format::from_accept(&headers), Format::Cbor);
+
+        headers.insert(ACCEPT, HeaderValue::from_static("application/x-msgpack"));
+        assert_eq!(Format::from_accept(&headers), Format::MessagePack);
+    }
+
+    #[test]
+    fn test_binary_formats_round_trip_game_types() {
+        let move_request = MoveRequest {
+            player_move: PlayerMove { row: 2, col: 1 },
+            expected_version: Some(4),
+        };
+        for format in [Format::MessagePack, Format::Cbor] {
+            let bytes = format.encode(&move_request).unwrap();
+            let decoded: MoveRequest = format.decode(&bytes).unwrap();
+            assert_eq!(decoded, move_request);
+
+            let game_state = GameState::default();
+            let bytes = format.encode(&game_state).unwrap();
+            assert!(bytes.len() < serde_json::to_vec(&game_state).unwrap().len());
+            assert_eq!(format.decode::<GameState>(&bytes).unwrap(), game_state);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_bodies_get_an_error_body() {
+        let request = Request::builder()
+            .header(CONTENT_TYPE, "application/cbor")
+            .body(axum::body::Body::from(vec![0xff, 0x00]))
+            .unwrap();
+        let response = <Decoded<MoveRequest> as FromRequest<()>>::from_request(request, &())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response
+            .extensions()
+            .get::<laika_types::ErrorBody>()
+            .unwrap();
+        assert_eq!(body.code, "invalid_body");
+        assert!(body.details.as_ref().unwrap()["reason"].is_string());
+    }
+
+    #[test]
+    fn test_a_tagged_response_is_not_sent_again() {
+        let mut game_state = GameState::default();
+        let first = tagged(Format::Json, 0, &game_state, &HeaderMap::new());
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("\"0-"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag.clone());
+        let again = tagged(Format::Json, 0, &game_state, &headers);
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(again.headers()[ETAG], etag);
+
+        // Neither another format nor a change that keeps the version match.
+        let cbor = tagged(Format::Cbor, 0, &game_state, &headers);
+        assert_eq!(cbor.status(), StatusCode::OK);
+        game_state.draw_offer = Some(crate::game::Player::X);
+        let offered = tagged(Format::Json, 0, &game_state, &headers);
+        assert_eq!(offered.status(), StatusCode::OK);
+        assert_ne!(offered.headers()[ETAG], etag);
+    }
+}
@@ -0,0 +1,332 @@
+//! Playing against the AI from Discord.
+//!
+//! Discord posts every interaction with the application to one endpoint. A
+//! slash command starts a game and answers with the board as a grid of
+//! buttons. Pressing one plays that cell for whoever started the game, and
+//! the answer replaces the message with the board after the AI's reply.
+//! Cells already played, and every cell once the game is over, are greyed
+//! out. Anyone else who presses a button is told the game isn't theirs.
+//!
+//! Interactions are signed with ed25519 over the timestamp and body, and
+//! are checked against the application's public key. Discord probes the
+//! endpoint with bad signatures, and refuses to use it unless those fail.
+
+use chrono::{DateTime, Utc};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::{
+    Error, MoveRequest,
+    config::DiscordConfig,
+    game::{Cell, GameState, GameStatus, Player, PlayerMove},
+    state::AppState,
+};
+
+pub const SIGNATURE_HEADER: &str = "x-signature-ed25519";
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+/// How old a signed interaction may be.
+const MAX_AGE_SECS: i64 = 5 * 60;
+
+// Interaction and response types, and message flags, from Discord's API.
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+const MESSAGE_COMPONENT: u8 = 3;
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE: u8 = 4;
+const UPDATE_MESSAGE: u8 = 7;
+const EPHEMERAL: u32 = 1 << 6;
+// Components, and button styles.
+const ACTION_ROW: u8 = 1;
+const BUTTON: u8 = 2;
+const PRIMARY: u8 = 1;
+const SECONDARY: u8 = 2;
+const DANGER: u8 = 4;
+
+#[derive(Debug, Default)]
+pub struct Discord {
+    public_key: Option<Vec<u8>>,
+}
+
+impl Discord {
+    pub fn new(config: &DiscordConfig) -> Self {
+        Self {
+            // Already checked by `Config::validate`.
+            public_key: config.public_key.as_deref().and_then(from_hex),
+        }
+    }
+
+    /// Checks that Discord sent `body`: `signature` must be the hex ed25519
+    /// signature of the timestamp followed by the body, and `timestamp`
+    /// must be recent.
+    pub fn verify(
+        &self,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let public_key = self
+            .public_key
+            .as_deref()
+            .ok_or(Error::Unauthorized("Discord isn't set up on this server"))?;
+        let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+            return Err(Error::Unauthorized("The Discord signature is missing"));
+        };
+        let signed_at: i64 = timestamp
+            .parse()
+            .map_err(|_| Error::Unauthorized("The Discord signature is invalid"))?;
+        if (now.timestamp() - signed_at).abs() > MAX_AGE_SECS {
+            return Err(Error::Unauthorized("The Discord signature has expired"));
+        }
+        let signature =
+            from_hex(signature).ok_or(Error::Unauthorized("The Discord signature is invalid"))?;
+        let message = [timestamp.as_bytes(), body].concat();
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(&message, &signature)
+            .map_err(|_| Error::Unauthorized("The Discord signature is invalid"))
+    }
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// An interaction, with only the fields used here.
+#[derive(Debug, Deserialize)]
+pub struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    data: Option<InteractionData>,
+    /// Set in servers.
+    member: Option<Member>,
+    /// Set in direct messages.
+    user: Option<DiscordUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InteractionData {
+    custom_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    user: DiscordUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordUser {
+    id: String,
+}
+
+impl Interaction {
+    fn user_id(&self) -> Option<&str> {
+        self.member
+            .as_ref()
+            .map(|member| &member.user)
+            .or(self.user.as_ref())
+            .map(|user| user.id.as_str())
+    }
+}
+
+/// A cell's button: which game, which cell, and who may press it, as
+/// `{game_id}:{row}:{col}:{user_id}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CellButton {
+    game_id: Uuid,
+    row: usize,
+    col: usize,
+    player: String,
+}
+
+impl CellButton {
+    fn custom_id(&self) -> String {
+        format!("{}:{}:{}:{}", self.game_id, self.row, self.col, self.player)
+    }
+
+    fn parse(custom_id: &str) -> Option<Self> {
+        let mut parts = custom_id.split(':');
+        let button = CellButton {
+            game_id: parts.next()?.parse().ok()?,
+            row: parts.next()?.parse().ok()?,
+            col: parts.next()?.parse().ok()?,
+            player: parts.next()?.to_string(),
+        };
+        parts.next().is_none().then_some(button)
+    }
+}
+
+/// A message only the user who used the command or button sees.
+fn ephemeral(content: &str) -> Value {
+    json!({
+        "type": CHANNEL_MESSAGE,
+        "data": { "content": content, "flags": EPHEMERAL },
+    })
+}
+
+/// The game's message: who is playing and where the game stands, over a
+/// row of buttons per board row. Discord allows five rows of five buttons,
+/// more than the 3x3 games started here need.
+fn game_message(game_id: Uuid, game_state: &GameState, player: &str) -> Value {
+    let over = game_state.status != GameStatus::InProgress;
+    let playing = if over { "played" } else { "is playing" };
+    let content = format!(
+        "<@{player}> {playing} X against the AI: **{}**",
+        game_state.summary()
+    );
+    let components: Vec<Value> = game_state
+        .board
+        .rows()
+        .into_iter()
+        .enumerate()
+        .map(|(row, cells)| {
+            let buttons: Vec<Value> = cells
+                .into_iter()
+                .enumerate()
+                .map(|(col, cell)| {
+                    let (label, style) = match cell {
+                        Cell::Empty => ("·", SECONDARY),
+                        Cell::Occupied(Player::X) => ("X", PRIMARY),
+                        Cell::Occupied(Player::O) => ("O", DANGER),
+                        Cell::Blocked => ("#", SECONDARY),
+                    };
+                    let button = CellButton {
+                        game_id,
+                        row,
+                        col,
+                        player: player.to_string(),
+                    };
+                    json!({
+                        "type": BUTTON,
+                        "style": style,
+                        "label": label,
+                        "custom_id": button.custom_id(),
+                        "disabled": over || cell != Cell::Empty,
+                    })
+                })
+                .collect();
+            json!({ "type": ACTION_ROW, "components": buttons })
+        })
+        .collect();
+    json!({ "content": content, "components": components })
+}
+
+/// Answers an interaction: a ping, a slash command, which starts a game, or
+/// the press of a cell's button, which plays it.
+pub async fn interact(state: &AppState, interaction: Interaction) -> Value {
+    let user_id = interaction.user_id().unwrap_or_default();
+    match interaction.kind {
+        PING => json!({ "type": PONG }),
+        APPLICATION_COMMAND => {
+            match crate::create_game(state, None, GameState::default(), None).await {
+                Ok((game_id, game_state)) => {
+                    log::info!("Started Discord game {} for {}", game_id, user_id);
+                    json!({
+                        "type": CHANNEL_MESSAGE,
+                        "data": game_message(game_id, &game_state, user_id),
+                    })
+                }
+                Err(e) => ephemeral(&e.to_string()),
+            }
+        }
+        MESSAGE_COMPONENT => {
+            let Some(button) = interaction
+                .data
+                .as_ref()
+                .and_then(|data| data.custom_id.as_deref())
+                .and_then(CellButton::parse)
+            else {
+                return ephemeral("That button doesn't belong to a game.");
+            };
+            if user_id != button.player {
+                return ephemeral(&format!("Only <@{}> can play this game.", button.player));
+            }
+            let move_request = MoveRequest {
+                player_move: PlayerMove {
+                    row: button.row,
+                    col: button.col,
+                },
+                expected_version: None,
+            };
+            match crate::play_move(state, button.game_id, move_request, None, None).await {
+                Ok(game_state) => json!({
+                    "type": UPDATE_MESSAGE,
+                    "data": game_message(button.game_id, &game_state, &button.player),
+                }),
+                Err(e) => ephemeral(&e.to_string()),
+            }
+        }
+        _ => ephemeral("Only slash commands and the board's buttons are supported."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    use super::*;
+    use crate::game::try_move;
+
+    #[test]
+    fn test_interactions_need_a_signature_and_games_render_as_buttons() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key: String = key_pair
+            .public_key()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let discord = Discord::new(&DiscordConfig {
+            public_key: Some(public_key),
+        });
+        let now = Utc::now();
+        let timestamp = now.timestamp().to_string();
+        let body = br#"{"type":1}"#;
+        let signature: String = key_pair
+            .sign(&[timestamp.as_bytes(), body].concat())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let verify =
+            |body: &[u8], now| discord.verify(Some(&timestamp), Some(&signature), body, now);
+        assert!(verify(body, now).is_ok());
+        assert!(verify(br#"{"type":2}"#, now).is_err());
+        assert!(verify(body, now + chrono::TimeDelta::minutes(6)).is_err());
+        assert!(
+            Discord::default()
+                .verify(Some(&timestamp), Some(&signature), body, now)
+                .is_err()
+        );
+
+        let mut game_state = GameState::default();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 0, col: 2 }).unwrap();
+        let game_id = Uuid::new_v4();
+        let message = game_message(game_id, &game_state, "80351110224678912");
+        let played = &message["components"][0]["components"][2];
+        assert_eq!(played["label"], "X");
+        assert_eq!(played["disabled"], true);
+        assert_eq!(message["components"][1]["components"][0]["disabled"], false);
+        assert_eq!(
+            CellButton::parse(played["custom_id"].as_str().unwrap()),
+            Some(CellButton {
+                game_id,
+                row: 0,
+                col: 2,
+                player: "80351110224678912".to_string(),
+            })
+        );
+        assert_eq!(CellButton::parse("not-a-game:0:0:1"), None);
+    }
+}
@@ -0,0 +1,197 @@
+//! Draining the server before a restart, for rolling deploys.
+//!
+//! A drain is started by `POST /admin/drain` or by sending the process
+//! `SIGUSR1`. From then on, starting a game fails with `503 Service
+//! Unavailable`, so the load balancer sends new players to another
+//! instance, while games already underway carry on as usual. Once none are
+//! left, or `server.drain_grace_secs` have passed, the server shuts down the
+//! same way it does on `SIGTERM`.
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{game::GameStatus, state::AppState};
+
+/// How often a drain checks whether games are still underway.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Started {
+    pub at: DateTime<Utc>,
+    /// When the server shuts down even if games are still underway.
+    pub deadline: DateTime<Utc>,
+    pub games_at_start: usize,
+}
+
+#[derive(Debug)]
+pub struct Drain {
+    grace: TimeDelta,
+    started: watch::Sender<Option<Started>>,
+}
+
+impl Drain {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace: TimeDelta::from_std(grace).unwrap_or(TimeDelta::MAX),
+            started: watch::Sender::new(None),
+        }
+    }
+
+    pub fn started(&self) -> Option<Started> {
+        *self.started.borrow()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.started.borrow().is_some()
+    }
+}
+
+/// Where a drain is at, as reported by `GET /admin/drain`.
+#[derive(Debug, Serialize)]
+pub struct Progress {
+    pub draining: bool,
+    #[serde(flatten)]
+    pub started: Option<Started>,
+    pub games_in_progress: usize,
+}
+
+/// Games that haven't finished yet, in any tenant. A game locked by a move
+/// being made counts as underway.
+fn games_in_progress(state: &AppState) -> usize {
+    state
+        .games
+        .iter()
+        .filter(|game| match game.try_lock() {
+            Ok(entry) => entry.state.status == GameStatus::InProgress,
+            Err(_) => true,
+        })
+        .count()
+}
+
+pub fn progress(state: &AppState) -> Progress {
+    let started = state.drain.started();
+    Progress {
+        draining: started.is_some(),
+        started,
+        games_in_progress: games_in_progress(state),
+    }
+}
+
+/// Starts draining, unless a drain is already underway. Returns the drain
+/// if this started it.
+pub fn start(state: &AppState) -> Option<Started> {
+    let now = Utc::now();
+    let started = Started {
+        at: now,
+        deadline: now.checked_add_signed(state.drain.grace).unwrap_or(now),
+        games_at_start: games_in_progress(state),
+    };
+    let started_now = state.drain.started.send_if_modified(|current| {
+        if current.is_some() {
+            return false;
+        }
+        *current = Some(started);
+        true
+    });
+    if !started_now {
+        return None;
+    }
+    log::warn!(
+        "Draining: no new games will be started; shutting down once {} games in progress finish, or at {}",
+        started.games_at_start,
+        started.deadline
+    );
+    Some(started)
+}
+
+/// Whether the drain is over as of `now`: every game has finished, or the
+/// grace period is up.
+fn is_over(state: &AppState, started: &Started, now: DateTime<Utc>) -> bool {
+    let remaining = games_in_progress(state);
+    if remaining == 0 {
+        log::info!("Drained: every game has finished");
+        return true;
+    }
+    if now >= started.deadline {
+        log::warn!(
+            "Drain grace period is up with {} games still in progress",
+            remaining
+        );
+        return true;
+    }
+    false
+}
+
+/// Resolves once a drain has been started and is over; never, otherwise.
+pub async fn drained(state: AppState) {
+    let mut receiver = state.drain.started.subscribe();
+    let Some(started) = receiver
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|started| *started)
+    else {
+        return std::future::pending().await;
+    };
+    while !is_over(&state, &started, Utc::now()) {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Starts a drain whenever the process receives `SIGUSR1`.
+#[cfg(unix)]
+pub async fn start_on_signal(state: AppState) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Failed to install SIGUSR1 handler: {}", e);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        log::info!("SIGUSR1 received");
+        start(&state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        Error,
+        game::GameState,
+        state::{GameEntry, GameRegistry},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_draining_refuses_new_games_until_the_last_one_finishes() {
+        let game_id = Uuid::new_v4();
+        let mut registry = GameRegistry::new();
+        registry.insert(game_id, GameEntry::new(GameState::default()));
+        let state =
+            AppState::new(registry, Arc::new(MemoryStore)).with_drain(Duration::from_secs(60));
+        assert!(state.accepting_games().is_ok());
+
+        let started = start(&state).unwrap();
+        assert_eq!(started.games_at_start, 1);
+        assert!(start(&state).is_none(), "already draining");
+        assert!(matches!(state.accepting_games(), Err(Error::Draining)));
+        assert!(!is_over(&state, &started, started.at));
+        assert!(is_over(&state, &started, started.deadline));
+
+        let game = state.game(&game_id).unwrap();
+        game.lock().await.state.status = GameStatus::Draw;
+        assert!(is_over(&state, &started, started.at));
+        assert_eq!(progress(&state).games_in_progress, 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! Draw offers in player-vs-player games.
+//!
+//! Either player can offer a draw at any point. The offer stays on the game,
+//! where the opponent's client can show it, until the opponent accepts it,
+//! declines it, or makes a move, which counts as declining.
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::{Ending, GameState, GameStatus, Player},
+    state::{AppState, GameEntry, GameEvent, GameMode},
+};
+
+/// What a player is doing about a draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawAction {
+    Offer,
+    Accept,
+    Decline,
+}
+
+/// Applies `action` for `player`, returning whether it ended the game.
+fn apply(entry: &mut GameEntry, player: Player, action: DrawAction) -> Result<bool, Error> {
+    if entry.state.status != GameStatus::InProgress {
+        return Err(Error::GameOver);
+    }
+    let offered_by_opponent = entry.state.draw_offer == Some(player.opponent());
+    match action {
+        // Offering a draw the opponent has already offered agrees to it.
+        DrawAction::Offer | DrawAction::Accept if offered_by_opponent => {
+            entry.state.status = GameStatus::Draw;
+            entry.state.ending = Some(Ending::ByAgreement);
+            entry.state.draw_offer = None;
+            if let Some(clock) = &mut entry.state.clock {
+                clock.stop(entry.state.to_play, Utc::now());
+            }
+            entry.finish(Utc::now());
+            Ok(true)
+        }
+        DrawAction::Offer => {
+            entry.state.draw_offer = Some(player);
+            Ok(false)
+        }
+        DrawAction::Accept | DrawAction::Decline if !offered_by_opponent => {
+            Err(Error::InvalidMove("There is no draw offer to answer"))
+        }
+        DrawAction::Decline => {
+            entry.state.draw_offer = None;
+            Ok(false)
+        }
+        DrawAction::Accept => unreachable!("accepting an opponent's offer is handled above"),
+    }
+}
+
+// --- Operations ---
+
+/// Offers, accepts, or declines a draw for whoever holds `seat_token`.
+pub async fn respond(
+    state: &AppState,
+    game_id: Uuid,
+    seat_token: Option<&str>,
+    action: DrawAction,
+) -> Result<GameState, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let mut entry = game.lock().await;
+    if !matches!(entry.mode, GameMode::Pvp { .. }) {
+        return Err(Error::BadRequest(
+            "Draws can only be offered in player-vs-player games",
+        ));
+    }
+    let player = seat_token
+        .and_then(|token| entry.mode.seat_of(token))
+        .ok_or(Error::Forbidden(
+            "A valid seat token is required to offer or answer a draw",
+        ))?;
+
+    let mut updated = entry.clone();
+    let first_new_event = updated.events.len();
+    let finished = apply(&mut updated, player, action)?;
+    state
+        .store
+        .append_events(game_id, &updated.events[first_new_event..], &updated)
+        .await
+        .map_err(Error::Storage)?;
+    let game_state = updated.state;
+    let (tournament_id, match_id, arena_id) =
+        (updated.tournament_id, updated.match_id, updated.arena_id);
+    *entry = updated;
+    drop(entry);
+
+    if finished {
+        log::info!("Game {} drawn by agreement", game_id);
+        state.publish(game_id, GameEvent::Moved { finished: true });
+        crate::game_finished(
+            state,
+            game_id,
+            tournament_id,
+            match_id,
+            arena_id,
+            game_state.status,
+        )
+        .await;
+    } else {
+        state.publish(game_id, GameEvent::DrawOffer);
+    }
+    Ok(game_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Seat;
+
+    #[test]
+    fn test_draw_offers() {
+        let seat = |name: &str| Seat {
+            name: name.to_string(),
+            token: name.to_string(),
+        };
+        let mut entry = GameEntry::pvp(seat("ada"), seat("bob"));
+        assert!(matches!(
+            apply(&mut entry, Player::O, DrawAction::Accept),
+            Err(Error::InvalidMove(_))
+        ));
+
+        assert!(!apply(&mut entry, Player::X, DrawAction::Offer).unwrap());
+        assert_eq!(entry.state.draw_offer, Some(Player::X));
+        // X can't accept their own offer.
+        assert!(apply(&mut entry, Player::X, DrawAction::Accept).is_err());
+        assert!(!apply(&mut entry, Player::O, DrawAction::Decline).unwrap());
+        assert_eq!(entry.state.draw_offer, None);
+
+        apply(&mut entry, Player::O, DrawAction::Offer).unwrap();
+        assert!(apply(&mut entry, Player::X, DrawAction::Accept).unwrap());
+        assert_eq!(entry.state.status, GameStatus::Draw);
+        assert_eq!(entry.state.ending, Some(Ending::ByAgreement));
+        assert!(entry.finished_at.is_some());
+    }
+}
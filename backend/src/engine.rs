@@ -0,0 +1,570 @@
+//! The AI opponent: an exhaustive minimax search over the bitboard.
+//!
+//! Larger boards have too many positions for that, so while more than nine
+//! cells are empty the engine searches to a limited depth instead, scoring
+//! the positions where it stops by the lines each side could still complete.
+
+use std::time::Instant;
+
+use clap::ValueEnum;
+use rand::{Rng, seq::IteratorRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error,
+    game::{Cell, GameState, GameStatus, Player, PlayerMove, try_move},
+    metrics,
+};
+
+/// The engines a seat can be played by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    /// Perfect play via exhaustive minimax.
+    #[default]
+    Minimax,
+    /// Uniformly random legal moves.
+    Random,
+}
+
+impl EngineKind {
+    pub const ALL: [EngineKind; 2] = [EngineKind::Minimax, EngineKind::Random];
+
+    /// Picks a move for the player to move, or `None` if the game is over.
+    pub fn choose_move(self, game_state: &GameState) -> Option<PlayerMove> {
+        self.search(game_state).0
+    }
+
+    /// Like `choose_move`, but also reports how much work the search did.
+    pub fn search(self, game_state: &GameState) -> (Option<PlayerMove>, SearchStats) {
+        let mut stats = SearchStats::default();
+        if game_state.status != GameStatus::InProgress {
+            return (None, stats);
+        }
+        let player_move = match self {
+            EngineKind::Minimax => search_counted(game_state, &mut stats).1,
+            EngineKind::Random => {
+                // Every legal move is a candidate; none is searched further.
+                stats.nodes = game_state.board.empty_cells().count() as u64;
+                stats.depth = 1;
+                game_state
+                    .board
+                    .empty_cells()
+                    .choose(&mut rand::rng())
+                    .map(|(row, col)| PlayerMove { row, col })
+            }
+        };
+        (player_move, stats)
+    }
+}
+
+/// How the engine picks among moves that search says are equally good.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// The first best move found, so the same position always gets the
+    /// same reply.
+    #[default]
+    Standard,
+    /// Any of the best moves, at random, so games vary.
+    RandomTieBreak,
+}
+
+/// Counters collected while searching, for benchmarks and diagnostics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Positions visited, including the root.
+    pub nodes: u64,
+    /// Plies searched ahead: to the end of the game for exhaustive search,
+    /// or the deepest pass of the depth-limited one.
+    pub depth: u32,
+}
+
+/// Exhaustive search is used once at most this many cells are empty.
+const EXHAUSTIVE_CELLS: usize = 9;
+
+/// Roughly how many positions the depth-limited search visits per move.
+const NODE_BUDGET: u64 = 200_000;
+
+/// The score of a win found by the depth-limited search, before the bonus
+/// for finding it sooner. Heuristic scores stay below it.
+const HEURISTIC_WIN: i32 = 1000;
+
+/// Scores a position from X's point of view: 10 for an X win, -10 for an O
+/// win, 0 for a draw, along with the move that gets there. While the
+/// depth-limited search is in use, wins score above `HEURISTIC_WIN` instead
+/// and anything in between is a heuristic estimate.
+#[cfg(test)]
+pub fn minimax(game_state: &GameState) -> (i32, Option<PlayerMove>) {
+    search_counted(game_state, &mut SearchStats::default())
+}
+
+fn search_counted(game_state: &GameState, stats: &mut SearchStats) -> (i32, Option<PlayerMove>) {
+    let empty = game_state.board.empty_cells().count();
+    if empty <= EXHAUSTIVE_CELLS {
+        stats.depth = stats.depth.max(empty as u32);
+        return minimax_counted(game_state, stats);
+    }
+
+    // Deepen one ply at a time until a result is forced or the next pass
+    // would blow the budget; each pass costs about `empty` times the last.
+    let mut best = (0, None);
+    for depth in 1..=empty {
+        let before = stats.nodes;
+        best = alpha_beta(game_state, depth, -i32::MAX, i32::MAX, stats);
+        stats.depth = stats.depth.max(depth as u32);
+        if best.0.abs() > HEURISTIC_WIN || (stats.nodes - before) * empty as u64 > NODE_BUDGET {
+            break;
+        }
+    }
+    best
+}
+
+/// Minimax to `depth` plies with alpha-beta pruning. Wins found sooner score
+/// higher, so the engine takes them rather than putting them off.
+fn alpha_beta(
+    game_state: &GameState,
+    depth: usize,
+    mut alpha: i32,
+    mut beta: i32,
+    stats: &mut SearchStats,
+) -> (i32, Option<PlayerMove>) {
+    stats.nodes += 1;
+    match game_state.check_status() {
+        GameStatus::Win(Player::X) => return (HEURISTIC_WIN + depth as i32, None),
+        GameStatus::Win(Player::O) => return (-HEURISTIC_WIN - depth as i32, None),
+        GameStatus::Draw => return (0, None),
+        GameStatus::InProgress if depth == 0 => return (evaluate(game_state), None),
+        GameStatus::InProgress => (),
+    }
+
+    let maximizing = game_state.to_play == Player::X;
+    let mut best: Option<(i32, PlayerMove)> = None;
+    for player_move in central_first(game_state) {
+        let mut new_state = *game_state;
+        new_state.board.set(
+            player_move.row,
+            player_move.col,
+            Cell::Occupied(new_state.to_play),
+        );
+        new_state.to_play = new_state.to_play.opponent();
+        let (score, _) = alpha_beta(&new_state, depth - 1, alpha, beta, stats);
+        let better = best.is_none_or(|(best, _)| {
+            if maximizing {
+                score > best
+            } else {
+                score < best
+            }
+        });
+        if better {
+            best = Some((score, player_move));
+        }
+        if maximizing {
+            alpha = alpha.max(score);
+        } else {
+            beta = beta.min(score);
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+    let (score, player_move) = best.expect("an in-progress game always has a legal move");
+    (score, Some(player_move))
+}
+
+/// Empty cells, nearest the middle of the board first. Central cells lie on
+/// the most lines, so trying them first lets alpha-beta prune more.
+fn central_first(game_state: &GameState) -> Vec<PlayerMove> {
+    let (rows, cols) = game_state.board.size();
+    let mut moves: Vec<PlayerMove> = game_state
+        .board
+        .empty_cells()
+        .map(|(row, col)| PlayerMove { row, col })
+        .collect();
+    moves.sort_by_key(|m| (2 * m.row).abs_diff(rows - 1) + (2 * m.col).abs_diff(cols - 1));
+    moves
+}
+
+/// Estimates an unfinished position from X's point of view. Every line a
+/// player could still complete counts for them, more the fuller it is.
+fn evaluate(game_state: &GameState) -> i32 {
+    let board = &game_state.board;
+    let (rows, cols) = board.size();
+    let (x, o, blocked) = (
+        board.mask(Player::X),
+        board.mask(Player::O),
+        board.blocked(),
+    );
+    let score: i32 = game_state
+        .rules
+        .winning_lines(rows, cols)
+        .iter()
+        .filter(|&&line| line & blocked == 0)
+        .map(|&line| {
+            let (xs, os) = (
+                (line & x).count_ones() as i32,
+                (line & o).count_ones() as i32,
+            );
+            match (xs, os) {
+                (xs, 0) => xs * xs,
+                (0, os) => -os * os,
+                _ => 0,
+            }
+        })
+        .sum();
+    score.clamp(1 - HEURISTIC_WIN, HEURISTIC_WIN - 1)
+}
+
+fn minimax_counted(game_state: &GameState, stats: &mut SearchStats) -> (i32, Option<PlayerMove>) {
+    stats.nodes += 1;
+    match game_state.check_status() {
+        GameStatus::Win(winner) => {
+            return if winner == Player::X {
+                (10, None)
+            } else {
+                (-10, None)
+            };
+        }
+        GameStatus::Draw => return (0, None),
+        GameStatus::InProgress => (),
+    }
+
+    let mut moves = Vec::new();
+    for (r, c) in game_state.board.empty_cells() {
+        let mut new_state = *game_state;
+        new_state.board.set(r, c, Cell::Occupied(new_state.to_play));
+        new_state.to_play = new_state.to_play.opponent();
+        let (score, _) = minimax_counted(&new_state, stats);
+        moves.push((score, PlayerMove { row: r, col: c }));
+    }
+
+    if game_state.to_play == Player::O {
+        // AI is minimizing
+        moves
+            .into_iter()
+            .min_by_key(|(score, _)| *score)
+            .map(|(s, m)| (s, Some(m)))
+            .unwrap()
+    } else {
+        // Human is maximizing
+        moves
+            .into_iter()
+            .max_by_key(|(score, _)| *score)
+            .map(|(s, m)| (s, Some(m)))
+            .unwrap()
+    }
+}
+
+/// Plays the AI's best move, returning it, or `None` if the game is already over.
+pub fn do_optimal_move(game_state: &mut GameState) -> Result<Option<PlayerMove>, Error> {
+    do_handicapped_move(game_state, 0.0, Strategy::Standard)
+}
+
+/// Like `do_optimal_move`, but with probability `blunder_chance` the AI
+/// deliberately plays a weaker move, so beginners can win sometimes, and
+/// ties between best moves are broken by `strategy`. How long the AI
+/// thought, and how far, goes into the engine metrics.
+pub fn do_handicapped_move(
+    game_state: &mut GameState,
+    blunder_chance: f64,
+    strategy: Strategy,
+) -> Result<Option<PlayerMove>, Error> {
+    if game_state.status != GameStatus::InProgress {
+        return Ok(None);
+    }
+
+    let started = Instant::now();
+    let mut stats = SearchStats::default();
+    let chosen = if rand::rng().random_bool(blunder_chance.clamp(0.0, 1.0)) {
+        choose_blunder(game_state, &mut stats)
+    } else {
+        match strategy {
+            Strategy::Standard => search_counted(game_state, &mut stats).1,
+            Strategy::RandomTieBreak => choose_any_best(game_state, &mut stats),
+        }
+    };
+    metrics::record_search(game_state, blunder_chance, started.elapsed(), stats);
+    let player_move = chosen.ok_or(Error::InvalidMove("AI could not find a valid move"))?;
+    try_move(game_state, Player::O, player_move)?;
+    Ok(Some(player_move))
+}
+
+/// Scores every legal move by searching the position after it, higher
+/// being better for the side to move.
+fn score_moves(game_state: &GameState, stats: &mut SearchStats) -> Vec<(i32, PlayerMove)> {
+    game_state
+        .board
+        .empty_cells()
+        .map(|(row, col)| {
+            let player_move = PlayerMove { row, col };
+            let mut after = *game_state;
+            try_move(&mut after, game_state.to_play, player_move)
+                .expect("empty cells are legal moves");
+            let mut searched = SearchStats::default();
+            let (score, _) = search_counted(&after, &mut searched);
+            stats.nodes += searched.nodes;
+            stats.depth = stats.depth.max(searched.depth + 1);
+            // Scores are from X's point of view; flip them so higher is
+            // always better for the side to move.
+            let score = match game_state.to_play {
+                Player::X => score,
+                Player::O => -score,
+            };
+            (score, player_move)
+        })
+        .collect()
+}
+
+/// Picks one of the moves that score best, at random.
+fn choose_any_best(game_state: &GameState, stats: &mut SearchStats) -> Option<PlayerMove> {
+    let scored = score_moves(game_state, stats);
+    let best = scored.iter().map(|(score, _)| *score).max()?;
+    scored
+        .into_iter()
+        .filter(|(score, _)| *score == best)
+        .map(|(_, player_move)| player_move)
+        .choose(&mut rand::rng())
+}
+
+/// Picks a move from the best group of moves that are worse than optimal,
+/// so a draw is given away before a loss is. When every move scores the same
+/// there is nothing worse to play, and one of them is chosen at random.
+fn choose_blunder(game_state: &GameState, stats: &mut SearchStats) -> Option<PlayerMove> {
+    let mut scored = score_moves(game_state, stats);
+    let best = scored.iter().map(|(score, _)| *score).max()?;
+    let runner_up = scored
+        .iter()
+        .map(|(score, _)| *score)
+        .filter(|score| *score < best)
+        .max()
+        .unwrap_or(best);
+    scored.retain(|(score, _)| *score == runner_up);
+    scored
+        .into_iter()
+        .map(|(_, player_move)| player_move)
+        .choose(&mut rand::rng())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Rules;
+    use rand::rng;
+    use rand::seq::IndexedRandom;
+
+    /// This test plays 100 games with a random-move-making human player (X)
+    /// and asserts that the AI (O) never loses.
+    #[test]
+    fn test_ai_is_unbeatable_over_100_random_games() {
+        for i in 0..100 {
+            println!("\n--- Starting Random Game #{} ---", i + 1);
+            let mut game_state = GameState::default();
+            let mut rng = rng();
+
+            // Loop until the game is no longer in progress.
+            while game_state.status == GameStatus::InProgress {
+                // It's always the human's turn first.
+                assert_eq!(game_state.to_play, Player::X);
+
+                // --- Human's Turn (Player X) ---
+                let available_moves: Vec<PlayerMove> = game_state
+                    .board
+                    .empty_cells()
+                    .map(|(row, col)| PlayerMove { row, col })
+                    .collect();
+
+                // If there are no moves, the game should already be over, but we break just in case.
+                if available_moves.is_empty() {
+                    break;
+                }
+
+                // Choose a random valid move for the human player.
+                let human_move = *available_moves.choose(&mut rng).unwrap();
+                println!(
+                    "Human (X) plays at ({}, {})",
+                    human_move.row, human_move.col
+                );
+
+                // Apply the human's move.
+                try_move(&mut game_state, Player::X, human_move)
+                    .expect("Human move should be valid");
+
+                // Check if the human's move ended the game.
+                if game_state.status != GameStatus::InProgress {
+                    break;
+                }
+
+                // --- AI's Turn (Player O) ---
+                assert_eq!(game_state.to_play, Player::O);
+                println!("AI (O) is thinking...");
+
+                // The AI makes its optimal move.
+                do_optimal_move(&mut game_state).expect("AI move should be valid");
+                println!("{}", game_state);
+            }
+
+            println!("Game Over. Final Status: {:?}", game_state.status);
+
+            // --- THE CORE ASSERTION ---
+            // The human player (X) should NEVER win.
+            // The game can be a Draw or a Win for O.
+            assert_ne!(
+                game_state.status,
+                GameStatus::Win(Player::X),
+                "AI FAILED: The AI lost a game! Final board:\n{}",
+                game_state
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_tie_breaks_only_pick_among_the_best_moves() {
+        let mut replies = std::collections::HashSet::new();
+        for _ in 0..30 {
+            // After a corner opening only the center holds the draw.
+            let mut corner = GameState::default();
+            try_move(&mut corner, Player::X, PlayerMove { row: 0, col: 0 }).unwrap();
+            do_handicapped_move(&mut corner, 0.0, Strategy::RandomTieBreak).unwrap();
+            assert_eq!(corner.board.get(1, 1), Cell::Occupied(Player::O));
+
+            // After a center opening any corner does.
+            let mut center = GameState::default();
+            try_move(&mut center, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+            let reply = do_handicapped_move(&mut center, 0.0, Strategy::RandomTieBreak)
+                .unwrap()
+                .unwrap();
+            assert!(reply.row != 1 && reply.col != 1, "{reply:?} loses");
+            replies.insert((reply.row, reply.col));
+        }
+        assert!(replies.len() > 1, "ties were always broken the same way");
+    }
+
+    #[test]
+    fn test_optimal_vs_optimal_is_always_a_draw() {
+        println!("\n--- Starting Optimal vs Optimal Game ---");
+        let mut game_state = GameState::default();
+
+        while game_state.status == GameStatus::InProgress {
+            // --- Player X's Turn (Optimal "Human") ---
+            if game_state.to_play == Player::X {
+                println!("Optimal Human (X) is thinking...");
+                // We manually find and apply the best move for 'X' since
+                // do_optimal_move is hardcoded for Player O.
+                let (_, optimal_move_for_x) = minimax(&game_state);
+                let player_move =
+                    optimal_move_for_x.expect("Minimax should always find a move for X");
+
+                try_move(&mut game_state, Player::X, player_move)
+                    .expect("Optimal move for X should be valid");
+
+                println!("{}", game_state);
+            }
+
+            // Check if Player X's move ended the game
+            if game_state.status != GameStatus::InProgress {
+                break;
+            }
+
+            // --- Player O's Turn (AI) ---
+            if game_state.to_play == Player::O {
+                println!("AI (O) is thinking...");
+                // We can use the existing function here as it's designed for 'O'.
+                do_optimal_move(&mut game_state).expect("Optimal move for O should be valid");
+                println!("{}", game_state);
+            }
+        }
+
+        println!("Game Over. Final Status: {:?}", game_state.status);
+
+        // --- THE CORE ASSERTION ---
+        // A game between two perfect players must result in a draw.
+        assert_eq!(
+            game_state.status,
+            GameStatus::Draw,
+            "MINIMAX FAILED: A game between two optimal players did not result in a draw! Final board:\n{}",
+            game_state
+        );
+    }
+
+    #[test]
+    fn test_large_boards_block_and_take_wins() {
+        let rules = Rules {
+            win_length: 4,
+            ..Rules::default()
+        };
+        let mut game_state = GameState::custom(5, 5, rules, &[]).unwrap();
+        for (player, row, col) in [
+            (Player::X, 2, 0),
+            (Player::O, 0, 4),
+            (Player::X, 2, 1),
+            (Player::O, 1, 4),
+            (Player::X, 2, 2),
+        ] {
+            try_move(&mut game_state, player, PlayerMove { row, col }).unwrap();
+        }
+        // X threatens to complete the middle row.
+        let mut stats = SearchStats::default();
+        let (_, reply) = search_counted(&game_state, &mut stats);
+        assert_eq!(reply, Some(PlayerMove { row: 2, col: 3 }));
+        assert!(stats.nodes < 2 * NODE_BUDGET);
+
+        do_optimal_move(&mut game_state).unwrap();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 0, col: 0 }).unwrap();
+        try_move(&mut game_state, Player::O, PlayerMove { row: 2, col: 4 }).unwrap();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 0, col: 1 }).unwrap();
+        // O has three at the top of the last column and takes the win.
+        assert_eq!(minimax(&game_state).1, Some(PlayerMove { row: 3, col: 4 }));
+        do_optimal_move(&mut game_state).unwrap();
+        assert_eq!(game_state.status, GameStatus::Win(Player::O));
+    }
+
+    fn position(moves: &[(Player, usize, usize)]) -> GameState {
+        let mut game_state = GameState::default();
+        for &(player, row, col) in moves {
+            try_move(&mut game_state, player, PlayerMove { row, col }).unwrap();
+        }
+        game_state
+    }
+
+    #[test]
+    fn test_blunders_give_away_a_draw_before_a_loss() {
+        // O can win at (0, 2), or block X's row at (1, 2) and draw; anything
+        // else loses.
+        let game_state = position(&[
+            (Player::X, 1, 1),
+            (Player::O, 0, 0),
+            (Player::X, 2, 2),
+            (Player::O, 0, 1),
+            (Player::X, 1, 0),
+        ]);
+        assert_eq!(minimax(&game_state).1, Some(PlayerMove { row: 0, col: 2 }));
+        let mut stats = SearchStats::default();
+        assert_eq!(
+            choose_blunder(&game_state, &mut stats),
+            Some(PlayerMove { row: 1, col: 2 })
+        );
+        // Each of the four replies is searched to the end of the game.
+        assert_eq!(stats.depth, 4);
+    }
+
+    #[test]
+    fn test_blunder_chance_controls_how_often_the_ai_errs() {
+        // After X takes the centre, only a corner holds the draw.
+        let centre = position(&[(Player::X, 1, 1)]);
+        let is_corner = |player_move: PlayerMove| player_move.row != 1 && player_move.col != 1;
+        for _ in 0..20 {
+            let mut never = centre;
+            let reply = do_handicapped_move(&mut never, 0.0, Strategy::Standard)
+                .unwrap()
+                .unwrap();
+            assert!(is_corner(reply));
+
+            let mut always = centre;
+            let reply = do_handicapped_move(&mut always, 1.0, Strategy::Standard)
+                .unwrap()
+                .unwrap();
+            assert!(!is_corner(reply));
+            assert_eq!(always.to_play, Player::X);
+        }
+    }
+}
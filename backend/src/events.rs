@@ -0,0 +1,198 @@
+//! The event log behind every game against the engine, a bot, or another
+//! player. A game is created, moves are made, and it finishes; each of those
+//! is appended to the game's log as it happens, and the position and result
+//! are whatever folding the log from the start gives.
+//!
+//! `GameEntry::state` is kept up to date alongside the log so handlers don't
+//! fold on every read, and stores rebuild it from the log when they load a
+//! game. The clock and any pending draw offer are live state rather than
+//! history, so they aren't in the log.
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error,
+    game::{Board, Cell, Ending, GameState, GameStatus, MoveRecord, Rules, try_move},
+};
+
+/// Something that happened to a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// The game was set up with this board, which may have blocked cells,
+    /// under these rules. X moves first.
+    GameCreated { board: Board, rules: Rules },
+    /// A move passed the rules and was played.
+    MoveMade(MoveRecord),
+    /// The game ended. `ending` is set when it didn't end on the board, in
+    /// which case `status` is the result it ended with.
+    GameFinished {
+        status: GameStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ending: Option<Ending>,
+    },
+}
+
+/// An event in a game's log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Position in the log, counting from 0.
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Applies `event` to `game_state`.
+fn apply(game_state: &mut GameState, event: &Event) -> Result<(), Error> {
+    match *event {
+        Event::GameCreated { board, rules } => {
+            *game_state = GameState {
+                board,
+                rules,
+                ..GameState::default()
+            };
+        }
+        Event::MoveMade(record) => try_move(game_state, record.player, record.player_move)?,
+        Event::GameFinished { status, ending } => {
+            game_state.status = status;
+            game_state.ending = ending;
+        }
+    }
+    Ok(())
+}
+
+/// The game as it stands after `events`, which must start with the game
+/// being created. Fails if a move in the log isn't legal where it is.
+pub fn fold(events: &[EventRecord]) -> Result<GameState, Error> {
+    match events.first() {
+        Some(EventRecord {
+            event: Event::GameCreated { .. },
+            ..
+        }) => {}
+        _ => return Err(Error::BadRequest("Event log doesn't start with the game")),
+    }
+    let mut game_state = GameState::default();
+    for record in events {
+        apply(&mut game_state, &record.event)?;
+    }
+    Ok(game_state)
+}
+
+/// The game as it stood after its first `ply` moves, replayed from the log.
+/// After the last move the result is the game's final one, even if it ended
+/// later off the board. `None` if fewer moves were played.
+pub fn replay(events: &[EventRecord], ply: u64) -> Result<Option<GameState>, Error> {
+    // Where each move is in the log.
+    let moves: Vec<usize> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, record)| matches!(record.event, Event::MoveMade(_)))
+        .map(|(index, _)| index)
+        .collect();
+    let end = match ply.cmp(&(moves.len() as u64)) {
+        Ordering::Less => moves[ply as usize],
+        Ordering::Equal => events.len(),
+        Ordering::Greater => return Ok(None),
+    };
+    fold(&events[..end]).map(Some)
+}
+
+/// A log for a game stored before games kept one, made from its current
+/// state and move list. The times are unknown, so every event gets `at`.
+pub fn backfill(
+    game_state: &GameState,
+    moves: &[MoveRecord],
+    at: DateTime<Utc>,
+) -> Vec<EventRecord> {
+    let mut board = game_state.board;
+    for record in moves {
+        board.set(record.player_move.row, record.player_move.col, Cell::Empty);
+    }
+    let created = Event::GameCreated {
+        board,
+        rules: game_state.rules,
+    };
+    let finished = (game_state.status != GameStatus::InProgress).then_some(Event::GameFinished {
+        status: game_state.status,
+        ending: game_state.ending,
+    });
+    std::iter::once(created)
+        .chain(moves.iter().copied().map(Event::MoveMade))
+        .chain(finished)
+        .enumerate()
+        .map(|(seq, event)| EventRecord {
+            seq: seq as u64,
+            at,
+            event,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{Player, PlayerMove},
+        state::GameEntry,
+    };
+
+    fn play(entry: &mut GameEntry, player: Player, row: usize, col: usize) {
+        let player_move = PlayerMove { row, col };
+        try_move(&mut entry.state, player, player_move).unwrap();
+        entry.record_move(player, player_move);
+    }
+
+    #[test]
+    fn test_folding_the_log_rebuilds_the_game() {
+        let blocked = [PlayerMove { row: 1, col: 1 }];
+        let start = GameState::custom(4, 4, Rules::default(), &blocked).unwrap();
+        let mut entry = GameEntry::new(start);
+        play(&mut entry, Player::X, 0, 0);
+        play(&mut entry, Player::O, 3, 3);
+        assert_eq!(fold(&entry.events[..1]).unwrap(), start);
+        assert_eq!(fold(&entry.events).unwrap(), entry.state);
+
+        // A game that ends off the board keeps its result.
+        entry.state.status = GameStatus::Win(Player::O);
+        entry.state.ending = Some(Ending::ByAbsence);
+        entry.finish(Utc::now());
+        assert_eq!(fold(&entry.events).unwrap(), entry.state);
+        assert_eq!(
+            entry
+                .events
+                .iter()
+                .map(|record| record.seq)
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+
+        let backfilled = backfill(&entry.state, &entry.moves(), Utc::now());
+        assert_eq!(fold(&backfilled).unwrap(), entry.state);
+        assert!(fold(&entry.events[1..]).is_err());
+    }
+
+    #[test]
+    fn test_replaying_stops_after_the_given_move() {
+        let mut entry = GameEntry::new(GameState::default());
+        play(&mut entry, Player::X, 0, 0);
+        play(&mut entry, Player::O, 1, 1);
+        play(&mut entry, Player::X, 2, 2);
+        entry.state.status = GameStatus::Win(Player::O);
+        entry.state.ending = Some(Ending::ByAbsence);
+        entry.finish(Utc::now());
+
+        let start = replay(&entry.events, 0).unwrap().unwrap();
+        assert_eq!(start, GameState::default());
+        let after_two = replay(&entry.events, 2).unwrap().unwrap();
+        assert_eq!(after_two.version, 2);
+        assert_eq!(after_two.board.get(1, 1), Cell::Occupied(Player::O));
+        assert_eq!(after_two.board.get(2, 2), Cell::Empty);
+        assert_eq!(after_two.status, GameStatus::InProgress);
+        assert_eq!(replay(&entry.events, 3).unwrap().unwrap(), entry.state);
+        assert!(replay(&entry.events, 4).unwrap().is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! A/B tests of engine strategies against real players.
+//!
+//! With `[experiment]` configured, every new game against the engine is put
+//! in one of its variants, in proportion to their weights, and the engine
+//! plays that variant's strategy for the whole game. Games remember the
+//! experiment and variant they were in, so `GET /admin/experiments` can
+//! compare how each variant fared. The variant is picked from the game's ID,
+//! so every instance puts a game in the same one.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    config::ExperimentConfig,
+    engine::Strategy,
+    game::{GameStatus, Player},
+    state::{AppState, GameEntry},
+    store::StoreError,
+};
+
+/// The variant a game was put in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: String,
+    pub strategy: Strategy,
+}
+
+#[derive(Debug, Default)]
+pub struct Experiment {
+    config: ExperimentConfig,
+}
+
+impl Experiment {
+    pub fn new(config: ExperimentConfig) -> Self {
+        Self { config }
+    }
+
+    /// The experiment's name; `None` if none is running.
+    pub fn name(&self) -> Option<&str> {
+        (!self.config.variants.is_empty()).then_some(self.config.name.as_str())
+    }
+
+    /// The variant a new game is put in, or `None` if no experiment is
+    /// running.
+    pub fn assign(&self, game_id: Uuid) -> Option<Assignment> {
+        let total: u64 = self
+            .config
+            .variants
+            .values()
+            .map(|variant| u64::from(variant.weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = (game_id.as_u128() % u128::from(total)) as u64;
+        for (name, variant) in &self.config.variants {
+            let weight = u64::from(variant.weight);
+            if pick < weight {
+                return Some(Assignment {
+                    experiment: self.config.name.clone(),
+                    variant: name.clone(),
+                    strategy: variant.strategy,
+                });
+            }
+            pick -= weight;
+        }
+        None
+    }
+}
+
+/// How the games in one variant went, from the player's side: the player
+/// is X and the engine O.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariantResults {
+    pub variant: String,
+    pub strategy: Strategy,
+    pub games: u64,
+    pub in_progress: u64,
+    pub player_wins: u64,
+    pub engine_wins: u64,
+    pub draws: u64,
+    /// Moves per finished game; 0 before any have finished.
+    pub average_moves: f64,
+    /// The share of finished games the player won; 0 before any have
+    /// finished.
+    pub player_win_rate: f64,
+}
+
+impl VariantResults {
+    /// Results from counts, with the averages worked out from
+    /// `finished_moves`, the moves played across every finished game.
+    pub fn from_counts(
+        variant: String,
+        strategy: Strategy,
+        in_progress: u64,
+        player_wins: u64,
+        engine_wins: u64,
+        draws: u64,
+        finished_moves: u64,
+    ) -> Self {
+        let finished = player_wins + engine_wins + draws;
+        let (average_moves, player_win_rate) = if finished > 0 {
+            (
+                finished_moves as f64 / finished as f64,
+                player_wins as f64 / finished as f64,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        Self {
+            variant,
+            strategy,
+            games: finished + in_progress,
+            in_progress,
+            player_wins,
+            engine_wins,
+            draws,
+            average_moves,
+            player_win_rate,
+        }
+    }
+}
+
+/// Tallies the games in experiment `name`, by variant.
+pub fn tally<'a>(
+    name: &str,
+    games: impl IntoIterator<Item = &'a GameEntry>,
+) -> Vec<VariantResults> {
+    // Counts in the order `from_counts` takes them.
+    let mut counts: BTreeMap<(&str, Strategy), [u64; 5]> = BTreeMap::new();
+    for entry in games {
+        let Some(assignment) = entry
+            .experiment
+            .as_ref()
+            .filter(|assignment| assignment.experiment == name)
+        else {
+            continue;
+        };
+        let counts = counts
+            .entry((&assignment.variant, assignment.strategy))
+            .or_default();
+        match entry.state.status {
+            GameStatus::InProgress => counts[0] += 1,
+            GameStatus::Win(Player::X) => counts[1] += 1,
+            GameStatus::Win(Player::O) => counts[2] += 1,
+            GameStatus::Draw => counts[3] += 1,
+        }
+        if entry.state.status != GameStatus::InProgress {
+            counts[4] += entry.state.version;
+        }
+    }
+    counts
+        .into_iter()
+        .map(
+            |((variant, strategy), [in_progress, wins, losses, draws, moves])| {
+                VariantResults::from_counts(
+                    variant.to_string(),
+                    strategy,
+                    in_progress,
+                    wins,
+                    losses,
+                    draws,
+                    moves,
+                )
+            },
+        )
+        .collect()
+}
+
+/// The results of experiment `name` from the games in the store, or from
+/// the registry and the archive for stores that keep nothing beyond them.
+pub async fn results(state: &AppState, name: &str) -> Result<Vec<VariantResults>, StoreError> {
+    if let Some(results) = state.store.experiment_results(name).await? {
+        return Ok(results);
+    }
+    let registry = state.snapshot_for_store().await;
+    Ok(tally(name, registry.values()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ExperimentVariant,
+        game::{GameState, PlayerMove, try_move},
+    };
+
+    #[test]
+    fn test_games_are_split_by_weight_and_tallied_by_variant() {
+        let experiment = Experiment::new(ExperimentConfig {
+            name: "tie-breaks".to_string(),
+            variants: BTreeMap::from([
+                (
+                    "control".to_string(),
+                    ExperimentVariant {
+                        strategy: Strategy::Standard,
+                        weight: 1,
+                    },
+                ),
+                (
+                    "random".to_string(),
+                    ExperimentVariant {
+                        strategy: Strategy::RandomTieBreak,
+                        weight: 3,
+                    },
+                ),
+            ]),
+        });
+        let assigned: Vec<Assignment> = (0..4u128)
+            .map(|id| experiment.assign(Uuid::from_u128(id)).unwrap())
+            .collect();
+        assert_eq!(assigned[0].variant, "control");
+        assert!(
+            assigned[1..]
+                .iter()
+                .all(|a| a.strategy == Strategy::RandomTieBreak)
+        );
+        assert!(Experiment::default().assign(Uuid::new_v4()).is_none());
+
+        let mut won = GameEntry::new(GameState::default());
+        won.state.status = GameStatus::Win(Player::X);
+        won.state.version = 5;
+        won.experiment = Some(assigned[1].clone());
+        let mut drawn = GameEntry::new(GameState::default());
+        drawn.state.status = GameStatus::Draw;
+        drawn.state.version = 9;
+        drawn.experiment = Some(assigned[2].clone());
+        let mut playing = GameEntry::new(GameState::default());
+        try_move(&mut playing.state, Player::X, PlayerMove { row: 0, col: 0 }).unwrap();
+        playing.experiment = Some(assigned[0].clone());
+        let outside = GameEntry::new(GameState::default());
+
+        let results = tally("tie-breaks", [&won, &drawn, &playing, &outside]);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].variant, "control");
+        assert_eq!((results[0].games, results[0].in_progress), (1, 1));
+        assert_eq!(results[1].games, 2);
+        assert_eq!(results[1].average_moves, 7.0);
+        assert_eq!(results[1].player_win_rate, 0.5);
+        assert!(tally("other", [&won]).is_empty());
+    }
+}
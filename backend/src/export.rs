@@ -0,0 +1,215 @@
+//! Exporting every game as newline-delimited JSON, for backups and
+//! analytics.
+//!
+//! Games are read a page at a time and written out as they are read, so an
+//! export holds one page in memory however many games there are. Stores that
+//! keep games beyond the registry, like PostgreSQL, are paged through by ID;
+//! otherwise the registry and the archive are, from a list of their IDs.
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, stream};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    game::{GameState, MoveRecord},
+    search::{self, FoundGame},
+    state::{AppState, GameEntry},
+    store::StoreError,
+};
+
+/// Games read, and written out, at a time.
+const PAGE_SIZE: usize = 500;
+
+/// One line of an export: everything about a game but its players' tokens.
+#[derive(Debug, Serialize)]
+struct ExportedGame<'a> {
+    game_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<&'a str>,
+    created_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archived_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tournament_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_id: Option<Uuid>,
+    state: &'a GameState,
+    moves: Vec<MoveRecord>,
+}
+
+impl<'a> ExportedGame<'a> {
+    fn new(game_id: Uuid, created_at: Option<DateTime<Utc>>, entry: &'a GameEntry) -> Self {
+        Self {
+            game_id,
+            tenant: entry.tenant.as_deref(),
+            created_at,
+            finished_at: entry.finished_at,
+            archived_at: entry.archived_at,
+            tournament_id: entry.tournament_id,
+            match_id: entry.match_id,
+            state: &entry.state,
+            moves: entry.moves(),
+        }
+    }
+}
+
+/// Appends the game as a line of `out`.
+fn write_line(out: &mut Vec<u8>, game: &ExportedGame) -> Result<(), StoreError> {
+    serde_json::to_writer(&mut *out, game)?;
+    out.push(b'\n');
+    Ok(())
+}
+
+/// Whether anything happened in the game at or after `since`.
+fn changed_since(entry: &GameEntry, since: Option<DateTime<Utc>>) -> bool {
+    since.is_none_or(|since| entry.events.last().is_some_and(|record| record.at >= since))
+}
+
+/// Where the export has got to.
+enum Cursor {
+    /// Paging through the store, after the given ID.
+    Store(Option<Uuid>),
+    /// Going through the registry and the archive; the IDs left.
+    Memory(Vec<Uuid>),
+    Done,
+}
+
+/// Every game that changed at or after `since`, or every game, one JSON
+/// object per line, a page per chunk.
+pub fn games(
+    state: AppState,
+    since: Option<DateTime<Utc>>,
+) -> impl Stream<Item = Result<Bytes, StoreError>> {
+    stream::try_unfold(Cursor::Store(None), move |cursor| {
+        let state = state.clone();
+        async move {
+            match cursor {
+                Cursor::Store(after) => {
+                    let Some(page) = state.store.export_games(since, after, PAGE_SIZE).await?
+                    else {
+                        let mut ids: Vec<Uuid> = state
+                            .games
+                            .iter()
+                            .map(|game| *game.key())
+                            .chain(state.archive.iter().map(|archived| *archived.key()))
+                            .collect();
+                        // Taken from the end, so this exports in ID order.
+                        ids.sort_unstable_by(|a, b| b.cmp(a));
+                        return from_memory(&state, since, ids).await;
+                    };
+                    let next = match page.last() {
+                        Some(last) if page.len() == PAGE_SIZE => Cursor::Store(Some(last.game_id)),
+                        _ => Cursor::Done,
+                    };
+                    let mut out = Vec::new();
+                    for FoundGame {
+                        game_id,
+                        created_at,
+                        entry,
+                    } in &page
+                    {
+                        write_line(&mut out, &ExportedGame::new(*game_id, *created_at, entry))?;
+                    }
+                    Ok(Some((Bytes::from(out), next)))
+                }
+                Cursor::Memory(ids) => from_memory(&state, since, ids).await,
+                Cursor::Done => Ok(None),
+            }
+        }
+    })
+}
+
+/// Exports the next page of `ids` from the registry or the archive. Games
+/// deleted since the IDs were listed are left out.
+async fn from_memory(
+    state: &AppState,
+    since: Option<DateTime<Utc>>,
+    mut ids: Vec<Uuid>,
+) -> Result<Option<(Bytes, Cursor)>, StoreError> {
+    if ids.is_empty() {
+        return Ok(None);
+    }
+    let mut out = Vec::new();
+    for game_id in ids
+        .split_off(ids.len().saturating_sub(PAGE_SIZE))
+        .into_iter()
+        .rev()
+    {
+        if let Some(game) = state.game(&game_id) {
+            let entry = game.lock().await;
+            if changed_since(&entry, since) {
+                let created_at = search::created_at(&entry);
+                write_line(&mut out, &ExportedGame::new(game_id, created_at, &entry))?;
+            }
+        } else if let Some(entry) = state.archive.get(&game_id)
+            && changed_since(&entry, since)
+        {
+            let created_at = search::created_at(&entry);
+            write_line(&mut out, &ExportedGame::new(game_id, created_at, &entry))?;
+        }
+    }
+    Ok(Some((Bytes::from(out), Cursor::Memory(ids))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::TimeDelta;
+    use futures_util::TryStreamExt;
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        game::{Player, PlayerMove, try_move},
+        state::GameRegistry,
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_every_game_is_exported_as_a_line() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let mut ids = Vec::new();
+        for _ in 0..PAGE_SIZE + 1 {
+            let game_id = Uuid::new_v4();
+            state.games.insert(
+                game_id,
+                Arc::new(Mutex::new(GameEntry::new(GameState::default()))),
+            );
+            ids.push(game_id);
+        }
+        let mut entry = GameEntry::new(GameState::default());
+        let center = PlayerMove { row: 1, col: 1 };
+        try_move(&mut entry.state, Player::X, center).unwrap();
+        entry.record_move(Player::X, center);
+        entry.archived_at = Some(Utc::now());
+        let archived = Uuid::new_v4();
+        state.archive.insert(archived, entry);
+        ids.push(archived);
+        ids.sort();
+
+        let chunks: Vec<Bytes> = games(state.clone(), None).try_collect().await.unwrap();
+        assert_eq!(chunks.len(), 2);
+        let lines: Vec<serde_json::Value> = chunks
+            .concat()
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        let exported: Vec<Uuid> = lines
+            .iter()
+            .map(|line| line["game_id"].as_str().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(exported, ids);
+        let line = &lines[ids.iter().position(|id| *id == archived).unwrap()];
+        assert_eq!(line["moves"][0]["player_move"]["row"], 1);
+        assert!(line.get("mode").is_none());
+
+        let since = Utc::now() + TimeDelta::minutes(1);
+        let chunks: Vec<Bytes> = games(state, Some(since)).try_collect().await.unwrap();
+        assert!(chunks.concat().is_empty());
+    }
+}
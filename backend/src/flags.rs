@@ -0,0 +1,183 @@
+//! Feature flags: capabilities that can be switched off per environment or
+//! per tenant without a rebuild.
+//!
+//! A flag is set server-wide under `[flags]` and per tenant under
+//! `[tenants.<name>.flags]`, and a tenant's own setting wins. Admins can
+//! change either at runtime through `/admin/flags`; such changes last until
+//! the next restart, when the config applies again. A switched-off
+//! capability only refuses to start anything new: games already underway
+//! carry on.
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, config::TenantConfig, tenant::Tenant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    /// Boards other than plain 3x3 in `POST /newgame`: larger, toroidal, or
+    /// with blocked cells.
+    BoardVariants,
+    Notakto,
+    ThreePlayer,
+    /// Registering external bots and starting games against them.
+    Bots,
+    /// Matchmaking through lobbies.
+    Lobbies,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 5] = [
+        Flag::BoardVariants,
+        Flag::Notakto,
+        Flag::ThreePlayer,
+        Flag::Bots,
+        Flag::Lobbies,
+    ];
+
+    /// Whether the capability is on where nothing says otherwise.
+    fn default_enabled(self) -> bool {
+        match self {
+            // Shipped before there were flags, so on unless turned off.
+            Flag::BoardVariants
+            | Flag::Notakto
+            | Flag::ThreePlayer
+            | Flag::Bots
+            | Flag::Lobbies => true,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Flag::BoardVariants => "board_variants",
+            Flag::Notakto => "notakto",
+            Flag::ThreePlayer => "three_player",
+            Flag::Bots => "bots",
+            Flag::Lobbies => "lobbies",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Flags {
+    global: RwLock<BTreeMap<Flag, bool>>,
+    /// Tenants' own settings, by tenant name.
+    tenants: RwLock<BTreeMap<String, BTreeMap<Flag, bool>>>,
+}
+
+/// Every flag as it stands, as listed by `GET /admin/flags`.
+#[derive(Debug, Serialize)]
+pub struct FlagList {
+    /// Server-wide, with defaults filled in.
+    pub flags: BTreeMap<Flag, bool>,
+    /// Only the flags each tenant sets itself.
+    pub tenants: BTreeMap<String, BTreeMap<Flag, bool>>,
+}
+
+impl Flags {
+    pub fn new(global: BTreeMap<Flag, bool>, tenants: &BTreeMap<String, TenantConfig>) -> Self {
+        Self {
+            global: RwLock::new(global),
+            tenants: RwLock::new(
+                tenants
+                    .iter()
+                    .filter(|(_, config)| !config.flags.is_empty())
+                    .map(|(name, config)| (name.clone(), config.flags.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Whether `flag` is on for `tenant`, or server-wide for `None`.
+    pub fn is_enabled(&self, flag: Flag, tenant: Option<&str>) -> bool {
+        let own = tenant.and_then(|tenant| {
+            let tenants = self.tenants.read().expect("flags poisoned");
+            tenants.get(tenant)?.get(&flag).copied()
+        });
+        own.unwrap_or_else(|| {
+            let global = self.global.read().expect("flags poisoned");
+            global.get(&flag).copied().unwrap_or(flag.default_enabled())
+        })
+    }
+
+    /// Fails if `flag` is off for the request's tenant.
+    pub fn require(&self, flag: Flag, tenant: &Tenant) -> Result<(), Error> {
+        if self.is_enabled(flag, tenant.0.as_deref()) {
+            Ok(())
+        } else {
+            Err(Error::FeatureDisabled(flag))
+        }
+    }
+
+    /// Sets `flag` for `tenant`, or server-wide for `None`. Setting a
+    /// tenant's flag to `None` makes it follow the server-wide setting again.
+    pub fn set(&self, flag: Flag, tenant: Option<&str>, enabled: Option<bool>) {
+        match tenant {
+            Some(tenant) => {
+                let mut tenants = self.tenants.write().expect("flags poisoned");
+                let flags = tenants.entry(tenant.to_string()).or_default();
+                match enabled {
+                    Some(enabled) => {
+                        flags.insert(flag, enabled);
+                    }
+                    None => {
+                        flags.remove(&flag);
+                    }
+                }
+                if flags.is_empty() {
+                    tenants.remove(tenant);
+                }
+            }
+            None => {
+                let mut global = self.global.write().expect("flags poisoned");
+                match enabled {
+                    Some(enabled) => global.insert(flag, enabled),
+                    None => global.remove(&flag),
+                };
+            }
+        }
+    }
+
+    pub fn list(&self) -> FlagList {
+        FlagList {
+            flags: Flag::ALL
+                .into_iter()
+                .map(|flag| (flag, self.is_enabled(flag, None)))
+                .collect(),
+            tenants: self.tenants.read().expect("flags poisoned").clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_flags_win_over_server_wide_ones() {
+        let tenants = BTreeMap::from([(
+            "room-101".to_string(),
+            TenantConfig {
+                flags: BTreeMap::from([(Flag::Bots, true)]),
+                ..TenantConfig::default()
+            },
+        )]);
+        let flags = Flags::new(BTreeMap::from([(Flag::Bots, false)]), &tenants);
+        assert!(!flags.is_enabled(Flag::Bots, None));
+        assert!(flags.is_enabled(Flag::Bots, Some("room-101")));
+        assert!(!flags.is_enabled(Flag::Bots, Some("room-102")));
+        assert!(flags.is_enabled(Flag::Notakto, Some("room-101")));
+
+        flags.set(Flag::Notakto, None, Some(false));
+        let room = Tenant(Some("room-101".to_string()));
+        assert!(matches!(
+            flags.require(Flag::Notakto, &room),
+            Err(Error::FeatureDisabled(Flag::Notakto))
+        ));
+        flags.set(Flag::Bots, Some("room-101"), None);
+        assert!(!flags.is_enabled(Flag::Bots, Some("room-101")));
+        assert!(flags.list().tenants.is_empty());
+    }
+}
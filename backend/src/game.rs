@@ -0,0 +1,704 @@
+//! Core game rules: players, the board, and move validation.
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, clock::Clock};
+
+// --- Game Logic Constants and Types ---
+
+/// Smallest and largest number of rows or columns a board can have. The
+/// largest board, 8x8, fills the 64 bits of a mask exactly.
+pub const MIN_SIDE: usize = 3;
+pub const MAX_SIDE: usize = 8;
+
+/// Most cells a game can start with blocked, so there is always room to play.
+pub const MAX_BLOCKED: usize = 4;
+
+/// Generates the winning lines: `length` cells in a row along a row, column,
+/// or diagonal. With `wrap`, lines that run off one edge continue from the
+/// opposite one; a line that would wrap onto itself doesn't count. The
+/// referee and the engine both score positions through
+/// `GameState::check_status`, so they always agree on these.
+fn generate_lines(rows: usize, cols: usize, length: usize, wrap: bool) -> Vec<u64> {
+    const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+    let (rows, cols) = (rows as isize, cols as isize);
+    let mut lines = Vec::new();
+    for (dr, dc) in DIRECTIONS {
+        for row in 0..rows {
+            for col in 0..cols {
+                let cells = (0..length as isize).map(|step| (row + dr * step, col + dc * step));
+                let on_board =
+                    |&(r, c): &(isize, isize)| (0..rows).contains(&r) && (0..cols).contains(&c);
+                if !wrap && !cells.clone().all(|cell| on_board(&cell)) {
+                    continue;
+                }
+                let line = cells.fold(0u64, |line, (r, c)| {
+                    line | 1 << (r.rem_euclid(rows) * cols + c.rem_euclid(cols))
+                });
+                if line.count_ones() as usize == length && !lines.contains(&line) {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// Variations on the standard rules, chosen when a game is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// Winning lines wrap around the board edges, as if the board were drawn
+    /// on a torus.
+    pub toroidal: bool,
+    /// How many in a row win.
+    pub win_length: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            toroidal: false,
+            win_length: 3,
+        }
+    }
+}
+
+impl Rules {
+    /// The winning lines on a `rows` x `cols` board. They are generated once
+    /// per board shape and kept for the life of the process.
+    pub fn winning_lines(&self, rows: usize, cols: usize) -> &'static [u64] {
+        type Shape = (usize, usize, usize, bool);
+        static STANDARD: LazyLock<Vec<u64>> = LazyLock::new(|| generate_lines(3, 3, 3, false));
+        static GENERATED: LazyLock<Mutex<HashMap<Shape, &'static [u64]>>> =
+            LazyLock::new(Mutex::default);
+
+        let shape = (rows, cols, self.win_length, self.toroidal);
+        // Skip the lock for the board nearly every game uses; the engine
+        // checks it at every node of its search.
+        if shape == (3, 3, 3, false) {
+            return &STANDARD;
+        }
+        let mut generated = GENERATED.lock().expect("winning lines cache poisoned");
+        generated
+            .entry(shape)
+            .or_insert_with(|| generate_lines(rows, cols, self.win_length, self.toroidal).leak())
+    }
+
+    /// Whether the cells set in `mask` include a winning line.
+    pub fn has_line(&self, rows: usize, cols: usize, mask: u64) -> bool {
+        self.winning_lines(rows, cols)
+            .iter()
+            .any(|line| line & !mask == 0)
+    }
+}
+
+/// Whether the cells set in `mask` include three in a row on a 3x3 board
+/// under the standard rules.
+pub fn has_line(mask: u16) -> bool {
+    Rules::default().has_line(3, 3, u64::from(mask))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Player {
+    X,
+    O,
+}
+
+impl Player {
+    pub fn opponent(&self) -> Player {
+        match self {
+            Player::X => Player::O,
+            Player::O => Player::X,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cell {
+    Empty,
+    Occupied(Player),
+    /// Out of play for the whole game; nobody can move there.
+    Blocked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameStatus {
+    InProgress,
+    Draw,
+    Win(Player),
+}
+
+/// How a game ended, when it wasn't decided on the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    /// The players agreed to a draw.
+    ByAgreement,
+    /// The side to move ran out of time.
+    OnTime,
+    /// The side to move stayed disconnected too long.
+    ByAbsence,
+    /// A bot didn't answer with a legal move in time.
+    ByForfeit,
+}
+
+/// A board of up to `MAX_SIDE` x `MAX_SIDE` cells, stored as one occupancy
+/// mask per player. Bit `row * cols + col` of a mask refers to the cell at
+/// (`row`, `col`).
+///
+/// On the wire it is an array of rows of `Cell`s, so clients never see the
+/// bitboard, and a 3x3 board looks just like it always has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Board {
+    rows: u8,
+    cols: u8,
+    x: u64,
+    o: u64,
+    blocked: u64,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(3, 3)
+    }
+}
+
+impl Board {
+    /// An empty board. The caller checks the size against `MIN_SIDE` and
+    /// `MAX_SIDE`.
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            rows: rows as u8,
+            cols: cols as u8,
+            x: 0,
+            o: 0,
+            blocked: 0,
+        }
+    }
+
+    /// The number of rows and columns.
+    pub fn size(&self) -> (usize, usize) {
+        (usize::from(self.rows), usize::from(self.cols))
+    }
+
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        let (rows, cols) = self.size();
+        row < rows && col < cols
+    }
+
+    fn bit(&self, row: usize, col: usize) -> u64 {
+        1 << (row * usize::from(self.cols) + col)
+    }
+
+    fn full(&self) -> u64 {
+        let (rows, cols) = self.size();
+        u64::MAX >> (64 - rows * cols)
+    }
+
+    /// The cells `player` occupies, as a mask.
+    pub fn mask(&self, player: Player) -> u64 {
+        match player {
+            Player::X => self.x,
+            Player::O => self.o,
+        }
+    }
+
+    /// The blocked cells, as a mask.
+    pub fn blocked(&self) -> u64 {
+        self.blocked
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Cell {
+        let bit = self.bit(row, col);
+        if self.x & bit != 0 {
+            Cell::Occupied(Player::X)
+        } else if self.o & bit != 0 {
+            Cell::Occupied(Player::O)
+        } else if self.blocked & bit != 0 {
+            Cell::Blocked
+        } else {
+            Cell::Empty
+        }
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, cell: Cell) {
+        let bit = self.bit(row, col);
+        self.x &= !bit;
+        self.o &= !bit;
+        self.blocked &= !bit;
+        match cell {
+            Cell::Empty => {}
+            Cell::Occupied(Player::X) => self.x |= bit,
+            Cell::Occupied(Player::O) => self.o |= bit,
+            Cell::Blocked => self.blocked |= bit,
+        }
+    }
+
+    /// Returns the player with a winning line under `rules`, if any.
+    pub fn winner(&self, rules: Rules) -> Option<Player> {
+        let (rows, cols) = self.size();
+        [Player::X, Player::O]
+            .into_iter()
+            .find(|&player| rules.has_line(rows, cols, self.mask(player)))
+    }
+
+    /// The cells of a winning line under `rules`, if either player has one.
+    pub fn winning_line(&self, rules: Rules) -> Option<Vec<(usize, usize)>> {
+        let (rows, cols) = self.size();
+        let line = [Player::X, Player::O].into_iter().find_map(|player| {
+            let mask = self.mask(player);
+            rules
+                .winning_lines(rows, cols)
+                .iter()
+                .find(|&&line| line & !mask == 0)
+        })?;
+        Some(
+            (0..rows * cols)
+                .filter(|i| line & (1 << i) != 0)
+                .map(|i| (i / cols, i % cols))
+                .collect(),
+        )
+    }
+
+    /// Whether no empty cells are left. Blocked cells count as filled.
+    pub fn is_full(&self) -> bool {
+        self.x | self.o | self.blocked == self.full()
+    }
+
+    /// Iterates over the coordinates of empty cells in row-major order.
+    /// Blocked cells are never empty.
+    pub fn empty_cells(&self) -> impl Iterator<Item = (usize, usize)> + use<> {
+        let mut empty = self.full() & !(self.x | self.o | self.blocked);
+        let cols = usize::from(self.cols);
+        std::iter::from_fn(move || {
+            let i = empty.trailing_zeros() as usize;
+            // Clear the lowest set bit; once none are left, stop.
+            (empty != 0).then(|| {
+                empty &= empty - 1;
+                (i / cols, i % cols)
+            })
+        })
+    }
+
+    /// The cells, row by row from the top.
+    pub fn rows(&self) -> Vec<Vec<Cell>> {
+        let (rows, cols) = self.size();
+        (0..rows)
+            .map(|row| (0..cols).map(|col| self.get(row, col)).collect())
+            .collect()
+    }
+
+    /// Builds a board from rows of cells, which must all be the same length
+    /// and make a board of an allowed size.
+    pub fn from_rows(cells: &[Vec<Cell>]) -> Option<Self> {
+        let rows = cells.len();
+        let cols = cells.first().map_or(0, Vec::len);
+        let side = MIN_SIDE..=MAX_SIDE;
+        if !side.contains(&rows)
+            || !side.contains(&cols)
+            || cells.iter().any(|row| row.len() != cols)
+        {
+            return None;
+        }
+        let mut board = Board::new(rows, cols);
+        for (r, row) in cells.iter().enumerate() {
+            for (c, &cell) in row.iter().enumerate() {
+                board.set(r, c, cell);
+            }
+        }
+        Some(board)
+    }
+}
+
+impl Serialize for Board {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.rows().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Board {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rows = Vec::<Vec<Cell>>::deserialize(deserializer)?;
+        Board::from_rows(&rows).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "board must be rectangular, with {MIN_SIDE} to {MAX_SIDE} rows and columns"
+            ))
+        })
+    }
+}
+
+// The state for a single game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameState {
+    pub board: Board,
+    pub status: GameStatus,
+    pub to_play: Player,
+    // Number of moves applied so far; bumped on every accepted move so clients
+    // can detect stale submissions.
+    pub version: u64,
+    #[serde(default)]
+    pub rules: Rules,
+    // Set for timed games.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<Clock>,
+    // A player who has offered a draw that the opponent hasn't answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draw_offer: Option<Player>,
+    // Set when the game ended off the board.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ending: Option<Ending>,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self {
+            board: Board::default(),
+            status: GameStatus::InProgress,
+            to_play: Player::X,
+            version: 0,
+            rules: Rules::default(),
+            clock: None,
+            draw_offer: None,
+            ending: None,
+        }
+    }
+}
+
+impl std::fmt::Display for GameState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in &self.board.rows() {
+            for cell in row {
+                let symbol = match cell {
+                    Cell::Empty => ".",
+                    Cell::Occupied(Player::X) => "X",
+                    Cell::Occupied(Player::O) => "O",
+                    Cell::Blocked => "#",
+                };
+                write!(f, "{} ", symbol)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "Status: {:?}", self.status)?;
+        writeln!(f, "Next to play: {:?}", self.to_play)
+    }
+}
+
+impl GameState {
+    /// A fresh game played under `rules`.
+    pub fn with_rules(rules: Rules) -> Self {
+        Self {
+            rules,
+            ..Self::default()
+        }
+    }
+
+    /// A fresh game on a `rows` x `cols` board under `rules`, with up to
+    /// `MAX_BLOCKED` cells out of play.
+    pub fn custom(
+        rows: usize,
+        cols: usize,
+        rules: Rules,
+        blocked: &[PlayerMove],
+    ) -> Result<Self, Error> {
+        let side = MIN_SIDE..=MAX_SIDE;
+        if !side.contains(&rows) || !side.contains(&cols) {
+            return Err(Error::BadRequest("rows and cols must be between 3 and 8"));
+        }
+        if !(3..=rows.max(cols)).contains(&rules.win_length) {
+            return Err(Error::BadRequest(
+                "win_length must be at least 3 and fit on the board",
+            ));
+        }
+        if blocked.len() > MAX_BLOCKED {
+            return Err(Error::BadRequest("At most 4 cells can be blocked"));
+        }
+        let mut game_state = Self {
+            board: Board::new(rows, cols),
+            ..Self::with_rules(rules)
+        };
+        for cell in blocked {
+            if !game_state.board.contains(cell.row, cell.col) {
+                return Err(Error::OutOfBounds {
+                    row: cell.row,
+                    col: cell.col,
+                });
+            }
+            game_state.board.set(cell.row, cell.col, Cell::Blocked);
+        }
+        Ok(game_state)
+    }
+
+    pub fn check_status(&self) -> GameStatus {
+        if let Some(player) = self.board.winner(self.rules) {
+            return GameStatus::Win(player);
+        }
+
+        if self.board.is_full() {
+            return GameStatus::Draw;
+        }
+
+        GameStatus::InProgress
+    }
+
+    /// Where the game stands in a few words, e.g. "O to move, 5 moves
+    /// played", for link previews and chat.
+    pub fn summary(&self) -> String {
+        let moves = match self.version {
+            1 => "1 move".to_string(),
+            n => format!("{n} moves"),
+        };
+        match self.status {
+            GameStatus::InProgress => format!("{:?} to move, {moves} played", self.to_play),
+            GameStatus::Win(player) => format!("{player:?} won after {moves}"),
+            GameStatus::Draw => format!("Drawn after {moves}"),
+        }
+    }
+}
+
+// --- Move Logic ---
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct PlayerMove {
+    pub row: usize,
+    pub col: usize,
+}
+
+/// A move accepted by the rules engine. `ply` is the game's version right
+/// after the move was applied, so moves sort in the order they were played.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct MoveRecord {
+    pub ply: u64,
+    pub player: Player,
+    pub player_move: PlayerMove,
+}
+
+/// Picks `count` distinct cells of a `rows` x `cols` board at random, e.g.
+/// to block.
+pub fn random_cells(rows: usize, cols: usize, count: usize) -> Vec<PlayerMove> {
+    // Sizes beyond the limit are rejected when the game is set up.
+    let (rows, cols) = (rows.min(MAX_SIDE), cols.min(MAX_SIDE));
+    let cells = rows * cols;
+    rand::seq::index::sample(&mut rand::rng(), cells, count.min(cells))
+        .into_iter()
+        .map(|i| PlayerMove {
+            row: i / cols,
+            col: i % cols,
+        })
+        .collect()
+}
+
+pub fn try_move(
+    game_state: &mut GameState,
+    player: Player,
+    player_move: PlayerMove,
+) -> Result<(), Error> {
+    if game_state.status != GameStatus::InProgress {
+        return Err(Error::GameOver);
+    }
+    if game_state.to_play != player {
+        return Err(Error::NotYourTurn);
+    }
+    if !game_state.board.contains(player_move.row, player_move.col) {
+        return Err(Error::OutOfBounds {
+            row: player_move.row,
+            col: player_move.col,
+        });
+    }
+    match game_state.board.get(player_move.row, player_move.col) {
+        Cell::Empty => {}
+        Cell::Occupied(_) => return Err(Error::CellOccupied),
+        Cell::Blocked => return Err(Error::InvalidMove("Cell is blocked")),
+    }
+
+    game_state.board.set(
+        player_move.row,
+        player_move.col,
+        Cell::Occupied(game_state.to_play),
+    );
+    game_state.to_play = game_state.to_play.opponent();
+    game_state.version += 1;
+    game_state.status = game_state.check_status();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_out_of_bounds_move_is_rejected() {
+        let mut game_state = GameState::default();
+        let result = try_move(&mut game_state, Player::X, PlayerMove { row: 7, col: 1 });
+        assert!(matches!(result, Err(Error::OutOfBounds { row: 7, col: 1 })));
+        assert_eq!(game_state, GameState::default());
+    }
+
+    #[test]
+    fn test_display_draws_the_board_as_text() {
+        let blocked = [PlayerMove { row: 1, col: 1 }];
+        let mut game_state = GameState::custom(3, 3, Rules::default(), &blocked).unwrap();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 0, col: 2 }).unwrap();
+        assert_eq!(
+            game_state.to_string(),
+            ". . X \n. # . \n. . . \nStatus: InProgress\nNext to play: O\n"
+        );
+    }
+
+    #[test]
+    fn test_board_detects_every_winning_line() {
+        let lines = [
+            [(0, 0), (0, 1), (0, 2)],
+            [(1, 0), (1, 1), (1, 2)],
+            [(2, 0), (2, 1), (2, 2)],
+            [(0, 0), (1, 0), (2, 0)],
+            [(0, 1), (1, 1), (2, 1)],
+            [(0, 2), (1, 2), (2, 2)],
+            [(0, 0), (1, 1), (2, 2)],
+            [(0, 2), (1, 1), (2, 0)],
+        ];
+        for line in lines {
+            let mut board = Board::default();
+            for (r, c) in line {
+                board.set(r, c, Cell::Occupied(Player::O));
+            }
+            assert_eq!(
+                board.winner(Rules::default()),
+                Some(Player::O),
+                "line {:?}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_toroidal_lines_wrap_around_the_edges() {
+        let toroidal = Rules {
+            toroidal: true,
+            ..Rules::default()
+        };
+        // A broken diagonal: a2, b1, c3.
+        let mut board = Board::default();
+        for (r, c) in [(1, 0), (2, 1), (0, 2)] {
+            board.set(r, c, Cell::Occupied(Player::X));
+        }
+        assert_eq!(board.winner(Rules::default()), None);
+        assert_eq!(board.winner(toroidal), Some(Player::X));
+        let standard = Rules::default().winning_lines(3, 3);
+        let wrapped = toroidal.winning_lines(3, 3);
+        assert_eq!((standard.len(), wrapped.len()), (8, 12));
+        assert!(standard.iter().all(|line| wrapped.contains(line)));
+
+        // The engine searches with the same lines, so it finds the wrapped
+        // win right away.
+        let mut game_state = GameState::with_rules(toroidal);
+        for (player, row, col) in [
+            (Player::X, 1, 0),
+            (Player::O, 0, 0),
+            (Player::X, 2, 1),
+            (Player::O, 1, 1),
+        ] {
+            try_move(&mut game_state, player, PlayerMove { row, col }).unwrap();
+        }
+        let value = crate::solver::solve(&game_state);
+        assert_eq!(value.plies, 1);
+        assert_eq!(value.best_move, Some(PlayerMove { row: 0, col: 2 }));
+    }
+
+    #[test]
+    fn test_blocked_cells_are_out_of_play() {
+        let blocked = [PlayerMove { row: 1, col: 1 }, PlayerMove { row: 0, col: 0 }];
+        let mut game_state = GameState::custom(3, 3, Rules::default(), &blocked).unwrap();
+        assert_eq!(game_state.board.empty_cells().count(), 7);
+        assert!(matches!(
+            try_move(&mut game_state, Player::X, PlayerMove { row: 1, col: 1 }),
+            Err(Error::InvalidMove("Cell is blocked"))
+        ));
+
+        // With the center and a corner gone, the board fills up after seven
+        // moves and nobody can have won.
+        let mut game_state = GameState::custom(3, 3, Rules::default(), &blocked).unwrap();
+        for (row, col) in [(0, 1), (0, 2), (1, 0), (2, 0), (1, 2), (2, 1), (2, 2)] {
+            let player = game_state.to_play;
+            try_move(&mut game_state, player, PlayerMove { row, col }).unwrap();
+        }
+        assert_eq!(game_state.status, GameStatus::Draw);
+
+        assert_eq!(random_cells(3, 3, MAX_BLOCKED).len(), MAX_BLOCKED);
+        assert!(GameState::custom(3, 3, Rules::default(), &random_cells(3, 3, 5)).is_err());
+    }
+
+    #[test]
+    fn test_rectangular_boards_use_the_win_length() {
+        let rules = Rules {
+            win_length: 4,
+            ..Rules::default()
+        };
+        let mut game_state = GameState::custom(4, 7, rules, &[]).unwrap();
+        // Four lines across each row, one down each column, and four
+        // diagonals each way.
+        assert_eq!(rules.winning_lines(4, 7).len(), 4 * 4 + 7 + 2 * 4);
+
+        for (player, row, col) in [
+            (Player::X, 3, 2),
+            (Player::O, 0, 0),
+            (Player::X, 3, 3),
+            (Player::O, 0, 1),
+            (Player::X, 3, 4),
+            (Player::O, 0, 2),
+        ] {
+            try_move(&mut game_state, player, PlayerMove { row, col }).unwrap();
+        }
+        // Three in a row no longer wins.
+        assert_eq!(game_state.status, GameStatus::InProgress);
+        try_move(&mut game_state, Player::X, PlayerMove { row: 3, col: 6 }).unwrap();
+        try_move(&mut game_state, Player::O, PlayerMove { row: 0, col: 6 }).unwrap();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 3, col: 5 }).unwrap();
+        assert_eq!(game_state.status, GameStatus::Win(Player::X));
+
+        assert!(matches!(
+            try_move(
+                &mut GameState::custom(4, 7, rules, &[]).unwrap(),
+                Player::X,
+                PlayerMove { row: 4, col: 0 }
+            ),
+            Err(Error::OutOfBounds { row: 4, col: 0 })
+        ));
+        assert!(GameState::custom(3, 9, Rules::default(), &[]).is_err());
+        let too_long = Rules {
+            win_length: 6,
+            ..Rules::default()
+        };
+        assert!(GameState::custom(4, 5, too_long, &[]).is_err());
+
+        // The board's shape round-trips through its wire format.
+        let json = serde_json::to_value(game_state).unwrap();
+        assert_eq!(json["board"].as_array().unwrap().len(), 4);
+        let round_trip: GameState = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, game_state);
+    }
+
+    #[test]
+    fn test_board_keeps_the_json_wire_format() {
+        let mut game_state = GameState::default();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 0, col: 2 }).unwrap();
+
+        let json = serde_json::to_value(game_state).unwrap();
+        assert_eq!(
+            json["board"],
+            serde_json::json!([
+                ["Empty", "Empty", {"Occupied": "X"}],
+                ["Empty", "Empty", "Empty"],
+                ["Empty", "Empty", "Empty"],
+            ])
+        );
+        let round_trip: GameState = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, game_state);
+    }
+}
@@ -0,0 +1,474 @@
+//! GraphQL API at `/api/graphql`, so clients can fetch a game, its history,
+//! and who is playing it in one round trip.
+//!
+//! Subscriptions are served over WebSocket at `/api/graphql/ws`.
+
+use async_graphql::{
+    Context, Enum, ErrorExtensions, ID, Object, Schema, SimpleObject, Subscription,
+    futures_util::{Stream, stream},
+    http::GraphiQLSource,
+};
+use async_graphql_axum::{GraphQL, GraphQLSubscription};
+use axum::{Router, response::Html, routing::get};
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    Error, MoveRequest, cluster,
+    engine::EngineKind,
+    flags::Flag,
+    game::{Cell, GameState, GameStatus, PlayerMove, Rules, random_cells},
+    notation, play_move,
+    state::{AppState, GameEntry, GameMode, Seat},
+    tenant::Tenant,
+};
+
+pub type LaikaSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Converts an API error, keeping its HTTP status as an error extension.
+fn graphql_error(e: Error) -> async_graphql::Error {
+    if let Error::Storage(storage_error) = &e {
+        log::error!("Storage error: {}", storage_error);
+    }
+    let status = e.status_code().as_u16();
+    async_graphql::Error::new(e.to_string()).extend_with(|_, ext| ext.set("status", status))
+}
+
+fn parse_id(id: &ID) -> async_graphql::Result<Uuid> {
+    Uuid::parse_str(id).map_err(|_| async_graphql::Error::new("Invalid game id"))
+}
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+#[graphql(name = "Player", remote = "crate::game::Player")]
+enum PlayerSymbol {
+    X,
+    O,
+}
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    InProgress,
+    Draw,
+    XWon,
+    OWon,
+}
+
+impl From<GameStatus> for Status {
+    fn from(status: GameStatus) -> Self {
+        match status {
+            GameStatus::InProgress => Status::InProgress,
+            GameStatus::Draw => Status::Draw,
+            GameStatus::Win(crate::game::Player::X) => Status::XWon,
+            GameStatus::Win(crate::game::Player::O) => Status::OWon,
+        }
+    }
+}
+
+/// Who sits in a seat. There are no accounts yet: against the engine X is an
+/// anonymous human, and in player-vs-player games players go by the name
+/// they registered with.
+#[derive(SimpleObject, Clone)]
+struct PlayerProfile {
+    name: String,
+    is_bot: bool,
+}
+
+impl PlayerProfile {
+    fn anonymous() -> Self {
+        Self {
+            name: "Anonymous".to_string(),
+            is_bot: false,
+        }
+    }
+
+    fn seat(seat: &Seat) -> Self {
+        Self {
+            name: seat.name.clone(),
+            is_bot: false,
+        }
+    }
+
+    fn bot(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            is_bot: true,
+        }
+    }
+
+    fn engine(engine: EngineKind) -> Self {
+        let name = match engine {
+            EngineKind::Minimax => "Minimax",
+            EngineKind::Random => "Random",
+        };
+        Self {
+            name: name.to_string(),
+            is_bot: true,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct Move {
+    ply: u64,
+    player: PlayerSymbol,
+    row: usize,
+    col: usize,
+    /// The square in text notation, e.g. `b2`.
+    square: String,
+}
+
+#[derive(SimpleObject, Default)]
+struct Stats {
+    games: usize,
+    in_progress: usize,
+    draws: usize,
+    x_wins: usize,
+    o_wins: usize,
+}
+
+/// A snapshot of one game.
+struct Game {
+    id: Uuid,
+    entry: GameEntry,
+}
+
+#[Object]
+impl Game {
+    async fn id(&self) -> ID {
+        ID(self.id.to_string())
+    }
+
+    /// Rows from top to bottom; each cell is `"X"`, `"O"`, `""`, or `"#"` if
+    /// it is blocked.
+    async fn board(&self) -> Vec<Vec<String>> {
+        self.entry
+            .state
+            .board
+            .rows()
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| match cell {
+                        Cell::Empty => String::new(),
+                        Cell::Occupied(player) => format!("{:?}", player),
+                        Cell::Blocked => "#".to_string(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    async fn status(&self) -> Status {
+        self.entry.state.status.into()
+    }
+
+    async fn to_play(&self) -> PlayerSymbol {
+        self.entry.state.to_play.into()
+    }
+
+    async fn version(&self) -> u64 {
+        self.entry.state.version
+    }
+
+    async fn finished_at(&self) -> Option<DateTime<Utc>> {
+        self.entry.finished_at
+    }
+
+    async fn moves(&self) -> Vec<Move> {
+        let (rows, _) = self.entry.state.board.size();
+        self.entry
+            .moves()
+            .iter()
+            .map(|record| Move {
+                ply: record.ply,
+                player: record.player.into(),
+                row: record.player_move.row,
+                col: record.player_move.col,
+                square: notation::square_on(rows, record.player_move),
+            })
+            .collect()
+    }
+
+    async fn notation(&self) -> String {
+        let (rows, _) = self.entry.state.board.size();
+        notation::format_moves(rows, &self.entry.moves())
+    }
+
+    async fn x(&self) -> PlayerProfile {
+        match &self.entry.mode {
+            GameMode::VsEngine | GameMode::VsBot { .. } => PlayerProfile::anonymous(),
+            GameMode::Pvp { x, .. } | GameMode::Open { x } => PlayerProfile::seat(x),
+        }
+    }
+
+    async fn o(&self) -> PlayerProfile {
+        match &self.entry.mode {
+            GameMode::VsEngine => PlayerProfile::engine(EngineKind::Minimax),
+            GameMode::Pvp { o, .. } => PlayerProfile::seat(o),
+            GameMode::Open { .. } => PlayerProfile::anonymous(),
+            GameMode::VsBot { name, .. } => PlayerProfile::bot(name),
+        }
+    }
+}
+
+/// Games that belong to a tenant are left out; GraphQL serves no tenant.
+async fn load_game(state: &AppState, id: Uuid) -> Option<Game> {
+    if let Err(e) = cluster::refresh(state, id).await {
+        log::error!(
+            "Failed to refresh game {}, serving the local copy: {}",
+            id,
+            e
+        );
+    }
+    let game = state.game(&id)?;
+    let entry = game.lock().await.clone();
+    entry.tenant.is_none().then_some(Game { id, entry })
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn game(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<Option<Game>> {
+        let state = ctx.data::<AppState>()?;
+        Ok(load_game(state, parse_id(&id)?).await)
+    }
+
+    /// Live and recently finished games, optionally filtered by status.
+    async fn games(
+        &self,
+        ctx: &Context<'_>,
+        status: Option<Status>,
+    ) -> async_graphql::Result<Vec<Game>> {
+        let state = ctx.data::<AppState>()?;
+        let games = state
+            .snapshot()
+            .await
+            .into_iter()
+            .filter(|(_, entry)| entry.tenant.is_none())
+            .filter(|(_, entry)| status.is_none_or(|s| Status::from(entry.state.status) == s))
+            .map(|(id, entry)| Game { id, entry })
+            .collect();
+        Ok(games)
+    }
+
+    /// The bots that can take a seat.
+    async fn players(&self) -> Vec<PlayerProfile> {
+        EngineKind::ALL
+            .into_iter()
+            .map(PlayerProfile::engine)
+            .collect()
+    }
+
+    /// Results across live and recently finished games.
+    async fn stats(&self, ctx: &Context<'_>) -> async_graphql::Result<Stats> {
+        let state = ctx.data::<AppState>()?;
+        let mut stats = Stats::default();
+        for entry in state.snapshot().await.values() {
+            if entry.tenant.is_some() {
+                continue;
+            }
+            stats.games += 1;
+            match Status::from(entry.state.status) {
+                Status::InProgress => stats.in_progress += 1,
+                Status::Draw => stats.draws += 1,
+                Status::XWon => stats.x_wins += 1,
+                Status::OWon => stats.o_wins += 1,
+            }
+        }
+        Ok(stats)
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Starts a game against the engine. `blunderChance`, from 0 to 1, is how
+    /// often the engine deliberately plays a weaker move; without one, the
+    /// tuned chance applies when auto-tuning, or 0. The board is `rows`
+    /// by `cols` and `winLength` in a row wins. With `toroidal`, winning lines
+    /// wrap around the board edges. `randomBlocked` takes that many random
+    /// cells out of play.
+    // Resolver arguments map one-to-one onto GraphQL arguments.
+    #[allow(clippy::too_many_arguments)]
+    async fn new_game(
+        &self,
+        ctx: &Context<'_>,
+        blunder_chance: Option<f64>,
+        #[graphql(default = 3)] rows: usize,
+        #[graphql(default = 3)] cols: usize,
+        #[graphql(default = 3)] win_length: usize,
+        #[graphql(default)] toroidal: bool,
+        #[graphql(default)] random_blocked: usize,
+    ) -> async_graphql::Result<Game> {
+        let state = ctx.data::<AppState>()?;
+        if (rows, cols, win_length) != (3, 3, 3) || toroidal || random_blocked > 0 {
+            state
+                .flags
+                .require(Flag::BoardVariants, &Tenant::default())
+                .map_err(graphql_error)?;
+        }
+        let rules = Rules {
+            toroidal,
+            win_length,
+        };
+        let blocked = random_cells(rows, cols, random_blocked);
+        let new_game = GameState::custom(rows, cols, rules, &blocked).map_err(graphql_error)?;
+        let (id, game_state) = crate::create_game(state, blunder_chance, new_game, None)
+            .await
+            .map_err(graphql_error)?;
+        Ok(Game {
+            id,
+            entry: GameEntry::new(game_state),
+        })
+    }
+
+    /// Plays a move. Against the engine the AI replies before this returns;
+    /// player-vs-player games need `seatToken`.
+    // Resolver arguments map one-to-one onto GraphQL arguments.
+    #[allow(clippy::too_many_arguments)]
+    async fn make_move(
+        &self,
+        ctx: &Context<'_>,
+        game_id: ID,
+        row: usize,
+        col: usize,
+        expected_version: Option<u64>,
+        idempotency_key: Option<String>,
+        seat_token: Option<String>,
+    ) -> async_graphql::Result<Game> {
+        let state = ctx.data::<AppState>()?;
+        let id = parse_id(&game_id)?;
+        let move_request = MoveRequest {
+            player_move: PlayerMove { row, col },
+            expected_version,
+        };
+        play_move(
+            state,
+            id,
+            move_request,
+            idempotency_key,
+            seat_token.as_deref(),
+        )
+        .await
+        .map_err(graphql_error)?;
+        load_game(state, id)
+            .await
+            .ok_or_else(|| graphql_error(Error::GameNotFound(id)))
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Emits the game after every accepted turn.
+    async fn game_updates(
+        &self,
+        ctx: &Context<'_>,
+        game_id: ID,
+    ) -> async_graphql::Result<impl Stream<Item = Game>> {
+        let state = ctx.data::<AppState>()?.clone();
+        let id = parse_id(&game_id)?;
+        let updates = state.updates.subscribe();
+        Ok(stream::unfold(
+            (state, updates),
+            move |(state, mut updates)| async move {
+                loop {
+                    match updates.recv().await {
+                        Ok(update) if update.game_id == id => {
+                            let game = load_game(&state, id).await?;
+                            return Some((game, (state, updates)));
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        ))
+    }
+}
+
+pub fn schema(state: AppState) -> LaikaSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(state)
+        .finish()
+}
+
+async fn graphiql() -> Html<String> {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/api/graphql")
+            .subscription_endpoint("/api/graphql/ws")
+            .finish(),
+    )
+}
+
+/// Routes for the GraphQL endpoint. `GET /api/graphql` serves GraphiQL.
+pub fn router<S: Clone + Send + Sync + 'static>(state: AppState) -> Router<S> {
+    let schema = schema(state);
+    Router::new()
+        .route(
+            "/api/graphql",
+            get(graphiql).post_service(GraphQL::new(schema.clone())),
+        )
+        .route_service("/api/graphql/ws", GraphQLSubscription::new(schema))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn test_new_game_move_and_query_in_one_schema() {
+        let state = AppState::new(Default::default(), Arc::new(MemoryStore));
+        let schema = schema(state);
+
+        let created = schema.execute("mutation { newGame { id } }").await;
+        assert!(created.errors.is_empty(), "{:?}", created.errors);
+        let id = created.data.into_json().unwrap()["newGame"]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let moved = schema
+            .execute(format!(
+                r#"mutation {{ makeMove(gameId: "{id}", row: 1, col: 1) {{ version }} }}"#
+            ))
+            .await;
+        assert!(moved.errors.is_empty(), "{:?}", moved.errors);
+
+        let queried = schema
+            .execute(format!(
+                r#"{{ game(id: "{id}") {{ status toPlay notation moves {{ square }} o {{ isBot }} }} stats {{ games inProgress }} }}"#
+            ))
+            .await;
+        assert!(queried.errors.is_empty(), "{:?}", queried.errors);
+        let data = queried.data.into_json().unwrap();
+        assert_eq!(data["game"]["status"], "IN_PROGRESS");
+        assert_eq!(data["game"]["toPlay"], "X");
+        assert_eq!(data["game"]["notation"], "X:b2 O:a3");
+        assert_eq!(data["game"]["o"]["isBot"], true);
+        assert_eq!(data["stats"]["games"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_moves_carry_the_http_status() {
+        let state = AppState::new(Default::default(), Arc::new(MemoryStore));
+        let response = schema(state)
+            .execute(format!(
+                r#"mutation {{ makeMove(gameId: "{}", row: 0, col: 0) {{ version }} }}"#,
+                Uuid::new_v4()
+            ))
+            .await;
+        let error = &response.errors[0];
+        assert!(error.message.contains("not found"));
+        let status = error.extensions.as_ref().unwrap().get("status").unwrap();
+        assert_eq!(status, &async_graphql::Value::from(404));
+    }
+}
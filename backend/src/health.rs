@@ -0,0 +1,251 @@
+//! Readiness, served at `/readyz`, broken down by dependency.
+//!
+//! Each check is `ok`, `degraded`, or `failed`. Degraded means the server
+//! still works but something needs looking at: storage is slow, the Redis
+//! relay is down so other instances miss this one's moves, subscribers are
+//! falling behind on game updates, or a background job has stopped running.
+//! Failed means requests can't be served, which so far only an unreachable
+//! store does; the response is then `503 Service Unavailable`, so an
+//! orchestrator stops sending traffic, and `200 OK` otherwise.
+
+use std::{
+    collections::BTreeMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{jobs::JobStatus, state::AppState};
+
+/// Storage slower than this to answer is degraded.
+const SLOW_STORAGE: Duration = Duration::from_millis(500);
+/// Storage that hasn't answered by now has failed.
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Degraded,
+    Failed,
+}
+
+/// The state of a connection kept open in the background, as last seen by
+/// the task that holds it.
+#[derive(Debug, Default)]
+pub struct Link {
+    state: RwLock<Option<LinkState>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LinkState {
+    connected: bool,
+    since: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Link {
+    fn set(&self, connected: bool, error: Option<String>) {
+        let mut state = self.state.write().expect("link poisoned");
+        match state.as_mut() {
+            // `since` stays when it was, e.g. the first of many failed
+            // reconnects.
+            Some(state) if state.connected == connected => state.error = error,
+            _ => {
+                *state = Some(LinkState {
+                    connected,
+                    since: Utc::now(),
+                    error,
+                })
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn up(&self) {
+        self.set(true, None);
+    }
+
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn down(&self, error: impl Into<String>) {
+        self.set(false, Some(error.into()));
+    }
+
+    /// `None` if the connection isn't used at all.
+    fn check(&self) -> Option<LinkCheck> {
+        let state = self.state.read().expect("link poisoned").clone()?;
+        Some(LinkCheck {
+            status: if state.connected {
+                Status::Ok
+            } else {
+                Status::Degraded
+            },
+            state,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StorageCheck {
+    status: Status,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct LinkCheck {
+    status: Status,
+    #[serde(flatten)]
+    state: LinkState,
+}
+
+#[derive(Debug, Serialize)]
+struct BrokerCheck {
+    status: Status,
+    /// Updates the slowest subscriber hasn't received yet.
+    lag: usize,
+    /// How far behind a subscriber can fall before it misses updates.
+    capacity: usize,
+    subscribers: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct JobsCheck {
+    status: Status,
+    jobs: BTreeMap<&'static str, JobStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct Checks {
+    storage: StorageCheck,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redis: Option<LinkCheck>,
+    event_broker: BrokerCheck,
+    jobs: JobsCheck,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// The worst of the checks.
+    status: Status,
+    draining: bool,
+    checks: Checks,
+}
+
+async fn check_storage(state: &AppState) -> StorageCheck {
+    let started = Instant::now();
+    let result = tokio::time::timeout(STORAGE_TIMEOUT, state.store.check()).await;
+    let latency = started.elapsed();
+    let (status, error) = match result {
+        Ok(Ok(())) if latency > SLOW_STORAGE => (Status::Degraded, None),
+        Ok(Ok(())) => (Status::Ok, None),
+        Ok(Err(e)) => (Status::Failed, Some(e.to_string())),
+        Err(_) => (
+            Status::Failed,
+            Some(format!("no answer within {:?}", STORAGE_TIMEOUT)),
+        ),
+    };
+    StorageCheck {
+        status,
+        latency_ms: latency.as_secs_f64() * 1000.0,
+        error,
+    }
+}
+
+fn check_broker(state: &AppState) -> BrokerCheck {
+    let lag = state.updates.len();
+    let capacity = state.update_capacity();
+    BrokerCheck {
+        status: if lag >= capacity / 2 {
+            Status::Degraded
+        } else {
+            Status::Ok
+        },
+        lag,
+        capacity,
+        subscribers: state.updates.receiver_count(),
+    }
+}
+
+fn check_jobs(state: &AppState, now: DateTime<Utc>) -> JobsCheck {
+    let jobs = state.jobs.statuses();
+    let stalled = jobs.values().any(|job| job.is_stalled(now));
+    JobsCheck {
+        status: if stalled {
+            Status::Degraded
+        } else {
+            Status::Ok
+        },
+        jobs,
+    }
+}
+
+pub async fn readiness(state: &AppState) -> Readiness {
+    let checks = Checks {
+        storage: check_storage(state).await,
+        redis: state.redis.check(),
+        event_broker: check_broker(state),
+        jobs: check_jobs(state, Utc::now()),
+    };
+    let status = [
+        checks.storage.status,
+        checks
+            .redis
+            .as_ref()
+            .map_or(Status::Ok, |redis| redis.status),
+        checks.event_broker.status,
+        checks.jobs.status,
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or(Status::Ok);
+    Readiness {
+        status,
+        draining: state.drain.is_draining(),
+        checks,
+    }
+}
+
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let readiness = readiness(&state).await;
+    let code = match readiness.status {
+        Status::Failed => StatusCode::SERVICE_UNAVAILABLE,
+        Status::Ok | Status::Degraded => StatusCode::OK,
+    };
+    (code, Json(readiness))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{state::GameRegistry, store::MemoryStore};
+
+    #[tokio::test]
+    async fn test_readiness_is_the_worst_of_the_checks() {
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let report = readiness(&state).await;
+        assert_eq!(report.status, Status::Ok);
+        assert!(report.checks.redis.is_none());
+
+        state.redis.down("connection refused");
+        let report = readiness(&state).await;
+        assert_eq!(report.status, Status::Degraded);
+        let redis = report.checks.redis.unwrap();
+        assert_eq!(redis.state.error.as_deref(), Some("connection refused"));
+
+        state.redis.up();
+        state.jobs.spawn("relay", async {});
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let report = readiness(&state).await;
+        assert_eq!(report.checks.redis.unwrap().status, Status::Ok);
+        assert_eq!(report.checks.jobs.status, Status::Degraded);
+        assert_eq!(report.status, Status::Degraded);
+    }
+}
@@ -0,0 +1,209 @@
+//! Error messages in the client's language, picked from `Accept-Language`.
+//!
+//! Translations are TOML bundles under `locales/`, built into the binary.
+//! Each maps error codes to messages, where `{name}` stands for a field of
+//! the error's details, and statuses to the titles of problem documents.
+//! Whatever the client's first language has no message for comes from the
+//! next one it accepts, and finally from English, the server's own text.
+//! Codes whose messages vary from case to case, like `invalid_move`, are
+//! always in English.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+
+use crate::ErrorBody;
+
+/// The built-in bundles, by language.
+const SOURCES: &[(&str, &str)] = &[
+    ("de", include_str!("../locales/de.toml")),
+    ("fr", include_str!("../locales/fr.toml")),
+];
+
+static BUNDLES: LazyLock<HashMap<&'static str, Bundle>> = LazyLock::new(|| {
+    SOURCES
+        .iter()
+        .map(|(language, source)| {
+            let bundle = toml::from_str(source)
+                .unwrap_or_else(|e| panic!("Invalid message bundle {language}: {e}"));
+            (*language, bundle)
+        })
+        .collect()
+});
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Bundle {
+    /// Messages by error code.
+    errors: HashMap<String, String>,
+    /// Problem titles by status code.
+    status: HashMap<String, String>,
+}
+
+/// The languages a client accepts, most preferred first, as lowercase tags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Languages(Vec<String>);
+
+impl Languages {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut weighted: Vec<(String, f32)> = headers
+            .get_all(header::ACCEPT_LANGUAGE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| {
+                let mut params = item.split(';');
+                let tag = params.next()?.trim().to_ascii_lowercase();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equally weighted languages keep the client's order.
+        weighted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        Self(weighted.into_iter().map(|(tag, _)| tag).collect())
+    }
+
+    /// The first of the client's languages with a translation `find` turns
+    /// up, e.g. `de` for `de-CH`. `None` once English or `*` comes first,
+    /// or if none has one.
+    fn pick<T>(&self, find: impl Fn(&Bundle) -> Option<T>) -> Option<(&'static str, T)> {
+        for tag in &self.0 {
+            let primary = tag.split('-').next().unwrap_or_default();
+            if primary == "en" || primary == "*" {
+                return None;
+            }
+            if let Some((language, bundle)) = BUNDLES.get_key_value(primary)
+                && let Some(found) = find(bundle)
+            {
+                return Some((language, found));
+            }
+        }
+        None
+    }
+
+    /// The error's message in the client's language, and that language.
+    fn error_message(&self, error: &ErrorBody) -> Option<(&'static str, String)> {
+        self.pick(|bundle| fill(bundle.errors.get(&error.code)?, error.details.as_ref()))
+    }
+
+    /// A problem title for `status` in the client's language.
+    pub fn status_title(&self, status: StatusCode) -> Option<String> {
+        self.pick(|bundle| bundle.status.get(status.as_str()).cloned())
+            .map(|(_, title)| title)
+    }
+}
+
+/// Puts the error's details into `{name}` placeholders. `None` if the
+/// details lack one, so a message never goes out half filled in.
+fn fill(template: &str, details: Option<&serde_json::Value>) -> Option<String> {
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        message.push_str(&rest[..start]);
+        match details?.get(&rest[start + 1..end])? {
+            serde_json::Value::String(value) => message.push_str(value),
+            value => message.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 1..];
+    }
+    message.push_str(rest);
+    Some(message)
+}
+
+/// Middleware that translates error messages into the client's language.
+pub async fn localize(request: Request, next: Next) -> Response {
+    let languages = Languages::from_headers(request.headers());
+    let response = next.run(request).await;
+    if languages.0.is_empty() {
+        return response;
+    }
+    let Some(error) = response.extensions().get::<ErrorBody>() else {
+        return response;
+    };
+    let Some((language, message)) = languages.error_message(error) else {
+        return response;
+    };
+    let error = ErrorBody {
+        message,
+        ..error.clone()
+    };
+    let Ok(body) = serde_json::to_vec(&error) else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(language));
+    // Passed on for `problem::negotiate`.
+    parts.extensions.insert(error);
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn accepting(value: &str) -> Languages {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, value.parse().unwrap());
+        Languages::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_messages_follow_the_clients_languages() {
+        assert!(!BUNDLES.is_empty());
+        let stale = ErrorBody::from(&Error::VersionConflict {
+            expected: 1,
+            actual: 3,
+        });
+        assert_eq!(
+            accepting("fr;q=0.5, de-CH").error_message(&stale),
+            Some((
+                "de",
+                "Veralteter Zug: Version 1 erwartet, das Spiel ist aber bei Version 3".to_string()
+            ))
+        );
+        // English, and anything preferred less, is the server's own text.
+        assert_eq!(accepting("en-GB, de").error_message(&stale), None);
+        // Languages without a bundle are skipped.
+        assert_eq!(
+            accepting("nl, fr").error_message(&ErrorBody::from(&Error::NotYourTurn)),
+            Some(("fr", "Ce n'est pas votre tour".to_string()))
+        );
+        // As are codes whose message varies.
+        assert_eq!(
+            accepting("de").error_message(&ErrorBody::from(&Error::InvalidMove("Game is paused"))),
+            None
+        );
+        assert_eq!(
+            accepting("de").status_title(StatusCode::NOT_FOUND),
+            Some("Nicht gefunden".to_string())
+        );
+    }
+
+    #[test]
+    fn test_placeholders_need_their_details() {
+        let details = serde_json::json!({ "limit": 3, "name": "ada" });
+        assert_eq!(
+            fill("{name} has {limit} games", Some(&details)).as_deref(),
+            Some("ada has 3 games")
+        );
+        assert_eq!(fill("{missing}", Some(&details)), None);
+        assert_eq!(fill("{limit}", None), None);
+        assert_eq!(fill("No details", None).as_deref(), Some("No details"));
+    }
+}
@@ -0,0 +1,280 @@
+//! Importing games from a list of moves played elsewhere, or from a dump
+//! written by `export`.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::{Cell, GameState, GameStatus, MoveRecord, Player, PlayerMove, try_move},
+    notation::{self, NotationError},
+    state::{AppState, GameEntry},
+};
+
+/// The body of an import: either canonical notation or the same moves as a
+/// JSON array.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ImportRequest {
+    Notation { notation: String },
+    Moves { moves: Vec<ImportedMove> },
+}
+
+#[derive(Debug, Deserialize, Copy, Clone)]
+pub struct ImportedMove {
+    pub player: Player,
+    #[serde(flatten)]
+    pub player_move: PlayerMove,
+}
+
+impl ImportRequest {
+    pub fn moves(&self) -> Result<Vec<(Player, PlayerMove)>, NotationError> {
+        match self {
+            ImportRequest::Notation { notation } => notation::parse_moves(notation),
+            ImportRequest::Moves { moves } => Ok(moves
+                .iter()
+                .map(|imported| (imported.player, imported.player_move))
+                .collect()),
+        }
+    }
+}
+
+/// Replays `moves` from the starting position through the rules engine,
+/// stopping at the first move it rejects.
+pub fn replay(moves: &[(Player, PlayerMove)]) -> Result<GameEntry, NotationError> {
+    let mut entry = GameEntry::new(GameState::default());
+    for (index, &(player, player_move)) in moves.iter().enumerate() {
+        try_move(&mut entry.state, player, player_move).map_err(|e| NotationError {
+            index,
+            token: if player_move.row < 3 && player_move.col < 3 {
+                format!("{:?}:{}", player, notation::square(player_move))
+            } else {
+                format!("{:?}:({}, {})", player, player_move.row, player_move.col)
+            },
+            reason: match e {
+                Error::OutOfBounds { .. } => "outside the 3x3 board",
+                e => rejection(e),
+            },
+        })?;
+        entry.record_move(player, player_move);
+    }
+    Ok(entry)
+}
+
+/// Why the rules engine turned a move down.
+fn rejection(error: Error) -> &'static str {
+    match error {
+        Error::NotYourTurn => "Not your turn",
+        Error::CellOccupied => "Cell already occupied",
+        Error::GameOver => "Game is not in progress",
+        Error::InvalidMove(msg) => msg,
+        Error::OutOfBounds { .. } => "outside the board",
+        _ => "rejected",
+    }
+}
+
+/// A line of a dump written by `export::games`.
+#[derive(Debug, Deserialize)]
+pub struct DumpedGame {
+    pub game_id: Uuid,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tournament_id: Option<Uuid>,
+    #[serde(default)]
+    pub match_id: Option<Uuid>,
+    pub state: GameState,
+    pub moves: Vec<MoveRecord>,
+}
+
+impl DumpedGame {
+    /// Replays the moves from an empty board of the same size, rules, and
+    /// blocked cells, and checks they end where the dump says the game did.
+    /// Results decided off the board, like a timeout, are taken as given.
+    pub fn verify(self) -> Result<GameEntry, String> {
+        let (rows, cols) = self.state.board.size();
+        let blocked: Vec<PlayerMove> = (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| PlayerMove { row, col }))
+            .filter(|cell| self.state.board.get(cell.row, cell.col) == Cell::Blocked)
+            .collect();
+        let start = GameState::custom(rows, cols, self.state.rules, &blocked)
+            .map_err(|e| format!("Invalid board: {}", e))?;
+        let mut entry = GameEntry::new(start);
+        for (index, record) in self.moves.iter().enumerate() {
+            try_move(&mut entry.state, record.player, record.player_move).map_err(|e| {
+                format!(
+                    "Move {} ({:?} at {}, {}): {}",
+                    index + 1,
+                    record.player,
+                    record.player_move.row,
+                    record.player_move.col,
+                    rejection(e)
+                )
+            })?;
+            entry.record_move(record.player, record.player_move);
+        }
+        if entry.state.board != self.state.board || entry.state.version != self.state.version {
+            return Err("The moves don't lead to the game's board".to_string());
+        }
+        let decided_off_board =
+            entry.state.status == GameStatus::InProgress && self.state.ending.is_some();
+        if entry.state.status != self.state.status && !decided_off_board {
+            return Err(format!(
+                "The moves end the game as {:?}, not {:?}",
+                entry.state.status, self.state.status
+            ));
+        }
+
+        // The dump has no time for each move; they are dated to the game's
+        // creation.
+        let created_at = self.created_at.unwrap_or_else(Utc::now);
+        for record in &mut entry.events {
+            record.at = created_at;
+        }
+        entry.state = self.state;
+        if entry.state.status != GameStatus::InProgress {
+            entry.finish(self.finished_at.unwrap_or(created_at));
+        }
+        entry.tenant = self.tenant;
+        entry.tournament_id = self.tournament_id;
+        entry.match_id = self.match_id;
+        entry.archived_at = self.archived_at;
+        Ok(entry)
+    }
+}
+
+/// Adds a game from a dump under its own ID, to the registry or, if it was
+/// archived, the archive. Fails if there already is a game with that ID.
+pub async fn load(state: &AppState, game_id: Uuid, entry: GameEntry) -> Result<(), String> {
+    let exists = state.games.contains_key(&game_id)
+        || state.archive.contains_key(&game_id)
+        || state
+            .store
+            .load_game(game_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .is_some();
+    if exists {
+        return Err(format!("Game {} already exists", game_id));
+    }
+    let _registering = state.registering.read().await;
+    state
+        .store
+        .insert_game(game_id, &entry)
+        .await
+        .map_err(|e| e.to_string())?;
+    if entry.archived_at.is_none() {
+        state.games.insert(game_id, Arc::new(Mutex::new(entry)));
+    } else if state.store.keeps_archive() {
+        state
+            .store
+            .archive_game(game_id, &entry)
+            .await
+            .map_err(|e| e.to_string())?;
+    } else {
+        state.archive.insert(game_id, entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameStatus;
+
+    #[test]
+    fn test_replay_accepts_a_finished_game() {
+        let moves = notation::parse_moves("X:a3 O:a1 X:b3 O:b1 X:c3").unwrap();
+        let entry = replay(&moves).unwrap();
+        assert_eq!(entry.state.status, GameStatus::Win(Player::X));
+        assert_eq!(entry.state.version, 5);
+        assert_eq!(
+            notation::format_moves(3, &entry.moves()),
+            "X:a3 O:a1 X:b3 O:b1 X:c3"
+        );
+    }
+
+    #[test]
+    fn test_replay_identifies_the_illegal_move() {
+        let moves = notation::parse_moves("X:b2 O:a1 X:b2").unwrap();
+        let error = replay(&moves).unwrap_err();
+        assert_eq!(error.index, 2);
+        assert_eq!(error.reason, "Cell already occupied");
+
+        let moves = notation::parse_moves("X:b2 X:a1").unwrap();
+        assert_eq!(replay(&moves).unwrap_err().reason, "Not your turn");
+
+        let moves = notation::parse_moves("X:a3 O:a1 X:b3 O:b1 X:c3 O:c1").unwrap();
+        assert_eq!(replay(&moves).unwrap_err().index, 5);
+    }
+
+    #[tokio::test]
+    async fn test_exported_games_are_verified_and_loaded() {
+        use futures_util::TryStreamExt;
+
+        use crate::{export, state::GameRegistry, store::MemoryStore};
+
+        let source = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let moves = notation::parse_moves("X:a3 O:a1 X:b3 O:b1 X:c3").unwrap();
+        let mut finished = replay(&moves).unwrap();
+        finished.finish(Utc::now());
+        let game_id = Uuid::new_v4();
+        source.games.insert(game_id, Arc::new(Mutex::new(finished)));
+        let dump: Vec<u8> = export::games(source, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .concat();
+
+        let target = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let dumped: DumpedGame = serde_json::from_slice(&dump).unwrap();
+        load(&target, game_id, dumped.verify().unwrap())
+            .await
+            .unwrap();
+        let loaded = target.game(&game_id).unwrap();
+        let loaded = loaded.lock().await;
+        assert_eq!(loaded.state.status, GameStatus::Win(Player::X));
+        assert_eq!(loaded.moves().len(), 5);
+        assert!(loaded.finished_at.is_some());
+
+        let dumped: DumpedGame = serde_json::from_slice(&dump).unwrap();
+        let error = load(&target, game_id, dumped.verify().unwrap()).await;
+        assert!(error.unwrap_err().contains("already exists"));
+
+        let mut record: serde_json::Value = serde_json::from_slice(&dump).unwrap();
+        record["moves"][2] = record["moves"][0].clone();
+        let tampered: DumpedGame = serde_json::from_value(record).unwrap();
+        assert!(tampered.verify().unwrap_err().starts_with("Move 3"));
+        let mut record: serde_json::Value = serde_json::from_slice(&dump).unwrap();
+        record["moves"].as_array_mut().unwrap().truncate(4);
+        let truncated: DumpedGame = serde_json::from_value(record).unwrap();
+        assert_eq!(
+            truncated.verify().unwrap_err(),
+            "The moves don't lead to the game's board"
+        );
+    }
+
+    #[test]
+    fn test_import_request_accepts_both_formats() {
+        let from_notation: ImportRequest =
+            serde_json::from_value(serde_json::json!({"notation": "X:b2 O:a1"})).unwrap();
+        let from_array: ImportRequest = serde_json::from_value(serde_json::json!({
+            "moves": [
+                {"player": "X", "row": 1, "col": 1},
+                {"player": "O", "row": 2, "col": 0},
+            ]
+        }))
+        .unwrap();
+        assert_eq!(from_notation.moves().unwrap(), from_array.moves().unwrap());
+    }
+}
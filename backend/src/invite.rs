@@ -0,0 +1,338 @@
+//! Invite links: single-use tokens that seat whoever redeems them as O in a
+//! player-vs-player game.
+//!
+//! A token is `<invite id>.<expiry>.<signature>`. The signature is an
+//! HMAC-SHA256 under a key generated at startup, so forged or altered tokens
+//! are turned away before any lookup. Invites are kept in memory only:
+//! redeeming or revoking one removes it, and all of them lapse on restart.
+
+use std::fmt;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::GameState,
+    state::{AppState, GameEvent, GameMode, Seat, new_token},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long an invite stays valid unless the inviter asks otherwise.
+pub const DEFAULT_TTL_SECS: u64 = 24 * 60 * 60;
+pub const MAX_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Invite {
+    pub id: Uuid,
+    pub game_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteError {
+    /// Malformed, forged, revoked, or already redeemed.
+    Invalid,
+    Expired,
+    SeatTaken,
+    /// Engine games can only be opened up before the first move.
+    GameStarted,
+}
+
+impl fmt::Display for InviteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            InviteError::Invalid => "Invite is invalid, revoked, or already used",
+            InviteError::Expired => "Invite has expired",
+            InviteError::SeatTaken => "Both seats in this game are taken",
+            InviteError::GameStarted => "Only games without any moves can be opened to invites",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for InviteError {}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Every outstanding invite, keyed by invite ID.
+pub struct Invites {
+    key: [u8; 32],
+    invites: DashMap<Uuid, Invite>,
+}
+
+impl Invites {
+    pub fn new() -> Self {
+        Self {
+            key: rand::random(),
+            invites: DashMap::new(),
+        }
+    }
+
+    fn mac(&self, id: Uuid, expires: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(format!("{}.{}", id.simple(), expires).as_bytes());
+        mac
+    }
+
+    /// Creates an invite to `game_id` and returns it with its token.
+    pub fn issue(&self, game_id: Uuid, ttl: TimeDelta, now: DateTime<Utc>) -> (Invite, String) {
+        let invite = Invite {
+            id: Uuid::new_v4(),
+            game_id,
+            expires_at: now + ttl,
+        };
+        let expires = invite.expires_at.timestamp();
+        let signature = self.mac(invite.id, expires).finalize().into_bytes();
+        let token = format!("{}.{}.{}", invite.id.simple(), expires, to_hex(&signature));
+        self.invites.insert(invite.id, invite);
+        (invite, token)
+    }
+
+    /// Checks the token's signature and expiry, returning the invite ID.
+    fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<Uuid, InviteError> {
+        let mut parts = token.split('.');
+        let (Some(id), Some(expires), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(InviteError::Invalid);
+        };
+        let id = Uuid::try_parse(id).map_err(|_| InviteError::Invalid)?;
+        let expires: i64 = expires.parse().map_err(|_| InviteError::Invalid)?;
+        let signature = from_hex(signature).ok_or(InviteError::Invalid)?;
+        self.mac(id, expires)
+            .verify_slice(&signature)
+            .map_err(|_| InviteError::Invalid)?;
+        if now.timestamp() >= expires {
+            return Err(InviteError::Expired);
+        }
+        Ok(id)
+    }
+
+    /// Looks an invite up without using it.
+    pub fn peek(&self, token: &str, now: DateTime<Utc>) -> Result<Invite, InviteError> {
+        let id = self.verify(token, now)?;
+        self.invites
+            .get(&id)
+            .map(|invite| *invite)
+            .ok_or(InviteError::Invalid)
+    }
+
+    /// Uses up an invite. Only the first caller gets it.
+    pub fn redeem(&self, token: &str, now: DateTime<Utc>) -> Result<Invite, InviteError> {
+        let id = self.verify(token, now)?;
+        self.invites
+            .remove(&id)
+            .map(|(_, invite)| invite)
+            .ok_or(InviteError::Invalid)
+    }
+
+    /// Puts back an invite that was redeemed but couldn't be honored.
+    pub fn restore(&self, invite: Invite) {
+        self.invites.insert(invite.id, invite);
+    }
+
+    /// Revokes one invite to `game_id`. Returns whether it existed.
+    pub fn revoke(&self, game_id: Uuid, invite_id: Uuid) -> bool {
+        self.invites
+            .remove_if(&invite_id, |_, invite| invite.game_id == game_id)
+            .is_some()
+    }
+
+    /// Revokes every invite to `game_id`, e.g. once its O seat is filled.
+    pub fn revoke_all(&self, game_id: Uuid) {
+        self.invites.retain(|_, invite| invite.game_id != game_id);
+    }
+
+    /// Removes expired invites. Returns the number removed.
+    pub fn purge_expired(&self, now: DateTime<Utc>) -> usize {
+        let before = self.invites.len();
+        self.invites.retain(|_, invite| invite.expires_at > now);
+        before - self.invites.len()
+    }
+}
+
+// --- Operations ---
+
+/// An invite just created, plus the X seat token if creating it turned an
+/// engine game into a player-vs-player one.
+pub struct IssuedInvite {
+    pub invite: Invite,
+    pub token: String,
+    pub host_token: Option<String>,
+}
+
+/// Invites someone to take O in `game_id`. A fresh engine game is opened up
+/// with the caller as X; a game already waiting for O requires X's
+/// `seat_token`.
+pub async fn create(
+    state: &AppState,
+    game_id: Uuid,
+    seat_token: Option<&str>,
+    host_name: String,
+    ttl: TimeDelta,
+) -> Result<IssuedInvite, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let mut entry = game.lock().await;
+    let host_token = match &entry.mode {
+        GameMode::VsEngine if entry.move_count() == 0 => {
+            let x = Seat {
+                name: host_name,
+                token: new_token(),
+            };
+            let token = x.token.clone();
+            let mut updated = entry.clone();
+            updated.mode = GameMode::Open { x };
+            state
+                .store
+                .update_mode(game_id, &updated)
+                .await
+                .map_err(Error::Storage)?;
+            *entry = updated;
+            Some(token)
+        }
+        GameMode::VsEngine => return Err(Error::Invite(InviteError::GameStarted)),
+        GameMode::Open { x } if seat_token == Some(x.token.as_str()) => None,
+        GameMode::Open { .. } => {
+            return Err(Error::Forbidden("Only X can invite an opponent"));
+        }
+        GameMode::Pvp { .. } | GameMode::VsBot { .. } => {
+            return Err(Error::Invite(InviteError::SeatTaken));
+        }
+    };
+    drop(entry);
+
+    let (invite, token) = state.invites.issue(game_id, ttl, Utc::now());
+    log::info!("Issued invite {} to game {}", invite.id, game_id);
+    Ok(IssuedInvite {
+        invite,
+        token,
+        host_token,
+    })
+}
+
+/// Redeems an invite, seating `guest_name` as O. Returns the game's ID and
+/// state and the new O seat.
+pub async fn redeem(
+    state: &AppState,
+    token: &str,
+    guest_name: String,
+) -> Result<(Uuid, GameState, Seat), Error> {
+    let invite = state
+        .invites
+        .redeem(token, Utc::now())
+        .map_err(Error::Invite)?;
+    let game_id = invite.game_id;
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let mut entry = game.lock().await;
+    let GameMode::Open { x } = &entry.mode else {
+        return Err(Error::Invite(InviteError::SeatTaken));
+    };
+    let o = Seat {
+        name: guest_name,
+        token: new_token(),
+    };
+    let mut updated = entry.clone();
+    updated.mode = GameMode::Pvp {
+        x: x.clone(),
+        o: o.clone(),
+    };
+    if let Err(e) = state.store.update_mode(game_id, &updated).await {
+        state.invites.restore(invite);
+        return Err(Error::Storage(e));
+    }
+    *entry = updated;
+    let game_state = entry.state;
+    drop(entry);
+
+    state.invites.revoke_all(game_id);
+    state.publish(game_id, GameEvent::Seated);
+    log::info!("Invite {} seated O in game {}", invite.id, game_id);
+    Ok((game_id, game_state, o))
+}
+
+/// Revokes an invite. Only X may do this.
+pub async fn revoke(
+    state: &AppState,
+    game_id: Uuid,
+    invite_id: Uuid,
+    seat_token: Option<&str>,
+) -> Result<(), Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    match &entry.mode {
+        GameMode::Open { x } if seat_token == Some(x.token.as_str()) => {}
+        _ => return Err(Error::Forbidden("Only X can revoke an invite")),
+    }
+    if !state.invites.revoke(game_id, invite_id) {
+        return Err(Error::Invite(InviteError::Invalid));
+    }
+    log::info!("Revoked invite {} to game {}", invite_id, game_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invites_are_single_use() {
+        let now = Utc::now();
+        let invites = Invites::new();
+        let game_id = Uuid::new_v4();
+        let (invite, token) = invites.issue(game_id, TimeDelta::hours(1), now);
+
+        assert_eq!(invites.peek(&token, now), Ok(invite));
+        assert_eq!(invites.redeem(&token, now), Ok(invite));
+        assert_eq!(invites.redeem(&token, now), Err(InviteError::Invalid));
+
+        let (invite, token) = invites.issue(game_id, TimeDelta::hours(1), now);
+        assert!(!invites.revoke(Uuid::new_v4(), invite.id));
+        assert!(invites.revoke(game_id, invite.id));
+        assert_eq!(invites.peek(&token, now), Err(InviteError::Invalid));
+    }
+
+    #[test]
+    fn test_tampered_and_expired_tokens_are_rejected() {
+        let now = Utc::now();
+        let invites = Invites::new();
+        let (invite, token) = invites.issue(Uuid::new_v4(), TimeDelta::hours(1), now);
+
+        // Pushing the expiry back breaks the signature.
+        let later = (invite.expires_at + TimeDelta::days(1)).timestamp();
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let later = later.to_string();
+        parts[1] = &later;
+        assert_eq!(
+            invites.peek(&parts.join("."), now),
+            Err(InviteError::Invalid)
+        );
+
+        // So does a token signed by another server.
+        let (_, foreign) = Invites::new().issue(invite.game_id, TimeDelta::hours(1), now);
+        assert_eq!(invites.peek(&foreign, now), Err(InviteError::Invalid));
+        assert_eq!(invites.peek("not-a-token", now), Err(InviteError::Invalid));
+
+        assert_eq!(
+            invites.redeem(&token, now + TimeDelta::hours(2)),
+            Err(InviteError::Expired)
+        );
+        assert_eq!(invites.purge_expired(now + TimeDelta::hours(2)), 1);
+    }
+}
@@ -0,0 +1,398 @@
+//! Background jobs: sweeps that run on a schedule, work retried until it
+//! succeeds, and tasks that run for as long as the server does.
+//!
+//! Every job is started through `Jobs`, which knows what is running. On
+//! shutdown it stops scheduling new runs and retries, lets runs already
+//! underway finish, and then gives the store its final flush, so a sweep or
+//! a checkpoint is never cut off halfway.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Days, NaiveTime, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+/// How long shutdown waits for runs underway to finish.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// How often, and how patiently, to retry a failed job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// The most times the job runs, the first included.
+    pub attempts: u32,
+    /// The wait after the first failure. Each later wait is twice as long.
+    pub first_delay: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Jobs {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Set once the server is shutting down.
+    stop: watch::Sender<bool>,
+    /// How many jobs are running.
+    running: watch::Sender<usize>,
+    /// Scheduled and long-running jobs by name, for `/readyz`.
+    statuses: Mutex<BTreeMap<&'static str, JobStatus>>,
+}
+
+/// What a scheduled or long-running job was last seen doing.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    /// How often the job runs; `None` for one that lasts as long as the
+    /// server.
+    #[serde(skip)]
+    period: Option<Duration>,
+    pub started_at: DateTime<Utc>,
+    pub last_run: Option<DateTime<Utc>>,
+    /// Whether the last run panicked.
+    pub last_run_failed: bool,
+    /// Whether a job meant to last as long as the server has stopped.
+    pub ended: bool,
+}
+
+impl JobStatus {
+    /// Whether the job looks stuck or gone as of `now`: it ended, or missed
+    /// the run it was due more than a period ago.
+    pub fn is_stalled(&self, now: DateTime<Utc>) -> bool {
+        if self.ended {
+            return true;
+        }
+        let Some(period) = self.period else {
+            return false;
+        };
+        let since = self.last_run.unwrap_or(self.started_at);
+        now - since > TimeDelta::from_std(period * 2).unwrap_or(TimeDelta::MAX)
+    }
+}
+
+/// Counts a job as running for as long as it is held.
+struct Running(Arc<Inner>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.running.send_modify(|running| *running -= 1);
+    }
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                stop: watch::Sender::new(false),
+                running: watch::Sender::new(0),
+                statuses: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+}
+
+/// Resolves once shutdown has begun.
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    // An error means the sender is gone, which only happens on shutdown.
+    let _ = stop.wait_for(|stop| *stop).await;
+}
+
+impl Jobs {
+    fn watch(&self, name: &'static str, period: Option<Duration>) {
+        let mut statuses = self.inner.statuses.lock().expect("job statuses poisoned");
+        statuses.insert(
+            name,
+            JobStatus {
+                period,
+                started_at: Utc::now(),
+                last_run: None,
+                last_run_failed: false,
+                ended: false,
+            },
+        );
+    }
+
+    fn update(inner: &Inner, name: &'static str, update: impl FnOnce(&mut JobStatus)) {
+        let mut statuses = inner.statuses.lock().expect("job statuses poisoned");
+        if let Some(status) = statuses.get_mut(name) {
+            update(status);
+        }
+    }
+
+    /// Runs `run` once for `name`, logging and recording how it went.
+    async fn run_once<Fut>(inner: &Inner, name: &'static str, run: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let started = Instant::now();
+        let result = tokio::spawn(run).await;
+        if let Err(e) = &result {
+            log::error!("Job {} failed: {}", name, e);
+        }
+        log::debug!("Job {} ran in {:?}", name, started.elapsed());
+        Self::update(inner, name, |status| {
+            status.last_run = Some(Utc::now());
+            status.last_run_failed = result.is_err();
+        });
+    }
+
+    /// Every scheduled and long-running job by name. Retries aren't
+    /// included.
+    pub fn statuses(&self) -> BTreeMap<&'static str, JobStatus> {
+        self.inner
+            .statuses
+            .lock()
+            .expect("job statuses poisoned")
+            .clone()
+    }
+
+    fn launch(&self, job: impl Future<Output = ()> + Send + 'static) {
+        self.inner.running.send_modify(|running| *running += 1);
+        let running = Running(self.inner.clone());
+        tokio::spawn(async move {
+            job.await;
+            drop(running);
+        });
+    }
+
+    /// Runs `run` every `period`, starting one period from now. A run that
+    /// takes longer than `period` delays the next rather than overlapping
+    /// it, and one that panics is logged and doesn't stop the schedule.
+    pub fn every<F, Fut>(&self, name: &'static str, period: Duration, mut run: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut stop = self.inner.stop.subscribe();
+        self.watch(name, Some(period));
+        let inner = self.inner.clone();
+        self.launch(async move {
+            let start = tokio::time::Instant::now() + period;
+            let mut interval = tokio::time::interval_at(start, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = stopped(&mut stop) => break,
+                }
+                Self::run_once(&inner, name, run()).await;
+            }
+        });
+    }
+
+    /// Runs `run` now, and then every day at `hour`:00 UTC. Like `every`, a
+    /// run that panics is logged and doesn't stop the schedule.
+    pub fn daily<F, Fut>(&self, name: &'static str, hour: u32, mut run: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut stop = self.inner.stop.subscribe();
+        self.watch(name, Some(Duration::from_secs(24 * 60 * 60)));
+        let inner = self.inner.clone();
+        self.launch(async move {
+            let mut next = Utc::now();
+            loop {
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = stopped(&mut stop) => break,
+                }
+                Self::run_once(&inner, name, run()).await;
+                next = next_daily_run(Utc::now(), hour);
+            }
+        });
+    }
+
+    /// Runs `attempt` until it succeeds, waiting longer after each failure,
+    /// and gives up after `retry.attempts` tries or on shutdown. `attempt` is
+    /// passed the number of the try, counting from 1.
+    pub fn retry<F, Fut>(&self, name: impl Into<String>, retry: Retry, mut attempt: F)
+    where
+        F: FnMut(u32) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let name = name.into();
+        let mut stop = self.inner.stop.subscribe();
+        self.launch(async move {
+            let mut delay = retry.first_delay;
+            for number in 1..=retry.attempts {
+                let error = match attempt(number).await {
+                    Ok(()) => return,
+                    Err(e) => e,
+                };
+                if number == retry.attempts {
+                    log::warn!(
+                        "Giving up on {} after {} attempts: {}",
+                        name,
+                        retry.attempts,
+                        error
+                    );
+                    return;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = stopped(&mut stop) => {
+                        log::warn!("Giving up on {} on shutdown: {}", name, error);
+                        return;
+                    }
+                }
+                delay *= 2;
+            }
+        });
+    }
+
+    /// Runs `task`, which is meant to last as long as the server, until
+    /// shutdown. It is dropped wherever it is waiting then.
+    pub fn spawn(&self, name: &'static str, task: impl Future<Output = ()> + Send + 'static) {
+        let mut stop = self.inner.stop.subscribe();
+        self.watch(name, None);
+        let inner = self.inner.clone();
+        self.launch(async move {
+            tokio::select! {
+                _ = task => {
+                    log::warn!("Job {} ended", name);
+                    Self::update(&inner, name, |status| status.ended = true);
+                }
+                _ = stopped(&mut stop) => {}
+            }
+        });
+    }
+
+    /// Stops every job, and waits up to `grace` for runs underway to finish.
+    pub async fn shutdown(&self, grace: Duration) {
+        self.inner.stop.send_replace(true);
+        let mut running = self.inner.running.subscribe();
+        let finished = tokio::time::timeout(grace, running.wait_for(|running| *running == 0));
+        if finished.await.is_err() {
+            log::warn!(
+                "{} background jobs still running after {:?}",
+                self.running(),
+                grace
+            );
+        }
+    }
+
+    /// How many jobs are running.
+    pub fn running(&self) -> usize {
+        *self.inner.running.borrow()
+    }
+}
+
+/// The first `hour`:00 UTC after `now`.
+fn next_daily_run(now: DateTime<Utc>, hour: u32) -> DateTime<Utc> {
+    let at = NaiveTime::from_hms_opt(hour, 0, 0).expect("hour is validated in config");
+    let today = now.date_naive().and_time(at).and_utc();
+    if today > now {
+        today
+    } else {
+        today + Days::new(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_jobs_run_on_schedule_and_finish_before_shutdown() {
+        let jobs = Jobs::default();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = runs.clone();
+        jobs.every("count", Duration::from_millis(10), move || {
+            let counted = counted.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let panics = Arc::new(AtomicU32::new(0));
+        let panicked = panics.clone();
+        jobs.every("panic", Duration::from_millis(10), move || {
+            panicked.fetch_add(1, Ordering::SeqCst);
+            async { panic!("job failed") }
+        });
+        jobs.spawn("forever", std::future::pending());
+        assert_eq!(jobs.running(), 3);
+        jobs.spawn("brief", async {});
+
+        // A panic doesn't stop the schedule.
+        while runs.load(Ordering::SeqCst) < 2 || panics.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let statuses = jobs.statuses();
+        let now = Utc::now();
+        assert!(statuses["panic"].last_run_failed);
+        assert!(!statuses["count"].last_run_failed);
+        assert!(!statuses["count"].is_stalled(now));
+        assert!(statuses["count"].is_stalled(now + TimeDelta::seconds(1)));
+        assert!(!statuses["forever"].is_stalled(now + TimeDelta::days(7)));
+        assert!(statuses["brief"].is_stalled(now));
+        jobs.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(jobs.running(), 0);
+        // The run underway finished, and nothing runs after.
+        let finished = runs.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), finished);
+    }
+
+    #[test]
+    fn test_daily_jobs_run_next_at_the_hour() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let now = at("2026-03-01T02:30:00Z");
+        assert_eq!(next_daily_run(now, 3), at("2026-03-01T03:00:00Z"));
+        assert_eq!(next_daily_run(now, 2), at("2026-03-02T02:00:00Z"));
+        assert_eq!(
+            next_daily_run(at("2026-03-01T03:00:00Z"), 3),
+            at("2026-03-02T03:00:00Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_jobs_are_retried_until_they_succeed_or_give_up() {
+        let jobs = Jobs::default();
+        let retry = Retry {
+            attempts: 3,
+            first_delay: Duration::from_millis(5),
+        };
+        let tries = Arc::new(AtomicU32::new(0));
+        let counted = tries.clone();
+        jobs.retry("flaky", retry, move |number| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if number < 2 {
+                    Err("not yet".to_string())
+                } else {
+                    Ok(())
+                }
+            }
+        });
+        let hopeless = Arc::new(AtomicU32::new(0));
+        let counted = hopeless.clone();
+        jobs.retry("hopeless", retry, move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async { Err("never".to_string()) }
+        });
+
+        while jobs.running() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(tries.load(Ordering::SeqCst), 2);
+        assert_eq!(hopeless.load(Ordering::SeqCst), 3);
+
+        // Shutdown ends the wait between tries.
+        let slow = Retry {
+            attempts: 3,
+            first_delay: Duration::from_secs(60),
+        };
+        jobs.retry("slow", slow, |_| async { Err("later".to_string()) });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        jobs.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(jobs.running(), 0);
+    }
+}
@@ -0,0 +1,520 @@
+//! Quotas for callers who say who they are, with a session token or with an
+//! API key from the config. Guests, registered accounts, and each API key's
+//! tier cap how many analysis calls a caller makes per minute and how many
+//! games against the AI they have going at once.
+//!
+//! Tenants can set their own guest and registered quotas, and an account's
+//! usage is counted separately in each tenant.
+//!
+//! Usage is kept in memory and written through to the store whenever it
+//! changes, so restarting the server doesn't hand out fresh quotas.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    api::constant_time_eq,
+    config::{LimitTier, LimitsConfig},
+    game::GameStatus,
+    state::AppState,
+    tenant::Tenants,
+};
+
+/// How long the analysis quota counts calls before starting over.
+const ANALYSIS_WINDOW: TimeDelta = TimeDelta::minutes(1);
+
+/// Who a request counts against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Account {
+        id: Uuid,
+        guest: bool,
+        /// The tenant the request came in through.
+        tenant: Option<String>,
+    },
+    /// An API key, by its name in the config.
+    ApiKey(String),
+}
+
+impl Caller {
+    /// The key usage is stored under.
+    fn subject(&self) -> String {
+        match self {
+            Caller::Account {
+                id, tenant: None, ..
+            } => format!("account:{id}"),
+            Caller::Account {
+                id,
+                tenant: Some(tenant),
+                ..
+            } => format!("tenant:{tenant}:account:{id}"),
+            Caller::ApiKey(name) => format!("key:{name}"),
+        }
+    }
+}
+
+/// What one caller has used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub subject: String,
+    /// When the current analysis window started.
+    pub window_start: DateTime<Utc>,
+    /// Analysis calls made in the current window.
+    pub analysis_calls: u32,
+    /// Games against the AI the caller started that may still be going.
+    pub games: Vec<Uuid>,
+    /// Places held for games still being created; see `GameSlot`.
+    #[serde(skip)]
+    pub reserved: u32,
+}
+
+impl Usage {
+    fn new(subject: String, now: DateTime<Utc>) -> Self {
+        Self {
+            subject,
+            window_start: now,
+            analysis_calls: 0,
+            games: Vec::new(),
+            reserved: 0,
+        }
+    }
+
+    /// Starts a new analysis window if the current one has run out.
+    fn roll_window(&mut self, now: DateTime<Utc>) {
+        if now - self.window_start >= ANALYSIS_WINDOW {
+            self.window_start = now;
+            self.analysis_calls = 0;
+        }
+    }
+
+    fn window_end(&self) -> DateTime<Utc> {
+        self.window_start + ANALYSIS_WINDOW
+    }
+}
+
+/// A caller's quotas next to what they have used of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub tier: String,
+    pub quotas: LimitTier,
+    pub analysis_calls: u32,
+    /// When the analysis count starts over; `None` if nothing was counted.
+    pub analysis_resets_at: Option<DateTime<Utc>>,
+    pub games_in_progress: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    UnknownApiKey,
+    /// Neither a session token nor an API key was sent.
+    Anonymous,
+    AnalysisQuota {
+        resets_at: DateTime<Utc>,
+    },
+    GameQuota {
+        limit: u32,
+    },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::UnknownApiKey => f.write_str("API key is not valid"),
+            LimitError::Anonymous => {
+                f.write_str("Send a session token or an API key to see its limits")
+            }
+            LimitError::AnalysisQuota { resets_at } => write!(
+                f,
+                "Analysis quota used up; it resets at {}",
+                resets_at.to_rfc3339()
+            ),
+            LimitError::GameQuota { limit } => write!(
+                f,
+                "Limit of {limit} games in progress reached; finish one before starting another"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+#[derive(Debug)]
+pub struct Limits {
+    config: LimitsConfig,
+    usage: Mutex<HashMap<String, Usage>>,
+    /// Held while writing usage to the store, so a slow store holds up
+    /// other writes but not quota checks.
+    saving: Mutex<()>,
+}
+
+impl Limits {
+    pub fn new(config: LimitsConfig) -> Self {
+        Self {
+            config,
+            usage: Mutex::new(HashMap::new()),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// Puts usage loaded from storage back.
+    pub async fn restore(&self, usage: Vec<Usage>) {
+        let mut map = self.usage.lock().await;
+        for record in usage {
+            map.insert(record.subject.clone(), record);
+        }
+    }
+
+    /// The caller an API key belongs to.
+    pub fn api_key(&self, key: &str) -> Result<Caller, LimitError> {
+        self.config
+            .api_keys
+            .iter()
+            .find(|(_, api_key)| constant_time_eq(api_key.key.as_bytes(), key.as_bytes()))
+            .map(|(name, _)| Caller::ApiKey(name.clone()))
+            .ok_or(LimitError::UnknownApiKey)
+    }
+
+    /// The tenant an API key belongs to, if the key is valid and assigned
+    /// to one.
+    pub fn api_key_tenant(&self, key: &str) -> Option<String> {
+        let Caller::ApiKey(name) = self.api_key(key).ok()? else {
+            return None;
+        };
+        self.config.api_keys[&name].tenant.clone()
+    }
+
+    /// The name of the caller's tier and its quotas, which the caller's
+    /// tenant may override.
+    fn tier(&self, caller: &Caller, tenants: &Tenants) -> (String, LimitTier) {
+        match caller {
+            Caller::Account {
+                guest: true,
+                tenant,
+                ..
+            } => {
+                let tier = tenant
+                    .as_deref()
+                    .and_then(|tenant| tenants.get(tenant)?.guest)
+                    .unwrap_or(self.config.guest);
+                ("guest".to_string(), tier)
+            }
+            Caller::Account {
+                guest: false,
+                tenant,
+                ..
+            } => {
+                let tier = tenant
+                    .as_deref()
+                    .and_then(|tenant| tenants.get(tenant)?.registered)
+                    .unwrap_or(self.config.registered);
+                ("registered".to_string(), tier)
+            }
+            Caller::ApiKey(name) => {
+                // Keys with an undefined tier are rejected by
+                // `Config::validate`.
+                let tier = &self.config.api_keys[name].tier;
+                (tier.clone(), self.config.tiers[tier])
+            }
+        }
+    }
+}
+
+/// A place in a caller's quota of concurrent games, held from the check
+/// until their game is created. Dropping it unfilled, as when creating the
+/// game fails or the request goes away, gives the place back.
+#[must_use]
+pub struct GameSlot {
+    limits: Arc<Limits>,
+    /// `None` for anonymous callers, and once filled.
+    subject: Option<String>,
+}
+
+impl GameSlot {
+    /// Counts `game_id` against the caller until it finishes.
+    pub async fn fill(mut self, state: &AppState, game_id: Uuid) -> Result<(), Error> {
+        let Some(subject) = self.subject.take() else {
+            return Ok(());
+        };
+        if let Some(usage) = self.limits.usage.lock().await.get_mut(&subject) {
+            usage.reserved = usage.reserved.saturating_sub(1);
+            usage.games.push(game_id);
+        }
+        save(state, &subject).await
+    }
+}
+
+impl Drop for GameSlot {
+    fn drop(&mut self) {
+        let Some(subject) = self.subject.take() else {
+            return;
+        };
+        let release = move |map: &mut HashMap<String, Usage>| {
+            if let Some(usage) = map.get_mut(&subject) {
+                usage.reserved = usage.reserved.saturating_sub(1);
+            }
+        };
+        if let Ok(mut map) = self.limits.usage.try_lock() {
+            release(&mut map);
+        } else if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let limits = self.limits.clone();
+            runtime.spawn(async move { release(&mut *limits.usage.lock().await) });
+        }
+    }
+}
+
+/// Drops games that are over or gone from the registry.
+async fn prune_games(state: &AppState, usage: &mut Usage) {
+    let mut live = Vec::with_capacity(usage.games.len());
+    for game_id in &usage.games {
+        if let Some(game) = state.game(game_id)
+            && game.lock().await.state.status == GameStatus::InProgress
+        {
+            live.push(*game_id);
+        }
+    }
+    usage.games = live;
+}
+
+// --- Operations ---
+
+/// Counts an analysis call against `caller`, or fails if their quota for
+/// this minute is used up. Anonymous calls aren't counted.
+pub async fn count_analysis(state: &AppState, caller: Option<&Caller>) -> Result<(), Error> {
+    let Some(caller) = caller else {
+        return Ok(());
+    };
+    let (_, tier) = state.limits.tier(caller, &state.tenants);
+    let now = Utc::now();
+    let subject = caller.subject();
+    let mut map = state.limits.usage.lock().await;
+    let usage = map
+        .entry(subject.clone())
+        .or_insert_with(|| Usage::new(subject, now));
+    usage.roll_window(now);
+    if let Some(limit) = tier.analysis_per_minute
+        && usage.analysis_calls >= limit
+    {
+        return Err(Error::Limit(LimitError::AnalysisQuota {
+            resets_at: usage.window_end(),
+        }));
+    }
+    usage.analysis_calls += 1;
+    drop(map);
+    save(state, &caller.subject()).await
+}
+
+/// Holds a place for a game against the AI that `caller` is about to
+/// start, or fails if they already have as many going, or being created, as
+/// their tier allows.
+pub async fn reserve_game(state: &AppState, caller: Option<&Caller>) -> Result<GameSlot, Error> {
+    let Some(caller) = caller else {
+        return Ok(GameSlot {
+            limits: state.limits.clone(),
+            subject: None,
+        });
+    };
+    let (_, tier) = state.limits.tier(caller, &state.tenants);
+    let now = Utc::now();
+    let subject = caller.subject();
+    let mut map = state.limits.usage.lock().await;
+    let usage = map
+        .entry(subject.clone())
+        .or_insert_with(|| Usage::new(subject.clone(), now));
+    prune_games(state, usage).await;
+    if let Some(limit) = tier.concurrent_games
+        && usage.games.len() + usage.reserved as usize >= limit as usize
+    {
+        return Err(Error::Limit(LimitError::GameQuota { limit }));
+    }
+    usage.reserved += 1;
+    Ok(GameSlot {
+        limits: state.limits.clone(),
+        subject: Some(subject),
+    })
+}
+
+/// Writes the latest usage for `subject` through to the store, without
+/// holding up quota checks while it does.
+async fn save(state: &AppState, subject: &str) -> Result<(), Error> {
+    let _saving = state.limits.saving.lock().await;
+    // Taken after waiting our turn, so a write that was overtaken can't put
+    // back older usage.
+    let Some(usage) = state.limits.usage.lock().await.get(subject).cloned() else {
+        return Ok(());
+    };
+    state.store.save_usage(&usage).await.map_err(Error::Storage)
+}
+
+/// The caller's tier and what they have used of it.
+pub async fn report(state: &AppState, caller: &Caller) -> Report {
+    let (tier, quotas) = state.limits.tier(caller, &state.tenants);
+    let now = Utc::now();
+    let mut map = state.limits.usage.lock().await;
+    let (analysis_calls, analysis_resets_at, games_in_progress) =
+        match map.get_mut(&caller.subject()) {
+            Some(usage) => {
+                usage.roll_window(now);
+                prune_games(state, usage).await;
+                let resets_at = (usage.analysis_calls > 0).then(|| usage.window_end());
+                (usage.analysis_calls, resets_at, usage.games.len() as u32)
+            }
+            None => (0, None, 0),
+        };
+    Report {
+        tier,
+        quotas,
+        analysis_calls,
+        analysis_resets_at,
+        games_in_progress,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use super::*;
+    use crate::{
+        config::{ApiKeyConfig, TenantConfig},
+        game::GameState,
+        state::{GameEntry, GameRegistry},
+        store::MemoryStore,
+    };
+
+    fn state() -> AppState {
+        let config = LimitsConfig {
+            tiers: BTreeMap::from([(
+                "partner".to_string(),
+                LimitTier {
+                    analysis_per_minute: Some(2),
+                    concurrent_games: Some(1),
+                },
+            )]),
+            api_keys: BTreeMap::from([(
+                "classroom".to_string(),
+                ApiKeyConfig {
+                    key: "k3y".to_string(),
+                    tier: "partner".to_string(),
+                    tenant: None,
+                },
+            )]),
+            ..LimitsConfig::default()
+        };
+        AppState::new(GameRegistry::new(), Arc::new(MemoryStore)).with_limits(config)
+    }
+
+    #[tokio::test]
+    async fn test_analysis_quota_counts_per_caller() {
+        let state = state();
+        assert_eq!(
+            state.limits.api_key("nope").unwrap_err(),
+            LimitError::UnknownApiKey
+        );
+        let key = state.limits.api_key("k3y").unwrap();
+        count_analysis(&state, Some(&key)).await.unwrap();
+        count_analysis(&state, Some(&key)).await.unwrap();
+        assert!(matches!(
+            count_analysis(&state, Some(&key)).await,
+            Err(Error::Limit(LimitError::AnalysisQuota { .. }))
+        ));
+        // Nobody else is affected, and anonymous calls are never counted.
+        let guest = Caller::Account {
+            id: Uuid::new_v4(),
+            guest: true,
+            tenant: None,
+        };
+        count_analysis(&state, Some(&guest)).await.unwrap();
+        count_analysis(&state, None).await.unwrap();
+
+        let report = report(&state, &key).await;
+        assert_eq!(report.tier, "partner");
+        assert_eq!(report.analysis_calls, 2);
+        assert!(report.analysis_resets_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_finished_games_stop_counting() {
+        let state = state();
+        let key = state.limits.api_key("k3y").unwrap();
+        let game_id = Uuid::new_v4();
+        state.games.insert(
+            game_id,
+            Arc::new(Mutex::new(GameEntry::new(GameState::default()))),
+        );
+        let slot = reserve_game(&state, Some(&key)).await.unwrap();
+        slot.fill(&state, game_id).await.unwrap();
+        assert!(matches!(
+            reserve_game(&state, Some(&key)).await,
+            Err(Error::Limit(LimitError::GameQuota { limit: 1 }))
+        ));
+        assert_eq!(report(&state, &key).await.games_in_progress, 1);
+
+        state.game(&game_id).unwrap().lock().await.state.status = GameStatus::Draw;
+        let _slot = reserve_game(&state, Some(&key)).await.unwrap();
+        assert_eq!(report(&state, &key).await.games_in_progress, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_games_started_together_share_one_quota() {
+        let state = state();
+        let key = state.limits.api_key("k3y").unwrap();
+        let attempts: Vec<_> = (0..8)
+            .map(|_| {
+                let state = state.clone();
+                let key = key.clone();
+                tokio::spawn(async move { reserve_game(&state, Some(&key)).await })
+            })
+            .collect();
+        let mut slots = Vec::new();
+        for attempt in attempts {
+            if let Ok(slot) = attempt.await.unwrap() {
+                slots.push(slot);
+            }
+        }
+        assert_eq!(slots.len(), 1);
+
+        // A game that was never created gives its place back.
+        drop(slots);
+        let slot = reserve_game(&state, Some(&key)).await.unwrap();
+        assert!(reserve_game(&state, Some(&key)).await.is_err());
+        drop(slot);
+        let _slot = reserve_game(&state, Some(&key)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tenants_override_account_tiers() {
+        let strict = LimitTier {
+            analysis_per_minute: Some(0),
+            concurrent_games: None,
+        };
+        let state = state().with_tenants(BTreeMap::from([(
+            "room-101".to_string(),
+            TenantConfig {
+                guest: Some(strict),
+                ..TenantConfig::default()
+            },
+        )]));
+        let id = Uuid::new_v4();
+        let pupil = Caller::Account {
+            id,
+            guest: true,
+            tenant: Some("room-101".to_string()),
+        };
+        assert!(matches!(
+            count_analysis(&state, Some(&pupil)).await,
+            Err(Error::Limit(LimitError::AnalysisQuota { .. }))
+        ));
+        // The same guest outside the tenant keeps the server's defaults.
+        let guest = Caller::Account {
+            id,
+            guest: true,
+            tenant: None,
+        };
+        count_analysis(&state, Some(&guest)).await.unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! Lobbies: a host opens one and gets a short code such as `LAIKA-4F9` to
+//! pass on however they like. Whoever joins with the code first takes the
+//! other seat, and the server starts a player-vs-player game between them.
+//!
+//! Lobbies are short-lived and kept in memory only; they are not restored
+//! after a restart.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::{
+    Error, account,
+    clock::{Clock, TimeControl},
+    config::LobbiesConfig,
+    game::Player,
+    state::{AppState, GameEntry, PieRule, Seat, new_token},
+};
+
+pub const CODE_PREFIX: &str = "LAIKA-";
+
+/// Digits and capitals, minus the ones easily confused when read aloud or
+/// copied by hand (0/O, 1/I).
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LEN: usize = 3;
+
+/// Gives up looking for an unused code after this many collisions.
+const MAX_CODE_ATTEMPTS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lobby {
+    pub code: String,
+    /// Plays X.
+    pub host: Seat,
+    /// Plays O, once someone has joined.
+    pub guest: Option<Seat>,
+    /// The accounts of host and guest, when they joined with a session.
+    pub host_account: Option<Uuid>,
+    pub guest_account: Option<Uuid>,
+    /// Set once the game between host and guest exists.
+    pub game_id: Option<Uuid>,
+    /// Play the game under the pie rule.
+    pub pie_rule: bool,
+    /// Play a timed game.
+    pub time_control: Option<TimeControl>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyError {
+    /// No such code, or the lobby expired.
+    NotFound,
+    AlreadyJoined,
+    TooManyLobbies,
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            LobbyError::NotFound => "No open lobby with that code",
+            LobbyError::AlreadyJoined => "Someone has already joined this lobby",
+            LobbyError::TooManyLobbies => "Too many open lobbies; try again later",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for LobbyError {}
+
+/// Upper-cases a code and adds the prefix if it was left off, so `4f9`,
+/// `laika-4f9`, and `LAIKA-4F9` all find the same lobby.
+pub fn normalize_code(code: &str) -> String {
+    let code = code.trim().to_ascii_uppercase();
+    if code.starts_with(CODE_PREFIX) {
+        code
+    } else {
+        format!("{CODE_PREFIX}{code}")
+    }
+}
+
+fn random_code() -> String {
+    let mut rng = rand::rng();
+    let suffix: String = (0..CODE_LEN)
+        .map(|_| char::from(CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())]))
+        .collect();
+    format!("{CODE_PREFIX}{suffix}")
+}
+
+/// Every open lobby, keyed by code.
+pub struct Lobbies {
+    lobbies: DashMap<String, Lobby>,
+    config: LobbiesConfig,
+}
+
+impl Lobbies {
+    pub fn new(config: LobbiesConfig) -> Self {
+        Self {
+            lobbies: DashMap::new(),
+            config,
+        }
+    }
+
+    /// Opens a lobby with `host_name` in the X seat.
+    pub fn open(
+        &self,
+        host_name: String,
+        host_account: Option<Uuid>,
+        pie_rule: bool,
+        time_control: Option<TimeControl>,
+        now: DateTime<Utc>,
+    ) -> Result<Lobby, LobbyError> {
+        if self.lobbies.len() >= self.config.max_open {
+            self.purge_expired(now);
+            if self.lobbies.len() >= self.config.max_open {
+                return Err(LobbyError::TooManyLobbies);
+            }
+        }
+        let lobby = Lobby {
+            code: String::new(),
+            host: Seat {
+                name: host_name,
+                token: new_token(),
+            },
+            guest: None,
+            host_account,
+            guest_account: None,
+            game_id: None,
+            pie_rule,
+            time_control,
+            created_at: now,
+            expires_at: now + self.config.ttl(),
+        };
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            if let Entry::Vacant(slot) = self.lobbies.entry(random_code()) {
+                let lobby = Lobby {
+                    code: slot.key().clone(),
+                    ..lobby
+                };
+                slot.insert(lobby.clone());
+                return Ok(lobby);
+            }
+        }
+        Err(LobbyError::TooManyLobbies)
+    }
+
+    pub fn get(&self, code: &str, now: DateTime<Utc>) -> Option<Lobby> {
+        self.lobbies
+            .get(&normalize_code(code))
+            .filter(|lobby| lobby.expires_at > now)
+            .map(|lobby| lobby.clone())
+    }
+
+    /// Takes the O seat for `guest_name`, returning the lobby with both
+    /// seats filled. Only the first caller succeeds.
+    pub fn claim(
+        &self,
+        code: &str,
+        guest_name: String,
+        guest_account: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<Lobby, LobbyError> {
+        let mut lobby = self
+            .lobbies
+            .get_mut(&normalize_code(code))
+            .filter(|lobby| lobby.expires_at > now)
+            .ok_or(LobbyError::NotFound)?;
+        if lobby.guest.is_some() {
+            return Err(LobbyError::AlreadyJoined);
+        }
+        lobby.guest = Some(Seat {
+            name: guest_name,
+            token: new_token(),
+        });
+        lobby.guest_account = guest_account;
+        Ok(lobby.clone())
+    }
+
+    /// Gives the O seat back after a claim whose game couldn't be created.
+    pub fn release(&self, code: &str) {
+        if let Some(mut lobby) = self.lobbies.get_mut(code) {
+            lobby.guest = None;
+            lobby.guest_account = None;
+        }
+    }
+
+    /// Records the game for a claimed lobby. The lobby stays readable for
+    /// another full TTL so the host has time to pick up the game ID.
+    pub fn start(&self, code: &str, game_id: Uuid, now: DateTime<Utc>) -> Option<Lobby> {
+        let mut lobby = self.lobbies.get_mut(code)?;
+        lobby.game_id = Some(game_id);
+        lobby.expires_at = lobby.expires_at.max(now + self.config.ttl());
+        Some(lobby.clone())
+    }
+
+    /// Removes expired lobbies. Returns the number removed.
+    pub fn purge_expired(&self, now: DateTime<Utc>) -> usize {
+        let before = self.lobbies.len();
+        self.lobbies.retain(|_, lobby| lobby.expires_at > now);
+        before - self.lobbies.len()
+    }
+}
+
+// --- Operations ---
+
+/// Seats `guest_name` in the lobby and creates the game between host and
+/// guest, recording it against the accounts of both. Returns the lobby with
+/// the game attached.
+pub async fn join(
+    state: &AppState,
+    code: &str,
+    guest_name: String,
+    guest_account: Option<Uuid>,
+) -> Result<Lobby, Error> {
+    state.accepting_games()?;
+    let lobby = state
+        .lobbies
+        .claim(code, guest_name, guest_account, Utc::now())
+        .map_err(Error::Lobby)?;
+    let guest = lobby.guest.clone().expect("claimed lobbies have a guest");
+    let mut entry = GameEntry::pvp(lobby.host.clone(), guest);
+    if lobby.pie_rule {
+        entry.pie_rule = PieRule::On;
+    }
+    entry.state.clock = lobby.time_control.map(Clock::new);
+    let game_id = match crate::create_pvp_game(state, entry).await {
+        Ok(game_id) => game_id,
+        Err(e) => {
+            state.lobbies.release(&lobby.code);
+            return Err(e);
+        }
+    };
+    let lobby = state
+        .lobbies
+        .start(&lobby.code, game_id, Utc::now())
+        .unwrap_or(Lobby {
+            game_id: Some(game_id),
+            ..lobby
+        });
+    let seats = [
+        (lobby.host_account, Player::X),
+        (lobby.guest_account, Player::O),
+    ];
+    for (account_id, player) in seats {
+        if let Some(account_id) = account_id
+            && let Err(e) = account::adopt(state, account_id, game_id, player).await
+        {
+            log::error!(
+                "Failed to record game {} for account {}: {}",
+                game_id,
+                account_id,
+                e
+            );
+        }
+    }
+    log::info!("Lobby {} started game {}", lobby.code, game_id);
+    Ok(lobby)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn lobbies(max_open: usize) -> Lobbies {
+        Lobbies::new(LobbiesConfig {
+            ttl_secs: 60,
+            max_open,
+        })
+    }
+
+    #[test]
+    fn test_codes_are_short_and_forgiving() {
+        let code = random_code();
+        assert_eq!(code.len(), CODE_PREFIX.len() + CODE_LEN);
+        assert!(!code[CODE_PREFIX.len()..].contains(['0', 'O', '1', 'I']));
+        assert_eq!(normalize_code(" laika-4f9 "), "LAIKA-4F9");
+        assert_eq!(normalize_code("4f9"), "LAIKA-4F9");
+    }
+
+    #[test]
+    fn test_only_the_first_guest_gets_the_seat() {
+        let now = Utc::now();
+        let lobbies = lobbies(10);
+        let lobby = lobbies
+            .open("alice".to_string(), None, false, None, now)
+            .unwrap();
+
+        let code = lobby.code.to_lowercase();
+        let claimed = lobbies.claim(&code, "bob".to_string(), None, now).unwrap();
+        assert_eq!(claimed.host, lobby.host);
+        assert_eq!(claimed.guest.as_ref().unwrap().name, "bob");
+        assert_eq!(
+            lobbies
+                .claim(&code, "carol".to_string(), None, now)
+                .unwrap_err(),
+            LobbyError::AlreadyJoined
+        );
+
+        // A failed game creation frees the seat again.
+        lobbies.release(&lobby.code);
+        assert!(lobbies.claim(&code, "carol".to_string(), None, now).is_ok());
+    }
+
+    #[test]
+    fn test_expired_lobbies_free_up_capacity() {
+        let now = Utc::now();
+        let lobbies = lobbies(1);
+        let lobby = lobbies
+            .open("alice".to_string(), None, false, None, now)
+            .unwrap();
+        assert_eq!(
+            lobbies
+                .open("bob".to_string(), None, false, None, now)
+                .unwrap_err(),
+            LobbyError::TooManyLobbies
+        );
+
+        let later = now + TimeDelta::minutes(2);
+        assert_eq!(lobbies.get(&lobby.code, later), None);
+        assert_eq!(
+            lobbies
+                .claim(&lobby.code, "bob".to_string(), None, later)
+                .unwrap_err(),
+            LobbyError::NotFound
+        );
+        assert!(
+            lobbies
+                .open("bob".to_string(), None, false, None, later)
+                .is_ok()
+        );
+    }
+}
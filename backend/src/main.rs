@@ -1,288 +1,1128 @@
+use account::AccountError;
+use arena::ArenaError;
 use axum::{
-    extract::{Path, State},
+    Json, Router,
     http::{Method, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
-    routing::post,
-    Json, Router,
 };
+use bot::BotError;
+use chrono::Utc;
+use clap::Parser;
+use config::{Cli, Command, Config, StorageBackend};
+use engine::{do_handicapped_move, do_optimal_move};
+use flags::Flag;
+use game::{GameState, GameStatus, Player, PlayerMove, try_move};
+use invite::InviteError;
+use jobs::Retry;
+use laika_types::ErrorBody;
+use limits::LimitError;
+use lobby::LobbyError;
+use matches::MatchError;
+use notation::NotationError;
+use puzzle::{PuzzleError, Puzzles};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::RwLock;
-use tower_http::cors::CorsLayer;
+use state::{AppState, GameEntry, GameEvent, GameMode, GameRegistry, PieRule};
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+use store::StoreError;
+use tokio::sync::MutexGuard;
+use tournament::TournamentError;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::{
+        CompressionLayer, Predicate,
+        predicate::{NotForContentType, SizeAbove},
+    },
+    cors::CorsLayer,
+    timeout::TimeoutLayer,
+};
 use uuid::Uuid;
 
+mod account;
+mod api;
+mod archive;
+mod arena;
+mod audit;
+mod bench;
+mod bot;
+mod calibration;
+mod clock;
+mod cluster;
+mod codec;
+mod config;
+mod discord;
+mod drain;
+mod draw;
+mod engine;
+mod events;
+mod experiments;
+mod export;
+mod flags;
+mod game;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod health;
+mod i18n;
+mod import;
+mod invite;
+mod jobs;
+mod limits;
+mod lobby;
+mod matches;
+mod metrics;
+mod notakto;
+mod notation;
+mod notify;
+#[cfg(feature = "oauth")]
+mod oauth;
+mod presence;
+mod problem;
+#[cfg(feature = "redis")]
+mod pubsub;
+#[cfg(feature = "webpush")]
+mod push;
+mod puzzle;
+mod render;
+#[cfg(feature = "sentry")]
+mod reporting;
+mod rollup;
+mod search;
+mod simulate;
+#[cfg(feature = "slack")]
+mod slack;
+mod solver;
+mod state;
+mod stdio;
+mod store;
+#[cfg(feature = "telegram")]
+mod telegram;
+mod tenant;
+mod three_player;
+mod tls;
+mod tournament;
+#[cfg(feature = "webhooks")]
+mod webhook;
+
 // --- Error Handling ---
 #[derive(Debug)]
-enum Error {
+pub enum Error {
+    NotYourTurn,
+    CellOccupied,
+    /// The game is over, or hasn't started.
+    GameOver,
     InvalidMove(&'static str),
+    GameNotFound(Uuid),
+    OutOfBounds {
+        row: usize,
+        col: usize,
+    },
+    VersionConflict {
+        expected: u64,
+        actual: u64,
+    },
+    IdempotencyKeyReused,
+    /// A move in a batch failed, so none of them were played.
+    InBatch {
+        index: usize,
+        error: Box<Error>,
+    },
+    BadRequest(&'static str),
+    InvalidImport(NotationError),
+    /// A MessagePack or CBOR request body that didn't decode; see `codec`.
+    InvalidBody(String),
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    /// The capability is switched off for the caller; see `flags`.
+    FeatureDisabled(Flag),
+    TenantNotFound(String),
+    TournamentNotFound(Uuid),
+    Tournament(TournamentError),
+    ArenaNotFound(Uuid),
+    Arena(ArenaError),
+    MatchNotFound(Uuid),
+    Match(MatchError),
+    Lobby(LobbyError),
+    Invite(InviteError),
+    Bot(BotError),
+    Puzzle(PuzzleError),
+    Account(AccountError),
+    Limit(LimitError),
+    #[cfg(feature = "oauth")]
+    OAuth(oauth::OAuthError),
+    #[cfg(feature = "webhooks")]
+    Webhook(webhook::WebhookError),
+    Maintenance,
+    /// The server is shutting down once its games finish; see `drain`.
+    Draining,
+    Storage(StoreError),
+    /// A handler panicked; see `panicked`.
+    Internal,
+}
+
+impl Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotYourTurn
+            | Error::CellOccupied
+            | Error::GameOver
+            | Error::InvalidMove(_)
+            | Error::OutOfBounds { .. }
+            | Error::BadRequest(_)
+            | Error::InvalidImport(_)
+            | Error::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            Error::GameNotFound(_)
+            | Error::TenantNotFound(_)
+            | Error::TournamentNotFound(_)
+            | Error::MatchNotFound(_)
+            | Error::ArenaNotFound(_) => StatusCode::NOT_FOUND,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) | Error::FeatureDisabled(_) => StatusCode::FORBIDDEN,
+            Error::Tournament(_) | Error::Match(_) => StatusCode::CONFLICT,
+            Error::Arena(ArenaError::UnknownPlayer) => StatusCode::NOT_FOUND,
+            Error::Arena(_) => StatusCode::CONFLICT,
+            Error::Lobby(LobbyError::NotFound) => StatusCode::NOT_FOUND,
+            Error::Lobby(LobbyError::AlreadyJoined) => StatusCode::CONFLICT,
+            Error::Lobby(LobbyError::TooManyLobbies) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Invite(InviteError::Invalid) => StatusCode::NOT_FOUND,
+            Error::Invite(InviteError::Expired) => StatusCode::GONE,
+            Error::Invite(InviteError::SeatTaken | InviteError::GameStarted) => {
+                StatusCode::CONFLICT
+            }
+            Error::Bot(BotError::NotFound) => StatusCode::NOT_FOUND,
+            Error::Bot(BotError::Offline | BotError::TooManyBots) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Error::Bot(BotError::Timeout) => StatusCode::GATEWAY_TIMEOUT,
+            Error::Bot(BotError::IllegalMove) => StatusCode::BAD_GATEWAY,
+            Error::Puzzle(
+                PuzzleError::AlreadyAttempted
+                | PuzzleError::AlreadyAnswered
+                | PuzzleError::RushOver,
+            ) => StatusCode::CONFLICT,
+            Error::Puzzle(_) => StatusCode::NOT_FOUND,
+            Error::Account(
+                AccountError::InvalidSession
+                | AccountError::SessionExpired
+                | AccountError::InvalidCredentials,
+            ) => StatusCode::UNAUTHORIZED,
+            Error::Account(AccountError::NotFound) => StatusCode::NOT_FOUND,
+            Error::Account(AccountError::AlreadyRegistered | AccountError::IdentityTaken) => {
+                StatusCode::CONFLICT
+            }
+            Error::Limit(LimitError::UnknownApiKey | LimitError::Anonymous) => {
+                StatusCode::UNAUTHORIZED
+            }
+            Error::Limit(LimitError::AnalysisQuota { .. } | LimitError::GameQuota { .. }) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            #[cfg(feature = "oauth")]
+            Error::OAuth(oauth::OAuthError::UnknownProvider) => StatusCode::NOT_FOUND,
+            #[cfg(feature = "oauth")]
+            Error::OAuth(oauth::OAuthError::InvalidState) => StatusCode::BAD_REQUEST,
+            #[cfg(feature = "oauth")]
+            Error::OAuth(oauth::OAuthError::Provider(_)) => StatusCode::BAD_GATEWAY,
+            #[cfg(feature = "webhooks")]
+            Error::Webhook(webhook::WebhookError::NotFound) => StatusCode::NOT_FOUND,
+            #[cfg(feature = "webhooks")]
+            Error::Webhook(_) => StatusCode::BAD_REQUEST,
+            Error::VersionConflict { .. } => StatusCode::CONFLICT,
+            Error::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::InBatch { error, .. } => error.status_code(),
+            Error::Maintenance | Error::Draining => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Storage(_) | Error::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A name for the error that clients can match on. Codes never change
+    /// once released, unlike the messages.
+    fn code(&self) -> &'static str {
+        match self {
+            Error::NotYourTurn => "not_your_turn",
+            Error::CellOccupied => "cell_occupied",
+            Error::GameOver => "game_over",
+            Error::InvalidMove(_) => "invalid_move",
+            Error::GameNotFound(_) => "game_not_found",
+            Error::OutOfBounds { .. } => "out_of_bounds",
+            Error::VersionConflict { .. } => "version_conflict",
+            Error::IdempotencyKeyReused => "idempotency_key_reused",
+            Error::InBatch { error, .. } => error.code(),
+            Error::BadRequest(_) => "bad_request",
+            Error::InvalidImport(_) => "invalid_import",
+            Error::InvalidBody(_) => "invalid_body",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::FeatureDisabled(_) => "feature_disabled",
+            Error::TenantNotFound(_) => "tenant_not_found",
+            Error::TournamentNotFound(_) => "tournament_not_found",
+            Error::Tournament(e) => match e {
+                TournamentError::RegistrationClosed => "registration_closed",
+                TournamentError::Full => "tournament_full",
+                TournamentError::DuplicateName => "duplicate_name",
+                TournamentError::NotEnoughEntrants => "not_enough_entrants",
+                TournamentError::TooManyRounds => "too_many_rounds",
+                TournamentError::UnknownGame => "game_not_in_tournament",
+            },
+            Error::MatchNotFound(_) => "match_not_found",
+            Error::ArenaNotFound(_) => "arena_not_found",
+            Error::Arena(e) => match e {
+                ArenaError::Over => "arena_over",
+                ArenaError::Full => "arena_full",
+                ArenaError::DuplicateName => "duplicate_name",
+                ArenaError::UnknownPlayer => "arena_player_not_found",
+                ArenaError::UnknownGame => "game_not_in_arena",
+            },
+            Error::Match(MatchError::UnknownGame) => "game_not_in_match",
+            Error::Lobby(e) => match e {
+                LobbyError::NotFound => "lobby_not_found",
+                LobbyError::AlreadyJoined => "lobby_already_joined",
+                LobbyError::TooManyLobbies => "too_many_lobbies",
+            },
+            Error::Invite(e) => match e {
+                InviteError::Invalid => "invalid_invite",
+                InviteError::Expired => "invite_expired",
+                InviteError::SeatTaken => "seat_taken",
+                InviteError::GameStarted => "game_started",
+            },
+            Error::Bot(e) => match e {
+                BotError::NotFound => "bot_not_found",
+                BotError::Offline => "bot_offline",
+                BotError::TooManyBots => "too_many_bots",
+                BotError::Timeout => "bot_timeout",
+                BotError::IllegalMove => "bot_illegal_move",
+            },
+            Error::Puzzle(e) => match e {
+                PuzzleError::NotFound => "puzzle_not_found",
+                PuzzleError::NoneAvailable => "no_puzzle_available",
+                PuzzleError::AttemptNotFound => "attempt_not_found",
+                PuzzleError::AlreadyAttempted => "puzzle_already_attempted",
+                PuzzleError::AlreadyAnswered => "attempt_already_answered",
+                PuzzleError::RushNotFound => "rush_not_found",
+                PuzzleError::RushOver => "rush_over",
+            },
+            Error::Account(e) => match e {
+                AccountError::InvalidSession => "invalid_session",
+                AccountError::SessionExpired => "session_expired",
+                AccountError::InvalidCredentials => "invalid_credentials",
+                AccountError::AlreadyRegistered => "already_registered",
+                AccountError::IdentityTaken => "identity_taken",
+                AccountError::NotFound => "account_not_found",
+            },
+            Error::Limit(e) => match e {
+                LimitError::UnknownApiKey => "unknown_api_key",
+                LimitError::Anonymous => "anonymous",
+                LimitError::AnalysisQuota { .. } => "analysis_quota_exceeded",
+                LimitError::GameQuota { .. } => "game_quota_exceeded",
+            },
+            #[cfg(feature = "oauth")]
+            Error::OAuth(e) => match e {
+                oauth::OAuthError::UnknownProvider => "unknown_provider",
+                oauth::OAuthError::InvalidState => "invalid_oauth_state",
+                oauth::OAuthError::Provider(_) => "oauth_provider_failed",
+            },
+            #[cfg(feature = "webhooks")]
+            Error::Webhook(e) => match e {
+                webhook::WebhookError::NotFound => "webhook_not_found",
+                webhook::WebhookError::InvalidUrl => "invalid_webhook_url",
+                webhook::WebhookError::PrivateAddress => "private_webhook_url",
+                webhook::WebhookError::TooMany => "too_many_webhooks",
+            },
+            Error::Maintenance => "maintenance",
+            Error::Draining => "draining",
+            Error::Storage(_) => "storage_failed",
+            Error::Internal => "internal_error",
+        }
+    }
+
+    /// Anything about the error a client can act on, beyond its message.
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::GameNotFound(id) => Some(serde_json::json!({ "game_id": id })),
+            Error::OutOfBounds { row, col } => Some(serde_json::json!({ "row": row, "col": col })),
+            Error::VersionConflict { expected, actual } => {
+                Some(serde_json::json!({ "expected": expected, "actual": actual }))
+            }
+            Error::InBatch { index, error } => {
+                let mut details = error.details().unwrap_or_else(|| serde_json::json!({}));
+                details["index"] = serde_json::json!(index);
+                Some(details)
+            }
+            Error::InvalidBody(reason) => Some(serde_json::json!({ "reason": reason })),
+            Error::FeatureDisabled(flag) => Some(serde_json::json!({ "flag": flag })),
+            Error::TenantNotFound(name) => Some(serde_json::json!({ "tenant": name })),
+            Error::TournamentNotFound(id) => Some(serde_json::json!({ "tournament_id": id })),
+            Error::MatchNotFound(id) => Some(serde_json::json!({ "match_id": id })),
+            Error::ArenaNotFound(id) => Some(serde_json::json!({ "arena_id": id })),
+            Error::Limit(LimitError::AnalysisQuota { resets_at }) => {
+                Some(serde_json::json!({ "resets_at": resets_at }))
+            }
+            Error::Limit(LimitError::GameQuota { limit }) => {
+                Some(serde_json::json!({ "limit": limit }))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<&Error> for ErrorBody {
+    fn from(error: &Error) -> Self {
+        Self {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            details: error.details(),
+        }
+    }
+}
+
+// The message shown to clients. Storage errors are logged rather than exposed.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotYourTurn => f.write_str("Not your turn"),
+            Error::CellOccupied => f.write_str("Cell already occupied"),
+            Error::GameOver => f.write_str("Game is not in progress"),
+            Error::InvalidMove(msg)
+            | Error::BadRequest(msg)
+            | Error::Unauthorized(msg)
+            | Error::Forbidden(msg) => f.write_str(msg),
+            Error::FeatureDisabled(flag) => {
+                write!(f, "The {} feature is turned off", flag.as_str())
+            }
+            Error::OutOfBounds { row, col } => {
+                write!(f, "Move ({row}, {col}) is outside the board")
+            }
+            Error::GameNotFound(game_id) => write!(f, "Game with id {} not found", game_id),
+            Error::VersionConflict { expected, actual } => write!(
+                f,
+                "Stale move: expected version {expected}, but game is at version {actual}"
+            ),
+            Error::IdempotencyKeyReused => {
+                f.write_str("Idempotency key was already used for a different move")
+            }
+            Error::InBatch { index, error } => write!(f, "Move at index {index}: {error}"),
+            Error::InvalidImport(e) => write!(f, "Invalid {}", e),
+            Error::InvalidBody(reason) => write!(f, "Failed to decode request body: {}", reason),
+            Error::TenantNotFound(name) => write!(f, "No tenant named {:?}", name),
+            Error::TournamentNotFound(id) => write!(f, "Tournament with id {} not found", id),
+            Error::Tournament(e) => write!(f, "{}", e),
+            Error::MatchNotFound(id) => write!(f, "Match with id {} not found", id),
+            Error::Match(e) => write!(f, "{}", e),
+            Error::ArenaNotFound(id) => write!(f, "Arena with id {} not found", id),
+            Error::Arena(e) => write!(f, "{}", e),
+            Error::Lobby(e) => write!(f, "{}", e),
+            Error::Invite(e) => write!(f, "{}", e),
+            Error::Bot(e) => write!(f, "{}", e),
+            Error::Puzzle(e) => write!(f, "{}", e),
+            Error::Account(e) => write!(f, "{}", e),
+            Error::Limit(e) => write!(f, "{}", e),
+            #[cfg(feature = "oauth")]
+            Error::OAuth(e) => write!(f, "{}", e),
+            #[cfg(feature = "webhooks")]
+            Error::Webhook(e) => write!(f, "{}", e),
+            Error::Maintenance => {
+                f.write_str("The server is in maintenance mode; new games cannot be started")
+            }
+            Error::Draining => {
+                f.write_str("The server is shutting down; start new games on another instance")
+            }
+            Error::Storage(_) => f.write_str("Failed to save the game"),
+            Error::Internal => f.write_str("Something went wrong on the server"),
+        }
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            Error::InvalidMove(msg) => (StatusCode::BAD_REQUEST, msg),
-        };
-        (status, error_message).into_response()
+        if let Error::Storage(e) = &self {
+            log::error!("Storage error: {}", e);
+        }
+        let body = ErrorBody::from(&self);
+        let mut response = (self.status_code(), Json(body.clone())).into_response();
+        // For `problem::negotiate`, which may swap the body for another.
+        response.extensions_mut().insert(body);
+        response
     }
 }
 
-// --- Game Logic Constants and Types ---
+/// Answers a request whose handler panicked with a `500` error like any
+/// other, instead of dropping the connection. The panic itself has been
+/// logged by the panic hook by now.
+fn panicked(panic: Box<dyn std::any::Any + Send>) -> Response {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    log::error!("Request handler panicked: {}", message);
+    metrics::record_panic();
+    Error::Internal.into_response()
+}
 
-static WINNING_LINES: [[(usize, usize); 3]; 8] = [
-    [(0, 0), (0, 1), (0, 2)],
-    [(1, 0), (1, 1), (1, 2)],
-    [(2, 0), (2, 1), (2, 2)], // Rows
-    [(0, 0), (1, 0), (2, 0)],
-    [(0, 1), (1, 1), (2, 1)],
-    [(0, 2), (1, 2), (2, 2)], // Columns
-    [(0, 0), (1, 1), (2, 2)],
-    [(0, 2), (1, 1), (2, 0)], // Diagonals
-];
+// --- Move Submission ---
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-enum Player {
-    X,
-    O,
+// The body of a move submission. `expected_version` is optional so older
+// clients keep working, but when present it must match the game's version.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+struct MoveRequest {
+    #[serde(flatten)]
+    player_move: PlayerMove,
+    expected_version: Option<u64>,
 }
 
-impl Player {
-    fn opponent(&self) -> Player {
-        match self {
-            Player::X => Player::O,
-            Player::O => Player::X,
-        }
+fn check_version(game_state: &GameState, expected_version: Option<u64>) -> Result<(), Error> {
+    match expected_version {
+        Some(expected) if expected != game_state.version => Err(Error::VersionConflict {
+            expected,
+            actual: game_state.version,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// --- Application State ---
+
+/// Header clients use to make move submissions safe to retry.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header callers send an API key from `[limits.api_keys]` in.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header players use to prove which side of a player-vs-player game is theirs.
+const SEAT_TOKEN_HEADER: &str = "seat-token";
+
+/// Header clients ask for the AI's reply to come in the background with
+/// (RFC 7240), and the one telling them it will.
+const PREFER_HEADER: &str = "prefer";
+const PREFERENCE_APPLIED_HEADER: &str = "preference-applied";
+
+// --- Game Operations ---
+
+// Shared by every API that creates games or submits moves.
+
+/// Creates a new game from the starting position `new_game`, adds it to the
+/// registry, and returns its ID and state. `blunder_chance` is the
+/// probability that the engine deliberately plays a weaker move on each turn;
+/// without one, the tuner picks it when auto-tuning, and it is 0 otherwise.
+/// A game started in a tenant can only be reached from that tenant.
+async fn create_game(
+    state: &AppState,
+    blunder_chance: Option<f64>,
+    new_game: GameState,
+    tenant: Option<String>,
+) -> Result<(Uuid, GameState), Error> {
+    state.accepting_games()?;
+    let (blunder_chance, auto_tuned) = match blunder_chance {
+        Some(blunder_chance) => (blunder_chance, false),
+        None => match state.tuner.blunder_chance() {
+            Some(blunder_chance) => (blunder_chance, true),
+            None => (0.0, false),
+        },
+    };
+    if !(0.0..=1.0).contains(&blunder_chance) {
+        return Err(Error::BadRequest("blunder_chance must be between 0 and 1"));
     }
+    let new_game_id = Uuid::new_v4();
+    let mut entry = GameEntry::new(new_game);
+    entry.blunder_chance = blunder_chance;
+    entry.auto_tuned = auto_tuned;
+    entry.tenant = tenant;
+    entry.experiment = state.experiment.assign(new_game_id);
+
+    state.insert_game(new_game_id, entry).await?;
+    state.publish(new_game_id, GameEvent::Created);
+
+    log::info!("Created new game with id: {}", new_game_id);
+    log::info!("Total number of games: {}", state.games.len());
+    Ok((new_game_id, new_game))
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-enum Cell {
-    Empty,
-    Occupied(Player),
+/// Registers a player-vs-player game built with `GameEntry::pvp`, after any
+/// tournament, match, or rule options have been set on it.
+async fn create_pvp_game(state: &AppState, entry: GameEntry) -> Result<Uuid, Error> {
+    let game_id = Uuid::new_v4();
+    state.insert_game(game_id, entry).await?;
+    state.publish(game_id, GameEvent::Created);
+    log::info!("Created player-vs-player game with id: {}", game_id);
+    Ok(game_id)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-enum GameStatus {
-    InProgress,
-    Draw,
-    Win(Player),
+/// Applies a move and archives the game if it is over. Against the engine the
+/// move is X's and the AI replies before this returns, after `engine.think_ms`
+/// if that is set; in player-vs-player games `seat_token` decides whose move
+/// it is.
+async fn play_move(
+    state: &AppState,
+    game_id: Uuid,
+    move_request: MoveRequest,
+    idempotency_key: Option<String>,
+    seat_token: Option<&str>,
+) -> Result<GameState, Error> {
+    let (game_state, reply) = submit_move(
+        state,
+        game_id,
+        move_request,
+        idempotency_key,
+        seat_token,
+        false,
+    )
+    .await?;
+    match reply {
+        Some(reply) => reply.await.map_err(|_| Error::Internal)?,
+        None => Ok(game_state),
+    }
 }
 
-type GameBoard = [[Cell; 3]; 3];
+/// The engine's reply being worked out in the background, which resolves to
+/// the game once it is played.
+type PendingReply = tokio::task::JoinHandle<Result<GameState, Error>>;
 
-// The state for a single game.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-struct GameState {
-    board: GameBoard,
-    status: GameStatus,
-    to_play: Player,
+/// Applies a move as `play_move` does, but against the engine only waits
+/// for the AI if it replies at once: with `engine.think_ms` set, or if
+/// `in_background`, the game is returned with just X's move and the reply
+/// follows in a task of its own. The task goes on even if its handle is
+/// dropped.
+async fn submit_move(
+    state: &AppState,
+    game_id: Uuid,
+    move_request: MoveRequest,
+    idempotency_key: Option<String>,
+    seat_token: Option<&str>,
+    in_background: bool,
+) -> Result<(GameState, Option<PendingReply>), Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    // Only this game is locked; moves in other games proceed concurrently.
+    let entry = game.lock().await;
+    let player = mover(state, &entry, seat_token.as_slice())?;
+
+    if let Some(key) = &idempotency_key
+        && let Some(response) = entry.replay_idempotent_move(key, &move_request)?
+    {
+        log::info!("Replayed idempotent move for game {}", game_id);
+        return Ok((response, None));
+    }
+
+    // Work on a copy so a failed AI move or storage write doesn't leave a
+    // half-applied turn behind.
+    let mut updated = entry.clone();
+    check_version(&updated.state, move_request.expected_version)?;
+    let first_new_event = updated.events.len();
+    let think_time = match updated.mode {
+        GameMode::VsEngine => state.engine.think_time(),
+        _ => Duration::ZERO,
+    };
+    let deferred =
+        matches!(updated.mode, GameMode::VsEngine) && (in_background || !think_time.is_zero());
+    let timed_out = apply_move(
+        state,
+        game_id,
+        &mut updated,
+        player,
+        move_request.player_move,
+        !deferred,
+    )
+    .await?;
+
+    let game_state = updated.state;
+    if let Some(key) = &idempotency_key {
+        updated
+            .idempotent_moves
+            .insert(key.clone(), (move_request, game_state));
+    }
+    // Marked before X's move is stored, so the reply sweep never finds the
+    // game waiting without a reply underway.
+    let replying = deferred.then(|| Replying::start(state, game_id, game_state.version));
+    commit_moves(state, game_id, entry, updated, first_new_event, timed_out).await?;
+    if timed_out {
+        log::info!("{:?} ran out of time in game {}", player, game_id);
+    }
+    match replying {
+        Some(replying) if game_state.status == GameStatus::InProgress => {
+            let reply = spawn_reply(state, game_id, think_time, idempotency_key, replying);
+            Ok((game_state, Some(reply)))
+        }
+        _ => Ok((game_state, None)),
+    }
 }
 
-impl Default for GameState {
-    fn default() -> Self {
+/// How often a reply that couldn't be stored is tried again, before it is
+/// left to the reply sweep.
+const REPLY_RETRY: Retry = Retry {
+    attempts: 4,
+    first_delay: Duration::from_secs(1),
+};
+
+/// How often the reply sweep looks for games left waiting on the engine.
+const REPLY_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Marks the engine's reply to the move that took a game to `version` as
+/// underway for as long as it is held.
+struct Replying {
+    replying: Arc<dashmap::DashMap<Uuid, u64>>,
+    game_id: Uuid,
+    version: u64,
+}
+
+impl Replying {
+    fn start(state: &AppState, game_id: Uuid, version: u64) -> Self {
+        state.replying.insert(game_id, version);
         Self {
-            board: [[Cell::Empty; 3]; 3],
-            status: GameStatus::InProgress,
-            to_play: Player::X,
+            replying: state.replying.clone(),
+            game_id,
+            version,
         }
     }
 }
 
-impl std::fmt::Display for GameState {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in &self.board {
-            for cell in row {
-                let symbol = match cell {
-                    Cell::Empty => ".",
-                    Cell::Occupied(Player::X) => "X",
-                    Cell::Occupied(Player::O) => "O",
-                };
-                write!(f, "{} ", symbol)?;
-            }
-            writeln!(f)?;
-        }
-        writeln!(f, "Status: {:?}", self.status)?;
-        writeln!(f, "Next to play: {:?}", self.to_play)
-    }
-}
-
-impl GameState {
-    fn check_status(&self) -> GameStatus {
-        for line in &WINNING_LINES {
-            let cells_in_line = [
-                self.board[line[0].0][line[0].1],
-                self.board[line[1].0][line[1].1],
-                self.board[line[2].0][line[2].1],
-            ];
-            if cells_in_line[0] != Cell::Empty
-                && cells_in_line[0] == cells_in_line[1]
-                && cells_in_line[1] == cells_in_line[2]
-            {
-                if let Cell::Occupied(player) = cells_in_line[0] {
-                    return GameStatus::Win(player);
-                }
+impl Drop for Replying {
+    fn drop(&mut self) {
+        // A reply to a later move may have been marked meanwhile.
+        self.replying
+            .remove_if(&self.game_id, |_, version| *version == self.version);
+    }
+}
+
+/// Plays the engine's reply with `engine_reply` in a task of its own, which
+/// goes on even if its handle is dropped. A reply that can't be stored is
+/// tried again in the background.
+fn spawn_reply(
+    state: &AppState,
+    game_id: Uuid,
+    think_time: Duration,
+    idempotency_key: Option<String>,
+    replying: Replying,
+) -> PendingReply {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let reply = engine_reply(&state, game_id, think_time, idempotency_key.clone()).await;
+        match &reply {
+            Ok(_) => {}
+            Err(Error::Storage(e)) => {
+                log::warn!(
+                    "Failed to store the engine's reply in game {}, retrying: {}",
+                    game_id,
+                    e
+                );
+                let retried = state.clone();
+                state.jobs.retry(
+                    format!("engine reply in game {game_id}"),
+                    REPLY_RETRY,
+                    move |_| {
+                        // Held until the last try is over.
+                        let _replying = &replying;
+                        let (state, idempotency_key) = (retried.clone(), idempotency_key.clone());
+                        async move {
+                            engine_reply(&state, game_id, Duration::ZERO, idempotency_key)
+                                .await
+                                .map(|_| ())
+                                .map_err(|e| e.to_string())
+                        }
+                    },
+                );
             }
+            Err(e) => log::error!("The engine couldn't reply in game {}: {}", game_id, e),
         }
+        reply
+    })
+}
 
-        if self
-            .board
-            .iter()
-            .all(|row| row.iter().all(|&cell| cell != Cell::Empty))
+/// Starts the engine's reply in every game against it that is waiting on
+/// one with none underway: a reply lost to a restart or crash while the
+/// engine was thinking, or one that couldn't be stored even when retried.
+/// Run at startup and every `REPLY_SWEEP_INTERVAL`.
+async fn resume_replies(state: AppState) {
+    let mut waiting = Vec::new();
+    // Locked games are in the middle of a move; the next sweep gets them.
+    for game in state.games.iter() {
+        let Ok(entry) = game.try_lock() else {
+            continue;
+        };
+        if matches!(entry.mode, GameMode::VsEngine)
+            && entry.state.status == GameStatus::InProgress
+            && entry.state.to_play == Player::O
+            && !state.replying.contains_key(game.key())
         {
-            return GameStatus::Draw;
+            waiting.push((*game.key(), entry.state.version));
         }
+    }
+    for (game_id, version) in waiting {
+        // With several instances, only the one that owns a game changes it.
+        if !cluster::is_local(&state, game_id).await {
+            continue;
+        }
+        log::info!("Resuming the engine's reply in game {}", game_id);
+        let replying = Replying::start(&state, game_id, version);
+        spawn_reply(&state, game_id, Duration::ZERO, None, replying);
+    }
+}
+
+/// Plays the engine's reply to X's move, already stored, once `think_time`
+/// has passed. The game isn't locked while the engine waits or searches, so
+/// it can be read in the meantime, and the search runs off the async
+/// workers. If the game changed meanwhile, say because X resigned, there is
+/// no reply. A replay of the move with `idempotency_key` gets the game as it
+/// is after the reply.
+async fn engine_reply(
+    state: &AppState,
+    game_id: Uuid,
+    think_time: Duration,
+    idempotency_key: Option<String>,
+) -> Result<GameState, Error> {
+    tokio::time::sleep(think_time).await;
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let (mut position, blunder_chance, strategy) = {
+        let entry = game.lock().await;
+        if entry.state.status != GameStatus::InProgress || entry.state.to_play != Player::O {
+            return Ok(entry.state);
+        }
+        (entry.state, entry.blunder_chance, entry.strategy())
+    };
+    let version = position.version;
+    let ai_move = tokio::task::spawn_blocking(move || {
+        do_handicapped_move(&mut position, blunder_chance, strategy)
+            .map(|ai_move| (position, ai_move))
+    })
+    .await
+    .map_err(|_| Error::Internal)?;
+    let (position, ai_move) = ai_move?;
 
-        GameStatus::InProgress
+    let entry = game.lock().await;
+    if entry.state.version != version {
+        return Ok(entry.state);
+    }
+    let mut updated = entry.clone();
+    let first_new_event = updated.events.len();
+    updated.state = position;
+    if let Some(ai_move) = ai_move {
+        updated.record_move(Player::O, ai_move);
     }
+    if updated.state.status != GameStatus::InProgress
+        && let Some(clock) = &mut updated.state.clock
+    {
+        clock.stop(updated.state.to_play, Utc::now());
+    }
+
+    let game_state = updated.state;
+    if let Some(key) = idempotency_key
+        && let Some((_, response)) = updated.idempotent_moves.get_mut(&key)
+    {
+        *response = game_state;
+    }
+    commit_moves(state, game_id, entry, updated, first_new_event, false).await?;
+    Ok(game_state)
 }
 
-// --- AI and Move Logic ---
+/// Plays `moves` in order, each answered by the AI or bot as a single move
+/// would be, and keeps all of them or, if any fails, none. In
+/// player-vs-player games each move is for the side to move, whose seat
+/// must be among `seat_tokens`.
+async fn play_moves(
+    state: &AppState,
+    game_id: Uuid,
+    moves: &[PlayerMove],
+    expected_version: Option<u64>,
+    seat_tokens: &[&str],
+) -> Result<GameState, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    let mut updated = entry.clone();
+    check_version(&updated.state, expected_version)?;
+    let first_new_event = updated.events.len();
+    let mut timed_out = false;
+    for (index, &player_move) in moves.iter().enumerate() {
+        let in_batch = |error| Error::InBatch {
+            index,
+            error: Box::new(error),
+        };
+        // A timeout ends the game, so any move after it fails as usual.
+        if timed_out {
+            return Err(in_batch(Error::GameOver));
+        }
+        let player = mover(state, &updated, seat_tokens).map_err(in_batch)?;
+        timed_out = apply_move(state, game_id, &mut updated, player, player_move, true)
+            .await
+            .map_err(in_batch)?;
+    }
+
+    let game_state = updated.state;
+    commit_moves(state, game_id, entry, updated, first_new_event, timed_out).await?;
+    log::info!("Played {} moves in game {}", moves.len(), game_id);
+    Ok(game_state)
+}
 
-#[derive(Debug, Deserialize, Copy, Clone)]
-struct PlayerMove {
-    row: usize,
-    col: usize,
+/// Whose move the caller is making: X's against the engine or a bot, and
+/// otherwise the side to move if the caller holds its seat, or else any
+/// seat they hold, so the move fails as out of turn.
+fn mover(state: &AppState, entry: &GameEntry, seat_tokens: &[&str]) -> Result<Player, Error> {
+    match &entry.mode {
+        GameMode::VsEngine => Ok(Player::X),
+        GameMode::VsBot { bot_id, .. } if !state.bots.is_online(*bot_id) => {
+            Err(Error::Bot(BotError::Offline))
+        }
+        GameMode::VsBot { .. } => Ok(Player::X),
+        mode @ (GameMode::Pvp { .. } | GameMode::Open { .. }) => {
+            let seats: Vec<Player> = seat_tokens
+                .iter()
+                .filter_map(|token| mode.seat_of(token))
+                .collect();
+            if seats.contains(&entry.state.to_play) {
+                Ok(entry.state.to_play)
+            } else {
+                seats.first().copied().ok_or(Error::Forbidden(
+                    "A valid seat token is required to move in this game",
+                ))
+            }
+        }
+    }
 }
 
-fn try_move(
-    game_state: &mut GameState,
+/// Plays `player`'s move on `updated` and, if `with_reply`, the engine's or
+/// bot's reply if it has one. Returns whether `player`'s clock ran out
+/// instead, which decides the game without the move.
+async fn apply_move(
+    state: &AppState,
+    game_id: Uuid,
+    updated: &mut GameEntry,
     player: Player,
     player_move: PlayerMove,
-) -> Result<(), Error> {
-    if game_state.status != GameStatus::InProgress {
-        return Err(Error::InvalidMove("Game is not in progress"));
+    with_reply: bool,
+) -> Result<bool, Error> {
+    if updated.state.clock.is_some_and(|clock| clock.is_paused()) {
+        return Err(Error::InvalidMove("Game is paused"));
     }
-    if game_state.to_play != player {
-        return Err(Error::InvalidMove("Not your turn"));
+    let now = Utc::now();
+    // Out-of-turn moves are left for `try_move` to reject; only the side to
+    // move has a clock running.
+    let on_turn = updated.state.status == GameStatus::InProgress && updated.state.to_play == player;
+    if on_turn
+        && let Some(clock) = &mut updated.state.clock
+        && clock.punch(player, now).is_err()
+    {
+        if clock.control.on_timeout == clock::TimeoutAction::RandomMove {
+            // The late move stands in for the random one the sweeper would
+            // have played.
+            clock.lapse(player, now);
+        } else {
+            // Otherwise the move isn't played; running out of time decides
+            // the game.
+            clock::apply_timeout(&mut updated.state, now)?;
+            return Ok(true);
+        }
     }
-    let target_cell = &mut game_state.board[player_move.row][player_move.col];
-    if *target_cell != Cell::Empty {
-        return Err(Error::InvalidMove("Cell already occupied"));
+    try_move(&mut updated.state, player, player_move)?;
+    // Moving instead of answering declines the opponent's draw offer.
+    if updated.state.draw_offer == Some(player.opponent()) {
+        updated.state.draw_offer = None;
     }
-
-    *target_cell = Cell::Occupied(game_state.to_play);
-    game_state.to_play = game_state.to_play.opponent();
-    game_state.status = game_state.check_status();
-
-    Ok(())
+    updated.record_move(player, player_move);
+    if with_reply {
+        reply(state, game_id, updated, now).await?;
+    } else if updated.state.status != GameStatus::InProgress
+        && let Some(clock) = &mut updated.state.clock
+    {
+        clock.stop(updated.state.to_play, now);
+    }
+    Ok(false)
 }
 
-fn minimax(game_state: &GameState) -> (i32, Option<PlayerMove>) {
-    match game_state.check_status() {
-        GameStatus::Win(winner) => {
-            return if winner == Player::X {
-                (10, None)
-            } else {
-                (-10, None)
-            };
+/// Plays the engine's or bot's move on `updated`, if O is theirs, and stops
+/// the clock if the game is over.
+async fn reply(
+    state: &AppState,
+    game_id: Uuid,
+    updated: &mut GameEntry,
+    now: chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    match updated.mode {
+        GameMode::VsEngine => {
+            let strategy = updated.strategy();
+            if let Some(ai_move) =
+                do_handicapped_move(&mut updated.state, updated.blunder_chance, strategy)?
+            {
+                updated.record_move(Player::O, ai_move);
+            }
         }
-        GameStatus::Draw => return (0, None),
-        GameStatus::InProgress => (),
-    }
-
-    let mut moves = Vec::new();
-    for r in 0..3 {
-        for c in 0..3 {
-            if game_state.board[r][c] == Cell::Empty {
-                let mut new_state = *game_state;
-                new_state.board[r][c] = Cell::Occupied(new_state.to_play);
-                new_state.to_play = new_state.to_play.opponent();
-                let (score, _) = minimax(&new_state);
-                moves.push((score, PlayerMove { row: r, col: c }));
+        GameMode::VsBot { bot_id, .. } => {
+            if let Some(bot_move) = bot::take_turn(state, bot_id, game_id, &mut updated.state).await
+            {
+                updated.record_move(Player::O, bot_move);
             }
         }
+        GameMode::Pvp { .. } | GameMode::Open { .. } => {}
     }
-
-    if game_state.to_play == Player::O {
-        // AI is minimizing
-        moves
-            .into_iter()
-            .min_by_key(|(score, _)| *score)
-            .map(|(s, m)| (s, Some(m)))
-            .unwrap()
-    } else {
-        // Human is maximizing
-        moves
-            .into_iter()
-            .max_by_key(|(score, _)| *score)
-            .map(|(s, m)| (s, Some(m)))
-            .unwrap()
+    if updated.state.status != GameStatus::InProgress
+        && let Some(clock) = &mut updated.state.clock
+    {
+        clock.stop(updated.state.to_play, now);
     }
+    Ok(())
 }
 
-fn do_optimal_move(game_state: &mut GameState) -> Result<(), Error> {
-    if game_state.status != GameStatus::InProgress {
-        return Ok(());
+/// Archives `updated` if its game is over, stores the events from
+/// `first_new_event` on and, only once they are stored, puts it in place
+/// of `entry` and tells everyone following the game.
+async fn commit_moves(
+    state: &AppState,
+    game_id: Uuid,
+    mut entry: MutexGuard<'_, GameEntry>,
+    mut updated: GameEntry,
+    first_new_event: usize,
+    timed_out: bool,
+) -> Result<(), Error> {
+    let status = updated.state.status;
+    // If the game is over, archive it so the result stays readable until the TTL runs out.
+    if status != GameStatus::InProgress {
+        updated.finish(Utc::now());
     }
 
-    let (_, optimal_move) = minimax(game_state);
-    if let Some(player_move) = optimal_move {
-        try_move(game_state, Player::O, player_move)
+    state
+        .store
+        .append_events(game_id, &updated.events[first_new_event..], &updated)
+        .await
+        .map_err(Error::Storage)?;
+    let (tournament_id, match_id, arena_id) =
+        (updated.tournament_id, updated.match_id, updated.arena_id);
+    *entry = updated;
+    drop(entry);
+    if timed_out {
+        state.publish(game_id, GameEvent::TimedOut);
     } else {
-        Err(Error::InvalidMove("AI could not find a valid move"))
+        state.publish(
+            game_id,
+            GameEvent::Moved {
+                finished: status != GameStatus::InProgress,
+            },
+        );
     }
-}
 
-// --- Application State ---
-
-// The shared application state: a map from a unique game ID to its state.
-type GameRegistry = HashMap<Uuid, GameState>;
-type AppState = Arc<RwLock<GameRegistry>>;
-
-// --- API Handlers ---
-
-/// Creates a new game, adds it to the registry, and returns the new game ID and state.
-async fn new_game(State(state): State<AppState>) -> impl IntoResponse {
-    let mut registry = state.write().await;
-    let new_game_id = Uuid::new_v4();
-    let new_game = GameState::default();
+    if status != GameStatus::InProgress {
+        game_finished(state, game_id, tournament_id, match_id, arena_id, status).await;
+    }
+    Ok(())
+}
 
-    registry.insert(new_game_id, new_game);
+/// Invokes the pie rule for O: the players change seats, so O takes over
+/// X's opening move and the first mover continues as O. Only possible
+/// between X's first move and O's reply.
+async fn swap_sides(
+    state: &AppState,
+    game_id: Uuid,
+    seat_token: Option<&str>,
+) -> Result<GameState, Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let mut entry = game.lock().await;
+    if entry.pie_rule == PieRule::Off {
+        return Err(Error::InvalidMove(
+            "The pie rule is not in effect for this game",
+        ));
+    }
+    if seat_token.and_then(|token| entry.mode.seat_of(token)) != Some(Player::O) {
+        return Err(Error::Forbidden("Only O can swap sides"));
+    }
+    if !entry.can_swap() {
+        return Err(Error::InvalidMove(
+            "Sides can only be swapped right after X's first move",
+        ));
+    }
 
-    log::info!("Created new game with id: {}", new_game_id);
-    log::info!("Total number of games: {}", registry.len());
+    let mut updated = entry.clone();
+    let GameMode::Pvp { x, o } = updated.mode else {
+        unreachable!("can_swap requires a player-vs-player game");
+    };
+    updated.mode = GameMode::Pvp { x: o, o: x };
+    updated.pie_rule = PieRule::Swapped;
+    state
+        .store
+        .update_mode(game_id, &updated)
+        .await
+        .map_err(Error::Storage)?;
+    let game_state = updated.state;
+    *entry = updated;
+    drop(entry);
 
-    Json(serde_json::json!({
-        "game_id": new_game_id,
-        "game_state": new_game
-    }))
+    state.presence.swap(game_id);
+    state.publish(game_id, GameEvent::Swapped);
+    log::info!("Players swapped sides in game {}", game_id);
+    Ok(game_state)
 }
 
-/// Updates a specific game state and removes it if the game is over.
-async fn update_game_state(
-    State(state): State<AppState>,
-    Path(game_id): Path<Uuid>,
-    Json(player_move): Json<PlayerMove>,
-) -> Result<Json<GameState>, Response> {
-    let mut registry = state.write().await;
-
-    // We use `get_mut` to ensure we can modify the state.
-    if let Some(mut game_state) = registry.get_mut(&game_id).copied() {
-        try_move(&mut game_state, Player::X, player_move).map_err(|e| e.into_response())?;
+/// Follow-up once a game has ended and been stored: advances its tournament,
+/// match, or arena, if any, and adds the result to the players' account
+/// stats.
+async fn game_finished(
+    state: &AppState,
+    game_id: Uuid,
+    tournament_id: Option<Uuid>,
+    match_id: Option<Uuid>,
+    arena_id: Option<Uuid>,
+    status: GameStatus,
+) {
+    log::info!("Game {} finished and was archived.", game_id);
+    calibration::record(state, game_id).await;
+    // The game itself is over either way; a failure to advance the
+    // tournament is the tournament's problem, not the players'.
+    if let Some(tournament_id) = tournament_id
+        && let Err(e) = tournament::record_result(state, tournament_id, game_id, status).await
+    {
+        log::error!(
+            "Failed to record game {} in tournament {}: {}",
+            game_id,
+            tournament_id,
+            e
+        );
+    }
+    if let Some(match_id) = match_id
+        && let Err(e) = matches::record_result(state, match_id, game_id, status).await
+    {
+        log::error!(
+            "Failed to record game {} in match {}: {}",
+            game_id,
+            match_id,
+            e
+        );
+    }
+    if let Some(arena_id) = arena_id
+        && let Err(e) = arena::record_result(state, arena_id, game_id, status).await
+    {
+        log::error!(
+            "Failed to record game {} in arena {}: {}",
+            game_id,
+            arena_id,
+            e
+        );
+    }
+    if let Err(e) = account::record_result(state, game_id, status).await {
+        log::error!("Failed to record game {} in account stats: {}", game_id, e);
+    }
+}
 
-        if game_state.status == GameStatus::InProgress {
-            do_optimal_move(&mut game_state).map_err(|e| e.into_response())?;
-        }
+/// Replays a move list played elsewhere and registers the result as a new
+/// game. If it is the AI's turn afterwards, the AI replies so the game can be
+/// continued right away.
+async fn import_game(
+    state: &AppState,
+    moves: &[(Player, PlayerMove)],
+    tenant: Option<String>,
+) -> Result<(Uuid, GameState), Error> {
+    state.accepting_games()?;
+    let mut entry = import::replay(moves).map_err(Error::InvalidImport)?;
+    entry.tenant = tenant;
 
-        // If the game is over, remove it from the registry.
-        // Otherwise, update the state in the registry.
-        if game_state.status != GameStatus::InProgress {
-            registry.remove(&game_id);
-            log::info!("Game {} finished and was removed.", game_id);
-            log::info!("Total number of games after removal: {}", registry.len());
-        } else {
-            // Update the state in the registry
-            *registry.get_mut(&game_id).unwrap() = game_state;
-        }
-
-        // Return the final or updated state to the client.
-        Ok(Json(game_state))
-    } else {
-        Err((
-            StatusCode::NOT_FOUND,
-            format!("Game with id {} not found", game_id),
-        )
-            .into_response())
+    if entry.state.to_play == Player::O
+        && let Some(ai_move) = do_optimal_move(&mut entry.state)?
+    {
+        entry.record_move(Player::O, ai_move);
     }
+    if entry.state.status != GameStatus::InProgress {
+        entry.finish(Utc::now());
+    }
+
+    // The entry's event log has the whole history, from the starting
+    // position on.
+    let game_id = Uuid::new_v4();
+    let game_state = entry.state;
+    state.insert_game(game_id, entry).await?;
+    state.publish(game_id, GameEvent::Created);
+    log::info!("Imported game {} with {} moves", game_id, moves.len());
+    Ok((game_id, game_state))
 }
 
 // --- Main Server Function ---
@@ -293,156 +1133,758 @@ async fn main() {
         .filter_level(log::LevelFilter::Info)
         .target(env_logger::Target::Stdout)
         .init();
-    // Initialize the shared state for the game registry.
-    let app_state = Arc::new(RwLock::new(GameRegistry::new()));
+
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Command::Bench(args)) => {
+            bench::run(args);
+            return;
+        }
+        Some(Command::Engine(args)) => {
+            if let Err(e) = stdio::run(args) {
+                log::error!("Engine I/O failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Puzzles(args)) => {
+            if let Err(e) = puzzle::run(args) {
+                log::error!("Failed to write puzzles to {}: {}", args.out.display(), e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Effective configuration:\n{}", config.to_toml());
+    // Held until the end of `main`, so reports still queued go out on
+    // shutdown.
+    #[cfg(feature = "sentry")]
+    let _reporting = reporting::init(&config.sentry);
+
+    // Initialize the shared state for the game registry, restoring any games
+    // saved by the previous run.
+    let store = store::connect(&config.storage).await.unwrap_or_else(|e| {
+        log::error!("Failed to open {:?} storage: {}", config.storage.backend, e);
+        std::process::exit(1);
+    });
+    let registry = store
+        .load(Utc::now() - config.games.finished_ttl())
+        .await
+        .unwrap_or_else(|e| {
+            log::error!("Failed to load games from storage: {}", e);
+            GameRegistry::new()
+        });
+    let tournaments = store.load_tournaments().await.unwrap_or_else(|e| {
+        log::error!("Failed to load tournaments from storage: {}", e);
+        Vec::new()
+    });
+    let saved_matches = store.load_matches().await.unwrap_or_else(|e| {
+        log::error!("Failed to load matches from storage: {}", e);
+        Vec::new()
+    });
+    let accounts = store.load_accounts().await.unwrap_or_else(|e| {
+        log::error!("Failed to load accounts from storage: {}", e);
+        Vec::new()
+    });
+    let usage = store.load_usage().await.unwrap_or_else(|e| {
+        log::error!("Failed to load limit usage from storage: {}", e);
+        Vec::new()
+    });
+    let audit_log = store.load_audit().await.unwrap_or_else(|e| {
+        log::error!("Failed to load the audit log from storage: {}", e);
+        Vec::new()
+    });
+    let daily_stats = store.load_daily_stats().await.unwrap_or_else(|e| {
+        log::error!("Failed to load daily stats from storage: {}", e);
+        Vec::new()
+    });
+    let puzzle_attempts = store.load_puzzle_attempts().await.unwrap_or_else(|e| {
+        log::error!("Failed to load puzzle attempts from storage: {}", e);
+        Vec::new()
+    });
+    let mailer = notify::connect(&config.email).unwrap_or_else(|e| {
+        log::error!("Failed to set up email: {}", e);
+        std::process::exit(1);
+    });
+    let puzzles = Puzzles::load(&config.puzzles.path).unwrap_or_else(|e| {
+        log::error!(
+            "Failed to load puzzles from {}: {}",
+            config.puzzles.path.display(),
+            e
+        );
+        Puzzles::default()
+    });
+    let puzzles = if puzzles.is_empty() {
+        log::warn!(
+            "No puzzles in {}; generating them now. Run `laika puzzles --out {}` to skip this at startup.",
+            config.puzzles.path.display(),
+            config.puzzles.path.display()
+        );
+        Puzzles::new(puzzle::generate())
+    } else {
+        puzzles
+    };
+    log::info!("Loaded {} puzzles", puzzles.len());
+    let app_state = AppState::new(registry, store)
+        .with_engine(config.engine.clone())
+        .with_lobbies(config.lobbies.clone())
+        .with_presence(config.presence.clone())
+        .with_bots(config.bots.clone())
+        .with_accounts(config.accounts.clone())
+        .with_limits(config.limits.clone())
+        .with_cluster(config.cluster.clone())
+        .with_tenants(config.tenants.clone())
+        .with_drain(config.server.drain_grace())
+        .with_public_url(config.server.public_url.as_deref())
+        .with_discord(&config.discord)
+        .with_flags(&config)
+        .with_experiment(config.experiment.clone())
+        .with_calibration(config.calibration.clone())
+        .with_puzzles(puzzles);
+    #[cfg(feature = "oauth")]
+    let app_state = app_state.with_oauth(&config.oauth);
+    #[cfg(feature = "slack")]
+    let app_state = app_state.with_slack(&config.slack);
+    #[cfg(feature = "telegram")]
+    let app_state = app_state.with_telegram(&config.telegram);
+    #[cfg(feature = "webpush")]
+    let app_state = app_state.with_push(push::Push::new(&config.push).unwrap_or_else(|e| {
+        log::error!("Failed to set up Web Push: {}", e);
+        std::process::exit(1);
+    }));
+    app_state.restore_tournaments(tournaments);
+    app_state.restore_matches(saved_matches);
+    app_state.puzzle_attempts.restore(puzzle_attempts);
+    app_state.accounts.restore(accounts).await;
+    app_state.limits.restore(usage).await;
+    app_state.audit.restore(audit_log);
+    app_state.rollups.restore(daily_stats);
+    // Replies the engine owed when the last run stopped are played now.
+    resume_replies(app_state.clone()).await;
+    let jobs = app_state.jobs.clone();
+    let resumed = app_state.clone();
+    jobs.every("reply sweep", REPLY_SWEEP_INTERVAL, move || {
+        resume_replies(resumed.clone())
+    });
+    let (purged, games_config) = (app_state.clone(), config.games.clone());
+    jobs.every("purge", config.games.purge_interval(), move || {
+        state::purge(purged.clone(), games_config.clone())
+    });
+    if config.storage.backend == StorageBackend::Snapshot {
+        let checkpointed = app_state.clone();
+        jobs.every(
+            "checkpoint",
+            config.storage.checkpoint_interval(),
+            move || state::checkpoint(checkpointed.clone()),
+        );
+    }
+    if let Some(after) = config.presence.forfeit_after() {
+        let forfeited = app_state.clone();
+        jobs.every("forfeit", presence::FORFEIT_CHECK_INTERVAL, move || {
+            presence::forfeit_absent(forfeited.clone(), after)
+        });
+    }
+    let (swept, clocks_config) = (app_state.clone(), config.clocks.clone());
+    jobs.every("clock sweep", clock::SWEEP_INTERVAL, move || {
+        clock::sweep(swept.clone(), clocks_config.clone())
+    });
+    let (rolled_up, stats_config) = (app_state.clone(), config.stats.clone());
+    jobs.daily("stats rollup", config.stats.rollup_hour, move || {
+        rollup::roll_up(rolled_up.clone(), stats_config.clone())
+    });
+    #[cfg(unix)]
+    jobs.spawn("drain signal", drain::start_on_signal(app_state.clone()));
+    #[cfg(feature = "webhooks")]
+    jobs.spawn(
+        "webhook dispatch",
+        webhook::dispatch_task(app_state.clone()),
+    );
+    #[cfg(feature = "webpush")]
+    jobs.spawn("push dispatch", push::dispatch_task(app_state.clone()));
+    if let Some(mailer) = mailer {
+        jobs.spawn(
+            "email reminders",
+            notify::remind_task(app_state.clone(), mailer, config.email.notify_after()),
+        );
+    }
+    #[cfg(feature = "redis")]
+    if let Some(url) = config.pubsub.redis_url.clone() {
+        jobs.spawn(
+            "pubsub relay",
+            pubsub::relay_task(app_state.clone(), config.pubsub.clone(), url),
+        );
+    }
 
     // Configure CORS to allow requests from the frontend server.
     let cors = CorsLayer::new()
-        .allow_origin(
-            "http://localhost:3001"
-                .parse::<axum::http::HeaderValue>()
-                .unwrap(),
-        )
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers(vec![axum::http::header::CONTENT_TYPE]);
+        .allow_origin(config.server.cors_origins())
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers(vec![
+            axum::http::header::ACCEPT,
+            axum::http::header::AUTHORIZATION,
+            axum::http::header::CONTENT_TYPE,
+            axum::http::header::IF_NONE_MATCH,
+            axum::http::HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+            axum::http::HeaderName::from_static(SEAT_TOKEN_HEADER),
+            axum::http::HeaderName::from_static(API_KEY_HEADER),
+            axum::http::HeaderName::from_static(PREFER_HEADER),
+        ])
+        .expose_headers([
+            axum::http::header::ETAG,
+            axum::http::HeaderName::from_static(PREFERENCE_APPLIED_HEADER),
+        ]);
 
     // Define the application routes.
-    let app = Router::new()
-        .route("/api/newgame", post(new_game))
-        .route("/api/games/{game_id}/move", post(update_game_state))
-        .with_state(app_state)
+    let app = api::router(app_state.clone(), &config.admin);
+    #[cfg(feature = "graphql")]
+    let app = app.merge(graphql::router(app_state.clone()));
+    let app = app
+        .layer(CatchPanicLayer::custom(panicked))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            config.server.request_timeout(),
+        ))
         .layer(cors);
+    // Tenant prefixes come off before the API's routes see the path.
+    let app = Router::new()
+        .fallback_service(app)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            tenant::strip_prefix,
+        ))
+        .layer(middleware::from_fn(i18n::localize))
+        .layer(middleware::from_fn(problem::negotiate))
+        .layer(compression(&config.server));
 
     // Start the server.
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let addr = config.server.bind;
     log::info!("Server starting...");
-    log::info!("Listening on http://{}", addr);
+    let handle = axum_server::Handle::new();
+    let (stopping, stop) = tokio::sync::watch::channel(false);
+    let (shutdown_handle, drained) = (handle.clone(), app_state.clone());
+    tokio::spawn(async move {
+        shutdown_requested(drained).await;
+        shutdown_handle.graceful_shutdown(None);
+        let _ = stopping.send(true);
+    });
+    #[cfg(unix)]
+    let unix_server = config.server.unix_socket().map(|path| {
+        let mut stop = stop.clone();
+        let stopped = async move {
+            let _ = stop.wait_for(|&stopping| stopping).await;
+        };
+        serve_unix(app.clone(), path, stopped)
+    });
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    if let Some(tls_config) = &config.server.tls {
+        tls::install_crypto_provider();
+        let rustls_config = tls::load(tls_config).await.unwrap_or_else(|e| {
+            log::error!(
+                "Failed to load TLS certificate from {}: {}",
+                tls_config.cert_path.display(),
+                e
+            );
+            std::process::exit(1);
+        });
+        jobs.spawn(
+            "tls reload",
+            tls::reload_on_change(rustls_config.clone(), tls_config.clone()),
+        );
+
+        log::info!("Listening on https://{}", addr);
+        let mut server = axum_server::bind_rustls(addr, rustls_config).handle(handle);
+        tune(&mut server, &config.server.connections);
+        server
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .expect("Failed to start server");
+    } else {
+        log::info!("Listening on http://{}", addr);
+        let mut server = axum_server::bind(addr).handle(handle);
+        tune(&mut server, &config.server.connections);
+        server
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .expect("Failed to start server");
+    }
+
+    #[cfg(unix)]
+    if let Some((unix_server, path)) = unix_server {
+        if unix_server.await.is_err() {
+            log::error!("The server on unix:{} stopped unexpectedly", path.display());
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    // Let sweeps and checkpoints underway finish before the final flush.
+    jobs.shutdown(jobs::SHUTDOWN_GRACE).await;
+    // In-flight requests have drained, so the registry is no longer changing.
+    let registry = app_state.snapshot_for_store().await;
+    if let Err(e) = app_state.store.flush(&registry).await {
+        log::error!("Failed to flush games to storage: {}", e);
+    }
+    cluster::release(&app_state).await;
+}
+
+/// Starts serving `app` on the Unix socket at `path`, next to the TCP
+/// listener, until `stopped`. A socket left behind by an earlier run is
+/// replaced. Connections are plain HTTP, without TLS or
+/// `server.connections`, as for a proxy on the same host.
+#[cfg(unix)]
+fn serve_unix(
+    app: Router,
+    path: &std::path::Path,
+    stopped: impl Future<Output = ()> + Send + 'static,
+) -> (tokio::task::JoinHandle<()>, std::path::PathBuf) {
+    let bound = match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => tokio::net::UnixListener::bind(path),
+    };
+    let listener = bound.unwrap_or_else(|e| {
+        log::error!("Failed to listen on unix:{}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    log::info!("Listening on unix:{}", path.display());
+    let server = axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(stopped)
+        .into_future();
+    let server = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Server on a Unix socket failed: {}", e);
+        }
+    });
+    (server, path.to_owned())
+}
+
+/// Applies `server.connections` to the connections `server` accepts.
+fn tune<A>(server: &mut axum_server::Server<A>, config: &config::ConnectionsConfig) {
+    let builder = server.http_builder();
+    builder
+        .http1()
+        .timer(hyper_util::rt::TokioTimer::new())
+        .keep_alive(config.keep_alive)
+        .header_read_timeout(config.header_read_timeout())
+        .max_buf_size(config.max_header_bytes as usize);
+    builder
+        .http2()
+        .timer(hyper_util::rt::TokioTimer::new())
+        .keep_alive_interval(config.http2_keep_alive_interval())
+        .keep_alive_timeout(config.http2_keep_alive_timeout())
+        .max_concurrent_streams(config.http2_max_concurrent_streams)
+        .max_header_list_size(config.max_header_bytes);
+}
+
+/// Compresses large responses with gzip or Brotli, but never event streams,
+/// which would be held back until a whole compressed block is ready, nor
+/// tagged ones, whose strong `ETag` names the uncompressed bytes.
+fn compression(config: &config::ServerConfig) -> CompressionLayer<impl Predicate + use<>> {
+    CompressionLayer::new()
+        .br(config.compression)
+        .gzip(config.compression)
+        .compress_when(
+            SizeAbove::new(config.compression_min_bytes)
+                .and(NotForContentType::GRPC)
+                .and(NotForContentType::IMAGES)
+                .and(NotForContentType::SSE)
+                .and(|_, _, headers: &axum::http::HeaderMap, _: &_| {
+                    !headers.contains_key(axum::http::header::ETAG)
+                }),
+        )
+}
+
+/// Resolves on a shutdown signal, or once a drain is over.
+async fn shutdown_requested(state: AppState) {
+    tokio::select! {
+        _ = shutdown_signal() => {},
+        _ = drain::drained(state) => {},
+    }
+}
+
+/// Resolves when the process receives SIGINT (Ctrl+C) or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    log::info!("Shutdown signal received, draining in-flight requests...");
 }
 
 #[cfg(test)]
 mod tests {
     // Import everything from the parent module (your main.rs code)
     use super::*;
-    use rand::rng;
-    use rand::seq::IndexedRandom;
 
-    /// This test plays 100 games with a random-move-making human player (X)
-    /// and asserts that the AI (O) never loses.
+    fn state() -> AppState {
+        AppState::new(GameRegistry::new(), Arc::new(store::MemoryStore))
+    }
+
+    /// A seat whose token is the player's name.
+    fn seat(name: &str) -> state::Seat {
+        state::Seat {
+            name: name.to_string(),
+            token: name.to_string(),
+        }
+    }
+
     #[test]
-    fn test_ai_is_unbeatable_over_100_random_games() {
-        for i in 0..100 {
-            println!("\n--- Starting Random Game #{} ---", i + 1);
-            let mut game_state = GameState::default();
-            let mut rng = rng();
-
-            // Loop until the game is no longer in progress.
-            while game_state.status == GameStatus::InProgress {
-                // It's always the human's turn first.
-                assert_eq!(game_state.to_play, Player::X);
-
-                // --- Human's Turn (Player X) ---
-                let mut available_moves = Vec::new();
-                for r in 0..3 {
-                    for c in 0..3 {
-                        if game_state.board[r][c] == Cell::Empty {
-                            available_moves.push(PlayerMove { row: r, col: c });
-                        }
-                    }
-                }
-
-                // If there are no moves, the game should already be over, but we break just in case.
-                if available_moves.is_empty() {
-                    break;
-                }
-
-                // Choose a random valid move for the human player.
-                let human_move = *available_moves.choose(&mut rng).unwrap();
-                println!(
-                    "Human (X) plays at ({}, {})",
-                    human_move.row, human_move.col
-                );
+    fn test_version_increments_and_stale_moves_are_rejected() {
+        let mut game_state = GameState::default();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+        do_optimal_move(&mut game_state).unwrap();
+        assert_eq!(game_state.version, 2);
 
-                // Apply the human's move.
-                try_move(&mut game_state, Player::X, human_move)
-                    .expect("Human move should be valid");
+        assert!(check_version(&game_state, None).is_ok());
+        assert!(check_version(&game_state, Some(2)).is_ok());
+        assert!(matches!(
+            check_version(&game_state, Some(0)),
+            Err(Error::VersionConflict {
+                expected: 0,
+                actual: 2
+            })
+        ));
+    }
 
-                // Check if the human's move ended the game.
-                if game_state.status != GameStatus::InProgress {
-                    break;
-                }
+    #[test]
+    fn test_panicking_handlers_get_a_500_error() {
+        let response = panicked(Box::new("index out of bounds"));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.extensions().get::<ErrorBody>().unwrap();
+        assert_eq!(body.code, "internal_error");
+        let response = panicked(Box::new(format!("row {} is out of range", 7)));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 
-                // --- AI's Turn (Player O) ---
-                assert_eq!(game_state.to_play, Player::O);
-                println!("AI (O) is thinking...");
+    #[test]
+    fn test_errors_have_stable_codes_and_details() {
+        let body = serde_json::to_value(ErrorBody::from(&Error::CellOccupied)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "code": "cell_occupied",
+                "message": "Cell already occupied",
+            })
+        );
+        let stale = Error::VersionConflict {
+            expected: 1,
+            actual: 3,
+        };
+        let body = serde_json::to_value(ErrorBody::from(&stale)).unwrap();
+        assert_eq!(body["code"], "version_conflict");
+        assert_eq!(
+            body["details"],
+            serde_json::json!({ "expected": 1, "actual": 3 })
+        );
+    }
 
-                // The AI makes its optimal move.
-                do_optimal_move(&mut game_state).expect("AI move should be valid");
-                println!("{}", game_state);
-            }
+    #[tokio::test]
+    async fn test_pie_rule_swaps_seats_after_the_first_move() {
+        let state = state();
+        let mut entry = GameEntry::pvp(seat("ada"), seat("bob"));
+        entry.pie_rule = PieRule::On;
+        let game_id = create_pvp_game(&state, entry).await.unwrap();
+        let center = MoveRequest {
+            player_move: PlayerMove { row: 1, col: 1 },
+            expected_version: None,
+        };
 
-            println!("Game Over. Final Status: {:?}", game_state.status);
+        // Nothing to swap before X has moved.
+        assert!(swap_sides(&state, game_id, Some("bob")).await.is_err());
+        play_move(&state, game_id, center, None, Some("ada"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            swap_sides(&state, game_id, Some("ada")).await,
+            Err(Error::Forbidden(_))
+        ));
+        swap_sides(&state, game_id, Some("bob")).await.unwrap();
 
-            // --- THE CORE ASSERTION ---
-            // The human player (X) should NEVER win.
-            // The game can be a Draw or a Win for O.
-            assert_ne!(
-                game_state.status,
-                GameStatus::Win(Player::X),
-                "AI FAILED: The AI lost a game! Final board:\n{}",
-                game_state
-            );
-        }
+        // Bob now owns the center as X, and Ada replies as O.
+        let corner = MoveRequest {
+            player_move: PlayerMove { row: 0, col: 0 },
+            expected_version: None,
+        };
+        assert!(
+            play_move(&state, game_id, corner, None, Some("bob"))
+                .await
+                .is_err()
+        );
+        let game_state = play_move(&state, game_id, corner, None, Some("ada"))
+            .await
+            .unwrap();
+        assert_eq!(game_state.to_play, Player::X);
+        assert!(swap_sides(&state, game_id, Some("ada")).await.is_err());
     }
 
-    #[test]
-    fn test_optimal_vs_optimal_is_always_a_draw() {
-        println!("\n--- Starting Optimal vs Optimal Game ---");
-        let mut game_state = GameState::default();
+    #[tokio::test]
+    async fn test_a_move_on_an_expired_clock_loses_on_time() {
+        let state = state();
+        let mut entry = GameEntry::pvp(seat("ada"), seat("bob"));
+        let mut clock = clock::Clock::new(clock::TimeControl {
+            initial_secs: 10,
+            increment_secs: 0,
+            mode: clock::IncrementMode::Fischer,
+            on_timeout: clock::TimeoutAction::Forfeit,
+        });
+        // X moved 11 seconds ago, and O has only just got round to replying.
+        clock
+            .punch(Player::X, Utc::now() - chrono::TimeDelta::seconds(11))
+            .unwrap();
+        try_move(&mut entry.state, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+        entry.state.clock = Some(clock);
+        let game_id = create_pvp_game(&state, entry).await.unwrap();
 
-        while game_state.status == GameStatus::InProgress {
-            // --- Player X's Turn (Optimal "Human") ---
-            if game_state.to_play == Player::X {
-                println!("Optimal Human (X) is thinking...");
-                // We manually find and apply the best move for 'X' since
-                // do_optimal_move is hardcoded for Player O.
-                let (_, optimal_move_for_x) = minimax(&game_state);
-                let player_move =
-                    optimal_move_for_x.expect("Minimax should always find a move for X");
+        let corner = MoveRequest {
+            player_move: PlayerMove { row: 0, col: 0 },
+            expected_version: None,
+        };
+        // X can't run O's clock down by trying to move out of turn.
+        assert!(matches!(
+            play_move(&state, game_id, corner, None, Some("ada")).await,
+            Err(Error::NotYourTurn)
+        ));
+        let game_state = play_move(&state, game_id, corner, None, Some("bob"))
+            .await
+            .unwrap();
+        assert_eq!(game_state.status, GameStatus::Win(Player::X));
+        assert_eq!(game_state.board.get(0, 0), game::Cell::Empty);
+        let clock = game_state.clock.unwrap();
+        assert_eq!((clock.o_ms, clock.running_since), (0, None));
+    }
 
-                try_move(&mut game_state, Player::X, player_move)
-                    .expect("Optimal move for X should be valid");
+    #[tokio::test]
+    async fn test_a_batch_of_moves_is_played_whole_or_not_at_all() {
+        let state = state();
+        let game_id = create_pvp_game(&state, GameEntry::pvp(seat("ada"), seat("bob")))
+            .await
+            .unwrap();
+        let at = |row, col| PlayerMove { row, col };
 
-                println!("{}", game_state);
-            }
+        // The third move takes an occupied cell, so the first two are undone.
+        let error = play_moves(
+            &state,
+            game_id,
+            &[at(1, 1), at(0, 0), at(1, 1)],
+            Some(0),
+            &["ada", "bob"],
+        )
+        .await
+        .unwrap_err();
+        let body = ErrorBody::from(&error);
+        assert_eq!(body.code, "cell_occupied");
+        assert_eq!(body.details, Some(serde_json::json!({ "index": 2 })));
+        let game = state.game(&game_id).unwrap();
+        assert_eq!(game.lock().await.state.version, 0);
 
-            // Check if Player X's move ended the game
-            if game_state.status != GameStatus::InProgress {
-                break;
-            }
+        // Holding only X's seat, O's reply is out of turn.
+        assert!(matches!(
+            play_moves(&state, game_id, &[at(1, 1), at(0, 0)], None, &["ada"]).await,
+            Err(Error::InBatch { index: 1, .. })
+        ));
+        let game_state = play_moves(
+            &state,
+            game_id,
+            &[at(1, 1), at(0, 0), at(0, 1), at(2, 2), at(2, 1)],
+            Some(0),
+            &["bob", "ada"],
+        )
+        .await
+        .unwrap();
+        assert_eq!(game_state.status, GameStatus::Win(Player::X));
+        assert_eq!(game.lock().await.events.len(), 7);
+    }
 
-            // --- Player O's Turn (AI) ---
-            if game_state.to_play == Player::O {
-                println!("AI (O) is thinking...");
-                // We can use the existing function here as it's designed for 'O'.
-                do_optimal_move(&mut game_state).expect("Optimal move for O should be valid");
-                println!("{}", game_state);
-            }
+    #[tokio::test]
+    async fn test_the_engine_thinks_without_holding_the_game() {
+        let state = state().with_engine(config::EngineConfig {
+            think_ms: 200,
+            think_jitter_ms: 0,
+        });
+        let (game_id, _) = create_game(&state, Some(0.0), GameState::default(), None)
+            .await
+            .unwrap();
+        let center = MoveRequest {
+            player_move: PlayerMove { row: 1, col: 1 },
+            expected_version: None,
+        };
+        let moving = tokio::spawn({
+            let state = state.clone();
+            async move { play_move(&state, game_id, center, Some("key".to_string()), None).await }
+        });
+
+        // While the engine thinks, X's move is stored and the game readable.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let game = state.game(&game_id).unwrap();
+        let thinking = game.try_lock().unwrap().state;
+        assert_eq!((thinking.version, thinking.to_play), (1, Player::O));
+
+        let replied = moving.await.unwrap().unwrap();
+        assert_eq!((replied.version, replied.to_play), (2, Player::X));
+        // A replay gets the game with the reply in it.
+        let replayed = play_move(&state, game_id, center, Some("key".to_string()), None)
+            .await
+            .unwrap();
+        assert_eq!(replayed, replied);
+    }
+
+    #[tokio::test]
+    async fn test_the_engine_can_reply_in_the_background() {
+        let state = state();
+        let (game_id, _) = create_game(&state, Some(0.0), GameState::default(), None)
+            .await
+            .unwrap();
+        let mut updates = state.updates.subscribe();
+        let center = MoveRequest {
+            player_move: PlayerMove { row: 1, col: 1 },
+            expected_version: None,
+        };
+
+        let (submitted, reply) = submit_move(&state, game_id, center, None, None, true)
+            .await
+            .unwrap();
+        assert_eq!((submitted.version, submitted.to_play), (1, Player::O));
+        let replied = reply.unwrap().await.unwrap().unwrap();
+        assert_eq!((replied.version, replied.to_play), (2, Player::X));
+        let game = state.game(&game_id).unwrap();
+        assert_eq!(game.lock().await.state, replied);
+        // X's move and the reply are announced one after the other.
+        for _ in 0..2 {
+            let update = updates.recv().await.unwrap();
+            assert_eq!(update.game_id, game_id);
         }
+    }
 
-        println!("Game Over. Final Status: {:?}", game_state.status);
+    #[tokio::test]
+    async fn test_a_reply_lost_to_a_restart_is_played_on_startup() {
+        let path = std::env::temp_dir().join(format!("laika-replies-{}.json", Uuid::new_v4()));
+        let state = AppState::new(
+            GameRegistry::new(),
+            Arc::new(store::SnapshotStore::new(path.clone())),
+        )
+        .with_engine(config::EngineConfig {
+            think_ms: 60_000,
+            think_jitter_ms: 0,
+        });
+        let (game_id, _) = create_game(&state, Some(0.0), GameState::default(), None)
+            .await
+            .unwrap();
+        let center = MoveRequest {
+            player_move: PlayerMove { row: 1, col: 1 },
+            expected_version: None,
+        };
+        let (submitted, reply) = submit_move(&state, game_id, center, None, None, false)
+            .await
+            .unwrap();
+        assert_eq!((submitted.version, submitted.to_play), (1, Player::O));
+        // The server goes down while the engine thinks.
+        reply.unwrap().abort();
+        drop(state);
 
-        // --- THE CORE ASSERTION ---
-        // A game between two perfect players must result in a draw.
-        assert_eq!(
-            game_state.status,
-            GameStatus::Draw,
-            "MINIMAX FAILED: A game between two optimal players did not result in a draw! Final board:\n{}",
-            game_state
+        let store = Arc::new(store::SnapshotStore::new(path.clone()));
+        let registry = store::GameStore::load(&*store, Utc::now()).await.unwrap();
+        let state = AppState::new(registry, store);
+        let mut updates = state.updates.subscribe();
+        resume_replies(state.clone()).await;
+        let update = tokio::time::timeout(Duration::from_secs(5), updates.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.game_id, game_id);
+        let game = state.game(&game_id).unwrap();
+        let replied = game.lock().await.state;
+        assert_eq!((replied.version, replied.to_play), (2, Player::X));
+        assert!(state.replying.is_empty());
+        // With the reply in, there is nothing left for the sweep.
+        resume_replies(state.clone()).await;
+        assert_eq!(game.lock().await.state, replied);
+        std::fs::remove_file(path.with_extension("journal.jsonl")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_only_large_responses_are_compressed() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let app = axum::Router::new()
+            .route("/small", axum::routing::get(|| async { "x".repeat(100) }))
+            .route("/large", axum::routing::get(|| async { "x".repeat(5000) }))
+            .layer(compression(&config::ServerConfig::default()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for (path, compressed) in [("/small", false), ("/large", true)] {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!(
+                "GET {path} HTTP/1.1\r\nHost: laika\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n"
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            let response = String::from_utf8_lossy(&response).to_lowercase();
+            assert_eq!(
+                response.contains("content-encoding: gzip"),
+                compressed,
+                "{path}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_the_unix_socket_replaces_a_stale_one_and_has_no_client_ip() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("laika-{}.sock", Uuid::new_v4()));
+        // Left behind by a run that didn't shut down cleanly.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let app = Router::new().route(
+            "/ip",
+            axum::routing::get(|api::ClientIp(ip): api::ClientIp| async move { format!("{ip:?}") }),
         );
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let (server, bound) = serve_unix(app, &path, async move {
+            let _ = stopped.await;
+        });
+        assert_eq!(bound, path);
+
+        let mut connection = tokio::net::UnixStream::connect(&path).await.unwrap();
+        connection
+            .write_all(b"GET /ip HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        connection.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("None"), "{response}");
+
+        let _ = stop.send(());
+        server.await.unwrap();
+        std::fs::remove_file(&path).unwrap();
     }
 }
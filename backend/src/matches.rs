@@ -0,0 +1,262 @@
+//! Best-of-N matches: two players play a series of player-vs-player games,
+//! alternating who starts, until one of them can no longer be caught.
+//!
+//! Like tournaments, the bookkeeping in `Match` is independent of the game
+//! registry: the operations below create each game, attach it, and feed the
+//! result back in when it finishes.
+
+use std::{fmt, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::{GameStatus, Player},
+    state::{AppState, GameEntry, Seat, new_token},
+};
+
+/// Longest match that can be created.
+pub const MAX_BEST_OF: u32 = 25;
+
+pub type SharedMatch = Arc<Mutex<Match>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchStatus {
+    InProgress,
+    Finished,
+}
+
+/// One game of a match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchGame {
+    pub game_id: Uuid,
+    /// Index into `Match::players` of whoever has X.
+    pub x: usize,
+    /// `None` while the game is being played.
+    pub result: Option<GameStatus>,
+}
+
+impl MatchGame {
+    /// Index of the player who won this game, if either did.
+    pub fn winner(&self) -> Option<usize> {
+        match self.result? {
+            GameStatus::Win(Player::X) => Some(self.x),
+            GameStatus::Win(Player::O) => Some(1 - self.x),
+            GameStatus::Draw | GameStatus::InProgress => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Match {
+    pub id: Uuid,
+    pub best_of: u32,
+    /// The first player has X in the first game; sides alternate after that.
+    /// Each seat token is used for every game of the match.
+    pub players: [Seat; 2],
+    /// Games won, by player index.
+    pub score: [u32; 2],
+    pub draws: u32,
+    pub games: Vec<MatchGame>,
+    pub status: MatchStatus,
+    /// Index of the winning player; `None` while in progress or if the match
+    /// ended level.
+    pub winner: Option<usize>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchError {
+    /// The game isn't part of this match, or its result is already in.
+    UnknownGame,
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            MatchError::UnknownGame => "That game is not being played in this match",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+impl Match {
+    pub fn new(names: [String; 2], best_of: u32) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            best_of,
+            players: names.map(|name| Seat {
+                name,
+                token: new_token(),
+            }),
+            score: [0, 0],
+            draws: 0,
+            games: Vec::new(),
+            status: MatchStatus::InProgress,
+            winner: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// The game being played right now.
+    pub fn current_game(&self) -> Option<Uuid> {
+        self.games
+            .last()
+            .filter(|game| game.result.is_none())
+            .map(|game| game.game_id)
+    }
+
+    /// Index of the player with X in the next game.
+    fn next_x(&self) -> usize {
+        self.games.len() % 2
+    }
+
+    /// The seats for the next game, X first.
+    pub fn next_seats(&self) -> (Seat, Seat) {
+        let x = self.next_x();
+        (self.players[x].clone(), self.players[1 - x].clone())
+    }
+
+    pub fn attach_game(&mut self, game_id: Uuid) {
+        let x = self.next_x();
+        self.games.push(MatchGame {
+            game_id,
+            x,
+            result: None,
+        });
+    }
+
+    /// Records a finished game. Returns whether another game is needed.
+    pub fn record_result(&mut self, game_id: Uuid, status: GameStatus) -> Result<bool, MatchError> {
+        let game = self
+            .games
+            .iter_mut()
+            .find(|game| game.game_id == game_id && game.result.is_none())
+            .ok_or(MatchError::UnknownGame)?;
+        game.result = Some(status);
+        match game.winner() {
+            Some(winner) => self.score[winner] += 1,
+            None => self.draws += 1,
+        }
+
+        // Over once the trailing player couldn't catch up even by winning
+        // every remaining game. Draws use up games too, so this always ends.
+        let remaining = self.best_of - self.games.len() as u32;
+        let [first, second] = self.score;
+        if first.abs_diff(second) > remaining || remaining == 0 {
+            self.status = MatchStatus::Finished;
+            self.winner = match first.cmp(&second) {
+                std::cmp::Ordering::Greater => Some(0),
+                std::cmp::Ordering::Less => Some(1),
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+        Ok(self.status == MatchStatus::InProgress)
+    }
+}
+
+// --- Operations ---
+
+pub async fn save(state: &AppState, m: &Match) -> Result<(), Error> {
+    state.store.save_match(m).await.map_err(Error::Storage)
+}
+
+async fn start_next_game(state: &AppState, m: &mut Match) -> Result<Uuid, Error> {
+    let (x, o) = m.next_seats();
+    let mut entry = GameEntry::pvp(x, o);
+    entry.match_id = Some(m.id);
+    let game_id = crate::create_pvp_game(state, entry).await?;
+    m.attach_game(game_id);
+    Ok(game_id)
+}
+
+/// Creates a match and its first game.
+pub async fn create(state: &AppState, names: [String; 2], best_of: u32) -> Result<Match, Error> {
+    state.accepting_games()?;
+    let mut m = Match::new(names, best_of);
+    start_next_game(state, &mut m).await?;
+    save(state, &m).await?;
+    state.matches.insert(m.id, Arc::new(Mutex::new(m.clone())));
+    log::info!("Created best-of-{} match {}", m.best_of, m.id);
+    Ok(m)
+}
+
+/// Feeds a finished game back into its match, starting the next game unless
+/// the match is decided.
+pub async fn record_result(
+    state: &AppState,
+    match_id: Uuid,
+    game_id: Uuid,
+    status: GameStatus,
+) -> Result<(), Error> {
+    let m = state
+        .find_match(&match_id)
+        .ok_or(Error::MatchNotFound(match_id))?;
+    let mut m = m.lock().await;
+    if m.record_result(game_id, status).map_err(Error::Match)? {
+        start_next_game(state, &mut m).await?;
+    } else {
+        log::info!("Match {} finished {}-{}", m.id, m.score[0], m.score[1]);
+    }
+    save(state, &m).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the current game of `m` with `winner` (a player index) winning,
+    /// or a draw.
+    fn play(m: &mut Match, winner: Option<usize>) -> bool {
+        let game_id = Uuid::new_v4();
+        m.attach_game(game_id);
+        let x = m.games.last().unwrap().x;
+        let status = match winner {
+            Some(winner) if winner == x => GameStatus::Win(Player::X),
+            Some(_) => GameStatus::Win(Player::O),
+            None => GameStatus::Draw,
+        };
+        m.record_result(game_id, status).unwrap()
+    }
+
+    #[test]
+    fn test_match_ends_once_the_leader_cannot_be_caught() {
+        let mut m = Match::new(["ada".to_string(), "bob".to_string()], 5);
+        assert!(play(&mut m, Some(0)));
+        assert!(play(&mut m, None));
+        assert!(play(&mut m, Some(0)));
+        // 2-0 with two games left can still be caught.
+        assert_eq!(m.status, MatchStatus::InProgress);
+        assert!(!play(&mut m, Some(0)));
+        assert_eq!(m.status, MatchStatus::Finished);
+        assert_eq!(m.winner, Some(0));
+        assert_eq!((m.score, m.draws), ([3, 0], 1));
+
+        // Sides alternated every game.
+        let sides: Vec<usize> = m.games.iter().map(|game| game.x).collect();
+        assert_eq!(sides, [0, 1, 0, 1]);
+        assert_eq!(m.current_game(), None);
+    }
+
+    #[test]
+    fn test_level_match_after_all_games_is_drawn() {
+        let mut m = Match::new(["ada".to_string(), "bob".to_string()], 3);
+        assert!(play(&mut m, Some(1)));
+        assert!(play(&mut m, Some(0)));
+        assert!(!play(&mut m, None));
+        assert_eq!(m.status, MatchStatus::Finished);
+        assert_eq!(m.winner, None);
+
+        let game_id = m.games[0].game_id;
+        assert_eq!(
+            m.record_result(game_id, GameStatus::Draw),
+            Err(MatchError::UnknownGame)
+        );
+    }
+}
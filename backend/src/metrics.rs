@@ -0,0 +1,250 @@
+//! Prometheus metrics, served at `/metrics`.
+//!
+//! Every API request is counted and timed by route and status class, so an
+//! error spike or a slow endpoint stands out. The engine's think time is
+//! recorded too: how long it took over each move, how many positions it
+//! visited, and how many plies it looked ahead, by board variant and
+//! difficulty. A slow move endpoint with a fast engine points at storage or
+//! serialization instead. Games against the engine are counted as they
+//! finish, by difficulty and how they ended, to tell how hard each
+//! difficulty really is.
+
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+    exponential_buckets,
+};
+
+use crate::{
+    calibration,
+    engine::SearchStats,
+    game::{GameState, GameStatus, Player},
+    search::Variant,
+};
+
+const LABELS: [&str; 2] = ["variant", "difficulty"];
+
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    panics: IntCounter,
+    engine_games: IntCounterVec,
+    request_seconds: HistogramVec,
+    search_seconds: HistogramVec,
+    search_nodes: HistogramVec,
+    search_depth: HistogramVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let histogram = |name: &str, help: &str, buckets: Vec<f64>| {
+        let opts = HistogramOpts::new(name, help).buckets(buckets);
+        HistogramVec::new(opts, &LABELS).expect("metric options are valid")
+    };
+    let metrics = Metrics {
+        registry: Registry::new_custom(Some("laika".to_string()), None)
+            .expect("the prefix is valid"),
+        requests: IntCounterVec::new(
+            Opts::new("http_requests_total", "API requests answered."),
+            &["method", "route", "status"],
+        )
+        .expect("metric options are valid"),
+        panics: IntCounter::new(
+            "http_panics_total",
+            "API requests whose handler panicked, answered with a 500.",
+        )
+        .expect("metric options are valid"),
+        engine_games: IntCounterVec::new(
+            Opts::new(
+                "engine_games_total",
+                "Games against the engine that finished, by how they ended for the player.",
+            ),
+            &["difficulty", "outcome"],
+        )
+        .expect("metric options are valid"),
+        // 1ms up to about 16s.
+        request_seconds: HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "How long API requests took to answer, up to the response headers.",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 15).expect("buckets are valid")),
+            &["method", "route"],
+        )
+        .expect("metric options are valid"),
+        // 50µs up to about 13s.
+        search_seconds: histogram(
+            "engine_search_seconds",
+            "How long the engine took to choose a move.",
+            exponential_buckets(0.00005, 4.0, 10).expect("buckets are valid"),
+        ),
+        // 1 up to about 16 million positions.
+        search_nodes: histogram(
+            "engine_search_nodes",
+            "Positions the engine visited to choose a move.",
+            exponential_buckets(1.0, 4.0, 13).expect("buckets are valid"),
+        ),
+        search_depth: histogram(
+            "engine_search_depth",
+            "Plies the engine looked ahead to choose a move.",
+            vec![
+                1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 12.0, 16.0, 24.0, 32.0, 64.0,
+            ],
+        ),
+    };
+    metrics
+        .registry
+        .register(Box::new(metrics.requests.clone()))
+        .expect("metric names are unique");
+    metrics
+        .registry
+        .register(Box::new(metrics.panics.clone()))
+        .expect("metric names are unique");
+    metrics
+        .registry
+        .register(Box::new(metrics.engine_games.clone()))
+        .expect("metric names are unique");
+    for histogram in [
+        &metrics.request_seconds,
+        &metrics.search_seconds,
+        &metrics.search_nodes,
+        &metrics.search_depth,
+    ] {
+        metrics
+            .registry
+            .register(Box::new(histogram.clone()))
+            .expect("metric names are unique");
+    }
+    metrics
+});
+
+/// Counts and times a request by its route's pattern, e.g.
+/// `/api/v1/games/{game_id}/move`, so every game shares one series. Only
+/// requests that matched a route are seen.
+pub async fn track(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(String::new, |path| path.as_str().to_string());
+    let method = request.method().clone();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let status = format!("{}xx", response.status().as_u16() / 100);
+    METRICS
+        .request_seconds
+        .with_label_values(&[method.as_str(), &route])
+        .observe(started.elapsed().as_secs_f64());
+    METRICS
+        .requests
+        .with_label_values(&[method.as_str(), &route, &status])
+        .inc();
+    response
+}
+
+/// Counts a request whose handler panicked. These don't show up in
+/// `http_requests_total`, since the panic cut `track` short.
+pub fn record_panic() {
+    METRICS.panics.inc();
+}
+
+/// The variants of the game, e.g. `large+toroidal`.
+fn variant_label(game_state: &GameState) -> String {
+    Variant::of(game_state)
+        .iter()
+        .map(|variant| variant.label())
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Records one move the engine chose for `game_state`.
+pub fn record_search(
+    game_state: &GameState,
+    blunder_chance: f64,
+    elapsed: Duration,
+    stats: SearchStats,
+) {
+    let variant = variant_label(game_state);
+    let difficulty = calibration::difficulty(blunder_chance);
+    let labels = [variant.as_str(), difficulty.as_str()];
+    METRICS
+        .search_seconds
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+    METRICS
+        .search_nodes
+        .with_label_values(&labels)
+        .observe(stats.nodes as f64);
+    METRICS
+        .search_depth
+        .with_label_values(&labels)
+        .observe(f64::from(stats.depth));
+}
+
+/// Counts a game against the engine that finished with `status`.
+pub fn record_engine_game(blunder_chance: f64, status: GameStatus) {
+    let outcome = match status {
+        GameStatus::Win(Player::X) => "player_win",
+        GameStatus::Win(Player::O) => "engine_win",
+        GameStatus::Draw => "draw",
+        GameStatus::InProgress => return,
+    };
+    METRICS
+        .engine_games
+        .with_label_values(&[calibration::difficulty(blunder_chance).as_str(), outcome])
+        .inc();
+}
+
+/// Every metric, in Prometheus' text format.
+fn render() -> String {
+    let mut out = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut out)
+        .expect("metrics encode as text");
+    String::from_utf8(out).expect("the text format is UTF-8")
+}
+
+pub async fn export() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{Strategy, do_handicapped_move},
+        game::{PlayerMove, Rules, try_move},
+    };
+
+    #[test]
+    fn test_engine_moves_are_recorded_by_variant_and_difficulty() {
+        let rules = Rules {
+            toroidal: true,
+            win_length: 4,
+        };
+        let mut game_state = GameState::custom(4, 4, rules, &[]).unwrap();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 0, col: 0 }).unwrap();
+        do_handicapped_move(&mut game_state, 0.3, Strategy::Standard).unwrap();
+
+        let rendered = render();
+        let count = rendered
+            .lines()
+            .find(|line| {
+                line.starts_with("laika_engine_search_depth_count")
+                    && line.contains(r#"variant="large+toroidal""#)
+                    && line.contains(r#"difficulty="0.3""#)
+            })
+            .unwrap();
+        assert!(count.ends_with(" 1"));
+        assert!(rendered.contains("# TYPE laika_engine_search_seconds histogram"));
+        assert!(rendered.contains("laika_engine_search_nodes_bucket"));
+    }
+}
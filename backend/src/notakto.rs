@@ -0,0 +1,450 @@
+//! Notakto: tic-tac-toe where both players place X's and whoever completes
+//! three in a row loses. It can be played on several boards at once. A board
+//! with three in a row is dead and takes no more moves, and whoever kills the
+//! last live board loses.
+//!
+//! With both sides playing the same mark, the X-maximizes/O-minimizes
+//! scoring in `engine` has nothing to go on, so the search here scores
+//! positions for the side to move instead. Notakto games are played against
+//! the engine and kept in memory only; finished ones are purged on the same
+//! TTL as regular games.
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{Error, game::has_line, solver::Outcome, state::AppState};
+
+/// Most boards a game can be played on.
+pub const MAX_BOARDS: usize = 3;
+
+/// Every cell of a 3x3 board.
+const FULL_BOARD: u16 = 0b111_111_111;
+
+pub type SharedNotakto = Arc<Mutex<NotaktoGame>>;
+
+/// A move: one more X on one of the boards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotaktoMove {
+    pub board: usize,
+    pub row: usize,
+    pub col: usize,
+}
+
+impl NotaktoMove {
+    fn bit(&self) -> u16 {
+        1 << (self.row * 3 + self.col)
+    }
+}
+
+/// The boards of a game, each as a mask of the cells holding an X, using the
+/// same bit layout as `game::Board`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub boards: Vec<u16>,
+}
+
+impl Position {
+    pub fn new(boards: usize) -> Self {
+        Self {
+            boards: vec![0; boards],
+        }
+    }
+
+    pub fn is_dead(&self, board: usize) -> bool {
+        has_line(self.boards[board])
+    }
+
+    /// Whether every board is dead, which means the last mover lost.
+    pub fn is_over(&self) -> bool {
+        (0..self.boards.len()).all(|board| self.is_dead(board))
+    }
+
+    /// Places an X, rejecting moves on dead boards and occupied cells.
+    pub fn play(&mut self, player_move: NotaktoMove) -> Result<(), Error> {
+        if player_move.board >= self.boards.len() {
+            return Err(Error::BadRequest("No board with that index"));
+        }
+        if player_move.row >= 3 || player_move.col >= 3 {
+            return Err(Error::OutOfBounds {
+                row: player_move.row,
+                col: player_move.col,
+            });
+        }
+        if self.is_dead(player_move.board) {
+            return Err(Error::InvalidMove("That board is already dead"));
+        }
+        let mask = &mut self.boards[player_move.board];
+        if *mask & player_move.bit() != 0 {
+            return Err(Error::CellOccupied);
+        }
+        *mask |= player_move.bit();
+        Ok(())
+    }
+
+    /// Every legal move, board by board in row-major order.
+    pub fn legal_moves(&self) -> impl Iterator<Item = NotaktoMove> + '_ {
+        (0..self.boards.len())
+            .filter(|&board| !self.is_dead(board))
+            .flat_map(move |board| {
+                let mask = self.boards[board];
+                (0..9)
+                    .filter(move |i| mask & (1 << i) == 0)
+                    .map(move |i| NotaktoMove {
+                        board,
+                        row: i / 3,
+                        col: i % 3,
+                    })
+            })
+    }
+
+    /// The live boards, each reduced to its smallest symmetric variant, in
+    /// sorted order. Positions with the same key play identically.
+    fn key(&self) -> Key {
+        let mut key: Key = self
+            .boards
+            .iter()
+            .filter(|&&mask| !has_line(mask))
+            .map(|&mask| canonical(mask))
+            .collect();
+        key.sort_unstable();
+        key
+    }
+}
+
+/// Maps each cell to where it lands under a rotation or reflection of the
+/// board. The first entry is the identity.
+const SYMMETRIES: [[usize; 9]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8],
+    [6, 3, 0, 7, 4, 1, 8, 5, 2],
+    [8, 7, 6, 5, 4, 3, 2, 1, 0],
+    [2, 5, 8, 1, 4, 7, 0, 3, 6],
+    [2, 1, 0, 5, 4, 3, 8, 7, 6],
+    [6, 7, 8, 3, 4, 5, 0, 1, 2],
+    [0, 3, 6, 1, 4, 7, 2, 5, 8],
+    [8, 5, 2, 7, 4, 1, 6, 3, 0],
+];
+
+fn canonical(mask: u16) -> u16 {
+    SYMMETRIES
+        .iter()
+        .map(|symmetry| {
+            (0..9)
+                .filter(|&i| mask & (1 << i) != 0)
+                .fold(0, |image, i| image | (1 << symmetry[i]))
+        })
+        .min()
+        .expect("there is at least the identity")
+}
+
+type Key = Vec<u16>;
+
+/// The value of a position for the side to move. There are no draws: a full
+/// board always has three in a row, so every game ends with a loser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    pub outcome: Outcome,
+    /// Plies until the last board dies with perfect play from both sides.
+    pub plies: u32,
+    /// A move that achieves the value; `None` once the game is over.
+    pub best_move: Option<NotaktoMove>,
+}
+
+/// Solves the position exactly. The winning side forces the end as quickly
+/// as it can and the losing side holds out as long as it can, so the engine
+/// never throws away a lost game early.
+pub fn solve(position: &Position) -> Value {
+    let mut memo = HashMap::new();
+    let mut best: Option<Value> = None;
+    for player_move in position.legal_moves() {
+        let mut after = position.clone();
+        after.play(player_move).expect("legal moves can be played");
+        let (outcome, plies) = value(&after.key(), &mut memo);
+        let value = Value {
+            outcome: flip(outcome),
+            plies: plies + 1,
+            best_move: Some(player_move),
+        };
+        if best.is_none_or(|best| preference(&value) > preference(&best)) {
+            best = Some(value);
+        }
+    }
+    // With no legal moves left, the opponent killed the last board.
+    best.unwrap_or(Value {
+        outcome: Outcome::Win,
+        plies: 0,
+        best_move: None,
+    })
+}
+
+fn flip(outcome: Outcome) -> Outcome {
+    match outcome {
+        Outcome::Win => Outcome::Loss,
+        Outcome::Loss => Outcome::Win,
+        Outcome::Draw => Outcome::Draw,
+    }
+}
+
+/// Orders values from the mover's point of view: fast wins first, then slow
+/// losses, then fast losses.
+fn preference(value: &Value) -> (i8, i64) {
+    match value.outcome {
+        Outcome::Win => (1, -i64::from(value.plies)),
+        Outcome::Draw => (0, 0),
+        Outcome::Loss => (-1, i64::from(value.plies)),
+    }
+}
+
+fn value(key: &Key, memo: &mut HashMap<Key, (Outcome, u32)>) -> (Outcome, u32) {
+    if key.is_empty() {
+        return (Outcome::Win, 0);
+    }
+    if let Some(&value) = memo.get(key) {
+        return value;
+    }
+
+    let mut best: Option<Value> = None;
+    for (index, &mask) in key.iter().enumerate() {
+        let mut empty = FULL_BOARD & !mask;
+        while empty != 0 {
+            let bit = empty & empty.wrapping_neg();
+            empty &= !bit;
+            let mut child = key.clone();
+            if has_line(mask | bit) {
+                child.remove(index);
+            } else {
+                child[index] = canonical(mask | bit);
+                child.sort_unstable();
+            }
+            let (outcome, plies) = value(&child, memo);
+            let value = Value {
+                outcome: flip(outcome),
+                plies: plies + 1,
+                best_move: None,
+            };
+            if best.is_none_or(|best| preference(&value) > preference(&best)) {
+                best = Some(value);
+            }
+        }
+    }
+    let best = best.expect("a live board always has an empty cell");
+    memo.insert(key.clone(), (best.outcome, best.plies));
+    (best.outcome, best.plies)
+}
+
+/// Who is playing a side of a Notakto game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Human,
+    Engine,
+}
+
+impl Side {
+    pub fn opponent(self) -> Side {
+        match self {
+            Side::Human => Side::Engine,
+            Side::Engine => Side::Human,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotaktoGame {
+    pub id: Uuid,
+    pub position: Position,
+    /// Who made the first move.
+    pub first: Side,
+    pub moves: Vec<NotaktoMove>,
+    /// Whoever killed the last live board; `None` while in progress.
+    pub loser: Option<Side>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl NotaktoGame {
+    pub fn new(boards: usize, first: Side) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            position: Position::new(boards),
+            first,
+            moves: Vec::new(),
+            loser: None,
+            created_at: Utc::now(),
+            finished_at: None,
+        }
+    }
+
+    pub fn to_play(&self) -> Side {
+        if self.moves.len().is_multiple_of(2) {
+            self.first
+        } else {
+            self.first.opponent()
+        }
+    }
+
+    /// Plays a move for `side`, finishing the game if it killed the last
+    /// board.
+    pub fn play(&mut self, side: Side, player_move: NotaktoMove) -> Result<(), Error> {
+        if self.loser.is_some() {
+            return Err(Error::GameOver);
+        }
+        if self.to_play() != side {
+            return Err(Error::NotYourTurn);
+        }
+        self.position.play(player_move)?;
+        self.moves.push(player_move);
+        if self.position.is_over() {
+            self.loser = Some(side);
+            self.finished_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    /// Lets the engine move if it is its turn.
+    fn engine_reply(&mut self) -> Result<(), Error> {
+        if self.loser.is_some() || self.to_play() != Side::Engine {
+            return Ok(());
+        }
+        let engine_move = solve(&self.position)
+            .best_move
+            .ok_or(Error::InvalidMove("AI could not find a valid move"))?;
+        self.play(Side::Engine, engine_move)
+    }
+}
+
+/// Removes finished Notakto games whose TTL has elapsed. Returns the number
+/// removed. Games that are locked are in use and are left for the next sweep.
+pub fn purge_expired(
+    games: &DashMap<Uuid, SharedNotakto>,
+    now: DateTime<Utc>,
+    ttl: TimeDelta,
+) -> usize {
+    let before = games.len();
+    games.retain(|_, game| {
+        game.try_lock().map_or(true, |game| {
+            game.finished_at
+                .is_none_or(|finished_at| now - finished_at < ttl)
+        })
+    });
+    before - games.len()
+}
+
+// --- Operations ---
+
+/// Starts a game against the engine on `boards` boards. If the engine goes
+/// first, its opening move is already played.
+pub async fn create(state: &AppState, boards: usize, first: Side) -> Result<NotaktoGame, Error> {
+    state.accepting_games()?;
+    if !(1..=MAX_BOARDS).contains(&boards) {
+        return Err(Error::BadRequest("boards must be between 1 and 3"));
+    }
+    let mut game = NotaktoGame::new(boards, first);
+    game.engine_reply()?;
+    state
+        .notakto
+        .insert(game.id, Arc::new(Mutex::new(game.clone())));
+    log::info!("Created Notakto game {} on {} boards", game.id, boards);
+    Ok(game)
+}
+
+/// Plays the human's move and the engine's reply.
+pub async fn play(
+    state: &AppState,
+    game_id: Uuid,
+    player_move: NotaktoMove,
+) -> Result<NotaktoGame, Error> {
+    let game = state
+        .notakto
+        .get(&game_id)
+        .map(|game| game.clone())
+        .ok_or(Error::GameNotFound(game_id))?;
+    let mut game = game.lock().await;
+    // Work on a copy so a rejected move leaves nothing half-applied.
+    let mut updated = game.clone();
+    updated.play(Side::Human, player_move)?;
+    updated.engine_reply()?;
+    *game = updated;
+    if let Some(loser) = game.loser {
+        log::info!("Notakto game {} finished; {:?} lost", game_id, loser);
+    }
+    Ok(game.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(board: usize, row: usize, col: usize) -> NotaktoMove {
+        NotaktoMove { board, row, col }
+    }
+
+    #[test]
+    fn test_completing_the_last_live_board_loses() {
+        let mut game = NotaktoGame::new(2, Side::Human);
+        for (side, player_move) in [
+            (Side::Human, at(0, 0, 0)),
+            (Side::Engine, at(0, 0, 1)),
+            (Side::Human, at(0, 0, 2)),
+        ] {
+            game.play(side, player_move).unwrap();
+        }
+        // The first board is dead, but the second is still live.
+        assert!(game.position.is_dead(0));
+        assert_eq!(game.loser, None);
+        assert!(matches!(
+            game.play(Side::Engine, at(0, 1, 1)),
+            Err(Error::InvalidMove(_))
+        ));
+
+        for (side, player_move) in [
+            (Side::Engine, at(1, 1, 1)),
+            (Side::Human, at(1, 0, 0)),
+            (Side::Engine, at(1, 2, 2)),
+        ] {
+            game.play(side, player_move).unwrap();
+        }
+        assert_eq!(game.loser, Some(Side::Engine));
+        assert!(game.finished_at.is_some());
+        assert!(matches!(
+            game.play(Side::Human, at(1, 0, 1)),
+            Err(Error::GameOver)
+        ));
+    }
+
+    #[test]
+    fn test_the_first_player_wins_a_single_board_by_taking_the_center() {
+        let value = solve(&Position::new(1));
+        assert_eq!(value.outcome, Outcome::Win);
+        assert_eq!(value.best_move, Some(at(0, 1, 1)));
+
+        // A corner opening throws the win away.
+        let mut position = Position::new(1);
+        position.play(at(0, 0, 0)).unwrap();
+        assert_eq!(solve(&position).outcome, Outcome::Win);
+    }
+
+    #[test]
+    fn test_engine_wins_from_the_winning_side() {
+        for boards in 1..=MAX_BOARDS {
+            // One and three boards are first-player wins; two boards are not.
+            let first = match solve(&Position::new(boards)).outcome {
+                Outcome::Win => Side::Engine,
+                _ => Side::Human,
+            };
+            assert_eq!(first == Side::Engine, boards != 2, "{boards} boards");
+            let mut game = NotaktoGame::new(boards, first);
+            game.engine_reply().unwrap();
+            while game.loser.is_none() {
+                let player_move = game.position.legal_moves().next().unwrap();
+                game.play(Side::Human, player_move).unwrap();
+                game.engine_reply().unwrap();
+            }
+            assert_eq!(game.loser, Some(Side::Human), "{boards} boards");
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Canonical text notation for games, e.g. `X:b2 O:a1 X:c3`.
+//!
+//! Each move is `<player>:<square>`, separated by single spaces. Squares use
+//! chess-style coordinates: the file `a`..`c` is the column from the left and
+//! the rank `1`..`3` is the row counted from the bottom, so `a3` is the top
+//! left cell (row 0, column 0) and `c1` the bottom right (row 2, column 2).
+//!
+//! Games on other board sizes can be written out the same way, with files
+//! and ranks running as far as the board does. Single squares can be read
+//! on any board, but whole games only on 3x3.
+
+use std::fmt;
+
+use crate::game::{MoveRecord, Player, PlayerMove};
+
+const SIZE: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotationError {
+    /// Zero-based position of the offending move in the input.
+    pub index: usize,
+    pub token: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "move {} ({:?}): {}",
+            self.index + 1,
+            self.token,
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+/// Formats a single cell as a square name such as `b2`.
+pub fn square(player_move: PlayerMove) -> String {
+    square_on(SIZE, player_move)
+}
+
+/// Like `square`, on a board with `rows` rows. Ranks count from the bottom,
+/// so the same row has a different rank on a taller board.
+pub fn square_on(rows: usize, player_move: PlayerMove) -> String {
+    let file = (b'a' + player_move.col as u8) as char;
+    let rank = rows - player_move.row;
+    format!("{}{}", file, rank)
+}
+
+/// Parses a square name such as `b2`. Returns `None` for anything that is not
+/// on the board.
+pub fn parse_square(square: &str) -> Option<PlayerMove> {
+    parse_square_on((SIZE, SIZE), square)
+}
+
+/// Like `parse_square`, on a board of `rows` by `cols`, which is at most
+/// `MAX_SIDE` on each side, so ranks are a single digit.
+pub fn parse_square_on((rows, cols): (usize, usize), square: &str) -> Option<PlayerMove> {
+    let &[file, rank] = square.as_bytes() else {
+        return None;
+    };
+    let col = file.to_ascii_lowercase().checked_sub(b'a')? as usize;
+    let rank = rank.checked_sub(b'0')? as usize;
+    if col >= cols || !(1..=rows).contains(&rank) {
+        return None;
+    }
+    Some(PlayerMove {
+        row: rows - rank,
+        col,
+    })
+}
+
+/// Formats the move history of a game on a board with `rows` rows in
+/// canonical notation.
+pub fn format_moves(rows: usize, moves: &[MoveRecord]) -> String {
+    moves
+        .iter()
+        .map(|record| {
+            format!(
+                "{:?}:{}",
+                record.player,
+                square_on(rows, record.player_move)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses notation into a list of moves. Only the syntax is checked here;
+/// whether the moves are legal is up to the rules engine.
+pub fn parse_moves(notation: &str) -> Result<Vec<(Player, PlayerMove)>, NotationError> {
+    notation
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, token)| {
+            let error = |reason| NotationError {
+                index,
+                token: token.to_string(),
+                reason,
+            };
+            let (player, square) = token
+                .split_once(':')
+                .ok_or_else(|| error("expected <player>:<square>"))?;
+            let player = match player {
+                "X" | "x" => Player::X,
+                "O" | "o" => Player::O,
+                _ => return Err(error("player must be X or O")),
+            };
+            let player_move = parse_square(square).ok_or_else(|| error("unknown square"))?;
+            Ok((player, player_move))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_squares_round_trip() {
+        for row in 0..SIZE {
+            for col in 0..SIZE {
+                let player_move = PlayerMove { row, col };
+                assert_eq!(parse_square(&square(player_move)), Some(player_move));
+            }
+        }
+        assert_eq!(square(PlayerMove { row: 0, col: 0 }), "a3");
+        assert_eq!(square(PlayerMove { row: 2, col: 2 }), "c1");
+        assert_eq!(square_on(4, PlayerMove { row: 0, col: 6 }), "g4");
+        assert_eq!(
+            parse_square_on((4, 7), "g4"),
+            Some(PlayerMove { row: 0, col: 6 })
+        );
+        assert_eq!(parse_square_on((4, 7), "h1"), None);
+        assert_eq!(parse_square("a4"), None);
+    }
+
+    #[test]
+    fn test_format_and_parse_moves() {
+        let moves = [
+            (Player::X, PlayerMove { row: 1, col: 1 }),
+            (Player::O, PlayerMove { row: 2, col: 0 }),
+            (Player::X, PlayerMove { row: 0, col: 2 }),
+        ];
+        let records: Vec<MoveRecord> = moves
+            .iter()
+            .enumerate()
+            .map(|(i, &(player, player_move))| MoveRecord {
+                ply: i as u64 + 1,
+                player,
+                player_move,
+            })
+            .collect();
+
+        let notation = format_moves(SIZE, &records);
+        assert_eq!(notation, "X:b2 O:a1 X:c3");
+        assert_eq!(parse_moves(&notation).unwrap(), moves);
+    }
+
+    #[test]
+    fn test_parse_reports_the_bad_move() {
+        let error = parse_moves("X:b2 O:d4").unwrap_err();
+        assert_eq!(error.index, 1);
+        assert_eq!(error.token, "O:d4");
+
+        assert_eq!(parse_moves("X:b2 Zb1").unwrap_err().index, 1);
+        assert_eq!(parse_moves("Q:b2").unwrap_err().index, 0);
+        assert_eq!(parse_moves("").unwrap(), vec![]);
+    }
+}
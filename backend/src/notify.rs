@@ -0,0 +1,352 @@
+//! Emails for correspondence games: once the opponent in a player-vs-player
+//! game has moved, a player who hasn't answered within
+//! `email.notify_after_secs`, and isn't connected to the game, gets an
+//! "it's your move" email, if their account asked for them. Games played
+//! live never send any.
+//!
+//! Emails go out through a `Mailer`: SMTP, or the log in development. Each
+//! carries a link that turns the emails off without logging in, also
+//! offered to mail clients as a one-click `List-Unsubscribe`.
+//!
+//! Reminders waiting to go out are kept in memory only, and a restart
+//! drops them.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    account::{self, Account},
+    config::{EmailConfig, MailerBackend},
+    game::{Cell, GameState, GameStatus, Player},
+    jobs::Retry,
+    state::{AppState, GameEvent, GameMode},
+};
+
+const SEND_RETRY: Retry = Retry {
+    attempts: 4,
+    first_delay: Duration::from_secs(30),
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    /// Plain text.
+    pub body: String,
+    pub unsubscribe_url: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+/// Writes emails to the log instead of sending them.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        log::info!("Email to {}: {}\n{}", email.to, email.subject, email.body);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "email")]
+pub struct SmtpMailer {
+    transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+    from: lettre::message::Mailbox,
+}
+
+#[cfg(feature = "email")]
+impl SmtpMailer {
+    pub fn new(url: &str, from: &str) -> Result<Self, String> {
+        let transport = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::from_url(url)
+            .map_err(|e| format!("email.smtp_url: {}", e))?
+            .build();
+        let from = from.parse().map_err(|e| format!("email.from: {}", e))?;
+        Ok(Self { transport, from })
+    }
+}
+
+#[cfg(feature = "email")]
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        use lettre::{
+            AsyncTransport, Message,
+            message::header::{ContentType, HeaderName, HeaderValue},
+        };
+
+        let header = |name: &'static str, value: String| {
+            HeaderValue::new(HeaderName::new_from_ascii_str(name), value)
+        };
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.to.parse().map_err(|e| format!("{}", e))?)
+            .subject(&email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .raw_header(header(
+                "List-Unsubscribe",
+                format!("<{}>", email.unsubscribe_url),
+            ))
+            .raw_header(header(
+                "List-Unsubscribe-Post",
+                "List-Unsubscribe=One-Click".to_string(),
+            ))
+            .body(email.body.clone())
+            .map_err(|e| e.to_string())?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The mailer `config` asks for; `None` if emails are off.
+pub fn connect(config: &EmailConfig) -> Result<Option<Arc<dyn Mailer>>, String> {
+    match config.mailer {
+        None => Ok(None),
+        Some(MailerBackend::Log) => Ok(Some(Arc::new(LogMailer))),
+        #[cfg(feature = "email")]
+        Some(MailerBackend::Smtp) => {
+            // Both are checked by `Config::validate`.
+            let url = config.smtp_url.as_deref().unwrap_or_default();
+            let from = config.from.as_deref().unwrap_or_default();
+            Ok(Some(Arc::new(SmtpMailer::new(url, from)?)))
+        }
+        #[cfg(not(feature = "email"))]
+        Some(MailerBackend::Smtp) => Err("the smtp mailer requires `--features email`".to_string()),
+    }
+}
+
+fn board_text(game_state: &GameState) -> String {
+    game_state
+        .board
+        .rows()
+        .into_iter()
+        .map(|cells| {
+            let row: Vec<&str> = cells
+                .into_iter()
+                .map(|cell| match cell {
+                    Cell::Empty => ".",
+                    Cell::Occupied(Player::X) => "X",
+                    Cell::Occupied(Player::O) => "O",
+                    Cell::Blocked => "#",
+                })
+                .collect();
+            format!("    {}\n", row.join(" "))
+        })
+        .collect()
+}
+
+fn your_move_email(
+    base_url: &str,
+    game_id: Uuid,
+    game_state: &GameState,
+    opponent: &str,
+    to: &str,
+    unsubscribe_token: &str,
+) -> Email {
+    let unsubscribe_url = format!("{base_url}/api/v1/unsubscribe?token={unsubscribe_token}");
+    let body = format!(
+        "{opponent} has moved in your game, and it's your turn.\n\n{}\n{}\n\
+         See the game at {base_url}/g/{game_id}\n\n\
+         You get this email because you asked to be told when it's your move. \
+         To stop, go to {unsubscribe_url}\n",
+        board_text(game_state),
+        game_state.summary(),
+    );
+    Email {
+        to: to.to_string(),
+        subject: format!("Your move against {opponent}"),
+        body,
+        unsubscribe_url,
+    }
+}
+
+/// Emails the player to move in `game_id`, if it is still the turn that
+/// began at `version`, they aren't connected, and they want the email.
+async fn remind(state: &AppState, mailer: &Arc<dyn Mailer>, game_id: Uuid, version: u64) {
+    let Some(base_url) = state.public_url.clone() else {
+        return;
+    };
+    let Some(game) = state.game(&game_id) else {
+        return;
+    };
+    let (game_state, opponent) = {
+        let entry = game.lock().await;
+        let GameMode::Pvp { x, o } = &entry.mode else {
+            return;
+        };
+        let game_state = entry.state;
+        if game_state.version != version || game_state.status != GameStatus::InProgress {
+            return;
+        }
+        let opponent = match game_state.to_play {
+            Player::X => o.name.clone(),
+            Player::O => x.name.clone(),
+        };
+        (game_state, opponent)
+    };
+    let player = game_state.to_play;
+    if state
+        .presence
+        .status(game_id, player, chrono::Utc::now())
+        .online
+    {
+        return;
+    }
+    for Account {
+        id, notifications, ..
+    } in account::seated(state, game_id, player).await
+    {
+        let (Some(to), Some(token), true) = (
+            notifications.email,
+            notifications.unsubscribe_token,
+            notifications.your_move,
+        ) else {
+            continue;
+        };
+        let email = your_move_email(&base_url, game_id, &game_state, &opponent, &to, &token);
+        let mailer = mailer.clone();
+        log::info!("Emailing account {} about its move in game {}", id, game_id);
+        state
+            .jobs
+            .retry(format!("email to account {id}"), SEND_RETRY, move |_| {
+                let (mailer, email) = (mailer.clone(), email.clone());
+                async move { mailer.send(&email).await }
+            });
+    }
+}
+
+/// Background task that schedules an email for every turn in a
+/// player-vs-player game, sent `after` it began unless the turn is over by
+/// then.
+pub async fn remind_task(state: AppState, mailer: Arc<dyn Mailer>, after: Duration) {
+    let mut updates = state.updates.subscribe();
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) => update,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Email reminders fell behind; {} updates dropped", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // The instance the move was made on sends the email.
+        if update.relayed || update.event != (GameEvent::Moved { finished: false }) {
+            continue;
+        }
+        let Some(game) = state.game(&update.game_id) else {
+            continue;
+        };
+        let version = {
+            let entry = game.lock().await;
+            if !matches!(entry.mode, GameMode::Pvp { .. }) {
+                continue;
+            }
+            entry.state.version
+        };
+        let (state, mailer) = (state.clone(), mailer.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            remind(&state, &mailer, update.game_id, version).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        game::{PlayerMove, try_move},
+        state::{GameEntry, GameRegistry, Seat},
+        store::MemoryStore,
+    };
+
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<Email>>);
+
+    #[async_trait]
+    impl Mailer for Outbox {
+        async fn send(&self, email: &Email) -> Result<(), String> {
+            self.0.lock().unwrap().push(email.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_players_who_asked_are_emailed_until_they_unsubscribe() {
+        let mut entry = GameEntry::new(GameState::default());
+        let seat = |name: &str| Seat {
+            name: name.to_string(),
+            token: name.to_lowercase(),
+        };
+        entry.mode = GameMode::Pvp {
+            x: seat("Ada"),
+            o: seat("Grace"),
+        };
+        try_move(&mut entry.state, Player::X, PlayerMove { row: 0, col: 0 }).unwrap();
+        let version = entry.state.version;
+        let game_id = Uuid::new_v4();
+        let state = AppState::new(
+            GameRegistry::from([(game_id, entry)]),
+            Arc::new(MemoryStore),
+        )
+        .with_public_url(Some("https://laika.example"));
+        let outbox = Arc::new(Outbox::default());
+        let mailer: Arc<dyn Mailer> = outbox.clone();
+
+        let (grace, session) = account::start_guest(&state).await.unwrap();
+        let token = session.access_token;
+        account::adopt(&state, grace.id, game_id, Player::O)
+            .await
+            .unwrap();
+        let email = Some("grace@example.com".to_string());
+        assert!(
+            account::set_notifications(&state, &token, email.clone(), true)
+                .await
+                .is_err()
+        );
+        account::register(&state, &token, "grace".to_string(), "hunter22")
+            .await
+            .unwrap();
+        let grace = account::set_notifications(&state, &token, email, true)
+            .await
+            .unwrap();
+
+        remind(&state, &mailer, game_id, version).await;
+        // A turn that has since been played isn't reminded of.
+        remind(&state, &mailer, game_id, version - 1).await;
+        state.jobs.shutdown(Duration::from_secs(1)).await;
+        let sent = outbox.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "grace@example.com");
+        assert_eq!(sent[0].subject, "Your move against Ada");
+        assert!(sent[0].body.contains("    X . .\n"));
+        assert!(
+            sent[0]
+                .body
+                .contains(&format!("https://laika.example/g/{game_id}"))
+        );
+        let unsubscribe_token = grace.notifications.unsubscribe_token.unwrap();
+        assert_eq!(
+            sent[0].unsubscribe_url,
+            format!("https://laika.example/api/v1/unsubscribe?token={unsubscribe_token}")
+        );
+
+        account::unsubscribe(&state, &unsubscribe_token)
+            .await
+            .unwrap();
+        assert!(account::unsubscribe(&state, "guess").await.is_err());
+        let seated = account::seated(&state, game_id, Player::O).await;
+        assert!(!seated[0].notifications.your_move);
+    }
+}
@@ -0,0 +1,313 @@
+//! Logging in with an OAuth2 provider such as GitHub or Google, using the
+//! authorization-code flow.
+//!
+//! The frontend asks for a provider's authorization URL and sends the player
+//! there. The provider redirects back to the frontend with a `code` and the
+//! `state` it was given, which the frontend passes on to the callback
+//! endpoint. The server exchanges the code for an access token, looks up who
+//! the player is, and returns a session token like a password login would.
+//! Pending logins are kept in memory and expire after `oauth.state_ttl_secs`.
+
+use std::{collections::HashMap, fmt, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    account::{self, Account, Identity, Session},
+    config::{OAuthConfig, OAuthEndpoints},
+    state::{AppState, new_token},
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// GitHub's API rejects requests without one.
+const USER_AGENT: &str = "laika";
+
+/// A configured provider, with its endpoints resolved.
+struct Provider {
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    endpoints: OAuthEndpoints,
+}
+
+/// A login that was started but hasn't come back from the provider yet.
+#[derive(Debug, Clone)]
+struct PendingLogin {
+    provider: String,
+    /// The account of the session that started the login, to link to.
+    account_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OAuthError {
+    UnknownProvider,
+    /// The state is unknown, expired, already used, or for another provider.
+    InvalidState,
+    /// The provider didn't hand over a token or profile.
+    Provider(String),
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OAuthError::UnknownProvider => f.write_str("No such login provider"),
+            OAuthError::InvalidState => f.write_str("Login expired or was already completed"),
+            OAuthError::Provider(msg) => write!(f, "Login provider failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+#[derive(Debug, Serialize)]
+struct TokenRequest<'a> {
+    grant_type: &'static str,
+    code: &'a str,
+    redirect_uri: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+}
+
+pub struct OAuth {
+    providers: HashMap<String, Provider>,
+    pending: DashMap<String, PendingLogin>,
+    state_ttl: TimeDelta,
+    client: reqwest::Client,
+}
+
+impl OAuth {
+    pub fn new(config: &OAuthConfig) -> Self {
+        let providers = config
+            .providers
+            .iter()
+            .filter_map(|(name, provider)| {
+                // Incomplete providers are rejected by `Config::validate`.
+                let endpoints = provider.endpoints(name)?;
+                Some((
+                    name.clone(),
+                    Provider {
+                        client_id: provider.client_id.clone(),
+                        client_secret: provider.client_secret.clone(),
+                        redirect_url: provider.redirect_url.clone(),
+                        endpoints,
+                    },
+                ))
+            })
+            .collect();
+        Self {
+            providers,
+            pending: DashMap::new(),
+            state_ttl: config.state_ttl(),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .user_agent(USER_AGENT)
+                .build()
+                .expect("HTTP client builds"),
+        }
+    }
+
+    /// The configured provider names, sorted.
+    pub fn provider_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Starts a login and returns the provider URL to send the player to,
+    /// along with the state the provider will hand back.
+    pub fn authorize_url(
+        &self,
+        provider_name: &str,
+        account_id: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<(String, String), OAuthError> {
+        let provider = self
+            .providers
+            .get(provider_name)
+            .ok_or(OAuthError::UnknownProvider)?;
+        self.purge_expired(now);
+        let state = new_token();
+        let scope = provider.endpoints.scopes.join(" ");
+        let url = reqwest::Url::parse_with_params(
+            &provider.endpoints.authorize_url,
+            [
+                ("response_type", "code"),
+                ("client_id", &provider.client_id),
+                ("redirect_uri", &provider.redirect_url),
+                ("scope", &scope),
+                ("state", &state),
+            ],
+        )
+        .map_err(|e| OAuthError::Provider(e.to_string()))?;
+        self.pending.insert(
+            state.clone(),
+            PendingLogin {
+                provider: provider_name.to_string(),
+                account_id,
+                expires_at: now + self.state_ttl,
+            },
+        );
+        Ok((url.to_string(), state))
+    }
+
+    /// Ends the pending login for `state`. Each state works once.
+    fn take_pending(
+        &self,
+        provider_name: &str,
+        state: &str,
+        now: DateTime<Utc>,
+    ) -> Result<PendingLogin, OAuthError> {
+        let (_, pending) = self.pending.remove(state).ok_or(OAuthError::InvalidState)?;
+        if pending.provider != provider_name || pending.expires_at <= now {
+            return Err(OAuthError::InvalidState);
+        }
+        Ok(pending)
+    }
+
+    /// Trades `code` for an access token and asks the provider who it
+    /// belongs to.
+    async fn identify(&self, provider_name: &str, code: &str) -> Result<Identity, OAuthError> {
+        let provider = self
+            .providers
+            .get(provider_name)
+            .ok_or(OAuthError::UnknownProvider)?;
+        let token: Value = self
+            .fetch_json(
+                self.client
+                    .post(&provider.endpoints.token_url)
+                    .header(reqwest::header::ACCEPT, "application/json")
+                    .form(&TokenRequest {
+                        grant_type: "authorization_code",
+                        code,
+                        redirect_uri: &provider.redirect_url,
+                        client_id: &provider.client_id,
+                        client_secret: &provider.client_secret,
+                    }),
+            )
+            .await?;
+        let access_token = token["access_token"].as_str().ok_or_else(|| {
+            let reason = token["error"].as_str().unwrap_or("no access token");
+            OAuthError::Provider(reason.to_string())
+        })?;
+        let profile = self
+            .fetch_json(
+                self.client
+                    .get(&provider.endpoints.userinfo_url)
+                    .header(reqwest::header::ACCEPT, "application/json")
+                    .bearer_auth(access_token),
+            )
+            .await?;
+        let subject = match &profile[&provider.endpoints.subject_field] {
+            Value::String(subject) => subject.clone(),
+            Value::Number(subject) => subject.to_string(),
+            _ => {
+                return Err(OAuthError::Provider(format!(
+                    "profile has no {}",
+                    provider.endpoints.subject_field
+                )));
+            }
+        };
+        Ok(Identity {
+            provider: provider_name.to_string(),
+            subject,
+        })
+    }
+
+    async fn fetch_json(&self, request: reqwest::RequestBuilder) -> Result<Value, OAuthError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| OAuthError::Provider(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(OAuthError::Provider(format!("HTTP {}", status.as_u16())));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| OAuthError::Provider(e.to_string()))?;
+        serde_json::from_slice(&body).map_err(|e| OAuthError::Provider(e.to_string()))
+    }
+
+    fn purge_expired(&self, now: DateTime<Utc>) {
+        self.pending.retain(|_, pending| pending.expires_at > now);
+    }
+}
+
+// --- Operations ---
+
+/// Finishes a login the provider redirected back from, returning the account
+/// and a new session for it.
+pub async fn complete(
+    state: &AppState,
+    provider_name: &str,
+    code: &str,
+    oauth_state: &str,
+) -> Result<(Account, Session), Error> {
+    let pending = state
+        .oauth
+        .take_pending(provider_name, oauth_state, Utc::now())
+        .map_err(Error::OAuth)?;
+    let identity = state
+        .oauth
+        .identify(provider_name, code)
+        .await
+        .map_err(Error::OAuth)?;
+    let (account, token) =
+        account::login_with_identity(state, identity, pending.account_id).await?;
+    log::info!("Account {} logged in with {}", account.id, provider_name);
+    Ok((account, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::config::OAuthProviderConfig;
+
+    #[test]
+    fn test_login_states_are_single_use_and_expire() {
+        let oauth = OAuth::new(&OAuthConfig {
+            providers: BTreeMap::from([(
+                "github".to_string(),
+                OAuthProviderConfig {
+                    client_id: "laika-client".to_string(),
+                    client_secret: "s3cret".to_string(),
+                    redirect_url: "http://localhost:3001/oauth/github".to_string(),
+                    ..OAuthProviderConfig::default()
+                },
+            )]),
+            state_ttl_secs: 60,
+        });
+        let now = Utc::now();
+        assert_eq!(
+            oauth.authorize_url("gitlab", None, now).unwrap_err(),
+            OAuthError::UnknownProvider
+        );
+
+        let (url, state) = oauth.authorize_url("github", None, now).unwrap();
+        assert!(url.starts_with("https://github.com/login/oauth/authorize?"));
+        assert!(url.contains("client_id=laika-client"));
+        assert!(url.contains(&format!("state={state}")));
+        assert!(!url.contains("s3cret"));
+        assert!(oauth.take_pending("github", &state, now).is_ok());
+        assert_eq!(
+            oauth.take_pending("github", &state, now).unwrap_err(),
+            OAuthError::InvalidState
+        );
+
+        let (_, state) = oauth.authorize_url("github", None, now).unwrap();
+        let later = now + TimeDelta::minutes(2);
+        assert_eq!(
+            oauth.take_pending("github", &state, later).unwrap_err(),
+            OAuthError::InvalidState
+        );
+    }
+}
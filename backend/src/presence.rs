@@ -0,0 +1,214 @@
+//! Who is connected to which player-vs-player game.
+//!
+//! Clients hold a WebSocket open per seat; the server pings it and counts
+//! every frame that comes back as a heartbeat. A seat is online while it has
+//! at least one live connection. Seats that never connected aren't tracked,
+//! so clients that only use plain HTTP are never reported as absent, nor
+//! forfeited for it.
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    config::PresenceConfig,
+    game::{Ending, GameStatus, Player},
+    state::{AppState, GameEvent, GameMode},
+};
+
+/// How often `forfeit_absent` looks for absent players.
+pub const FORFEIT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SeatPresence {
+    connections: usize,
+    last_seen: DateTime<Utc>,
+}
+
+/// What the other side sees of a seat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PresenceView {
+    pub online: bool,
+    /// When the seat last showed a sign of life; `null` if it never
+    /// connected.
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Seconds since the seat disconnected; `null` while online or if it
+    /// never connected.
+    pub absent_secs: Option<i64>,
+}
+
+pub struct Presence {
+    seats: DashMap<(Uuid, Player), SeatPresence>,
+    config: PresenceConfig,
+}
+
+impl Presence {
+    pub fn new(config: PresenceConfig) -> Self {
+        Self {
+            seats: DashMap::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &PresenceConfig {
+        &self.config
+    }
+
+    pub fn connect(&self, game_id: Uuid, player: Player, now: DateTime<Utc>) {
+        let mut seat = self.seats.entry((game_id, player)).or_insert(SeatPresence {
+            connections: 0,
+            last_seen: now,
+        });
+        seat.connections += 1;
+        seat.last_seen = now;
+    }
+
+    pub fn heartbeat(&self, game_id: Uuid, player: Player, now: DateTime<Utc>) {
+        if let Some(mut seat) = self.seats.get_mut(&(game_id, player)) {
+            seat.last_seen = now;
+        }
+    }
+
+    pub fn disconnect(&self, game_id: Uuid, player: Player, now: DateTime<Utc>) {
+        if let Some(mut seat) = self.seats.get_mut(&(game_id, player)) {
+            seat.connections = seat.connections.saturating_sub(1);
+            seat.last_seen = now;
+        }
+    }
+
+    pub fn status(&self, game_id: Uuid, player: Player, now: DateTime<Utc>) -> PresenceView {
+        match self.seats.get(&(game_id, player)).map(|seat| *seat) {
+            None => PresenceView {
+                online: false,
+                last_seen: None,
+                absent_secs: None,
+            },
+            Some(seat) => PresenceView {
+                online: seat.connections > 0,
+                last_seen: Some(seat.last_seen),
+                absent_secs: (seat.connections == 0).then(|| (now - seat.last_seen).num_seconds()),
+            },
+        }
+    }
+
+    /// Seats that have been disconnected for at least `after`.
+    fn absent(&self, now: DateTime<Utc>, after: TimeDelta) -> Vec<(Uuid, Player)> {
+        self.seats
+            .iter()
+            .filter(|seat| seat.connections == 0 && now - seat.last_seen >= after)
+            .map(|seat| *seat.key())
+            .collect()
+    }
+
+    /// Exchanges the two seats of a game after the players swapped sides.
+    pub fn swap(&self, game_id: Uuid) {
+        let x = self.seats.remove(&(game_id, Player::X));
+        let o = self.seats.remove(&(game_id, Player::O));
+        if let Some((_, seat)) = x {
+            self.seats.insert((game_id, Player::O), seat);
+        }
+        if let Some((_, seat)) = o {
+            self.seats.insert((game_id, Player::X), seat);
+        }
+    }
+
+    /// Stops tracking both seats of a game.
+    pub fn forget(&self, game_id: Uuid) {
+        self.seats.retain(|(id, _), _| *id != game_id);
+    }
+}
+
+// --- Operations ---
+
+/// Ends the game in the opponent's favor if it is still `player`'s turn.
+/// Returns whether it did.
+async fn forfeit(state: &AppState, game_id: Uuid, player: Player) -> Result<bool, Error> {
+    let Some(game) = state.game(&game_id) else {
+        state.presence.forget(game_id);
+        return Ok(false);
+    };
+    let mut entry = game.lock().await;
+    if entry.state.status != GameStatus::InProgress || !matches!(entry.mode, GameMode::Pvp { .. }) {
+        drop(entry);
+        state.presence.forget(game_id);
+        return Ok(false);
+    }
+    if entry.state.to_play != player {
+        return Ok(false);
+    }
+
+    let mut updated = entry.clone();
+    updated.state.status = GameStatus::Win(player.opponent());
+    updated.state.ending = Some(Ending::ByAbsence);
+    if let Some(clock) = &mut updated.state.clock {
+        clock.stop(player, Utc::now());
+    }
+    let first_new_event = updated.events.len();
+    updated.finish(Utc::now());
+    state
+        .store
+        .append_events(game_id, &updated.events[first_new_event..], &updated)
+        .await
+        .map_err(Error::Storage)?;
+    let (status, tournament_id, match_id, arena_id) = (
+        updated.state.status,
+        updated.tournament_id,
+        updated.match_id,
+        updated.arena_id,
+    );
+    *entry = updated;
+    drop(entry);
+
+    log::info!("{:?} forfeited game {} by absence", player, game_id);
+    state.presence.forget(game_id);
+    state.publish(game_id, GameEvent::Forfeited);
+    crate::game_finished(state, game_id, tournament_id, match_id, arena_id, status).await;
+    Ok(true)
+}
+
+/// Forfeits games for players who stay disconnected on their turn for longer
+/// than `after`. Run every `FORFEIT_CHECK_INTERVAL`.
+pub async fn forfeit_absent(state: AppState, after: TimeDelta) {
+    for (game_id, player) in state.presence.absent(Utc::now(), after) {
+        if let Err(e) = forfeit(&state, game_id, player).await {
+            log::error!("Failed to forfeit game {}: {}", game_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_absence_counts_from_the_last_connection_closing() {
+        let now = Utc::now();
+        let presence = Presence::new(PresenceConfig::default());
+        let game_id = Uuid::new_v4();
+        assert_eq!(presence.status(game_id, Player::O, now).last_seen, None);
+
+        // Two tabs open; closing one keeps the seat online.
+        presence.connect(game_id, Player::X, now);
+        presence.connect(game_id, Player::X, now);
+        presence.disconnect(game_id, Player::X, now);
+        assert!(presence.status(game_id, Player::X, now).online);
+
+        presence.disconnect(game_id, Player::X, now);
+        let later = now + TimeDelta::seconds(30);
+        let status = presence.status(game_id, Player::X, later);
+        assert!(!status.online);
+        assert_eq!(status.absent_secs, Some(30));
+
+        assert!(presence.absent(later, TimeDelta::minutes(1)).is_empty());
+        assert_eq!(
+            presence.absent(later, TimeDelta::seconds(30)),
+            [(game_id, Player::X)]
+        );
+        presence.forget(game_id);
+        assert!(presence.absent(later, TimeDelta::seconds(30)).is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+//! RFC 7807 problem details, for clients that send
+//! `Accept: application/problem+json`.
+//!
+//! Handlers fail with `Error` as usual; this middleware rewrites any failed
+//! response into a problem document on the way out. Failures from outside
+//! `Error`, such as rejected request bodies, unknown routes, or timeouts, get
+//! one too, built from their status and whatever text they carried, and
+//! titled in the client's language where there is a translation.
+
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{ErrorBody, i18n::Languages};
+
+const MEDIA_TYPE: &str = "application/problem+json";
+
+/// The most of a plain-text failure's body kept as its `detail`.
+const MAX_DETAIL_BYTES: usize = 4096;
+
+#[derive(Debug, Serialize)]
+struct Problem {
+    /// Identifies the kind of problem: `urn:laika:error:<code>` for the
+    /// server's own errors, `about:blank` when the status says it all.
+    #[serde(rename = "type")]
+    kind: String,
+    title: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    /// The path the failed request was sent to.
+    instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
+}
+
+impl Problem {
+    fn new(
+        status: StatusCode,
+        error: Option<&ErrorBody>,
+        text: String,
+        instance: String,
+        languages: &Languages,
+    ) -> Self {
+        let reason = languages
+            .status_title(status)
+            .unwrap_or_else(|| status.canonical_reason().unwrap_or("Error").to_string());
+        match error {
+            Some(error) => Self {
+                kind: format!("urn:laika:error:{}", error.code),
+                title: title_of(&error.code),
+                status: status.as_u16(),
+                detail: Some(error.message.clone()),
+                instance,
+                code: Some(error.code.clone()),
+                details: error.details.clone(),
+            },
+            None => Self {
+                kind: "about:blank".to_string(),
+                title: reason,
+                status: status.as_u16(),
+                detail: Some(text).filter(|text| !text.is_empty()),
+                instance,
+                code: None,
+                details: None,
+            },
+        }
+    }
+}
+
+/// A title for an error code that is the same every time it occurs, e.g.
+/// "Not your turn" for `not_your_turn`.
+fn title_of(code: &str) -> String {
+    let words = code.replace('_', " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => words,
+    }
+}
+
+/// Whether the client listed problem details in `Accept`.
+fn wants_problem(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+        .any(|essence| essence.eq_ignore_ascii_case(MEDIA_TYPE))
+}
+
+/// Middleware that turns failed responses into problem details when the
+/// client asks for them.
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    if !wants_problem(request.headers()) {
+        return next.run(request).await;
+    }
+    let instance = request.uri().path().to_string();
+    let languages = Languages::from_headers(request.headers());
+    let response = next.run(request).await;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let error = parts.extensions.remove::<ErrorBody>();
+    let text = match error {
+        Some(_) => String::new(),
+        None => body::to_bytes(body, MAX_DETAIL_BYTES)
+            .await
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default(),
+    };
+    let problem = Problem::new(status, error.as_ref(), text, instance, &languages);
+    let Ok(document) = serde_json::to_vec(&problem) else {
+        return status.into_response();
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(MEDIA_TYPE));
+    Response::from_parts(parts, Body::from(document))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_problems_carry_the_error_code_or_the_status() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+        assert!(!wants_problem(&headers));
+        headers.insert(
+            header::ACCEPT,
+            "application/json, application/problem+json; q=0.9"
+                .parse()
+                .unwrap(),
+        );
+        assert!(wants_problem(&headers));
+
+        let error = ErrorBody::from(&Error::NotYourTurn);
+        let problem = Problem::new(
+            StatusCode::BAD_REQUEST,
+            Some(&error),
+            String::new(),
+            "/api/v1/games/1/move".to_string(),
+            &Languages::default(),
+        );
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            serde_json::json!({
+                "type": "urn:laika:error:not_your_turn",
+                "title": "Not your turn",
+                "status": 400,
+                "detail": "Not your turn",
+                "instance": "/api/v1/games/1/move",
+                "code": "not_your_turn",
+            })
+        );
+
+        let problem = Problem::new(
+            StatusCode::NOT_FOUND,
+            None,
+            String::new(),
+            "/nowhere".to_string(),
+            &Languages::default(),
+        );
+        assert_eq!(problem.kind, "about:blank");
+        assert_eq!(problem.title, "Not Found");
+        assert_eq!(problem.detail, None);
+    }
+}
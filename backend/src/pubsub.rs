@@ -0,0 +1,194 @@
+//! Relays game updates between instances over Redis pub/sub.
+//!
+//! Every instance keeps its own copy of the games it has loaded, and its
+//! WebSocket, SSE, and GraphQL subscribers only hear about changes made on
+//! that instance. With `pubsub.redis_url` set, each local update is published
+//! on the game's channel together with the game as it now stands. The other
+//! instances take that copy in place of their own and pass the update on to
+//! their subscribers, so a client connected to one instance sees moves made
+//! through any of them.
+
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, broadcast::error::RecvError};
+use uuid::Uuid;
+
+use crate::{
+    config::PubSubConfig,
+    state::{AppState, GameEntry, GameEvent, GameUpdate},
+};
+
+/// How long to wait before reconnecting after losing the Redis connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// What goes over a game's channel.
+#[derive(Debug, Serialize, Deserialize)]
+struct Message {
+    /// The instance the change happened on.
+    origin: Uuid,
+    game_id: Uuid,
+    event: GameEvent,
+    /// `None` once the game is gone, e.g. after it expired.
+    entry: Option<GameEntry>,
+}
+
+fn channel(prefix: &str, game_id: Uuid) -> String {
+    format!("{prefix}:game:{game_id}")
+}
+
+/// Takes in a change made on another instance: replaces the local copy of
+/// the game, unless it is already further along, and tells local
+/// subscribers.
+async fn receive(state: &AppState, message: Message) {
+    match message.entry {
+        Some(entry) => match state.game(&message.game_id) {
+            Some(game) => {
+                let mut local = game.lock().await;
+                if entry.events.len() >= local.events.len() {
+                    *local = entry;
+                }
+            }
+            None => {
+                state
+                    .games
+                    .insert(message.game_id, std::sync::Arc::new(Mutex::new(entry)));
+            }
+        },
+        None => {
+            state.games.remove(&message.game_id);
+        }
+    }
+    let _ = state.updates.send(GameUpdate {
+        game_id: message.game_id,
+        event: message.event,
+        relayed: true,
+    });
+}
+
+/// Publishes local updates until the connection fails.
+async fn publish_updates(
+    state: &AppState,
+    client: &redis::Client,
+    prefix: &str,
+    origin: Uuid,
+) -> redis::RedisResult<()> {
+    let mut connection = client.get_multiplexed_async_connection().await?;
+    let mut updates = state.updates.subscribe();
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) if !update.relayed => update,
+            Ok(_) => continue,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Pub/sub fell behind and skipped {} updates", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let entry = match state.game(&update.game_id) {
+            Some(game) => Some(game.lock().await.clone()),
+            None => None,
+        };
+        let message = Message {
+            origin,
+            game_id: update.game_id,
+            event: update.event,
+            entry,
+        };
+        let payload = serde_json::to_string(&message).expect("messages serialize");
+        let _: () = connection
+            .publish(channel(prefix, update.game_id), payload)
+            .await?;
+    }
+}
+
+/// Passes on updates from other instances until the connection fails.
+async fn subscribe_updates(
+    state: &AppState,
+    client: &redis::Client,
+    prefix: &str,
+    origin: Uuid,
+) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.psubscribe(format!("{prefix}:game:*")).await?;
+    state.redis.up();
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = message.get_payload()?;
+        match serde_json::from_str::<Message>(&payload) {
+            Ok(message) if message.origin == origin => {}
+            Ok(message) => receive(state, message).await,
+            Err(e) => log::warn!("Ignoring unreadable pub/sub message: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Background task that relays updates to and from the other instances,
+/// reconnecting whenever the connection to Redis drops.
+pub async fn relay_task(state: AppState, config: PubSubConfig, url: String) {
+    let client = match redis::Client::open(url) {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Invalid pubsub.redis_url: {}", e);
+            state.redis.down(format!("invalid pubsub.redis_url: {}", e));
+            return;
+        }
+    };
+    let origin = Uuid::new_v4();
+    let prefix = config.channel_prefix.as_str();
+    log::info!("Relaying game updates through Redis as instance {}", origin);
+    state.redis.down("not connected yet");
+    loop {
+        let result = tokio::select! {
+            result = publish_updates(&state, &client, prefix, origin) => result,
+            result = subscribe_updates(&state, &client, prefix, origin) => result,
+        };
+        match result {
+            Ok(()) => state.redis.down("connection closed"),
+            Err(e) => {
+                log::error!("Lost the Redis connection: {}", e);
+                state.redis.down(e.to_string());
+            }
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::GameState, state::GameRegistry, store::MemoryStore};
+
+    #[tokio::test]
+    async fn test_relayed_updates_replace_older_copies() {
+        let state = AppState::new(GameRegistry::new(), std::sync::Arc::new(MemoryStore));
+        let mut updates = state.updates.subscribe();
+        let game_id = Uuid::new_v4();
+        let mut entry = GameEntry::new(GameState::default());
+        entry.record_move(
+            crate::game::Player::X,
+            crate::game::PlayerMove { row: 0, col: 0 },
+        );
+        let message = |entry: Option<GameEntry>| Message {
+            origin: Uuid::new_v4(),
+            game_id,
+            event: GameEvent::Moved { finished: false },
+            entry,
+        };
+
+        receive(&state, message(Some(entry.clone()))).await;
+        assert_eq!(state.game(&game_id).unwrap().lock().await.move_count(), 1);
+        let update = updates.recv().await.unwrap();
+        assert!(update.relayed);
+
+        // A copy from before the local one doesn't roll it back.
+        receive(&state, message(Some(GameEntry::new(GameState::default())))).await;
+        assert_eq!(state.game(&game_id).unwrap().lock().await.move_count(), 1);
+
+        receive(&state, message(None)).await;
+        assert!(state.game(&game_id).is_none());
+    }
+}
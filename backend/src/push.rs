@@ -0,0 +1,423 @@
+//! Web Push: browsers subscribe through their push service, hand the
+//! subscription to `/api/v1/push/subscriptions`, and get a push when the
+//! opponent in a player-vs-player game has moved or the game is over, even
+//! with the tab closed. The frontend's service worker shows it.
+//!
+//! Pushes are encrypted for the browser as RFC 8291 says, and carry a
+//! VAPID token (RFC 8292) signed with the server's key, which push services
+//! check against the key the browser subscribed with. Subscriptions the
+//! push service reports gone are dropped.
+
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use ring::{
+    aead, agreement,
+    rand::{SecureRandom, SystemRandom},
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair},
+};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    account::{self, PushSubscription},
+    config::PushConfig,
+    game::{GameStatus, Player},
+    jobs::Retry,
+    state::{AppState, GameEvent, GameMode, GameUpdate},
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const PUSH_RETRY: Retry = Retry {
+    attempts: 3,
+    first_delay: Duration::from_secs(5),
+};
+/// How long push services keep a push for a browser that is offline.
+const TTL_SECS: u32 = 24 * 60 * 60;
+/// How long a VAPID token is good for; RFC 8292 allows up to a day.
+const TOKEN_LIFETIME_SECS: i64 = 12 * 60 * 60;
+/// The record size in the encrypted body's header. Pushes fit in one
+/// record.
+const RECORD_SIZE: u32 = 4096;
+const MAX_ENDPOINT_LEN: usize = 2048;
+
+struct Vapid {
+    key_pair: EcdsaKeyPair,
+    /// As configured, for `Authorization` and browsers subscribing.
+    public_key: String,
+    subject: String,
+}
+
+pub struct Push {
+    vapid: Option<Vapid>,
+    client: reqwest::Client,
+}
+
+/// How a push went.
+#[derive(Debug)]
+enum Sent {
+    Delivered,
+    /// The subscription expired or was cancelled.
+    Gone,
+}
+
+impl Push {
+    pub fn new(config: &PushConfig) -> Result<Self, String> {
+        let vapid = match (&config.vapid_public_key, &config.vapid_private_key) {
+            (Some(public_key), Some(private_key)) => {
+                let decode = |key: &str, name| {
+                    URL_SAFE_NO_PAD
+                        .decode(key)
+                        .map_err(|e| format!("push.{}: {}", name, e))
+                };
+                let key_pair = EcdsaKeyPair::from_private_key_and_public_key(
+                    &ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &decode(private_key, "vapid_private_key")?,
+                    &decode(public_key, "vapid_public_key")?,
+                    &SystemRandom::new(),
+                )
+                .map_err(|e| format!("push: the VAPID keys aren't a P-256 key pair: {}", e))?;
+                Some(Vapid {
+                    key_pair,
+                    public_key: public_key.clone(),
+                    // Checked by `Config::validate`.
+                    subject: config.subject.clone().unwrap_or_default(),
+                })
+            }
+            _ => None,
+        };
+        Ok(Self {
+            vapid,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client builds"),
+        })
+    }
+
+    /// The key browsers subscribe with, their `applicationServerKey`.
+    pub fn public_key(&self) -> Option<&str> {
+        self.vapid.as_ref().map(|vapid| vapid.public_key.as_str())
+    }
+
+    async fn send(&self, subscription: &PushSubscription, payload: &[u8]) -> Result<Sent, String> {
+        let Some(vapid) = &self.vapid else {
+            return Ok(Sent::Delivered);
+        };
+        let body = encrypt(payload, &subscription.p256dh, &subscription.auth)?;
+        let token = vapid.token(&subscription.endpoint, Utc::now())?;
+        let response = self
+            .client
+            .post(&subscription.endpoint)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("vapid t={token}, k={}", vapid.public_key),
+            )
+            .header(reqwest::header::CONTENT_ENCODING, "aes128gcm")
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .header("TTL", TTL_SECS)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        match response.status().as_u16() {
+            200..=299 => Ok(Sent::Delivered),
+            404 | 410 => Ok(Sent::Gone),
+            status => Err(format!("the push service answered {}", status)),
+        }
+    }
+}
+
+impl Vapid {
+    /// A JWT for the push service behind `endpoint`, signed with ES256.
+    fn token(&self, endpoint: &str, now: DateTime<Utc>) -> Result<String, String> {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = json!({
+            "aud": origin(endpoint).ok_or("the endpoint isn't a URL")?,
+            "exp": now.timestamp() + TOKEN_LIFETIME_SECS,
+            "sub": self.subject,
+        });
+        let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signed = format!("{header}.{claims}");
+        let signature = self
+            .key_pair
+            .sign(&SystemRandom::new(), signed.as_bytes())
+            .map_err(|e| e.to_string())?;
+        Ok(format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature)))
+    }
+}
+
+/// `https://push.example` for `https://push.example/send/abc`.
+fn origin(url: &str) -> Option<&str> {
+    let host_start = url.find("://")? + 3;
+    let end = url[host_start..]
+        .find('/')
+        .map_or(url.len(), |i| host_start + i);
+    Some(&url[..end])
+}
+
+/// Checks a subscription a browser handed over.
+pub fn check_subscription(endpoint: &str, p256dh: &str, auth: &str) -> Result<(), Error> {
+    if !endpoint.starts_with("https://") || endpoint.len() > MAX_ENDPOINT_LEN {
+        return Err(Error::BadRequest("endpoint must be an https URL"));
+    }
+    let p256dh = URL_SAFE_NO_PAD.decode(p256dh.trim_end_matches('='));
+    if !p256dh.is_ok_and(|key| key.len() == 65 && key[0] == 4) {
+        return Err(Error::BadRequest("keys.p256dh must be a P-256 public key"));
+    }
+    let auth = URL_SAFE_NO_PAD.decode(auth.trim_end_matches('='));
+    if !auth.is_ok_and(|secret| secret.len() == 16) {
+        return Err(Error::BadRequest("keys.auth must be 16 bytes"));
+    }
+    Ok(())
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// The content key and nonce for a push, from the ECDH secret between the
+/// server's one-off key and the browser's key (RFC 8291, section 3).
+fn content_keys(
+    ecdh_secret: &[u8],
+    auth_secret: &[u8],
+    browser_key: &[u8],
+    server_key: &[u8],
+    salt: &[u8],
+) -> ([u8; 16], [u8; 12]) {
+    let prk_key = hmac_sha256(auth_secret, &[ecdh_secret]);
+    let ikm = hmac_sha256(
+        &prk_key,
+        &[b"WebPush: info\0", browser_key, server_key, &[1]],
+    );
+    let prk = hmac_sha256(salt, &[&ikm]);
+    let cek = hmac_sha256(&prk, &[b"Content-Encoding: aes128gcm\0", &[1]]);
+    let nonce = hmac_sha256(&prk, &[b"Content-Encoding: nonce\0", &[1]]);
+    (
+        cek[..16].try_into().expect("16 bytes"),
+        nonce[..12].try_into().expect("12 bytes"),
+    )
+}
+
+/// Encrypts `payload` for the browser with `p256dh` and `auth`, as an
+/// `aes128gcm` body of a single record.
+fn encrypt(payload: &[u8], p256dh: &str, auth: &str) -> Result<Vec<u8>, String> {
+    let decode = |value: &str| URL_SAFE_NO_PAD.decode(value.trim_end_matches('='));
+    let (Ok(browser_key), Ok(auth_secret)) = (decode(p256dh), decode(auth)) else {
+        return Err("the subscription's keys aren't base64url".to_string());
+    };
+    let rng = SystemRandom::new();
+    let private_key = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
+        .map_err(|e| e.to_string())?;
+    let server_key = private_key
+        .compute_public_key()
+        .map_err(|e| e.to_string())?;
+    let mut salt = [0u8; 16];
+    rng.fill(&mut salt).map_err(|e| e.to_string())?;
+    let (cek, nonce) = agreement::agree_ephemeral(
+        private_key,
+        &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &browser_key),
+        |ecdh_secret| {
+            content_keys(
+                ecdh_secret,
+                &auth_secret,
+                &browser_key,
+                server_key.as_ref(),
+                &salt,
+            )
+        },
+    )
+    .map_err(|_| "the subscription's p256dh isn't a P-256 key".to_string())?;
+
+    // The payload, then the delimiter that marks the last record.
+    let mut record = [payload, &[2]].concat();
+    let key = aead::LessSafeKey::new(
+        aead::UnboundKey::new(&aead::AES_128_GCM, &cek).expect("16-byte key"),
+    );
+    key.seal_in_place_append_tag(
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::Aad::empty(),
+        &mut record,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut body = Vec::with_capacity(16 + 4 + 1 + 65 + record.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(server_key.as_ref().len() as u8);
+    body.extend_from_slice(server_key.as_ref());
+    body.extend_from_slice(&record);
+    Ok(body)
+}
+
+/// Pushes `payload` to every browser of the accounts playing `player` in
+/// `game_id`.
+async fn push_to(state: &AppState, game_id: Uuid, player: Player, payload: &serde_json::Value) {
+    let payload = payload.to_string().into_bytes();
+    for account in account::seated(state, game_id, player).await {
+        for subscription in account.push_subscriptions {
+            let (pushing, payload) = (state.clone(), payload.clone());
+            let name = format!("push to account {}", account.id);
+            state.jobs.retry(name, PUSH_RETRY, move |_| {
+                let (state, subscription, payload) =
+                    (pushing.clone(), subscription.clone(), payload.clone());
+                async move {
+                    match state.push.send(&subscription, &payload).await? {
+                        Sent::Delivered => {}
+                        Sent::Gone => {
+                            let endpoint = &subscription.endpoint;
+                            if let Err(e) = account::unsubscribe_push(&state, None, endpoint).await
+                            {
+                                log::warn!("Failed to drop a push subscription: {}", e);
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+            });
+        }
+    }
+}
+
+async fn dispatch(state: &AppState, update: GameUpdate) {
+    let finished = match update.event {
+        GameEvent::Moved { finished } => finished,
+        GameEvent::TimedOut | GameEvent::Forfeited => true,
+        _ => return,
+    };
+    let Some(game) = state.game(&update.game_id) else {
+        return;
+    };
+    let (game_state, x, o) = {
+        let entry = game.lock().await;
+        let GameMode::Pvp { x, o } = &entry.mode else {
+            return;
+        };
+        (entry.state, x.name.clone(), o.name.clone())
+    };
+    let url = format!(
+        "{}/g/{}",
+        state.public_url.as_deref().unwrap_or_default(),
+        update.game_id
+    );
+    if finished || game_state.status != GameStatus::InProgress {
+        let payload = json!({
+            "type": "game_finished",
+            "game_id": update.game_id,
+            "title": format!("{x} vs {o} is over"),
+            "body": game_state.summary(),
+            "url": url,
+        });
+        push_to(state, update.game_id, Player::X, &payload).await;
+        push_to(state, update.game_id, Player::O, &payload).await;
+    } else {
+        let player = game_state.to_play;
+        let opponent = if player == Player::X { o } else { x };
+        let payload = json!({
+            "type": "opponent_moved",
+            "game_id": update.game_id,
+            "title": format!("Your move against {opponent}"),
+            "body": game_state.summary(),
+            "url": url,
+        });
+        push_to(state, update.game_id, player, &payload).await;
+    }
+}
+
+/// Background task that turns moves and endings in player-vs-player games
+/// into pushes.
+pub async fn dispatch_task(state: AppState) {
+    let mut updates = state.updates.subscribe();
+    loop {
+        match updates.recv().await {
+            // The instance the change happened on pushes it.
+            Ok(update) if update.relayed => {}
+            Ok(update) => dispatch(&state, update).await,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Push dispatch fell behind; {} updates dropped", missed);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{ECDSA_P256_SHA256_FIXED, KeyPair, UnparsedPublicKey};
+
+    use super::*;
+
+    #[test]
+    fn test_pushes_decrypt_for_the_browser_and_carry_a_signed_token() {
+        // The browser's side.
+        let rng = SystemRandom::new();
+        let browser_private =
+            agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).unwrap();
+        let browser_key = browser_private.compute_public_key().unwrap();
+        let auth_secret = [7u8; 16];
+        let p256dh = URL_SAFE_NO_PAD.encode(browser_key.as_ref());
+        let auth = URL_SAFE_NO_PAD.encode(auth_secret);
+        assert!(check_subscription("https://push.example/send/1", &p256dh, &auth).is_ok());
+        assert!(check_subscription("http://push.example/send/1", &p256dh, &auth).is_err());
+        assert!(check_subscription("https://push.example/send/1", &auth, &auth).is_err());
+
+        let body = encrypt(br#"{"type":"opponent_moved"}"#, &p256dh, &auth).unwrap();
+        let (salt, rest) = body.split_at(16);
+        assert_eq!(rest[..4], RECORD_SIZE.to_be_bytes());
+        assert_eq!(rest[4], 65);
+        let (server_key, record) = rest[5..].split_at(65);
+        let (cek, nonce) = agreement::agree_ephemeral(
+            browser_private,
+            &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, server_key),
+            |secret| content_keys(secret, &auth_secret, browser_key.as_ref(), server_key, salt),
+        )
+        .unwrap();
+        let key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek).unwrap());
+        let mut record = record.to_vec();
+        let plaintext = key
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut record,
+            )
+            .unwrap();
+        assert_eq!(plaintext, b"{\"type\":\"opponent_moved\"}\x02");
+
+        // The server's VAPID key, and the token push services check.
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        let public_key = key_pair.public_key().as_ref().to_vec();
+        let vapid = Vapid {
+            key_pair,
+            public_key: URL_SAFE_NO_PAD.encode(&public_key),
+            subject: "mailto:ops@laika.example".to_string(),
+        };
+        let token = vapid
+            .token("https://push.example/send/1", Utc::now())
+            .unwrap();
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, &public_key)
+            .verify(
+                signed.as_bytes(),
+                &URL_SAFE_NO_PAD.decode(signature).unwrap(),
+            )
+            .unwrap();
+        let claims = URL_SAFE_NO_PAD
+            .decode(signed.split_once('.').unwrap().1)
+            .unwrap();
+        let claims: serde_json::Value = serde_json::from_slice(&claims).unwrap();
+        assert_eq!(claims["aud"], "https://push.example");
+        assert_eq!(claims["sub"], "mailto:ops@laika.example");
+    }
+}
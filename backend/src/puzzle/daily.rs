@@ -0,0 +1,289 @@
+//! The puzzle of the day: everyone gets the same puzzle on the same UTC
+//! date, and solve times go on a shared leaderboard.
+//!
+//! A player starts an attempt under a name, which reveals the puzzle and
+//! starts the clock. The clock stops at the attempt's first answer; later
+//! answers are rejected, so the solution can't be looked up and resubmitted.
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Puzzle, PuzzleError};
+use crate::{
+    Error,
+    game::PlayerMove,
+    state::{AppState, new_token},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub id: Uuid,
+    /// Required to answer.
+    pub token: String,
+    pub name: String,
+    pub date: NaiveDate,
+    pub puzzle_id: u32,
+    pub started_at: DateTime<Utc>,
+    pub answered_at: Option<DateTime<Utc>>,
+    pub correct: bool,
+}
+
+impl Attempt {
+    /// How long a correct answer took; `None` until the attempt is solved.
+    pub fn solve_time(&self) -> Option<TimeDelta> {
+        self.answered_at
+            .filter(|_| self.correct)
+            .map(|answered_at| answered_at - self.started_at)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub name: String,
+    pub solve_time: TimeDelta,
+    pub answered_at: DateTime<Utc>,
+}
+
+/// Attempts at daily puzzles, by ID.
+#[derive(Debug, Default)]
+pub struct Attempts {
+    attempts: DashMap<Uuid, Attempt>,
+    /// Attempt IDs by date and name, to allow one attempt per name per day.
+    by_name: DashMap<(NaiveDate, String), Uuid>,
+}
+
+impl Attempts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts attempts loaded from storage back.
+    pub fn restore(&self, attempts: Vec<Attempt>) {
+        for attempt in attempts {
+            self.by_name
+                .insert((attempt.date, attempt.name.clone()), attempt.id);
+            self.attempts.insert(attempt.id, attempt);
+        }
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<Attempt, PuzzleError> {
+        self.attempts
+            .get(&id)
+            .map(|attempt| attempt.clone())
+            .ok_or(PuzzleError::AttemptNotFound)
+    }
+
+    pub fn start(
+        &self,
+        name: String,
+        puzzle_id: u32,
+        now: DateTime<Utc>,
+    ) -> Result<Attempt, PuzzleError> {
+        let date = now.date_naive();
+        let Entry::Vacant(slot) = self.by_name.entry((date, name.clone())) else {
+            return Err(PuzzleError::AlreadyAttempted);
+        };
+        let attempt = Attempt {
+            id: Uuid::new_v4(),
+            token: new_token(),
+            name,
+            date,
+            puzzle_id,
+            started_at: now,
+            answered_at: None,
+            correct: false,
+        };
+        self.attempts.insert(attempt.id, attempt.clone());
+        slot.insert(attempt.id);
+        Ok(attempt)
+    }
+
+    /// Records the attempt's answer. The caller checks the token.
+    pub fn answer(
+        &self,
+        id: Uuid,
+        correct: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Attempt, PuzzleError> {
+        let mut attempt = self
+            .attempts
+            .get_mut(&id)
+            .ok_or(PuzzleError::AttemptNotFound)?;
+        if attempt.answered_at.is_some() {
+            return Err(PuzzleError::AlreadyAnswered);
+        }
+        attempt.answered_at = Some(now);
+        attempt.correct = correct;
+        Ok(attempt.clone())
+    }
+
+    /// How many attempts were started on `date`, and how many solved it.
+    pub fn counts(&self, date: NaiveDate) -> (usize, usize) {
+        self.attempts
+            .iter()
+            .filter(|attempt| attempt.date == date)
+            .fold((0, 0), |(started, solved), attempt| {
+                (started + 1, solved + usize::from(attempt.correct))
+            })
+    }
+
+    /// Solved attempts on `date`, fastest first. Ties go to whoever answered
+    /// first.
+    pub fn leaderboard(&self, date: NaiveDate) -> Vec<LeaderboardEntry> {
+        let mut solved: Vec<(TimeDelta, DateTime<Utc>, String)> = self
+            .attempts
+            .iter()
+            .filter(|attempt| attempt.date == date)
+            .filter_map(|attempt| {
+                Some((
+                    attempt.solve_time()?,
+                    attempt.answered_at?,
+                    attempt.name.clone(),
+                ))
+            })
+            .collect();
+        solved.sort();
+        solved
+            .into_iter()
+            .enumerate()
+            .map(
+                |(index, (solve_time, answered_at, name))| LeaderboardEntry {
+                    rank: index + 1,
+                    name,
+                    solve_time,
+                    answered_at,
+                },
+            )
+            .collect()
+    }
+}
+
+// --- Operations ---
+
+/// Starts `name`'s attempt at today's puzzle.
+pub async fn start(
+    state: &AppState,
+    name: String,
+    now: DateTime<Utc>,
+) -> Result<(Attempt, Puzzle), Error> {
+    let puzzle = state
+        .puzzles
+        .daily(now.date_naive())
+        .map_err(Error::Puzzle)?
+        .clone();
+    let attempt = state
+        .puzzle_attempts
+        .start(name, puzzle.id, now)
+        .map_err(Error::Puzzle)?;
+    save(state, &attempt).await?;
+    Ok((attempt, puzzle))
+}
+
+/// Answers an attempt, stopping its clock.
+pub async fn answer(
+    state: &AppState,
+    attempt_id: Uuid,
+    token: &str,
+    player_move: PlayerMove,
+    now: DateTime<Utc>,
+) -> Result<(Attempt, Puzzle), Error> {
+    let attempt = state
+        .puzzle_attempts
+        .get(attempt_id)
+        .map_err(Error::Puzzle)?;
+    if attempt.token != token {
+        return Err(Error::Forbidden("Invalid attempt token"));
+    }
+    let puzzle = state
+        .puzzles
+        .get(attempt.puzzle_id)
+        .map_err(Error::Puzzle)?
+        .clone();
+    let attempt = state
+        .puzzle_attempts
+        .answer(attempt_id, player_move == puzzle.solution, now)
+        .map_err(Error::Puzzle)?;
+    save(state, &attempt).await?;
+    Ok((attempt, puzzle))
+}
+
+async fn save(state: &AppState, attempt: &Attempt) -> Result<(), Error> {
+    state
+        .store
+        .save_puzzle_attempt(attempt)
+        .await
+        .map_err(Error::Storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn test_one_attempt_per_name_per_day() {
+        let attempts = Attempts::new();
+        let attempt = attempts.start("ada".to_string(), 7, at(0)).unwrap();
+        assert_eq!(
+            attempts.start("ada".to_string(), 7, at(60)),
+            Err(PuzzleError::AlreadyAttempted)
+        );
+        assert!(attempts.start("bob".to_string(), 7, at(60)).is_ok());
+        assert!(
+            attempts
+                .start("ada".to_string(), 7, at(0) + TimeDelta::days(1))
+                .is_ok()
+        );
+
+        attempts.answer(attempt.id, true, at(5)).unwrap();
+        assert_eq!(
+            attempts.answer(attempt.id, false, at(6)),
+            Err(PuzzleError::AlreadyAnswered)
+        );
+        assert_eq!(
+            attempts.answer(Uuid::new_v4(), true, at(6)),
+            Err(PuzzleError::AttemptNotFound)
+        );
+    }
+
+    #[test]
+    fn test_leaderboard_ranks_solved_attempts_by_time() {
+        let attempts = Attempts::new();
+        let date = at(0).date_naive();
+        for (name, started, answered, correct) in [
+            ("slow", 0, 90, true),
+            ("wrong", 0, 1, false),
+            ("fast", 30, 40, true),
+            ("pending", 0, 0, false),
+        ] {
+            let attempt = attempts.start(name.to_string(), 7, at(started)).unwrap();
+            if name != "pending" {
+                attempts.answer(attempt.id, correct, at(answered)).unwrap();
+            }
+        }
+
+        let leaderboard = attempts.leaderboard(date);
+        let names: Vec<&str> = leaderboard
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, ["fast", "slow"]);
+        assert_eq!(leaderboard[0].rank, 1);
+        assert_eq!(leaderboard[0].solve_time, TimeDelta::seconds(10));
+        assert_eq!(attempts.counts(date), (4, 2));
+
+        let restored = Attempts::new();
+        restored.restore(attempts.attempts.iter().map(|a| a.clone()).collect());
+        assert_eq!(restored.leaderboard(date), leaderboard);
+        assert_eq!(
+            restored.start("fast".to_string(), 7, at(100)),
+            Err(PuzzleError::AlreadyAttempted)
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! Puzzles: positions where exactly one move wins, or exactly one move
+//! avoids losing.
+//!
+//! The puzzle set is mined offline by `laika puzzles`, which walks every
+//! reachable position, solves each candidate move, and writes the puzzles
+//! to a JSON file tagged by difficulty. The server loads that file at
+//! startup. `daily` builds the puzzle of the day on top of the set, and
+//! `rush` timed runs through it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::Path,
+};
+
+use chrono::{Datelike, NaiveDate};
+use clap::{Args, ValueEnum};
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Cell, GameState, GameStatus, Player, PlayerMove, try_move},
+    import, notation, solver,
+};
+
+pub mod daily;
+pub mod rush;
+
+#[derive(Debug, Args)]
+pub struct PuzzlesArgs {
+    /// File to write the puzzles to
+    #[arg(long, default_value = "laika-puzzles.json")]
+    pub out: std::path::PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PuzzleKind {
+    /// Find the only move that wins.
+    Win,
+    /// Find the only move that doesn't lose.
+    Save,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// Puzzles get harder the further away the payoff is: a win in one move
+    /// or a threat to block right now is easy.
+    fn from_depth(moves: u32) -> Self {
+        match moves {
+            0 | 1 => Difficulty::Easy,
+            2 => Difficulty::Medium,
+            _ => Difficulty::Hard,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Puzzle {
+    /// Derived from the board, so the same position always has the same ID.
+    pub id: u32,
+    /// Moves that lead to the position.
+    pub notation: String,
+    pub kind: PuzzleKind,
+    pub difficulty: Difficulty,
+    pub solution: PlayerMove,
+}
+
+impl Puzzle {
+    pub fn game_state(&self) -> GameState {
+        let moves = notation::parse_moves(&self.notation).expect("puzzle notation is valid");
+        import::replay(&moves)
+            .expect("puzzle moves are legal")
+            .state
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleError {
+    NotFound,
+    /// No puzzle matches the requested difficulty, or none are loaded.
+    NoneAvailable,
+    AttemptNotFound,
+    /// Each name gets one attempt at the daily puzzle per day.
+    AlreadyAttempted,
+    /// Only the first answer to an attempt counts.
+    AlreadyAnswered,
+    RushNotFound,
+    /// The puzzle rush's time is up, or it struck out.
+    RushOver,
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            PuzzleError::NotFound => "No puzzle with that ID",
+            PuzzleError::NoneAvailable => "No puzzles are available",
+            PuzzleError::AttemptNotFound => "No puzzle attempt with that ID",
+            PuzzleError::AlreadyAttempted => "That name already attempted today's puzzle",
+            PuzzleError::AlreadyAnswered => "That attempt was already answered",
+            PuzzleError::RushNotFound => "No puzzle rush with that ID",
+            PuzzleError::RushOver => "That puzzle rush is over",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for PuzzleError {}
+
+/// A board as a base-3 number, one digit per cell.
+fn board_id(game_state: &GameState) -> u32 {
+    game_state
+        .board
+        .rows()
+        .iter()
+        .flatten()
+        .fold(0, |id, cell| {
+            id * 3
+                + match cell {
+                    Cell::Empty => 0,
+                    Cell::Occupied(Player::X) => 1,
+                    Cell::Occupied(Player::O) => 2,
+                    Cell::Blocked => unreachable!("puzzles are mined from open boards"),
+                }
+        })
+}
+
+/// Turns a position into a puzzle if exactly one of its moves is right.
+fn mine(game_state: &GameState, notation: &str) -> Option<Puzzle> {
+    let to_play = game_state.to_play;
+    // Each move with its value for the side that played it.
+    let results: Vec<(PlayerMove, solver::Value)> = game_state
+        .board
+        .empty_cells()
+        .map(|(row, col)| {
+            let player_move = PlayerMove { row, col };
+            let mut after = *game_state;
+            try_move(&mut after, to_play, player_move).expect("empty cells are legal moves");
+            (player_move, solver::solve(&after))
+        })
+        .collect();
+    if results.len() < 2 {
+        return None;
+    }
+    let winning = |value: &solver::Value| value.winner(to_play.opponent()) == Some(to_play);
+    let losing =
+        |value: &solver::Value| value.winner(to_play.opponent()) == Some(to_play.opponent());
+
+    let wins: Vec<_> = results.iter().filter(|(_, value)| winning(value)).collect();
+    let (kind, solution, depth) = match wins.as_slice() {
+        [(solution, value)] => {
+            // The solution is one of the winner's moves, on top of the
+            // ones still to come after it.
+            let moves = value.moves_to_win().expect("a won position has a winner") + 1;
+            (PuzzleKind::Win, *solution, moves)
+        }
+        [] => {
+            let safe: Vec<_> = results.iter().filter(|(_, value)| !losing(value)).collect();
+            let [(solution, _)] = safe.as_slice() else {
+                return None;
+            };
+            // How soon the quickest refutation of a wrong move lands.
+            let depth = results
+                .iter()
+                .filter(|(_, value)| losing(value))
+                .filter_map(|(_, value)| value.moves_to_win())
+                .min()
+                .expect("every other move loses");
+            (PuzzleKind::Save, *solution, depth)
+        }
+        _ => return None,
+    };
+    Some(Puzzle {
+        id: board_id(game_state),
+        notation: notation.to_string(),
+        kind,
+        difficulty: Difficulty::from_depth(depth),
+        solution,
+    })
+}
+
+/// Mines every reachable position for puzzles, ordered by ID.
+pub fn generate() -> Vec<Puzzle> {
+    fn walk(
+        game_state: GameState,
+        notation: String,
+        seen: &mut HashMap<u32, ()>,
+        puzzles: &mut Vec<Puzzle>,
+    ) {
+        if game_state.status != GameStatus::InProgress
+            || seen.insert(board_id(&game_state), ()).is_some()
+        {
+            return;
+        }
+        if let Some(puzzle) = mine(&game_state, &notation) {
+            puzzles.push(puzzle);
+        }
+        for (row, col) in game_state.board.empty_cells() {
+            let player_move = PlayerMove { row, col };
+            let mut after = game_state;
+            try_move(&mut after, game_state.to_play, player_move)
+                .expect("empty cells are legal moves");
+            let token = format!("{:?}:{}", game_state.to_play, notation::square(player_move));
+            let notation = if notation.is_empty() {
+                token
+            } else {
+                format!("{notation} {token}")
+            };
+            walk(after, notation, seen, puzzles);
+        }
+    }
+
+    let mut puzzles = Vec::new();
+    walk(
+        GameState::default(),
+        String::new(),
+        &mut HashMap::new(),
+        &mut puzzles,
+    );
+    puzzles.sort_by_key(|puzzle| puzzle.id);
+    puzzles
+}
+
+/// The loaded puzzle set.
+#[derive(Debug, Default)]
+pub struct Puzzles {
+    puzzles: Vec<Puzzle>,
+}
+
+impl Puzzles {
+    pub fn new(mut puzzles: Vec<Puzzle>) -> Self {
+        puzzles.sort_by_key(|puzzle| puzzle.id);
+        Self { puzzles }
+    }
+
+    /// Reads puzzles written by `laika puzzles`. A missing file means no
+    /// puzzles rather than an error.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Self::new(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.puzzles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.puzzles.is_empty()
+    }
+
+    pub fn get(&self, id: u32) -> Result<&Puzzle, PuzzleError> {
+        self.puzzles
+            .binary_search_by_key(&id, |puzzle| puzzle.id)
+            .map(|index| &self.puzzles[index])
+            .map_err(|_| PuzzleError::NotFound)
+    }
+
+    pub fn random(&self, difficulty: Option<Difficulty>) -> Result<&Puzzle, PuzzleError> {
+        let candidates: Vec<&Puzzle> = self
+            .puzzles
+            .iter()
+            .filter(|puzzle| difficulty.is_none_or(|difficulty| puzzle.difficulty == difficulty))
+            .collect();
+        candidates
+            .choose(&mut rand::rng())
+            .copied()
+            .ok_or(PuzzleError::NoneAvailable)
+    }
+
+    /// A random puzzle of `difficulty` that isn't in `seen`, or of another
+    /// difficulty once those run out. Puzzles only repeat once all of them
+    /// have been seen.
+    pub fn random_unseen(
+        &self,
+        difficulty: Difficulty,
+        seen: &HashSet<u32>,
+    ) -> Result<&Puzzle, PuzzleError> {
+        let unseen: Vec<&Puzzle> = self
+            .puzzles
+            .iter()
+            .filter(|puzzle| !seen.contains(&puzzle.id))
+            .collect();
+        let candidates: Vec<&Puzzle> = unseen
+            .iter()
+            .copied()
+            .filter(|puzzle| puzzle.difficulty == difficulty)
+            .collect();
+        let mut rng = rand::rng();
+        match candidates
+            .choose(&mut rng)
+            .or_else(|| unseen.choose(&mut rng))
+        {
+            Some(puzzle) => Ok(puzzle),
+            None => self.random(None),
+        }
+    }
+
+    /// The puzzle of the day. The pick depends only on the date and the
+    /// puzzle set, so every instance serves the same one.
+    pub fn daily(&self, date: NaiveDate) -> Result<&Puzzle, PuzzleError> {
+        if self.puzzles.is_empty() {
+            return Err(PuzzleError::NoneAvailable);
+        }
+        // Scatter consecutive days across the set.
+        let hash = (date.num_days_from_ce() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 16;
+        Ok(&self.puzzles[(hash % self.puzzles.len() as u64) as usize])
+    }
+}
+
+/// Generates the puzzle set and writes it to `args.out`.
+pub fn run(args: &PuzzlesArgs) -> io::Result<()> {
+    let puzzles = generate();
+    fs::write(&args.out, serde_json::to_vec_pretty(&puzzles)?)?;
+    for difficulty in Difficulty::value_variants() {
+        let count = puzzles
+            .iter()
+            .filter(|puzzle| puzzle.difficulty == *difficulty)
+            .count();
+        println!(
+            "{:<8} {:>5}",
+            format!("{difficulty:?}").to_lowercase(),
+            count
+        );
+    }
+    println!("Wrote {} puzzles to {}", puzzles.len(), args.out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(moves: &str) -> GameState {
+        import::replay(&notation::parse_moves(moves).unwrap())
+            .unwrap()
+            .state
+    }
+
+    #[test]
+    fn test_mining_finds_the_only_winning_move() {
+        // Completing the diagonal wins, and also blocks O's column.
+        let moves = "X:b2 O:c2 X:a1 O:c1";
+        let puzzle = mine(&position(moves), moves).unwrap();
+        assert_eq!(puzzle.kind, PuzzleKind::Win);
+        assert_eq!(puzzle.difficulty, Difficulty::Easy);
+        assert_eq!(notation::square(puzzle.solution), "c3");
+        assert_eq!(puzzle.game_state(), position(moves));
+
+        // O must block the diagonal or lose right away.
+        let moves = "X:b2 O:c1 X:a1";
+        let puzzle = mine(&position(moves), moves).unwrap();
+        assert_eq!(puzzle.kind, PuzzleKind::Save);
+        assert_eq!(notation::square(puzzle.solution), "c3");
+
+        // Every first move draws, so the empty board is no puzzle.
+        assert_eq!(mine(&GameState::default(), ""), None);
+    }
+
+    #[test]
+    fn test_generated_puzzles_are_unique_and_solvable() {
+        let puzzles = Puzzles::new(generate());
+        assert!(puzzles.len() > 100);
+        for difficulty in Difficulty::value_variants() {
+            assert!(puzzles.random(Some(*difficulty)).is_ok(), "{difficulty:?}");
+        }
+        let ids: Vec<u32> = puzzles.puzzles.iter().map(|puzzle| puzzle.id).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let puzzle = puzzles.random(None).unwrap();
+        assert_eq!(puzzles.get(puzzle.id), Ok(puzzle));
+        assert_eq!(board_id(&puzzle.game_state()), puzzle.id);
+        assert_eq!(puzzles.get(u32::MAX), Err(PuzzleError::NotFound));
+    }
+}
@@ -0,0 +1,299 @@
+//! Puzzle rush: as many puzzles as a player can solve against the clock.
+//!
+//! A run starts under a name and lasts `DURATION`. Answers are checked
+//! here, a right one scores a point, and the next puzzle follows at once,
+//! harder as the score grows. The run ends when time is up or at the
+//! `MAX_STRIKES`th wrong answer, and its score goes on the leaderboard.
+//!
+//! Every answer spends the run's token and the response carries the next
+//! one, so a request can't be replayed to answer a puzzle twice. Runs and
+//! the leaderboard live in memory only.
+
+use std::{cmp::Reverse, collections::HashSet, sync::Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use super::{Difficulty, Puzzle, PuzzleError, Puzzles};
+use crate::{Error, game::PlayerMove, state::new_token};
+
+pub const DURATION: TimeDelta = TimeDelta::minutes(3);
+/// Wrong answers that end a run early.
+pub const MAX_STRIKES: u32 = 3;
+const LEADERBOARD_SIZE: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub id: Uuid,
+    /// Required to answer, and replaced by every answer.
+    pub token: String,
+    pub name: String,
+    pub started_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    /// The puzzle waiting for an answer.
+    pub puzzle_id: u32,
+    pub score: u32,
+    pub strikes: u32,
+    /// Set when the run ends before its time is up.
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Puzzles served so far, so none comes up twice.
+    seen: HashSet<u32>,
+}
+
+impl Run {
+    pub fn is_over(&self, now: DateTime<Utc>) -> bool {
+        self.finished_at.is_some() || now >= self.ends_at
+    }
+
+    /// When the run ended, or will end if nothing else stops it.
+    fn end(&self) -> DateTime<Utc> {
+        self.finished_at.unwrap_or(self.ends_at)
+    }
+
+    /// Easy puzzles to warm up, then medium and hard ones.
+    fn difficulty(&self) -> Difficulty {
+        match self.score {
+            0..5 => Difficulty::Easy,
+            5..10 => Difficulty::Medium,
+            _ => Difficulty::Hard,
+        }
+    }
+
+    fn serve(&mut self, puzzles: &Puzzles) -> Result<Puzzle, PuzzleError> {
+        let puzzle = puzzles.random_unseen(self.difficulty(), &self.seen)?;
+        self.puzzle_id = puzzle.id;
+        self.seen.insert(puzzle.id);
+        Ok(puzzle.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub name: String,
+    pub score: u32,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// The result of an answer: the puzzle it was for, and the one after it
+/// unless the run is over.
+#[derive(Debug, Clone)]
+pub struct Answered {
+    pub run: Run,
+    pub correct: bool,
+    pub puzzle: Puzzle,
+    pub next: Option<Puzzle>,
+}
+
+/// Runs in progress, by ID, and the best finished ones.
+#[derive(Debug, Default)]
+pub struct Rushes {
+    runs: DashMap<Uuid, Run>,
+    /// Best first: higher scores, then whoever finished first.
+    best: Mutex<Vec<(u32, DateTime<Utc>, String)>>,
+}
+
+impl Rushes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts `name`'s run and serves its first puzzle.
+    pub fn start(
+        &self,
+        puzzles: &Puzzles,
+        name: String,
+        now: DateTime<Utc>,
+    ) -> Result<(Run, Puzzle), PuzzleError> {
+        self.retire(now);
+        let mut run = Run {
+            id: Uuid::new_v4(),
+            token: new_token(),
+            name,
+            started_at: now,
+            ends_at: now + DURATION,
+            puzzle_id: 0,
+            score: 0,
+            strikes: 0,
+            finished_at: None,
+            seen: HashSet::new(),
+        };
+        let puzzle = run.serve(puzzles)?;
+        self.runs.insert(run.id, run.clone());
+        Ok((run, puzzle))
+    }
+
+    /// Checks an answer to the run's current puzzle and serves the next.
+    pub fn answer(
+        &self,
+        puzzles: &Puzzles,
+        id: Uuid,
+        token: &str,
+        player_move: PlayerMove,
+        now: DateTime<Utc>,
+    ) -> Result<Answered, Error> {
+        let mut run = self
+            .runs
+            .get_mut(&id)
+            .ok_or(Error::Puzzle(PuzzleError::RushNotFound))?;
+        if run.token != token {
+            return Err(Error::Forbidden("Invalid rush token"));
+        }
+        if run.is_over(now) {
+            let over = run.clone();
+            // `finish` takes the map's lock again.
+            drop(run);
+            self.finish(over);
+            return Err(Error::Puzzle(PuzzleError::RushOver));
+        }
+        let puzzle = puzzles.get(run.puzzle_id).map_err(Error::Puzzle)?.clone();
+        let correct = player_move == puzzle.solution;
+        if correct {
+            run.score += 1;
+        } else {
+            run.strikes += 1;
+        }
+        run.token = new_token();
+        let next = if run.strikes >= MAX_STRIKES {
+            run.finished_at = Some(now);
+            None
+        } else {
+            Some(run.serve(puzzles).map_err(Error::Puzzle)?)
+        };
+        let updated = run.clone();
+        drop(run);
+        if next.is_none() {
+            self.finish(updated.clone());
+        }
+        Ok(Answered {
+            run: updated,
+            correct,
+            puzzle,
+            next,
+        })
+    }
+
+    /// The best finished runs.
+    pub fn leaderboard(&self, now: DateTime<Utc>) -> Vec<LeaderboardEntry> {
+        self.retire(now);
+        self.best
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(index, (score, finished_at, name))| LeaderboardEntry {
+                rank: index + 1,
+                name: name.clone(),
+                score: *score,
+                finished_at: *finished_at,
+            })
+            .collect()
+    }
+
+    /// Finishes the runs whose time is up.
+    fn retire(&self, now: DateTime<Utc>) {
+        let over: Vec<Uuid> = self
+            .runs
+            .iter()
+            .filter(|run| run.is_over(now))
+            .map(|run| run.id)
+            .collect();
+        for id in over {
+            if let Some((_, run)) = self.runs.remove(&id) {
+                self.finish(run);
+            }
+        }
+    }
+
+    /// Drops a run that is over, putting it on the leaderboard if it made it.
+    fn finish(&self, run: Run) {
+        self.runs.remove(&run.id);
+        if run.score == 0 {
+            return;
+        }
+        let mut best = self.best.lock().unwrap();
+        best.push((run.score, run.end(), run.name));
+        best.sort_by_key(|(score, end, _)| (Reverse(*score), *end));
+        best.truncate(LEADERBOARD_SIZE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puzzle::generate;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000 + secs, 0).unwrap()
+    }
+
+    fn wrong(puzzle: &Puzzle) -> PlayerMove {
+        PlayerMove {
+            row: puzzle.solution.row,
+            col: (puzzle.solution.col + 1) % 3,
+        }
+    }
+
+    #[test]
+    fn test_a_rush_scores_answers_until_time_or_strikes_run_out() {
+        let puzzles = Puzzles::new(generate());
+        let rushes = Rushes::new();
+
+        let (run, mut puzzle) = rushes.start(&puzzles, "ada".to_string(), at(0)).unwrap();
+        let mut token = run.token;
+        for (secs, right) in [(1, true), (2, true), (3, false), (4, false), (5, false)] {
+            let answer = if right {
+                puzzle.solution
+            } else {
+                wrong(&puzzle)
+            };
+            let answered = rushes
+                .answer(&puzzles, run.id, &token, answer, at(secs))
+                .unwrap();
+            assert_eq!(answered.correct, right);
+            // The spent token can't answer again, nor can anyone once the
+            // run has struck out.
+            let replayed = rushes.answer(&puzzles, run.id, &token, answer, at(secs));
+            token = answered.run.token;
+            match answered.next {
+                Some(next) => {
+                    assert!(matches!(replayed, Err(Error::Forbidden(_))));
+                    puzzle = next;
+                }
+                None => {
+                    assert_eq!(answered.run.strikes, MAX_STRIKES);
+                    assert!(matches!(
+                        replayed,
+                        Err(Error::Puzzle(PuzzleError::RushNotFound))
+                    ));
+                }
+            }
+        }
+
+        // A run that outlasts its time is refused, then ranked.
+        let (late, puzzle) = rushes.start(&puzzles, "bob".to_string(), at(0)).unwrap();
+        let answered = rushes
+            .answer(&puzzles, late.id, &late.token, puzzle.solution, at(10))
+            .unwrap();
+        let after_time = at(0) + DURATION;
+        assert!(matches!(
+            rushes.answer(
+                &puzzles,
+                late.id,
+                &answered.run.token,
+                puzzle.solution,
+                after_time
+            ),
+            Err(Error::Puzzle(PuzzleError::RushOver))
+        ));
+
+        let leaderboard = rushes.leaderboard(after_time);
+        let ranking: Vec<(&str, u32)> = leaderboard
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.score))
+            .collect();
+        assert_eq!(ranking, [("ada", 2), ("bob", 1)]);
+        assert_eq!(leaderboard[0].finished_at, at(5));
+    }
+}
@@ -0,0 +1,302 @@
+//! Boards drawn as PNG images, for link previews and chat clients that
+//! won't show anything richer.
+//!
+//! A small canvas is enough for a board: filled rectangles for cells,
+//! thick strokes for X, rings for O, all with soft edges. The last move's
+//! cell is tinted, and so are the cells of a winning line, which works the
+//! same on a torus, where a line can wrap around the edge. The PNG is
+//! written by hand; only the compression comes from `miniz_oxide`.
+
+use crate::game::{Cell, GameState, Player, PlayerMove};
+
+type Rgb = [u8; 3];
+
+/// Pixels per cell, and around the board.
+const CELL: usize = 100;
+const MARGIN: usize = 16;
+/// Width of the grid lines, and of the strokes of X and O.
+const GRID_WIDTH: f32 = 4.0;
+const MARK_WIDTH: f32 = 10.0;
+
+const BACKGROUND: Rgb = [0xf8, 0xf8, 0xf8];
+const GRID: Rgb = [0x33, 0x33, 0x33];
+const BLOCKED: Rgb = [0xb0, 0xb0, 0xb0];
+const LAST_MOVE: Rgb = [0xff, 0xf0, 0xa8];
+const WINNING_LINE: Rgb = [0xbc, 0xec, 0xbc];
+const X_MARK: Rgb = [0x1f, 0x5f, 0xbf];
+const O_MARK: Rgb = [0xc0, 0x39, 0x2b];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, color: Rgb) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        }
+    }
+
+    /// Mixes `color` into the pixel at `(x, y)`, `alpha` being how much of
+    /// it to take, from 0 to 1.
+    fn blend(&mut self, x: usize, y: usize, color: Rgb, alpha: f32) {
+        if x >= self.width || y >= self.height || alpha <= 0.0 {
+            return;
+        }
+        let alpha = alpha.min(1.0);
+        let pixel = &mut self.pixels[y * self.width + x];
+        for (channel, target) in pixel.iter_mut().zip(color) {
+            let mixed = f32::from(*channel) * (1.0 - alpha) + f32::from(target) * alpha;
+            *channel = mixed.round() as u8;
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                self.pixels[y * self.width + x] = color;
+            }
+        }
+    }
+
+    /// Paints every pixel near the shape `distance` measures, which gives
+    /// how far a point is from the middle of the stroke. Pixels within
+    /// `width / 2` are covered, with a pixel-wide soft edge.
+    fn stroke(
+        &mut self,
+        bounds: (f32, f32, f32, f32),
+        width: f32,
+        color: Rgb,
+        distance: impl Fn(f32, f32) -> f32,
+    ) {
+        let reach = width / 2.0 + 1.0;
+        let (left, top, right, bottom) = bounds;
+        let x_range = (left - reach).max(0.0) as usize..=(right + reach) as usize;
+        for y in (top - reach).max(0.0) as usize..=(bottom + reach) as usize {
+            for x in x_range.clone() {
+                // Sample the middle of the pixel.
+                let d = distance(x as f32 + 0.5, y as f32 + 0.5);
+                self.blend(x, y, color, width / 2.0 + 0.5 - d);
+            }
+        }
+    }
+
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, color: Rgb) {
+        let bounds = (
+            from.0.min(to.0),
+            from.1.min(to.1),
+            from.0.max(to.0),
+            from.1.max(to.1),
+        );
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length_squared = dx * dx + dy * dy;
+        self.stroke(bounds, width, color, |x, y| {
+            // The nearest point on the segment, as a share of the way along.
+            let t = if length_squared == 0.0 {
+                0.0
+            } else {
+                (((x - from.0) * dx + (y - from.1) * dy) / length_squared).clamp(0.0, 1.0)
+            };
+            let (nx, ny) = (from.0 + t * dx, from.1 + t * dy);
+            ((x - nx).powi(2) + (y - ny).powi(2)).sqrt()
+        });
+    }
+
+    fn ring(&mut self, center: (f32, f32), radius: f32, width: f32, color: Rgb) {
+        let bounds = (
+            center.0 - radius,
+            center.1 - radius,
+            center.0 + radius,
+            center.1 + radius,
+        );
+        self.stroke(bounds, width, color, |x, y| {
+            (((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt() - radius).abs()
+        });
+    }
+
+    /// The canvas as an 8-bit RGB PNG.
+    fn to_png(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, truecolor, deflate, adaptive filtering, no interlace.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        // Each scanline starts with its filter type, 0 for none.
+        let mut scanlines = Vec::with_capacity(self.height * (1 + self.width * 3));
+        for row in self.pixels.chunks(self.width) {
+            scanlines.push(0);
+            scanlines.extend(row.iter().flatten());
+        }
+        let data = miniz_oxide::deflate::compress_to_vec_zlib(&scanlines, 6);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &data);
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// The CRC-32 PNG chunks end with.
+fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    0xedb8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !bytes.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The width and height of the image of a `rows` x `cols` board.
+pub fn image_size(rows: usize, cols: usize) -> (usize, usize) {
+    (cols * CELL + 2 * MARGIN, rows * CELL + 2 * MARGIN)
+}
+
+/// Draws the board of `game_state` as a PNG, tinting the cell of
+/// `last_move` and those of a winning line.
+pub fn board_png(game_state: &GameState, last_move: Option<PlayerMove>) -> Vec<u8> {
+    let board = &game_state.board;
+    let (rows, cols) = board.size();
+    let (width, height) = image_size(rows, cols);
+    let mut canvas = Canvas::new(width, height, BACKGROUND);
+    let origin = |row: usize, col: usize| (MARGIN + col * CELL, MARGIN + row * CELL);
+
+    if let Some(last_move) = last_move {
+        let (x, y) = origin(last_move.row, last_move.col);
+        canvas.fill_rect(x, y, CELL, CELL, LAST_MOVE);
+    }
+    for (row, col) in board.winning_line(game_state.rules).unwrap_or_default() {
+        let (x, y) = origin(row, col);
+        canvas.fill_rect(x, y, CELL, CELL, WINNING_LINE);
+    }
+
+    // Lines between cells only, like a board drawn on paper.
+    let (left, top) = (MARGIN as f32, MARGIN as f32);
+    let (right, bottom) = ((MARGIN + cols * CELL) as f32, (MARGIN + rows * CELL) as f32);
+    for col in 1..cols {
+        let x = (MARGIN + col * CELL) as f32;
+        canvas.line((x, top), (x, bottom), GRID_WIDTH, GRID);
+    }
+    for row in 1..rows {
+        let y = (MARGIN + row * CELL) as f32;
+        canvas.line((left, y), (right, y), GRID_WIDTH, GRID);
+    }
+
+    let inset = CELL as f32 * 0.25;
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x, y) = origin(row, col);
+            let (x0, y0) = (x as f32 + inset, y as f32 + inset);
+            let (x1, y1) = ((x + CELL) as f32 - inset, (y + CELL) as f32 - inset);
+            match board.get(row, col) {
+                Cell::Empty => {}
+                Cell::Blocked => {
+                    let gap = (GRID_WIDTH / 2.0).ceil() as usize + 4;
+                    canvas.fill_rect(x + gap, y + gap, CELL - 2 * gap, CELL - 2 * gap, BLOCKED);
+                }
+                Cell::Occupied(Player::X) => {
+                    canvas.line((x0, y0), (x1, y1), MARK_WIDTH, X_MARK);
+                    canvas.line((x0, y1), (x1, y0), MARK_WIDTH, X_MARK);
+                }
+                Cell::Occupied(Player::O) => {
+                    let center = ((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+                    canvas.ring(center, (x1 - x0) / 2.0, MARK_WIDTH, O_MARK);
+                }
+            }
+        }
+    }
+    canvas.to_png()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::try_move;
+
+    /// The pixels of `png`, which must be one this module wrote.
+    fn decode(png: &[u8]) -> (usize, usize, Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (body, crc) = rest[4..].split_at(4 + length);
+            assert_eq!(crc32(body).to_be_bytes(), crc[..4]);
+            chunks.push((body[..4].to_vec(), body[4..].to_vec()));
+            rest = &crc[4..];
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+        let width = u32::from_be_bytes(chunks[0].1[..4].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(chunks[0].1[4..8].try_into().unwrap()) as usize;
+        let pixels = miniz_oxide::inflate::decompress_to_vec_zlib(&chunks[1].1).unwrap();
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn test_boards_render_with_the_last_move_and_winning_line_tinted() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+
+        let mut game_state = GameState::default();
+        let moves = [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)];
+        let mut player = Player::X;
+        for (row, col) in moves {
+            try_move(&mut game_state, player, PlayerMove { row, col }).unwrap();
+            player = player.opponent();
+        }
+        let png = board_png(&game_state, Some(PlayerMove { row: 0, col: 2 }));
+        let (width, height, pixels) = decode(&png);
+        assert_eq!(
+            (width, height),
+            (3 * CELL + 2 * MARGIN, 3 * CELL + 2 * MARGIN)
+        );
+        assert_eq!(pixels.len(), height * (1 + width * 3));
+
+        let pixel = |x: usize, y: usize| -> Rgb {
+            let start = y * (1 + width * 3) + 1 + x * 3;
+            pixels[start..start + 3].try_into().unwrap()
+        };
+        // Near a corner of each cell, clear of the marks.
+        let corner =
+            |row: usize, col: usize| pixel(MARGIN + col * CELL + 8, MARGIN + row * CELL + 8);
+        // The last move finished the line, so it shows as part of it.
+        assert_eq!(corner(0, 2), WINNING_LINE);
+        assert_eq!(corner(0, 0), WINNING_LINE);
+        assert_eq!(corner(1, 0), BACKGROUND);
+        // The middle of X's cross and a point on O's ring.
+        let middle = MARGIN + CELL / 2;
+        assert_eq!(pixel(middle, middle), X_MARK);
+        let radius = (CELL as f32 * 0.25) as usize;
+        assert_eq!(pixel(middle - radius, middle + CELL), O_MARK);
+    }
+}
@@ -0,0 +1,126 @@
+//! Reporting handler panics and server errors to Sentry.
+//!
+//! Each API request gets its own Sentry hub, tagged with the route it
+//! matched, the game it is about if any, and the client's address with the
+//! host part zeroed. A panic while handling the request is reported from
+//! that hub by Sentry's panic hook, and so is any `500 Internal Server
+//! Error` response, such as a storage failure, so either can be traced back
+//! to the request behind it. Other 5xx statuses stand for known conditions,
+//! like maintenance mode or an offline bot, and aren't reported.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{FromRequestParts, MatchedPath, RawPathParams, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use sentry::{ClientInitGuard, ClientOptions, Hub, Level, SentryFutureExt, protocol::IpAddress};
+
+use crate::{ErrorBody, api::ClientIp, config::SentryConfig};
+
+/// Starts reporting, if a DSN is configured. Reports are sent until the
+/// guard is dropped, which waits for those still queued.
+pub fn init(config: &SentryConfig) -> Option<ClientInitGuard> {
+    let dsn = config.dsn.as_deref()?;
+    let guard = sentry::init((
+        dsn,
+        ClientOptions {
+            release: sentry::release_name!(),
+            environment: config.environment.clone().map(Into::into),
+            // Addresses are added by `capture`, anonymized.
+            send_default_pii: false,
+            ..ClientOptions::default()
+        },
+    ));
+    if guard.is_enabled() {
+        log::info!("Reporting panics and server errors to Sentry");
+    } else {
+        log::error!("sentry.dsn is invalid; nothing will be reported");
+    }
+    Some(guard)
+}
+
+/// The network an address belongs to, without the host: the /24 of an IPv4
+/// address or the /48 of an IPv6 one.
+fn anonymize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// Handles the request with its own hub, tagged with what the request was
+/// about, and reports the response if it is an internal server error.
+pub async fn capture(request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let route = parts
+        .extensions
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let game_id = RawPathParams::from_request_parts(&mut parts, &())
+        .await
+        .ok()
+        .and_then(|params| {
+            params
+                .iter()
+                .find(|(name, _)| *name == "game_id")
+                .map(|(_, value)| value.to_string())
+        });
+    let Ok(ClientIp(ip)) = ClientIp::from_request_parts(&mut parts, &()).await;
+    let method = parts.method.clone();
+    let request = Request::from_parts(parts, body);
+
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    hub.configure_scope(|scope| {
+        scope.set_tag("method", &method);
+        if let Some(route) = &route {
+            scope.set_tag("route", route);
+        }
+        if let Some(game_id) = &game_id {
+            scope.set_tag("game_id", game_id);
+        }
+        if let Some(ip) = ip {
+            scope.set_user(Some(sentry::User {
+                ip_address: Some(IpAddress::Exact(anonymize(ip))),
+                ..sentry::User::default()
+            }));
+        }
+    });
+    let response = next.run(request).bind_hub(hub.clone()).await;
+
+    let status = response.status();
+    if status == StatusCode::INTERNAL_SERVER_ERROR {
+        let message = match response.extensions().get::<ErrorBody>() {
+            Some(error) => format!("{} {}: {}", status.as_u16(), error.code, error.message),
+            None => status.to_string(),
+        };
+        hub.capture_message(&message, Level::Error);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_are_reported_without_the_host() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(anonymize(ip("203.0.113.57")), ip("203.0.113.0"));
+        assert_eq!(
+            anonymize(ip("2001:db8:85a3:8d3:1319:8a2e:370:7348")),
+            ip("2001:db8:85a3::")
+        );
+    }
+}
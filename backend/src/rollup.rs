@@ -0,0 +1,281 @@
+//! Daily statistics, rolled up once a day so reading them never scans games.
+//!
+//! Each rollup covers the games that finished on one UTC day: how many,
+//! how they ended, how long they ran, and how many players took part. The
+//! nightly job rolls up every day since the last one, so days missed while
+//! the server was down are caught up. Games against the engine are also
+//! counted by difficulty, for `calibration`. Games in tenants aren't
+//! counted.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::RwLock,
+};
+
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    calibration::{self, Outcomes},
+    config::StatsConfig,
+    game::{GameStatus, Player},
+    state::{AppState, GameEntry, GameMode},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyStats {
+    pub day: NaiveDate,
+    /// Games that finished that day.
+    pub games: u64,
+    pub x_wins: u64,
+    pub o_wins: u64,
+    pub draws: u64,
+    /// Moves per game; 0 on a day without games.
+    pub average_moves: f64,
+    /// Named players and bots who finished a game that day, each counted
+    /// once. Anonymous players against the engine or a bot aren't counted.
+    pub distinct_players: u64,
+    /// Games against the engine, by difficulty; see
+    /// `calibration::difficulty`.
+    #[serde(default)]
+    pub by_difficulty: BTreeMap<String, Outcomes>,
+    pub rolled_up_at: DateTime<Utc>,
+}
+
+impl DailyStats {
+    /// Tallies `games`, which all finished on `day`.
+    pub fn tally<'a>(
+        day: NaiveDate,
+        games: impl IntoIterator<Item = &'a GameEntry>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let mut stats = Self {
+            day,
+            games: 0,
+            x_wins: 0,
+            o_wins: 0,
+            draws: 0,
+            average_moves: 0.0,
+            distinct_players: 0,
+            by_difficulty: BTreeMap::new(),
+            rolled_up_at: now,
+        };
+        let mut moves = 0;
+        let mut players = HashSet::new();
+        for entry in games {
+            stats.games += 1;
+            match entry.state.status {
+                GameStatus::Win(Player::X) => stats.x_wins += 1,
+                GameStatus::Win(Player::O) => stats.o_wins += 1,
+                GameStatus::Draw => stats.draws += 1,
+                GameStatus::InProgress => {}
+            }
+            moves += entry.state.version;
+            players.extend(participants(&entry.mode));
+            if entry.mode == GameMode::VsEngine {
+                stats
+                    .by_difficulty
+                    .entry(calibration::difficulty(entry.blunder_chance))
+                    .or_default()
+                    .add(entry.state.status);
+            }
+        }
+        if stats.games > 0 {
+            stats.average_moves = moves as f64 / stats.games as f64;
+        }
+        stats.distinct_players = players.len() as u64;
+        stats
+    }
+}
+
+/// Who took part in a game, by seat name or bot ID.
+fn participants(mode: &GameMode) -> Vec<String> {
+    match mode {
+        GameMode::VsEngine => Vec::new(),
+        GameMode::Pvp { x, o } => vec![x.name.clone(), o.name.clone()],
+        GameMode::Open { x } => vec![x.name.clone()],
+        GameMode::VsBot { bot_id, .. } => vec![bot_id.to_string()],
+    }
+}
+
+/// The start and end of `day`, in UTC.
+pub fn bounds(day: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = day.and_time(NaiveTime::MIN).and_utc();
+    (start, start + TimeDelta::days(1))
+}
+
+/// Every rollup so far, by day.
+#[derive(Debug, Default)]
+pub struct Rollups {
+    days: RwLock<BTreeMap<NaiveDate, DailyStats>>,
+}
+
+impl Rollups {
+    /// Loads the rollups saved before a restart.
+    pub fn restore(&self, saved: Vec<DailyStats>) {
+        let mut days = self.days.write().expect("rollups poisoned");
+        days.extend(saved.into_iter().map(|stats| (stats.day, stats)));
+    }
+
+    fn insert(&self, stats: DailyStats) {
+        let mut days = self.days.write().expect("rollups poisoned");
+        days.insert(stats.day, stats);
+    }
+
+    fn latest(&self) -> Option<NaiveDate> {
+        let days = self.days.read().expect("rollups poisoned");
+        days.keys().next_back().copied()
+    }
+
+    /// The days from `from` to `to`, both included, that have been rolled up.
+    pub fn range(&self, from: NaiveDate, to: NaiveDate) -> Vec<DailyStats> {
+        let days = self.days.read().expect("rollups poisoned");
+        days.range(from..=to)
+            .map(|(_, stats)| stats.clone())
+            .collect()
+    }
+}
+
+/// The days to roll up as of `now`: every finished day after the latest
+/// rollup, or the last `backfill_days` before the first one.
+fn pending_days(
+    latest: Option<NaiveDate>,
+    now: DateTime<Utc>,
+    backfill_days: u32,
+) -> Vec<NaiveDate> {
+    let today = now.date_naive();
+    let first = match latest {
+        Some(latest) => latest + Days::new(1),
+        None => today - Days::new(u64::from(backfill_days)),
+    };
+    first.iter_days().take_while(|day| *day < today).collect()
+}
+
+/// Tallies `day` from the games in the store, or from the registry and the
+/// archive for stores that keep nothing beyond them.
+async fn roll_up_day(state: &AppState, day: NaiveDate) -> Result<DailyStats, String> {
+    if let Some(stats) = state
+        .store
+        .roll_up_day(day)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return Ok(stats);
+    }
+    let (start, end) = bounds(day);
+    let registry = state.snapshot_for_store().await;
+    let finished = registry.values().filter(|entry| {
+        entry.tenant.is_none()
+            && entry
+                .finished_at
+                .is_some_and(|finished_at| start <= finished_at && finished_at < end)
+    });
+    Ok(DailyStats::tally(day, finished, Utc::now()))
+}
+
+/// Rolls up every day that has ended since the last rollup. Run at startup
+/// and then daily at `stats.rollup_hour`.
+pub async fn roll_up(state: AppState, config: StatsConfig) {
+    for day in pending_days(state.rollups.latest(), Utc::now(), config.backfill_days) {
+        let stats = match roll_up_day(&state, day).await {
+            Ok(stats) => stats,
+            Err(e) => {
+                // The next run starts over from here.
+                log::error!("Failed to roll up stats for {}: {}", day, e);
+                return;
+            }
+        };
+        if let Err(e) = state.store.save_daily_stats(&stats).await {
+            log::error!("Failed to save stats for {}: {}", day, e);
+            return;
+        }
+        log::debug!("Rolled up {} games finished on {}", stats.games, day);
+        state.rollups.insert(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        game::GameState,
+        state::{GameRegistry, Seat},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_finished_days_are_rolled_up_once() {
+        let now = Utc::now();
+        let yesterday = now.date_naive() - Days::new(1);
+        let seat = |name: &str| Seat {
+            name: name.to_string(),
+            token: name.to_string(),
+        };
+        let finished = |mut entry: GameEntry, status: GameStatus, moves: u64| {
+            entry.state.status = status;
+            entry.state.version = moves;
+            entry.finished_at = Some(bounds(yesterday).0 + TimeDelta::hours(12));
+            entry
+        };
+
+        let state = AppState::new(GameRegistry::new(), Arc::new(MemoryStore));
+        let games = [
+            finished(
+                GameEntry::pvp(seat("ada"), seat("bob")),
+                GameStatus::Win(Player::X),
+                5,
+            ),
+            finished(GameEntry::pvp(seat("bob"), seat("cy")), GameStatus::Draw, 9),
+            finished(
+                GameEntry::new(GameState::default()),
+                GameStatus::Win(Player::O),
+                6,
+            ),
+            // Still going, in a tenant, or finished today: not counted.
+            GameEntry::new(GameState::default()),
+            GameEntry {
+                tenant: Some("room-101".to_string()),
+                ..finished(GameEntry::new(GameState::default()), GameStatus::Draw, 9)
+            },
+            GameEntry {
+                finished_at: Some(now),
+                ..finished(GameEntry::new(GameState::default()), GameStatus::Draw, 9)
+            },
+        ];
+        for entry in games {
+            state
+                .games
+                .insert(Uuid::new_v4(), Arc::new(Mutex::new(entry)));
+        }
+
+        let config = StatsConfig {
+            rollup_hour: 0,
+            backfill_days: 3,
+        };
+        roll_up(state.clone(), config.clone()).await;
+        let days = state.rollups.range(yesterday - Days::new(7), yesterday);
+        assert_eq!(days.len(), 3);
+        let stats = &days[2];
+        assert_eq!(stats.day, yesterday);
+        assert_eq!(
+            (stats.games, stats.x_wins, stats.o_wins, stats.draws),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(stats.average_moves, 20.0 / 3.0);
+        assert_eq!(stats.distinct_players, 3);
+        assert_eq!(stats.by_difficulty["0.0"].engine_wins, 1);
+        assert_eq!(days[0].games, 0);
+
+        // Nothing is left to roll up until today ends.
+        assert!(pending_days(state.rollups.latest(), now, 3).is_empty());
+        assert_eq!(
+            pending_days(state.rollups.latest(), now + TimeDelta::days(2), 3),
+            [now.date_naive(), now.date_naive() + Days::new(1)]
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Finding games by who plays them, how they ended, and their rules.
+//!
+//! The registry is searched in memory. Stores that keep games after the
+//! registry lets them go, like PostgreSQL, answer the same queries from
+//! indexed columns instead, so archived games turn up too.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    game::{GameState, GameStatus, Player},
+    state::{GameEntry, GameMode, GameRegistry},
+};
+
+pub const DEFAULT_RESULTS: usize = 100;
+pub const MAX_RESULTS: usize = 1000;
+
+/// A family of rules a game was played under. A game can be several at
+/// once, e.g. a large toroidal board, or `Standard` alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    /// 3x3, three in a row, nothing blocked.
+    Standard,
+    /// A board bigger than 3x3.
+    Large,
+    Toroidal,
+    /// Some cells taken out of play.
+    Blocked,
+}
+
+impl Variant {
+    pub fn label(self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::Large => "large",
+            Variant::Toroidal => "toroidal",
+            Variant::Blocked => "blocked",
+        }
+    }
+
+    /// The variants a game belongs to, which never change once it starts.
+    pub fn of(state: &GameState) -> Vec<Variant> {
+        let mut variants = Vec::new();
+        if state.board.size() != (3, 3) {
+            variants.push(Variant::Large);
+        }
+        if state.rules.toroidal {
+            variants.push(Variant::Toroidal);
+        }
+        if state.board.blocked() != 0 {
+            variants.push(Variant::Blocked);
+        }
+        if variants.is_empty() {
+            variants.push(Variant::Standard);
+        }
+        variants
+    }
+}
+
+/// A game's status, or any finished one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusFilter {
+    InProgress,
+    Finished,
+    Draw,
+    XWon,
+    OWon,
+}
+
+impl StatusFilter {
+    /// The one status this stands for; `None` for `Finished`.
+    pub fn status(self) -> Option<GameStatus> {
+        match self {
+            StatusFilter::InProgress => Some(GameStatus::InProgress),
+            StatusFilter::Finished => None,
+            StatusFilter::Draw => Some(GameStatus::Draw),
+            StatusFilter::XWon => Some(GameStatus::Win(Player::X)),
+            StatusFilter::OWon => Some(GameStatus::Win(Player::O)),
+        }
+    }
+
+    fn matches(self, status: GameStatus) -> bool {
+        match self.status() {
+            Some(wanted) => status == wanted,
+            None => status != GameStatus::InProgress,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    CreatedAt,
+    FinishedAt,
+    Moves,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// What to look for. Every filter left out matches all games.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GameQuery {
+    /// A seat's name in a player-vs-player game, or a bot's ID.
+    pub player_id: Option<String>,
+    pub variant: Option<Variant>,
+    pub status: Option<StatusFilter>,
+    /// Exclusive.
+    pub created_after: Option<DateTime<Utc>>,
+    pub tenant: Option<String>,
+    /// Only archived games, or only live ones.
+    pub archived: Option<bool>,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+}
+
+impl GameQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_RESULTS).min(MAX_RESULTS)
+    }
+
+    fn matches(&self, entry: &GameEntry) -> bool {
+        self.player_id
+            .as_deref()
+            .is_none_or(|player_id| plays_in(player_id, &entry.mode))
+            && self
+                .variant
+                .is_none_or(|variant| Variant::of(&entry.state).contains(&variant))
+            && self
+                .status
+                .is_none_or(|status| status.matches(entry.state.status))
+            && self
+                .created_after
+                .is_none_or(|after| created_at(entry).is_some_and(|at| at > after))
+            && (self.tenant.is_none() || entry.tenant == self.tenant)
+            && self
+                .archived
+                .is_none_or(|archived| entry.archived_at.is_some() == archived)
+    }
+}
+
+fn plays_in(player_id: &str, mode: &GameMode) -> bool {
+    match mode {
+        GameMode::VsEngine => false,
+        GameMode::Pvp { x, o } => x.name == player_id || o.name == player_id,
+        GameMode::Open { x } => x.name == player_id,
+        GameMode::VsBot { bot_id, .. } => bot_id.to_string() == player_id,
+    }
+}
+
+/// When the game was started, from the first entry in its log.
+pub fn created_at(entry: &GameEntry) -> Option<DateTime<Utc>> {
+    entry.events.first().map(|record| record.at)
+}
+
+/// A game a search turned up.
+#[derive(Debug, Clone)]
+pub struct FoundGame {
+    pub game_id: Uuid,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Without its event log when it came from the store.
+    pub entry: GameEntry,
+}
+
+/// Searches the games in `registry`.
+pub fn in_registry(registry: &GameRegistry, query: &GameQuery) -> Vec<FoundGame> {
+    let mut found: Vec<FoundGame> = registry
+        .iter()
+        .filter(|(_, entry)| query.matches(entry))
+        .map(|(game_id, entry)| FoundGame {
+            game_id: *game_id,
+            created_at: created_at(entry),
+            entry: entry.clone(),
+        })
+        .collect();
+    found.sort_by(|a, b| {
+        let ordering = match query.sort {
+            SortKey::CreatedAt => a.created_at.cmp(&b.created_at),
+            SortKey::FinishedAt => a.entry.finished_at.cmp(&b.entry.finished_at),
+            SortKey::Moves => a.entry.state.version.cmp(&b.entry.state.version),
+        };
+        match query.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+        .then(a.game_id.cmp(&b.game_id))
+    });
+    found.truncate(query.limit());
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{PlayerMove, Rules, try_move},
+        state::Seat,
+    };
+
+    #[test]
+    fn test_games_are_found_by_player_variant_and_status() {
+        let seat = |name: &str| Seat {
+            name: name.to_string(),
+            token: name.to_string(),
+        };
+        let mut registry = GameRegistry::new();
+        let pvp = Uuid::new_v4();
+        registry.insert(pvp, GameEntry::pvp(seat("ada"), seat("bob")));
+        let toroidal = Uuid::new_v4();
+        let rules = Rules {
+            toroidal: true,
+            win_length: 4,
+        };
+        let mut state = GameState::custom(4, 4, rules, &[]).unwrap();
+        try_move(&mut state, Player::X, PlayerMove { row: 0, col: 0 }).unwrap();
+        registry.insert(toroidal, GameEntry::new(state));
+
+        let ids = |query: GameQuery| -> Vec<Uuid> {
+            in_registry(&registry, &query)
+                .iter()
+                .map(|found| found.game_id)
+                .collect()
+        };
+        let query = GameQuery {
+            player_id: Some("bob".to_string()),
+            ..GameQuery::default()
+        };
+        assert_eq!(ids(query), [pvp]);
+        let query = GameQuery {
+            variant: Some(Variant::Toroidal),
+            ..GameQuery::default()
+        };
+        assert_eq!(ids(query), [toroidal]);
+        let query = GameQuery {
+            status: Some(StatusFilter::Finished),
+            ..GameQuery::default()
+        };
+        assert!(ids(query).is_empty());
+        let query = GameQuery {
+            sort: SortKey::Moves,
+            limit: Some(1),
+            ..GameQuery::default()
+        };
+        assert_eq!(ids(query), [toroidal]);
+
+        assert_eq!(
+            Variant::of(&registry[&toroidal].state),
+            [Variant::Large, Variant::Toroidal]
+        );
+        assert_eq!(Variant::of(&registry[&pvp].state), [Variant::Standard]);
+    }
+}
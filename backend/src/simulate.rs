@@ -0,0 +1,143 @@
+//! Server-side engine-vs-engine matches, for validating engines and tuning
+//! difficulty without a human in the loop.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::EngineKind,
+    game::{GameState, GameStatus, Player, try_move},
+};
+
+/// Upper bound on games per request; a full minimax game takes a few
+/// milliseconds, so this keeps a single request well under a second or two.
+pub const MAX_SIMULATION_GAMES: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SimulationRequest {
+    pub games: u32,
+    #[serde(default = "default_x_engine")]
+    pub x: EngineKind,
+    #[serde(default)]
+    pub o: EngineKind,
+}
+
+fn default_x_engine() -> EngineKind {
+    EngineKind::Random
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct EngineReport {
+    pub engine: EngineKind,
+    pub wins: u32,
+    pub losses: u32,
+    pub moves: u64,
+    pub average_think_time_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SimulationReport {
+    pub games: u32,
+    pub draws: u32,
+    pub average_game_length: f64,
+    pub x: EngineReport,
+    pub o: EngineReport,
+}
+
+/// Plays `request.games` games between the two engines and aggregates the
+/// results. CPU-bound; run it off the async runtime.
+pub fn simulate(request: &SimulationRequest) -> SimulationReport {
+    let mut draws = 0;
+    let mut total_plies = 0;
+    let mut x = EngineReport {
+        engine: request.x,
+        ..EngineReport::default()
+    };
+    let mut o = EngineReport {
+        engine: request.o,
+        ..EngineReport::default()
+    };
+    let mut think_time = [Duration::ZERO; 2];
+
+    for _ in 0..request.games {
+        let mut game_state = GameState::default();
+        while game_state.status == GameStatus::InProgress {
+            let player = game_state.to_play;
+            let (engine, report, clock) = match player {
+                Player::X => (request.x, &mut x, &mut think_time[0]),
+                Player::O => (request.o, &mut o, &mut think_time[1]),
+            };
+
+            let started = Instant::now();
+            let player_move = engine
+                .choose_move(&game_state)
+                .expect("an in-progress game always has a legal move");
+            *clock += started.elapsed();
+            report.moves += 1;
+
+            try_move(&mut game_state, player, player_move)
+                .expect("engines only choose legal moves");
+        }
+
+        total_plies += game_state.version;
+        match game_state.status {
+            GameStatus::Win(Player::X) => {
+                x.wins += 1;
+                o.losses += 1;
+            }
+            GameStatus::Win(Player::O) => {
+                o.wins += 1;
+                x.losses += 1;
+            }
+            GameStatus::Draw => draws += 1,
+            GameStatus::InProgress => unreachable!(),
+        }
+    }
+
+    for (report, clock) in [(&mut x, think_time[0]), (&mut o, think_time[1])] {
+        if report.moves > 0 {
+            report.average_think_time_ms = clock.as_secs_f64() * 1000.0 / report.moves as f64;
+        }
+    }
+
+    SimulationReport {
+        games: request.games,
+        draws,
+        average_game_length: if request.games > 0 {
+            total_plies as f64 / request.games as f64
+        } else {
+            0.0
+        },
+        x,
+        o,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimax_never_loses_to_random() {
+        let report = simulate(&SimulationRequest {
+            games: 20,
+            x: EngineKind::Random,
+            o: EngineKind::Minimax,
+        });
+        assert_eq!(report.x.wins, 0);
+        assert_eq!(report.o.wins + report.draws, 20);
+        assert!(report.average_game_length >= 5.0);
+    }
+
+    #[test]
+    fn test_minimax_mirror_match_is_all_draws() {
+        let report = simulate(&SimulationRequest {
+            games: 2,
+            x: EngineKind::Minimax,
+            o: EngineKind::Minimax,
+        });
+        assert_eq!(report.draws, 2);
+        assert_eq!(report.average_game_length, 9.0);
+    }
+}
@@ -0,0 +1,339 @@
+//! Playing against the AI from Slack.
+//!
+//! A Slack app's slash command posts a new game to the channel as a Block
+//! Kit message with a button for every cell. Clicking one plays that cell
+//! for whoever started the game, and the message is replaced with the board
+//! after the AI's reply, through the `response_url` Slack hands over with
+//! the click. Anyone else who clicks is told the game isn't theirs.
+//!
+//! Every request must carry a signature made with the app's signing
+//! secret, over a timestamp from the last five minutes, so requests can't
+//! be forged or replayed.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    Error, MoveRequest,
+    api::constant_time_eq,
+    config::SlackConfig,
+    game::{Cell, GameState, GameStatus, Player, PlayerMove},
+    state::AppState,
+};
+
+pub const TIMESTAMP_HEADER: &str = "x-slack-request-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-slack-signature";
+/// How old a signed request may be.
+const MAX_AGE_SECS: i64 = 5 * 60;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Where every `response_url` points; nothing else is posted to.
+const RESPONSE_URL_PREFIX: &str = "https://hooks.slack.com/";
+
+pub struct Slack {
+    signing_secret: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Slack {
+    pub fn new(config: &SlackConfig) -> Self {
+        Self {
+            signing_secret: config.signing_secret.clone(),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client builds"),
+        }
+    }
+
+    /// Checks that Slack sent `body`: `signature` must be `v0=` followed by
+    /// the hex HMAC-SHA256 of `v0:{timestamp}:{body}`, keyed with the
+    /// signing secret, and `timestamp` must be recent.
+    pub fn verify(
+        &self,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let secret = self
+            .signing_secret
+            .as_deref()
+            .ok_or(Error::Unauthorized("Slack isn't set up on this server"))?;
+        let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+            return Err(Error::Unauthorized("The Slack signature is missing"));
+        };
+        let signed_at: i64 = timestamp
+            .parse()
+            .map_err(|_| Error::Unauthorized("The Slack signature is invalid"))?;
+        if (now.timestamp() - signed_at).abs() > MAX_AGE_SECS {
+            return Err(Error::Unauthorized("The Slack signature has expired"));
+        }
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(format!("v0:{timestamp}:").as_bytes());
+        mac.update(body);
+        let expected: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        if !constant_time_eq(format!("v0={expected}").as_bytes(), signature.as_bytes()) {
+            return Err(Error::Unauthorized("The Slack signature is invalid"));
+        }
+        Ok(())
+    }
+
+    /// Posts `message` to a `response_url`, to replace the game's message
+    /// or to answer the clicker.
+    async fn respond(&self, response_url: &str, message: &Value) {
+        if !response_url.starts_with(RESPONSE_URL_PREFIX) {
+            log::warn!("Ignoring a Slack response URL outside Slack");
+            return;
+        }
+        let result = self
+            .client
+            .post(response_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(message.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            log::warn!("Failed to answer Slack: {}", e);
+        }
+    }
+}
+
+/// A form Slack posts: a slash command, or a button click with everything
+/// in `payload`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Request {
+    pub payload: Option<String>,
+    pub text: String,
+    pub user_id: String,
+}
+
+/// A button click, from a request's `payload`.
+#[derive(Debug, Deserialize)]
+pub struct Interaction {
+    #[serde(rename = "type")]
+    kind: String,
+    user: SlackUser,
+    #[serde(default)]
+    actions: Vec<Action>,
+    response_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackUser {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Action {
+    value: Option<String>,
+}
+
+/// The value of a cell's button: which game, which cell, and who may
+/// click it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CellButton {
+    game_id: Uuid,
+    row: usize,
+    col: usize,
+    player: String,
+}
+
+/// A message only the user who sent the command or click sees.
+fn ephemeral(text: &str) -> Value {
+    json!({
+        "response_type": "ephemeral",
+        "replace_original": false,
+        "text": text,
+    })
+}
+
+/// The game as a message: its status, then a row of buttons per board row
+/// while it is on, or the final board once it is over.
+fn game_message(game_id: Uuid, game_state: &GameState, player: &str) -> Value {
+    let summary = game_state.summary();
+    let mut blocks = Vec::new();
+    if game_state.status == GameStatus::InProgress {
+        blocks.push(json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("<@{player}> is playing X against the AI: *{summary}*"),
+            },
+        }));
+        for (row, cells) in game_state.board.rows().into_iter().enumerate() {
+            let buttons: Vec<Value> = cells
+                .into_iter()
+                .enumerate()
+                .map(|(col, cell)| {
+                    let value = CellButton {
+                        game_id,
+                        row,
+                        col,
+                        player: player.to_string(),
+                    };
+                    json!({
+                        "type": "button",
+                        "action_id": format!("cell-{row}-{col}"),
+                        "text": { "type": "plain_text", "text": symbol(cell) },
+                        "value": serde_json::to_string(&value).expect("buttons serialize"),
+                    })
+                })
+                .collect();
+            blocks.push(json!({ "type": "actions", "elements": buttons }));
+        }
+    } else {
+        let board: String = game_state
+            .board
+            .rows()
+            .into_iter()
+            .map(|cells| {
+                let row: Vec<&str> = cells.into_iter().map(symbol).collect();
+                row.join(" ") + "\n"
+            })
+            .collect();
+        blocks.push(json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!(
+                    "<@{player}> played X against the AI: *{summary}*\n```{board}```"
+                ),
+            },
+        }));
+    }
+    json!({
+        "response_type": "in_channel",
+        "replace_original": true,
+        "text": summary,
+        "blocks": blocks,
+    })
+}
+
+/// How a cell shows on its button. Buttons can't be blank.
+fn symbol(cell: Cell) -> &'static str {
+    match cell {
+        Cell::Empty => "·",
+        Cell::Occupied(Player::X) => "X",
+        Cell::Occupied(Player::O) => "O",
+        Cell::Blocked => "#",
+    }
+}
+
+/// Answers a slash command: `play`, or nothing at all, starts a game for
+/// `user_id`; anything else gets the usage.
+pub async fn command(state: &AppState, user_id: &str, text: &str) -> Value {
+    if !matches!(text.trim(), "" | "play") {
+        return ephemeral(
+            "Send the command on its own, or with `play`, to start a game against the AI \
+             in this channel. You play X: click a cell to move there.",
+        );
+    }
+    match crate::create_game(state, None, GameState::default(), None).await {
+        Ok((game_id, game_state)) => {
+            log::info!("Started Slack game {} for {}", game_id, user_id);
+            game_message(game_id, &game_state, user_id)
+        }
+        Err(e) => ephemeral(&e.to_string()),
+    }
+}
+
+/// Plays the clicked cell, then updates the game's message or tells the
+/// clicker what went wrong.
+pub async fn click(state: AppState, interaction: Interaction) {
+    let Some(response_url) = interaction.response_url.as_deref() else {
+        return;
+    };
+    if interaction.kind != "block_actions" {
+        return;
+    }
+    let Some(button) = interaction
+        .actions
+        .first()
+        .and_then(|action| action.value.as_deref())
+        .and_then(|value| serde_json::from_str::<CellButton>(value).ok())
+    else {
+        return;
+    };
+    let message = if interaction.user.id != button.player {
+        ephemeral(&format!("Only <@{}> can play this game.", button.player))
+    } else {
+        let move_request = MoveRequest {
+            player_move: PlayerMove {
+                row: button.row,
+                col: button.col,
+            },
+            expected_version: None,
+        };
+        match crate::play_move(&state, button.game_id, move_request, None, None).await {
+            Ok(game_state) => game_message(button.game_id, &game_state, &button.player),
+            Err(e) => ephemeral(&e.to_string()),
+        }
+    };
+    state.slack.respond(response_url, &message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::game::try_move;
+
+    #[test]
+    fn test_requests_need_a_fresh_signature_and_games_render_as_buttons() {
+        // The example from Slack's documentation.
+        let slack = Slack::new(&SlackConfig {
+            signing_secret: Some("8f742231b10e8888abcd99yyyzzz85a5".to_string()),
+        });
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        let signed_at = Utc.timestamp_opt(1531420618, 0).unwrap();
+        let verify = |signature, now| slack.verify(Some("1531420618"), Some(signature), body, now);
+        assert!(verify(signature, signed_at + chrono::TimeDelta::minutes(4)).is_ok());
+        assert!(verify(signature, signed_at + chrono::TimeDelta::minutes(6)).is_err());
+        assert!(verify(&signature.replace('a', "b"), signed_at).is_err());
+        assert!(
+            Slack::new(&SlackConfig::default())
+                .verify(Some("1531420618"), Some(signature), body, signed_at)
+                .is_err()
+        );
+
+        let mut game_state = GameState::default();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 1, col: 1 }).unwrap();
+        let game_id = Uuid::new_v4();
+        let message = game_message(game_id, &game_state, "U2CERLKJA");
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 4);
+        let middle = &blocks[2]["elements"][1];
+        assert_eq!(middle["text"]["text"], "X");
+        let value: CellButton = serde_json::from_str(middle["value"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            value,
+            CellButton {
+                game_id,
+                row: 1,
+                col: 1,
+                player: "U2CERLKJA".to_string(),
+            }
+        );
+
+        game_state.status = GameStatus::Win(Player::X);
+        let message = game_message(game_id, &game_state, "U2CERLKJA");
+        assert_eq!(message["blocks"].as_array().unwrap().len(), 1);
+        assert!(message["text"].as_str().unwrap().starts_with("X won"));
+    }
+}
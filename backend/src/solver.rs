@@ -0,0 +1,178 @@
+//! Exact game-theoretic values: who wins with perfect play, and how quickly.
+//!
+//! `minimax` only scores positions as ±10 or 0, which is enough to pick a
+//! move but says nothing about how far away the result is. The solver here
+//! also tracks the distance to the outcome: the winning side takes the
+//! fastest win and the losing side holds out as long as it can.
+
+use std::cmp::Ordering;
+
+use serde::Serialize;
+
+use crate::game::{GameState, GameStatus, Player, PlayerMove, try_move};
+
+/// The result of a position for the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    /// For the side to move.
+    pub outcome: Outcome,
+    /// Plies until the game ends with perfect play from both sides.
+    pub plies: u32,
+    /// A move that achieves the value; `None` once the game is over.
+    pub best_move: Option<PlayerMove>,
+}
+
+impl Value {
+    /// The side that wins with perfect play, if either does.
+    pub fn winner(&self, to_play: Player) -> Option<Player> {
+        match self.outcome {
+            Outcome::Win => Some(to_play),
+            Outcome::Loss => Some(to_play.opponent()),
+            Outcome::Draw => None,
+        }
+    }
+
+    /// How many more moves the winner needs, "mate in N" style.
+    pub fn moves_to_win(&self) -> Option<u32> {
+        match self.outcome {
+            // The side to move plays the first, third, ... ply.
+            Outcome::Win => Some(self.plies.div_ceil(2)),
+            Outcome::Loss => Some(self.plies / 2),
+            Outcome::Draw => None,
+        }
+    }
+
+    /// Orders values from the mover's point of view: fast wins first, then
+    /// draws, then slow losses, then fast losses.
+    fn preference(&self) -> (i8, i64) {
+        match self.outcome {
+            Outcome::Win => (1, -i64::from(self.plies)),
+            Outcome::Draw => (0, 0),
+            Outcome::Loss => (-1, i64::from(self.plies)),
+        }
+    }
+}
+
+/// Solves the position exactly.
+pub fn solve(game_state: &GameState) -> Value {
+    match game_state.status {
+        GameStatus::Win(winner) => {
+            return Value {
+                outcome: if winner == game_state.to_play {
+                    Outcome::Win
+                } else {
+                    Outcome::Loss
+                },
+                plies: 0,
+                best_move: None,
+            };
+        }
+        GameStatus::Draw => {
+            return Value {
+                outcome: Outcome::Draw,
+                plies: 0,
+                best_move: None,
+            };
+        }
+        GameStatus::InProgress => {}
+    }
+
+    let mut best: Option<Value> = None;
+    for (row, col) in game_state.board.empty_cells() {
+        let player_move = PlayerMove { row, col };
+        let mut after = *game_state;
+        try_move(&mut after, game_state.to_play, player_move).expect("empty cells are legal moves");
+        let reply = solve(&after);
+        // The opponent's result, seen from this side, one ply further away.
+        let value = Value {
+            outcome: match reply.outcome {
+                Outcome::Win => Outcome::Loss,
+                Outcome::Draw => Outcome::Draw,
+                Outcome::Loss => Outcome::Win,
+            },
+            plies: reply.plies + 1,
+            best_move: Some(player_move),
+        };
+        let better =
+            best.is_none_or(|best| value.preference().cmp(&best.preference()) == Ordering::Greater);
+        if better {
+            best = Some(value);
+        }
+    }
+    best.expect("an in-progress game always has a legal move")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::minimax, import, notation};
+
+    fn position(moves: &str) -> GameState {
+        import::replay(&notation::parse_moves(moves).unwrap())
+            .unwrap()
+            .state
+    }
+
+    #[test]
+    fn test_the_empty_board_is_a_draw() {
+        let value = solve(&GameState::default());
+        assert_eq!(value.outcome, Outcome::Draw);
+        assert_eq!(value.plies, 9);
+        assert_eq!(value.moves_to_win(), None);
+    }
+
+    #[test]
+    fn test_wins_are_taken_as_fast_as_possible() {
+        // X can complete the top row right away, or set up other wins later.
+        let game_state = position("X:a3 O:a1 X:b3 O:b1");
+        let value = solve(&game_state);
+        assert_eq!(value.outcome, Outcome::Win);
+        assert_eq!(value.plies, 1);
+        assert_eq!(value.moves_to_win(), Some(1));
+        assert_eq!(
+            value.best_move.map(notation::square),
+            Some("c3".to_string())
+        );
+        assert_eq!(value.winner(Player::X), Some(Player::X));
+    }
+
+    #[test]
+    fn test_losses_are_delayed_as_long_as_possible() {
+        // X has a fork; O can block one line but not both.
+        let game_state = position("X:a3 O:b3 X:b2 O:c1 X:a1");
+        let value = solve(&game_state);
+        assert_eq!(value.outcome, Outcome::Loss);
+        assert_eq!(value.plies, 2);
+        assert_eq!(value.moves_to_win(), Some(1));
+        assert_eq!(value.winner(Player::O), Some(Player::X));
+    }
+
+    #[test]
+    fn test_outcomes_agree_with_minimax() {
+        for moves in [
+            "",
+            "X:b2",
+            "X:a3",
+            "X:b2 O:b3",
+            "X:a3 O:b2 X:c1",
+            "X:b2 O:a3 X:c1",
+        ] {
+            let game_state = position(moves);
+            let winner = solve(&game_state).winner(game_state.to_play);
+            let expected = match minimax(&game_state).0 {
+                10 => Some(Player::X),
+                -10 => Some(Player::O),
+                _ => None,
+            };
+            assert_eq!(winner, expected, "position {moves:?}");
+        }
+    }
+}
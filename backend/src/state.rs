@@ -0,0 +1,812 @@
+//! Shared application state: the live games and the store that persists them.
+//!
+//! Each game sits behind its own lock, so a slow AI search in one game never
+//! blocks moves in another. The map itself is only locked (per shard) for the
+//! instant it takes to look up, insert, or remove an entry.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock, broadcast};
+use uuid::Uuid;
+
+#[cfg(feature = "webhooks")]
+use crate::webhook::Webhooks;
+use crate::{
+    Error, MoveRequest,
+    account::Accounts,
+    archive,
+    arena::Arenas,
+    audit::AuditLog,
+    bot::Bots,
+    calibration::{self, Tuner},
+    cluster::Cluster,
+    config::{
+        AccountsConfig, BotsConfig, CalibrationConfig, ClusterConfig, Config, DiscordConfig,
+        EngineConfig, ExperimentConfig, GamesConfig, LimitsConfig, LobbiesConfig, PresenceConfig,
+        ServerConfig, TenantConfig,
+    },
+    discord::Discord,
+    drain::Drain,
+    engine::Strategy,
+    events::{self, Event, EventRecord},
+    experiments::{Assignment, Experiment},
+    flags::Flags,
+    game::{GameState, GameStatus, MoveRecord, Player, PlayerMove},
+    health::Link,
+    invite::Invites,
+    jobs::Jobs,
+    limits::Limits,
+    lobby::Lobbies,
+    matches::{Match, SharedMatch},
+    notakto::{self, SharedNotakto},
+    presence::Presence,
+    puzzle::{Puzzles, daily::Attempts, rush::Rushes},
+    rollup::Rollups,
+    store::GameStore,
+    tenant::Tenants,
+    three_player::{self, SharedThreePlayer},
+    tournament::{SharedTournament, Tournament},
+};
+#[cfg(feature = "oauth")]
+use crate::{config::OAuthConfig, oauth::OAuth};
+#[cfg(feature = "webpush")]
+use crate::{config::PushConfig, push::Push};
+#[cfg(feature = "slack")]
+use crate::{config::SlackConfig, slack::Slack};
+#[cfg(feature = "telegram")]
+use crate::{config::TelegramConfig, telegram::Telegram};
+
+/// One side of a player-vs-player game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Seat {
+    pub name: String,
+    /// Secret the player sends with each move to prove the seat is theirs.
+    pub token: String,
+}
+
+/// Who is playing a game.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GameMode {
+    /// An anonymous human as X against the minimax engine as O.
+    #[default]
+    VsEngine,
+    /// Two people, each identified by their seat token.
+    Pvp { x: Seat, o: Seat },
+    /// A player-vs-player game whose O seat is still waiting for someone to
+    /// redeem an invite.
+    Open { x: Seat },
+    /// An anonymous human as X against a registered external bot as O.
+    VsBot { bot_id: Uuid, name: String },
+}
+
+impl GameMode {
+    /// The side `token` belongs to, if any.
+    pub fn seat_of(&self, token: &str) -> Option<Player> {
+        match self {
+            GameMode::VsEngine | GameMode::VsBot { .. } => None,
+            GameMode::Open { x } => (x.token == token).then_some(Player::X),
+            GameMode::Pvp { x, o } => {
+                if x.token == token {
+                    Some(Player::X)
+                } else if o.token == token {
+                    Some(Player::O)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// A fresh random secret for seats, organizers, and the like.
+pub fn new_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+// A game tracked by the registry. Finished games are archived rather than
+// removed, so clients can still fetch the result until the TTL runs out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameEntry {
+    pub state: GameState,
+    pub finished_at: Option<DateTime<Utc>>,
+    // Successful move responses keyed by `Idempotency-Key`, so a retried
+    // request replays the original result instead of failing with "Not your turn".
+    pub idempotent_moves: HashMap<String, (MoveRequest, GameState)>,
+    // Everything that happened to the game, in order; see `events`.
+    #[serde(default)]
+    pub events: Vec<EventRecord>,
+    #[serde(default)]
+    pub mode: GameMode,
+    // Set for games played as part of a tournament, which advances when the
+    // game finishes.
+    #[serde(default)]
+    pub tournament_id: Option<Uuid>,
+    // Set for games played as part of a best-of-N match, which starts its
+    // next game when this one finishes.
+    #[serde(default)]
+    pub match_id: Option<Uuid>,
+    // Set for games played in an arena, which scores the game and pairs
+    // its players again when it finishes.
+    #[serde(default)]
+    pub arena_id: Option<Uuid>,
+    // Whether O may take over X's opening move instead of replying, in
+    // player-vs-player games.
+    #[serde(default)]
+    pub pie_rule: PieRule,
+    // Chance, from 0 to 1, that the engine deliberately plays a weaker move
+    // in this game. Only used against the engine.
+    #[serde(default)]
+    pub blunder_chance: f64,
+    // Whether `blunder_chance` was picked by the tuner, whose chance then
+    // follows how the game ends; see `calibration`.
+    #[serde(default)]
+    pub auto_tuned: bool,
+    // The tenant the game was started in, which is the only one that can
+    // reach it. `None` for games open to everyone.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // Set once the game has been archived; see `archive`.
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Utc>>,
+    // When an admin last brought the game back from the archive, which
+    // restarts the wait before it is archived again.
+    #[serde(default)]
+    pub restored_at: Option<DateTime<Utc>>,
+    // The experiment variant the engine plays in this game, if it was
+    // started while one was running; see `experiments`.
+    #[serde(default)]
+    pub experiment: Option<Assignment>,
+}
+
+impl GameEntry {
+    /// A game starting from `state`, which is logged as its creation.
+    pub fn new(state: GameState) -> Self {
+        let created = Event::GameCreated {
+            board: state.board,
+            rules: state.rules,
+        };
+        let mut entry = Self {
+            state,
+            finished_at: None,
+            idempotent_moves: HashMap::new(),
+            events: Vec::new(),
+            mode: GameMode::VsEngine,
+            tournament_id: None,
+            match_id: None,
+            arena_id: None,
+            pie_rule: PieRule::Off,
+            blunder_chance: 0.0,
+            auto_tuned: false,
+            tenant: None,
+            archived_at: None,
+            restored_at: None,
+            experiment: None,
+        };
+        entry.log(created, Utc::now());
+        entry
+    }
+
+    /// A fresh player-vs-player game between two seats.
+    pub fn pvp(x: Seat, o: Seat) -> Self {
+        Self {
+            mode: GameMode::Pvp { x, o },
+            ..Self::new(GameState::default())
+        }
+    }
+
+    /// How the engine breaks ties between its best moves in this game.
+    pub fn strategy(&self) -> Strategy {
+        self.experiment
+            .as_ref()
+            .map_or_else(Strategy::default, |assignment| assignment.strategy)
+    }
+
+    /// Whether O can still invoke the pie rule: only right after X's first
+    /// move, before O has replied.
+    pub fn can_swap(&self) -> bool {
+        self.pie_rule == PieRule::On
+            && self.move_count() == 1
+            && self.state.status == GameStatus::InProgress
+            && matches!(self.mode, GameMode::Pvp { .. })
+    }
+
+    fn log(&mut self, event: Event, at: DateTime<Utc>) {
+        self.events.push(EventRecord {
+            seq: self.events.len() as u64,
+            at,
+            event,
+        });
+    }
+
+    /// Logs a move that was just applied to `state`.
+    pub fn record_move(&mut self, player: Player, player_move: PlayerMove) {
+        let record = MoveRecord {
+            ply: self.state.version,
+            player,
+            player_move,
+        };
+        self.log(Event::MoveMade(record), Utc::now());
+    }
+
+    /// Archives the game, whose `state` was just finished, and logs its
+    /// result.
+    pub fn finish(&mut self, now: DateTime<Utc>) {
+        self.finished_at = Some(now);
+        let finished = Event::GameFinished {
+            status: self.state.status,
+            ending: self.state.ending,
+        };
+        self.log(finished, now);
+    }
+
+    /// Every move played so far, in order.
+    pub fn moves(&self) -> Vec<MoveRecord> {
+        self.events
+            .iter()
+            .filter_map(|record| match record.event {
+                Event::MoveMade(record) => Some(record),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn move_count(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|record| matches!(record.event, Event::MoveMade(_)))
+            .count()
+    }
+
+    /// Rebuilds the position and result from the event log, keeping the
+    /// clock and any draw offer, which the log doesn't cover.
+    pub fn replay_events(&mut self) -> Result<(), Error> {
+        let folded = events::fold(&self.events)?;
+        self.state = GameState {
+            clock: self.state.clock,
+            draw_offer: self.state.draw_offer,
+            ..folded
+        };
+        Ok(())
+    }
+
+    /// Looks up a previously recorded response for `key`, rejecting reuse of
+    /// the key with a different move.
+    pub fn replay_idempotent_move(
+        &self,
+        key: &str,
+        move_request: &MoveRequest,
+    ) -> Result<Option<GameState>, Error> {
+        match self.idempotent_moves.get(key) {
+            Some((original, response)) if original == move_request => Ok(Some(*response)),
+            Some(_) => Err(Error::IdempotencyKeyReused),
+            None => Ok(None),
+        }
+    }
+
+    /// A rough count of the heap and inline bytes this entry holds, for
+    /// capacity planning rather than exact accounting.
+    pub fn approximate_size(&self) -> usize {
+        let idempotent_moves: usize = self
+            .idempotent_moves
+            .keys()
+            .map(|key| key.capacity() + size_of::<(String, (MoveRequest, GameState))>())
+            .sum();
+        size_of::<Self>() + self.events.capacity() * size_of::<EventRecord>() + idempotent_moves
+    }
+
+    /// Whether the game has been over, or restored, for `ttl`, and is due
+    /// to be archived.
+    pub fn is_expired(&self, now: DateTime<Utc>, ttl: TimeDelta) -> bool {
+        self.finished_at
+            .map(|finished_at| finished_at.max(self.restored_at.unwrap_or(finished_at)))
+            .is_some_and(|since| now - since >= ttl)
+    }
+}
+
+// A plain map from game ID to entry, used to move whole sets of games in and
+// out of storage.
+pub type GameRegistry = HashMap<Uuid, GameEntry>;
+
+/// A single game, independently lockable.
+pub type SharedGame = Arc<Mutex<GameEntry>>;
+
+/// Number of updates a slow subscriber may fall behind before it starts
+/// missing some.
+const UPDATE_CHANNEL_CAPACITY: usize = 256;
+
+/// The pie rule: after X's first move, O may swap sides instead of replying,
+/// taking over that move and leaving the first mover to continue as O. It
+/// keeps the first move honest, since an opening that is too strong just
+/// gets taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PieRule {
+    #[default]
+    Off,
+    On,
+    /// O took the swap; the seats in the game mode were exchanged.
+    Swapped,
+}
+
+/// What happened to a game.
+#[cfg_attr(not(any(feature = "graphql", feature = "webhooks")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GameEvent {
+    /// The game was started.
+    Created,
+    /// A turn was accepted; `finished` if it ended the game.
+    Moved { finished: bool },
+    /// A player lost by being away on their turn.
+    Forfeited,
+    /// The side to move ran out of time, which ended the game.
+    TimedOut,
+    /// A player asked to pause or resume, or the game was paused or resumed.
+    PauseChanged,
+    /// A player offered or declined a draw. An accepted draw is `Moved`
+    /// with `finished` set.
+    DrawOffer,
+    /// Someone took a seat, e.g. by redeeming an invite.
+    Seated,
+    /// O invoked the pie rule and the players changed sides.
+    Swapped,
+    /// The finished game's TTL ran out and it was purged.
+    Expired,
+}
+
+/// Published whenever a game changes, for clients following games live.
+#[cfg_attr(not(any(feature = "graphql", feature = "webhooks")), allow(dead_code))]
+#[derive(Debug, Clone, Copy)]
+pub struct GameUpdate {
+    pub game_id: Uuid,
+    pub event: GameEvent,
+    /// Set when the change happened on another instance and was relayed
+    /// here, so it isn't relayed back.
+    pub relayed: bool,
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub games: Arc<DashMap<Uuid, SharedGame>>,
+    /// Archived games, when the store doesn't keep them itself.
+    pub archive: Arc<DashMap<Uuid, GameEntry>>,
+    /// Held shared while a game is stored and moved into or out of the
+    /// registry, and exclusively while a checkpoint starts, so a checkpoint
+    /// never falls between the two.
+    pub registering: Arc<RwLock<()>>,
+    pub store: Arc<dyn GameStore>,
+    pub updates: broadcast::Sender<GameUpdate>,
+    pub jobs: Jobs,
+    /// While set, no new games can be started; existing games continue.
+    pub maintenance: Arc<AtomicBool>,
+    pub drain: Arc<Drain>,
+    /// The Redis connection used to relay updates, if `pubsub.redis_url` is
+    /// set.
+    pub redis: Arc<Link>,
+    pub flags: Arc<Flags>,
+    pub experiment: Arc<Experiment>,
+    pub tuner: Arc<Tuner>,
+    /// How long the engine takes over its replies.
+    pub engine: EngineConfig,
+    /// Games whose engine reply is underway, with the version it answers,
+    /// so the reply sweep leaves them alone.
+    pub replying: Arc<DashMap<Uuid, u64>>,
+    pub tournaments: Arc<DashMap<Uuid, SharedTournament>>,
+    pub matches: Arc<DashMap<Uuid, SharedMatch>>,
+    pub arenas: Arc<Arenas>,
+    pub notakto: Arc<DashMap<Uuid, SharedNotakto>>,
+    pub three_player: Arc<DashMap<Uuid, SharedThreePlayer>>,
+    pub lobbies: Arc<Lobbies>,
+    pub invites: Arc<Invites>,
+    pub presence: Arc<Presence>,
+    pub bots: Arc<Bots>,
+    pub puzzles: Arc<Puzzles>,
+    pub puzzle_attempts: Arc<Attempts>,
+    pub puzzle_rushes: Arc<Rushes>,
+    pub accounts: Arc<Accounts>,
+    pub limits: Arc<Limits>,
+    pub audit: Arc<AuditLog>,
+    pub rollups: Arc<Rollups>,
+    pub cluster: Arc<Cluster>,
+    pub tenants: Arc<Tenants>,
+    pub discord: Arc<Discord>,
+    /// `server.public_url`, without a trailing slash.
+    pub public_url: Option<Arc<str>>,
+    #[cfg(feature = "webhooks")]
+    pub webhooks: Arc<Webhooks>,
+    #[cfg(feature = "oauth")]
+    pub oauth: Arc<OAuth>,
+    #[cfg(feature = "slack")]
+    pub slack: Arc<Slack>,
+    #[cfg(feature = "telegram")]
+    pub telegram: Arc<Telegram>,
+    #[cfg(feature = "webpush")]
+    pub push: Arc<Push>,
+}
+
+impl AppState {
+    pub fn new(registry: GameRegistry, store: Arc<dyn GameStore>) -> Self {
+        let (archived, live): (GameRegistry, GameRegistry) = registry
+            .into_iter()
+            .partition(|(_, entry)| entry.archived_at.is_some());
+        let games = live
+            .into_iter()
+            .map(|(id, entry)| (id, Arc::new(Mutex::new(entry))))
+            .collect();
+        let (updates, _) = broadcast::channel(UPDATE_CHANNEL_CAPACITY);
+        Self {
+            games: Arc::new(games),
+            archive: Arc::new(archived.into_iter().collect()),
+            registering: Arc::new(RwLock::new(())),
+            store,
+            updates,
+            jobs: Jobs::default(),
+            maintenance: Arc::new(AtomicBool::new(false)),
+            drain: Arc::new(Drain::new(ServerConfig::default().drain_grace())),
+            redis: Arc::new(Link::default()),
+            flags: Arc::new(Flags::default()),
+            experiment: Arc::new(Experiment::default()),
+            tuner: Arc::new(Tuner::default()),
+            engine: EngineConfig::default(),
+            replying: Arc::new(DashMap::new()),
+            tournaments: Arc::new(DashMap::new()),
+            matches: Arc::new(DashMap::new()),
+            arenas: Arc::new(Arenas::new()),
+            notakto: Arc::new(DashMap::new()),
+            three_player: Arc::new(DashMap::new()),
+            lobbies: Arc::new(Lobbies::new(LobbiesConfig::default())),
+            invites: Arc::new(Invites::new()),
+            presence: Arc::new(Presence::new(PresenceConfig::default())),
+            bots: Arc::new(Bots::new(BotsConfig::default())),
+            puzzles: Arc::new(Puzzles::default()),
+            puzzle_attempts: Arc::new(Attempts::new()),
+            puzzle_rushes: Arc::new(Rushes::new()),
+            accounts: Arc::new(Accounts::new(AccountsConfig::default())),
+            limits: Arc::new(Limits::new(LimitsConfig::default())),
+            audit: Arc::new(AuditLog::default()),
+            rollups: Arc::new(Rollups::default()),
+            cluster: Arc::new(Cluster::new(ClusterConfig::default())),
+            tenants: Arc::new(Tenants::default()),
+            discord: Arc::new(Discord::default()),
+            public_url: None,
+            #[cfg(feature = "webhooks")]
+            webhooks: Arc::new(Webhooks::new()),
+            #[cfg(feature = "oauth")]
+            oauth: Arc::new(OAuth::new(&OAuthConfig::default())),
+            #[cfg(feature = "slack")]
+            slack: Arc::new(Slack::new(&SlackConfig::default())),
+            #[cfg(feature = "telegram")]
+            telegram: Arc::new(Telegram::new(&TelegramConfig::default())),
+            #[cfg(feature = "webpush")]
+            push: Arc::new(Push::new(&PushConfig::default()).expect("no keys to check")),
+        }
+    }
+
+    pub fn with_engine(mut self, config: EngineConfig) -> Self {
+        self.engine = config;
+        self
+    }
+
+    pub fn with_lobbies(mut self, config: LobbiesConfig) -> Self {
+        self.lobbies = Arc::new(Lobbies::new(config));
+        self
+    }
+
+    pub fn with_presence(mut self, config: PresenceConfig) -> Self {
+        self.presence = Arc::new(Presence::new(config));
+        self
+    }
+
+    pub fn with_bots(mut self, config: BotsConfig) -> Self {
+        self.bots = Arc::new(Bots::new(config));
+        self
+    }
+
+    #[cfg(feature = "oauth")]
+    pub fn with_oauth(mut self, config: &OAuthConfig) -> Self {
+        self.oauth = Arc::new(OAuth::new(config));
+        self
+    }
+
+    #[cfg(feature = "slack")]
+    pub fn with_slack(mut self, config: &SlackConfig) -> Self {
+        self.slack = Arc::new(Slack::new(config));
+        self
+    }
+
+    #[cfg(feature = "telegram")]
+    pub fn with_telegram(mut self, config: &TelegramConfig) -> Self {
+        self.telegram = Arc::new(Telegram::new(config));
+        self
+    }
+
+    #[cfg(feature = "webpush")]
+    pub fn with_push(mut self, push: Push) -> Self {
+        self.push = Arc::new(push);
+        self
+    }
+
+    pub fn with_accounts(mut self, config: AccountsConfig) -> Self {
+        self.accounts = Arc::new(Accounts::new(config));
+        self
+    }
+
+    pub fn with_limits(mut self, config: LimitsConfig) -> Self {
+        self.limits = Arc::new(Limits::new(config));
+        self
+    }
+
+    pub fn with_cluster(mut self, config: ClusterConfig) -> Self {
+        self.cluster = Arc::new(Cluster::new(config));
+        self
+    }
+
+    pub fn with_tenants(mut self, config: BTreeMap<String, TenantConfig>) -> Self {
+        self.tenants = Arc::new(Tenants::new(config));
+        self
+    }
+
+    pub fn with_discord(mut self, config: &DiscordConfig) -> Self {
+        self.discord = Arc::new(Discord::new(config));
+        self
+    }
+
+    pub fn with_public_url(mut self, url: Option<&str>) -> Self {
+        self.public_url = url.map(|url| url.trim_end_matches('/').into());
+        self
+    }
+
+    pub fn with_drain(mut self, grace: Duration) -> Self {
+        self.drain = Arc::new(Drain::new(grace));
+        self
+    }
+
+    pub fn with_flags(mut self, config: &Config) -> Self {
+        self.flags = Arc::new(Flags::new(config.flags.clone(), &config.tenants));
+        self
+    }
+
+    pub fn with_experiment(mut self, config: ExperimentConfig) -> Self {
+        self.experiment = Arc::new(Experiment::new(config));
+        self
+    }
+
+    /// Sets up the tuner, carrying on from the games in the registry.
+    pub fn with_calibration(mut self, config: CalibrationConfig) -> Self {
+        let resumed = calibration::latest_tuned(&self.games);
+        self.tuner = Arc::new(Tuner::new(config, resumed));
+        self
+    }
+
+    pub fn with_puzzles(mut self, puzzles: Puzzles) -> Self {
+        self.puzzles = Arc::new(puzzles);
+        self
+    }
+
+    /// Puts tournaments loaded from storage back into the registry.
+    pub fn restore_tournaments(&self, tournaments: Vec<Tournament>) {
+        for tournament in tournaments {
+            self.tournaments
+                .insert(tournament.id, Arc::new(Mutex::new(tournament)));
+        }
+    }
+
+    pub fn tournament(&self, tournament_id: &Uuid) -> Option<SharedTournament> {
+        self.tournaments
+            .get(tournament_id)
+            .map(|tournament| tournament.clone())
+    }
+
+    /// Puts matches loaded from storage back into the registry.
+    pub fn restore_matches(&self, matches: Vec<Match>) {
+        for m in matches {
+            self.matches.insert(m.id, Arc::new(Mutex::new(m)));
+        }
+    }
+
+    pub fn find_match(&self, match_id: &Uuid) -> Option<SharedMatch> {
+        self.matches.get(match_id).map(|m| m.clone())
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Fails while new games can't be started: in maintenance mode, or while
+    /// draining before a restart.
+    pub fn accepting_games(&self) -> Result<(), Error> {
+        if self.drain.is_draining() {
+            return Err(Error::Draining);
+        }
+        if self.in_maintenance() {
+            return Err(Error::Maintenance);
+        }
+        Ok(())
+    }
+
+    /// How many updates a subscriber can fall behind by before missing some.
+    pub fn update_capacity(&self) -> usize {
+        UPDATE_CHANNEL_CAPACITY
+    }
+
+    /// Notifies subscribers that a game changed. Nobody listening is fine.
+    pub fn publish(&self, game_id: Uuid, event: GameEvent) {
+        let _ = self.updates.send(GameUpdate {
+            game_id,
+            event,
+            relayed: false,
+        });
+    }
+
+    /// Stores a new game and adds it to the registry.
+    pub async fn insert_game(&self, game_id: Uuid, entry: GameEntry) -> Result<(), Error> {
+        let _registering = self.registering.read().await;
+        self.store
+            .insert_game(game_id, &entry)
+            .await
+            .map_err(Error::Storage)?;
+        self.games.insert(game_id, Arc::new(Mutex::new(entry)));
+        Ok(())
+    }
+
+    /// Returns a handle to the game, without holding any lock on the map.
+    pub fn game(&self, game_id: &Uuid) -> Option<SharedGame> {
+        self.games.get(game_id).map(|game| game.clone())
+    }
+
+    /// Copies every game out of the map, waiting for in-progress moves to
+    /// finish so each entry is consistent.
+    pub async fn snapshot(&self) -> GameRegistry {
+        let games: Vec<(Uuid, SharedGame)> = self
+            .games
+            .iter()
+            .map(|game| (*game.key(), game.value().clone()))
+            .collect();
+        let mut registry = GameRegistry::with_capacity(games.len());
+        for (id, game) in games {
+            registry.insert(id, game.lock().await.clone());
+        }
+        registry
+    }
+
+    /// Like `snapshot`, with the archived games the store doesn't keep
+    /// itself, for saving.
+    pub async fn snapshot_for_store(&self) -> GameRegistry {
+        let mut registry = self.snapshot().await;
+        for archived in self.archive.iter() {
+            registry.insert(*archived.key(), archived.value().clone());
+        }
+        registry
+    }
+}
+
+/// Archives expired finished games, and purges archived ones past their
+/// retention, expired Notakto and three-player games, lobbies, invites, and
+/// lapsed game leases. Run every `games.purge_interval_secs`.
+pub async fn purge(state: AppState, games_config: GamesConfig) {
+    let archived = archive::archive_expired(&state, Utc::now(), games_config.finished_ttl()).await;
+    if !archived.is_empty() {
+        log::info!("Archived {} finished games.", archived.len());
+        log::info!("Total number of games after purge: {}", state.games.len());
+    }
+    for game_id in archived {
+        state.publish(game_id, GameEvent::Expired);
+    }
+    let removed = archive::purge(&state, Utc::now(), games_config.archive_retention()).await;
+    if removed > 0 {
+        log::info!("Purged {} archived games.", removed);
+    }
+    let removed = notakto::purge_expired(&state.notakto, Utc::now(), games_config.finished_ttl());
+    if removed > 0 {
+        log::info!("Purged {} finished Notakto games.", removed);
+    }
+    let removed =
+        three_player::purge_expired(&state.three_player, Utc::now(), games_config.finished_ttl());
+    if removed > 0 {
+        log::info!("Purged {} finished three-player games.", removed);
+    }
+    let removed = state.lobbies.purge_expired(Utc::now());
+    if removed > 0 {
+        log::info!("Purged {} expired lobbies.", removed);
+    }
+    let removed = state.invites.purge_expired(Utc::now());
+    if removed > 0 {
+        log::info!("Purged {} expired invites.", removed);
+    }
+    state.cluster.forget_expired(Utc::now());
+}
+
+/// Saves every game through the store, so stores that only save on shutdown
+/// have less to replay after a crash. Run every
+/// `storage.checkpoint_interval_secs`.
+pub async fn checkpoint(state: AppState) {
+    let started = {
+        let _registering = state.registering.write().await;
+        state.store.start_checkpoint().await
+    };
+    if let Err(e) = started {
+        log::error!("Failed to start a checkpoint: {}", e);
+        return;
+    }
+    let registry = state.snapshot_for_store().await;
+    if let Err(e) = state.store.finish_checkpoint(&registry).await {
+        log::error!("Failed to save a checkpoint: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::try_move, store::SnapshotStore};
+
+    #[tokio::test]
+    async fn test_checkpoints_wait_for_games_being_added() {
+        let path = std::env::temp_dir().join(format!("laika-adding-{}.json", Uuid::new_v4()));
+        let journal = path.with_extension("journal.jsonl");
+        let store = Arc::new(SnapshotStore::new(path.clone()));
+        let state = AppState::new(GameRegistry::new(), store.clone());
+        state
+            .insert_game(Uuid::new_v4(), GameEntry::new(GameState::default()))
+            .await
+            .unwrap();
+
+        // A game between its journal line and the registry holds the
+        // checkpoint back, so it is in the snapshot or the journal after.
+        let adding = state.registering.read().await;
+        let checkpointing = tokio::spawn(checkpoint(state.clone()));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(journal.exists());
+        drop(adding);
+        checkpointing.await.unwrap();
+        assert!(!journal.exists());
+
+        let restored = store.load(Utc::now()).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.len(), 1);
+    }
+
+    #[test]
+    fn test_idempotent_moves_replay_and_reject_key_reuse() {
+        let mut entry = GameEntry::new(GameState::default());
+        let move_request = MoveRequest {
+            player_move: PlayerMove { row: 0, col: 0 },
+            expected_version: None,
+        };
+        assert_eq!(
+            entry.replay_idempotent_move("key", &move_request).unwrap(),
+            None
+        );
+
+        try_move(&mut entry.state, Player::X, move_request.player_move).unwrap();
+        entry
+            .idempotent_moves
+            .insert("key".to_string(), (move_request, entry.state));
+
+        assert_eq!(
+            entry.replay_idempotent_move("key", &move_request).unwrap(),
+            Some(entry.state)
+        );
+
+        let other_move = MoveRequest {
+            player_move: PlayerMove { row: 2, col: 2 },
+            expected_version: None,
+        };
+        assert!(matches!(
+            entry.replay_idempotent_move("key", &other_move),
+            Err(Error::IdempotencyKeyReused)
+        ));
+    }
+}
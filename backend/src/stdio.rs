@@ -0,0 +1,172 @@
+//! `laika engine`: the engine over stdin/stdout, so other tools and GUIs can
+//! embed it without going through HTTP.
+//!
+//! The protocol is line-based JSON, loosely modeled on UCI. Each line on
+//! stdin is one command and gets exactly one line back on stdout:
+//!
+//! ```text
+//! {"cmd": "hello"}
+//! {"ok": true, "name": "laika", "version": "0.1.0", "engines": ["minimax", "random"]}
+//! {"cmd": "bestmove", "notation": "X:b2 O:a1 X:c3"}
+//! {"ok": true, "move": {"row": 0, "col": 0}, "square": "a3", "to_play": "O", "status": "InProgress", "nodes": 1173}
+//! {"cmd": "quit"}
+//! ```
+//!
+//! The position is given like an import, as `notation` or as a `moves`
+//! array; an empty board is `"notation": ""`. Commands that fail get
+//! `{"ok": false, "error": "..."}` and the engine keeps reading.
+
+use std::io::{self, BufRead, Write};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::EngineKind,
+    game::{GameStatus, Player, PlayerMove},
+    import::{self, ImportRequest},
+    notation,
+};
+
+#[derive(Debug, Args)]
+pub struct EngineArgs {
+    /// Engine used when a command doesn't name one
+    #[arg(long, value_enum, default_value_t)]
+    pub engine: EngineKind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+enum Command {
+    Hello,
+    BestMove {
+        #[serde(flatten)]
+        position: ImportRequest,
+        engine: Option<EngineKind>,
+    },
+    Quit,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Hello {
+        ok: bool,
+        name: &'static str,
+        version: &'static str,
+        engines: [EngineKind; 2],
+    },
+    BestMove {
+        ok: bool,
+        /// `null` once the game is over.
+        #[serde(rename = "move")]
+        best_move: Option<PlayerMove>,
+        square: Option<String>,
+        to_play: Player,
+        status: GameStatus,
+        nodes: u64,
+    },
+    Error {
+        ok: bool,
+        error: String,
+    },
+}
+
+impl Reply {
+    fn error(error: impl ToString) -> Self {
+        Reply::Error {
+            ok: false,
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Answers one line of input, or returns `None` for `quit`.
+fn respond(line: &str, default_engine: EngineKind) -> Option<Reply> {
+    let command = match serde_json::from_str(line) {
+        Ok(command) => command,
+        Err(e) => return Some(Reply::error(format!("Invalid command: {e}"))),
+    };
+    let reply = match command {
+        Command::Hello => Reply::Hello {
+            ok: true,
+            name: "laika",
+            version: env!("CARGO_PKG_VERSION"),
+            engines: EngineKind::ALL,
+        },
+        Command::BestMove { position, engine } => {
+            let state = match position.moves().and_then(|moves| import::replay(&moves)) {
+                Ok(entry) => entry.state,
+                Err(e) => return Some(Reply::error(format!("Invalid {e}"))),
+            };
+            let (best_move, stats) = engine.unwrap_or(default_engine).search(&state);
+            Reply::BestMove {
+                ok: true,
+                best_move,
+                square: best_move.map(notation::square),
+                to_play: state.to_play,
+                status: state.status,
+                nodes: stats.nodes,
+            }
+        }
+        Command::Quit => return None,
+    };
+    Some(reply)
+}
+
+/// Reads commands from stdin until `quit` or end of input.
+pub fn run(args: &EngineArgs) -> io::Result<()> {
+    let stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    for line in stdin.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(reply) = respond(&line, args.engine) else {
+            break;
+        };
+        let reply = serde_json::to_string(&reply).expect("replies serialize");
+        writeln!(stdout, "{reply}")?;
+        // GUIs wait for each reply before sending the next command.
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(line: &str) -> serde_json::Value {
+        let reply = respond(line, EngineKind::Minimax).expect("not a quit");
+        serde_json::to_value(reply).unwrap()
+    }
+
+    #[test]
+    fn test_best_move_blocks_the_open_line() {
+        let best = reply(r#"{"cmd": "bestmove", "notation": "X:a3 O:b2 X:b3"}"#);
+        assert_eq!(best["ok"], true);
+        assert_eq!(best["square"], "c3");
+        assert_eq!(best["to_play"], "O");
+
+        let moves = reply(
+            r#"{"cmd": "bestmove", "moves": [{"player": "X", "row": 0, "col": 0}, {"player": "O", "row": 1, "col": 1}, {"player": "X", "row": 0, "col": 1}]}"#,
+        );
+        assert_eq!(moves["move"], best["move"]);
+
+        let finished = reply(r#"{"cmd": "bestmove", "notation": "X:a3 O:a1 X:b3 O:b1 X:c3"}"#);
+        assert_eq!(finished["move"], serde_json::Value::Null);
+        assert_eq!(finished["status"]["Win"], "X");
+    }
+
+    #[test]
+    fn test_bad_commands_get_an_error_reply() {
+        assert_eq!(reply("not json")["ok"], false);
+        assert_eq!(reply(r#"{"cmd": "go"}"#)["ok"], false);
+        let illegal = reply(r#"{"cmd": "bestmove", "notation": "X:b2 O:b2"}"#);
+        assert_eq!(illegal["ok"], false);
+        assert!(respond(r#"{"cmd": "quit"}"#, EngineKind::Minimax).is_none());
+        assert_eq!(reply(r#"{"cmd": "hello"}"#)["engines"][0], "minimax");
+    }
+}
@@ -0,0 +1,17 @@
+//! The no-op store: games live only in the in-memory registry and are lost
+//! on restart.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::{GameStore, StoreError};
+use crate::state::GameRegistry;
+
+pub struct MemoryStore;
+
+#[async_trait]
+impl GameStore for MemoryStore {
+    async fn load(&self, _finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError> {
+        Ok(GameRegistry::new())
+    }
+}
@@ -0,0 +1,341 @@
+//! Persistence for games.
+//!
+//! The in-memory `GameRegistry` is always the working set that handlers read
+//! and mutate. A `GameStore` decides what survives a restart: it is asked to
+//! load the registry at startup, is told about every new game and every event
+//! appended to a game's log, and gets one last look at the registry on
+//! shutdown.
+
+use std::{fmt, io, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use uuid::Uuid;
+
+use crate::{
+    account::Account,
+    audit::AuditEntry,
+    cluster::Lease,
+    config::{StorageBackend, StorageConfig},
+    events::EventRecord,
+    experiments::VariantResults,
+    limits::Usage,
+    matches::Match,
+    puzzle::daily::Attempt,
+    rollup::DailyStats,
+    search::{FoundGame, GameQuery},
+    state::{GameEntry, GameRegistry},
+    tournament::Tournament,
+};
+
+mod memory;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod snapshot;
+
+pub use memory::MemoryStore;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+pub use snapshot::SnapshotStore;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Serialization(serde_json::Error),
+    /// The stored game moved on since it was loaded, e.g. because another
+    /// instance applied a move to it.
+    #[cfg(feature = "postgres")]
+    VersionMismatch(Uuid),
+    #[cfg(feature = "postgres")]
+    Database(sqlx::Error),
+    #[cfg(feature = "postgres")]
+    Migration(sqlx::migrate::MigrateError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "I/O error: {}", e),
+            StoreError::Serialization(e) => write!(f, "serialization error: {}", e),
+            #[cfg(feature = "postgres")]
+            StoreError::VersionMismatch(id) => {
+                write!(f, "game {} was modified concurrently", id)
+            }
+            #[cfg(feature = "postgres")]
+            StoreError::Database(e) => write!(f, "database error: {}", e),
+            #[cfg(feature = "postgres")]
+            StoreError::Migration(e) => write!(f, "migration error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Serialization(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<sqlx::Error> for StoreError {
+    fn from(e: sqlx::Error) -> Self {
+        StoreError::Database(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<sqlx::migrate::MigrateError> for StoreError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        StoreError::Migration(e)
+    }
+}
+
+#[async_trait]
+pub trait GameStore: Send + Sync {
+    /// Loads the games that should be live at startup: everything still in
+    /// progress plus games that finished at or after `finished_since`.
+    async fn load(&self, finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError>;
+
+    /// Fails if storage can't be reached, for `/readyz`.
+    async fn check(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Loads a single game as it is stored now, e.g. after another instance
+    /// changed it. `None` if the store doesn't have it.
+    async fn load_game(&self, _id: Uuid) -> Result<Option<GameEntry>, StoreError> {
+        Ok(None)
+    }
+
+    /// Searches every game the store has, including those no longer in the
+    /// registry. `None` for stores that keep nothing beyond the registry,
+    /// which is then searched instead.
+    async fn search_games(&self, _query: &GameQuery) -> Result<Option<Vec<FoundGame>>, StoreError> {
+        Ok(None)
+    }
+
+    /// Reads up to `limit` games with their event logs, in ID order after
+    /// `after`, that changed at or after `since`. `None` for stores that keep
+    /// nothing beyond the registry, which is then exported instead.
+    async fn export_games(
+        &self,
+        _since: Option<DateTime<Utc>>,
+        _after: Option<Uuid>,
+        _limit: usize,
+    ) -> Result<Option<Vec<FoundGame>>, StoreError> {
+        Ok(None)
+    }
+
+    /// Persists a newly created game, with its event log so far.
+    async fn insert_game(&self, _id: Uuid, _entry: &GameEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Persists `events`, the newest in the game's log, together with the
+    /// entry they produced. Either all of it is stored or none of it is.
+    /// `events` is empty when only the clock or a draw offer changed.
+    async fn append_events(
+        &self,
+        _id: Uuid,
+        _events: &[EventRecord],
+        _entry: &GameEntry,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Persists a change of who is playing a game, such as an invite being
+    /// redeemed or the players swapping sides under the pie rule.
+    async fn update_mode(&self, _id: Uuid, _entry: &GameEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Removes a game and its history.
+    async fn delete_game(&self, _id: Uuid) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Whether the store holds archived games itself, so they needn't stay
+    /// in memory. If not, they are saved with the registry instead.
+    fn keeps_archive(&self) -> bool {
+        false
+    }
+
+    /// Marks a game as archived, as of `entry.archived_at`. Stores that don't
+    /// keep the archive may record it to replay after a crash.
+    async fn archive_game(&self, _id: Uuid, _entry: &GameEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Brings an archived game back, as of `entry.restored_at`. Stores that
+    /// don't keep the archive may record it as `archive_game` does.
+    async fn restore_game(&self, _id: Uuid, _entry: &GameEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Deletes the games archived at or before `before`. Returns how many.
+    async fn purge_archived(&self, _before: DateTime<Utc>) -> Result<usize, StoreError> {
+        Ok(0)
+    }
+
+    /// Loads every tournament, finished or not.
+    async fn load_tournaments(&self) -> Result<Vec<Tournament>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Persists a tournament after any change to it.
+    async fn save_tournament(&self, _tournament: &Tournament) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Loads every best-of-N match, finished or not.
+    async fn load_matches(&self) -> Result<Vec<Match>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Persists a match after any change to it.
+    async fn save_match(&self, _m: &Match) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Loads every attempt at a daily puzzle.
+    async fn load_puzzle_attempts(&self) -> Result<Vec<Attempt>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Persists a daily puzzle attempt when it is started and when it is
+    /// answered.
+    async fn save_puzzle_attempt(&self, _attempt: &Attempt) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Loads every account, guest or registered.
+    async fn load_accounts(&self) -> Result<Vec<Account>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Persists an account after any change to it.
+    async fn save_account(&self, _account: &Account) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Persists `account` after it took over the guest account `guest_id`,
+    /// and deletes the guest. Either both happen or neither does.
+    async fn merge_accounts(&self, _guest_id: Uuid, _account: &Account) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Loads what every caller with quotas has used.
+    async fn load_usage(&self) -> Result<Vec<Usage>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Persists a caller's usage after any change to it.
+    async fn save_usage(&self, _usage: &Usage) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Loads the whole audit log.
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Adds an entry to the audit log. Entries are never changed or removed.
+    async fn append_audit(&self, _entry: &AuditEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Tallies the games that finished on `day`, including archived ones.
+    /// `None` for stores that keep nothing beyond the registry and the
+    /// archive, which are then tallied instead.
+    async fn roll_up_day(&self, _day: NaiveDate) -> Result<Option<DailyStats>, StoreError> {
+        Ok(None)
+    }
+
+    /// Tallies the games in experiment `name`, including archived ones.
+    /// `None` for stores that keep nothing beyond the registry and the
+    /// archive, which are then tallied instead.
+    async fn experiment_results(
+        &self,
+        _name: &str,
+    ) -> Result<Option<Vec<VariantResults>>, StoreError> {
+        Ok(None)
+    }
+
+    /// Loads every daily rollup.
+    async fn load_daily_stats(&self) -> Result<Vec<DailyStats>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Persists a day's rollup, replacing any earlier one for that day.
+    async fn save_daily_stats(&self, _stats: &DailyStats) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Takes or renews the lease on a game for instance `owner`, reachable
+    /// at `url`, for `ttl`. Another instance's lease is only taken over
+    /// once it has run out. Returns the lease now in effect, whoever holds
+    /// it. A store no other instance shares always grants the lease.
+    async fn acquire_lease(
+        &self,
+        game_id: Uuid,
+        owner: Uuid,
+        url: &str,
+        ttl: TimeDelta,
+    ) -> Result<Lease, StoreError> {
+        Ok(Lease {
+            game_id,
+            owner,
+            url: url.to_string(),
+            expires_at: Utc::now() + ttl,
+        })
+    }
+
+    /// Gives up every lease `owner` holds, so other instances can take its
+    /// games over without waiting for the leases to run out.
+    async fn release_leases(&self, _owner: Uuid) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Starts a checkpoint, before the registry is copied for
+    /// `finish_checkpoint`. Whatever is stored from here on is kept apart
+    /// from what came before, which the copy is sure to include.
+    async fn start_checkpoint(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Saves `registry`, copied after `start_checkpoint`, in place of what
+    /// was stored before that.
+    async fn finish_checkpoint(&self, _registry: &GameRegistry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Called once on shutdown, after in-flight requests have drained.
+    async fn flush(&self, _registry: &GameRegistry) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// Builds the store selected by `config`.
+pub async fn connect(config: &StorageConfig) -> Result<Arc<dyn GameStore>, StoreError> {
+    match config.backend {
+        StorageBackend::Memory => Ok(Arc::new(MemoryStore)),
+        StorageBackend::Snapshot => Ok(Arc::new(
+            SnapshotStore::new(config.snapshot_path.clone()).with_sync(config.sync_journal),
+        )),
+        #[cfg(feature = "postgres")]
+        StorageBackend::Postgres => {
+            // Presence is checked by `Config::validate`.
+            let url = config.database_url.as_deref().unwrap_or_default();
+            Ok(Arc::new(PostgresStore::connect(url).await?))
+        }
+        #[cfg(not(feature = "postgres"))]
+        StorageBackend::Postgres => unreachable!("rejected by Config::validate"),
+    }
+}
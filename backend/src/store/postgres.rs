@@ -0,0 +1,901 @@
+//! PostgreSQL-backed store. Every new game and every event in a game's log is
+//! written through to the database before the response is sent, so games
+//! survive crashes as well as clean restarts.
+
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use sqlx::{
+    PgPool, Postgres, QueryBuilder, Row,
+    postgres::{PgPoolOptions, PgRow},
+    types::Json,
+};
+use uuid::Uuid;
+
+use super::{GameStore, StoreError};
+use crate::{
+    MoveRequest,
+    account::Account,
+    audit::AuditEntry,
+    calibration::Outcomes,
+    cluster::Lease,
+    engine::Strategy,
+    events::{self, Event, EventRecord},
+    experiments::{Assignment, VariantResults},
+    game::{GameState, GameStatus, MoveRecord, Player, PlayerMove},
+    limits::Usage,
+    matches::Match,
+    puzzle::daily::Attempt,
+    rollup::{self, DailyStats},
+    search::{FoundGame, GameQuery, SortKey, SortOrder, StatusFilter, Variant},
+    state::{GameEntry, GameMode, GameRegistry, PieRule},
+    tournament::Tournament,
+};
+
+/// Seeded by the initial migration; the AI always plays O.
+const MINIMAX_PLAYER_ID: Uuid = Uuid::from_u128(1);
+
+const MAX_CONNECTIONS: u32 = 10;
+
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    /// Connects to `url` and applies any pending migrations.
+    pub async fn connect(url: &str) -> Result<Self, StoreError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+fn status_label(status: GameStatus) -> &'static str {
+    match status {
+        GameStatus::InProgress => "in_progress",
+        GameStatus::Draw => "draw",
+        GameStatus::Win(Player::X) => "x_won",
+        GameStatus::Win(Player::O) => "o_won",
+    }
+}
+
+fn player_label(player: Player) -> &'static str {
+    match player {
+        Player::X => "X",
+        Player::O => "O",
+    }
+}
+
+type IdempotentMoves = HashMap<String, (MoveRequest, GameState)>;
+
+/// The `games` columns `entry_from_row` reads.
+const GAME_COLUMNS: &str = "state, idempotent_moves, finished_at, mode, tournament_id, match_id, \
+                            pie_rule, blunder_chance, auto_tuned, tenant, archived_at, \
+                            restored_at, experiment";
+
+/// Builds an entry from a `games` row, with an empty event log for the
+/// caller to load.
+fn entry_from_row(row: &PgRow) -> Result<GameEntry, StoreError> {
+    let Json(state): Json<GameState> = row.try_get("state")?;
+    let Json(idempotent_moves): Json<IdempotentMoves> = row.try_get("idempotent_moves")?;
+    let mut entry = GameEntry::new(state);
+    entry.events.clear();
+    entry.finished_at = row.try_get("finished_at")?;
+    entry.idempotent_moves = idempotent_moves;
+    let Json(mode): Json<GameMode> = row.try_get("mode")?;
+    entry.mode = mode;
+    entry.tournament_id = row.try_get("tournament_id")?;
+    entry.match_id = row.try_get("match_id")?;
+    let Json(pie_rule): Json<PieRule> = row.try_get("pie_rule")?;
+    entry.pie_rule = pie_rule;
+    entry.blunder_chance = row.try_get("blunder_chance")?;
+    entry.auto_tuned = row.try_get("auto_tuned")?;
+    entry.tenant = row.try_get("tenant")?;
+    entry.archived_at = row.try_get("archived_at")?;
+    entry.restored_at = row.try_get("restored_at")?;
+    let experiment: Option<Json<Assignment>> = row.try_get("experiment")?;
+    entry.experiment = experiment.map(|Json(assignment)| assignment);
+    Ok(entry)
+}
+
+fn event_from_row(row: &PgRow) -> Result<EventRecord, StoreError> {
+    let Json(event): Json<Event> = row.try_get("event")?;
+    Ok(EventRecord {
+        seq: row.try_get::<i64, _>("seq")? as u64,
+        at: row.try_get("at")?,
+        event,
+    })
+}
+
+fn lease_from_row(row: &PgRow) -> Result<Lease, StoreError> {
+    Ok(Lease {
+        game_id: row.try_get("game_id")?,
+        owner: row.try_get("owner")?,
+        url: row.try_get("url")?,
+        expires_at: row.try_get("expires_at")?,
+    })
+}
+
+#[async_trait]
+impl GameStore for PostgresStore {
+    async fn check(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn load(&self, finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError> {
+        // Games that expired while no instance was running to archive them.
+        let archived = sqlx::query(
+            "UPDATE games SET archived_at = now() \
+             WHERE archived_at IS NULL AND GREATEST(finished_at, restored_at) < $1",
+        )
+        .bind(finished_since)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        if archived > 0 {
+            log::info!("Archived {} expired games in PostgreSQL", archived);
+        }
+
+        let rows = sqlx::query(&format!(
+            "SELECT id, {GAME_COLUMNS} FROM games WHERE archived_at IS NULL"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut registry = GameRegistry::new();
+        for row in rows {
+            registry.insert(row.try_get("id")?, entry_from_row(&row)?);
+        }
+
+        let ids: Vec<Uuid> = registry.keys().copied().collect();
+        let event_rows = sqlx::query(
+            "SELECT game_id, seq, at, event FROM game_events \
+             WHERE game_id = ANY($1) ORDER BY game_id, seq",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        for row in event_rows {
+            let game_id: Uuid = row.try_get("game_id")?;
+            let record = event_from_row(&row)?;
+            if let Some(entry) = registry.get_mut(&game_id) {
+                entry.events.push(record);
+            }
+        }
+
+        // Games from before event logs only have their moves.
+        let mut legacy: HashMap<Uuid, Vec<MoveRecord>> = registry
+            .iter()
+            .filter(|(_, entry)| entry.events.is_empty())
+            .map(|(id, _)| (*id, Vec::new()))
+            .collect();
+        let legacy_ids: Vec<Uuid> = legacy.keys().copied().collect();
+        let move_rows = sqlx::query(
+            "SELECT game_id, ply, player, row_index, col_index FROM moves \
+             WHERE game_id = ANY($1) ORDER BY game_id, ply",
+        )
+        .bind(&legacy_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        for row in move_rows {
+            let game_id: Uuid = row.try_get("game_id")?;
+            let player: String = row.try_get("player")?;
+            let record = MoveRecord {
+                ply: row.try_get::<i64, _>("ply")? as u64,
+                player: if player == "X" { Player::X } else { Player::O },
+                player_move: PlayerMove {
+                    row: row.try_get::<i16, _>("row_index")? as usize,
+                    col: row.try_get::<i16, _>("col_index")? as usize,
+                },
+            };
+            if let Some(moves) = legacy.get_mut(&game_id) {
+                moves.push(record);
+            }
+        }
+        if !legacy.is_empty() {
+            let mut tx = self.pool.begin().await?;
+            for (game_id, moves) in legacy {
+                let entry = registry.get_mut(&game_id).expect("legacy games are loaded");
+                entry.events = events::backfill(&entry.state, &moves, Utc::now());
+                insert_events(&mut tx, game_id, &entry.events, false).await?;
+            }
+            tx.commit().await?;
+        }
+
+        for (game_id, entry) in registry.iter_mut() {
+            if let Err(e) = entry.replay_events() {
+                log::warn!("Keeping stored state of game {}: {}", game_id, e);
+            }
+        }
+        log::info!("Loaded {} games from PostgreSQL", registry.len());
+        Ok(registry)
+    }
+
+    async fn load_game(&self, id: Uuid) -> Result<Option<GameEntry>, StoreError> {
+        let Some(row) = sqlx::query(&format!("SELECT {GAME_COLUMNS} FROM games WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let mut entry = entry_from_row(&row)?;
+        // Games loaded at startup already had any legacy log filled in.
+        entry.events =
+            sqlx::query("SELECT seq, at, event FROM game_events WHERE game_id = $1 ORDER BY seq")
+                .bind(id)
+                .fetch_all(&self.pool)
+                .await?
+                .iter()
+                .map(event_from_row)
+                .collect::<Result<_, _>>()?;
+        if let Err(e) = entry.replay_events() {
+            log::warn!("Keeping stored state of game {}: {}", id, e);
+        }
+        Ok(Some(entry))
+    }
+
+    async fn search_games(&self, query: &GameQuery) -> Result<Option<Vec<FoundGame>>, StoreError> {
+        // Built up filter by filter, so each query only has conditions the
+        // indexes can serve.
+        let mut sql = QueryBuilder::<Postgres>::new(format!(
+            "SELECT id, created_at, {GAME_COLUMNS} FROM games WHERE TRUE"
+        ));
+        if let Some(player_id) = &query.player_id {
+            sql.push(" AND (mode->'x'->>'name' = ")
+                .push_bind(player_id)
+                .push(" OR mode->'o'->>'name' = ")
+                .push_bind(player_id)
+                .push(" OR mode->>'bot_id' = ")
+                .push_bind(player_id)
+                .push(")");
+        }
+        if let Some(variant) = query.variant {
+            sql.push(" AND variants @> ARRAY[")
+                .push_bind(variant.label())
+                .push("]");
+        }
+        match query.status.map(StatusFilter::status) {
+            None => {}
+            Some(None) => {
+                sql.push(" AND status <> 'in_progress'");
+            }
+            Some(Some(status)) => {
+                sql.push(" AND status = ").push_bind(status_label(status));
+            }
+        }
+        if let Some(after) = query.created_after {
+            sql.push(" AND created_at > ").push_bind(after);
+        }
+        if let Some(tenant) = &query.tenant {
+            sql.push(" AND tenant = ").push_bind(tenant);
+        }
+        match query.archived {
+            None => {}
+            Some(true) => {
+                sql.push(" AND archived_at IS NOT NULL");
+            }
+            Some(false) => {
+                sql.push(" AND archived_at IS NULL");
+            }
+        }
+        sql.push(match query.sort {
+            SortKey::CreatedAt => " ORDER BY created_at",
+            SortKey::FinishedAt => " ORDER BY finished_at",
+            SortKey::Moves => " ORDER BY version",
+        });
+        sql.push(match query.order {
+            SortOrder::Asc => " ASC NULLS FIRST, id ASC",
+            SortOrder::Desc => " DESC NULLS LAST, id DESC",
+        });
+        sql.push(" LIMIT ").push_bind(query.limit() as i64);
+
+        let rows = sql.build().fetch_all(&self.pool).await?;
+        let found = rows
+            .iter()
+            .map(|row| {
+                Ok(FoundGame {
+                    game_id: row.try_get("id")?,
+                    created_at: row.try_get("created_at")?,
+                    entry: entry_from_row(row)?,
+                })
+            })
+            .collect::<Result<_, StoreError>>()?;
+        Ok(Some(found))
+    }
+
+    async fn export_games(
+        &self,
+        since: Option<DateTime<Utc>>,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Option<Vec<FoundGame>>, StoreError> {
+        let mut sql = QueryBuilder::<Postgres>::new(format!(
+            "SELECT id, created_at, {GAME_COLUMNS} FROM games WHERE TRUE"
+        ));
+        if let Some(since) = since {
+            sql.push(" AND updated_at >= ").push_bind(since);
+        }
+        if let Some(after) = after {
+            sql.push(" AND id > ").push_bind(after);
+        }
+        sql.push(" ORDER BY id LIMIT ").push_bind(limit as i64);
+        let rows = sql.build().fetch_all(&self.pool).await?;
+        let mut found = rows
+            .iter()
+            .map(|row| {
+                Ok(FoundGame {
+                    game_id: row.try_get("id")?,
+                    created_at: row.try_get("created_at")?,
+                    entry: entry_from_row(row)?,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let ids: Vec<Uuid> = found.iter().map(|game| game.game_id).collect();
+        let event_rows = sqlx::query(
+            "SELECT game_id, seq, at, event FROM game_events \
+             WHERE game_id = ANY($1) ORDER BY game_id, seq",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut events: HashMap<Uuid, Vec<EventRecord>> = HashMap::new();
+        for row in event_rows {
+            events
+                .entry(row.try_get("game_id")?)
+                .or_default()
+                .push(event_from_row(&row)?);
+        }
+        for game in &mut found {
+            game.entry.events = events.remove(&game.game_id).unwrap_or_default();
+        }
+        Ok(Some(found))
+    }
+
+    async fn insert_game(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let variants: Vec<&str> = Variant::of(&entry.state)
+            .into_iter()
+            .map(Variant::label)
+            .collect();
+        sqlx::query(
+            "INSERT INTO games \
+             (id, o_player_id, state, status, version, idempotent_moves, finished_at, mode, \
+              tournament_id, match_id, pie_rule, blunder_chance, tenant, variants, experiment, \
+              auto_tuned) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+        )
+        .bind(id)
+        // Only the engine has a row in `players` so far.
+        .bind((entry.mode == GameMode::VsEngine).then_some(MINIMAX_PLAYER_ID))
+        .bind(Json(&entry.state))
+        .bind(status_label(entry.state.status))
+        .bind(entry.state.version as i64)
+        .bind(Json(&entry.idempotent_moves))
+        .bind(entry.finished_at)
+        .bind(Json(&entry.mode))
+        .bind(entry.tournament_id)
+        .bind(entry.match_id)
+        .bind(Json(&entry.pie_rule))
+        .bind(entry.blunder_chance)
+        .bind(&entry.tenant)
+        .bind(&variants)
+        .bind(entry.experiment.as_ref().map(Json))
+        .bind(entry.auto_tuned)
+        .execute(&mut *tx)
+        .await?;
+        insert_events(&mut tx, id, &entry.events, true).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn append_events(
+        &self,
+        id: Uuid,
+        events: &[EventRecord],
+        entry: &GameEntry,
+    ) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Guard against another writer having advanced the game since we
+        // loaded it: only update the row if it is still at the version the
+        // first of these moves was played against.
+        let moves = events
+            .iter()
+            .filter(|record| matches!(record.event, Event::MoveMade(_)))
+            .count();
+        let previous_version = entry.state.version - moves as u64;
+        let updated = sqlx::query(
+            "UPDATE games SET state = $2, status = $3, version = $4, idempotent_moves = $5, \
+             finished_at = $6, updated_at = now() \
+             WHERE id = $1 AND version = $7",
+        )
+        .bind(id)
+        .bind(Json(&entry.state))
+        .bind(status_label(entry.state.status))
+        .bind(entry.state.version as i64)
+        .bind(Json(&entry.idempotent_moves))
+        .bind(entry.finished_at)
+        .bind(previous_version as i64)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(StoreError::VersionMismatch(id));
+        }
+
+        insert_events(&mut tx, id, events, true).await?;
+
+        // Dropping `tx` without committing rolls everything back.
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_mode(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        sqlx::query(
+            "UPDATE games SET mode = $2, o_player_id = $3, pie_rule = $4, updated_at = now() \
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(Json(&entry.mode))
+        .bind((entry.mode == GameMode::VsEngine).then_some(MINIMAX_PLAYER_ID))
+        .bind(Json(&entry.pie_rule))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_tournaments(&self) -> Result<Vec<Tournament>, StoreError> {
+        let rows = sqlx::query("SELECT state FROM tournaments")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let Json(tournament): Json<Tournament> = row.try_get("state")?;
+                Ok(tournament)
+            })
+            .collect()
+    }
+
+    async fn save_tournament(&self, tournament: &Tournament) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO tournaments (id, state) VALUES ($1, $2) \
+             ON CONFLICT (id) DO UPDATE SET state = $2, updated_at = now()",
+        )
+        .bind(tournament.id)
+        .bind(Json(tournament))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_matches(&self) -> Result<Vec<Match>, StoreError> {
+        let rows = sqlx::query("SELECT state FROM matches")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let Json(m): Json<Match> = row.try_get("state")?;
+                Ok(m)
+            })
+            .collect()
+    }
+
+    async fn save_match(&self, m: &Match) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO matches (id, state) VALUES ($1, $2) \
+             ON CONFLICT (id) DO UPDATE SET state = $2, updated_at = now()",
+        )
+        .bind(m.id)
+        .bind(Json(m))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_puzzle_attempts(&self) -> Result<Vec<Attempt>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT id, token, name, date, puzzle_id, started_at, answered_at, correct \
+             FROM puzzle_attempts",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(Attempt {
+                    id: row.try_get("id")?,
+                    token: row.try_get("token")?,
+                    name: row.try_get("name")?,
+                    date: row.try_get("date")?,
+                    puzzle_id: row.try_get::<i32, _>("puzzle_id")? as u32,
+                    started_at: row.try_get("started_at")?,
+                    answered_at: row.try_get("answered_at")?,
+                    correct: row.try_get("correct")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_puzzle_attempt(&self, attempt: &Attempt) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO puzzle_attempts \
+                 (id, token, name, date, puzzle_id, started_at, answered_at, correct) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (id) DO UPDATE SET answered_at = $7, correct = $8",
+        )
+        .bind(attempt.id)
+        .bind(&attempt.token)
+        .bind(&attempt.name)
+        .bind(attempt.date)
+        .bind(attempt.puzzle_id as i32)
+        .bind(attempt.started_at)
+        .bind(attempt.answered_at)
+        .bind(attempt.correct)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_accounts(&self) -> Result<Vec<Account>, StoreError> {
+        let rows = sqlx::query("SELECT account FROM players WHERE account IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let Json(account): Json<Account> = row.try_get("account")?;
+                Ok(account)
+            })
+            .collect()
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<(), StoreError> {
+        save_account(&self.pool, account).await
+    }
+
+    async fn merge_accounts(&self, guest_id: Uuid, account: &Account) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM players WHERE id = $1")
+            .bind(guest_id)
+            .execute(&mut *tx)
+            .await?;
+        save_account(&mut *tx, account).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_usage(&self) -> Result<Vec<Usage>, StoreError> {
+        let rows = sqlx::query("SELECT usage FROM usage")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let Json(usage): Json<Usage> = row.try_get("usage")?;
+                Ok(usage)
+            })
+            .collect()
+    }
+
+    async fn save_usage(&self, usage: &Usage) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO usage (subject, usage) VALUES ($1, $2) \
+             ON CONFLICT (subject) DO UPDATE SET usage = $2, updated_at = now()",
+        )
+        .bind(&usage.subject)
+        .bind(Json(usage))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        let rows = sqlx::query("SELECT entry FROM audit_log ORDER BY at, id")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let Json(entry): Json<AuditEntry> = row.try_get("entry")?;
+                Ok(entry)
+            })
+            .collect()
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<(), StoreError> {
+        sqlx::query("INSERT INTO audit_log (at, entry) VALUES ($1, $2)")
+            .bind(entry.at)
+            .bind(Json(entry))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn roll_up_day(&self, day: NaiveDate) -> Result<Option<DailyStats>, StoreError> {
+        let (start, end) = rollup::bounds(day);
+        let row = sqlx::query(
+            "SELECT count(*) AS games, \
+                    count(*) FILTER (WHERE status = 'x_won') AS x_wins, \
+                    count(*) FILTER (WHERE status = 'o_won') AS o_wins, \
+                    count(*) FILTER (WHERE status = 'draw') AS draws, \
+                    COALESCE(avg(version), 0)::DOUBLE PRECISION AS average_moves, \
+                    (SELECT count(DISTINCT player) FROM games, \
+                         LATERAL (VALUES (mode->'x'->>'name'), (mode->'o'->>'name'), \
+                                         (mode->>'bot_id')) AS players (player) \
+                     WHERE tenant IS NULL AND finished_at >= $1 AND finished_at < $2) \
+                        AS distinct_players \
+             FROM games WHERE tenant IS NULL AND finished_at >= $1 AND finished_at < $2",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_one(&self.pool)
+        .await?;
+        // Rounded the way `calibration::difficulty` rounds.
+        let difficulties = sqlx::query(
+            "SELECT to_char(round((LEAST(GREATEST(blunder_chance, 0), 1) * 10)::NUMERIC) / 10, \
+                            'FM0.0') AS difficulty, \
+                    count(*) AS games, \
+                    count(*) FILTER (WHERE status = 'x_won') AS player_wins, \
+                    count(*) FILTER (WHERE status = 'o_won') AS engine_wins, \
+                    count(*) FILTER (WHERE status = 'draw') AS draws \
+             FROM games \
+             WHERE tenant IS NULL AND finished_at >= $1 AND finished_at < $2 \
+                 AND mode->>'kind' = 'vs_engine' \
+             GROUP BY 1",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut by_difficulty = BTreeMap::new();
+        for row in &difficulties {
+            let count = |column: &str| -> Result<u64, StoreError> {
+                Ok(row.try_get::<i64, _>(column)? as u64)
+            };
+            let outcomes = Outcomes {
+                games: count("games")?,
+                player_wins: count("player_wins")?,
+                engine_wins: count("engine_wins")?,
+                draws: count("draws")?,
+            };
+            by_difficulty.insert(row.try_get("difficulty")?, outcomes);
+        }
+        let count =
+            |column: &str| -> Result<u64, StoreError> { Ok(row.try_get::<i64, _>(column)? as u64) };
+        Ok(Some(DailyStats {
+            day,
+            games: count("games")?,
+            x_wins: count("x_wins")?,
+            o_wins: count("o_wins")?,
+            draws: count("draws")?,
+            average_moves: row.try_get("average_moves")?,
+            distinct_players: count("distinct_players")?,
+            by_difficulty,
+            rolled_up_at: Utc::now(),
+        }))
+    }
+
+    async fn experiment_results(
+        &self,
+        name: &str,
+    ) -> Result<Option<Vec<VariantResults>>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT experiment->>'variant' AS variant, experiment->'strategy' AS strategy, \
+                    count(*) FILTER (WHERE status = 'in_progress') AS in_progress, \
+                    count(*) FILTER (WHERE status = 'x_won') AS player_wins, \
+                    count(*) FILTER (WHERE status = 'o_won') AS engine_wins, \
+                    count(*) FILTER (WHERE status = 'draw') AS draws, \
+                    COALESCE(sum(version) FILTER (WHERE status <> 'in_progress'), 0)::BIGINT \
+                        AS finished_moves \
+             FROM games WHERE experiment->>'experiment' = $1 \
+             GROUP BY 1, 2 ORDER BY 1",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                let count = |column: &str| -> Result<u64, StoreError> {
+                    Ok(row.try_get::<i64, _>(column)? as u64)
+                };
+                let Json(strategy): Json<Strategy> = row.try_get("strategy")?;
+                Ok(VariantResults::from_counts(
+                    row.try_get("variant")?,
+                    strategy,
+                    count("in_progress")?,
+                    count("player_wins")?,
+                    count("engine_wins")?,
+                    count("draws")?,
+                    count("finished_moves")?,
+                ))
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    async fn load_daily_stats(&self) -> Result<Vec<DailyStats>, StoreError> {
+        let rows = sqlx::query("SELECT * FROM daily_stats ORDER BY day")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let count = |column: &str| -> Result<u64, StoreError> {
+                    Ok(row.try_get::<i64, _>(column)? as u64)
+                };
+                Ok(DailyStats {
+                    day: row.try_get("day")?,
+                    games: count("games")?,
+                    x_wins: count("x_wins")?,
+                    o_wins: count("o_wins")?,
+                    draws: count("draws")?,
+                    average_moves: row.try_get("average_moves")?,
+                    distinct_players: count("distinct_players")?,
+                    by_difficulty: row.try_get::<Json<_>, _>("by_difficulty")?.0,
+                    rolled_up_at: row.try_get("rolled_up_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_daily_stats(&self, stats: &DailyStats) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO daily_stats \
+                 (day, games, x_wins, o_wins, draws, average_moves, distinct_players, \
+                  rolled_up_at, by_difficulty) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+             ON CONFLICT (day) DO UPDATE SET games = $2, x_wins = $3, o_wins = $4, draws = $5, \
+                 average_moves = $6, distinct_players = $7, rolled_up_at = $8, \
+                 by_difficulty = $9",
+        )
+        .bind(stats.day)
+        .bind(stats.games as i64)
+        .bind(stats.x_wins as i64)
+        .bind(stats.o_wins as i64)
+        .bind(stats.draws as i64)
+        .bind(stats.average_moves)
+        .bind(stats.distinct_players as i64)
+        .bind(stats.rolled_up_at)
+        .bind(Json(&stats.by_difficulty))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn acquire_lease(
+        &self,
+        game_id: Uuid,
+        owner: Uuid,
+        url: &str,
+        ttl: TimeDelta,
+    ) -> Result<Lease, StoreError> {
+        // The database's clock decides when leases run out, so the
+        // instances' clocks don't have to agree.
+        let taken = sqlx::query(
+            "INSERT INTO game_leases (game_id, owner, url, expires_at) \
+             VALUES ($1, $2, $3, now() + make_interval(secs => $4)) \
+             ON CONFLICT (game_id) DO UPDATE \
+                 SET owner = EXCLUDED.owner, url = EXCLUDED.url, expires_at = EXCLUDED.expires_at \
+                 WHERE game_leases.owner = EXCLUDED.owner OR game_leases.expires_at < now() \
+             RETURNING game_id, owner, url, expires_at",
+        )
+        .bind(game_id)
+        .bind(owner)
+        .bind(url)
+        .bind(ttl.num_milliseconds() as f64 / 1000.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        let row =
+            match taken {
+                Some(row) => row,
+                None => sqlx::query(
+                    "SELECT game_id, owner, url, expires_at FROM game_leases WHERE game_id = $1",
+                )
+                .bind(game_id)
+                .fetch_one(&self.pool)
+                .await?,
+            };
+        lease_from_row(&row)
+    }
+
+    async fn release_leases(&self, owner: Uuid) -> Result<(), StoreError> {
+        sqlx::query("DELETE FROM game_leases WHERE owner = $1")
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_game(&self, id: Uuid) -> Result<(), StoreError> {
+        // Moves and events go with it via ON DELETE CASCADE.
+        sqlx::query("DELETE FROM games WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn keeps_archive(&self) -> bool {
+        true
+    }
+
+    async fn archive_game(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        sqlx::query("UPDATE games SET archived_at = COALESCE(archived_at, $2) WHERE id = $1")
+            .bind(id)
+            .bind(entry.archived_at.unwrap_or_else(Utc::now))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn restore_game(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        sqlx::query("UPDATE games SET archived_at = NULL, restored_at = $2 WHERE id = $1")
+            .bind(id)
+            .bind(entry.restored_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_archived(&self, before: DateTime<Utc>) -> Result<usize, StoreError> {
+        let purged = sqlx::query("DELETE FROM games WHERE archived_at <= $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(purged as usize)
+    }
+}
+
+/// Appends `events` to a game's log, and with `with_moves` also adds the moves
+/// among them to `moves`.
+async fn insert_events(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    id: Uuid,
+    events: &[EventRecord],
+    with_moves: bool,
+) -> Result<(), StoreError> {
+    for record in events {
+        sqlx::query("INSERT INTO game_events (game_id, seq, at, event) VALUES ($1, $2, $3, $4)")
+            .bind(id)
+            .bind(record.seq as i64)
+            .bind(record.at)
+            .bind(Json(&record.event))
+            .execute(&mut **tx)
+            .await?;
+        if let Event::MoveMade(record) = record.event
+            && with_moves
+        {
+            sqlx::query(
+                "INSERT INTO moves (game_id, ply, player, row_index, col_index) \
+                 VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(id)
+            .bind(record.ply as i64)
+            .bind(player_label(record.player))
+            .bind(record.player_move.row as i16)
+            .bind(record.player_move.col as i16)
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn save_account<'e, E>(executor: E, account: &Account) -> Result<(), StoreError>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query(
+        "INSERT INTO players (id, display_name, username, account) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (id) DO UPDATE SET display_name = $2, username = $3, account = $4",
+    )
+    .bind(account.id)
+    .bind(account.username.as_deref().unwrap_or("Guest"))
+    .bind(&account.username)
+    .bind(Json(account))
+    .execute(executor)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,844 @@
+//! Persists the game registry to disk across restarts.
+//!
+//! On shutdown, and every `storage.checkpoint_interval_secs` while running,
+//! the whole registry is serialized as JSON. Between snapshots, new games,
+//! every event appended to a game's log, and games being archived or
+//! restored go to a JSON Lines journal next to the snapshot. On startup the snapshot is loaded and the journal replayed
+//! on top of it, so active (and recently finished) games survive a deploy,
+//! and a crash loses at most the journal line being written. Each game's
+//! position and result are rebuilt from its event log as it is loaded.
+//! Tournaments, matches, daily puzzle attempts, accounts, limit usage, and
+//! daily stats are written through to their own files next to the snapshot
+//! whenever they change. The audit log is appended to a JSON Lines file as it grows.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+use super::{GameStore, StoreError};
+use crate::{
+    MoveRequest,
+    account::Account,
+    audit::AuditEntry,
+    clock::Clock,
+    events::{self, EventRecord},
+    game::{GameState, MoveRecord, Player},
+    limits::Usage,
+    matches::Match,
+    puzzle::daily::Attempt,
+    rollup::DailyStats,
+    state::{GameEntry, GameMode, GameRegistry, PieRule},
+    tournament::Tournament,
+};
+
+/// Keeps games in memory while running and round-trips them through a JSON
+/// file across restarts.
+pub struct SnapshotStore {
+    path: PathBuf,
+    tournaments: Mutex<HashMap<Uuid, Tournament>>,
+    matches: Mutex<HashMap<Uuid, Match>>,
+    puzzle_attempts: Mutex<HashMap<Uuid, Attempt>>,
+    accounts: Mutex<HashMap<Uuid, Account>>,
+    usage: Mutex<HashMap<String, Usage>>,
+    daily_stats: Mutex<BTreeMap<NaiveDate, DailyStats>>,
+    /// Held while appending, so concurrent entries don't interleave.
+    audit: Mutex<()>,
+    /// Held while rewriting one of the write-through files, so writes land
+    /// in the order their caches were changed.
+    writing: tokio::sync::Mutex<()>,
+    /// Held while appending to or rotating the game journal; shared with the
+    /// blocking tasks that do the appending.
+    journal: Arc<Mutex<()>>,
+    /// Whether each journal line is flushed to disk before the change it
+    /// records is acknowledged.
+    sync: bool,
+}
+
+impl SnapshotStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            tournaments: Mutex::new(HashMap::new()),
+            matches: Mutex::new(HashMap::new()),
+            puzzle_attempts: Mutex::new(HashMap::new()),
+            accounts: Mutex::new(HashMap::new()),
+            usage: Mutex::new(HashMap::new()),
+            daily_stats: Mutex::new(BTreeMap::new()),
+            audit: Mutex::new(()),
+            writing: tokio::sync::Mutex::new(()),
+            journal: Arc::new(Mutex::new(())),
+            sync: false,
+        }
+    }
+
+    /// Flushes the journal to disk after every line, so games also survive
+    /// the machine going down, at the cost of a disk flush per move.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    fn tournaments_path(&self) -> PathBuf {
+        self.path.with_extension("tournaments.json")
+    }
+
+    fn matches_path(&self) -> PathBuf {
+        self.path.with_extension("matches.json")
+    }
+
+    fn puzzle_attempts_path(&self) -> PathBuf {
+        self.path.with_extension("puzzle-attempts.json")
+    }
+
+    fn accounts_path(&self) -> PathBuf {
+        self.path.with_extension("accounts.json")
+    }
+
+    fn usage_path(&self) -> PathBuf {
+        self.path.with_extension("usage.json")
+    }
+
+    fn stats_path(&self) -> PathBuf {
+        self.path.with_extension("stats.json")
+    }
+
+    fn audit_path(&self) -> PathBuf {
+        self.path.with_extension("audit.jsonl")
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.path.with_extension("journal.jsonl")
+    }
+
+    /// The journal set aside by a checkpoint that hasn't finished yet.
+    fn previous_journal_path(&self) -> PathBuf {
+        self.path.with_extension("journal.previous.jsonl")
+    }
+
+    /// Applies `update` to a write-through cache, which returns the cache as
+    /// JSON, and replaces the file at `path` with it on the blocking pool.
+    async fn write_through<C>(
+        &self,
+        path: PathBuf,
+        cache: &Mutex<C>,
+        update: impl FnOnce(&mut C) -> serde_json::Result<Vec<u8>>,
+    ) -> Result<(), StoreError> {
+        let _writing = self.writing.lock().await;
+        let json = update(&mut cache.lock().expect("write-through cache poisoned"))?;
+        tokio::task::spawn_blocking(move || write_file(&path, &json))
+            .await
+            .map_err(io::Error::other)??;
+        Ok(())
+    }
+
+    /// Appends `line` to the journal on the blocking pool. Any flush to disk
+    /// comes after the lock is let go, so other appends don't queue behind
+    /// it.
+    async fn append_journal(&self, line: &JournalLine) -> Result<(), StoreError> {
+        let mut bytes = serde_json::to_vec(line)?;
+        bytes.push(b'\n');
+        let (journal, path, sync) = (self.journal.clone(), self.journal_path(), self.sync);
+        tokio::task::spawn_blocking(move || {
+            let file = {
+                let _guard = journal.lock().expect("journal lock poisoned");
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(&bytes)?;
+                file
+            };
+            if sync {
+                file.sync_data()?;
+            }
+            Ok::<_, io::Error>(())
+        })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(())
+    }
+}
+
+/// A change to the registry since the last snapshot.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JournalLine {
+    Created {
+        game_id: Uuid,
+        entry: Box<GameEntry>,
+    },
+    /// Events appended to a game's log, with the live state that isn't part
+    /// of it as of then.
+    Appended {
+        game_id: Uuid,
+        events: Vec<EventRecord>,
+        clock: Option<Clock>,
+        draw_offer: Option<Player>,
+        finished_at: Option<DateTime<Utc>>,
+        idempotent_moves: HashMap<String, (MoveRequest, GameState)>,
+    },
+    Seated {
+        game_id: Uuid,
+        mode: GameMode,
+        pie_rule: PieRule,
+    },
+    Deleted {
+        game_id: Uuid,
+    },
+    Archived {
+        game_id: Uuid,
+        archived_at: Option<DateTime<Utc>>,
+    },
+    Restored {
+        game_id: Uuid,
+        restored_at: Option<DateTime<Utc>>,
+    },
+}
+
+/// Cuts a line left unfinished by a crash off the end of the journal at
+/// `path`, so the next line appended starts on a line of its own instead of
+/// being glued to it and lost as well.
+fn trim_torn_tail(path: &Path) -> io::Result<()> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let complete = bytes
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |end| end + 1);
+    if complete < bytes.len() {
+        log::warn!(
+            "Cutting an unfinished line of {} bytes off {}",
+            bytes.len() - complete,
+            path.display()
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(())
+}
+
+/// Applies a journal's lines to `registry` in order. Events the registry
+/// already has are skipped, so lines that made it into the snapshot can be
+/// replayed harmlessly. A line cut short by a crash is skipped too.
+fn replay_journal(path: &Path, registry: &mut GameRegistry) -> io::Result<usize> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut replayed = 0;
+    for line in BufReader::new(file).lines() {
+        let line = match serde_json::from_str(&line?) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Skipping unreadable journal line: {}", e);
+                continue;
+            }
+        };
+        match line {
+            JournalLine::Created { game_id, entry } => {
+                registry.entry(game_id).or_insert(*entry);
+            }
+            JournalLine::Appended {
+                game_id,
+                events,
+                clock,
+                draw_offer,
+                finished_at,
+                idempotent_moves,
+            } => {
+                let Some(entry) = registry.get_mut(&game_id) else {
+                    continue;
+                };
+                for record in events {
+                    if record.seq == entry.events.len() as u64 {
+                        entry.events.push(record);
+                    }
+                }
+                entry.state.clock = clock;
+                entry.state.draw_offer = draw_offer;
+                entry.finished_at = finished_at;
+                entry.idempotent_moves = idempotent_moves;
+            }
+            JournalLine::Seated {
+                game_id,
+                mode,
+                pie_rule,
+            } => {
+                if let Some(entry) = registry.get_mut(&game_id) {
+                    entry.mode = mode;
+                    entry.pie_rule = pie_rule;
+                }
+            }
+            JournalLine::Deleted { game_id } => {
+                registry.remove(&game_id);
+            }
+            JournalLine::Archived {
+                game_id,
+                archived_at,
+            } => {
+                if let Some(entry) = registry.get_mut(&game_id) {
+                    entry.archived_at = archived_at;
+                }
+            }
+            JournalLine::Restored {
+                game_id,
+                restored_at,
+            } => {
+                if let Some(entry) = registry.get_mut(&game_id) {
+                    entry.archived_at = None;
+                    entry.restored_at = restored_at;
+                }
+            }
+        }
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[async_trait]
+impl GameStore for SnapshotStore {
+    /// The snapshot's directory must be there and writable.
+    async fn check(&self) -> Result<(), StoreError> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if fs::metadata(dir)?.permissions().readonly() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is read-only", dir.display()),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    async fn load(&self, _finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError> {
+        // Expired games are left to the purge task.
+        let mut registry = load(&self.path)?;
+        for path in [self.previous_journal_path(), self.journal_path()] {
+            trim_torn_tail(&path)?;
+        }
+        let replayed = replay_journal(&self.previous_journal_path(), &mut registry)?
+            + replay_journal(&self.journal_path(), &mut registry)?;
+        if replayed > 0 {
+            for (game_id, entry) in registry.iter_mut() {
+                if let Err(e) = entry.replay_events() {
+                    log::warn!("Keeping stored state of game {}: {}", game_id, e);
+                }
+            }
+        }
+        log::info!(
+            "Restored {} games from {}, replaying {} journal lines",
+            registry.len(),
+            self.path.display(),
+            replayed
+        );
+        Ok(registry)
+    }
+
+    async fn insert_game(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        self.append_journal(&JournalLine::Created {
+            game_id: id,
+            entry: Box::new(entry.clone()),
+        })
+        .await
+    }
+
+    async fn append_events(
+        &self,
+        id: Uuid,
+        events: &[EventRecord],
+        entry: &GameEntry,
+    ) -> Result<(), StoreError> {
+        self.append_journal(&JournalLine::Appended {
+            game_id: id,
+            events: events.to_vec(),
+            clock: entry.state.clock,
+            draw_offer: entry.state.draw_offer,
+            finished_at: entry.finished_at,
+            idempotent_moves: entry.idempotent_moves.clone(),
+        })
+        .await
+    }
+
+    async fn update_mode(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        self.append_journal(&JournalLine::Seated {
+            game_id: id,
+            mode: entry.mode.clone(),
+            pie_rule: entry.pie_rule,
+        })
+        .await
+    }
+
+    async fn delete_game(&self, id: Uuid) -> Result<(), StoreError> {
+        self.append_journal(&JournalLine::Deleted { game_id: id })
+            .await
+    }
+
+    async fn archive_game(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        self.append_journal(&JournalLine::Archived {
+            game_id: id,
+            archived_at: entry.archived_at,
+        })
+        .await
+    }
+
+    async fn restore_game(&self, id: Uuid, entry: &GameEntry) -> Result<(), StoreError> {
+        self.append_journal(&JournalLine::Restored {
+            game_id: id,
+            restored_at: entry.restored_at,
+        })
+        .await
+    }
+
+    async fn load_tournaments(&self) -> Result<Vec<Tournament>, StoreError> {
+        let tournaments: Vec<Tournament> = read_json(&self.tournaments_path())?.unwrap_or_default();
+        let mut cache = self.tournaments.lock().expect("tournament cache poisoned");
+        *cache = tournaments
+            .iter()
+            .map(|tournament| (tournament.id, tournament.clone()))
+            .collect();
+        Ok(tournaments)
+    }
+
+    async fn save_tournament(&self, tournament: &Tournament) -> Result<(), StoreError> {
+        self.write_through(self.tournaments_path(), &self.tournaments, |cache| {
+            cache.insert(tournament.id, tournament.clone());
+            serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+        })
+        .await
+    }
+
+    async fn load_matches(&self) -> Result<Vec<Match>, StoreError> {
+        let matches: Vec<Match> = read_json(&self.matches_path())?.unwrap_or_default();
+        let mut cache = self.matches.lock().expect("match cache poisoned");
+        *cache = matches.iter().map(|m| (m.id, m.clone())).collect();
+        Ok(matches)
+    }
+
+    async fn save_match(&self, m: &Match) -> Result<(), StoreError> {
+        self.write_through(self.matches_path(), &self.matches, |cache| {
+            cache.insert(m.id, m.clone());
+            serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+        })
+        .await
+    }
+
+    async fn load_puzzle_attempts(&self) -> Result<Vec<Attempt>, StoreError> {
+        let attempts: Vec<Attempt> = read_json(&self.puzzle_attempts_path())?.unwrap_or_default();
+        let mut cache = self.puzzle_attempts.lock().expect("attempt cache poisoned");
+        *cache = attempts
+            .iter()
+            .map(|attempt| (attempt.id, attempt.clone()))
+            .collect();
+        Ok(attempts)
+    }
+
+    async fn save_puzzle_attempt(&self, attempt: &Attempt) -> Result<(), StoreError> {
+        self.write_through(
+            self.puzzle_attempts_path(),
+            &self.puzzle_attempts,
+            |cache| {
+                cache.insert(attempt.id, attempt.clone());
+                serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+            },
+        )
+        .await
+    }
+
+    async fn load_accounts(&self) -> Result<Vec<Account>, StoreError> {
+        let accounts: Vec<Account> = read_json(&self.accounts_path())?.unwrap_or_default();
+        let mut cache = self.accounts.lock().expect("account cache poisoned");
+        *cache = accounts
+            .iter()
+            .map(|account| (account.id, account.clone()))
+            .collect();
+        Ok(accounts)
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<(), StoreError> {
+        self.write_through(self.accounts_path(), &self.accounts, |cache| {
+            cache.insert(account.id, account.clone());
+            serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+        })
+        .await
+    }
+
+    async fn merge_accounts(&self, guest_id: Uuid, account: &Account) -> Result<(), StoreError> {
+        self.write_through(self.accounts_path(), &self.accounts, |cache| {
+            cache.remove(&guest_id);
+            cache.insert(account.id, account.clone());
+            serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+        })
+        .await
+    }
+
+    async fn load_usage(&self) -> Result<Vec<Usage>, StoreError> {
+        let usage: Vec<Usage> = read_json(&self.usage_path())?.unwrap_or_default();
+        let mut cache = self.usage.lock().expect("usage cache poisoned");
+        *cache = usage
+            .iter()
+            .map(|record| (record.subject.clone(), record.clone()))
+            .collect();
+        Ok(usage)
+    }
+
+    async fn save_usage(&self, usage: &Usage) -> Result<(), StoreError> {
+        self.write_through(self.usage_path(), &self.usage, |cache| {
+            cache.insert(usage.subject.clone(), usage.clone());
+            serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+        })
+        .await
+    }
+
+    async fn load_daily_stats(&self) -> Result<Vec<DailyStats>, StoreError> {
+        let days: Vec<DailyStats> = read_json(&self.stats_path())?.unwrap_or_default();
+        let mut cache = self.daily_stats.lock().expect("stats cache poisoned");
+        *cache = days
+            .iter()
+            .map(|stats| (stats.day, stats.clone()))
+            .collect();
+        Ok(days)
+    }
+
+    async fn save_daily_stats(&self, stats: &DailyStats) -> Result<(), StoreError> {
+        self.write_through(self.stats_path(), &self.daily_stats, |cache| {
+            cache.insert(stats.day, stats.clone());
+            serde_json::to_vec(&cache.values().collect::<Vec<_>>())
+        })
+        .await
+    }
+
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        let file = match fs::File::open(self.audit_path()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            // A crash mid-append can leave a partial last line behind.
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => log::warn!("Skipping unreadable audit entry: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<(), StoreError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let _guard = self.audit.lock().expect("audit lock poisoned");
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.audit_path())?
+            .write_all(&line)?;
+        Ok(())
+    }
+
+    async fn start_checkpoint(&self) -> Result<(), StoreError> {
+        let _guard = self.journal.lock().expect("journal lock poisoned");
+        // A previous checkpoint that failed left its journal behind; keep
+        // both until one succeeds.
+        if self.previous_journal_path().exists() {
+            let mut previous = OpenOptions::new()
+                .append(true)
+                .open(self.previous_journal_path())?;
+            match fs::read(self.journal_path()) {
+                Ok(bytes) => previous.write_all(&bytes)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            fs::remove_file(self.journal_path())?;
+            return Ok(());
+        }
+        match fs::rename(self.journal_path(), self.previous_journal_path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn finish_checkpoint(&self, registry: &GameRegistry) -> Result<(), StoreError> {
+        save(&self.path, registry)?;
+        match fs::remove_file(self.previous_journal_path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        log::info!("Saved {} games to {}", registry.len(), self.path.display());
+        Ok(())
+    }
+
+    async fn flush(&self, registry: &GameRegistry) -> Result<(), StoreError> {
+        // Nothing is changing any more, so the whole journal is in
+        // `registry`.
+        self.start_checkpoint().await?;
+        self.finish_checkpoint(registry).await
+    }
+}
+
+/// Writes `json` to `path`, going through a temporary file flushed to disk
+/// first, so a crash mid-write never leaves a truncated file behind and the
+/// new file is there once this returns.
+fn write_file(path: &Path, json: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(json)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads JSON from `path`, or `None` if the file doesn't exist.
+fn read_json<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes the registry to `path`, flushed to disk before the journal it
+/// replaces is removed.
+pub fn save(path: &Path, registry: &GameRegistry) -> io::Result<()> {
+    write_file(path, &serde_json::to_vec(registry)?)
+}
+
+/// A game as stored in a snapshot. Snapshots from before games kept an event
+/// log have a list of moves instead.
+#[derive(Deserialize)]
+struct StoredGame {
+    #[serde(flatten)]
+    entry: GameEntry,
+    #[serde(default)]
+    moves: Vec<MoveRecord>,
+}
+
+/// Loads a registry from `path`. A missing snapshot is not an error; it just
+/// means there is nothing to restore.
+pub fn load(path: &Path) -> io::Result<GameRegistry> {
+    let stored: HashMap<Uuid, StoredGame> = read_json(path)?.unwrap_or_default();
+    let registry = stored
+        .into_iter()
+        .map(|(id, StoredGame { mut entry, moves })| {
+            if entry.events.is_empty() {
+                let at = entry.finished_at.unwrap_or_else(Utc::now);
+                entry.events = events::backfill(&entry.state, &moves, at);
+            }
+            if let Err(e) = entry.replay_events() {
+                log::warn!("Keeping stored state of game {}: {}", id, e);
+            }
+            (id, entry)
+        })
+        .collect();
+    Ok(registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{PlayerMove, try_move};
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("laika-snapshot-{}.json", Uuid::new_v4()));
+        let mut registry = GameRegistry::new();
+        registry.insert(Uuid::new_v4(), GameEntry::new(GameState::default()));
+
+        save(&path, &registry).unwrap();
+        let restored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.len(), 1);
+        let (id, entry) = registry.iter().next().unwrap();
+        assert_eq!(restored[id].state, entry.state);
+        assert_eq!(restored[id].events, entry.events);
+    }
+
+    #[test]
+    fn test_missing_snapshot_loads_empty_registry() {
+        let path = std::env::temp_dir().join(format!("laika-missing-{}.json", Uuid::new_v4()));
+        assert!(load(&path).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_journal_replays_on_top_of_the_last_checkpoint() {
+        let path = std::env::temp_dir().join(format!("laika-journal-{}.json", Uuid::new_v4()));
+        let store = SnapshotStore::new(path.clone());
+        let game_id = Uuid::new_v4();
+        let mut entry = GameEntry::new(GameState::default());
+        store.insert_game(game_id, &entry).await.unwrap();
+
+        let play = |entry: &mut GameEntry, player, row, col| {
+            let first_new_event = entry.events.len();
+            try_move(&mut entry.state, player, PlayerMove { row, col }).unwrap();
+            entry.record_move(player, PlayerMove { row, col });
+            entry.events[first_new_event..].to_vec()
+        };
+        let events = play(&mut entry, Player::X, 1, 1);
+        store.append_events(game_id, &events, &entry).await.unwrap();
+
+        // A checkpoint taken now is overtaken by a move made while it runs.
+        store.start_checkpoint().await.unwrap();
+        let mut registry = GameRegistry::new();
+        registry.insert(game_id, entry.clone());
+        let events = play(&mut entry, Player::O, 0, 0);
+        store.append_events(game_id, &events, &entry).await.unwrap();
+        store.finish_checkpoint(&registry).await.unwrap();
+
+        // The process dies without flushing.
+        let restored = SnapshotStore::new(path.clone())
+            .load(Utc::now())
+            .await
+            .unwrap();
+        for path in [store.journal_path(), path] {
+            fs::remove_file(path).unwrap();
+        }
+        assert_eq!(restored[&game_id].events, entry.events);
+        assert_eq!(restored[&game_id].state, entry.state);
+    }
+
+    #[tokio::test]
+    async fn test_archiving_and_restoring_are_replayed() {
+        let path = std::env::temp_dir().join(format!("laika-archive-{}.json", Uuid::new_v4()));
+        let store = SnapshotStore::new(path.clone());
+        let (archived, restored) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut entry = GameEntry::new(GameState::default());
+        for game_id in [archived, restored] {
+            store.insert_game(game_id, &entry).await.unwrap();
+        }
+        entry.archived_at = Some(Utc::now());
+        for game_id in [archived, restored] {
+            store.archive_game(game_id, &entry).await.unwrap();
+        }
+        entry.archived_at = None;
+        entry.restored_at = Some(Utc::now());
+        store.restore_game(restored, &entry).await.unwrap();
+
+        let registry = SnapshotStore::new(path.clone())
+            .load(Utc::now())
+            .await
+            .unwrap();
+        fs::remove_file(store.journal_path()).unwrap();
+        assert!(registry[&archived].archived_at.is_some());
+        assert!(registry[&archived].restored_at.is_none());
+        assert!(registry[&restored].archived_at.is_none());
+        assert_eq!(registry[&restored].restored_at, entry.restored_at);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_usage_is_written_through_whole() {
+        let path = std::env::temp_dir().join(format!("laika-usage-{}.json", Uuid::new_v4()));
+        let store = Arc::new(SnapshotStore::new(path.clone()));
+        let saves = (0..8).map(|calls| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let usage = Usage {
+                    subject: format!("key:{calls}"),
+                    window_start: Utc::now(),
+                    analysis_calls: calls,
+                    games: Vec::new(),
+                    reserved: 0,
+                };
+                store.save_usage(&usage).await
+            })
+        });
+        for save in saves.collect::<Vec<_>>() {
+            save.await.unwrap().unwrap();
+        }
+
+        let mut usage = SnapshotStore::new(path.clone()).load_usage().await.unwrap();
+        fs::remove_file(store.usage_path()).unwrap();
+        assert!(!store.usage_path().with_extension("tmp").exists());
+        usage.sort_by_key(|usage| usage.analysis_calls);
+        assert_eq!(
+            usage
+                .iter()
+                .map(|usage| usage.analysis_calls)
+                .collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_synced_journals_take_concurrent_appends() {
+        let path = std::env::temp_dir().join(format!("laika-synced-{}.json", Uuid::new_v4()));
+        let store = Arc::new(SnapshotStore::new(path.clone()).with_sync(true));
+        assert!(store.sync);
+        let games: Vec<Uuid> = (0..8).map(|_| Uuid::new_v4()).collect();
+        let inserts = games.iter().map(|&game_id| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let entry = GameEntry::new(GameState::default());
+                store.insert_game(game_id, &entry).await
+            })
+        });
+        for insert in inserts.collect::<Vec<_>>() {
+            insert.await.unwrap().unwrap();
+        }
+
+        let restored = SnapshotStore::new(path.clone())
+            .load(Utc::now())
+            .await
+            .unwrap();
+        fs::remove_file(store.journal_path()).unwrap();
+        assert_eq!(restored.len(), games.len());
+        assert!(!SnapshotStore::new(path).sync);
+    }
+
+    #[tokio::test]
+    async fn test_a_torn_journal_line_is_cut_before_appending() {
+        let path = std::env::temp_dir().join(format!("laika-torn-{}.json", Uuid::new_v4()));
+        let store = SnapshotStore::new(path.clone());
+        let game_id = Uuid::new_v4();
+        let mut entry = GameEntry::new(GameState::default());
+        store.insert_game(game_id, &entry).await.unwrap();
+        let mut play = async |store: &SnapshotStore, player, row, col| {
+            let first_new_event = entry.events.len();
+            try_move(&mut entry.state, player, PlayerMove { row, col }).unwrap();
+            entry.record_move(player, PlayerMove { row, col });
+            store
+                .append_events(game_id, &entry.events[first_new_event..], &entry)
+                .await
+                .unwrap();
+        };
+        play(&store, Player::X, 1, 1).await;
+
+        // The process dies halfway through writing a line.
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(store.journal_path())
+            .unwrap();
+        journal
+            .write_all(br#"{"kind":"appended","game_id":"#)
+            .unwrap();
+
+        let store = SnapshotStore::new(path.clone());
+        let restored = store.load(Utc::now()).await.unwrap();
+        assert_eq!(restored[&game_id].state.version, 1);
+        play(&store, Player::O, 0, 0).await;
+        play(&store, Player::X, 2, 2).await;
+
+        let restored = SnapshotStore::new(path.clone())
+            .load(Utc::now())
+            .await
+            .unwrap();
+        fs::remove_file(store.journal_path()).unwrap();
+        assert_eq!(restored[&game_id].events, entry.events);
+        assert_eq!(restored[&game_id].state.version, 3);
+    }
+}
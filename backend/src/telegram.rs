@@ -0,0 +1,352 @@
+//! Playing against the AI from Telegram.
+//!
+//! Telegram posts every update for the bot to a webhook. Each chat has one
+//! game at a time: `/new` starts it, and the bot answers with the board and
+//! an inline keyboard, a button per cell. Pressing one plays that cell as
+//! X, for anyone in the chat, and the message is edited to show the board
+//! after the AI's reply. `/board` shows the chat's game again, and
+//! `/image` sends it as a picture, fetched by Telegram from `board.png`,
+//! which needs `server.public_url`. Sessions are kept in memory, so a
+//! restart forgets which game each chat was playing.
+//!
+//! Telegram sends the secret the webhook was registered with in a header,
+//! and updates without it are refused.
+
+use std::time::Duration;
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::{
+    Error, MoveRequest,
+    api::constant_time_eq,
+    config::TelegramConfig,
+    game::{Cell, GameState, GameStatus, Player, PlayerMove},
+    state::AppState,
+};
+
+pub const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+const API_URL: &str = "https://api.telegram.org";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const USAGE: &str = "/new starts a game against the AI in this chat, and you play X: \
+    press a cell to move there. /board shows the game again, and /image sends it \
+    as a picture.";
+
+pub struct Telegram {
+    bot_token: Option<String>,
+    webhook_secret: Option<String>,
+    /// The game each chat is playing.
+    sessions: DashMap<i64, Uuid>,
+    client: reqwest::Client,
+}
+
+impl Telegram {
+    pub fn new(config: &TelegramConfig) -> Self {
+        Self {
+            bot_token: config.bot_token.clone(),
+            webhook_secret: config.webhook_secret.clone(),
+            sessions: DashMap::new(),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client builds"),
+        }
+    }
+
+    /// Checks the secret Telegram sent with an update.
+    pub fn verify(&self, secret: Option<&str>) -> Result<(), Error> {
+        let expected = self
+            .webhook_secret
+            .as_deref()
+            .filter(|_| self.bot_token.is_some())
+            .ok_or(Error::Unauthorized("Telegram isn't set up on this server"))?;
+        match secret {
+            Some(secret) if constant_time_eq(secret.as_bytes(), expected.as_bytes()) => Ok(()),
+            _ => Err(Error::Unauthorized("The Telegram secret token is invalid")),
+        }
+    }
+
+    /// Calls a Bot API method.
+    async fn call(&self, method: &str, params: Value) {
+        let Some(token) = &self.bot_token else {
+            return;
+        };
+        let result = self
+            .client
+            .post(format!("{API_URL}/bot{token}/{method}"))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(params.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            // Without the URL, which has the token in it.
+            log::warn!("Telegram {} failed: {}", method, e.without_url());
+        }
+    }
+}
+
+/// An update, with only the fields used here.
+#[derive(Debug, Deserialize)]
+pub struct Update {
+    message: Option<Message>,
+    callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+/// A press of an inline keyboard button.
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    id: String,
+    message: Option<Message>,
+    data: Option<String>,
+}
+
+/// The data of a cell's button, `{game_id}:{row}:{col}`, within the 64
+/// bytes Telegram allows.
+fn button_data(game_id: Uuid, row: usize, col: usize) -> String {
+    format!("{}:{row}:{col}", game_id.simple())
+}
+
+fn parse_button_data(data: &str) -> Option<(Uuid, PlayerMove)> {
+    let mut parts = data.split(':');
+    let game_id = parts.next()?.parse().ok()?;
+    let row = parts.next()?.parse().ok()?;
+    let col = parts.next()?.parse().ok()?;
+    parts
+        .next()
+        .is_none()
+        .then_some((game_id, PlayerMove { row, col }))
+}
+
+/// The command in a message, without any `@botname`, e.g. `/new`.
+fn command(text: &str) -> &str {
+    let word = text.split_whitespace().next().unwrap_or_default();
+    word.split('@').next().unwrap_or_default()
+}
+
+fn symbol(cell: Cell) -> &'static str {
+    match cell {
+        Cell::Empty => "·",
+        Cell::Occupied(Player::X) => "X",
+        Cell::Occupied(Player::O) => "O",
+        Cell::Blocked => "#",
+    }
+}
+
+/// The board as text and where the game stands, in HTML.
+fn board_text(game_state: &GameState) -> String {
+    let rows: Vec<String> = game_state
+        .board
+        .rows()
+        .into_iter()
+        .map(|cells| {
+            let row: Vec<&str> = cells.into_iter().map(symbol).collect();
+            row.join(" ")
+        })
+        .collect();
+    format!("<pre>{}</pre>\n{}", rows.join("\n"), game_state.summary())
+}
+
+/// A button per cell while the game is on, and none after.
+fn keyboard(game_id: Uuid, game_state: &GameState) -> Value {
+    if game_state.status != GameStatus::InProgress {
+        return json!({ "inline_keyboard": [] });
+    }
+    let rows: Vec<Vec<Value>> = game_state
+        .board
+        .rows()
+        .into_iter()
+        .enumerate()
+        .map(|(row, cells)| {
+            cells
+                .into_iter()
+                .enumerate()
+                .map(|(col, cell)| {
+                    json!({
+                        "text": symbol(cell),
+                        "callback_data": button_data(game_id, row, col),
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    json!({ "inline_keyboard": rows })
+}
+
+/// Handles an update. Updates that aren't commands or button presses are
+/// ignored.
+pub async fn handle(state: AppState, update: Update) {
+    if let Some(query) = update.callback_query {
+        press(&state, query).await;
+    } else if let Some(message) = update.message
+        && let Some(text) = &message.text
+    {
+        reply(&state, message.chat.id, command(text)).await;
+    }
+}
+
+async fn reply(state: &AppState, chat_id: i64, command: &str) {
+    let telegram = &state.telegram;
+    let send = |text: String, reply_markup: Option<Value>| {
+        let mut params = json!({ "chat_id": chat_id, "text": text, "parse_mode": "HTML" });
+        if let Some(reply_markup) = reply_markup {
+            params["reply_markup"] = reply_markup;
+        }
+        telegram.call("sendMessage", params)
+    };
+    let session = telegram.sessions.get(&chat_id).map(|game_id| *game_id);
+    let game = match session {
+        Some(game_id) => match state.game(&game_id) {
+            Some(game) => Some((game_id, game.lock().await.state)),
+            None => None,
+        },
+        None => None,
+    };
+    match (command, game) {
+        ("/new" | "/start", _) => {
+            match crate::create_game(state, None, GameState::default(), None).await {
+                Ok((game_id, game_state)) => {
+                    telegram.sessions.insert(chat_id, game_id);
+                    log::info!("Started Telegram game {} in chat {}", game_id, chat_id);
+                    send(
+                        board_text(&game_state),
+                        Some(keyboard(game_id, &game_state)),
+                    )
+                    .await;
+                }
+                Err(e) => send(e.to_string(), None).await,
+            }
+        }
+        ("/board" | "/image", None) => {
+            send(
+                "No game in this chat. Send /new to start one.".to_string(),
+                None,
+            )
+            .await;
+        }
+        ("/board", Some((game_id, game_state))) => {
+            send(
+                board_text(&game_state),
+                Some(keyboard(game_id, &game_state)),
+            )
+            .await;
+        }
+        ("/image", Some((game_id, game_state))) => {
+            let Some(base) = &state.public_url else {
+                send(
+                    "Pictures of the board aren't available on this server.".to_string(),
+                    None,
+                )
+                .await;
+                return;
+            };
+            // The version keeps Telegram from showing a cached picture.
+            let photo = format!(
+                "{base}/api/v1/games/{game_id}/board.png?v={}",
+                game_state.version
+            );
+            let params = json!({
+                "chat_id": chat_id,
+                "photo": photo,
+                "caption": game_state.summary(),
+            });
+            telegram.call("sendPhoto", params).await;
+        }
+        ("/help", _) => send(USAGE.to_string(), None).await,
+        // Other messages in group chats aren't for the bot.
+        _ => {}
+    }
+}
+
+async fn press(state: &AppState, query: CallbackQuery) {
+    let telegram = &state.telegram;
+    let pressed = query.data.as_deref().and_then(parse_button_data);
+    let notice = match (pressed, &query.message) {
+        (Some((game_id, player_move)), Some(message))
+            if telegram
+                .sessions
+                .get(&message.chat.id)
+                .is_some_and(|session| *session == game_id) =>
+        {
+            let move_request = MoveRequest {
+                player_move,
+                expected_version: None,
+            };
+            match crate::play_move(state, game_id, move_request, None, None).await {
+                Ok(game_state) => {
+                    let params = json!({
+                        "chat_id": message.chat.id,
+                        "message_id": message.message_id,
+                        "text": board_text(&game_state),
+                        "parse_mode": "HTML",
+                        "reply_markup": keyboard(game_id, &game_state),
+                    });
+                    telegram.call("editMessageText", params).await;
+                    None
+                }
+                Err(e) => Some(e.to_string()),
+            }
+        }
+        _ => Some("That game is no longer this chat's. Send /new to start one.".to_string()),
+    };
+    // Stops the button's spinner, with a note if the move wasn't played.
+    let mut params = json!({ "callback_query_id": query.id });
+    if let Some(notice) = notice {
+        params["text"] = Value::String(notice);
+    }
+    telegram.call("answerCallbackQuery", params).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::try_move;
+
+    #[test]
+    fn test_updates_need_the_secret_and_boards_render_as_keyboards() {
+        let telegram = Telegram::new(&TelegramConfig {
+            bot_token: Some("123:abc".to_string()),
+            webhook_secret: Some("s3cret".to_string()),
+        });
+        assert!(telegram.verify(Some("s3cret")).is_ok());
+        assert!(telegram.verify(Some("guess")).is_err());
+        assert!(telegram.verify(None).is_err());
+        assert!(
+            Telegram::new(&TelegramConfig::default())
+                .verify(Some(""))
+                .is_err()
+        );
+        assert_eq!(command("/new@laika_bot please"), "/new");
+
+        let mut game_state = GameState::default();
+        try_move(&mut game_state, Player::X, PlayerMove { row: 2, col: 0 }).unwrap();
+        let game_id = Uuid::new_v4();
+        let keyboard = keyboard(game_id, &game_state);
+        let pressed = &keyboard["inline_keyboard"][2][0];
+        assert_eq!(pressed["text"], "X");
+        let data = pressed["callback_data"].as_str().unwrap();
+        assert!(data.len() <= 64);
+        assert_eq!(
+            parse_button_data(data),
+            Some((game_id, PlayerMove { row: 2, col: 0 }))
+        );
+        assert_eq!(
+            board_text(&game_state),
+            "<pre>· · ·\n· · ·\nX · ·</pre>\nO to move, 1 move played"
+        );
+    }
+}
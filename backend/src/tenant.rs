@@ -0,0 +1,262 @@
+//! Tenants: isolated groups of users, such as different frontends or
+//! classrooms, sharing one server.
+//!
+//! A request belongs to a tenant when it comes in under `/t/{tenant}` (so
+//! `/t/room-101/api/v1/newgame` is `/api/v1/newgame` for tenant `room-101`)
+//! or carries an API key assigned to the tenant. Games against the AI record
+//! the tenant they were started in and can only be reached from it, quotas
+//! are counted separately per tenant, and each tenant can have its own AI
+//! and quota defaults under `[tenants.<name>]`. Games started by shared
+//! features, such as lobbies and tournaments, belong to no tenant and stay
+//! reachable from everywhere.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{FromRef, FromRequestParts, OriginalUri, Request, State},
+    http::{StatusCode, Uri, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{API_KEY_HEADER, Error, cluster, config::TenantConfig, state::AppState};
+
+/// Path prefix that puts a request in a tenant.
+const PATH_PREFIX: &str = "/t/";
+
+/// The tenant named in the request's path, as found by `strip_prefix`.
+#[derive(Debug, Clone)]
+struct PathTenant(String);
+
+#[derive(Debug, Default)]
+pub struct Tenants {
+    config: BTreeMap<String, TenantConfig>,
+}
+
+impl Tenants {
+    pub fn new(config: BTreeMap<String, TenantConfig>) -> Self {
+        Self { config }
+    }
+
+    /// The settings of the tenant called `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&TenantConfig> {
+        self.config.get(name)
+    }
+}
+
+/// The tenant a request belongs to; `None` outside any tenant.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Tenant(pub Option<String>);
+
+impl Tenant {
+    /// The tenant's settings; `None` outside any tenant.
+    pub fn config<'a>(&self, state: &'a AppState) -> Option<&'a TenantConfig> {
+        state.tenants.get(self.0.as_deref()?)
+    }
+}
+
+impl<S> FromRequestParts<S> for Tenant
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    /// The tenant from the path or the API key. A key belonging to another
+    /// tenant than the path names is refused; an unknown key is left for
+    /// the quota checks to reject.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        let from_path = parts
+            .extensions
+            .get::<PathTenant>()
+            .map(|PathTenant(name)| name.clone());
+        let from_key = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|key| state.limits.api_key_tenant(key));
+        match (from_path, from_key) {
+            (Some(path), Some(key)) if path != key => {
+                Err(Error::Forbidden("This API key belongs to another tenant"))
+            }
+            (path, key) => Ok(Tenant(path.or(key))),
+        }
+    }
+}
+
+/// Middleware, run before routing, that takes `/t/{tenant}` off the front
+/// of the path and remembers the tenant for the `Tenant` extractor. Unknown
+/// tenants get `404 Not Found`.
+pub async fn strip_prefix(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let sent = request.uri().clone();
+    let Some((name, path)) = split_prefix(sent.path()) else {
+        return next.run(request).await;
+    };
+    if state.tenants.get(name).is_none() {
+        return Error::TenantNotFound(name.to_string()).into_response();
+    }
+    let path_and_query = match sent.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    let mut parts = sent.clone().into_parts();
+    parts.path_and_query = match path_and_query.parse() {
+        Ok(path_and_query) => Some(path_and_query),
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let Ok(uri) = Uri::from_parts(parts) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    // Keep the path as sent, e.g. for redirects to another instance.
+    request.extensions_mut().insert(OriginalUri(sent.clone()));
+    request
+        .extensions_mut()
+        .insert(PathTenant(name.to_string()));
+    *request.uri_mut() = uri;
+    next.run(request).await
+}
+
+/// Splits `/t/{tenant}/rest` into the tenant and `/rest`.
+fn split_prefix(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(PATH_PREFIX)?;
+    Some(match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    })
+}
+
+/// Middleware that hides a tenant's games from everyone outside it, as if
+/// they didn't exist. The admin API sees every tenant.
+pub async fn isolate(
+    State(state): State<AppState>,
+    tenant: Result<Tenant, Error>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if path.starts_with("/admin/") {
+        return next.run(request).await;
+    }
+    let Some(game_id) = cluster::game_id_in(path) else {
+        return next.run(request).await;
+    };
+    let tenant = match tenant {
+        Ok(Tenant(tenant)) => tenant,
+        Err(e) => return e.into_response(),
+    };
+    let game = match state.game(&game_id) {
+        Some(game) => Some(game),
+        // In a cluster the game may only be in the store so far. It has to
+        // be loaded to know its tenant, before anything serves or changes it.
+        None => match cluster::refresh(&state, game_id).await {
+            Ok(()) => state.game(&game_id),
+            Err(e) => return e.into_response(),
+        },
+    };
+    if let Some(game) = game {
+        let owner = game.lock().await.tenant.clone();
+        if owner.is_some() && owner != tenant {
+            return Error::GameNotFound(game_id).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use async_trait::async_trait;
+    use axum::{Router, middleware};
+    use chrono::{DateTime, Utc};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        config::{AdminConfig, ClusterConfig},
+        game::GameState,
+        state::{GameEntry, GameRegistry},
+        store::{GameStore, StoreError},
+    };
+
+    /// A shared store holding one game of tenant `room-101`, saved by
+    /// another instance.
+    struct SharedStore(Uuid);
+
+    #[async_trait]
+    impl GameStore for SharedStore {
+        async fn load(&self, _finished_since: DateTime<Utc>) -> Result<GameRegistry, StoreError> {
+            Ok(GameRegistry::new())
+        }
+
+        async fn load_game(&self, id: Uuid) -> Result<Option<GameEntry>, StoreError> {
+            let mut entry = GameEntry::new(GameState::default());
+            entry.tenant = Some("room-101".to_string());
+            Ok((id == self.0).then_some(entry))
+        }
+    }
+
+    /// Sends `request` and returns the status code of the response.
+    async fn status(addr: SocketAddr, request: &str) -> u16 {
+        let mut connection = tokio::net::TcpStream::connect(addr).await.unwrap();
+        connection.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        connection.read_to_string(&mut response).await.unwrap();
+        response[9..12].parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_games_only_in_the_store_stay_in_their_tenant() {
+        let game_id = Uuid::new_v4();
+        let tenants =
+            ["room-101", "room-102"].map(|name| (name.to_string(), TenantConfig::default()));
+        let state = AppState::new(GameRegistry::new(), Arc::new(SharedStore(game_id)))
+            .with_tenants(BTreeMap::from(tenants))
+            .with_cluster(ClusterConfig {
+                advertise_url: Some("http://me:3000".to_string()),
+                ..ClusterConfig::default()
+            });
+        let app = Router::new()
+            .fallback_service(crate::api::router(state.clone(), &AdminConfig::default()))
+            .layer(middleware::from_fn_with_state(state.clone(), strip_prefix));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let get = |tenant: &str| {
+            format!(
+                "GET /t/{tenant}/api/v1/games/{game_id} HTTP/1.1\r\n\
+                 Host: localhost\r\nConnection: close\r\n\r\n"
+            )
+        };
+        let body = r#"{"row":0,"col":0}"#;
+        let play = |tenant: &str| {
+            format!(
+                "POST /t/{tenant}/api/v1/games/{game_id}/move HTTP/1.1\r\n\
+                 Host: localhost\r\nConnection: close\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+        };
+        assert_eq!(status(addr, &get("room-102")).await, 404);
+        assert_eq!(status(addr, &play("room-102")).await, 404);
+        assert_eq!(status(addr, &get("room-101")).await, 200);
+    }
+
+    #[test]
+    fn test_tenant_prefix_is_split_off_the_path() {
+        assert_eq!(
+            split_prefix("/t/room-101/api/v1/newgame"),
+            Some(("room-101", "/api/v1/newgame"))
+        );
+        assert_eq!(split_prefix("/t/room-101"), Some(("room-101", "/")));
+        assert_eq!(split_prefix("/api/v1/newgame"), None);
+        assert_eq!(split_prefix("/tournaments"), None);
+    }
+}
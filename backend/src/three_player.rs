@@ -0,0 +1,384 @@
+//! Three-player tic-tac-toe: X, O, and Y take turns in that order on a
+//! bigger board, and the first to complete a line wins.
+//!
+//! `game::Player` is a two-sided enum with `opponent()` baked into the engine
+//! and the wire formats, so three-player games have their own `Mark` and
+//! board, and reuse only the line tables from `game::Rules`. They are played
+//! between people, each holding a seat token, and kept in memory only;
+//! finished ones are purged on the same TTL as regular games.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::{MAX_SIDE, PlayerMove, Rules},
+    state::{AppState, Seat, new_token},
+};
+
+/// Players in a game, and so the number of marks.
+pub const PLAYERS: usize = 3;
+
+/// The smallest board that leaves room for three players.
+pub const MIN_SIDE: usize = 4;
+
+const SYMBOLS: [char; PLAYERS] = ['X', 'O', 'Y'];
+
+pub type SharedThreePlayer = Arc<Mutex<ThreePlayerGame>>;
+
+/// One of the three marks, by turn order: 0 moves first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Mark(u8);
+
+impl Mark {
+    pub fn new(index: usize) -> Option<Self> {
+        (index < PLAYERS).then_some(Mark(index as u8))
+    }
+
+    pub fn index(self) -> usize {
+        usize::from(self.0)
+    }
+
+    pub fn symbol(self) -> char {
+        SYMBOLS[self.index()]
+    }
+
+    pub fn all() -> impl Iterator<Item = Mark> {
+        (0..PLAYERS as u8).map(Mark)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThreePlayerStatus {
+    /// Waiting for every seat to be taken.
+    Open,
+    InProgress,
+    Draw,
+    Win(Mark),
+}
+
+/// A board with one cell mask per mark, using the same bit layout as
+/// `game::Board`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreePlayerBoard {
+    pub rows: usize,
+    pub cols: usize,
+    pub marks: [u64; PLAYERS],
+}
+
+impl ThreePlayerBoard {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            marks: [0; PLAYERS],
+        }
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<Mark> {
+        let bit = 1 << (row * self.cols + col);
+        Mark::all().find(|mark| self.marks[mark.index()] & bit != 0)
+    }
+
+    fn occupied(&self) -> u64 {
+        self.marks.iter().fold(0, |occupied, mask| occupied | mask)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.occupied().count_ones() as usize == self.rows * self.cols
+    }
+
+    /// The cells as rows of marks, `None` for empty.
+    pub fn cells(&self) -> Vec<Vec<Option<Mark>>> {
+        (0..self.rows)
+            .map(|row| (0..self.cols).map(|col| self.get(row, col)).collect())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreePlayerGame {
+    pub id: Uuid,
+    pub board: ThreePlayerBoard,
+    pub rules: Rules,
+    /// Taken seats, in mark order.
+    pub seats: Vec<Seat>,
+    pub moves: Vec<PlayerMove>,
+    pub status: ThreePlayerStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl ThreePlayerGame {
+    /// A game on a `rows` x `cols` board with `creator` in the first seat.
+    pub fn new(rows: usize, cols: usize, rules: Rules, creator: Seat) -> Result<Self, Error> {
+        let side = MIN_SIDE..=MAX_SIDE;
+        if !side.contains(&rows) || !side.contains(&cols) {
+            return Err(Error::BadRequest("rows and cols must be between 4 and 8"));
+        }
+        if !(3..=rows.max(cols)).contains(&rules.win_length) {
+            return Err(Error::BadRequest(
+                "win_length must be at least 3 and fit on the board",
+            ));
+        }
+        Ok(Self {
+            id: Uuid::new_v4(),
+            board: ThreePlayerBoard::new(rows, cols),
+            rules,
+            seats: vec![creator],
+            moves: Vec::new(),
+            status: ThreePlayerStatus::Open,
+            created_at: Utc::now(),
+            finished_at: None,
+        })
+    }
+
+    /// Round-robin: X, O, Y, X, ...
+    pub fn to_play(&self) -> Mark {
+        Mark((self.moves.len() % PLAYERS) as u8)
+    }
+
+    /// The mark `token` plays, if it belongs to a seat.
+    pub fn seat_of(&self, token: &str) -> Option<Mark> {
+        self.seats
+            .iter()
+            .position(|seat| seat.token == token)
+            .and_then(Mark::new)
+    }
+
+    /// Seats a player in the next free seat. The game starts once the last
+    /// one is taken.
+    pub fn join(&mut self, seat: Seat) -> Result<Mark, Error> {
+        if self.status != ThreePlayerStatus::Open {
+            return Err(Error::BadRequest("All seats are taken"));
+        }
+        self.seats.push(seat);
+        if self.seats.len() == PLAYERS {
+            self.status = ThreePlayerStatus::InProgress;
+        }
+        Ok(Mark::new(self.seats.len() - 1).expect("an open game has a free seat"))
+    }
+
+    /// Plays a move for `mark`, finishing the game on a line or a full board.
+    pub fn play(&mut self, mark: Mark, player_move: PlayerMove) -> Result<(), Error> {
+        match self.status {
+            ThreePlayerStatus::Open => {
+                return Err(Error::InvalidMove("Waiting for players to join"));
+            }
+            ThreePlayerStatus::InProgress => {}
+            _ => return Err(Error::GameOver),
+        }
+        if self.to_play() != mark {
+            return Err(Error::NotYourTurn);
+        }
+        let PlayerMove { row, col } = player_move;
+        if row >= self.board.rows || col >= self.board.cols {
+            return Err(Error::OutOfBounds { row, col });
+        }
+        if self.board.get(row, col).is_some() {
+            return Err(Error::CellOccupied);
+        }
+        self.board.marks[mark.index()] |= 1 << (row * self.board.cols + col);
+        self.moves.push(player_move);
+
+        let (rows, cols) = (self.board.rows, self.board.cols);
+        if self
+            .rules
+            .has_line(rows, cols, self.board.marks[mark.index()])
+        {
+            self.status = ThreePlayerStatus::Win(mark);
+        } else if self.board.is_full() {
+            self.status = ThreePlayerStatus::Draw;
+        }
+        if !matches!(self.status, ThreePlayerStatus::InProgress) {
+            self.finished_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+}
+
+/// Removes finished three-player games whose TTL has elapsed. Returns the
+/// number removed. Games that are locked are in use and are left for the
+/// next sweep.
+pub fn purge_expired(
+    games: &DashMap<Uuid, SharedThreePlayer>,
+    now: DateTime<Utc>,
+    ttl: TimeDelta,
+) -> usize {
+    let before = games.len();
+    games.retain(|_, game| {
+        game.try_lock().map_or(true, |game| {
+            game.finished_at
+                .is_none_or(|finished_at| now - finished_at < ttl)
+        })
+    });
+    before - games.len()
+}
+
+fn shared_game(state: &AppState, game_id: Uuid) -> Result<SharedThreePlayer, Error> {
+    state
+        .three_player
+        .get(&game_id)
+        .map(|game| game.clone())
+        .ok_or(Error::GameNotFound(game_id))
+}
+
+// --- Operations ---
+
+/// Opens a game with `name` as X. Returns the game and X's seat token.
+pub async fn create(
+    state: &AppState,
+    name: String,
+    rows: usize,
+    cols: usize,
+    rules: Rules,
+) -> Result<(ThreePlayerGame, String), Error> {
+    state.accepting_games()?;
+    let token = new_token();
+    let game = ThreePlayerGame::new(
+        rows,
+        cols,
+        rules,
+        Seat {
+            name,
+            token: token.clone(),
+        },
+    )?;
+    state
+        .three_player
+        .insert(game.id, Arc::new(Mutex::new(game.clone())));
+    log::info!("Created three-player game {} on {}x{}", game.id, rows, cols);
+    Ok((game, token))
+}
+
+/// Takes the next free seat. Returns the game, the seat's mark, and its token.
+pub async fn join(
+    state: &AppState,
+    game_id: Uuid,
+    name: String,
+) -> Result<(ThreePlayerGame, Mark, String), Error> {
+    let game = shared_game(state, game_id)?;
+    let mut game = game.lock().await;
+    let token = new_token();
+    let mark = game.join(Seat {
+        name,
+        token: token.clone(),
+    })?;
+    if game.status == ThreePlayerStatus::InProgress {
+        log::info!("Three-player game {} started", game_id);
+    }
+    Ok((game.clone(), mark, token))
+}
+
+/// Plays a move for whoever holds `seat_token`.
+pub async fn play(
+    state: &AppState,
+    game_id: Uuid,
+    player_move: PlayerMove,
+    seat_token: Option<&str>,
+) -> Result<ThreePlayerGame, Error> {
+    let game = shared_game(state, game_id)?;
+    let mut game = game.lock().await;
+    let mark = seat_token
+        .and_then(|token| game.seat_of(token))
+        .ok_or(Error::Forbidden("A valid Seat-Token header is required"))?;
+    game.play(mark, player_move)?;
+    if let ThreePlayerStatus::Win(mark) = game.status {
+        log::info!("Three-player game {} won by {}", game_id, mark.symbol());
+    }
+    Ok(game.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seat(name: &str) -> Seat {
+        Seat {
+            name: name.to_string(),
+            token: new_token(),
+        }
+    }
+
+    fn started(rows: usize, cols: usize, win_length: usize) -> ThreePlayerGame {
+        let rules = Rules {
+            win_length,
+            ..Rules::default()
+        };
+        let mut game = ThreePlayerGame::new(rows, cols, rules, seat("a")).unwrap();
+        game.join(seat("b")).unwrap();
+        game.join(seat("c")).unwrap();
+        game
+    }
+
+    #[test]
+    fn test_turns_rotate_through_all_three_marks() {
+        let mut game = ThreePlayerGame::new(5, 5, Rules::default(), seat("a")).unwrap();
+        assert!(matches!(
+            game.play(Mark(0), PlayerMove { row: 0, col: 0 }),
+            Err(Error::InvalidMove(_))
+        ));
+        assert_eq!(game.join(seat("b")).unwrap(), Mark(1));
+        assert_eq!(game.join(seat("c")).unwrap(), Mark(2));
+        assert_eq!(game.status, ThreePlayerStatus::InProgress);
+        assert!(game.join(seat("d")).is_err());
+
+        let order: Vec<char> = (0..4)
+            .map(|col| {
+                let mark = game.to_play();
+                game.play(mark, PlayerMove { row: 0, col }).unwrap();
+                mark.symbol()
+            })
+            .collect();
+        assert_eq!(order, ['X', 'O', 'Y', 'X']);
+        assert!(matches!(
+            game.play(Mark(0), PlayerMove { row: 1, col: 0 }),
+            Err(Error::NotYourTurn)
+        ));
+    }
+
+    #[test]
+    fn test_the_third_mark_can_win() {
+        let mut game = started(4, 4, 3);
+        let moves = [
+            (0, 0),
+            (1, 0),
+            (3, 0),
+            (0, 2),
+            (1, 2),
+            (3, 1),
+            (2, 3),
+            (2, 1),
+            (3, 2),
+        ];
+        for (turn, &(row, col)) in moves.iter().enumerate() {
+            game.play(game.to_play(), PlayerMove { row, col }).unwrap();
+            assert_eq!(game.finished_at.is_some(), turn == moves.len() - 1);
+        }
+        assert_eq!(game.status, ThreePlayerStatus::Win(Mark(2)));
+    }
+
+    #[test]
+    fn test_a_full_board_without_a_line_is_a_draw() {
+        let grid = ["XOXO", "YYOX", "XOYY", "XOXY"];
+        let mut cells: Vec<Vec<PlayerMove>> = vec![Vec::new(); PLAYERS];
+        for (row, line) in grid.iter().enumerate() {
+            for (col, symbol) in line.chars().enumerate() {
+                let mark = SYMBOLS.iter().position(|&s| s == symbol).unwrap();
+                cells[mark].push(PlayerMove { row, col });
+            }
+        }
+        let mut game = started(4, 4, 4);
+        while game.status == ThreePlayerStatus::InProgress {
+            let mark = game.to_play();
+            let player_move = cells[mark.index()].pop().unwrap();
+            game.play(mark, player_move).unwrap();
+        }
+        assert_eq!(game.status, ThreePlayerStatus::Draw);
+    }
+}
@@ -0,0 +1,82 @@
+//! Native TLS termination, so small deployments can serve HTTPS without a
+//! reverse proxy.
+//!
+//! Certificates are reloaded in place when the files on disk change (e.g.
+//! after a certbot renewal), without dropping existing connections.
+
+use std::{io, path::Path, sync::Arc, time::SystemTime};
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+use crate::config::TlsConfig;
+
+/// Installs the process-wide rustls crypto provider. Must run before any TLS
+/// configuration is built.
+pub fn install_crypto_provider() {
+    // Fails only if a provider is already installed, which is fine.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+}
+
+/// Loads the certificate chain and private key named in `tls`.
+pub async fn load(tls: &TlsConfig) -> io::Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(
+        server_config(tls).await?,
+    )))
+}
+
+/// Builds the server configuration from the certificate chain and private
+/// key, offering HTTP/2 during the handshake only if `tls.http2` is set.
+async fn server_config(tls: &TlsConfig) -> io::Result<ServerConfig> {
+    let cert = tokio::fs::read(&tls.cert_path).await?;
+    let key = tokio::fs::read(&tls.key_path).await?;
+    let cert_chain = CertificateDer::pem_slice_iter(&cert)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::other)?;
+    let key = PrivateKeyDer::from_pem_slice(&key).map_err(io::Error::other)?;
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(io::Error::other)?;
+    server_config.alpn_protocols = if tls.http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(server_config)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Background task that polls the certificate and key files and reloads
+/// `rustls_config` whenever either changes. A failed reload keeps serving the
+/// previous certificate.
+pub async fn reload_on_change(rustls_config: RustlsConfig, tls: TlsConfig) {
+    let mut last_seen = (modified(&tls.cert_path), modified(&tls.key_path));
+    let mut interval = tokio::time::interval(tls.reload_interval());
+    loop {
+        interval.tick().await;
+        let current = (modified(&tls.cert_path), modified(&tls.key_path));
+        if current == last_seen {
+            continue;
+        }
+        last_seen = current;
+
+        match server_config(&tls).await {
+            Ok(server_config) => {
+                rustls_config.reload_from_config(Arc::new(server_config));
+                log::info!("Reloaded TLS certificate from {}", tls.cert_path.display());
+            }
+            Err(e) => log::error!(
+                "Failed to reload TLS certificate from {}: {}",
+                tls.cert_path.display(),
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Single-elimination brackets.
+//!
+//! Entrants are seeded in registration order. The bracket is padded to a
+//! power of two with byes, which go to the top seeds, and laid out so the top
+//! two seeds can only meet in the final.
+
+use uuid::Uuid;
+
+use super::Match;
+
+/// Seed numbers (1-based) in bracket order for a bracket of `size` slots,
+/// e.g. `[1, 8, 4, 5, 2, 7, 3, 6]` for eight. Adjacent pairs play each other.
+fn seed_order(size: usize) -> Vec<usize> {
+    let mut order = vec![1];
+    while order.len() < size {
+        let slots = order.len() * 2;
+        order = order
+            .iter()
+            .flat_map(|&seed| [seed, slots + 1 - seed])
+            .collect();
+    }
+    order
+}
+
+/// Pairs up the entrants for the first round.
+pub fn first_round(entrants: &[Uuid]) -> Vec<Match> {
+    let size = entrants.len().next_power_of_two().max(2);
+    seed_order(size)
+        .chunks(2)
+        .map(|pair| {
+            let x = entrants[pair[0] - 1];
+            let o = entrants.get(pair[1] - 1).copied();
+            Match::new(x, o)
+        })
+        .collect()
+}
+
+/// Pairs the winners of a finished round, or returns `None` if that round
+/// was the final.
+pub fn next_round(round: &[Match]) -> Option<Vec<Match>> {
+    if round.len() < 2 {
+        return None;
+    }
+    let round = round
+        .chunks(2)
+        .map(|pair| {
+            let first = pair[0].winner.expect("round is finished");
+            let second = pair[1].winner.expect("round is finished");
+            Match::new(first, Some(second))
+        })
+        .collect();
+    Some(round)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_order_keeps_top_seeds_apart() {
+        assert_eq!(seed_order(2), [1, 2]);
+        assert_eq!(seed_order(8), [1, 8, 4, 5, 2, 7, 3, 6]);
+    }
+
+    #[test]
+    fn test_byes_go_to_the_top_seeds() {
+        let entrants: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        let round = first_round(&entrants);
+        assert_eq!(round.len(), 4);
+
+        let byes: Vec<Uuid> = round
+            .iter()
+            .filter(|m| m.o.is_none())
+            .map(|m| m.x)
+            .collect();
+        assert_eq!(byes, [entrants[0], entrants[1], entrants[2]]);
+        assert!(
+            round
+                .iter()
+                .filter(|m| m.o.is_none())
+                .all(|m| m.winner == Some(m.x))
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! Tournaments: players register, get paired into player-vs-player games,
+//! and advance as those games finish. Tournaments are either a
+//! single-elimination bracket or a fixed number of Swiss rounds.
+//!
+//! The bookkeeping here is independent of HTTP and of the game registry:
+//! `Tournament` hands out `Pairing`s, the caller creates a game for each one
+//! and attaches it, and finished games are fed back in via `record_result`.
+
+use std::{fmt, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::{GameStatus, Player},
+    state::{AppState, GameEntry, Seat, new_token},
+};
+
+pub mod bracket;
+pub mod swiss;
+
+/// Registration closes at this many entrants.
+pub const MAX_ENTRANTS: usize = 64;
+
+/// Draws are replayed with colors swapped; after this many games in a match
+/// the higher seed advances.
+pub const MAX_GAMES_PER_MATCH: usize = 3;
+
+/// Swiss tournaments run at most this many rounds.
+pub const MAX_SWISS_ROUNDS: usize = 12;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TournamentFormat {
+    /// Losers are out; draws are replayed.
+    #[default]
+    SingleElimination,
+    /// Everyone plays every round, one game per match, and the standings
+    /// after the last round decide the winner.
+    Swiss { rounds: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentStatus {
+    Registration,
+    InProgress,
+    Finished,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entrant {
+    pub id: Uuid,
+    pub name: String,
+    /// Seat token for every game this entrant plays in the tournament.
+    pub token: String,
+}
+
+/// Two entrants playing until one of them advances. `x` has X in the first
+/// game and colors alternate after that. A match without `o` is a bye.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Match {
+    pub x: Uuid,
+    pub o: Option<Uuid>,
+    pub games: Vec<Uuid>,
+    pub winner: Option<Uuid>,
+    /// Swiss matches can end drawn; elimination matches never do.
+    #[serde(default)]
+    pub draw: bool,
+}
+
+impl Match {
+    pub fn new(x: Uuid, o: Option<Uuid>) -> Self {
+        Self {
+            x,
+            o,
+            games: Vec::new(),
+            // A bye advances without playing.
+            winner: if o.is_none() { Some(x) } else { None },
+            draw: false,
+        }
+    }
+
+    pub fn is_decided(&self) -> bool {
+        self.winner.is_some() || self.draw
+    }
+
+    /// Who plays X and O in the next game of this match.
+    fn next_sides(&self) -> Option<(Uuid, Uuid)> {
+        let o = self.o?;
+        Some(if self.games.len().is_multiple_of(2) {
+            (self.x, o)
+        } else {
+            (o, self.x)
+        })
+    }
+}
+
+/// A game the tournament needs created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pairing {
+    pub round: usize,
+    pub index: usize,
+    pub x: Uuid,
+    pub o: Uuid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TournamentError {
+    RegistrationClosed,
+    Full,
+    DuplicateName,
+    NotEnoughEntrants,
+    TooManyRounds,
+    UnknownGame,
+}
+
+impl fmt::Display for TournamentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            TournamentError::RegistrationClosed => "Registration for this tournament is closed",
+            TournamentError::Full => "The tournament is full",
+            TournamentError::DuplicateName => "That name is already registered",
+            TournamentError::NotEnoughEntrants => "At least two entrants are needed to start",
+            TournamentError::TooManyRounds => {
+                "More rounds than it takes for everyone to meet everyone"
+            }
+            TournamentError::UnknownGame => "Game is not part of this tournament",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for TournamentError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tournament {
+    pub id: Uuid,
+    pub name: String,
+    /// Lets whoever created the tournament start it.
+    pub organizer_token: String,
+    pub status: TournamentStatus,
+    #[serde(default)]
+    pub format: TournamentFormat,
+    /// In seed order.
+    pub entrants: Vec<Entrant>,
+    pub rounds: Vec<Vec<Match>>,
+    pub winner: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Tournament {
+    pub fn new(name: String, format: TournamentFormat) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            organizer_token: new_token(),
+            status: TournamentStatus::Registration,
+            format,
+            entrants: Vec::new(),
+            rounds: Vec::new(),
+            winner: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn entrant(&self, id: Uuid) -> Option<&Entrant> {
+        self.entrants.iter().find(|entrant| entrant.id == id)
+    }
+
+    fn entrant_ids(&self) -> Vec<Uuid> {
+        self.entrants.iter().map(|entrant| entrant.id).collect()
+    }
+
+    /// Swiss standings, best first. `None` for elimination tournaments.
+    pub fn standings(&self) -> Option<Vec<swiss::Standing>> {
+        match self.format {
+            TournamentFormat::SingleElimination => None,
+            TournamentFormat::Swiss { .. } => {
+                Some(swiss::standings(&self.entrant_ids(), &self.rounds))
+            }
+        }
+    }
+
+    fn seed(&self, id: Uuid) -> usize {
+        self.entrants
+            .iter()
+            .position(|entrant| entrant.id == id)
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn register(&mut self, name: String) -> Result<&Entrant, TournamentError> {
+        if self.status != TournamentStatus::Registration {
+            return Err(TournamentError::RegistrationClosed);
+        }
+        if self.entrants.len() >= MAX_ENTRANTS {
+            return Err(TournamentError::Full);
+        }
+        if self.entrants.iter().any(|entrant| entrant.name == name) {
+            return Err(TournamentError::DuplicateName);
+        }
+        self.entrants.push(Entrant {
+            id: Uuid::new_v4(),
+            name,
+            token: new_token(),
+        });
+        Ok(self.entrants.last().expect("just pushed"))
+    }
+
+    /// Closes registration and pairs the first round.
+    pub fn start(&mut self) -> Result<Vec<Pairing>, TournamentError> {
+        if self.status != TournamentStatus::Registration {
+            return Err(TournamentError::RegistrationClosed);
+        }
+        if self.entrants.len() < 2 {
+            return Err(TournamentError::NotEnoughEntrants);
+        }
+        let ids = self.entrant_ids();
+        let first_round = match self.format {
+            TournamentFormat::SingleElimination => bracket::first_round(&ids),
+            // With an odd field everyone also sits out once.
+            TournamentFormat::Swiss { rounds } if rounds > ids.len() - 1 + ids.len() % 2 => {
+                return Err(TournamentError::TooManyRounds);
+            }
+            TournamentFormat::Swiss { .. } => swiss::pair_round(&ids, &[]),
+        };
+        self.status = TournamentStatus::InProgress;
+        self.rounds.push(first_round);
+        Ok(self.pending_pairings())
+    }
+
+    /// Matches in the current round that are undecided and have no game
+    /// underway.
+    fn pending_pairings(&self) -> Vec<Pairing> {
+        let round = self.rounds.len() - 1;
+        self.rounds[round]
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| !m.is_decided())
+            .filter_map(|(index, m)| {
+                let (x, o) = m.next_sides()?;
+                Some(Pairing { round, index, x, o })
+            })
+            .collect()
+    }
+
+    pub fn attach_game(&mut self, pairing: &Pairing, game_id: Uuid) {
+        self.rounds[pairing.round][pairing.index]
+            .games
+            .push(game_id);
+    }
+
+    /// Records the result of a finished game and returns the games that need
+    /// creating as a consequence: an elimination rematch after a draw, or the
+    /// next round.
+    pub fn record_result(
+        &mut self,
+        game_id: Uuid,
+        status: GameStatus,
+    ) -> Result<Vec<Pairing>, TournamentError> {
+        let round = self.rounds.len() - 1;
+        let (index, m) = self.rounds[round]
+            .iter()
+            .enumerate()
+            .find(|(_, m)| m.games.last() == Some(&game_id))
+            .ok_or(TournamentError::UnknownGame)?;
+        if m.is_decided() {
+            // Already recorded.
+            return Ok(Vec::new());
+        }
+
+        // The sides of the game that just finished.
+        let o = m.o.expect("byes have no games");
+        let (x_player, o_player) = if !m.games.len().is_multiple_of(2) {
+            (m.x, o)
+        } else {
+            (o, m.x)
+        };
+        let swiss = matches!(self.format, TournamentFormat::Swiss { .. });
+        let winner = match status {
+            GameStatus::InProgress => return Ok(Vec::new()),
+            GameStatus::Win(Player::X) => Some(x_player),
+            GameStatus::Win(Player::O) => Some(o_player),
+            GameStatus::Draw if swiss => None,
+            GameStatus::Draw if m.games.len() >= MAX_GAMES_PER_MATCH => {
+                Some(if self.seed(m.x) < self.seed(o) {
+                    m.x
+                } else {
+                    o
+                })
+            }
+            GameStatus::Draw => None,
+        };
+        match winner {
+            Some(winner) => self.rounds[round][index].winner = Some(winner),
+            None if swiss => self.rounds[round][index].draw = true,
+            None => {
+                let (x, o) = m.next_sides().expect("not a bye");
+                return Ok(vec![Pairing { round, index, x, o }]);
+            }
+        }
+
+        if !self.rounds[round].iter().all(Match::is_decided) {
+            return Ok(Vec::new());
+        }
+        let next = match self.format {
+            TournamentFormat::SingleElimination => bracket::next_round(&self.rounds[round]),
+            TournamentFormat::Swiss { rounds } if self.rounds.len() < rounds => {
+                Some(swiss::pair_round(&self.entrant_ids(), &self.rounds))
+            }
+            TournamentFormat::Swiss { .. } => None,
+        };
+        match next {
+            Some(next) => {
+                self.rounds.push(next);
+                Ok(self.pending_pairings())
+            }
+            None => {
+                self.finish();
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.winner = match self.standings() {
+            Some(standings) => standings.first().map(|standing| standing.entrant),
+            None => self.rounds.last().and_then(|round| round[0].winner),
+        };
+        self.status = TournamentStatus::Finished;
+    }
+}
+
+// --- Operations ---
+
+/// A tournament, independently lockable.
+pub type SharedTournament = Arc<Mutex<Tournament>>;
+
+/// Creates a game for each pairing and attaches it to the tournament.
+async fn create_games(
+    state: &AppState,
+    tournament: &mut Tournament,
+    pairings: Vec<Pairing>,
+) -> Result<(), Error> {
+    for pairing in pairings {
+        let seat = |id| {
+            let entrant = tournament
+                .entrant(id)
+                .expect("paired entrants are registered");
+            Seat {
+                name: entrant.name.clone(),
+                token: entrant.token.clone(),
+            }
+        };
+        let (x, o) = (seat(pairing.x), seat(pairing.o));
+        let mut entry = GameEntry::pvp(x, o);
+        entry.tournament_id = Some(tournament.id);
+        let game_id = crate::create_pvp_game(state, entry).await?;
+        tournament.attach_game(&pairing, game_id);
+    }
+    Ok(())
+}
+
+pub async fn save(state: &AppState, tournament: &Tournament) -> Result<(), Error> {
+    state
+        .store
+        .save_tournament(tournament)
+        .await
+        .map_err(Error::Storage)
+}
+
+/// Closes registration and creates the first round's games.
+pub async fn start(state: &AppState, tournament: &mut Tournament) -> Result<(), Error> {
+    let pairings = tournament.start().map_err(Error::Tournament)?;
+    create_games(state, tournament, pairings).await?;
+    save(state, tournament).await?;
+    log::info!(
+        "Started tournament {} with {} entrants",
+        tournament.id,
+        tournament.entrants.len()
+    );
+    Ok(())
+}
+
+/// Feeds a finished game back into its tournament, creating whatever games
+/// that unlocks.
+pub async fn record_result(
+    state: &AppState,
+    tournament_id: Uuid,
+    game_id: Uuid,
+    status: GameStatus,
+) -> Result<(), Error> {
+    let tournament = state
+        .tournament(&tournament_id)
+        .ok_or(Error::TournamentNotFound(tournament_id))?;
+    let mut tournament = tournament.lock().await;
+    let pairings = tournament
+        .record_result(game_id, status)
+        .map_err(Error::Tournament)?;
+    create_games(state, &mut tournament, pairings).await?;
+    save(state, &tournament).await?;
+    if tournament.status == TournamentStatus::Finished {
+        log::info!("Tournament {} finished", tournament.id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays every pending pairing with `result`, returning the next ones.
+    fn play_all(
+        tournament: &mut Tournament,
+        pairings: Vec<Pairing>,
+        result: GameStatus,
+    ) -> Vec<Pairing> {
+        let mut next = Vec::new();
+        for pairing in pairings {
+            let game_id = Uuid::new_v4();
+            tournament.attach_game(&pairing, game_id);
+            next.extend(tournament.record_result(game_id, result).unwrap());
+        }
+        next
+    }
+
+    #[test]
+    fn test_x_wins_every_game_until_a_champion_is_crowned() {
+        let mut tournament = Tournament::new("Weekly".to_string(), TournamentFormat::default());
+        for name in ["a", "b", "c", "d"] {
+            tournament.register(name.to_string()).unwrap();
+        }
+        let mut pairings = tournament.start().unwrap();
+        assert_eq!(pairings.len(), 2);
+        assert_eq!(
+            tournament.register("e".to_string()).unwrap_err(),
+            TournamentError::RegistrationClosed
+        );
+
+        pairings = play_all(&mut tournament, pairings, GameStatus::Win(Player::X));
+        assert_eq!(pairings.len(), 1);
+        assert_eq!(tournament.rounds.len(), 2);
+
+        // Seeds 1 and 2 had X in the first round and meet in the final.
+        let final_pairing = pairings[0];
+        assert_eq!(final_pairing.x, tournament.entrants[0].id);
+        assert_eq!(final_pairing.o, tournament.entrants[1].id);
+
+        assert!(play_all(&mut tournament, pairings, GameStatus::Win(Player::O)).is_empty());
+        assert_eq!(tournament.status, TournamentStatus::Finished);
+        assert_eq!(tournament.winner, Some(tournament.entrants[1].id));
+    }
+
+    #[test]
+    fn test_draws_are_replayed_with_colors_swapped() {
+        let mut tournament = Tournament::new("Cup".to_string(), TournamentFormat::default());
+        tournament.register("a".to_string()).unwrap();
+        tournament.register("b".to_string()).unwrap();
+        let (a, b) = (tournament.entrants[0].id, tournament.entrants[1].id);
+
+        let pairings = tournament.start().unwrap();
+        let rematch = play_all(&mut tournament, pairings, GameStatus::Draw);
+        assert_eq!((rematch[0].x, rematch[0].o), (b, a));
+
+        // b wins the rematch playing X.
+        play_all(&mut tournament, rematch, GameStatus::Win(Player::X));
+        assert_eq!(tournament.winner, Some(b));
+    }
+
+    #[test]
+    fn test_higher_seed_advances_after_repeated_draws() {
+        let mut tournament = Tournament::new("Cup".to_string(), TournamentFormat::default());
+        tournament.register("a".to_string()).unwrap();
+        tournament.register("b".to_string()).unwrap();
+
+        let mut pairings = tournament.start().unwrap();
+        for _ in 0..MAX_GAMES_PER_MATCH {
+            pairings = play_all(&mut tournament, pairings, GameStatus::Draw);
+        }
+        assert!(pairings.is_empty());
+        assert_eq!(tournament.winner, Some(tournament.entrants[0].id));
+    }
+
+    #[test]
+    fn test_swiss_runs_a_fixed_number_of_rounds() {
+        let mut tournament =
+            Tournament::new("Open".to_string(), TournamentFormat::Swiss { rounds: 3 });
+        for name in ["a", "b", "c", "d", "e"] {
+            tournament.register(name.to_string()).unwrap();
+        }
+        let mut pairings = tournament.start().unwrap();
+        for round in 1..=3 {
+            assert_eq!(pairings.len(), 2, "round {round}");
+            // Draws don't replay in Swiss; the round just moves on.
+            pairings = play_all(&mut tournament, pairings, GameStatus::Draw);
+        }
+        assert!(pairings.is_empty());
+        assert_eq!(tournament.rounds.len(), 3);
+        assert_eq!(tournament.status, TournamentStatus::Finished);
+
+        let standings = tournament.standings().unwrap();
+        assert_eq!(tournament.winner, Some(standings[0].entrant));
+        // Each bye is worth a point, each draw half a point.
+        let total: f64 = standings.iter().map(|standing| standing.points).sum();
+        assert_eq!(total, 3.0 * (1.0 + 2.0));
+    }
+
+    #[test]
+    fn test_swiss_rejects_more_rounds_than_opponents() {
+        let mut tournament =
+            Tournament::new("Open".to_string(), TournamentFormat::Swiss { rounds: 4 });
+        for name in ["a", "b", "c", "d"] {
+            tournament.register(name.to_string()).unwrap();
+        }
+        assert_eq!(
+            tournament.start().unwrap_err(),
+            TournamentError::TooManyRounds
+        );
+    }
+
+    #[test]
+    fn test_unknown_games_are_rejected() {
+        let mut tournament = Tournament::new("Cup".to_string(), TournamentFormat::default());
+        tournament.register("a".to_string()).unwrap();
+        assert_eq!(
+            tournament.start().unwrap_err(),
+            TournamentError::NotEnoughEntrants
+        );
+        tournament.register("b".to_string()).unwrap();
+        tournament.start().unwrap();
+        assert_eq!(
+            tournament
+                .record_result(Uuid::new_v4(), GameStatus::Draw)
+                .unwrap_err(),
+            TournamentError::UnknownGame
+        );
+    }
+}
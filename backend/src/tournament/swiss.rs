@@ -0,0 +1,205 @@
+//! Swiss-system pairing and standings.
+//!
+//! Every round, players are ranked by points and paired with the
+//! highest-ranked player they haven't met yet. A win is worth one point, a
+//! draw half a point, and a bye one point. Ties in the standings are broken
+//! by Buchholz (the sum of the opponents' points), then by seed.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::Match;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Standing {
+    pub entrant: Uuid,
+    pub points: f64,
+    pub buchholz: f64,
+}
+
+/// What each entrant has done so far. Points are kept doubled so half
+/// points stay exact.
+#[derive(Default)]
+struct Record {
+    double_points: u32,
+    opponents: Vec<Uuid>,
+    games_as_x: usize,
+    had_bye: bool,
+}
+
+fn records(entrants: &[Uuid], rounds: &[Vec<Match>]) -> HashMap<Uuid, Record> {
+    let mut records: HashMap<Uuid, Record> =
+        entrants.iter().map(|&id| (id, Record::default())).collect();
+    for m in rounds.iter().flatten() {
+        let Some(o) = m.o else {
+            let record = records.entry(m.x).or_default();
+            record.double_points += 2;
+            record.had_bye = true;
+            continue;
+        };
+        records.entry(m.x).or_default().opponents.push(o);
+        records.entry(m.x).or_default().games_as_x += 1;
+        records.entry(o).or_default().opponents.push(m.x);
+        if m.draw {
+            records.entry(m.x).or_default().double_points += 1;
+            records.entry(o).or_default().double_points += 1;
+        } else if let Some(winner) = m.winner {
+            records.entry(winner).or_default().double_points += 2;
+        }
+    }
+    records
+}
+
+/// Current standings, best first.
+pub fn standings(entrants: &[Uuid], rounds: &[Vec<Match>]) -> Vec<Standing> {
+    let records = records(entrants, rounds);
+    let seed = |id: &Uuid| entrants.iter().position(|e| e == id);
+    let mut standings: Vec<(Standing, u32, u32)> = entrants
+        .iter()
+        .map(|&id| {
+            let record = &records[&id];
+            let double_buchholz = record
+                .opponents
+                .iter()
+                .map(|opponent| records[opponent].double_points)
+                .sum();
+            let standing = Standing {
+                entrant: id,
+                points: f64::from(record.double_points) / 2.0,
+                buchholz: f64::from(double_buchholz) / 2.0,
+            };
+            (standing, record.double_points, double_buchholz)
+        })
+        .collect();
+    standings.sort_by(|a, b| {
+        (b.1, b.2)
+            .cmp(&(a.1, a.2))
+            .then_with(|| seed(&a.0.entrant).cmp(&seed(&b.0.entrant)))
+    });
+    standings
+        .into_iter()
+        .map(|(standing, _, _)| standing)
+        .collect()
+}
+
+/// Pairs the next round given every round played so far.
+pub fn pair_round(entrants: &[Uuid], rounds: &[Vec<Match>]) -> Vec<Match> {
+    let records = records(entrants, rounds);
+    let mut unpaired: Vec<Uuid> = standings(entrants, rounds)
+        .into_iter()
+        .map(|standing| standing.entrant)
+        .collect();
+
+    let mut matches = Vec::new();
+    // With an odd field, the lowest-ranked player who hasn't had a bye sits
+    // this round out.
+    if unpaired.len() % 2 == 1 {
+        let bye = unpaired
+            .iter()
+            .rposition(|id| !records[id].had_bye)
+            .unwrap_or(unpaired.len() - 1);
+        matches.push(Match::new(unpaired.remove(bye), None));
+    }
+
+    while !unpaired.is_empty() {
+        let first = unpaired.remove(0);
+        let met: HashSet<&Uuid> = records[&first].opponents.iter().collect();
+        // Prefer the best-ranked fresh opponent; repeat a pairing only when
+        // there is no one left to meet.
+        let opponent = unpaired
+            .iter()
+            .position(|id| !met.contains(id))
+            .unwrap_or(0);
+        let second = unpaired.remove(opponent);
+        // Whoever has had X less often gets it this time.
+        let (x, o) = if records[&second].games_as_x < records[&first].games_as_x {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        matches.push(Match::new(x, Some(o)));
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide(round: &mut [Match], results: &[Option<usize>]) {
+        // `Some(0)` means x won, `Some(1)` o won, `None` a draw.
+        for (m, result) in round.iter_mut().filter(|m| m.o.is_some()).zip(results) {
+            match result {
+                Some(0) => m.winner = Some(m.x),
+                Some(_) => m.winner = m.o,
+                None => m.draw = true,
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_rematches_while_fresh_opponents_remain() {
+        let entrants: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let mut rounds = Vec::new();
+        for _ in 0..3 {
+            let mut round = pair_round(&entrants, &rounds);
+            assert_eq!(round.len(), 2);
+            decide(&mut round, &[Some(0), None]);
+            rounds.push(round);
+        }
+
+        let mut pairs: Vec<(Uuid, Uuid)> = rounds
+            .iter()
+            .flatten()
+            .map(|m| {
+                let o = m.o.unwrap();
+                (m.x.min(o), m.x.max(o))
+            })
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        // Four players, three rounds: everyone met everyone exactly once.
+        assert_eq!(pairs.len(), 6);
+    }
+
+    #[test]
+    fn test_odd_fields_rotate_the_bye() {
+        let entrants: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut rounds = Vec::new();
+        let mut byes = Vec::new();
+        for _ in 0..3 {
+            let mut round = pair_round(&entrants, &rounds);
+            byes.push(round.iter().find(|m| m.o.is_none()).unwrap().x);
+            decide(&mut round, &[Some(0)]);
+            rounds.push(round);
+        }
+        byes.sort();
+        byes.dedup();
+        assert_eq!(byes.len(), 3);
+    }
+
+    #[test]
+    fn test_standings_break_ties_by_buchholz() {
+        let [a, b, c, d] = std::array::from_fn(|_| Uuid::new_v4());
+        let entrants = [a, b, c, d];
+        let win = |x, o| {
+            let mut m = Match::new(x, Some(o));
+            m.winner = Some(x);
+            m
+        };
+        // Round 1: a beats b, c beats d. Round 2: a beats c, d beats b.
+        let rounds = vec![vec![win(a, b), win(c, d)], vec![win(a, c), win(d, b)]];
+
+        let standings = standings(&entrants, &rounds);
+        assert_eq!(standings[0].entrant, a);
+        assert_eq!(standings[0].points, 2.0);
+        // c and d both have one point, but c lost to the leader.
+        assert_eq!(standings[1].entrant, c);
+        assert_eq!(standings[1].buchholz, 3.0);
+        assert_eq!(standings[2].entrant, d);
+        assert_eq!(standings[2].buchholz, 1.0);
+        assert_eq!(standings[3].entrant, b);
+    }
+}
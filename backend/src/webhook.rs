@@ -0,0 +1,507 @@
+//! Webhooks: URLs registered against a game that receive a signed POST
+//! whenever something happens in it.
+//!
+//! Each body is signed with HMAC-SHA256 under the webhook's own secret, sent
+//! as `Laika-Signature: sha256=<hex>`. Failed deliveries are retried with
+//! exponential backoff, and every attempt is kept in a short per-webhook log.
+//! Webhooks live in memory and go away with their game.
+//!
+//! Anyone can add a webhook to a game against the engine, so deliveries only
+//! go to public addresses: a webhook's host is checked when it is registered,
+//! and again each time a delivery connects, in case the name has since been
+//! pointed somewhere else. Redirects aren't followed.
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    game::{GameState, MoveRecord},
+    jobs::Retry,
+    state::{AppState, GameEvent, GameMode, GameUpdate, new_token},
+};
+
+pub const MAX_WEBHOOKS_PER_GAME: usize = 5;
+/// Five attempts, waiting 1s, 2s, 4s, then 8s between them.
+const DELIVERY_RETRY: Retry = Retry {
+    attempts: 5,
+    first_delay: Duration::from_secs(1),
+};
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts kept per webhook; older ones are dropped.
+const DELIVERY_LOG_LEN: usize = 50;
+
+pub const SIGNATURE_HEADER: &str = "laika-signature";
+pub const EVENT_HEADER: &str = "laika-event";
+pub const DELIVERY_HEADER: &str = "laika-delivery";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "move.made")]
+    MoveMade,
+    #[serde(rename = "game.finished")]
+    GameFinished,
+    #[serde(rename = "game.expired")]
+    GameExpired,
+}
+
+impl WebhookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::MoveMade => "move.made",
+            WebhookEvent::GameFinished => "game.finished",
+            WebhookEvent::GameExpired => "game.expired",
+        }
+    }
+
+    /// The webhook events a game update turns into, in order.
+    fn from_update(event: GameEvent) -> &'static [WebhookEvent] {
+        match event {
+            GameEvent::Moved { finished: false } => &[WebhookEvent::MoveMade],
+            GameEvent::Moved { finished: true } => {
+                &[WebhookEvent::MoveMade, WebhookEvent::GameFinished]
+            }
+            GameEvent::Forfeited | GameEvent::TimedOut => &[WebhookEvent::GameFinished],
+            GameEvent::Expired => &[WebhookEvent::GameExpired],
+            GameEvent::Created
+            | GameEvent::Seated
+            | GameEvent::Swapped
+            | GameEvent::PauseChanged
+            | GameEvent::DrawOffer => &[],
+        }
+    }
+}
+
+/// One attempt at delivering an event.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub delivery_id: Uuid,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub attempted_at: DateTime<Utc>,
+    /// The receiver's HTTP status, if it answered at all.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    /// Shared with the receiver so it can check signatures.
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    deliveries: Mutex<VecDeque<Delivery>>,
+}
+
+impl Webhook {
+    /// Delivery attempts, oldest first.
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        let deliveries = self.deliveries.lock().expect("delivery log poisoned");
+        deliveries.iter().cloned().collect()
+    }
+
+    fn log(&self, delivery: Delivery) {
+        let mut deliveries = self.deliveries.lock().expect("delivery log poisoned");
+        if deliveries.len() == DELIVERY_LOG_LEN {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(delivery);
+    }
+}
+
+/// The JSON body of every delivery.
+#[derive(Debug, Serialize)]
+struct Payload {
+    delivery_id: Uuid,
+    event: WebhookEvent,
+    game_id: Uuid,
+    occurred_at: DateTime<Utc>,
+    /// `null` for `game.expired`, since the game is gone.
+    state: Option<GameState>,
+    last_move: Option<MoveRecord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookError {
+    NotFound,
+    InvalidUrl,
+    /// The URL's host is, or resolves to, an address that isn't public.
+    PrivateAddress,
+    TooMany,
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            WebhookError::NotFound => "No such webhook on this game",
+            WebhookError::InvalidUrl => "Webhook URL must be an absolute http or https URL",
+            WebhookError::PrivateAddress => "Webhook URL must resolve to public addresses only",
+            WebhookError::TooMany => "This game already has the maximum number of webhooks",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={signature}")
+}
+
+/// Every registered webhook, by game.
+pub struct Webhooks {
+    hooks: DashMap<Uuid, Vec<Arc<Webhook>>>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new() -> Self {
+        Self {
+            hooks: DashMap::new(),
+            client: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .dns_resolver(Arc::new(PublicResolver))
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("HTTP client builds"),
+        }
+    }
+
+    pub async fn register(&self, game_id: Uuid, url: &str) -> Result<Arc<Webhook>, WebhookError> {
+        let parsed = reqwest::Url::parse(url).map_err(|_| WebhookError::InvalidUrl)?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(WebhookError::InvalidUrl);
+        }
+        // Addresses given as such are never looked up, so they are only
+        // checked here.
+        let host = parsed.host_str().ok_or(WebhookError::InvalidUrl)?;
+        let public = match host.trim_matches(['[', ']']).parse() {
+            Ok(ip) => is_public(ip),
+            Err(_) => public_addrs(host).await.is_ok(),
+        };
+        if !public {
+            return Err(WebhookError::PrivateAddress);
+        }
+        let mut hooks = self.hooks.entry(game_id).or_default();
+        if hooks.len() >= MAX_WEBHOOKS_PER_GAME {
+            return Err(WebhookError::TooMany);
+        }
+        let hook = Arc::new(Webhook {
+            id: Uuid::new_v4(),
+            url: parsed.to_string(),
+            secret: new_token(),
+            created_at: Utc::now(),
+            deliveries: Mutex::new(VecDeque::new()),
+        });
+        hooks.push(hook.clone());
+        Ok(hook)
+    }
+
+    pub fn list(&self, game_id: Uuid) -> Vec<Arc<Webhook>> {
+        self.hooks
+            .get(&game_id)
+            .map(|hooks| hooks.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, game_id: Uuid, webhook_id: Uuid) -> Result<Arc<Webhook>, WebhookError> {
+        self.list(game_id)
+            .into_iter()
+            .find(|hook| hook.id == webhook_id)
+            .ok_or(WebhookError::NotFound)
+    }
+
+    pub fn remove(&self, game_id: Uuid, webhook_id: Uuid) -> Result<(), WebhookError> {
+        let mut hooks = self.hooks.get_mut(&game_id).ok_or(WebhookError::NotFound)?;
+        let before = hooks.len();
+        hooks.retain(|hook| hook.id != webhook_id);
+        if before == hooks.len() {
+            return Err(WebhookError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn forget(&self, game_id: Uuid) {
+        self.hooks.remove(&game_id);
+    }
+}
+
+/// Whether `ip` can be reached from the internet at large, rather than
+/// being loopback, private, link-local (cloud metadata services among them),
+/// or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", carrier-grade NAT, IETF protocol assignments,
+        // benchmarking, and the reserved block.
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a, b, c) == (192, 0, 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // Documentation, and IPv4 addresses behind NAT64, whose IPv4 half
+        // could be anything.
+        || (first, ip.segments()[1]) == (0x2001, 0xdb8)
+        || first == 0x64)
+}
+
+/// The addresses `host` resolves to, if every one of them is public.
+async fn public_addrs(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{host} doesn't resolve to public addresses only"),
+        ));
+    }
+    Ok(addrs)
+}
+
+/// Looks up webhook hosts as deliveries connect, refusing any that resolve
+/// to an address that isn't public.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs = public_addrs(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+// --- Operations ---
+
+/// Webhooks on engine games are open to anyone with the game ID, like the
+/// rest of the game; on player-vs-player games they need either seat token.
+pub async fn authorize(
+    state: &AppState,
+    game_id: Uuid,
+    seat_token: Option<&str>,
+) -> Result<(), Error> {
+    let game = state.game(&game_id).ok_or(Error::GameNotFound(game_id))?;
+    let entry = game.lock().await;
+    match &entry.mode {
+        GameMode::VsEngine | GameMode::VsBot { .. } => Ok(()),
+        mode if seat_token.and_then(|token| mode.seat_of(token)).is_some() => Ok(()),
+        _ => Err(Error::Forbidden(
+            "A seat token for this game is required to manage its webhooks",
+        )),
+    }
+}
+
+/// Posts `body` to the webhook until it answers with a 2xx or the attempts
+/// run out.
+fn deliver(
+    state: &AppState,
+    hook: Arc<Webhook>,
+    delivery_id: Uuid,
+    event: WebhookEvent,
+    body: Vec<u8>,
+) {
+    let client = state.webhooks.client.clone();
+    let signature = sign(&hook.secret, &body);
+    let name = format!("delivery {} to webhook {}", delivery_id, hook.id);
+    state.jobs.retry(name, DELIVERY_RETRY, move |attempt| {
+        let request = client
+            .post(&hook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(EVENT_HEADER, event.name())
+            .header(DELIVERY_HEADER, delivery_id.to_string())
+            .body(body.clone());
+        let hook = hook.clone();
+        async move {
+            let (status, error) = match request.send().await {
+                Ok(response) => (Some(response.status()), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let delivered = status.is_some_and(|status| status.is_success());
+            hook.log(Delivery {
+                delivery_id,
+                event,
+                attempt,
+                attempted_at: Utc::now(),
+                status: status.map(|status| status.as_u16()),
+                error: error.clone(),
+                delivered,
+            });
+            match (delivered, status) {
+                (true, _) => Ok(()),
+                (false, Some(status)) => Err(format!("answered {}", status)),
+                (false, None) => Err(error.unwrap_or_default()),
+            }
+        }
+    });
+}
+
+async fn dispatch(state: &AppState, update: GameUpdate) {
+    let hooks = state.webhooks.list(update.game_id);
+    if hooks.is_empty() {
+        return;
+    }
+    let (game_state, last_move) = match state.game(&update.game_id) {
+        Some(game) => {
+            let entry = game.lock().await;
+            (Some(entry.state), entry.moves().last().copied())
+        }
+        None => (None, None),
+    };
+    for &event in WebhookEvent::from_update(update.event) {
+        let delivery_id = Uuid::new_v4();
+        let payload = Payload {
+            delivery_id,
+            event,
+            game_id: update.game_id,
+            occurred_at: Utc::now(),
+            state: game_state,
+            last_move,
+        };
+        let body = serde_json::to_vec(&payload).expect("payload serializes");
+        for hook in &hooks {
+            deliver(state, hook.clone(), delivery_id, event, body.clone());
+        }
+    }
+    if update.event == GameEvent::Expired {
+        state.webhooks.forget(update.game_id);
+    }
+}
+
+/// Background task that turns game updates into webhook deliveries.
+pub async fn dispatch_task(state: AppState) {
+    let mut updates = state.updates.subscribe();
+    loop {
+        match updates.recv().await {
+            Ok(update) => dispatch(&state, update).await,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Webhook dispatch fell behind; {} updates dropped", missed);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signatures_use_the_webhook_secret() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registration_checks_urls_and_limits() {
+        let webhooks = Webhooks::new();
+        let game_id = Uuid::new_v4();
+        for url in ["not a url", "ftp://example.com/hook", "file:///etc/passwd"] {
+            assert_eq!(
+                webhooks.register(game_id, url).await.err(),
+                Some(WebhookError::InvalidUrl)
+            );
+        }
+        for _ in 0..MAX_WEBHOOKS_PER_GAME {
+            webhooks
+                .register(game_id, "https://93.184.215.14/hook")
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            webhooks
+                .register(game_id, "https://93.184.215.14/hook")
+                .await
+                .err(),
+            Some(WebhookError::TooMany)
+        );
+
+        let hook = webhooks.list(game_id)[0].clone();
+        assert_eq!(webhooks.remove(game_id, hook.id), Ok(()));
+        assert_eq!(
+            webhooks.remove(game_id, hook.id),
+            Err(WebhookError::NotFound)
+        );
+        assert_eq!(webhooks.list(game_id).len(), MAX_WEBHOOKS_PER_GAME - 1);
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_only_reach_public_addresses() {
+        let webhooks = Webhooks::new();
+        let game_id = Uuid::new_v4();
+        for url in [
+            "http://127.0.0.1:6379/",
+            "http://0x7f.1/",
+            "http://localhost:3000/admin",
+            "http://10.1.2.3/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.100.100.200/",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:172.16.0.1]/hook",
+        ] {
+            assert_eq!(
+                webhooks.register(game_id, url).await.err(),
+                Some(WebhookError::PrivateAddress),
+                "{url}"
+            );
+        }
+        assert!(is_public("2606:4700::1111".parse().unwrap()));
+
+        // Names are checked again when a delivery connects.
+        let resolved =
+            reqwest::dns::Resolve::resolve(&PublicResolver, "localhost".parse().unwrap()).await;
+        assert!(resolved.is_err());
+    }
+}
@@ -0,0 +1,6 @@
+//! Prints the TypeScript definitions of the wire types, for
+//! `frontend/src/laika.d.ts`.
+
+fn main() {
+    print!("{}", laika_types::typescript());
+}
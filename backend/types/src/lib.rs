@@ -0,0 +1,462 @@
+//! The wire types of the Laika API's v1, shared by the server, which maps
+//! its own types onto them, and its clients.
+//!
+//! The JSON each type produces is the API, so it is pinned by the tests
+//! below and follows semver: a release that removes or renames a field or
+//! variant, or changes how one is written, is a major one. New fields are
+//! optional, so older clients skip them and older servers' responses still
+//! decode; a new enum variant is a major release too, because clients
+//! match on them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[cfg(feature = "schema")]
+pub use schemars;
+
+#[cfg(feature = "typescript")]
+mod typescript;
+
+#[cfg(feature = "typescript")]
+pub use typescript::typescript;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub enum Player {
+    X,
+    O,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub enum Cell {
+    Empty,
+    Occupied(Player),
+    /// Only in games created with blocked cells.
+    Blocked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub enum GameStatus {
+    InProgress,
+    Draw,
+    Win(Player),
+}
+
+/// How a game ended, when it wasn't decided on the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    ByAgreement,
+    OnTime,
+    ByAbsence,
+    ByForfeit,
+}
+
+/// How `increment_secs` applies to each move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum IncrementMode {
+    Fischer,
+    Delay,
+}
+
+/// What happens to a player who runs out of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutAction {
+    Forfeit,
+    RandomMove,
+    Draw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct TimeControl {
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub initial_secs: u64,
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub increment_secs: u64,
+    pub mode: IncrementMode,
+    pub on_timeout: TimeoutAction,
+}
+
+/// Both clocks of a timed game, as of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct ClocksView {
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub x_ms: u64,
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub o_ms: u64,
+    /// Whose clock is running; `null` before the first move, while paused,
+    /// and once the game is over.
+    pub running: Option<Player>,
+    pub time_control: TimeControl,
+    pub paused_at: Option<DateTime<Utc>>,
+    /// Who asked to pause, or to resume while paused, and is waiting for
+    /// the opponent to agree.
+    pub pause_offered_by: Option<Player>,
+}
+
+/// A game as v1 clients see it. The board is 3x3 unless the game was
+/// created with another size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct GameView {
+    pub board: Vec<Vec<Cell>>,
+    pub status: GameStatus,
+    pub to_play: Player,
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub version: u64,
+    /// Only in timed games.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clocks: Option<ClocksView>,
+    /// Only while a draw offer is waiting for an answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draw_offered_by: Option<Player>,
+    /// Only for games that ended off the board.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ending: Option<Ending>,
+}
+
+impl GameView {
+    pub fn is_over(&self) -> bool {
+        self.status != GameStatus::InProgress
+    }
+}
+
+/// A cell, by its zero-based row and column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct PlayerMove {
+    pub row: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(default)]
+pub struct NewGameRequest {
+    /// Chance, from 0 to 1, that the engine plays a weaker move on each turn.
+    /// Defaults to the tenant's setting, then the tuned chance when
+    /// `calibration.auto_tune` is on, then 0.
+    pub blunder_chance: Option<f64>,
+    pub rows: usize,
+    pub cols: usize,
+    /// How many in a row win.
+    pub win_length: usize,
+    /// Winning lines wrap around the board edges.
+    pub toroidal: bool,
+    /// Cells to take out of play.
+    pub blocked: Vec<PlayerMove>,
+    /// How many cells to block at random, instead of listing them.
+    pub random_blocked: usize,
+}
+
+impl NewGameRequest {
+    /// Anything but a plain 3x3 board.
+    pub fn is_variant(&self) -> bool {
+        let plain = Self::default();
+        (self.rows, self.cols, self.win_length) != (plain.rows, plain.cols, plain.win_length)
+            || self.toroidal
+            || !self.blocked.is_empty()
+            || self.random_blocked > 0
+    }
+}
+
+impl Default for NewGameRequest {
+    fn default() -> Self {
+        Self {
+            blunder_chance: None,
+            rows: 3,
+            cols: 3,
+            win_length: 3,
+            toroidal: false,
+            blocked: Vec::new(),
+            random_blocked: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct NewGameResponse {
+    pub game_id: Uuid,
+    pub game_state: GameView,
+}
+
+/// Where a move goes: by row and column; by the cell's index, which counts
+/// along each row from the top left, so it is `row * cols + col`: 0 to 8 on
+/// a 3x3 board; or by its algebraic square, from `a3` at the top left to
+/// `c1` at the bottom right of a 3x3 board.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(untagged)]
+pub enum MoveTarget {
+    Square { row: usize, col: usize },
+    Cell { cell: usize },
+    Algebraic { square: String },
+}
+
+/// The body of a move. With `expected_version`, the move is refused with
+/// `409 Conflict` if the game has changed since that version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct MoveRequest {
+    #[serde(flatten)]
+    pub target: MoveTarget,
+    #[serde(default)]
+    #[cfg_attr(feature = "typescript", ts(type = "number | null"))]
+    pub expected_version: Option<u64>,
+}
+
+impl MoveRequest {
+    pub fn new(row: usize, col: usize) -> Self {
+        Self {
+            target: MoveTarget::Square { row, col },
+            expected_version: None,
+        }
+    }
+
+    /// A move to the cell at `index`, as `MoveTarget::Cell` counts them.
+    pub fn cell(index: usize) -> Self {
+        Self {
+            target: MoveTarget::Cell { cell: index },
+            expected_version: None,
+        }
+    }
+
+    /// A move to an algebraic square such as `b2`.
+    pub fn square(square: impl Into<String>) -> Self {
+        Self {
+            target: MoveTarget::Algebraic {
+                square: square.into(),
+            },
+            expected_version: None,
+        }
+    }
+
+    pub fn expecting(self, version: u64) -> Self {
+        Self {
+            expected_version: Some(version),
+            ..self
+        }
+    }
+}
+
+/// The body of a batch of moves, played in order and all or none of them.
+/// `expected_version` applies to the game before the first move.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct MoveBatchRequest {
+    pub moves: Vec<MoveTarget>,
+    #[serde(default)]
+    #[cfg_attr(feature = "typescript", ts(type = "number | null"))]
+    pub expected_version: Option<u64>,
+}
+
+/// A move, with its `square` in notation, e.g. `b2`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct MoveView {
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub ply: u64,
+    pub player: Player,
+    pub row: usize,
+    pub col: usize,
+    /// The cell's index, as `MoveTarget::Cell` counts them. Older servers
+    /// leave it out.
+    #[serde(default)]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    pub cell: Option<usize>,
+    pub square: String,
+}
+
+/// An entry in a game's event log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventView {
+    GameCreated {
+        board: Vec<Vec<Cell>>,
+        win_length: usize,
+        toroidal: bool,
+    },
+    MoveMade(MoveView),
+    GameFinished {
+        status: GameStatus,
+        /// Only for games that ended off the board.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ending: Option<Ending>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct EventRecordView {
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: EventView,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct EventsResponse {
+    pub events: Vec<EventRecordView>,
+    /// The game as of the last event returned. Clocks and draw offers aren't
+    /// part of the log, so they only show when every event is returned.
+    pub game_state: GameView,
+}
+
+/// The body of every error response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(type = "unknown", optional))]
+    pub details: Option<serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_representations_are_stable() {
+        let at = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .to_utc();
+        let cases = [
+            (
+                serde_json::to_value(GameView {
+                    board: vec![vec![Cell::Occupied(Player::X), Cell::Blocked, Cell::Empty]],
+                    status: GameStatus::Win(Player::X),
+                    to_play: Player::O,
+                    version: 3,
+                    clocks: None,
+                    draw_offered_by: None,
+                    ending: Some(Ending::OnTime),
+                })
+                .unwrap(),
+                json!({
+                    "board": [[{"Occupied": "X"}, "Blocked", "Empty"]],
+                    "status": {"Win": "X"},
+                    "to_play": "O",
+                    "version": 3,
+                    "ending": "on_time",
+                }),
+            ),
+            (
+                serde_json::to_value(MoveRequest::new(1, 2).expecting(4)).unwrap(),
+                json!({"row": 1, "col": 2, "expected_version": 4}),
+            ),
+            (
+                serde_json::to_value(MoveRequest::cell(8)).unwrap(),
+                json!({"cell": 8, "expected_version": null}),
+            ),
+            (
+                serde_json::to_value(MoveBatchRequest {
+                    moves: vec![
+                        MoveTarget::Cell { cell: 4 },
+                        MoveTarget::Algebraic {
+                            square: "a1".to_string(),
+                        },
+                    ],
+                    expected_version: Some(0),
+                })
+                .unwrap(),
+                json!({"moves": [{"cell": 4}, {"square": "a1"}], "expected_version": 0}),
+            ),
+            (
+                serde_json::to_value(EventRecordView {
+                    seq: 1,
+                    at,
+                    event: EventView::MoveMade(MoveView {
+                        ply: 1,
+                        player: Player::X,
+                        row: 1,
+                        col: 1,
+                        cell: Some(4),
+                        square: "b2".to_string(),
+                    }),
+                })
+                .unwrap(),
+                json!({
+                    "seq": 1,
+                    "at": "2025-01-02T03:04:05Z",
+                    "kind": "move_made",
+                    "ply": 1,
+                    "player": "X",
+                    "row": 1,
+                    "col": 1,
+                    "cell": 4,
+                    "square": "b2",
+                }),
+            ),
+            (
+                serde_json::to_value(ErrorBody {
+                    code: "not_your_turn".to_string(),
+                    message: "Not your turn".to_string(),
+                    details: None,
+                })
+                .unwrap(),
+                json!({"code": "not_your_turn", "message": "Not your turn"}),
+            ),
+        ];
+        for (written, expected) in cases {
+            assert_eq!(written, expected);
+        }
+
+        // And they read back, including from before a field was added.
+        let request: MoveRequest = serde_json::from_value(json!({"cell": 4})).unwrap();
+        assert_eq!(request, MoveRequest::cell(4));
+        let request: MoveRequest = serde_json::from_value(json!({"square": "b2"})).unwrap();
+        assert_eq!(request, MoveRequest::square("b2"));
+        let event: EventRecordView = serde_json::from_value(json!({
+            "seq": 4,
+            "at": "2025-01-02T03:04:05Z",
+            "kind": "game_finished",
+            "status": "Draw",
+        }))
+        .unwrap();
+        assert_eq!(
+            event.event,
+            EventView::GameFinished {
+                status: GameStatus::Draw,
+                ending: None
+            }
+        );
+    }
+}
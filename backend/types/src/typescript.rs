@@ -0,0 +1,57 @@
+//! TypeScript definitions of the wire types, generated from their serde
+//! attributes so they can't disagree with the JSON.
+
+use ts_rs::TS;
+
+use crate::*;
+
+/// Every type's definition, as a `.d.ts` file.
+pub fn typescript() -> String {
+    let decls = [
+        Player::decl(),
+        Cell::decl(),
+        GameStatus::decl(),
+        Ending::decl(),
+        IncrementMode::decl(),
+        TimeoutAction::decl(),
+        TimeControl::decl(),
+        ClocksView::decl(),
+        GameView::decl(),
+        PlayerMove::decl(),
+        NewGameRequest::decl(),
+        NewGameResponse::decl(),
+        MoveTarget::decl(),
+        MoveRequest::decl(),
+        MoveBatchRequest::decl(),
+        MoveView::decl(),
+        EventView::decl(),
+        EventRecordView::decl(),
+        EventsResponse::decl(),
+        ErrorBody::decl(),
+    ];
+    let mut file = String::from(
+        "// Generated from the laika-types crate; don't edit by hand. Regenerate\n\
+         // with `npm run types`.\n",
+    );
+    for decl in decls {
+        file.push_str("\nexport ");
+        file.push_str(&decl);
+        file.push('\n');
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_the_frontend_has_the_current_definitions() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../frontend/src/laika.d.ts");
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == typescript(),
+            "{path} is out of date; run `npm run types` in frontend"
+        );
+    }
+}
@@ -3,12 +3,13 @@ import Board from './Board';
 import Status from './Status';
 import './App.css';
 
-const API_BASE_URL = 'http://localhost:3000/api';
+const API_BASE_URL = 'http://localhost:3000/api/v1';
 
 const initialGameState = {
   board: [['Empty', 'Empty', 'Empty'], ['Empty', 'Empty', 'Empty'], ['Empty', 'Empty', 'Empty']],
   status: 'InProgress',
   to_play: 'X',
+  version: 0,
 };
 
 function App() {
@@ -55,16 +56,25 @@ function App() {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
+          'Idempotency-Key': crypto.randomUUID(),
         },
-        body: JSON.stringify({ row, col }),
+        body: JSON.stringify({ row, col, expected_version: gameState.version }),
       });
 
       if (!response.ok) {
         if (response.status === 404) {
              setError("This game has already ended. Please start a new one.");
+        } else if (response.status === 409) {
+            // Another tab (or a retried request) moved first; resync with the server.
+            const latest = await fetch(`${API_BASE_URL}/games/${gameId}`);
+            if (latest.ok) {
+              setGameState(await latest.json());
+            }
+            setError("The game changed since your last move. The board has been refreshed.");
+            return;
         } else {
-            const errorText = await response.text();
-            throw new Error(`Invalid move: ${errorText}`);
+            const { message } = await response.json();
+            throw new Error(`Invalid move: ${message}`);
         }
         setGameId(null);
       } else {
@@ -0,0 +1,104 @@
+// Generated from the laika-types crate; don't edit by hand. Regenerate
+// with `npm run types`.
+
+export type Player = "X" | "O";
+
+export type Cell = "Empty" | { "Occupied": Player } | "Blocked";
+
+export type GameStatus = "InProgress" | "Draw" | { "Win": Player };
+
+export type Ending = "by_agreement" | "on_time" | "by_absence" | "by_forfeit";
+
+export type IncrementMode = "fischer" | "delay";
+
+export type TimeoutAction = "forfeit" | "random_move" | "draw";
+
+export type TimeControl = { initial_secs: number, increment_secs: number, mode: IncrementMode, on_timeout: TimeoutAction, };
+
+export type ClocksView = { x_ms: number, o_ms: number, 
+/**
+ * Whose clock is running; `null` before the first move, while paused,
+ * and once the game is over.
+ */
+running: Player | null, time_control: TimeControl, paused_at: string | null, 
+/**
+ * Who asked to pause, or to resume while paused, and is waiting for
+ * the opponent to agree.
+ */
+pause_offered_by: Player | null, };
+
+export type GameView = { board: Array<Array<Cell>>, status: GameStatus, to_play: Player, version: number, 
+/**
+ * Only in timed games.
+ */
+clocks?: ClocksView | null, 
+/**
+ * Only while a draw offer is waiting for an answer.
+ */
+draw_offered_by?: Player | null, 
+/**
+ * Only for games that ended off the board.
+ */
+ending?: Ending | null, };
+
+export type PlayerMove = { row: number, col: number, };
+
+export type NewGameRequest = { 
+/**
+ * Chance, from 0 to 1, that the engine plays a weaker move on each turn.
+ * Defaults to the tenant's setting, then the tuned chance when
+ * `calibration.auto_tune` is on, then 0.
+ */
+blunder_chance: number | null, rows: number, cols: number, 
+/**
+ * How many in a row win.
+ */
+win_length: number, 
+/**
+ * Winning lines wrap around the board edges.
+ */
+toroidal: boolean, 
+/**
+ * Cells to take out of play.
+ */
+blocked: Array<PlayerMove>, 
+/**
+ * How many cells to block at random, instead of listing them.
+ */
+random_blocked: number, };
+
+export type NewGameResponse = { game_id: string, game_state: GameView, };
+
+export type MoveTarget = { row: number, col: number, } | { cell: number, } | { square: string, };
+
+export type MoveRequest = { expected_version: number | null, } & ({ row: number, col: number, } | { cell: number, } | { square: string, });
+
+export type MoveBatchRequest = { moves: Array<MoveTarget>, expected_version: number | null, };
+
+export type MoveView = { ply: number, player: Player, row: number, col: number, 
+/**
+ * The cell's index, as `MoveTarget::Cell` counts them. Older servers
+ * leave it out.
+ */
+cell?: number, square: string, };
+
+export type EventView = { "kind": "game_created", board: Array<Array<Cell>>, win_length: number, toroidal: boolean, } | { "kind": "move_made" } & MoveView | { "kind": "game_finished", status: GameStatus, 
+/**
+ * Only for games that ended off the board.
+ */
+ending?: Ending | null, };
+
+export type EventRecordView = { seq: number, at: string, } & ({ "kind": "game_created", board: Array<Array<Cell>>, win_length: number, toroidal: boolean, } | { "kind": "move_made" } & MoveView | { "kind": "game_finished", status: GameStatus, 
+/**
+ * Only for games that ended off the board.
+ */
+ending?: Ending | null, });
+
+export type EventsResponse = { events: Array<EventRecordView>, 
+/**
+ * The game as of the last event returned. Clocks and draw offers aren't
+ * part of the log, so they only show when every event is returned.
+ */
+game_state: GameView, };
+
+export type ErrorBody = { code: string, message: string, details?: unknown, };